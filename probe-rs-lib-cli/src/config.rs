@@ -0,0 +1,273 @@
+// English comments: defaults from probe-rs-lib.toml files, so a board's chip, probe and flash
+// options are written down once (and can be committed with the project) instead of being passed
+// on every invocation. The file in the current directory or its closest parent overrides the
+// per-user file; a [profile.NAME] table selected with --profile overrides both. Options given on
+// the command line always win, the values here only replace the built-in defaults.
+//
+//     chip = "stm32f407zet6"
+//     speed = 8000
+//     protocol = "swd"
+//
+//     [flash]
+//     verify = true
+//     chip-erase = false
+//     after = "reset"
+//
+//     [profile.bootloader]
+//     chip = "stm32f407zgt6"
+//     probe = "0483:3748:0670FF"
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+pub const FILE_NAME: &str = "probe-rs-lib.toml";
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct FlashSettings {
+    /// Read back the flash after programming (--no-verify when false)
+    pub verify: Option<bool>,
+    pub preverify: Option<bool>,
+    /// Erase the whole chip (--no-chip-erase when false)
+    pub chip_erase: Option<bool>,
+    pub after: Option<String>,
+    /// Load address of .bin images
+    pub base: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Settings {
+    pub chip: Option<String>,
+    pub probe: Option<String>,
+    pub speed: Option<u32>,
+    pub protocol: Option<String>,
+    pub programmer_type: Option<String>,
+    #[serde(default)]
+    pub flash: FlashSettings,
+    #[serde(default)]
+    pub profile: BTreeMap<String, Settings>,
+}
+
+impl FlashSettings {
+    fn merge(self, over: FlashSettings) -> FlashSettings {
+        FlashSettings {
+            verify: over.verify.or(self.verify),
+            preverify: over.preverify.or(self.preverify),
+            chip_erase: over.chip_erase.or(self.chip_erase),
+            after: over.after.or(self.after),
+            base: over.base.or(self.base),
+        }
+    }
+}
+
+impl Settings {
+    // English comments: the settings of `over` where it has them, else those of `self`;
+    // profiles of the same name are merged the same way
+    pub fn merge(self, over: Settings) -> Settings {
+        let mut profile = self.profile;
+        for (name, settings) in over.profile {
+            let merged = match profile.remove(&name) {
+                Some(base) => base.merge(settings),
+                None => settings,
+            };
+            profile.insert(name, merged);
+        }
+        Settings {
+            chip: over.chip.or(self.chip),
+            probe: over.probe.or(self.probe),
+            speed: over.speed.or(self.speed),
+            protocol: over.protocol.or(self.protocol),
+            programmer_type: over.programmer_type.or(self.programmer_type),
+            flash: self.flash.merge(over.flash),
+            profile,
+        }
+    }
+
+    pub fn parse(text: &str, path: &Path) -> Result<Settings, String> {
+        let settings: Settings =
+            toml::from_str(text).map_err(|e| format!("{}: {}", path.display(), e))?;
+        if settings.profile.values().any(|p| !p.profile.is_empty()) {
+            return Err(format!("{}: profiles cannot be nested", path.display()));
+        }
+        Ok(settings)
+    }
+
+    // English comments: the top-level settings overridden by those of profile `name`
+    pub fn select(mut self, name: Option<&str>) -> Result<Settings, String> {
+        let Some(name) = name else {
+            return Ok(self);
+        };
+        let Some(profile) = self.profile.remove(name) else {
+            let known: Vec<&str> = self.profile.keys().map(String::as_str).collect();
+            return Err(match known.is_empty() {
+                true => format!("unknown profile {:?}: no profiles in {}", name, FILE_NAME),
+                false => format!("unknown profile {:?}, defined: {}", name, known.join(", ")),
+            });
+        };
+        Ok(self.merge(profile))
+    }
+
+    // English comments: (argument id, default value) pairs for the subcommand `command`
+    fn defaults(&self, command: &str) -> Vec<(&'static str, String)> {
+        // English comments: `info` without --chip lists the chip database
+        let chip = self.chip.clone().filter(|_| command != "info");
+        let mut defaults = vec![
+            ("chip", chip),
+            ("probe", self.probe.clone()),
+            ("speed", self.speed.map(|s| s.to_string())),
+            ("protocol", self.protocol.clone()),
+            ("programmer_type", self.programmer_type.clone()),
+        ];
+        if command == "flash" {
+            let flash = &self.flash;
+            defaults.extend([
+                ("no_verify", flash.verify.map(|v| (!v).to_string())),
+                ("preverify", flash.preverify.map(|v| v.to_string())),
+                ("no_chip_erase", flash.chip_erase.map(|v| (!v).to_string())),
+                ("after", flash.after.clone()),
+                ("base", flash.base.map(|b| format!("{:#x}", b))),
+            ]);
+        }
+        defaults
+            .into_iter()
+            .filter_map(|(id, value)| Some((id, value?)))
+            .collect()
+    }
+
+    // English comments: make the settings the default values of the subcommands' arguments,
+    // so clap still validates them and the command line overrides them
+    pub fn apply(&self, mut cli: clap::Command) -> clap::Command {
+        let names: Vec<String> = cli
+            .get_subcommands()
+            .map(|s| s.get_name().to_string())
+            .collect();
+        for name in names {
+            let defaults = self.defaults(&name);
+            cli = cli.mut_subcommand(&name, |mut sub| {
+                for (id, value) in defaults {
+                    if sub.get_arguments().any(|a| a.get_id() == id) {
+                        sub = sub.mut_arg(id, |a| a.default_value(value).required(false));
+                    }
+                }
+                sub
+            });
+        }
+        cli
+    }
+}
+
+// English comments: the per-user configuration directory
+fn user_config_dir() -> Option<PathBuf> {
+    let var = |name| std::env::var_os(name).filter(|v| !v.is_empty());
+    if cfg!(windows) {
+        return var("APPDATA").map(PathBuf::from);
+    }
+    var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| var("HOME").map(|home| Path::new(&home).join(".config")))
+}
+
+// English comments: the configuration files that apply in `dir`, per-user file first
+pub fn files(dir: &Path) -> Vec<PathBuf> {
+    let user = user_config_dir().map(|d| d.join(FILE_NAME));
+    let project = dir
+        .ancestors()
+        .map(|d| d.join(FILE_NAME))
+        .find(|p| p.is_file());
+    [user.filter(|p| p.is_file()), project]
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+// English comments: the settings of the files for the current directory with profile `name`
+pub fn load(profile: Option<&str>) -> Result<Settings, String> {
+    let dir = std::env::current_dir().unwrap_or_default();
+    let mut settings = Settings::default();
+    for path in files(&dir) {
+        let text =
+            std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+        settings = settings.merge(Settings::parse(&text, &path)?);
+    }
+    settings.select(profile)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Cli, Command};
+    use clap::{CommandFactory, FromArgMatches};
+
+    const PROJECT: &str = r#"
+        chip = "stm32f407zet6"
+        speed = 8000
+
+        [flash]
+        verify = false
+        after = "reset"
+
+        [profile.second]
+        chip = "stm32f407zgt6"
+        probe = "0483:3748:0670FF"
+    "#;
+
+    fn project() -> Settings {
+        Settings::parse(PROJECT, Path::new(FILE_NAME)).unwrap()
+    }
+
+    #[test]
+    fn files_are_merged_and_profiles_selected() {
+        let user = Settings::parse("speed = 100\nprotocol = \"swd\"", Path::new("user")).unwrap();
+        let settings = user.merge(project());
+        assert_eq!(settings.speed, Some(8000));
+        assert_eq!(settings.protocol.as_deref(), Some("swd"));
+
+        let second = settings.clone().select(Some("second")).unwrap();
+        assert_eq!(second.chip.as_deref(), Some("stm32f407zgt6"));
+        assert_eq!(second.probe.as_deref(), Some("0483:3748:0670FF"));
+        assert_eq!(second.flash.after.as_deref(), Some("reset"));
+        let err = settings.select(Some("third")).unwrap_err();
+        assert_eq!(err, "unknown profile \"third\", defined: second");
+
+        assert!(Settings::parse("chips = \"x\"", Path::new("bad")).is_err());
+        let nested = "[profile.a.profile.b]\nchip = \"x\"";
+        assert!(Settings::parse(nested, Path::new("bad")).is_err());
+    }
+
+    #[test]
+    fn settings_are_defaults_under_the_command_line() {
+        let parse = |settings: &Settings, args: &[&str]| {
+            let args = std::iter::once("probe-rs-lib-cli").chain(args.iter().copied());
+            let matches = settings.apply(Cli::command()).try_get_matches_from(args)?;
+            Cli::from_arg_matches(&matches).map(|cli| cli.command)
+        };
+        let settings = project();
+        let Ok(Command::Flash {
+            target,
+            no_verify,
+            no_chip_erase,
+            ..
+        }) = parse(&settings, &["flash", "fw.hex"])
+        else {
+            panic!("expected flash");
+        };
+        assert_eq!(target.chip, "stm32f407zet6");
+        assert_eq!(target.connect.speed, 8000);
+        assert!(no_verify && !no_chip_erase);
+
+        let Ok(Command::Verify { target, .. }) =
+            parse(&settings, &["verify", "--file", "fw.hex", "--chip", "x"])
+        else {
+            panic!("expected verify");
+        };
+        assert_eq!(target.chip, "x");
+        assert!(matches!(
+            parse(&settings, &["info"]),
+            Ok(Command::Info { chip: None, .. })
+        ));
+        assert!(parse(&Settings::default(), &["flash", "fw.hex"]).is_err());
+    }
+}
@@ -1,24 +1,735 @@
-use std::env;
 use std::ffi::{CStr, CString, c_char};
-use std::io::{self, Write};
-use std::path::PathBuf;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{OnceLock, mpsc};
+use std::time::{Duration, Instant, SystemTime};
 
-use windows_sys::Win32::System::LibraryLoader::{GetProcAddress, LoadLibraryA};
+use clap::{ArgGroup, Args, CommandFactory, FromArgMatches, Parser, Subcommand, ValueEnum};
+#[cfg(not(feature = "static"))]
+use libloading::Library;
+use serde_json::{Value, json};
 
-// English comments: minimal CLI using libloading to call probe_rs_lib.dll
+// English comments: minimal CLI using libloading to call probe_rs_lib (.dll, .so or .dylib);
+// with the "static" feature the library is linked in and called directly
 
-#[derive(Clone, Copy)]
+mod config;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
 enum Protocol {
     Auto,
     Swd,
     Jtag,
 }
 
+// English comments: what the target does after flashing, see pr_flash_set_after
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum After {
+    /// Leave the core halted
+    None,
+    /// Reset and run
+    Reset,
+    /// Reset and halt
+    Halt,
+    /// Start at the ELF entry point
+    Run,
+}
+
+// English comments: what the verify step compares, numbered as for
+// pr_flash_option_verify_mode
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum VerifyMode {
+    /// The programmed pages
+    Pages,
+    /// Only the bytes of the image
+    Written,
+    /// Every sector the image touches, expecting erased bytes around the image
+    Sectors,
+}
+
+// English comments: how flash progress is printed
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Progress {
+    /// Status lines with percent and ETA on stdout
+    Human,
+    /// One JSON object per event on stderr
+    Json,
+}
+
+// English comments: library log levels, numbered as for pr_set_log_callback
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum LogLevel {
+    Error = 1,
+    Warn,
+    Info,
+    Debug,
+    Trace,
+}
+
+// English comments: how `trace` decodes the SWO data
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Decode {
+    /// Output of the ITM stimulus ports, e.g. ITM_SendChar
+    Itm,
+}
+
+// English comments: exit codes by kind of failure, a contract for scripts (see the README);
+// clap exits with 2 on invalid arguments as well
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Exit {
+    Failed = 1,
+    Usage = 2,
+    // English comments: loading the library failed, not with "static"
+    #[cfg_attr(feature = "static", allow(dead_code))]
+    Library = 3,
+    ProbeNotFound = 10,
+    AttachFailed = 11,
+    VerifyFailed = 12,
+    FileError = 13,
+    FlashFailed = 14,
+    TargetAccess = 15,
+    UnknownChip = 16,
+    Timeout = 17,
+}
+
+impl Exit {
+    fn name(self) -> &'static str {
+        match self {
+            Exit::Failed => "failed",
+            Exit::Usage => "usage",
+            Exit::Library => "library",
+            Exit::ProbeNotFound => "probe-not-found",
+            Exit::AttachFailed => "target-attach-failed",
+            Exit::VerifyFailed => "flash-verify-failed",
+            Exit::FileError => "file-error",
+            Exit::FlashFailed => "flash-failed",
+            Exit::TargetAccess => "target-access-failed",
+            Exit::UnknownChip => "unknown-chip",
+            Exit::Timeout => "timeout",
+        }
+    }
+
+    // English comments: the library reports errors as text; recognize the kinds that can come
+    // up in any call, e.g. pr_flash_auto also attaches, else keep the kind of the operation
+    fn of_message(self, message: &str) -> Exit {
+        const PROBE: [&str; 4] = [
+            "no matching probes found",
+            "no probe found",
+            "open probe error",
+            "selector parse error",
+        ];
+        const FILE: [&str; 5] = [
+            "failed to read ",
+            "failed to parse elf",
+            "unsupported file format",
+            "no defmt data",
+            "defmt data error",
+        ];
+        let lower = message.to_lowercase();
+        if PROBE.iter().any(|p| message.starts_with(p)) {
+            Exit::ProbeNotFound
+        } else if message.starts_with("attach error") {
+            Exit::AttachFailed
+        } else if message.starts_with("get_target_by_name error")
+            || message.starts_with("unknown chip")
+        {
+            Exit::UnknownChip
+        } else if lower.contains("verification failed") {
+            Exit::VerifyFailed
+        } else if FILE.iter().any(|p| message.starts_with(p)) {
+            Exit::FileError
+        } else {
+            self
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+enum Width {
+    #[value(name = "8")]
+    W8,
+    #[value(name = "16")]
+    W16,
+    #[value(name = "32")]
+    W32,
+}
+
+impl Width {
+    fn bytes(self) -> u64 {
+        match self {
+            Width::W8 => 1,
+            Width::W16 => 2,
+            Width::W32 => 4,
+        }
+    }
+}
+
+#[derive(Parser, Debug)]
+#[command(version, about = "Test tool calling probe-rs-lib through its C ABI")]
+struct Cli {
+    /// Path to probe_rs_lib (.dll, .so or .dylib) [default: next to the executable]
+    #[arg(long, global = true, value_name = "PATH", env = "PRL_DLL")]
+    dll: Option<PathBuf>,
+
+    /// Print results and errors as JSON on stdout, one object per result
+    #[arg(long, global = true)]
+    json: bool,
+
+    /// Flash progress format; json prints {"phase", "percent", "bytes", "total", "eta_ms"}
+    /// lines on stderr, also with --json
+    #[arg(long, global = true, value_enum, default_value_t = Progress::Human)]
+    progress: Progress,
+
+    /// Use the [profile.NAME] settings of probe-rs-lib.toml
+    #[arg(long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Print the library's log records up to this level on stderr, or write them to --log-file;
+    /// debug and trace slow down probe communication
+    #[arg(
+        long,
+        global = true,
+        value_enum,
+        value_name = "LEVEL",
+        env = "PRL_LOG_LEVEL"
+    )]
+    log_level: Option<LogLevel>,
+
+    /// Append the library's log records to this file instead, rotated at 10 MiB
+    /// [default level: debug]
+    #[arg(long, global = true, value_name = "PATH", env = "PRL_LOG_FILE")]
+    log_file: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Args, Debug)]
+struct ProgrammerArgs {
+    /// Only use probes of one driver: cmsis-dap, stlink, jlink, ftdi, esp-usb-jtag, wch-link,
+    /// sifli-uart, glasgow, ch347-usb-jtag or blackmagic
+    #[arg(long, value_name = "TYPE", env = "PRL_PROGRAMMER_TYPE")]
+    programmer_type: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct ConnectArgs {
+    #[command(flatten)]
+    programmer: ProgrammerArgs,
+
+    /// Wire protocol
+    #[arg(long, value_enum, default_value_t = Protocol::Auto)]
+    protocol: Protocol,
+
+    /// Probe speed in kHz
+    #[arg(long, value_name = "KHZ", default_value_t = 4000, env = "PRL_SPEED")]
+    speed: u32,
+}
+
+#[derive(Args, Debug)]
+struct TargetArgs {
+    /// Target chip name, see `info`
+    #[arg(long, env = "PRL_CHIP")]
+    chip: String,
+
+    /// Probe to use: VID:PID[:SERIAL], tcp:HOST:PORT or glasgow:tcp:HOST:PORT [default: the
+    /// first probe, or a choice if several are connected and stdin is a terminal]
+    #[arg(long, value_name = "SELECTOR", env = "PRL_PROBE")]
+    probe: Option<String>,
+
+    /// Probe to use by its index in `list` (among probes of --programmer-type, if given);
+    /// overrides --probe
+    #[arg(long, value_name = "N")]
+    probe_index: Option<u32>,
+
+    #[command(flatten)]
+    connect: ConnectArgs,
+}
+
+#[derive(Args, Debug)]
+struct AlgorithmArgs {
+    /// Run the flash algorithm in this RAM (END exclusive) instead of the RAM probe-rs picks,
+    /// for chips whose boot ROM or DMA buffers use part of it
+    #[arg(long, value_name = "START..END", value_parser = parse_range)]
+    algorithm_ram: Option<Range<u64>>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List connected probes and whether a target answers on them
+    List {
+        #[command(flatten)]
+        programmer: ProgrammerArgs,
+    },
+    /// Show the chip database, the spec of one chip, or check the connection to it
+    Info {
+        /// Chip to show [default: all manufacturers and models]
+        #[arg(long)]
+        chip: Option<String>,
+
+        /// Open a session to the chip instead of printing its spec
+        #[arg(long, requires = "chip")]
+        connect: bool,
+
+        /// Probe to use with --connect, as for `flash`
+        #[arg(long, value_name = "SELECTOR", requires = "connect")]
+        probe: Option<String>,
+
+        /// Probe to use with --connect by its index, as for `flash`
+        #[arg(long, value_name = "N", requires = "connect", conflicts_with = "probe")]
+        probe_index: Option<u32>,
+
+        #[command(flatten)]
+        connect_args: ConnectArgs,
+    },
+    /// Identify the target of a probe: debug port and ROM table IDs, the chip autodetection
+    /// finds and the best-matching chip names
+    Detect {
+        /// Probe to use, as for `flash`
+        #[arg(long, value_name = "SELECTOR", env = "PRL_PROBE")]
+        probe: Option<String>,
+
+        /// Probe to use by its index, as for `flash`; overrides --probe
+        #[arg(long, value_name = "N")]
+        probe_index: Option<u32>,
+
+        #[command(flatten)]
+        connect: ConnectArgs,
+    },
+    /// Measure RAM write and read throughput at several block sizes and show the clock the probe
+    /// runs at, the numbers to attach to "flashing is slow" reports
+    Bench {
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// RAM address to use [default: the first RAM region of the core]
+        #[arg(long, value_name = "ADDRESS", value_parser = parse_number)]
+        address: Option<u64>,
+
+        /// Bytes to transfer per block size [default: up to 64 KiB of the RAM region]
+        #[arg(long, value_parser = parse_number)]
+        size: Option<u64>,
+
+        /// Core to access the RAM through
+        #[arg(long, default_value_t = 0)]
+        core: u32,
+    },
+    /// Flash an image; the format (ELF, HEX or BIN) is detected from the extension
+    Flash {
+        /// Image to flash
+        file: PathBuf,
+
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Load address of a .bin image
+        #[arg(long, value_name = "ADDRESS", value_parser = parse_number)]
+        base: Option<u64>,
+
+        /// Do not read back the flash after programming
+        #[arg(long)]
+        no_verify: bool,
+
+        /// What the verify step after programming compares
+        #[arg(long, value_enum, default_value_t = VerifyMode::Pages, conflicts_with = "no_verify")]
+        verify_mode: VerifyMode,
+
+        /// Use a flash algorithm that takes compressed pages where the chip has one, so less
+        /// data goes over USB
+        #[arg(long)]
+        prefer_compressed: bool,
+
+        /// Transfer a page only after the previous one is programmed, for targets that fail with
+        /// double buffering
+        #[arg(long)]
+        single_buffer: bool,
+
+        /// Program pages of at most this many bytes (at least 64), halving the flash
+        /// algorithm's page size
+        #[arg(long, value_name = "BYTES", value_parser = clap::value_parser!(u32).range(64..))]
+        max_page_size: Option<u32>,
+
+        /// Compare with the flash contents first and skip what is already programmed
+        #[arg(long)]
+        preverify: bool,
+
+        /// Erase only the sectors the image touches instead of the whole chip
+        #[arg(long)]
+        no_chip_erase: bool,
+
+        /// What the target does after flashing
+        #[arg(long, value_enum, default_value_t = After::None)]
+        after: After,
+
+        /// Keep running and flash again whenever the image file changes, e.g. after a rebuild
+        #[arg(long)]
+        watch: bool,
+
+        /// With --watch, print RTT channel 0 after each flash until the image changes; use with
+        /// --after reset to start the new firmware
+        #[arg(long, requires = "watch")]
+        rtt: bool,
+
+        /// Print the sectors that would be erased and the pages that would be programmed, then
+        /// exit without connecting to the target
+        #[arg(long, conflicts_with = "watch")]
+        dry_run: bool,
+
+        #[command(flatten)]
+        algorithm: AlgorithmArgs,
+
+        /// Flash as a resumable job that keeps its progress in this file, sector by sector and
+        /// without a chip erase; if the file exists, the interrupted job is continued
+        #[arg(long, value_name = "STATE", conflicts_with_all = ["watch", "dry_run", "preverify"])]
+        job: Option<PathBuf>,
+
+        /// Program only the sectors that differ from this image, which the target must hold;
+        /// nothing is read back to compare
+        #[arg(
+            long,
+            value_name = "OLD_IMAGE",
+            conflicts_with_all = ["watch", "dry_run", "preverify", "job"]
+        )]
+        diff_from: Option<PathBuf>,
+    },
+    /// Compare the target memory with an image without programming; fails at the first
+    /// differing address
+    Verify {
+        /// Image to compare with; the format (ELF, HEX or BIN) is detected from the extension
+        #[arg(long)]
+        file: PathBuf,
+
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Load address of a .bin image
+        #[arg(long, value_name = "ADDRESS", value_parser = parse_number)]
+        base: Option<u64>,
+
+        /// Core to read through
+        #[arg(long, default_value_t = 0)]
+        core: u32,
+    },
+    /// Erase the whole flash of a chip, an address range or a single sector
+    Erase {
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Erase the sectors overlapping START..END (END exclusive) instead of the whole chip
+        #[arg(long, value_name = "START..END", value_parser = parse_range)]
+        range: Option<Range<u64>>,
+
+        /// Erase only the sector holding this address
+        #[arg(long, value_name = "ADDRESS", value_parser = parse_number, conflicts_with = "range")]
+        sector: Option<u64>,
+
+        /// Print the sectors that would be erased, then exit without connecting to the target
+        #[arg(long)]
+        dry_run: bool,
+
+        #[command(flatten)]
+        algorithm: AlgorithmArgs,
+    },
+    /// Read memory and print it as hex words or a hex dump, or save it to a file
+    Read {
+        /// Start address
+        #[arg(long, value_parser = parse_number)]
+        address: u64,
+
+        /// Number of bytes to read, a multiple of the access width
+        #[arg(long, value_parser = parse_number, default_value = "4")]
+        length: u64,
+
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Access width in bits
+        #[arg(long, value_enum, default_value_t = Width::W32)]
+        width: Width,
+
+        /// Write the bytes to this file instead of printing them
+        #[arg(long, value_name = "FILE")]
+        out: Option<PathBuf>,
+
+        /// Print a hex dump with offsets and ASCII instead of words
+        #[arg(long, conflicts_with = "out")]
+        hexdump: bool,
+
+        /// Core to read through
+        #[arg(long, default_value_t = 0)]
+        core: u32,
+    },
+    /// Write values (e.g. a register) or the contents of a file to memory
+    #[command(group(ArgGroup::new("data").required(true).args(["value", "file"])))]
+    Write {
+        /// Start address
+        #[arg(long, value_parser = parse_number)]
+        address: u64,
+
+        /// Value to write, e.g. 0x00000001; repeat or separate with commas for consecutive words
+        #[arg(long, value_parser = parse_number, value_delimiter = ',')]
+        value: Vec<u64>,
+
+        /// Write the bytes of this file, e.g. a blob for RAM
+        #[arg(long, value_name = "FILE")]
+        file: Option<PathBuf>,
+
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Access width in bits; a file is written in words of this width
+        #[arg(long, value_enum, default_value_t = Width::W32)]
+        width: Width,
+
+        /// Core to write through
+        #[arg(long, default_value_t = 0)]
+        core: u32,
+    },
+    /// Reset a core through the debug port or the reset line (--hw), and let it run or halt it
+    Reset {
+        /// Target chip name, see `info`; not needed for --hw without --halt
+        #[arg(
+            long,
+            env = "PRL_CHIP",
+            required_unless_present = "hw",
+            required_if_eq("halt", "true")
+        )]
+        chip: Option<String>,
+
+        /// Probe to use: VID:PID[:SERIAL], tcp:HOST:PORT or glasgow:tcp:HOST:PORT [default: the
+        /// first probe, or a choice if several are connected and stdin is a terminal]
+        #[arg(long, value_name = "SELECTOR", env = "PRL_PROBE")]
+        probe: Option<String>,
+
+        /// Probe to use by its index in `list` (among probes of --programmer-type, if given);
+        /// overrides --probe
+        #[arg(long, value_name = "N")]
+        probe_index: Option<u32>,
+
+        #[command(flatten)]
+        connect: ConnectArgs,
+
+        /// Halt the core at the reset vector and leave it halted
+        #[arg(long)]
+        halt: bool,
+
+        /// Pulse the probe's reset line instead of a reset through the debug port; with --halt
+        /// the target is attached under reset
+        #[arg(long, conflicts_with = "connect_under_reset")]
+        hw: bool,
+
+        /// Hold the reset line while connecting, for firmware that disables the debug pins
+        #[arg(long)]
+        connect_under_reset: bool,
+
+        /// Core to reset
+        #[arg(long, default_value_t = 0)]
+        core: u32,
+    },
+    /// Print RTT output, decoding defmt with --elf, and send lines from stdin to the target
+    /// until Ctrl-C
+    Rtt {
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Up channel with the firmware output
+        #[arg(long, alias = "up", default_value_t = 0)]
+        channel: u32,
+
+        /// Down channel for the input
+        #[arg(long, default_value_t = 0)]
+        down: u32,
+
+        /// Firmware ELF file to decode defmt output with
+        #[arg(long, value_name = "FILE")]
+        elf: Option<PathBuf>,
+
+        /// Do not start each line with the time since attaching, in seconds
+        #[arg(long)]
+        no_timestamps: bool,
+
+        /// Stop after this many seconds
+        #[arg(long, value_name = "SECONDS")]
+        duration: Option<u64>,
+    },
+    /// Print SWO trace data: with --decode itm the ITM stimulus port output of firmware printing
+    /// through ITM, else the raw bytes; for chips where RTT is not available
+    Trace {
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// SWO baud rate, supported by the probe and a divisor of --clock
+        #[arg(long, default_value_t = 2_000_000)]
+        baud: u32,
+
+        /// Trace clock of the target in Hz, usually the core clock the firmware configured
+        #[arg(long, value_name = "HZ")]
+        clock: u32,
+
+        /// Decode the trace data instead of printing it raw
+        #[arg(long, value_enum)]
+        decode: Option<Decode>,
+
+        /// Stimulus ports to print with --decode itm [default: all]
+        #[arg(
+            long,
+            value_name = "N",
+            value_delimiter = ',',
+            requires = "decode",
+            value_parser = clap::value_parser!(u8).range(0..32)
+        )]
+        port: Vec<u8>,
+
+        /// Core whose ITM is traced
+        #[arg(long, default_value_t = 0)]
+        core: u32,
+
+        /// Stop after this many seconds
+        #[arg(long, value_name = "SECONDS")]
+        duration: Option<u64>,
+    },
+    /// Flash an image, reset and print the semihosting and RTT output until the firmware exits
+    /// through semihosting; exits with the firmware's exit code, for on-target tests
+    Run {
+        /// Image to flash
+        #[arg(long)]
+        file: PathBuf,
+
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Load address of a .bin image
+        #[arg(long, value_name = "ADDRESS", value_parser = parse_number)]
+        base: Option<u64>,
+
+        /// Do not look for RTT up channel 0 output
+        #[arg(long)]
+        no_rtt: bool,
+
+        /// Fail if the firmware has not exited after this many seconds
+        #[arg(long, value_name = "SECONDS")]
+        timeout: Option<u64>,
+
+        /// Core running the firmware
+        #[arg(long, default_value_t = 0)]
+        core: u32,
+
+        #[command(flatten)]
+        algorithm: AlgorithmArgs,
+    },
+    /// Use a target held by a GDB server (e.g. `probe-rs gdb`): flash, then run monitor
+    /// commands
+    Gdb {
+        /// Server address, host:port
+        address: String,
+
+        /// Connection and reply timeout in ms
+        #[arg(long, default_value_t = 5000)]
+        timeout_ms: u32,
+
+        /// Image to flash
+        #[arg(long, value_name = "FILE")]
+        flash: Option<PathBuf>,
+
+        /// Load address of a .bin image
+        #[arg(long, value_name = "ADDRESS", value_parser = parse_number, requires = "flash")]
+        base: Option<u64>,
+
+        /// Monitor command, e.g. "reset halt"; may be repeated
+        #[arg(long, value_name = "COMMAND")]
+        monitor: Vec<String>,
+    },
+    /// Optionally flash an image, then serve the target to GDB or an IDE on 127.0.0.1 until
+    /// Ctrl-C
+    GdbServer {
+        #[command(flatten)]
+        target: TargetArgs,
+
+        /// Port to listen on
+        #[arg(long, default_value_t = 1337)]
+        port: u16,
+
+        /// Image to flash first; the core is left halted after the reset
+        #[arg(long, value_name = "FILE")]
+        flash: Option<PathBuf>,
+
+        /// Load address of a .bin image
+        #[arg(long, value_name = "ADDRESS", value_parser = parse_number, requires = "flash")]
+        base: Option<u64>,
+
+        /// Core to serve
+        #[arg(long, default_value_t = 0)]
+        core: u32,
+    },
+}
+
+// English comments: numbers may be given in decimal or with a 0x, 0b or 0o prefix
+fn parse_number(s: &str) -> Result<u64, String> {
+    let s = s.trim();
+    let (digits, radix) = if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        (hex, 16)
+    } else if let Some(bin) = s.strip_prefix("0b").or_else(|| s.strip_prefix("0B")) {
+        (bin, 2)
+    } else if let Some(oct) = s.strip_prefix("0o").or_else(|| s.strip_prefix("0O")) {
+        (oct, 8)
+    } else {
+        (s, 10)
+    };
+    u64::from_str_radix(digits, radix).map_err(|e| e.to_string())
+}
+
+// English comments: START..END with END exclusive, both as for parse_number
+fn parse_range(s: &str) -> Result<Range<u64>, String> {
+    let (start, end) = s.split_once("..").ok_or("expected START..END")?;
+    let range = parse_number(start)?..parse_number(end)?;
+    if range.is_empty() {
+        return Err("END must be above START".to_string());
+    }
+    Ok(range)
+}
+
 type ProgressCb = unsafe extern "C" fn(i32, f32, *const c_char, i32);
+type ProgressBytesFn = unsafe extern "C" fn(*mut u64, *mut u64) -> i32;
+type ProgressIndeterminateFn = unsafe extern "C" fn() -> i32;
+type LogCb = unsafe extern "C" fn(i32, *const c_char, *const c_char);
+
+// English comments: one list of the library functions used generates the struct and both
+// ways of filling it, looked up in the dynamic library or linked in with "static"
+macro_rules! ffi {
+    ($($name:ident: $ty:ty,)*) => {
+        struct Ffi {
+            // English comments: keeps the library loaded as long as the function pointers are
+            // used
+            #[cfg(not(feature = "static"))]
+            _lib: Library,
+            $($name: $ty,)*
+        }
+
+        #[cfg(not(feature = "static"))]
+        fn resolve(lib: Library) -> Ffi {
+            unsafe {
+                Ffi {
+                    $($name: load(&lib, stringify!($name)),)*
+                    _lib: lib,
+                }
+            }
+        }
+
+        // English comments: the "static" feature calls the linked-in functions, no lookup
+        #[cfg(feature = "static")]
+        fn load_ffi() -> Ffi {
+            Ffi {
+                $($name: probe_rs_lib::$name,)*
+            }
+        }
+    };
+}
 
-struct Ffi {
+ffi! {
     pr_last_error: unsafe extern "C" fn(*mut c_char, usize) -> usize,
     pr_probe_count: unsafe extern "C" fn() -> u32,
+    pr_probe_count_filtered: unsafe extern "C" fn(i32) -> i32,
     pr_probe_info: unsafe extern "C" fn(
         u32,
         *mut c_char,
@@ -28,14 +739,39 @@ struct Ffi {
         *mut c_char,
         usize,
     ) -> i32,
+    pr_probe_info_filtered: unsafe extern "C" fn(
+        i32,
+        u32,
+        *mut c_char,
+        usize,
+        *mut u16,
+        *mut u16,
+        *mut c_char,
+        usize,
+    ) -> i32,
     pr_probe_features: unsafe extern "C" fn(u32, *mut u32, *mut u32) -> i32,
     pr_probe_check_target: unsafe extern "C" fn(u32) -> i32,
+    pr_target_detect: unsafe extern "C" fn(*const c_char, u32, i32, *mut c_char, usize) -> usize,
+    pr_benchmark: unsafe extern "C" fn(
+        *const c_char,
+        *const c_char,
+        u32,
+        i32,
+        u32,
+        u64,
+        u64,
+        *mut c_char,
+        usize,
+    ) -> usize,
     pr_session_open_auto: unsafe extern "C" fn(*const c_char, u32, i32) -> u64,
     pr_session_open_with_probe: unsafe extern "C" fn(*const c_char, *const c_char, u32, i32) -> u64,
     pr_session_close: unsafe extern "C" fn(u64) -> i32,
     pr_set_progress_callback: unsafe extern "C" fn(ProgressCb),
+    pr_set_log_callback: unsafe extern "C" fn(i32, Option<LogCb>) -> i32,
+    pr_enable_file_logging: unsafe extern "C" fn(*const c_char, i32) -> i32,
     pr_clear_progress_callback: unsafe extern "C" fn(),
-    // removed unused getters to eliminate dead_code warnings and keep CLI lean
+    pr_progress_bytes: ProgressBytesFn,
+    pr_progress_indeterminate: ProgressIndeterminateFn,
     pr_flash_auto: unsafe extern "C" fn(
         *const c_char,
         *const c_char,
@@ -47,7 +783,51 @@ struct Ffi {
         u32,
         i32,
     ) -> i32,
+    pr_session_flash: unsafe extern "C" fn(u64, *const c_char, u64, u32, i32, i32, i32) -> i32,
+    pr_flash_job: unsafe extern "C" fn(
+        *const c_char,
+        *const c_char,
+        u64,
+        u32,
+        i32,
+        u32,
+        i32,
+        *const c_char,
+    ) -> i32,
+    pr_flash_resume: unsafe extern "C" fn(*const c_char) -> i32,
+    pr_flash_diff: unsafe extern "C" fn(
+        *const c_char,
+        *const c_char,
+        *const c_char,
+        u64,
+        u32,
+        i32,
+        u32,
+        i32,
+        *mut u32,
+    ) -> i32,
+    pr_flash_set_after: unsafe extern "C" fn(i32) -> i32,
+    pr_flash_option_algorithm_ram: unsafe extern "C" fn(u64, u64) -> i32,
+    pr_flash_option_verify_mode: unsafe extern "C" fn(i32) -> i32,
+    pr_flash_option_compression: unsafe extern "C" fn(i32) -> i32,
+    pr_flash_option_buffering: unsafe extern "C" fn(i32, u32) -> i32,
+    pr_flash_last_timing: unsafe extern "C" fn(*mut c_char, usize) -> usize,
     pr_chip_erase: unsafe extern "C" fn(*const c_char, u32, i32) -> i32,
+    pr_session_erase_range: unsafe extern "C" fn(u64, u64, u64, *mut u64, *mut u64) -> i32,
+    pr_flash_plan: unsafe extern "C" fn(
+        *const c_char,
+        *const c_char,
+        *const c_char,
+        u64,
+        i32,
+        *mut c_char,
+        usize,
+    ) -> usize,
+    pr_flash_erase_plan: unsafe extern "C" fn(*const c_char, u64, u64, *mut c_char, usize) -> usize,
+    pr_session_verify:
+        unsafe extern "C" fn(u64, u32, *const c_char, *const c_char, u64, *mut u64) -> i32,
+    pr_probe_hw_reset: unsafe extern "C" fn(*const c_char, u32) -> i32,
+    pr_set_attach_under_reset: unsafe extern "C" fn(i32) -> i32,
     pr_set_programmer_type_code: unsafe extern "C" fn(i32) -> i32,
     pr_programmer_type_is_supported_code: unsafe extern "C" fn(i32) -> i32,
     pr_programmer_type_from_string: unsafe extern "C" fn(*const c_char, *mut i32) -> i32,
@@ -55,229 +835,226 @@ struct Ffi {
     pr_chip_manufacturer_name: unsafe extern "C" fn(u32, *mut c_char, usize) -> usize,
     pr_chip_model_count: unsafe extern "C" fn(u32) -> u32,
     pr_chip_model_name: unsafe extern "C" fn(u32, u32, *mut c_char, usize) -> usize,
-    pr_chip_model_specs: unsafe extern "C" fn(u32, u32, *mut c_char, usize) -> usize,
     pr_chip_specs_by_name: unsafe extern "C" fn(*const c_char, *mut c_char, usize) -> usize,
+    pr_flash_sector_layout: unsafe extern "C" fn(*const c_char, *mut c_char, usize) -> usize,
+    pr_read_8: unsafe extern "C" fn(u64, u32, u64, *mut u8, u32) -> i32,
     pr_read_16: unsafe extern "C" fn(u64, u32, u64, *mut u16, u32) -> i32,
+    pr_read_32: unsafe extern "C" fn(u64, u32, u64, *mut u32, u32) -> i32,
+    pr_write_8: unsafe extern "C" fn(u64, u32, u64, *const u8, u32) -> i32,
     pr_write_16: unsafe extern "C" fn(u64, u32, u64, *const u16, u32) -> i32,
+    pr_write_32: unsafe extern "C" fn(u64, u32, u64, *const u32, u32) -> i32,
+    pr_session_close_ex: unsafe extern "C" fn(u64, i32) -> i32,
+    pr_core_reset: unsafe extern "C" fn(u64, u32) -> i32,
+    pr_core_reset_and_halt: unsafe extern "C" fn(u64, u32, u32) -> i32,
+    pr_terminal_open: unsafe extern "C" fn(u64, u32, u32) -> u64,
+    pr_terminal_open_defmt: unsafe extern "C" fn(u64, u32, u32, *const c_char) -> u64,
+    pr_terminal_read: unsafe extern "C" fn(u64, *mut u8, usize) -> i32,
+    pr_terminal_write_line: unsafe extern "C" fn(u64, *const c_char) -> i32,
+    pr_terminal_close: unsafe extern "C" fn(u64) -> i32,
+    pr_semihosting_poll: unsafe extern "C" fn(u64, u32, *mut i32) -> i32,
+    pr_semihosting_read: unsafe extern "C" fn(u64, *mut u8, usize) -> i32,
+    pr_swo_start: unsafe extern "C" fn(u64, u32, u32, u32, u32) -> i32,
+    pr_swo_read: unsafe extern "C" fn(u64, *mut u8, usize) -> i32,
+    pr_swo_stop: unsafe extern "C" fn(u64) -> i32,
+    pr_gdb_attach: unsafe extern "C" fn(*const c_char, u32) -> u64,
+    pr_gdb_detach: unsafe extern "C" fn(u64) -> i32,
+    pr_gdb_monitor: unsafe extern "C" fn(u64, *const c_char, *mut c_char, usize) -> usize,
+    pr_gdb_flash: unsafe extern "C" fn(u64, *const c_char, *const c_char, u64) -> i32,
+    pr_gdb_server_start: unsafe extern "C" fn(u64, u32, u16) -> i32,
 }
 
-fn load_ffi(dll_path: &str) -> Ffi {
-    unsafe {
-        let dll_c = CString::new(dll_path).unwrap();
-        let h = LoadLibraryA(dll_c.as_ptr() as *const u8);
-        if h.is_null() {
-            panic!("LoadLibraryA failed");
-        }
-        let load = |name: &str| {
-            let name_c = CString::new(name).unwrap();
-            let p = GetProcAddress(h, name_c.as_ptr() as *const u8);
-            if p.is_none() {
-                panic!("GetProcAddress failed for {}", name);
-            }
-            p.unwrap()
-        };
-        Ffi {
-            pr_last_error: std::mem::transmute(load("pr_last_error")),
-            pr_probe_count: std::mem::transmute(load("pr_probe_count")),
-            pr_probe_info: std::mem::transmute(load("pr_probe_info")),
-            pr_probe_features: std::mem::transmute(load("pr_probe_features")),
-            pr_probe_check_target: std::mem::transmute(load("pr_probe_check_target")),
-            pr_session_open_auto: std::mem::transmute(load("pr_session_open_auto")),
-            pr_session_open_with_probe: std::mem::transmute(load("pr_session_open_with_probe")),
-            pr_session_close: std::mem::transmute(load("pr_session_close")),
-            pr_set_progress_callback: std::mem::transmute(load("pr_set_progress_callback")),
-            pr_clear_progress_callback: std::mem::transmute(load("pr_clear_progress_callback")),
-            pr_flash_auto: std::mem::transmute(load("pr_flash_auto")),
-            pr_chip_erase: std::mem::transmute(load("pr_chip_erase")),
-            pr_set_programmer_type_code: std::mem::transmute(load("pr_set_programmer_type_code")),
-            pr_programmer_type_is_supported_code: std::mem::transmute(load(
-                "pr_programmer_type_is_supported_code",
-            )),
-            pr_programmer_type_from_string: std::mem::transmute(load(
-                "pr_programmer_type_from_string",
-            )),
-            pr_chip_manufacturer_count: std::mem::transmute(load("pr_chip_manufacturer_count")),
-            pr_chip_manufacturer_name: std::mem::transmute(load("pr_chip_manufacturer_name")),
-            pr_chip_model_count: std::mem::transmute(load("pr_chip_model_count")),
-            pr_chip_model_name: std::mem::transmute(load("pr_chip_model_name")),
-            pr_chip_model_specs: std::mem::transmute(load("pr_chip_model_specs")),
-            pr_chip_specs_by_name: std::mem::transmute(load("pr_chip_specs_by_name")),
-            pr_read_16: std::mem::transmute(load("pr_read_16")),
-            pr_write_16: std::mem::transmute(load("pr_write_16")),
-        }
+#[cfg(not(feature = "static"))]
+unsafe fn load<T: Copy>(lib: &Library, name: &str) -> T {
+    match unsafe { lib.get::<T>(name.as_bytes()) } {
+        Ok(f) => *f,
+        Err(e) => panic!("symbol lookup failed for {}: {}", name, e),
     }
 }
 
-fn print_last_error(ffi: &Ffi) {
-    unsafe {
-        let need = (ffi.pr_last_error)(std::ptr::null_mut(), 0);
-        if need > 0 {
-            let mut buf = vec![0u8; need];
-            (ffi.pr_last_error)(buf.as_mut_ptr() as *mut c_char, buf.len());
-            eprintln!(
-                "probe-rs-lib error: {}",
-                String::from_utf8_lossy(&buf[..need - 1])
-            );
-        }
-    }
+// English comments: ABI version this CLI is built against, see PR_ABI_VERSION_* in the
+// header; pr_chip_db_prefetch arrived with minor version 38
+#[cfg(not(feature = "static"))]
+const ABI_VERSION_MAJOR: u32 = 1;
+#[cfg(not(feature = "static"))]
+const ABI_VERSION_MINOR: u32 = 38;
+
+#[cfg(not(feature = "static"))]
+type AbiVersionFn = unsafe extern "C" fn(*mut u32, *mut u32);
+
+// English comments: platform file name of the library, e.g. libprobe_rs_lib.so on Linux
+#[cfg(not(feature = "static"))]
+fn lib_file_name() -> String {
+    libloading::library_filename("probe_rs_lib")
+        .to_string_lossy()
+        .into_owned()
 }
 
-// English comments: split parsing into a testable function; keep public API unchanged
-fn parse_args_from<I: Iterator<Item = String>>(
-    mut args: I,
-) -> (
-    Option<String>,
-    Option<String>,
-    Option<PathBuf>,
-    Protocol,
-    u32,
-    Option<String>,
-    Option<u64>,
-    String,
-    bool,
-    bool,
-    bool,
-    Option<String>,
-    Option<u32>,
-    Vec<u16>,
-) {
-    // English comments: very simple argument parser without external crates
-    let mut chip = None;
-    let mut probe = None;
-    let mut file = None;
-    let mut protocol = Protocol::Auto;
-    let mut speed = 4000u32;
-    let mut op = None; // list|check|flash
-    let mut base = None; // for bin
-    let mut dll_hint = String::new();
-    let mut verify = true;
-    let mut preverify = false;
-    let mut chip_erase = true;
-    let mut programmer_type: Option<String> = None;
-    let mut len = None;
-    let mut data = Vec::new();
-
-    while let Some(a) = args.next() {
-        match a.as_str() {
-            "--chip" => chip = args.next(),
-            "--probe" => probe = args.next(),
-            "--file" => file = args.next().map(PathBuf::from),
-            "--protocol" => match args.next().as_deref() {
-                Some("swd") => protocol = Protocol::Swd,
-                Some("jtag") => protocol = Protocol::Jtag,
-                _ => protocol = Protocol::Auto,
-            },
-            "--speed" => speed = args.next().and_then(|v| v.parse().ok()).unwrap_or(speed),
-            "--format" => {
-                let _ = args.next(); /* deprecated: ignored */
-            }
-            "--op" => op = args.next(),
-            "--base" => {
-                base = args.next().and_then(|v| {
-                    let s = v.trim();
-                    if let Some(hex) = s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
-                        u64::from_str_radix(hex, 16).ok()
-                    } else if let Some(bin) = s.strip_prefix("0b").or_else(|| s.strip_prefix("0B"))
-                    {
-                        u64::from_str_radix(bin, 2).ok()
-                    } else if let Some(oct) = s.strip_prefix("0o").or_else(|| s.strip_prefix("0O"))
-                    {
-                        u64::from_str_radix(oct, 8).ok()
-                    } else {
-                        u64::from_str_radix(s, 10).ok()
-                    }
-                });
-            }
-            "--dll" => dll_hint = args.next().unwrap_or_default(),
-            "--programmer-type" => programmer_type = args.next(),
-            "--verify" => verify = true,
-            "--no-verify" => verify = false,
-            "--preverify" => preverify = true,
-            "--no-preverify" => preverify = false,
-            "--chip-erase" => chip_erase = true,
-            "--no-chip-erase" => chip_erase = false,
-            "--len" => len = args.next().and_then(|v| v.parse().ok()),
-            "--data" => {
-                if let Some(s) = args.next() {
-                    for part in s.split(',') {
-                        let part = part.trim();
-                        let val = if let Some(hex) =
-                            part.strip_prefix("0x").or_else(|| part.strip_prefix("0X"))
-                        {
-                            u16::from_str_radix(hex, 16).ok()
-                        } else {
-                            part.parse().ok()
-                        };
-                        if let Some(v) = val {
-                            data.push(v);
-                        }
-                    }
-                }
-            }
-            "--help" => {
-                println!(
-                    "Usage: --chip <name> --programmer-type <type> [--probe VID:PID[:SERIAL]] [--file <path>] [--protocol swd|jtag] [--speed KHZ] [--op list|check|flash|chips|spec|erase-all|read16|write16] [--base 0xADDR] [--dll <path>] [--verify|--no-verify] [--preverify|--no-preverify] [--chip-erase|--no-chip-erase] [--len N] [--data 0x1234,0x5678]\\nSupported programmer types: cmsis-dap, stlink, jlink, ftdi, esp-usb-jtag, wch-link, sifli-uart, glasgow, ch347-usb-jtag\\nExtra ops:\\n  chips  - list supported manufacturers and chip models\\n  spec   - print detailed spec of --chip\\n  erase-all - perform a full chip erase\\n  read16 - read 16-bit memory\\n  write16 - write 16-bit memory"
-                );
-                std::process::exit(0);
-            }
-            _ => {}
-        }
+// English comments: refuse an incompatible library before resolving the other functions
+#[cfg(not(feature = "static"))]
+fn check_abi_version(lib: &Library) {
+    let Ok(abi_version) = (unsafe { lib.get::<AbiVersionFn>(b"pr_abi_version") }) else {
+        let msg = format!("{} is too old: pr_abi_version not found", lib_file_name());
+        std::process::exit(error(&msg, Exit::Library));
+    };
+    let (mut major, mut minor) = (0u32, 0u32);
+    unsafe { abi_version(&mut major, &mut minor) };
+    if major != ABI_VERSION_MAJOR || minor < ABI_VERSION_MINOR {
+        let msg = format!(
+            "{} ABI {}.{} is incompatible, {}.{} or a later {}.x is required",
+            lib_file_name(),
+            major,
+            minor,
+            ABI_VERSION_MAJOR,
+            ABI_VERSION_MINOR,
+            ABI_VERSION_MAJOR
+        );
+        std::process::exit(error(&msg, Exit::Library));
     }
-    (
-        chip,
-        probe,
-        file,
-        protocol,
-        speed,
-        op,
-        base,
-        dll_hint,
-        verify,
-        preverify,
-        chip_erase,
-        programmer_type,
-        len,
-        data,
-    )
 }
 
-fn parse_args() -> (
-    Option<String>,
-    Option<String>,
-    Option<PathBuf>,
-    Protocol,
-    u32,
-    Option<String>,
-    Option<u64>,
-    String,
-    bool,
-    bool,
-    bool,
-    Option<String>,
-    Option<u32>,
-    Vec<u16>,
-) {
-    parse_args_from(env::args().skip(1))
+#[cfg(not(feature = "static"))]
+fn load_ffi(lib_path: &Path) -> Ffi {
+    let lib = match unsafe { Library::new(lib_path) } {
+        Ok(lib) => lib,
+        Err(e) => {
+            let msg = format!("failed to load {}: {}", lib_path.display(), e);
+            std::process::exit(error(&msg, Exit::Library));
+        }
+    };
+    check_abi_version(&lib);
+    resolve(lib)
 }
 
-fn find_dll(hint: &str) -> Option<PathBuf> {
+#[cfg(not(feature = "static"))]
+fn find_dll(hint: &Path) -> Option<PathBuf> {
     // English comments: try hint, then current exe dir, then dist paths in workspace
-    let mut candidates: Vec<PathBuf> = vec![];
-    if !hint.is_empty() {
-        candidates.push(PathBuf::from(hint));
-    }
+    let name = lib_file_name();
+    let mut candidates: Vec<PathBuf> = vec![hint.to_path_buf()];
     if let Ok(mut p) = std::env::current_exe() {
-        p.set_file_name("probe_rs_lib.dll");
+        p.set_file_name(&name);
         candidates.push(p);
     }
     if let Ok(manifest) = std::env::var("CARGO_MANIFEST_DIR") {
         let root = PathBuf::from(manifest).parent().unwrap().to_path_buf();
-        candidates.push(root.join("dist/probe-rs-lib/bin/release/probe_rs_lib.dll"));
-        candidates.push(root.join("dist/probe-rs-lib/bin/debug/probe_rs_lib.dll"));
+        candidates.push(root.join("dist/probe-rs-lib/bin/release").join(&name));
+        candidates.push(root.join("dist/probe-rs-lib/bin/debug").join(&name));
     }
     candidates.into_iter().find(|p| p.is_file())
 }
 
+#[cfg(not(feature = "static"))]
+fn open_ffi(dll_hint: Option<&Path>) -> Ffi {
+    let dll = match dll_hint {
+        None => {
+            let mut p = std::env::current_exe().expect("get current exe failed");
+            p.set_file_name(lib_file_name());
+            if !p.is_file() {
+                let msg = format!(
+                    "Required {} not found in executable directory",
+                    lib_file_name()
+                );
+                std::process::exit(error(&msg, Exit::Library));
+            }
+            p
+        }
+        Some(hint) => match find_dll(hint) {
+            Some(p) => p,
+            None => {
+                let msg = format!("{} not found; use --dll <path> to specify", lib_file_name());
+                std::process::exit(error(&msg, Exit::Library));
+            }
+        },
+    };
+    load_ffi(&dll)
+}
+
+#[cfg(feature = "static")]
+fn open_ffi(dll_hint: Option<&Path>) -> Ffi {
+    if dll_hint.is_some() {
+        eprintln!("--dll is ignored: probe-rs-lib is linked into this build");
+    }
+    load_ffi()
+}
+
+// English comments: set from --json before anything is printed
+static JSON_OUTPUT: AtomicBool = AtomicBool::new(false);
+
+fn json_output() -> bool {
+    JSON_OUTPUT.load(Ordering::Relaxed)
+}
+
+// English comments: set from --progress json; the callbacks have no context, so the
+// byte count and estimate functions are kept here as well
+static PROGRESS_JSON: OnceLock<(ProgressBytesFn, ProgressIndeterminateFn)> = OnceLock::new();
+
+// English comments: print a result as text, or as one line of JSON with --json
+fn report(text: impl std::fmt::Display, value: Value) {
+    if json_output() {
+        println!("{}", value);
+    } else {
+        println!("{}", text);
+    }
+}
+
+// English comments: print an error of the CLI on stderr, or as JSON on stdout, and return the
+// exit code of its kind
+fn error(message: &str, exit: Exit) -> i32 {
+    if json_output() {
+        let value = json!({ "error": message, "code": exit as i32, "category": exit.name() });
+        println!("{}", value);
+    } else {
+        eprintln!("{}", message);
+    }
+    exit as i32
+}
+
+fn last_error(ffi: &Ffi) -> Option<String> {
+    read_string(|buf, len| unsafe { (ffi.pr_last_error)(buf, len) })
+}
+
+// English comments: print the library error of an operation of kind `exit` and return the
+// exit code; attaching without any probe present means no probe was found
+fn fail(ffi: &Ffi, exit: Exit) -> i32 {
+    let msg = last_error(ffi).unwrap_or_default();
+    let exit = match exit.of_message(&msg) {
+        Exit::AttachFailed if unsafe { (ffi.pr_probe_count_filtered)(0) } == 0 => {
+            Exit::ProbeNotFound
+        }
+        exit => exit,
+    };
+    match msg.as_str() {
+        "" => error("probe-rs-lib error", exit),
+        msg if json_output() => error(msg, exit),
+        msg => error(&format!("probe-rs-lib error: {}", msg), exit),
+    }
+}
+
+// English comments: fail early on an unreadable file instead of after attaching
+fn check_file(path: &Path) -> Result<(), i32> {
+    match std::fs::File::open(path) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(error(
+            &format!("cannot read {}: {}", path.display(), e),
+            Exit::FileError,
+        )),
+    }
+}
+
+// English comments: read a string of the size-query convention (required size incl. NUL)
+fn read_string(f: impl Fn(*mut c_char, usize) -> usize) -> Option<String> {
+    let need = f(std::ptr::null_mut(), 0);
+    if need == 0 {
+        return None;
+    }
+    let mut buf = vec![0u8; need];
+    f(buf.as_mut_ptr() as *mut c_char, buf.len());
+    Some(
+        String::from_utf8_lossy(&buf)
+            .trim_end_matches('\0')
+            .to_string(),
+    )
+}
+
 fn proto_code(p: Protocol) -> i32 {
     match p {
         Protocol::Auto => 0,
@@ -286,354 +1063,1823 @@ fn proto_code(p: Protocol) -> i32 {
     }
 }
 
-fn main() {
-    let (
+fn after_code(a: After) -> i32 {
+    match a {
+        After::None => 0,
+        After::Reset => 1,
+        After::Halt => 2,
+        After::Run => 3,
+    }
+}
+
+fn c_string(s: &str) -> CString {
+    CString::new(s).unwrap_or_else(|_| {
+        std::process::exit(error(
+            &format!("argument contains a NUL character: {:?}", s),
+            Exit::Usage,
+        ));
+    })
+}
+
+fn c_path(p: &Path) -> CString {
+    c_string(&p.to_string_lossy())
+}
+
+// English comments: restrict the probes to one driver; without --programmer-type all are used
+fn set_programmer_type(ffi: &Ffi, args: &ProgrammerArgs) -> Result<(), i32> {
+    let Some(pt_str) = &args.programmer_type else {
+        return Ok(());
+    };
+    let c_pt = c_string(pt_str);
+    let mut code: i32 = -1;
+    let rc_conv = unsafe { (ffi.pr_programmer_type_from_string)(c_pt.as_ptr(), &mut code) };
+    if rc_conv != 0 || code < 0 {
+        return Err(error(
+            &format!("Unsupported programmer type: {}", pt_str),
+            Exit::Usage,
+        ));
+    }
+    if unsafe { (ffi.pr_programmer_type_is_supported_code)(code) } == 0 {
+        return Err(error(
+            &format!("Unsupported programmer type code: {}", pt_str),
+            Exit::Usage,
+        ));
+    }
+    match unsafe { (ffi.pr_set_programmer_type_code)(code) } {
+        0 => Ok(()),
+        _ => Err(fail(ffi, Exit::Usage)),
+    }
+}
+
+fn open_session(
+    ffi: &Ffi,
+    chip: &str,
+    probe: Option<&str>,
+    connect: &ConnectArgs,
+) -> Result<u64, i32> {
+    set_programmer_type(ffi, &connect.programmer)?;
+    let c_chip = c_string(chip);
+    let proto = proto_code(connect.protocol);
+    let handle = unsafe {
+        match probe {
+            Some(sel) => {
+                let c_sel = c_string(sel);
+                (ffi.pr_session_open_with_probe)(
+                    c_sel.as_ptr(),
+                    c_chip.as_ptr(),
+                    connect.speed,
+                    proto,
+                )
+            }
+            None => (ffi.pr_session_open_auto)(c_chip.as_ptr(), connect.speed, proto),
+        }
+    };
+    if handle == 0 {
+        return Err(fail(ffi, Exit::AttachFailed));
+    }
+    Ok(handle)
+}
+
+// English comments: run `f` on a session of the target, closing it afterwards
+fn with_session(ffi: &Ffi, target: &TargetArgs, f: impl FnOnce(u64) -> i32) -> i32 {
+    match open_session(ffi, &target.chip, target.probe.as_deref(), &target.connect) {
+        Ok(handle) => {
+            let rc = f(handle);
+            let _ = unsafe { (ffi.pr_session_close)(handle) };
+            rc
+        }
+        Err(rc) => rc,
+    }
+}
+
+impl Command {
+    // English comments: the --algorithm-ram of commands that run the flash algorithm
+    fn algorithm_ram(&self) -> Option<Range<u64>> {
+        match self {
+            Command::Flash { algorithm, .. }
+            | Command::Erase { algorithm, .. }
+            | Command::Run { algorithm, .. } => algorithm.algorithm_ram.clone(),
+            _ => None,
+        }
+    }
+
+    // English comments: the probe options of commands that attach to a probe
+    fn probe_selection(&mut self) -> Option<(&mut Option<String>, Option<u32>, &ProgrammerArgs)> {
+        match self {
+            // English comments: a dry run does not use a probe
+            Command::Flash { dry_run: true, .. } | Command::Erase { dry_run: true, .. } => None,
+            Command::Info {
+                connect: true,
+                probe,
+                probe_index,
+                connect_args,
+                ..
+            }
+            | Command::Reset {
+                probe,
+                probe_index,
+                connect: connect_args,
+                ..
+            }
+            | Command::Detect {
+                probe,
+                probe_index,
+                connect: connect_args,
+            } => Some((probe, *probe_index, &connect_args.programmer)),
+            Command::Flash { target, .. }
+            | Command::Verify { target, .. }
+            | Command::Erase { target, .. }
+            | Command::Read { target, .. }
+            | Command::Write { target, .. }
+            | Command::Rtt { target, .. }
+            | Command::Trace { target, .. }
+            | Command::Bench { target, .. }
+            | Command::Run { target, .. }
+            | Command::GdbServer { target, .. } => Some((
+                &mut target.probe,
+                target.probe_index,
+                &target.connect.programmer,
+            )),
+            Command::List { .. } | Command::Info { .. } | Command::Gdb { .. } => None,
+        }
+    }
+}
+
+// English comments: the selector of a listed probe; probes without a serial number cannot be
+// told apart by VID:PID
+fn probe_selector(vid: u16, pid: u16, serial: &str) -> Option<String> {
+    (!serial.is_empty()).then(|| format!("{:04x}:{:04x}:{}", vid, pid, serial))
+}
+
+// English comments: "name VID:PID SN=serial" and the selector of probe `index` among the
+// probes of the programmer type
+fn filtered_probe(ffi: &Ffi, index: u32) -> Result<(String, Option<String>), i32> {
+    let mut name = vec![0u8; 128];
+    let mut sn = vec![0u8; 128];
+    let (mut vid, mut pid) = (0u16, 0u16);
+    let rc = unsafe {
+        (ffi.pr_probe_info_filtered)(
+            0,
+            index,
+            name.as_mut_ptr() as *mut c_char,
+            name.len(),
+            &mut vid,
+            &mut pid,
+            sn.as_mut_ptr() as *mut c_char,
+            sn.len(),
+        )
+    };
+    if rc != 0 {
+        return Err(fail(ffi, Exit::ProbeNotFound));
+    }
+    let name = String::from_utf8_lossy(&name)
+        .trim_end_matches('\0')
+        .to_string();
+    let sn = String::from_utf8_lossy(&sn)
+        .trim_end_matches('\0')
+        .to_string();
+    let line = format!("{} {:04x}:{:04x} SN={}", name, vid, pid, sn);
+    Ok((line, probe_selector(vid, pid, &sn)))
+}
+
+// English comments: ask on the terminal which of `count` probes to use
+fn pick_probe(ffi: &Ffi, count: u32) -> Result<u32, i32> {
+    eprintln!("{} probes found:", count);
+    for i in 0..count {
+        eprintln!("  [{}] {}", i, filtered_probe(ffi, i)?.0);
+    }
+    eprint!("Probe to use [0-{}]: ", count - 1);
+    let mut line = String::new();
+    let _ = io::stdin().lock().read_line(&mut line);
+    match line.trim().parse::<u32>() {
+        Ok(i) if i < count => Ok(i),
+        _ => Err(error(
+            &format!("no probe selected: {:?}", line.trim()),
+            Exit::Usage,
+        )),
+    }
+}
+
+// English comments: turn --probe-index, or the choice on the terminal when several probes
+// could be meant, into the --probe selector the commands use
+fn select_probe(
+    ffi: &Ffi,
+    probe: &mut Option<String>,
+    index: Option<u32>,
+    programmer: &ProgrammerArgs,
+) -> Result<(), i32> {
+    // English comments: an index wins over a probe from PRL_PROBE or probe-rs-lib.toml
+    if probe.is_some() && index.is_none() {
+        return Ok(());
+    }
+    set_programmer_type(ffi, programmer)?;
+    let index = match index {
+        Some(index) => index,
+        None => {
+            let count = unsafe { (ffi.pr_probe_count_filtered)(0) };
+            if count <= 1 || !io::stdin().is_terminal() {
+                return Ok(());
+            }
+            pick_probe(ffi, count as u32)?
+        }
+    };
+    let (line, selector) = filtered_probe(ffi, index)?;
+    match selector {
+        Some(selector) => {
+            *probe = Some(selector);
+            Ok(())
+        }
+        None => Err(error(
+            &format!(
+                "probe {} ({}) has no serial number and cannot be selected",
+                index, line
+            ),
+            Exit::ProbeNotFound,
+        )),
+    }
+}
+
+// English comments: parse the command line with the settings of probe-rs-lib.toml as defaults;
+// a first lenient pass finds --profile and --json
+fn parse_cli() -> Cli {
+    let args: Vec<std::ffi::OsString> = std::env::args_os().collect();
+    let early = Cli::command()
+        .ignore_errors(true)
+        .try_get_matches_from(&args)
+        .ok();
+    let early_flag = |id: &str| {
+        early
+            .as_ref()
+            .and_then(|m| m.try_get_one::<bool>(id).ok()?.copied())
+    };
+    JSON_OUTPUT.store(early_flag("json").unwrap_or(false), Ordering::Relaxed);
+    let profile = early
+        .as_ref()
+        .and_then(|m| m.try_get_one::<String>("profile").ok()?.cloned());
+    let settings = match config::load(profile.as_deref()) {
+        Ok(settings) => settings,
+        Err(e) => std::process::exit(error(&e, Exit::Usage)),
+    };
+    let matches = settings.apply(Cli::command()).get_matches_from(&args);
+    Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit())
+}
+
+fn main() {
+    let cli = parse_cli();
+    JSON_OUTPUT.store(cli.json, Ordering::Relaxed);
+    let ffi = open_ffi(cli.dll.as_deref());
+    if cli.progress == Progress::Json {
+        let _ = PROGRESS_JSON.set((ffi.pr_progress_bytes, ffi.pr_progress_indeterminate));
+    }
+    if let Err(rc) = start_logging(&ffi, cli.log_level, cli.log_file.as_deref()) {
+        std::process::exit(rc);
+    }
+    std::process::exit(run(&ffi, cli.command));
+}
+
+fn run(ffi: &Ffi, mut command: Command) -> i32 {
+    if let Some((probe, index, programmer)) = command.probe_selection()
+        && let Err(rc) = select_probe(ffi, probe, index, programmer)
+    {
+        return rc;
+    }
+    if let Some(ram) = command.algorithm_ram()
+        && unsafe { (ffi.pr_flash_option_algorithm_ram)(ram.start, ram.end - ram.start) } != 0
+    {
+        return fail(ffi, Exit::Usage);
+    }
+    match command {
+        Command::List { programmer } => {
+            if let Err(rc) = set_programmer_type(ffi, &programmer) {
+                return rc;
+            }
+            list(ffi);
+            0
+        }
+        Command::Info { chip: None, .. } => {
+            chips(ffi);
+            0
+        }
+        Command::Info {
+            chip: Some(chip),
+            connect: false,
+            ..
+        } => chip_info(ffi, &chip),
+        Command::Info {
+            chip: Some(chip),
+            connect: true,
+            probe,
+            connect_args,
+            ..
+        } => match open_session(ffi, &chip, probe.as_deref(), &connect_args) {
+            Ok(handle) => {
+                let _ = unsafe { (ffi.pr_session_close)(handle) };
+                report(
+                    format!("Session opened and closed: {}", chip),
+                    json!({ "chip": chip, "connected": true }),
+                );
+                0
+            }
+            Err(rc) => rc,
+        },
+        Command::Detect { probe, connect, .. } => detect(ffi, probe.as_deref(), &connect),
+        Command::Bench {
+            target,
+            address,
+            size,
+            core,
+        } => bench(ffi, &target, address.unwrap_or(0), size.unwrap_or(0), core),
+        Command::Flash {
+            file,
+            target,
+            base,
+            no_verify,
+            verify_mode,
+            prefer_compressed,
+            single_buffer,
+            max_page_size,
+            preverify,
+            no_chip_erase,
+            after,
+            watch,
+            rtt,
+            dry_run,
+            job,
+            diff_from,
+            ..
+        } => {
+            let flags = [!no_verify, preverify, !no_chip_erase].map(i32::from);
+            let base = base.unwrap_or(0);
+            if unsafe { (ffi.pr_flash_option_verify_mode)(verify_mode as i32) } != 0
+                || unsafe { (ffi.pr_flash_option_compression)(i32::from(prefer_compressed)) } != 0
+                || unsafe {
+                    (ffi.pr_flash_option_buffering)(
+                        i32::from(!single_buffer),
+                        max_page_size.unwrap_or(0),
+                    )
+                } != 0
+            {
+                return fail(ffi, Exit::Usage);
+            }
+            if let Some(state) = job {
+                flash_job(ffi, &file, &target, base, !no_verify, after, &state)
+            } else if let Some(old) = diff_from {
+                flash_diff(ffi, &file, &target, base, !no_verify, after, &old)
+            } else if dry_run {
+                flash_plan(ffi, &file, &target.chip, base, !no_chip_erase)
+            } else if watch {
+                flash_watch(ffi, &file, &target, base, flags, after, rtt)
+            } else {
+                flash(ffi, &file, &target, base, flags, after)
+            }
+        }
+        Command::Verify {
+            file,
+            target,
+            base,
+            core,
+        } => {
+            if let Err(rc) = check_file(&file) {
+                return rc;
+            }
+            with_session(ffi, &target, |h| {
+                let c_file = c_path(&file);
+                let mut address = 0u64;
+                let rc = unsafe {
+                    (ffi.pr_session_verify)(
+                        h,
+                        core,
+                        c_file.as_ptr(),
+                        std::ptr::null(),
+                        base.unwrap_or(0),
+                        &mut address,
+                    )
+                };
+                let file = file.display().to_string();
+                match rc {
+                    0 => {
+                        report(
+                            format!("Verify OK: {}", file),
+                            json!({ "file": file, "match": true, "mismatch": null }),
+                        );
+                        0
+                    }
+                    1 if json_output() => {
+                        let value = json!({ "file": file, "match": false, "mismatch": address });
+                        println!("{}", value);
+                        Exit::VerifyFailed as i32
+                    }
+                    1 => error(
+                        &format!("Verify failed: first mismatch at {:#010x}", address),
+                        Exit::VerifyFailed,
+                    ),
+                    _ => fail(ffi, Exit::TargetAccess),
+                }
+            })
+        }
+        Command::Erase {
+            target,
+            range,
+            sector,
+            dry_run,
+            ..
+        } => match (
+            range.or(sector.map(|a| a..a.saturating_add(1))),
+            &target.probe,
+        ) {
+            (range, _) if dry_run => erase_plan(ffi, &target.chip, range.unwrap_or(0..u64::MAX)),
+            (None, None) => chip_erase(ffi, &target.chip, &target.connect),
+            // English comments: pr_chip_erase takes the first probe; with a selected probe the
+            // whole flash is erased sector by sector through a session instead
+            (range, _) => with_session(ffi, &target, |h| {
+                erase_range(ffi, h, range.unwrap_or(0..u64::MAX))
+            }),
+        },
+        Command::Read {
+            address,
+            length,
+            target,
+            width,
+            out,
+            hexdump,
+            core,
+        } => {
+            let count = length / width.bytes();
+            if !length.is_multiple_of(width.bytes()) || count > u32::MAX as u64 {
+                let msg = format!(
+                    "--length {} is not a multiple of {} bytes or too large",
+                    length,
+                    width.bytes()
+                );
+                return error(&msg, Exit::Usage);
+            }
+            with_session(ffi, &target, |h| {
+                let words = match read_words(ffi, h, core, address, count as u32, width) {
+                    Ok(words) => words,
+                    Err(rc) => return rc,
+                };
+                if let Some(path) = out {
+                    if let Err(e) = std::fs::write(&path, to_bytes(&words, width)) {
+                        let msg = format!("cannot write {}: {}", path.display(), e);
+                        return error(&msg, Exit::FileError);
+                    }
+                    let path = path.display().to_string();
+                    report(
+                        format!("Read {} bytes from {:#x} to {}", length, address, path),
+                        json!({ "address": address, "length": length, "file": path }),
+                    );
+                    return 0;
+                }
+                let text = if hexdump {
+                    hex_dump(address, &to_bytes(&words, width))
+                        .trim_end()
+                        .to_string()
+                } else {
+                    let w = width.bytes() as usize * 2 + 2;
+                    words.iter().fold(format!("Read {:#x}:", address), |t, v| {
+                        t + &format!(" {:#0w$x}", v, w = w)
+                    })
+                };
+                let value = json!({
+                    "address": address,
+                    "width": 8 * width.bytes(),
+                    "values": words,
+                });
+                report(text, value);
+                0
+            })
+        }
+        Command::Write {
+            address,
+            value,
+            file,
+            target,
+            width,
+            core,
+        } => {
+            let values = match file {
+                Some(path) => match std::fs::read(&path) {
+                    Ok(bytes) if bytes.len().is_multiple_of(width.bytes() as usize) => {
+                        from_bytes(&bytes, width)
+                    }
+                    Ok(bytes) => {
+                        let msg = format!(
+                            "{} has {} bytes, not a multiple of {} bytes; use --width 8",
+                            path.display(),
+                            bytes.len(),
+                            width.bytes()
+                        );
+                        return error(&msg, Exit::Usage);
+                    }
+                    Err(e) => {
+                        let msg = format!("cannot read {}: {}", path.display(), e);
+                        return error(&msg, Exit::FileError);
+                    }
+                },
+                None => value,
+            };
+            let max = u64::MAX >> (64 - 8 * width.bytes());
+            if let Some(v) = values.iter().find(|v| **v > max) {
+                let msg = format!(
+                    "value {:#x} does not fit into {} bits",
+                    v,
+                    8 * width.bytes()
+                );
+                return error(&msg, Exit::Usage);
+            }
+            if values.len() > u32::MAX as usize {
+                return error("too much data for one write", Exit::Usage);
+            }
+            with_session(ffi, &target, |h| {
+                write(ffi, h, core, address, &values, width)
+            })
+        }
+        Command::Reset {
+            chip,
+            probe,
+            connect,
+            halt,
+            hw,
+            connect_under_reset,
+            core,
+            ..
+        } => match chip {
+            Some(chip) if halt || !hw => {
+                if (hw || connect_under_reset) && unsafe { (ffi.pr_set_attach_under_reset)(1) } != 0
+                {
+                    return fail(ffi, Exit::Failed);
+                }
+                // English comments: attaching under reset already leaves the core halted
+                reset(ffi, &chip, probe.as_deref(), &connect, !hw, halt, core)
+            }
+            _ => hw_reset(ffi, probe.as_deref(), &connect.programmer),
+        },
+        Command::Rtt {
+            target,
+            channel,
+            down,
+            elf,
+            no_timestamps,
+            duration,
+        } => {
+            if let Some(Err(rc)) = elf.as_deref().map(check_file) {
+                return rc;
+            }
+            with_session(ffi, &target, |h| {
+                let term = match &elf {
+                    Some(elf) => {
+                        let c_elf = c_path(elf);
+                        unsafe { (ffi.pr_terminal_open_defmt)(h, channel, down, c_elf.as_ptr()) }
+                    }
+                    None => unsafe { (ffi.pr_terminal_open)(h, channel, down) },
+                };
+                if term == 0 {
+                    return fail(ffi, Exit::TargetAccess);
+                }
+                let deadline = duration.map(|secs| Instant::now() + Duration::from_secs(secs));
+                rtt(ffi, term, !no_timestamps, || {
+                    deadline.is_some_and(|d| Instant::now() >= d)
+                })
+            })
+        }
+        Command::Trace {
+            target,
+            baud,
+            clock,
+            decode,
+            port,
+            core,
+            duration,
+        } => {
+            // English comments: 0 selects the raw bytes in pr_swo_start
+            let ports = match (decode, port.is_empty()) {
+                (None, _) => 0,
+                (Some(Decode::Itm), true) => u32::MAX,
+                (Some(Decode::Itm), false) => port.iter().fold(0, |mask, p| mask | 1 << p),
+            };
+            with_session(ffi, &target, |h| {
+                if unsafe { (ffi.pr_swo_start)(h, core, clock, baud, ports) } != 0 {
+                    return fail(ffi, Exit::TargetAccess);
+                }
+                let deadline = duration.map(|secs| Instant::now() + Duration::from_secs(secs));
+                let rc = trace(ffi, h, ports != 0, || {
+                    deadline.is_some_and(|d| Instant::now() >= d)
+                });
+                unsafe { (ffi.pr_swo_stop)(h) };
+                rc
+            })
+        }
+        Command::Run {
+            file,
+            target,
+            base,
+            no_rtt,
+            timeout,
+            core,
+            ..
+        } => {
+            let flags = [1, 0, 1];
+            match flash(ffi, &file, &target, base.unwrap_or(0), flags, After::None) {
+                0 => {
+                    let timeout = timeout.map(Duration::from_secs);
+                    run_firmware(ffi, &target, !no_rtt, timeout, core)
+                }
+                rc => rc,
+            }
+        }
+        Command::Gdb {
+            address,
+            timeout_ms,
+            flash,
+            base,
+            monitor,
+        } => gdb(
+            ffi,
+            &address,
+            timeout_ms,
+            flash.as_deref(),
+            base.unwrap_or(0),
+            &monitor,
+        ),
+        Command::GdbServer {
+            target,
+            port,
+            flash: image,
+            base,
+            core,
+        } => {
+            if let Some(image) = &image {
+                let flags = [1, 0, 1];
+                match flash(ffi, image, &target, base.unwrap_or(0), flags, After::Halt) {
+                    0 => {}
+                    rc => return rc,
+                }
+            }
+            gdb_server(ffi, &target, port, core)
+        }
+    }
+}
+
+fn list(ffi: &Ffi) {
+    let n = unsafe { (ffi.pr_probe_count)() };
+    let mut lines = vec![format!("Found {} probes", n)];
+    let mut probes = Vec::new();
+    for i in 0..n {
+        let mut name = vec![0u8; 128];
+        let mut sn = vec![0u8; 128];
+        let mut vid: u16 = 0;
+        let mut pid: u16 = 0;
+        let rc = unsafe {
+            (ffi.pr_probe_info)(
+                i,
+                name.as_mut_ptr() as *mut c_char,
+                name.len(),
+                &mut vid,
+                &mut pid,
+                sn.as_mut_ptr() as *mut c_char,
+                sn.len(),
+            )
+        };
+        if rc != 0 {
+            fail(ffi, Exit::Failed);
+            continue;
+        }
+        let mut drv = 0u32;
+        let mut feat = 0u32;
+        let connected = unsafe {
+            let _ = (ffi.pr_probe_features)(i, &mut drv, &mut feat);
+            (ffi.pr_probe_check_target)(i) == 1
+        };
+        let name = String::from_utf8_lossy(&name)
+            .trim_end_matches('\0')
+            .to_string();
+        let sn = String::from_utf8_lossy(&sn)
+            .trim_end_matches('\0')
+            .to_string();
+        lines.push(format!(
+            "[{}] {} {:04x}:{:04x} SN={} drv=0x{:08x} feat=0x{:08x} connected={}",
+            i,
+            name,
+            vid,
+            pid,
+            sn,
+            drv,
+            feat,
+            if connected { "yes" } else { "no" }
+        ));
+        probes.push(json!({
+            "index": i,
+            "name": name,
+            "vid": vid,
+            "pid": pid,
+            "serial": sn,
+            "driver": drv,
+            "features": feat,
+            "connected": connected,
+        }));
+    }
+    report(lines.join("\n"), json!({ "probes": probes }));
+}
+
+fn chips(ffi: &Ffi) {
+    let m = unsafe { (ffi.pr_chip_manufacturer_count)() };
+    let mut lines = vec![format!("{} manufacturers", m)];
+    let mut manufacturers = Vec::new();
+    for mi in 0..m {
+        let mname =
+            read_string(|buf, len| unsafe { (ffi.pr_chip_manufacturer_name)(mi, buf, len) })
+                .unwrap_or_default();
+        let c = unsafe { (ffi.pr_chip_model_count)(mi) };
+        lines.push(format!("[{}] {} ({} models)", mi, mname, c));
+        let models: Vec<String> = (0..c)
+            .filter_map(|ci| {
+                read_string(|buf, len| unsafe { (ffi.pr_chip_model_name)(mi, ci, buf, len) })
+            })
+            .collect();
+        lines.extend(models.iter().map(|cname| format!("    - {}", cname)));
+        manufacturers.push(json!({ "name": mname, "models": models }));
+    }
+    report(lines.join("\n"), json!({ "manufacturers": manufacturers }));
+}
+
+fn chip_info(ffi: &Ffi, chip: &str) -> i32 {
+    let c_chip = c_string(chip);
+    let parse = |s: Option<String>| s.and_then(|s| serde_json::from_str::<Value>(&s).ok());
+    let spec = parse(read_string(|buf, len| unsafe {
+        (ffi.pr_chip_specs_by_name)(c_chip.as_ptr(), buf, len)
+    }));
+    let layout = parse(read_string(|buf, len| unsafe {
+        (ffi.pr_flash_sector_layout)(c_chip.as_ptr(), buf, len)
+    }));
+    let (Some(spec), Some(layout)) = (spec, layout) else {
+        return fail(ffi, Exit::UnknownChip);
+    };
+    report(
+        describe_chip(&spec, &layout).trim_end(),
+        json!({ "spec": spec, "flash": layout }),
+    );
+    0
+}
+
+// English comments: 16 KiB, 1 MiB or 516 B
+fn size_text(bytes: u64) -> String {
+    match bytes {
+        b if b >= 1 << 20 && b % (1 << 20) == 0 => format!("{} MiB", b >> 20),
+        b if b >= 1 << 10 && b % (1 << 10) == 0 => format!("{} KiB", b >> 10),
+        b => format!("{} B", b),
+    }
+}
+
+// English comments: human-readable form of the spec JSON and the pr_flash_sector_layout array
+fn describe_chip(spec: &Value, layout: &Value) -> String {
+    let text = |v: &Value| v.as_str().unwrap_or("?").to_string();
+    let mut out = format!(
+        "{} ({}), {}\n",
+        text(&spec["chip"]),
+        text(&spec["manufacturer"]),
+        text(&spec["architecture"])
+    );
+    out += "Cores:\n";
+    for core in spec["core_details"].as_array().into_iter().flatten() {
+        out += &format!("  {:<8} {}\n", text(&core["name"]), text(&core["type"]));
+    }
+    out += "Memory map:\n";
+    // English comments: "regions" is "Kind(0xSTART-0xEND);..."
+    for region in spec["regions"].as_str().unwrap_or("").split(';') {
+        let parsed = region.strip_suffix(')').and_then(|r| {
+            let (kind, range) = r.split_once('(')?;
+            let (start, end) = range.split_once('-')?;
+            Some((kind, parse_number(start).ok()?, parse_number(end).ok()?))
+        });
+        if let Some((kind, start, end)) = parsed {
+            out += &format!(
+                "  {:<4} {:#010x}..{:#010x}  {}\n",
+                kind,
+                start,
+                end,
+                size_text(end - start)
+            );
+        }
+    }
+    out += &format!("Flash algorithms: {}\n", text(&spec["flash_algorithms"]));
+    for region in layout.as_array().into_iter().flatten() {
+        let end = region["end"].as_u64().unwrap_or(0);
+        // English comments: algorithms taking compressed pages, see pr_flash_option_compression
+        let compressed = region["compressed"].as_array().cloned().unwrap_or_default();
+        out += &format!(
+            "Flash {}{}: {:#010x}..{:#010x}, page {}, algorithms {}\n",
+            text(&region["name"]),
+            if region["external"] == true {
+                " (external)"
+            } else {
+                ""
+            },
+            region["start"].as_u64().unwrap_or(0),
+            end,
+            region["page_size"]
+                .as_u64()
+                .map_or("?".to_string(), size_text),
+            region["algorithms"]
+                .as_array()
+                .map(|a| a
+                    .iter()
+                    .map(|name| if compressed.contains(name) {
+                        format!("{} (compressed)", text(name))
+                    } else {
+                        text(name)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "))
+                .unwrap_or_default()
+        );
+        let groups: Vec<(u64, u64)> = region["sectors"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .filter_map(|g| Some((g["address"].as_u64()?, g["size"].as_u64()?)))
+            .collect();
+        for (i, &(address, size)) in groups.iter().enumerate() {
+            let next = groups.get(i + 1).map_or(end, |g| g.0).min(end);
+            let count = next.saturating_sub(address) / size.max(1);
+            out += &format!(
+                "  {} x {} sectors from {:#010x}\n",
+                count,
+                size_text(size),
+                address
+            );
+        }
+    }
+    out
+}
+
+fn flash(
+    ffi: &Ffi,
+    file: &Path,
+    target: &TargetArgs,
+    base: u64,
+    [verify, preverify, chip_erase]: [i32; 3],
+    after: After,
+) -> i32 {
+    if let Err(rc) = check_file(file) {
+        return rc;
+    }
+    let c_file = c_path(file);
+    if let Err(rc) = start_flash(ffi, after) {
+        return rc;
+    }
+    let rc = match &target.probe {
+        // English comments: a selected probe needs a session, pr_flash_auto takes the first one
+        Some(_) => with_session(ffi, target, |h| unsafe {
+            match (ffi.pr_session_flash)(h, c_file.as_ptr(), base, 0, verify, preverify, chip_erase)
+            {
+                0 => 0,
+                _ => fail(ffi, Exit::FlashFailed),
+            }
+        }),
+        None => match set_programmer_type(ffi, &target.connect.programmer) {
+            Ok(()) => {
+                let c_chip = c_string(&target.chip);
+                match unsafe {
+                    (ffi.pr_flash_auto)(
+                        c_chip.as_ptr(),
+                        c_file.as_ptr(),
+                        base,
+                        0,
+                        verify,
+                        preverify,
+                        chip_erase,
+                        target.connect.speed,
+                        proto_code(target.connect.protocol),
+                    )
+                } {
+                    0 => 0,
+                    _ => fail(ffi, Exit::FlashFailed),
+                }
+            }
+            Err(rc) => rc,
+        },
+    };
+    finish_flash(ffi, file, rc)
+}
+
+// English comments: set the post-flash behavior and the progress callback of a flash call
+fn start_flash(ffi: &Ffi, after: After) -> Result<(), i32> {
+    unsafe {
+        if (ffi.pr_flash_set_after)(after_code(after)) != 0 {
+            return Err(fail(ffi, Exit::Failed));
+        }
+        // English comments: progress lines would break the JSON on stdout, JSON progress
+        // goes to stderr
+        if PROGRESS_JSON.get().is_some() {
+            (ffi.pr_set_progress_callback)(cli_json_progress_cb);
+        } else if !json_output() {
+            (ffi.pr_set_progress_callback)(cli_progress_cb);
+        }
+    }
+    Ok(())
+}
+
+// English comments: clear the progress callback and report a successful flash with its timing
+fn finish_flash(ffi: &Ffi, file: &Path, rc: i32) -> i32 {
+    unsafe { (ffi.pr_clear_progress_callback)() };
+    if rc == 0 {
+        let timing = read_string(|buf, len| unsafe { (ffi.pr_flash_last_timing)(buf, len) })
+            .and_then(|t| serde_json::from_str::<Value>(&t).ok());
+        report(
+            "Flash complete",
+            json!({ "file": file.display().to_string(), "timing": timing }),
+        );
+    }
+    rc
+}
+
+// English comments: flash through `call`, which attaches to the first probe of the programmer
+// type itself (--job, --diff-from) and gets the chip and image
+fn flash_first_probe(
+    ffi: &Ffi,
+    file: &Path,
+    target: &TargetArgs,
+    after: After,
+    option: &str,
+    call: impl FnOnce(*const c_char, *const c_char) -> i32,
+) -> i32 {
+    if target.probe.is_some() {
+        let msg = format!(
+            "{} flashes through the first probe of the programmer type, not a selected one",
+            option
+        );
+        return error(&msg, Exit::Usage);
+    }
+    if let Err(rc) = check_file(file) {
+        return rc;
+    }
+    if let Err(rc) = set_programmer_type(ffi, &target.connect.programmer) {
+        return rc;
+    }
+    if let Err(rc) = start_flash(ffi, after) {
+        return rc;
+    }
+    let (c_chip, c_file) = (c_string(&target.chip), c_path(file));
+    let rc = match call(c_chip.as_ptr(), c_file.as_ptr()) {
+        0 => 0,
+        1 => fail(ffi, Exit::Failed),
+        _ => fail(ffi, Exit::FlashFailed),
+    };
+    finish_flash(ffi, file, rc)
+}
+
+// English comments: flash --job; an existing state file means an interrupted job, which is
+// continued with the settings it was started with
+fn flash_job(
+    ffi: &Ffi,
+    file: &Path,
+    target: &TargetArgs,
+    base: u64,
+    verify: bool,
+    after: After,
+    state: &Path,
+) -> i32 {
+    let c_state = c_path(state);
+    flash_first_probe(ffi, file, target, after, "--job", |chip, file| {
+        if state.exists() {
+            if !json_output() {
+                eprintln!("Resuming the flash job of {}", state.display());
+            }
+            return unsafe { (ffi.pr_flash_resume)(c_state.as_ptr()) };
+        }
+        unsafe {
+            (ffi.pr_flash_job)(
+                chip,
+                file,
+                base,
+                0,
+                i32::from(verify),
+                target.connect.speed,
+                proto_code(target.connect.protocol),
+                c_state.as_ptr(),
+            )
+        }
+    })
+}
+
+// English comments: flash --diff-from; only the sectors that differ from the old image are
+// programmed
+fn flash_diff(
+    ffi: &Ffi,
+    file: &Path,
+    target: &TargetArgs,
+    base: u64,
+    verify: bool,
+    after: After,
+    old: &Path,
+) -> i32 {
+    if let Err(rc) = check_file(old) {
+        return rc;
+    }
+    let c_old = c_path(old);
+    flash_first_probe(ffi, file, target, after, "--diff-from", |chip, file| {
+        let mut sectors = 0u32;
+        let rc = unsafe {
+            (ffi.pr_flash_diff)(
+                chip,
+                c_old.as_ptr(),
+                file,
+                base,
+                0,
+                i32::from(verify),
+                target.connect.speed,
+                proto_code(target.connect.protocol),
+                &mut sectors,
+            )
+        };
+        if rc == 0 && !json_output() {
+            println!("Changed sectors: {}", sectors);
+        }
+        rc
+    })
+}
+
+// English comments: how often --watch looks at the image, and how long it must stay unchanged
+// before it is flashed, so a half-written build output is not used
+const WATCH_POLL: Duration = Duration::from_millis(250);
+const WATCH_SETTLE: Duration = Duration::from_millis(500);
+
+// English comments: modification time and size of a file, None while it is missing
+fn file_stamp(path: &Path) -> Option<(SystemTime, u64)> {
+    let meta = std::fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.len()))
+}
+
+// English comments: wait until the file differs from `stamp` and has settled
+fn wait_for_change(path: &Path, stamp: Option<(SystemTime, u64)>) -> Option<(SystemTime, u64)> {
+    loop {
+        std::thread::sleep(WATCH_POLL);
+        let now = file_stamp(path);
+        if now.is_some() && now != stamp {
+            std::thread::sleep(WATCH_SETTLE);
+            if file_stamp(path) == now {
+                return now;
+            }
+        }
+    }
+}
+
+// English comments: flash, then again on every change of the image until interrupted; failures
+// are reported and the next change is awaited, as in a development loop
+fn flash_watch(
+    ffi: &Ffi,
+    file: &Path,
+    target: &TargetArgs,
+    base: u64,
+    flags: [i32; 3],
+    after: After,
+    rtt_output: bool,
+) -> i32 {
+    let mut stamp = file_stamp(file);
+    loop {
+        if flash(ffi, file, target, base, flags, after) == 0 && rtt_output {
+            with_session(ffi, target, |h| {
+                let term = unsafe { (ffi.pr_terminal_open)(h, 0, 0) };
+                if term == 0 {
+                    return fail(ffi, Exit::TargetAccess);
+                }
+                rtt(ffi, term, true, || file_stamp(file) != stamp)
+            });
+        }
+        if !json_output() {
+            eprintln!("Waiting for changes of {}", file.display());
+        }
+        stamp = wait_for_change(file, stamp);
+    }
+}
+
+fn chip_erase(ffi: &Ffi, chip: &str, connect: &ConnectArgs) -> i32 {
+    if let Err(rc) = set_programmer_type(ffi, &connect.programmer) {
+        return rc;
+    }
+    let c_chip = c_string(chip);
+    let rc = unsafe {
+        (ffi.pr_chip_erase)(c_chip.as_ptr(), connect.speed, proto_code(connect.protocol))
+    };
+    if rc != 0 {
+        return fail(ffi, Exit::FlashFailed);
+    }
+    report("Chip erase complete", json!({ "erased": "chip" }));
+    0
+}
+
+fn erase_range(ffi: &Ffi, h: u64, range: Range<u64>) -> i32 {
+    let (mut start, mut end) = (0u64, 0u64);
+    let rc =
+        unsafe { (ffi.pr_session_erase_range)(h, range.start, range.end, &mut start, &mut end) };
+    if rc != 0 {
+        return fail(ffi, Exit::FlashFailed);
+    }
+    report(
+        format!("Erased {:#x}..{:#x}", start, end),
+        json!({ "erased": { "start": start, "end": end } }),
+    );
+    0
+}
+
+// English comments: runs of adjacent {"address", "size"} extents of equal size, as (start,
+// end, size, count), so a plan of thousands of pages stays readable
+fn extent_runs(extents: &Value) -> Vec<(u64, u64, u64, u64)> {
+    let mut runs: Vec<(u64, u64, u64, u64)> = Vec::new();
+    for extent in extents.as_array().into_iter().flatten() {
+        let (Some(address), Some(size)) = (extent["address"].as_u64(), extent["size"].as_u64())
+        else {
+            continue;
+        };
+        match runs.last_mut() {
+            Some(run) if run.1 == address && run.2 == size => {
+                run.1 += size;
+                run.3 += 1;
+            }
+            _ => runs.push((address, address + size, size, 1)),
+        }
+    }
+    runs
+}
+
+fn describe_extents(title: &str, extents: &Value, unit: &str) -> String {
+    let runs = extent_runs(extents);
+    let count: u64 = runs.iter().map(|r| r.3).sum();
+    let mut out = format!(
+        "{} {} {}{}:\n",
+        title,
+        count,
+        unit,
+        if count == 1 { "" } else { "s" }
+    );
+    for (start, end, size, count) in runs {
+        out += &format!(
+            "  {:#010x}..{:#010x}  {} x {}\n",
+            start,
+            end,
+            count,
+            size_text(size)
+        );
+    }
+    out
+}
+
+// English comments: print what flashing the image would do, from the chip description alone
+fn flash_plan(ffi: &Ffi, file: &Path, chip: &str, base: u64, chip_erase: bool) -> i32 {
+    if let Err(rc) = check_file(file) {
+        return rc;
+    }
+    let (c_chip, c_file) = (c_string(chip), c_path(file));
+    let plan = read_string(|buf, len| unsafe {
+        (ffi.pr_flash_plan)(
+            c_chip.as_ptr(),
+            c_file.as_ptr(),
+            std::ptr::null(),
+            base,
+            i32::from(chip_erase),
+            buf,
+            len,
+        )
+    })
+    .and_then(|p| serde_json::from_str::<Value>(&p).ok());
+    let Some(plan) = plan else {
+        return fail(ffi, Exit::Failed);
+    };
+    let file = file.display().to_string();
+    let mut text = format!("Dry run, nothing is written: {} to {}\n", file, chip);
+    let chip_erase = if chip_erase { " (chip erase)" } else { "" };
+    text += &describe_extents(&format!("Erase{}", chip_erase), &plan["erase"], "sector");
+    text += &describe_extents("Program", &plan["pages"], "page");
+    if plan["fills"].as_array().is_some_and(|f| !f.is_empty()) {
+        text += &describe_extents("Fill", &plan["fills"], "gap");
+    }
+    if plan["ram"].as_array().is_some_and(|r| !r.is_empty()) {
+        text += &describe_extents("Write to RAM", &plan["ram"], "block");
+    }
+    report(
+        text.trim_end(),
+        json!({ "file": file, "chip": chip, "dry_run": true, "plan": plan }),
+    );
+    0
+}
+
+// English comments: print the sectors an erase would cover
+fn erase_plan(ffi: &Ffi, chip: &str, range: Range<u64>) -> i32 {
+    let c_chip = c_string(chip);
+    let erase = read_string(|buf, len| unsafe {
+        (ffi.pr_flash_erase_plan)(c_chip.as_ptr(), range.start, range.end, buf, len)
+    })
+    .and_then(|p| serde_json::from_str::<Value>(&p).ok());
+    let Some(erase) = erase else {
+        return fail(ffi, Exit::Failed);
+    };
+    let text = format!(
+        "Dry run, nothing is erased on {}\n{}",
         chip,
-        probe,
-        file,
-        protocol,
-        speed,
-        op,
-        base,
-        dll_hint,
-        verify,
-        preverify,
-        chip_erase,
-        programmer_type,
-        len,
-        data,
-    ) = parse_args();
-    let dll = if dll_hint.is_empty() {
-        let mut p = std::env::current_exe().expect("get current exe failed");
-        p.set_file_name("probe_rs_lib.dll");
-        if !p.is_file() {
-            eprintln!("Required DLL not found in executable directory");
-            std::process::exit(2);
-        }
-        p
-    } else {
-        match find_dll(&dll_hint) {
-            Some(p) => p,
-            None => {
-                eprintln!("probe_rs_lib.dll not found; use --dll <path> to specify");
-                std::process::exit(2);
+        describe_extents("Erase", &erase, "sector")
+    );
+    report(
+        text.trim_end(),
+        json!({ "chip": chip, "dry_run": true, "erase": erase }),
+    );
+    0
+}
+
+fn read_words(
+    ffi: &Ffi,
+    h: u64,
+    core: u32,
+    address: u64,
+    count: u32,
+    width: Width,
+) -> Result<Vec<u64>, i32> {
+    let (rc, words): (i32, Vec<u64>) = unsafe {
+        match width {
+            Width::W8 => {
+                let mut buf = vec![0u8; count as usize];
+                let rc = (ffi.pr_read_8)(h, core, address, buf.as_mut_ptr(), count);
+                (rc, buf.into_iter().map(u64::from).collect())
+            }
+            Width::W16 => {
+                let mut buf = vec![0u16; count as usize];
+                let rc = (ffi.pr_read_16)(h, core, address, buf.as_mut_ptr(), count);
+                (rc, buf.into_iter().map(u64::from).collect())
+            }
+            Width::W32 => {
+                let mut buf = vec![0u32; count as usize];
+                let rc = (ffi.pr_read_32)(h, core, address, buf.as_mut_ptr(), count);
+                (rc, buf.into_iter().map(u64::from).collect())
+            }
+        }
+    };
+    match rc {
+        0 => Ok(words),
+        _ => Err(fail(ffi, Exit::TargetAccess)),
+    }
+}
+
+// English comments: words in little-endian memory order, as on the supported targets
+fn to_bytes(words: &[u64], width: Width) -> Vec<u8> {
+    words
+        .iter()
+        .flat_map(|w| w.to_le_bytes().into_iter().take(width.bytes() as usize))
+        .collect()
+}
+
+// English comments: the inverse of to_bytes; `bytes` holds whole words
+fn from_bytes(bytes: &[u8], width: Width) -> Vec<u64> {
+    bytes
+        .chunks(width.bytes() as usize)
+        .map(|word| {
+            word.iter()
+                .rev()
+                .fold(0u64, |acc, b| (acc << 8) | u64::from(*b))
+        })
+        .collect()
+}
+
+// English comments: 16 bytes per line with the address and the printable ASCII characters
+fn hex_dump(address: u64, bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = line.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = line
+            .iter()
+            .map(|b| {
+                if b.is_ascii_graphic() || *b == b' ' {
+                    *b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        out += &format!(
+            "{:08x}  {:<47}  |{}|\n",
+            address + 16 * i as u64,
+            hex.join(" "),
+            ascii
+        );
+    }
+    out
+}
+
+fn write(ffi: &Ffi, h: u64, core: u32, address: u64, values: &[u64], width: Width) -> i32 {
+    let n = values.len() as u32;
+    // English comments: the values were checked to fit the width before opening the session
+    let rc = unsafe {
+        match width {
+            Width::W8 => {
+                let buf: Vec<u8> = values.iter().map(|v| *v as u8).collect();
+                (ffi.pr_write_8)(h, core, address, buf.as_ptr(), n)
+            }
+            Width::W16 => {
+                let buf: Vec<u16> = values.iter().map(|v| *v as u16).collect();
+                (ffi.pr_write_16)(h, core, address, buf.as_ptr(), n)
+            }
+            Width::W32 => {
+                let buf: Vec<u32> = values.iter().map(|v| *v as u32).collect();
+                (ffi.pr_write_32)(h, core, address, buf.as_ptr(), n)
             }
         }
     };
-    let ffi = load_ffi(dll.to_string_lossy().as_ref());
+    if rc != 0 {
+        return fail(ffi, Exit::TargetAccess);
+    }
+    report(
+        "Write complete",
+        json!({ "address": address, "width": 8 * width.bytes(), "count": n }),
+    );
+    0
+}
+
+// English comments: reset through the debug port unless `core_reset` is false, then close the
+// session leaving the core halted with `halt`
+fn reset(
+    ffi: &Ffi,
+    chip: &str,
+    probe: Option<&str>,
+    connect: &ConnectArgs,
+    core_reset: bool,
+    halt: bool,
+    core: u32,
+) -> i32 {
+    let h = match open_session(ffi, chip, probe, connect) {
+        Ok(h) => h,
+        Err(rc) => return rc,
+    };
+    let rc = match (core_reset, halt) {
+        (false, _) => 0,
+        (true, false) => unsafe { (ffi.pr_core_reset)(h, core) },
+        (true, true) => unsafe { (ffi.pr_core_reset_and_halt)(h, core, 1000) },
+    };
+    let _ = unsafe { (ffi.pr_session_close_ex)(h, halt as i32) };
+    if rc != 0 {
+        return fail(ffi, Exit::TargetAccess);
+    }
+    let text = if halt {
+        format!("Core {} reset and halted", core)
+    } else {
+        format!("Core {} reset", core)
+    };
+    report(text, json!({ "core": core, "halted": halt }));
+    0
+}
+
+// English comments: pulse the reset line of the probe without attaching to the target
+// English comments: "ARM Ltd" for a {"cc", "id", "name"} JEP106 code, or the bare code
+fn jep106_text(code: &Value) -> String {
+    match code["name"].as_str() {
+        Some(name) => name.to_string(),
+        None => format!(
+            "JEP106 {}/{:#04x}",
+            code["cc"],
+            code["id"].as_u64().unwrap_or(0)
+        ),
+    }
+}
 
-    let op = op.unwrap_or_else(|| {
-        if file.is_some() {
-            "flash".to_string()
+// English comments: human-readable form of the pr_target_detect JSON
+fn describe_detection(found: &Value) -> String {
+    let text = |v: &Value| v.as_str().unwrap_or("?").to_string();
+    let mut out = format!("Protocol:    {}\n", text(&found["protocol"]));
+    let dp = &found["debug_port"];
+    out += &match dp["dpidr"].as_u64() {
+        Some(dpidr) => format!(
+            "Debug port:  DPIDR {:#010x} ({}, designer {})\n",
+            dpidr,
+            text(&dp["version"]),
+            jep106_text(&dp["designer"])
+        ),
+        None => "Debug port:  no ARM debug port\n".to_string(),
+    };
+    let rom = &found["rom_table"];
+    if let Some(part) = rom["part"].as_u64() {
+        out += &format!(
+            "ROM table:   {}, part {:#05x} (at {:#010x}, {})\n",
+            jep106_text(&rom["manufacturer"]),
+            part,
+            rom["address"].as_u64().unwrap_or(0),
+            text(&rom["access_port"])
+        );
+    }
+    out += &match found["target"].as_str() {
+        Some(target) => {
+            let cores: Vec<String> = found["cores"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .map(|c| format!("{} {}", text(&c["name"]), text(&c["type"])))
+                .collect();
+            format!(
+                "Target:      {} ({}; {})\n",
+                target,
+                text(&found["architecture"]),
+                cores.join(", ")
+            )
+        }
+        None => "Target:      not identified by autodetection\n".to_string(),
+    };
+    let names: Vec<String> = found["candidates"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .map(text)
+        .collect();
+    out += &format!(
+        "Candidates:  {}",
+        if names.is_empty() {
+            "none, pass the chip name with --chip".to_string()
         } else {
-            "check".to_string()
+            names.join(", ")
+        }
+    );
+    out
+}
+
+// English comments: identify the target of the probe; exits with UnknownChip when no registry
+// target matches, after printing what was read
+fn detect(ffi: &Ffi, probe: Option<&str>, connect: &ConnectArgs) -> i32 {
+    if let Err(rc) = set_programmer_type(ffi, &connect.programmer) {
+        return rc;
+    }
+    let c_sel = probe.map(c_string);
+    let sel = c_sel.as_ref().map_or(std::ptr::null(), |s| s.as_ptr());
+    let found = read_string(|buf, len| unsafe {
+        (ffi.pr_target_detect)(sel, connect.speed, proto_code(connect.protocol), buf, len)
+    })
+    .and_then(|s| serde_json::from_str::<Value>(&s).ok());
+    let Some(found) = found else {
+        return fail(ffi, Exit::AttachFailed);
+    };
+    report(describe_detection(&found), found.clone());
+    match found["candidates"].as_array() {
+        Some(names) if !names.is_empty() => 0,
+        _ => Exit::UnknownChip as i32,
+    }
+}
+
+// English comments: human-readable form of the pr_benchmark JSON
+fn describe_benchmark(bench: &Value) -> String {
+    let number = |v: &Value| v.as_u64().unwrap_or(0);
+    let rate = |v: &Value| format!("{:.1} KiB/s", number(v) as f64 / 1024.0);
+    let requested = match bench["requested_speed_khz"].as_u64() {
+        Some(khz) => format!(" (requested {} kHz)", khz),
+        None => String::new(),
+    };
+    let mut out = format!(
+        "Probe:   {}, {}\nClock:   {} kHz{}\nTarget:  {}, {} at {:#010x}\n\n",
+        bench["probe"].as_str().unwrap_or("?"),
+        bench["protocol"].as_str().unwrap_or("default protocol"),
+        number(&bench["speed_khz"]),
+        requested,
+        bench["target"].as_str().unwrap_or("?"),
+        size_text(number(&bench["size"])),
+        number(&bench["address"]),
+    );
+    out += &format!("{:>10}  {:>14}  {:>14}", "Block", "Write", "Read");
+    for result in bench["results"].as_array().into_iter().flatten() {
+        out += &format!(
+            "\n{:>10}  {:>14}  {:>14}",
+            size_text(number(&result["block_size"])),
+            rate(&result["write_bytes_per_sec"]),
+            rate(&result["read_bytes_per_sec"])
+        );
+    }
+    out
+}
+
+fn bench(ffi: &Ffi, target: &TargetArgs, address: u64, size: u64, core: u32) -> i32 {
+    if let Err(rc) = set_programmer_type(ffi, &target.connect.programmer) {
+        return rc;
+    }
+    let c_chip = c_string(&target.chip);
+    let c_sel = target.probe.as_deref().map(c_string);
+    let sel = c_sel.as_ref().map_or(std::ptr::null(), |s| s.as_ptr());
+    let connect = &target.connect;
+    // English comments: one call with room for the result, read_string would run the
+    // benchmark twice
+    let mut buf = vec![0u8; 8192];
+    let need = unsafe {
+        (ffi.pr_benchmark)(
+            c_chip.as_ptr(),
+            sel,
+            connect.speed,
+            proto_code(connect.protocol),
+            core,
+            address,
+            size,
+            buf.as_mut_ptr() as *mut c_char,
+            buf.len(),
+        )
+    };
+    let result = (need > 0 && need <= buf.len())
+        .then(|| serde_json::from_slice::<Value>(&buf[..need - 1]).ok())
+        .flatten();
+    let Some(result) = result else {
+        return fail(ffi, Exit::TargetAccess);
+    };
+    report(describe_benchmark(&result), result.clone());
+    0
+}
+
+fn hw_reset(ffi: &Ffi, probe: Option<&str>, programmer: &ProgrammerArgs) -> i32 {
+    if let Err(rc) = set_programmer_type(ffi, programmer) {
+        return rc;
+    }
+    let c_sel = probe.map(c_string);
+    let sel = c_sel.as_ref().map_or(std::ptr::null(), |s| s.as_ptr());
+    if unsafe { (ffi.pr_probe_hw_reset)(sel, 0) } != 0 {
+        return fail(ffi, Exit::TargetAccess);
+    }
+    report(
+        "Target reset through the reset line",
+        json!({ "hw_reset": true }),
+    );
+    0
+}
+
+// English comments: put `stamp` before every line of `data`; `at_line_start` carries over
+// between chunks so lines split across reads get one stamp
+fn timestamp_lines(data: &[u8], stamp: &str, at_line_start: &mut bool) -> Vec<u8> {
+    let mut out = Vec::with_capacity(data.len() + stamp.len());
+    for &b in data {
+        if *at_line_start {
+            out.extend_from_slice(stamp.as_bytes());
+        }
+        out.push(b);
+        *at_line_start = b == b'\n';
+    }
+    out
+}
+
+// English comments: remove the complete lines from `pending`, without their line endings
+fn take_lines(pending: &mut Vec<u8>) -> Vec<String> {
+    let Some(last) = pending.iter().rposition(|&b| b == b'\n') else {
+        return Vec::new();
+    };
+    let complete: Vec<u8> = pending.drain(..=last).collect();
+    complete[..last]
+        .split(|&b| b == b'\n')
+        .map(|l| String::from_utf8_lossy(l.strip_suffix(b"\r").unwrap_or(l)).into_owned())
+        .collect()
+}
+
+// English comments: stream the terminal until `stop` returns true or reading fails
+fn rtt(ffi: &Ffi, term: u64, timestamps: bool, stop: impl Fn() -> bool) -> i32 {
+    // English comments: stdin is read on its own thread so output keeps flowing meanwhile
+    let (tx, rx) = mpsc::channel::<String>();
+    std::thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
         }
     });
-    if op != "list" {
-        let pt_str = match programmer_type {
-            Some(t) => t,
-            None => {
-                eprintln!("--programmer-type required");
-                std::process::exit(1);
-            }
-        };
-        let c_pt = CString::new(pt_str.clone()).unwrap();
-        let mut code: i32 = -1;
-        let rc_conv =
-            unsafe { (ffi.pr_programmer_type_from_string)(c_pt.as_ptr(), &mut code as *mut i32) };
-        if rc_conv != 0 || code < 0 {
-            eprintln!("Unsupported programmer type: {}", pt_str);
-            std::process::exit(1);
-        }
-        let supported = unsafe { (ffi.pr_programmer_type_is_supported_code)(code) };
-        if supported == 0 {
-            eprintln!("Unsupported programmer type code: {}", pt_str);
-            std::process::exit(1);
-        }
-        let rc = unsafe { (ffi.pr_set_programmer_type_code)(code) };
-        if rc != 0 {
-            print_last_error(&ffi);
-            std::process::exit(rc);
-        }
-    }
-
-    match op.as_str() {
-        "list" => unsafe {
-            let n = (ffi.pr_probe_count)();
-            println!("Found {} probes", n);
-            for i in 0..n {
-                let mut name = vec![0u8; 128];
-                let mut sn = vec![0u8; 128];
-                let mut vid: u16 = 0;
-                let mut pid: u16 = 0;
-                let rc = (ffi.pr_probe_info)(
-                    i,
-                    name.as_mut_ptr() as *mut c_char,
-                    name.len(),
-                    &mut vid,
-                    &mut pid,
-                    sn.as_mut_ptr() as *mut c_char,
-                    sn.len(),
-                );
-                if rc != 0 {
-                    print_last_error(&ffi);
-                    continue;
-                }
-                let mut drv = 0u32;
-                let mut feat = 0u32;
-                let _ = (ffi.pr_probe_features)(i, &mut drv, &mut feat);
-                let connected = (ffi.pr_probe_check_target)(i);
-                println!(
-                    "[{}] {} {:04x}:{:04x} SN={} drv=0x{:08x} feat=0x{:08x} connected={}",
-                    i,
-                    String::from_utf8_lossy(&name).trim_end_matches('\0'),
-                    vid,
-                    pid,
-                    String::from_utf8_lossy(&sn).trim_end_matches('\0'),
-                    drv,
-                    feat,
-                    if connected == 1 { "yes" } else { "no" }
-                );
-            }
-        },
-        "check" => unsafe {
-            let chip = match chip {
-                Some(c) => c,
-                None => {
-                    eprintln!("--chip required for check");
-                    std::process::exit(1);
+    let start = Instant::now();
+    let mut buf = [0u8; 1024];
+    let mut stdout = io::stdout();
+    let mut at_line_start = true;
+    let mut pending = Vec::new();
+    let rc = loop {
+        let n = unsafe { (ffi.pr_terminal_read)(term, buf.as_mut_ptr(), buf.len()) };
+        if n < 0 {
+            break fail(ffi, Exit::TargetAccess);
+        }
+        if n > 0 {
+            let data = &buf[..n as usize];
+            if json_output() {
+                // English comments: one object per line with the seconds since attaching
+                pending.extend_from_slice(data);
+                let time = start.elapsed().as_secs_f64();
+                for line in take_lines(&mut pending) {
+                    let _ = writeln!(stdout, "{}", json!({ "time": time, "text": line }));
                 }
-            };
-            let c_chip = CString::new(chip).unwrap();
-            let handle = if let Some(sel) = probe.clone() {
-                let c_sel = CString::new(sel).unwrap();
-                (ffi.pr_session_open_with_probe)(
-                    c_sel.as_ptr(),
-                    c_chip.as_ptr(),
-                    speed,
-                    proto_code(protocol),
-                )
+            } else if timestamps {
+                let stamp = format!("[{:10.3}] ", start.elapsed().as_secs_f64());
+                let _ = stdout.write_all(&timestamp_lines(data, &stamp, &mut at_line_start));
             } else {
-                (ffi.pr_session_open_auto)(c_chip.as_ptr(), speed, proto_code(protocol))
-            };
-            if handle == 0 {
-                print_last_error(&ffi);
-                std::process::exit(3);
-            }
-            println!("Session opened: {}", handle);
-            let _ = (ffi.pr_session_close)(handle);
-            println!("Session closed");
-        },
-        "flash" => unsafe {
-            (ffi.pr_set_progress_callback)(cli_progress_cb);
-            let chip = match chip {
-                Some(c) => c,
-                None => {
-                    eprintln!("--chip required for flash");
-                    std::process::exit(1);
-                }
-            };
-            let path = match file {
-                Some(p) => p,
-                None => {
-                    eprintln!("--file required for flash");
-                    std::process::exit(1);
-                }
-            };
-            let c_chip = CString::new(chip).unwrap();
-            let c_path = CString::new(path.to_string_lossy().to_string()).unwrap();
-            let base_val = base.unwrap_or(0);
-            let rc = (ffi.pr_flash_auto)(
-                c_chip.as_ptr(),
-                c_path.as_ptr(),
-                base_val,
-                0,
-                if verify { 1 } else { 0 },
-                if preverify { 1 } else { 0 },
-                if chip_erase { 1 } else { 0 },
-                speed,
-                proto_code(protocol),
-            );
-            (ffi.pr_clear_progress_callback)();
-            if rc != 0 {
-                print_last_error(&ffi);
-                std::process::exit(rc);
+                let _ = stdout.write_all(data);
             }
-            println!("Flash complete");
-        },
-        "erase-all" => unsafe {
-            let chip = match chip {
-                Some(c) => c,
-                None => {
-                    eprintln!("--chip required for erase-all");
-                    std::process::exit(1);
-                }
-            };
-            let c_chip = CString::new(chip).unwrap();
-            let rc = (ffi.pr_chip_erase)(c_chip.as_ptr(), speed, proto_code(protocol));
-            if rc != 0 {
-                print_last_error(&ffi);
-                std::process::exit(rc);
+            let _ = stdout.flush();
+            continue;
+        }
+        while let Ok(line) = rx.try_recv() {
+            if let Ok(c_line) = CString::new(line) {
+                unsafe { (ffi.pr_terminal_write_line)(term, c_line.as_ptr()) };
             }
-            println!("Chip erase complete");
-        },
-        "read16" => unsafe {
-            let chip = match chip {
-                Some(c) => c,
-                None => {
-                    eprintln!("--chip required for read16");
-                    std::process::exit(1);
+        }
+        if stop() {
+            break 0;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    };
+    unsafe { (ffi.pr_terminal_close)(term) };
+    rc
+}
+
+// English comments: stream the SWO data to stdout until `stop` returns true or reading fails;
+// with --json, decoded text as one object per line, raw data as hex per read
+fn trace(ffi: &Ffi, h: u64, decoded: bool, stop: impl Fn() -> bool) -> i32 {
+    let start = Instant::now();
+    let mut buf = [0u8; 4096];
+    let mut stdout = io::stdout();
+    let mut pending = Vec::new();
+    loop {
+        let n = unsafe { (ffi.pr_swo_read)(h, buf.as_mut_ptr(), buf.len()) };
+        if n < 0 {
+            return fail(ffi, Exit::TargetAccess);
+        }
+        let data = &buf[..n as usize];
+        let time = start.elapsed().as_secs_f64();
+        if !data.is_empty() {
+            if !json_output() {
+                let _ = stdout.write_all(data);
+            } else if decoded {
+                pending.extend_from_slice(data);
+                for line in take_lines(&mut pending) {
+                    let _ = writeln!(stdout, "{}", json!({ "time": time, "text": line }));
                 }
-            };
-            let c_chip = CString::new(chip).unwrap();
-            let handle = if let Some(sel) = probe.clone() {
-                let c_sel = CString::new(sel).unwrap();
-                (ffi.pr_session_open_with_probe)(
-                    c_sel.as_ptr(),
-                    c_chip.as_ptr(),
-                    speed,
-                    proto_code(protocol),
-                )
             } else {
-                (ffi.pr_session_open_auto)(c_chip.as_ptr(), speed, proto_code(protocol))
-            };
-            if handle == 0 {
-                print_last_error(&ffi);
-                std::process::exit(3);
+                let hex: String = data.iter().map(|b| format!("{:02x}", b)).collect();
+                let _ = writeln!(stdout, "{}", json!({ "time": time, "data": hex }));
             }
+            let _ = stdout.flush();
+        }
+        if stop() {
+            return 0;
+        }
+        if data.is_empty() {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+}
 
-            let addr = base.unwrap_or(0);
-            let count = len.unwrap_or(1);
-            let mut buf = vec![0u16; count as usize];
-            let rc = (ffi.pr_read_16)(handle, 0, addr, buf.as_mut_ptr(), count);
-            if rc != 0 {
-                print_last_error(&ffi);
-                let _ = (ffi.pr_session_close)(handle);
-                std::process::exit(rc);
-            }
-            print!("Read {:#x}:", addr);
-            for val in buf {
-                print!(" {:#06x}", val);
-            }
-            println!();
-            let _ = (ffi.pr_session_close)(handle);
-        },
-        "write16" => unsafe {
-            let chip = match chip {
-                Some(c) => c,
-                None => {
-                    eprintln!("--chip required for write16");
-                    std::process::exit(1);
-                }
-            };
-            if data.is_empty() {
-                eprintln!("--data required for write16");
-                std::process::exit(1);
+// English comments: how long `run` looks for the RTT control block after the reset, the
+// firmware sets it up first thing
+const RTT_SEARCH: Duration = Duration::from_secs(1);
+
+// English comments: reset into the flashed firmware and forward its output until it exits
+// through semihosting; returns its exit code
+fn run_firmware(
+    ffi: &Ffi,
+    target: &TargetArgs,
+    rtt: bool,
+    timeout: Option<Duration>,
+    core: u32,
+) -> i32 {
+    let h = match open_session(ffi, &target.chip, target.probe.as_deref(), &target.connect) {
+        Ok(h) => h,
+        Err(rc) => return rc,
+    };
+    let rc = unsafe { (ffi.pr_core_reset)(h, core) };
+    let rc = if rc != 0 {
+        fail(ffi, Exit::TargetAccess)
+    } else {
+        forward_output(ffi, h, rtt, timeout, core)
+    };
+    // English comments: leave the core halted where the firmware exited
+    let _ = unsafe { (ffi.pr_session_close_ex)(h, 1) };
+    rc
+}
+
+fn forward_output(ffi: &Ffi, h: u64, rtt: bool, timeout: Option<Duration>, core: u32) -> i32 {
+    let start = Instant::now();
+    let mut term = 0u64;
+    let mut buf = [0u8; 1024];
+    let mut stdout = io::stdout();
+    // English comments: semihosting and RTT output, collected into lines with --json
+    let mut pending = [Vec::new(), Vec::new()];
+    let mut emit = |source: usize, data: &[u8]| {
+        if json_output() {
+            pending[source].extend_from_slice(data);
+            let time = start.elapsed().as_secs_f64();
+            let name = ["semihosting", "rtt"][source];
+            for line in take_lines(&mut pending[source]) {
+                let value = json!({ "time": time, "source": name, "text": line });
+                let _ = writeln!(stdout, "{}", value);
             }
-            let c_chip = CString::new(chip).unwrap();
-            let handle = if let Some(sel) = probe.clone() {
-                let c_sel = CString::new(sel).unwrap();
-                (ffi.pr_session_open_with_probe)(
-                    c_sel.as_ptr(),
-                    c_chip.as_ptr(),
-                    speed,
-                    proto_code(protocol),
-                )
-            } else {
-                (ffi.pr_session_open_auto)(c_chip.as_ptr(), speed, proto_code(protocol))
-            };
+        } else {
+            let _ = stdout.write_all(data);
+        }
+        let _ = stdout.flush();
+    };
+    loop {
+        let mut exit_code = 0i32;
+        let status = unsafe { (ffi.pr_semihosting_poll)(h, core, &mut exit_code) };
+        if rtt && term == 0 && start.elapsed() < RTT_SEARCH {
+            term = unsafe { (ffi.pr_terminal_open)(h, 0, 0) };
+        }
+        // English comments: both reads take a handle and a buffer; 0 is no terminal (yet)
+        let reads = [(h, ffi.pr_semihosting_read), (term, ffi.pr_terminal_read)];
+        for (source, (handle, read)) in reads.into_iter().enumerate() {
             if handle == 0 {
-                print_last_error(&ffi);
-                std::process::exit(3);
+                continue;
             }
-
-            let addr = base.unwrap_or(0);
-            let rc = (ffi.pr_write_16)(handle, 0, addr, data.as_ptr(), data.len() as u32);
-            if rc != 0 {
-                print_last_error(&ffi);
-                let _ = (ffi.pr_session_close)(handle);
-                std::process::exit(rc);
-            }
-            println!("Write complete");
-            let _ = (ffi.pr_session_close)(handle);
-        },
-        "chips" => unsafe {
-            let m = (ffi.pr_chip_manufacturer_count)();
-            println!("{} manufacturers", m);
-            for mi in 0..m {
-                let need = (ffi.pr_chip_manufacturer_name)(mi, std::ptr::null_mut(), 0);
-                let mut mname = vec![0u8; need.max(1)];
-                if need > 0 {
-                    (ffi.pr_chip_manufacturer_name)(
-                        mi,
-                        mname.as_mut_ptr() as *mut c_char,
-                        mname.len(),
-                    );
-                }
-                let mname_str = String::from_utf8_lossy(&mname)
-                    .trim_end_matches('\0')
-                    .to_string();
-                let c = (ffi.pr_chip_model_count)(mi);
-                println!("[{}] {} ({} models)", mi, mname_str, c);
-                for ci in 0..c.min(50) {
-                    // limit to 50 to keep output reasonable
-                    let need_c = (ffi.pr_chip_model_name)(mi, ci, std::ptr::null_mut(), 0);
-                    if need_c == 0 {
-                        continue;
-                    }
-                    let mut cname = vec![0u8; need_c];
-                    (ffi.pr_chip_model_name)(
-                        mi,
-                        ci,
-                        cname.as_mut_ptr() as *mut c_char,
-                        cname.len(),
-                    );
-                    println!(
-                        "    - {}",
-                        String::from_utf8_lossy(&cname).trim_end_matches('\0')
-                    );
-                    let spec_need = (ffi.pr_chip_model_specs)(mi, ci, std::ptr::null_mut(), 0);
-                    if spec_need > 0 {
-                        let mut spec = vec![0u8; spec_need];
-                        (ffi.pr_chip_model_specs)(
-                            mi,
-                            ci,
-                            spec.as_mut_ptr() as *mut c_char,
-                            spec.len(),
-                        );
-                        println!(
-                            "      {}",
-                            String::from_utf8_lossy(&spec).trim_end_matches('\0')
-                        );
-                    }
+            loop {
+                let n = unsafe { read(handle, buf.as_mut_ptr(), buf.len()) };
+                if n <= 0 {
+                    break;
                 }
+                emit(source, &buf[..n as usize]);
             }
-        },
-        "spec" => unsafe {
-            let chip = match chip {
-                Some(c) => c,
-                None => {
-                    eprintln!("--chip required for spec");
-                    std::process::exit(1);
-                }
-            };
-            let c_chip = CString::new(chip).unwrap();
-            let need = (ffi.pr_chip_specs_by_name)(c_chip.as_ptr(), std::ptr::null_mut(), 0);
-            if need > 0 {
-                let mut spec = vec![0u8; need];
-                (ffi.pr_chip_specs_by_name)(
-                    c_chip.as_ptr(),
-                    spec.as_mut_ptr() as *mut c_char,
-                    spec.len(),
+        }
+        match status {
+            0 => {}
+            1 => {
+                report(
+                    format!("Firmware exited with code {}", exit_code),
+                    json!({ "exit_code": exit_code }),
                 );
-                println!("{}", String::from_utf8_lossy(&spec).trim_end_matches('\0'));
+                break exit_code;
             }
-        },
-        _ => {
-            eprintln!("Unknown operation: {}", op);
-            std::process::exit(1);
+            _ => break fail(ffi, Exit::TargetAccess),
+        }
+        if let Some(t) = timeout
+            && start.elapsed() >= t
+        {
+            let msg = format!("firmware did not exit within {} s", t.as_secs());
+            break error(&msg, Exit::Timeout);
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+fn gdb(
+    ffi: &Ffi,
+    address: &str,
+    timeout_ms: u32,
+    image: Option<&Path>,
+    base: u64,
+    monitor: &[String],
+) -> i32 {
+    if let Some(Err(rc)) = image.map(check_file) {
+        return rc;
+    }
+    let c_address = c_string(address);
+    let gdb = unsafe { (ffi.pr_gdb_attach)(c_address.as_ptr(), timeout_ms) };
+    if gdb == 0 {
+        return fail(ffi, Exit::AttachFailed);
+    }
+    if !json_output() {
+        println!("Attached to {}", address);
+    }
+    let mut rc = 0;
+    if let Some(image) = image {
+        let c_file = c_path(image);
+        if unsafe { (ffi.pr_gdb_flash)(gdb, c_file.as_ptr(), std::ptr::null(), base) } != 0 {
+            rc = fail(ffi, Exit::FlashFailed);
+        } else if !json_output() {
+            println!("Flash complete");
+        }
+    }
+    let mut outputs = Vec::new();
+    for command in monitor {
+        if rc != 0 {
+            break;
+        }
+        let c_command = c_string(command);
+        match read_string(|buf, len| unsafe {
+            (ffi.pr_gdb_monitor)(gdb, c_command.as_ptr(), buf, len)
+        }) {
+            Some(output) if json_output() => {
+                outputs.push(json!({ "command": command, "output": output }))
+            }
+            Some(output) => print!("{}", output),
+            None => rc = fail(ffi, Exit::Failed),
         }
     }
+    if unsafe { (ffi.pr_gdb_detach)(gdb) } != 0 && rc == 0 {
+        rc = fail(ffi, Exit::Failed);
+    }
+    if rc == 0 && json_output() {
+        let value = json!({ "address": address, "flashed": image.is_some(), "monitor": outputs });
+        println!("{}", value);
+    }
+    rc
+}
+
+fn gdb_server(ffi: &Ffi, target: &TargetArgs, port: u16, core: u32) -> i32 {
+    with_session(ffi, target, |h| {
+        let port = unsafe { (ffi.pr_gdb_server_start)(h, core, port) };
+        if port < 0 {
+            return fail(ffi, Exit::Failed);
+        }
+        if json_output() {
+            println!(
+                "{}",
+                json!({ "address": format!("127.0.0.1:{}", port), "core": core })
+            );
+        } else {
+            println!(
+                "GDB server listening on 127.0.0.1:{}, stop with Ctrl-C",
+                port
+            );
+        }
+        // English comments: the library serves clients in its own thread until the process ends
+        loop {
+            std::thread::sleep(Duration::from_secs(1));
+        }
+    })
 }
 
 unsafe extern "C" fn cli_progress_cb(_op: i32, percent: f32, status: *const c_char, eta_ms: i32) {
@@ -648,119 +2894,851 @@ unsafe extern "C" fn cli_progress_cb(_op: i32, percent: f32, status: *const c_ch
     let _ = io::stdout().flush();
 }
 
+// English comments: the phase names of the operation codes of pr_progress_cb
+fn phase_name(op: i32) -> &'static str {
+    match op {
+        1 => "erase",
+        2 => "program",
+        3 => "verify",
+        _ => "fill",
+    }
+}
+
+fn progress_event(
+    op: i32,
+    percent: f32,
+    bytes: Option<(u64, u64)>,
+    eta_ms: i32,
+    indeterminate: bool,
+) -> Value {
+    let (done, total) = bytes.map_or((None, None), |(d, t)| (Some(d), Some(t).filter(|&t| t > 0)));
+    json!({
+        "phase": phase_name(op),
+        "percent": (f64::from(percent) * 100.0).round() / 100.0,
+        "bytes": done,
+        "total": total,
+        "eta_ms": (eta_ms >= 0).then_some(eta_ms),
+        "indeterminate": indeterminate,
+    })
+}
+
+unsafe extern "C" fn cli_json_progress_cb(
+    op: i32,
+    percent: f32,
+    _status: *const c_char,
+    eta_ms: i32,
+) {
+    let (mut done, mut total) = (0u64, 0u64);
+    let functions = PROGRESS_JSON.get();
+    let bytes = functions
+        .filter(|(progress_bytes, _)| unsafe { progress_bytes(&mut done, &mut total) } == 0)
+        .map(|_| (done, total));
+    // English comments: chip erases report estimated progress, shown as a busy indicator
+    let indeterminate = functions.is_some_and(|(_, indeterminate)| unsafe { indeterminate() } == 1);
+    let line = format!(
+        "{}\n",
+        progress_event(op, percent, bytes, eta_ms, indeterminate)
+    );
+    let _ = io::stderr().write_all(line.as_bytes());
+}
+
+// English comments: --log-level alone prints the records on stderr, --log-file writes them to
+// the file instead (at debug unless a level is given)
+fn start_logging(ffi: &Ffi, level: Option<LogLevel>, file: Option<&Path>) -> Result<(), i32> {
+    let rc = match (level, file) {
+        (None, None) => return Ok(()),
+        (level, Some(path)) => {
+            let level = level.unwrap_or(LogLevel::Debug);
+            let c_file = c_path(path);
+            match unsafe { (ffi.pr_enable_file_logging)(c_file.as_ptr(), level as i32) } {
+                -1 | -2 => return Err(fail(ffi, Exit::FileError)),
+                rc => rc,
+            }
+        }
+        (Some(level), None) => unsafe { (ffi.pr_set_log_callback)(level as i32, Some(cli_log_cb)) },
+    };
+    match rc {
+        0 => Ok(()),
+        _ => Err(fail(ffi, Exit::Library)),
+    }
+}
+
+fn log_level_name(level: i32) -> &'static str {
+    match level {
+        1 => "ERROR",
+        2 => "WARN",
+        3 => "INFO",
+        4 => "DEBUG",
+        _ => "TRACE",
+    }
+}
+
+// English comments: one line per record on stderr, a JSON object with --json
+unsafe extern "C" fn cli_log_cb(level: i32, target: *const c_char, message: *const c_char) {
+    let target = unsafe { CStr::from_ptr(target) }.to_string_lossy();
+    let message = unsafe { CStr::from_ptr(message) }.to_string_lossy();
+    let line = if json_output() {
+        let value = json!({
+            "log": log_level_name(level).to_lowercase(),
+            "target": target,
+            "message": message,
+        });
+        format!("{}\n", value)
+    } else {
+        format!("{:<5} {}: {}\n", log_level_name(level), target, message)
+    };
+    let _ = io::stderr().write_all(line.as_bytes());
+}
+
 // English comments: unit tests cover argument parsing behavior without touching the DLL
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    fn make_args(v: &[&str]) -> impl Iterator<Item = String> {
-        v.iter()
-            .map(|s| s.to_string())
-            .collect::<Vec<_>>()
-            .into_iter()
+    fn parse(args: &[&str]) -> Command {
+        Cli::try_parse_from(std::iter::once("probe-rs-lib-cli").chain(args.iter().copied()))
+            .unwrap()
+            .command
     }
 
     #[test]
-    fn parse_defaults() {
-        let (
-            chip,
-            probe,
+    fn command_definition_is_valid() {
+        Cli::command().debug_assert();
+    }
+
+    #[test]
+    fn flash_defaults_and_flags() {
+        let Command::Flash {
             file,
-            protocol,
-            speed,
-            op,
+            target,
             base,
-            dll_hint,
-            verify,
+            no_verify,
+            verify_mode,
+            prefer_compressed,
+            single_buffer,
+            max_page_size,
             preverify,
-            chip_erase,
-            programmer_type,
-        ) = parse_args_from(make_args(&[]));
-        assert!(chip.is_none());
-        assert!(probe.is_none());
-        assert!(file.is_none());
-        match protocol {
-            Protocol::Auto => {}
-            _ => panic!("protocol should default to Auto"),
-        }
-        assert_eq!(speed, 4000);
-        assert!(op.is_none());
+            no_chip_erase,
+            after,
+            watch,
+            rtt,
+            dry_run,
+            algorithm,
+            job,
+            diff_from,
+        } = parse(&["flash", "fw.hex", "--chip", "stm32f407zet6"])
+        else {
+            panic!("expected flash");
+        };
+        assert_eq!(file, PathBuf::from("fw.hex"));
+        assert_eq!(target.chip, "stm32f407zet6");
+        assert!(target.probe.is_none());
+        assert!(target.connect.programmer.programmer_type.is_none());
+        assert_eq!(target.connect.protocol, Protocol::Auto);
+        assert_eq!(target.connect.speed, 4000);
         assert!(base.is_none());
-        assert_eq!(dll_hint, "");
-        assert!(verify);
-        assert!(!preverify);
-        assert!(chip_erase);
-        assert!(programmer_type.is_none());
-    }
+        assert!(!no_verify && !preverify && !no_chip_erase && !prefer_compressed);
+        assert!(!single_buffer && max_page_size.is_none());
+        assert_eq!(verify_mode, VerifyMode::Pages);
+        assert_eq!(after, After::None);
+        assert!(!watch && !rtt && !dry_run);
+        assert!(algorithm.algorithm_ram.is_none() && job.is_none() && diff_from.is_none());
 
-    #[test]
-    fn parse_protocol_speed_and_flags() {
-        let args = make_args(&[
+        let Command::Flash {
+            target,
+            base,
+            no_verify,
+            after,
+            ..
+        } = parse(&[
+            "flash",
+            "fw.bin",
+            "--chip",
+            "x",
+            "--base",
+            "0x08000000",
             "--protocol",
             "swd",
             "--speed",
             "5000",
-            "--verify",
-            "--no-preverify",
-            "--chip-erase",
-        ]);
-        let (_, _, _, protocol, speed, _, _, _, verify, preverify, chip_erase, _, _, _) =
-            parse_args_from(args);
-        match protocol {
-            Protocol::Swd => {}
-            _ => panic!("protocol should be swd"),
+            "--no-verify",
+            "--after",
+            "reset",
+            "--programmer-type",
+            "stlink",
+        ])
+        else {
+            panic!("expected flash");
+        };
+        assert_eq!(base, Some(0x0800_0000));
+        assert_eq!(target.connect.protocol, Protocol::Swd);
+        assert_eq!(target.connect.speed, 5000);
+        assert_eq!(
+            target.connect.programmer.programmer_type.as_deref(),
+            Some("stlink")
+        );
+        assert!(no_verify);
+        assert_eq!(after, After::Reset);
+
+        let Command::Flash { watch, rtt, .. } =
+            parse(&["flash", "fw.elf", "--chip", "x", "--watch", "--rtt"])
+        else {
+            panic!("expected flash");
+        };
+        assert!(watch && rtt);
+        let rtt_alone = [
+            "probe-rs-lib-cli",
+            "flash",
+            "fw.elf",
+            "--chip",
+            "x",
+            "--rtt",
+        ];
+        assert!(Cli::try_parse_from(rtt_alone).is_err());
+    }
+
+    #[test]
+    fn dumps_in_memory_order() {
+        assert_eq!(
+            to_bytes(&[0x1234_5678, 0x4142], Width::W32),
+            [0x78, 0x56, 0x34, 0x12, 0x42, 0x41, 0, 0]
+        );
+        assert_eq!(
+            from_bytes(&[0x78, 0x56, 0x34, 0x12, 0x42, 0x41, 0, 0], Width::W32),
+            [0x1234_5678, 0x4142]
+        );
+        assert_eq!(from_bytes(&[0x01, 0x02], Width::W16), [0x0201]);
+        let dump = hex_dump(0x2000_0000, &[0x41, 0x42, 0x00, 0x7f]);
+        assert_eq!(dump, format!("20000000  {:<47}  |AB..|\n", "41 42 00 7f"));
+    }
+
+    #[test]
+    fn number_formats() {
+        assert_eq!(parse_number("0x1000"), Ok(0x1000));
+        assert_eq!(parse_number("0b1010"), Ok(10));
+        assert_eq!(parse_number("0o77"), Ok(63));
+        assert_eq!(parse_number("4096"), Ok(4096));
+        assert!(parse_number("0xZZ").is_err());
+    }
+
+    #[test]
+    fn erase_areas() {
+        let Command::Erase { range, sector, .. } =
+            parse(&["erase", "--chip", "x", "--range", "0x08004000..0x08008000"])
+        else {
+            panic!("expected erase");
+        };
+        assert_eq!(range, Some(0x0800_4000..0x0800_8000));
+        assert!(sector.is_none());
+
+        let Command::Erase { range, sector, .. } =
+            parse(&["erase", "--chip", "x", "--sector", "0x08060000"])
+        else {
+            panic!("expected erase");
+        };
+        assert!(range.is_none());
+        assert_eq!(sector, Some(0x0806_0000));
+    }
+
+    #[test]
+    fn dry_runs_use_no_probe() {
+        let mut flash = parse(&["flash", "fw.hex", "--chip", "x", "--dry-run"]);
+        assert!(matches!(flash, Command::Flash { dry_run: true, .. }));
+        assert!(flash.probe_selection().is_none());
+        let mut erase = parse(&["erase", "--chip", "x", "--sector", "0x0", "--dry-run"]);
+        assert!(erase.probe_selection().is_none());
+        let args = ["probe-rs-lib-cli", "flash", "fw.hex", "--chip", "x"];
+        let watch = args.iter().copied().chain(["--watch", "--dry-run"]);
+        assert!(Cli::try_parse_from(watch).is_err());
+    }
+
+    #[test]
+    fn verify_mode_of_flash() {
+        let flash = parse(&["flash", "fw.hex", "--chip", "x", "--verify-mode", "sectors"]);
+        let Command::Flash { verify_mode, .. } = flash else {
+            panic!("expected flash");
+        };
+        assert_eq!(verify_mode as i32, 2);
+        let conflicting = ["flash", "fw.hex", "--chip", "x", "--no-verify"];
+        let args = [&conflicting[..], &["--verify-mode", "written"]].concat();
+        assert!(Cli::try_parse_from(std::iter::once("probe-rs-lib-cli").chain(args)).is_err());
+    }
+
+    #[test]
+    fn job_of_flash() {
+        let flash = parse(&["flash", "fw.bin", "--chip", "x", "--job", "fw.job"]);
+        let Command::Flash { job, .. } = flash else {
+            panic!("expected flash");
+        };
+        assert_eq!(job, Some(PathBuf::from("fw.job")));
+        let watch = [
+            "probe-rs-lib-cli",
+            "flash",
+            "fw.bin",
+            "--chip",
+            "x",
+            "--watch",
+        ];
+        assert!(Cli::try_parse_from(watch.into_iter().chain(["--job", "fw.job"])).is_err());
+    }
+
+    #[test]
+    fn buffering_of_flash() {
+        let args = [
+            "flash",
+            "fw.hex",
+            "--chip",
+            "x",
+            "--single-buffer",
+            "--max-page-size",
+            "256",
+        ];
+        let Command::Flash {
+            single_buffer,
+            max_page_size,
+            ..
+        } = parse(&args)
+        else {
+            panic!("expected flash");
+        };
+        assert!(single_buffer);
+        assert_eq!(max_page_size, Some(256));
+        let small = [
+            "probe-rs-lib-cli",
+            "flash",
+            "fw.hex",
+            "--chip",
+            "x",
+            "--max-page-size",
+            "32",
+        ];
+        assert!(Cli::try_parse_from(small).is_err());
+    }
+
+    #[test]
+    fn diff_from_of_flash() {
+        let flash = parse(&["flash", "new.hex", "--chip", "x", "--diff-from", "old.hex"]);
+        let Command::Flash { diff_from, .. } = flash else {
+            panic!("expected flash");
+        };
+        assert_eq!(diff_from, Some(PathBuf::from("old.hex")));
+        let job = [
+            "probe-rs-lib-cli",
+            "flash",
+            "new.hex",
+            "--chip",
+            "x",
+            "--job",
+            "fw.job",
+        ];
+        assert!(Cli::try_parse_from(job.into_iter().chain(["--diff-from", "old.hex"])).is_err());
+    }
+
+    #[test]
+    fn algorithm_ram_of_flashing_commands() {
+        let args = ["--chip", "x", "--algorithm-ram", "0x20001000..0x20002000"];
+        let flash = parse(&[&["flash", "fw.hex"][..], &args].concat());
+        assert_eq!(flash.algorithm_ram(), Some(0x2000_1000..0x2000_2000));
+        let erase = parse(&[&["erase"][..], &args].concat());
+        assert_eq!(erase.algorithm_ram(), Some(0x2000_1000..0x2000_2000));
+        assert_eq!(
+            parse(&["flash", "fw.hex", "--chip", "x"]).algorithm_ram(),
+            None
+        );
+        let read = ["probe-rs-lib-cli", "read", "--address", "0"];
+        assert!(Cli::try_parse_from(read.iter().copied().chain(args)).is_err());
+    }
+
+    #[test]
+    fn reset_strategies() {
+        let Command::Reset { chip, hw, halt, .. } = parse(&["reset", "--hw"]) else {
+            panic!("expected reset");
+        };
+        assert!(chip.is_none() && hw && !halt);
+
+        let Command::Reset {
+            connect_under_reset,
+            halt,
+            ..
+        } = parse(&["reset", "--chip", "x", "--connect-under-reset", "--halt"])
+        else {
+            panic!("expected reset");
+        };
+        assert!(connect_under_reset && halt);
+
+        assert!(Cli::try_parse_from(["probe-rs-lib-cli", "reset"]).is_err());
+        assert!(Cli::try_parse_from(["probe-rs-lib-cli", "reset", "--hw", "--halt"]).is_err());
+        assert!(
+            Cli::try_parse_from([
+                "probe-rs-lib-cli",
+                "reset",
+                "--chip",
+                "x",
+                "--hw",
+                "--connect-under-reset"
+            ])
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn rtt_lines_are_timestamped() {
+        let mut at_line_start = true;
+        let out = timestamp_lines(b"boot\nre", "[t] ", &mut at_line_start);
+        assert_eq!(out, b"[t] boot\n[t] re");
+        let out = timestamp_lines(b"ady\n", "[u] ", &mut at_line_start);
+        assert_eq!(out, b"ady\n");
+        assert!(at_line_start);
+
+        let mut pending = b"one\r\ntwo\nthr".to_vec();
+        assert_eq!(take_lines(&mut pending), ["one", "two"]);
+        assert_eq!(pending, b"thr");
+        assert!(take_lines(&mut pending).is_empty());
+
+        let Command::Rtt {
+            channel, down, elf, ..
+        } = parse(&["rtt", "--chip", "x", "--channel", "1", "--elf", "fw.elf"])
+        else {
+            panic!("expected rtt");
+        };
+        assert_eq!((channel, down), (1, 0));
+        assert_eq!(elf, Some(PathBuf::from("fw.elf")));
+    }
+
+    #[test]
+    fn chip_spec_is_described() {
+        let spec = json!({
+            "chip": "STM32F407VGTx",
+            "manufacturer": "STMicroelectronics",
+            "architecture": "Arm",
+            "regions": "Nvm(0x08000000-0x08100000);Ram(0x20000000-0x2001c000)",
+            "flash_algorithms": "stm32f4xx_1024",
+            "core_details": [{"name": "main", "type": "Armv7em"}],
+        });
+        let layout = json!([{
+            "name": "BANK_1", "start": 0x0800_0000u32, "end": 0x0810_0000u32, "external": false,
+            "algorithms": ["stm32f4xx_1024", "fast"], "compressed": ["fast"], "page_size": 1024,
+            "sectors": [
+                {"address": 0x0800_0000u32, "size": 0x4000},
+                {"address": 0x0801_0000u32, "size": 0x10000},
+                {"address": 0x0802_0000u32, "size": 0x20000},
+            ],
+        }]);
+        let text = describe_chip(&spec, &layout);
+        assert!(text.starts_with("STM32F407VGTx (STMicroelectronics), Arm\n"));
+        assert!(text.contains("  main     Armv7em\n"));
+        assert!(text.contains("  Nvm  0x08000000..0x08100000  1 MiB\n"));
+        assert!(text.contains("  Ram  0x20000000..0x2001c000  112 KiB\n"));
+        assert!(text.contains("algorithms stm32f4xx_1024, fast (compressed)\n"));
+        assert!(text.contains("  4 x 16 KiB sectors from 0x08000000\n"));
+        assert!(text.contains("  7 x 128 KiB sectors from 0x08020000\n"));
+    }
+
+    #[test]
+    fn verify_takes_an_image() {
+        let Command::Verify { file, base, .. } = parse(&[
+            "verify",
+            "--chip",
+            "x",
+            "--file",
+            "fw.bin",
+            "--base",
+            "0x08000000",
+        ]) else {
+            panic!("expected verify");
+        };
+        assert_eq!(file, PathBuf::from("fw.bin"));
+        assert_eq!(base, Some(0x0800_0000));
+        assert!(Cli::try_parse_from(["probe-rs-lib-cli", "verify", "--chip", "x"]).is_err());
+    }
+
+    #[test]
+    fn json_is_a_global_flag() {
+        for args in [
+            &["probe-rs-lib-cli", "--json", "list"][..],
+            &["probe-rs-lib-cli", "info", "--chip", "x", "--json"],
+        ] {
+            assert!(Cli::try_parse_from(args).unwrap().json);
         }
-        assert_eq!(speed, 5000);
-        assert!(verify);
-        assert!(!preverify);
-        assert!(chip_erase);
+        assert!(
+            !Cli::try_parse_from(["probe-rs-lib-cli", "list"])
+                .unwrap()
+                .json
+        );
+    }
+
+    #[test]
+    fn progress_events_are_json() {
+        let cli = Cli::try_parse_from(["probe-rs-lib-cli", "flash", "a.hex", "--chip", "x"]);
+        assert_eq!(cli.unwrap().progress, Progress::Human);
+        let cli = Cli::try_parse_from(["probe-rs-lib-cli", "--progress", "json", "--json", "list"]);
+        assert_eq!(cli.unwrap().progress, Progress::Json);
+        assert!(Cli::try_parse_from(["probe-rs-lib-cli", "--progress", "bar", "list"]).is_err());
+
+        assert_eq!(
+            progress_event(2, 12.3456, Some((4096, 65536)), 1500, false),
+            json!({ "phase": "program", "percent": 12.35, "bytes": 4096, "total": 65536, "eta_ms": 1500, "indeterminate": false })
+        );
+        assert_eq!(
+            progress_event(1, 0.0, Some((0, 0)), -1, true),
+            json!({ "phase": "erase", "percent": 0.0, "bytes": 0, "total": null, "eta_ms": null, "indeterminate": true })
+        );
+    }
+
+    #[test]
+    fn library_errors_are_categorized() {
+        for (message, during, exit) in [
+            (
+                "no matching probes found",
+                Exit::TargetAccess,
+                Exit::ProbeNotFound,
+            ),
+            (
+                "open probe error: busy",
+                Exit::AttachFailed,
+                Exit::ProbeNotFound,
+            ),
+            (
+                "attach error: timeout",
+                Exit::FlashFailed,
+                Exit::AttachFailed,
+            ),
+            (
+                "get_target_by_name error: x",
+                Exit::FlashFailed,
+                Exit::UnknownChip,
+            ),
+            (
+                "flash error: Flash content verification failed.",
+                Exit::FlashFailed,
+                Exit::VerifyFailed,
+            ),
+            (
+                "failed to read image: denied",
+                Exit::TargetAccess,
+                Exit::FileError,
+            ),
+            (
+                "read error at 0x0: fault",
+                Exit::TargetAccess,
+                Exit::TargetAccess,
+            ),
+            ("flash error: timeout", Exit::FlashFailed, Exit::FlashFailed),
+        ] {
+            assert_eq!(during.of_message(message), exit, "{}", message);
+        }
+        assert_eq!(Exit::VerifyFailed as i32, 12);
+        assert_eq!(Exit::FileError.name(), "file-error");
+    }
+
+    #[test]
+    fn run_flashes_and_waits_for_the_exit() {
+        let Command::Run {
+            file,
+            target,
+            no_rtt,
+            timeout,
+            core,
+            ..
+        } = parse(&[
+            "run",
+            "--chip",
+            "x",
+            "--file",
+            "test.elf",
+            "--timeout",
+            "30",
+        ])
+        else {
+            panic!("expected run");
+        };
+        assert_eq!(file, PathBuf::from("test.elf"));
+        assert_eq!(target.chip, "x");
+        assert!(!no_rtt);
+        assert_eq!(timeout, Some(30));
+        assert_eq!(core, 0);
+        assert!(Cli::try_parse_from(["probe-rs-lib-cli", "run", "--chip", "x"]).is_err());
+    }
+
+    #[test]
+    fn trace_options() {
+        let Command::Trace {
+            baud,
+            clock,
+            decode,
+            port,
+            ..
+        } = parse(&["trace", "--chip", "x", "--clock", "168000000"])
+        else {
+            panic!("expected trace");
+        };
+        assert_eq!((baud, clock, decode), (2_000_000, 168_000_000, None));
+        assert!(port.is_empty());
+
+        let Command::Trace { decode, port, .. } = parse(&[
+            "trace", "--chip", "x", "--clock", "64000000", "--decode", "itm", "--port", "0,3",
+        ]) else {
+            panic!("expected trace");
+        };
+        assert_eq!(decode, Some(Decode::Itm));
+        assert_eq!(port, vec![0, 3]);
+
+        let args = ["probe-rs-lib-cli", "trace", "--chip", "x", "--clock", "1"];
+        assert!(Cli::try_parse_from(args.iter().copied().chain(["--port", "0"])).is_err());
+        let itm = ["--decode", "itm", "--port", "32"];
+        assert!(Cli::try_parse_from(args.iter().copied().chain(itm)).is_err());
+        assert!(Cli::try_parse_from(["probe-rs-lib-cli", "trace", "--chip", "x"]).is_err());
+    }
+
+    #[test]
+    fn detection_is_described() {
+        let mut command = parse(&["detect", "--probe-index", "1", "--protocol", "swd"]);
+        assert!(matches!(command.probe_selection(), Some((_, Some(1), _))));
+
+        let found = json!({
+            "protocol": "SWD",
+            "debug_port": {
+                "dpidr": 0x2ba0_1477u32,
+                "version": "DPv1",
+                "designer": { "cc": 4, "id": 0x3b, "name": "ARM Ltd" },
+            },
+            "rom_table": {
+                "access_port": "V1(0)",
+                "address": 0xe00f_f000u32,
+                "manufacturer": { "cc": 0, "id": 0x20, "name": null },
+                "part": 0x413,
+            },
+            "target": null,
+            "cores": [],
+            "candidates": [],
+        });
+        let text = describe_detection(&found);
+        assert!(text.contains("DPIDR 0x2ba01477 (DPv1, designer ARM Ltd)"));
+        assert!(text.contains("JEP106 0/0x20, part 0x413"));
+        assert!(text.contains("not identified"));
+        assert!(text.ends_with("none, pass the chip name with --chip"));
+    }
+
+    #[test]
+    fn log_options_are_global() {
+        let cli =
+            Cli::try_parse_from(["probe-rs-lib-cli", "list", "--log-level", "trace"]).unwrap();
+        assert_eq!(cli.log_level.map(|l| l as i32), Some(5));
+        assert_eq!(cli.log_file, None);
+        let cli =
+            Cli::try_parse_from(["probe-rs-lib-cli", "--log-file", "prl.log", "list"]).unwrap();
+        assert_eq!(cli.log_level, None);
+        assert_eq!(cli.log_file.as_deref(), Some(Path::new("prl.log")));
+        assert!(Cli::try_parse_from(["probe-rs-lib-cli", "--log-level", "loud", "list"]).is_err());
+        assert_eq!(log_level_name(LogLevel::Warn as i32), "WARN");
     }
 
     #[test]
-    fn parse_base_formats() {
-        let args_hex = make_args(&["--base", "0x1000"]);
-        let (_, _, _, _, _, _, base_hex, _, _, _, _, _, _, _) = parse_args_from(args_hex);
-        assert_eq!(base_hex, Some(0x1000));
+    fn benchmark_is_described() {
+        let mut command = parse(&[
+            "bench",
+            "--chip",
+            "x",
+            "--size",
+            "0x400",
+            "--probe-index",
+            "2",
+        ]);
+        assert!(matches!(command.probe_selection(), Some((_, Some(2), _))));
+        assert!(matches!(
+            command,
+            Command::Bench {
+                address: None,
+                size: Some(1024),
+                core: 0,
+                ..
+            }
+        ));
+
+        let bench = json!({
+            "probe": "STLink V2",
+            "protocol": "SWD",
+            "requested_speed_khz": 4000,
+            "speed_khz": 1800,
+            "target": "STM32F407VGTx",
+            "address": 0x1000_0000u32,
+            "size": 1024,
+            "results": [
+                { "block_size": 4, "write_bytes_per_sec": 2048, "read_bytes_per_sec": 3072 },
+                { "block_size": 1024, "write_bytes_per_sec": 102_400, "read_bytes_per_sec": 97_280 },
+            ],
+        });
+        let text = describe_benchmark(&bench);
+        assert!(text.contains("Clock:   1800 kHz (requested 4000 kHz)"));
+        assert!(text.contains("1 KiB at 0x10000000"));
+        assert!(text.ends_with("1 KiB     100.0 KiB/s      95.0 KiB/s"));
+    }
 
-        let args_bin = make_args(&["--base", "0b1010"]);
-        let (_, _, _, _, _, _, base_bin, _, _, _, _, _, _, _) = parse_args_from(args_bin);
-        assert_eq!(base_bin, Some(10));
+    #[test]
+    fn probes_are_selected_by_index() {
+        let mut command = parse(&["erase", "--chip", "x", "--probe-index", "1"]);
+        let (probe, index, _) = command.probe_selection().unwrap();
+        assert!(probe.is_none());
+        assert_eq!(index, Some(1));
+        let mut command = parse(&[
+            "erase",
+            "--chip",
+            "x",
+            "--probe",
+            "1:2",
+            "--probe-index",
+            "0",
+        ]);
+        assert_eq!(command.probe_selection().unwrap().1, Some(0));
+        assert!(parse(&["list"]).probe_selection().is_none());
+        assert!(parse(&["info", "--chip", "x"]).probe_selection().is_none());
 
-        let args_oct = make_args(&["--base", "0o77"]);
-        let (_, _, _, _, _, _, base_oct, _, _, _, _, _, _, _) = parse_args_from(args_oct);
-        assert_eq!(base_oct, Some(63));
+        let selector = probe_selector(0x0d28, 0x0204, "0240000034");
+        assert_eq!(selector.as_deref(), Some("0d28:0204:0240000034"));
+        assert_eq!(probe_selector(0x0d28, 0x0204, ""), None);
+    }
 
-        let args_dec = make_args(&["--base", "4096"]);
-        let (_, _, _, _, _, _, base_dec, _, _, _, _, _, _, _) = parse_args_from(args_dec);
-        assert_eq!(base_dec, Some(4096));
+    #[test]
+    fn options_have_environment_variables() {
+        let cli = Cli::command();
+        let env = |command: &clap::Command, id: &str| {
+            let arg = command.get_arguments().find(|a| a.get_id() == id)?;
+            Some(arg.get_env()?.to_string_lossy().into_owned())
+        };
+        assert_eq!(env(&cli, "dll").as_deref(), Some("PRL_DLL"));
+        for name in ["flash", "reset"] {
+            let command = cli.find_subcommand(name).unwrap();
+            assert_eq!(env(command, "chip").as_deref(), Some("PRL_CHIP"));
+            assert_eq!(env(command, "probe").as_deref(), Some("PRL_PROBE"));
+            assert_eq!(env(command, "speed").as_deref(), Some("PRL_SPEED"));
+            let programmer_type = env(command, "programmer_type");
+            assert_eq!(programmer_type.as_deref(), Some("PRL_PROGRAMMER_TYPE"));
+        }
+        // English comments: `info` lists the chip database unless --chip is given
+        let info = cli.find_subcommand("info").unwrap();
+        assert_eq!(env(info, "chip"), None);
     }
 
     #[test]
-    fn parse_ops_chips_detect_spec() {
-        let a_chips = make_args(&["--op", "chips"]);
-        let (_, _, _, _, _, op_chips, _, _, _, _, _, _, _, _) = parse_args_from(a_chips);
-        assert_eq!(op_chips, Some("chips".to_string()));
+    fn gdb_server_defaults() {
+        let Command::GdbServer {
+            target,
+            port,
+            flash,
+            core,
+            ..
+        } = parse(&["gdb-server", "--chip", "x", "--flash", "fw.elf"])
+        else {
+            panic!("expected gdb-server");
+        };
+        assert_eq!(target.chip, "x");
+        assert_eq!(port, 1337);
+        assert_eq!(flash, Some(PathBuf::from("fw.elf")));
+        assert_eq!(core, 0);
+        let args = [
+            "probe-rs-lib-cli",
+            "gdb-server",
+            "--chip",
+            "x",
+            "--base",
+            "0",
+        ];
+        assert!(Cli::try_parse_from(args).is_err());
+    }
 
-        let a_detect = make_args(&["--op", "detect"]);
-        let (_, _, _, _, _, op_detect, _, _, _, _, _, _, _, _) = parse_args_from(a_detect);
-        assert_eq!(op_detect, Some("detect".to_string()));
+    #[test]
+    fn read_and_write_words() {
+        let Command::Read {
+            address,
+            length,
+            width,
+            out,
+            hexdump,
+            core,
+            ..
+        } = parse(&[
+            "read",
+            "--address",
+            "0x20000000",
+            "--chip",
+            "x",
+            "--length",
+            "256",
+            "--width",
+            "16",
+            "--hexdump",
+        ])
+        else {
+            panic!("expected read");
+        };
+        assert_eq!(address, 0x2000_0000);
+        assert_eq!((length, width, core), (256, Width::W16, 0));
+        assert!(out.is_none() && hexdump);
 
-        let a_spec = make_args(&["--op", "spec", "--chip", "nrf51822_Xxaa"]);
-        let (chip, _, _, _, _, op_spec, _, _, _, _, _, _, _, _) = parse_args_from(a_spec);
-        assert_eq!(op_spec, Some("spec".to_string()));
-        assert_eq!(chip, Some("nrf51822_Xxaa".to_string()));
+        let Command::Write {
+            address,
+            value,
+            file,
+            width,
+            ..
+        } = parse(&[
+            "write",
+            "--address",
+            "0x40021000",
+            "--value",
+            "0x12,0x34",
+            "--value",
+            "56",
+            "--chip",
+            "x",
+        ])
+        else {
+            panic!("expected write");
+        };
+        assert_eq!(address, 0x4002_1000);
+        assert_eq!(value, vec![0x12, 0x34, 56]);
+        assert!(file.is_none());
+        assert_eq!(width, Width::W32);
     }
 
     #[test]
-    fn parse_read16_write16_params() {
-        let args_read = make_args(&["--op", "read16", "--len", "10"]);
-        let (_, _, _, _, _, op_read, _, _, _, _, _, _, len, _) = parse_args_from(args_read);
-        assert_eq!(op_read, Some("read16".to_string()));
-        assert_eq!(len, Some(10));
-
-        let args_write = make_args(&["--op", "write16", "--data", "0x12,0x34,56"]);
-        let (_, _, _, _, _, op_write, _, _, _, _, _, _, _, data) = parse_args_from(args_write);
-        assert_eq!(op_write, Some("write16".to_string()));
-        assert_eq!(data, vec![0x12, 0x34, 56]);
+    fn typos_and_missing_options_are_rejected() {
+        let run = |args: &[&str]| {
+            Cli::try_parse_from(std::iter::once("probe-rs-lib-cli").chain(args.iter().copied()))
+        };
+        assert!(run(&["flash", "fw.hex", "--chip", "x", "--no-verfy"]).is_err());
+        assert!(run(&["flash", "fw.hex"]).is_err());
+        assert!(run(&["read", "--address", "0", "--chip", "x", "--width", "24"]).is_err());
+        assert!(
+            run(&[
+                "read",
+                "--address",
+                "0",
+                "--chip",
+                "x",
+                "--out",
+                "a",
+                "--hexdump"
+            ])
+            .is_err()
+        );
+        assert!(run(&["info", "--connect"]).is_err());
+        assert!(run(&["write", "--address", "0", "--chip", "x"]).is_err());
+        let both = [
+            "write",
+            "--address",
+            "0",
+            "--chip",
+            "x",
+            "--value",
+            "1",
+            "--file",
+            "a",
+        ];
+        assert!(run(&both).is_err());
+        assert!(run(&["erase", "--chip", "x", "--range", "0x100..0x80"]).is_err());
+        assert!(run(&["erase", "--chip", "x", "--range", "0..4", "--sector", "0"]).is_err());
+        assert!(run(&["--op", "list"]).is_err());
+        assert!(run(&["list", "--dll", "lib.so"]).is_ok());
     }
 }
@@ -9,25 +9,98 @@ extern "C" {
 /*
  Error API
  - Retrieve the last error string. If buf==NULL or buf_len==0, returns the required size (including NUL).
+ - The error is kept per thread: it is that of the calling thread's last failing call.
+
+ Threads: sessions are independent, so sessions on different probes can be used (and flashed)
+ concurrently from different threads; calls on the same session are serialized. Use a progress
+ callback per session (pr_session_set_progress_callback) and a programmer type per thread
+ (pr_set_thread_programmer_type_code) so concurrent work does not share those settings.
+ The flash options are shared by all sessions and threads: pr_flash_set_after, pr_flash_set_patch,
+ pr_flash_option_preserve_range, pr_flash_option_algorithm_ram, pr_flash_option_verify_mode,
+ pr_flash_option_compression and pr_flash_option_buffering apply to every flash started after the
+ call, so threads flashing concurrently must use the same options or set them while no flash runs.
 */
 size_t pr_last_error(char* buf, size_t buf_len);
 
+/*
+ API manifest (for binding generators)
+ - pr_get_api_manifest_json: this header as JSON: {"version", "functions": [{"name", "return",
+   "params": [{"name", "type"}]}], "callbacks" (function pointer typedefs, same form), "enums":
+   [{"name", "values": [{"name", "value"}]}], "constants": [{"name", "value"}]}. Types are spelled as
+   in this header. Returns the required size including NUL, or 0 on error.
+*/
+size_t pr_get_api_manifest_json(char* out_json, size_t out_json_len);
+
 /*
  Version API
  - Returns the library version string length (including NUL). If buf provided, writes the version string.
 */
 size_t pr_version(char* buf, size_t buf_len);
 
+/*
+ ABI version
+ - PR_ABI_VERSION_MAJOR changes with every incompatible change (changed signature or meaning, removed
+   function), PR_ABI_VERSION_MINOR with every addition. pr_abi_version writes the library's version
+   (either pointer may be NULL); hosts loading the library dynamically should refuse one with another
+   major or a lower minor version than this header.
+*/
+#define PR_ABI_VERSION_MAJOR 1
+#define PR_ABI_VERSION_MINOR 38
+void pr_abi_version(uint32_t* out_major, uint32_t* out_minor);
+
+/*
+ Library allocated strings (for .NET SafeHandle / JNI callers)
+ - pr_string_alloc: zeroed buffer of len bytes (at least 1), e.g. to pass to a string-writing call.
+   Returns NULL if len is too large.
+ - pr_string_free: free a buffer from pr_string_alloc or a string from a *_alloc call; NULL is ignored.
+ - The *_alloc calls return what the call without the suffix writes, as a NUL-terminated UTF-8 string
+   to be freed with pr_string_free, or NULL where that call fails (see pr_last_error). They exist for
+   the calls without side effects.
+ - Handles: 0 is never a valid session handle, so a SafeHandle can use 0 as invalid and release with
+   pr_session_close (0 = closed); string SafeHandles release with pr_string_free.
+*/
+char* pr_string_alloc(size_t len);
+void  pr_string_free(char* s);
+char* pr_last_error_alloc(void);
+char* pr_version_alloc(void);
+char* pr_get_api_manifest_json_alloc(void);
+char* pr_probe_list_json_alloc(void);
+char* pr_probe_version_info_alloc(uint32_t index);
+char* pr_probe_associated_serial_ports_alloc(uint32_t index);
+char* pr_programmer_type_to_string_alloc(int32_t type_code);
+char* pr_flash_last_timing_alloc(void);
+char* pr_flash_bank_info_alloc(uint64_t session);
+char* pr_flash_get_protection_alloc(uint64_t session);
+char* pr_flash_sector_layout_alloc(const char* chip);
+char* pr_chip_manufacturer_name_alloc(uint32_t index);
+char* pr_chip_model_name_alloc(uint32_t manu_index, uint32_t chip_index);
+char* pr_chip_family_name_alloc(uint32_t manu_index, uint32_t family_index);
+char* pr_chip_model_name_in_family_alloc(uint32_t manu_index, uint32_t family_index, uint32_t index);
+char* pr_jep106_name_alloc(uint8_t cc, uint8_t id);
+char* pr_chip_list_json_alloc(const char* manufacturer_filter, const char* family_filter,
+                              uint32_t offset, uint32_t limit);
+char* pr_elf_address_symbol_alloc(const char* elf_path, uint64_t address);
+char* pr_svd_peripheral_list_alloc(uint64_t session);
+char* pr_svd_register_read_alloc(uint64_t session, uint32_t core_index, const char* name);
+
 /*
  Probe listing
  - Count connected debug probes
  - Query probe info (identifier, VID, PID, optional serial)
+ - The _filtered variants only count probes of a programmer type (see pr_set_programmer_type_code);
+   type_code 0 = the configured type, or all probes if none is set, which is what sessions attach
+   with. Their indices are only valid for pr_probe_info_filtered. -1 on unsupported type code.
 */
 uint32_t pr_probe_count(void);
 int32_t pr_probe_info(uint32_t index,
                       char* identifier, size_t identifier_len,
                       uint16_t* vid, uint16_t* pid,
                       char* serial, size_t serial_len);
+int32_t pr_probe_count_filtered(int32_t type_code);
+int32_t pr_probe_info_filtered(int32_t type_code, uint32_t index,
+                               char* identifier, size_t identifier_len,
+                               uint16_t* vid, uint16_t* pid,
+                               char* serial, size_t serial_len);
 
 /*
  Probe capabilities & target detection
@@ -45,6 +118,7 @@ int32_t pr_probe_info(uint32_t index,
 #define PR_DRIVER_SIFLI_UART    0x00000040u
 #define PR_DRIVER_GLASGOW       0x00000080u
 #define PR_DRIVER_CH347_USBJTAG 0x00000100u
+#define PR_DRIVER_BLACKMAGIC    0x00000200u
 
 /* Feature flag bits */
 #define PR_FEATURE_SWD          0x00000001u
@@ -58,14 +132,162 @@ int32_t pr_probe_info(uint32_t index,
 int32_t pr_probe_features(uint32_t index, uint32_t* out_driver_flags, uint32_t* out_feature_flags);
 int32_t pr_probe_check_target(uint32_t index);
 
+/*
+ Probe list
+ - pr_probe_list_json: all probes in pr_probe_count order as a JSON array of {"index", "identifier",
+   "vid", "pid", "vid_pid", "serial", "driver", "driver_code", "capabilities": {"swd", "jtag", "arm",
+   "riscv", "xtensa", "swo"}}. driver is the programmer type name ("cmsis-dap", ...) and driver_code its
+   code (null and 0 if none); capabilities are the driver's, the probes are not opened (see
+   pr_probe_features). Returns the required size including NUL, or 0 on error.
+*/
+size_t pr_probe_list_json(char* out_json, size_t out_json_len);
+
+/*
+ Target identification (no chip name needed)
+ - pr_target_detect: connect to the target of a probe (selector VID:PID[:SN], or NULL for the first
+   probe of the programmer type in effect; speed_khz 0 = default; protocol_code 0 auto, 1 SWD, 2 JTAG)
+   and report JSON {"protocol", "debug_port", "rom_table", "target", "architecture", "cores",
+   "candidates"}. debug_port {"dpidr", "version", "part", "revision", "designer"} and rom_table
+   {"access_port", "address", "manufacturer", "part"} are read over the ARM debug interface (null on
+   other architectures); JEP106 codes are {"cc", "id", "name"}. target is the chip probe-rs'
+   autodetection identified (null if none) with its architecture and cores {"name", "type"};
+   candidates lists it and the registry targets matching the ROM table's manufacturer and part.
+   Returns the required size including NUL, or 0 if the probe cannot be opened or does not connect.
+*/
+size_t pr_target_detect(const char* selector, uint32_t speed_khz, int32_t protocol_code, char* out_json,
+                        size_t out_json_len);
+
+/*
+ Probe benchmark (the numbers to attach to "flashing is slow" reports)
+ - pr_benchmark: attach to chip through a probe (selector, speed_khz and protocol_code as for
+   pr_target_detect), halt core core_index, and write then read back size bytes of RAM at address in
+   blocks of 4 bytes up to 64 KiB. address 0 = the first writable RAM region of the core, size 0 = up
+   to 64 KiB of it. The RAM is restored and a running core resumed afterwards. JSON {"probe",
+   "protocol", "requested_speed_khz", "speed_khz", "target", "address", "size", "results":
+   [{"block_size", "write_bytes_per_sec", "read_bytes_per_sec"}]}; speed_khz is the clock the probe
+   actually runs at. Returns the required size including NUL, or 0 on error (also on a readback
+   mismatch).
+*/
+size_t pr_benchmark(const char* chip, const char* selector, uint32_t speed_khz, int32_t protocol_code,
+                    uint32_t core_index, uint64_t address, uint64_t size, char* out_json, size_t out_json_len);
+
+/*
+ Network probes (probes attached to another machine, e.g. a lab host of a CI farm)
+ - Addresses: "tcp:host:port" for a Black Magic Probe speaking its remote protocol over TCP,
+   "glasgow:tcp:host:port" or "glasgow:unix:path" for a Glasgow. They are accepted as the selector of
+   pr_session_open_with_probe (and the other VID:PID[:SN] selectors) without registering.
+ - pr_probe_add_remote: list the probe after the USB probes in pr_probe_count, pr_probe_info,
+   pr_probe_list_json, ..., and let pr_session_open_auto pick it (programmer type "blackmagic" or
+   "glasgow"). Nothing is connected until it is opened; host names are resolved now. Returns 0 on
+   success (also if already added), -1 on an invalid or unresolvable address.
+ - pr_probe_remove_remote: stop listing it; open sessions are not affected. -1 if not added.
+*/
+int32_t pr_probe_add_remote(const char* address);
+int32_t pr_probe_remove_remote(const char* address);
+
+/*
+ GDB server attach (use a probe owned by a running GDB server: probe-rs gdb, OpenOCD, J-Link GDB
+ server, ... e.g. to flash from an IDE tool without fighting the debug server for the USB device).
+ The server must accept the connection; probe-rs gdb serves one client at a time.
+ - pr_gdb_attach: connect to "host:port" and stop the target. timeout_ms bounds the connection and
+   each reply (0 = 5000); flash erase/programming may take up to 5 minutes. Returns a handle, 0 on
+   error. Handles are distinct from session handles.
+ - pr_gdb_detach: detach (the server decides whether the target runs) and invalidate the handle.
+ - pr_gdb_halt / pr_gdb_continue: stop or resume the target; while it runs the other calls return -1.
+ - pr_gdb_read_memory / pr_gdb_write_memory: memory access (write RAM; flash with pr_gdb_flash).
+ - pr_gdb_monitor: run a monitor command ("reset", "reset halt" with probe-rs gdb) and return its
+   output; if out is NULL/too small the output is kept for a repeated call with the same command.
+   Returns the required size including NUL, or 0 on error.
+ - pr_gdb_flash: flash an image with vFlashErase/vFlashWrite/vFlashDone, erasing the blocks of the
+   server's memory map it touches; data outside flash is written as memory. format and base as for
+   pr_flash_check_fit. The target is not reset. Returns 0 ok, 1 invalid arguments or running
+   target, 2 flash error.
+ Other calls return 0 on success, -1 on an invalid handle/arguments or a running target, -2 on a
+ connection or target error.
+*/
+uint64_t pr_gdb_attach(const char* address, uint32_t timeout_ms);
+int32_t  pr_gdb_detach(uint64_t gdb);
+int32_t  pr_gdb_halt(uint64_t gdb);
+int32_t  pr_gdb_continue(uint64_t gdb);
+int32_t  pr_gdb_read_memory(uint64_t gdb, uint64_t address, uint8_t* out, size_t len);
+int32_t  pr_gdb_write_memory(uint64_t gdb, uint64_t address, const uint8_t* data, size_t len);
+size_t   pr_gdb_monitor(uint64_t gdb, const char* command, char* out, size_t out_len);
+int32_t  pr_gdb_flash(uint64_t gdb, const char* path, const char* format, uint64_t base);
+
+/*
+ GDB server (serve a core of an open session to GDB or an IDE: target extended-remote :port)
+ - pr_gdb_server_start: listen on 127.0.0.1:port (0 picks a free port) for one client at a time;
+   the core is halted when a client connects. Registers are the core's main group (no FPU
+   registers), breakpoints are hardware breakpoints; GDB's load flashes through vFlashWrite with
+   the target's memory map, "monitor reset" resets and halts. The session stays usable from other
+   calls. Returns the port, -1 on invalid handle/core, an Xtensa core or if the session is served
+   already, -2 if the port cannot be bound.
+ - pr_gdb_server_stop: stop serving; a connected client is dropped within 100 ms. Returns the port,
+   -1 if not served. pr_session_close stops the server too.
+*/
+int32_t pr_gdb_server_start(uint64_t session, uint32_t core_index, uint16_t port);
+int32_t pr_gdb_server_stop(uint64_t session);
+/*
+ Probe firmware/hardware version (the probe is opened, so it must not be in use)
+ - pr_probe_version_info: JSON {"name", "identifier", "serial", "firmware", "hardware", "details": {}}.
+   firmware is in the vendor's notation (ST-Link "V2J45", CMSIS-DAP firmware string, J-Link banner);
+   details holds driver specific entries (CMSIS-DAP interface version, packet size, capabilities).
+   Unknown fields are null. Returns the required size including NUL, or 0 on error.
+*/
+size_t pr_probe_version_info(uint32_t index, char* out_json, size_t out_json_len);
+/*
+ - pr_probe_needs_firmware_update: check against known-bad firmware (ST-Link V2 before V2J32, ST-Links
+   too old to be opened, DAPLink before build 0254). The reason is written to out_reason (may be NULL).
+   Returns 1 if an update is recommended, 0 if no problem is known, -1 if the probe could not be queried.
+*/
+int32_t pr_probe_needs_firmware_update(uint32_t index, char* out_reason, size_t out_reason_len);
+/*
+ - pr_probe_cmsisdap_transport: the transport a CMSIS-DAP probe is opened with, after applying the
+   driver options ("cmsisdap.transport"). v1 (HID) is a fallback that flashes far slower than v2 (bulk).
+   Returns 1 for v1, 2 for v2, -1 if the probe could not be opened, -3 if it is not a CMSIS-DAP probe.
+*/
+int32_t pr_probe_cmsisdap_transport(uint32_t index);
+/*
+ - pr_probe_associated_serial_ports: the CDC-ACM serial ports (VCP) on the probe's USB device, matched by
+   VID, PID and serial number, as a JSON array of {"port", "interface", "product"}; port is the name to
+   open ("COM7", "/dev/ttyACM0"). The probe is not opened. Returns the required size including NUL, or 0
+   on error.
+*/
+size_t pr_probe_associated_serial_ports(uint32_t index, char* out_json, size_t out_json_len);
+
 /*
  Session management
  - Open/close sessions. Returns a non-zero session handle on success.
  - protocol_code: 0=auto, 1=SWD, 2=JTAG; speed_khz=0 means not set.
+ - pr_session_close_ex: detach_mode 0 = leave running (halted cores are resumed), 1 = leave halted
+   (debugging stays enabled), 2 = reset and run. Hardware/software breakpoints are removed in every
+   mode. pr_session_close is pr_session_close_ex with mode 0. Returns 0, or -1 on invalid handle/mode.
+ - pr_session_count / pr_session_list: the open sessions, to find leaked handles. pr_session_list
+   writes up to max handles (oldest first; out_handles may be NULL if max is 0) and returns the
+   number of open sessions, which may exceed max.
+ - pr_session_close_all: pr_session_close_ex on every open session. Returns the number closed, or -1
+   on invalid mode.
 */
 uint64_t pr_session_open_auto(const char* chip, uint32_t speed_khz, int32_t protocol_code);
 uint64_t pr_session_open_with_probe(const char* selector, const char* chip, uint32_t speed_khz, int32_t protocol_code);
 int32_t pr_session_close(uint64_t session);
+int32_t pr_session_close_ex(uint64_t session, int32_t detach_mode);
+uint32_t pr_session_count(void);
+uint32_t pr_session_list(uint64_t* out_handles, uint32_t max);
+int32_t pr_session_close_all(int32_t detach_mode);
+
+/*
+ Session recovery (USB glitches, cable hiccups)
+ - pr_session_reconnect: reopen the session's probe (same selector, speed and protocol) and re-attach
+   to the target without resetting it; the handle stays valid and background pollers, monitors and
+   terminals continue. The old probe is closed first without touching the target. Returns 0, -1
+   invalid handle, -2 if reopening failed (target calls then fail until a reconnect succeeds).
+ - pr_set_auto_reconnect: when a memory or register access (pr_read_8/16/32, pr_write_8/16/32,
+   pr_read_reg_u64, pr_write_reg_u64) fails with a USB error, reconnect and retry it up to attempts
+   times, 500 ms apart; 0 = off (default). Applies to all sessions.
+*/
+int32_t pr_session_reconnect(uint64_t session);
+int32_t pr_set_auto_reconnect(uint32_t attempts);
 uint32_t pr_core_count(uint64_t session);
 
 /*
@@ -75,6 +297,15 @@ uint32_t pr_core_count(uint64_t session);
 int32_t pr_core_halt(uint64_t session, uint32_t core_index, uint32_t timeout_ms);
 int32_t pr_core_run(uint64_t session, uint32_t core_index);
 int32_t pr_core_step(uint64_t session, uint32_t core_index);
+/*
+ Step over / step out using temporary breakpoints (hardware if available, software otherwise).
+ - pr_core_step_over: single step, but run over calls (BL/BLX/JAL/CALLn...) until they return.
+ - pr_core_step_out: run until the current function returns (return address taken from LR/ra).
+ Both need a halted core. Returns 0 on success, -1 on invalid handle/core, -2 on target error,
+ -3 if the target was not reached within 5 s (the core is halted again).
+*/
+int32_t pr_core_step_over(uint64_t session, uint32_t core_index);
+int32_t pr_core_step_out(uint64_t session, uint32_t core_index);
 int32_t pr_core_reset(uint64_t session, uint32_t core_index);
 int32_t pr_core_reset_and_halt(uint64_t session, uint32_t core_index, uint32_t timeout_ms);
 
@@ -98,6 +329,89 @@ int32_t pr_write_16(uint64_t session, uint32_t core_index, uint64_t address, con
 
 int32_t pr_read_32(uint64_t session, uint32_t core_index, uint64_t address, uint32_t* buf, uint32_t len_words);
 int32_t pr_write_32(uint64_t session, uint32_t core_index, uint64_t address, const uint32_t* buf, uint32_t len_words);
+/*
+ pr_read_mem_nonstop: read memory without halting the core. Returns 0 on success, -1 on invalid input/handle,
+ -2 on read error, -3 if the core is running and the architecture/probe cannot read without halting
+ (supported on ARM; RISC-V and Xtensa only while halted).
+*/
+int32_t pr_read_mem_nonstop(uint64_t session, uint32_t core_index, uint64_t address, uint8_t* buf, uint32_t len);
+/*
+ pr_memory_modify_bits: clear clear_mask, then set set_mask in the width-byte (1, 2, 4 or 8) value at
+ address as a single read-modify-write with the session locked. Returns 0 on success, -1 on invalid
+ input/handle, -2 on memory access error.
+*/
+int32_t pr_memory_modify_bits(uint64_t session, uint32_t core_index, uint64_t address, uint32_t width,
+                              uint64_t clear_mask, uint64_t set_mask);
+/*
+ pr_memory_compare_file: compare target memory at address (read through core 0) byte by byte with the
+ file at path. out_first_diff (written only on a mismatch) and out_diff_count may be NULL.
+ Returns 0 if equal, 1 if different, -1 on invalid input/handle or unreadable file, -2 on read error.
+*/
+int32_t pr_memory_compare_file(uint64_t session, uint64_t address, const char* path, uint64_t* out_first_diff,
+                               uint64_t* out_diff_count);
+/*
+ Batched memory accesses: queue reads and writes on the host and run them with one commit, the session
+ locked and the core attached once. Only contiguous accesses of the same kind and width are coalesced,
+ into block transfers (CMSIS-DAP transfer blocks, J-Link multi-word reads); each read at a scattered
+ address still takes its own USB round-trip.
+ - pr_batch_begin: start a batch on core_index. Returns a non-zero handle, or 0 on invalid session/core.
+ - pr_batch_queue_read / pr_batch_queue_write: queue count elements of width bytes (1, 2, 4 or 8) at
+   address; write data is in host byte order and copied. Return the index of the access, or -1.
+ - pr_batch_results_size: bytes of read results of the accesses queued so far (0 on invalid handle).
+ - pr_batch_commit: run the accesses in order and end the batch. Read data goes to out_results one read
+   after the other in queue order, host byte order; out_completed (may be NULL) receives how many queued
+   accesses ran. Returns 0 on success, -1 on invalid handle or buffer smaller than pr_batch_results_size
+   (the batch is kept), -2 on target error (results of the completed accesses are valid).
+ - pr_batch_discard: end a batch without running it. Returns 0, or -1 on invalid handle.
+ Batches are dropped when their session is closed.
+*/
+uint64_t pr_batch_begin(uint64_t session, uint32_t core_index);
+int32_t pr_batch_queue_read(uint64_t batch, uint64_t address, uint32_t width, uint32_t count);
+int32_t pr_batch_queue_write(uint64_t batch, uint64_t address, uint32_t width, const void* data, uint32_t count);
+size_t pr_batch_results_size(uint64_t batch);
+int32_t pr_batch_commit(uint64_t batch, void* out_results, size_t results_len, uint32_t* out_completed);
+int32_t pr_batch_discard(uint64_t batch);
+/*
+ Memory access with bus attributes (ARM ADIv5 AHB-APs): read/write len bytes at address through the
+ MEM-AP ap_index of the debug port of core_index (the core's own AP if ap_index < 0, e.g. a system AP
+ otherwise), with the CSW protection bits chosen by attrs. attrs = 0 gives the attributes of pr_read_8
+ (privileged, cacheable, secure if secure debug is enabled). The AP's CSW is restored afterwards.
+ Returns 0 on success, -1 on invalid input/handle or an address beyond 32 bits, -2 on AP/memory error,
+ -3 if the AP is no AHB-AP or PR_MEM_ATTR_SECURE is requested while secure debug is disabled.
+*/
+#define PR_MEM_ATTR_NONSECURE 1      /* HNONSEC = 1: access as Non-secure */
+#define PR_MEM_ATTR_SECURE 2         /* HNONSEC = 0: access as Secure */
+#define PR_MEM_ATTR_UNPRIVILEGED 4   /* HPROT[1] = 0: user mode access */
+#define PR_MEM_ATTR_NONCACHEABLE 8   /* HPROT[3] = 0: bypass caches */
+#define PR_MEM_ATTR_BUFFERABLE 0x10  /* HPROT[2] = 1 */
+int32_t pr_read_mem_ap(uint64_t session, uint32_t core_index, int32_t ap_index, uint64_t address, uint8_t* buf,
+                       uint32_t len, uint32_t attrs);
+int32_t pr_write_mem_ap(uint64_t session, uint32_t core_index, int32_t ap_index, uint64_t address,
+                        const uint8_t* buf, uint32_t len, uint32_t attrs);
+/*
+ Address translation: debug memory accesses are physical, which reads the wrong thing once an MMU is on.
+ - pr_core_translate_address: the physical address virt of core_index maps to right now. RISC-V (RV32)
+   harts halted in S or U mode are translated by walking their Sv32 page table; M mode, satp Bare and
+   Cortex-M (no MMU) are identity. Returns 0 with *out_phys set, 1 if virt is not mapped, -1 on invalid
+   input/handle, -2 on target error, -3 if unsupported: Cortex-A translation table walks (ARMv7-A
+   short-descriptor/LPAE, ARMv8-A), RV64 Sv39/Sv48 paging, Xtensa, or a running RISC-V hart.
+ - pr_read_mem_virtual / pr_write_mem_virtual: access memory at virt as the core sees it, translating page
+   by page. Same return codes; on 1 (part of the range unmapped) nothing is accessed.
+*/
+int32_t pr_core_translate_address(uint64_t session, uint32_t core_index, uint64_t virt, uint64_t* out_phys);
+int32_t pr_read_mem_virtual(uint64_t session, uint32_t core_index, uint64_t virt, uint8_t* buf, uint32_t len);
+int32_t pr_write_mem_virtual(uint64_t session, uint32_t core_index, uint64_t virt, const uint8_t* buf,
+                             uint32_t len);
+/*
+ pr_ram_test: RAM integrity test for board bring-up. Tests the region_index-th RAM region of the memory
+ map, or start/size if region_index < 0 (word aligned). pattern_mode bits: 1 = walking ones/zeros
+ (data lines), 2 = address-in-address and inverse (address lines); 0 = all. Destroys the RAM contents
+ and halts core 0 first. out_report receives JSON {"start", "end", "passed", "tests": [{"name",
+ "errors", "first_error": {"address", "expected", "actual"} or null}]} (cut off if too small).
+ Returns 0 passed, 1 errors found, -1 invalid input/handle, -2 memory access error.
+*/
+int32_t pr_ram_test(uint64_t session, int32_t region_index, uint64_t start, uint64_t size, uint32_t pattern_mode,
+                    char* out_report, size_t out_report_len);
 
 /*
  Register operations
@@ -117,6 +431,16 @@ int32_t pr_available_breakpoint_units(uint64_t session, uint32_t core_index, uin
 int32_t pr_set_hw_breakpoint(uint64_t session, uint32_t core_index, uint64_t address);
 int32_t pr_clear_hw_breakpoint(uint64_t session, uint32_t core_index, uint64_t address);
 int32_t pr_clear_all_hw_breakpoints(uint64_t session);
+/*
+ Software breakpoints patch a break instruction (BKPT/BRK/EBREAK/BREAK) into RAM-resident code and
+ restore the original instruction when cleared or when the session is closed. pr_core_run and
+ pr_core_step transparently execute the original instruction when halted on one.
+ Returns 0 on success, -1 on invalid handle/core, -2 if the address cannot be patched (e.g. flash)
+ or no software breakpoint exists there.
+*/
+int32_t pr_set_sw_breakpoint(uint64_t session, uint32_t core_index, uint64_t address);
+int32_t pr_clear_sw_breakpoint(uint64_t session, uint32_t core_index, uint64_t address);
+int32_t pr_clear_all_sw_breakpoints(uint64_t session);
 
 /* Flashing operations (firmware programming)
 */
@@ -131,6 +455,53 @@ int32_t pr_clear_all_hw_breakpoints(uint64_t session);
 typedef void (*pr_progress_cb)(int32_t operation, float percent, const char* status, int32_t eta_ms);
 void pr_set_progress_callback(pr_progress_cb cb);
 void pr_clear_progress_callback(void);
+/*
+ - pr_progress_bytes: from within a progress callback, write the bytes done and the total
+   announced for the operation (0 if unknown) to out_done/out_total (either may be NULL); outside
+   one, those of the last report on the calling thread. Returns 0, or -1 if no report was made
+   on the calling thread yet.
+*/
+int32_t pr_progress_bytes(uint64_t* out_done, uint64_t* out_total);
+/*
+ - pr_progress_indeterminate: from within a progress callback, 1 if the report is estimated rather
+   than measured, 0 otherwise; outside one, that of the last report on the calling thread. A chip
+   erase reports nothing until it is done: its status is "erasing chip" and percent/eta_ms are
+   derived from the target's typical mass erase time (0 and -1 if unknown), reported from a helper
+   thread while the flash call waits. GUIs can show an indeterminate bar for these. Returns -1 if
+   no report was made on the calling thread yet.
+*/
+int32_t pr_progress_indeterminate(void);
+/*
+ - pr_session_set_progress_callback: report the session's flash progress (pr_session_flash,
+   pr_flash_bin_to_region) to cb instead of the global callback; cb NULL returns to the global one.
+   Returns 0, or -1 on invalid handle.
+*/
+typedef void (*pr_session_progress_cb)(uint64_t session, int32_t operation, float percent,
+                                       const char* status, int32_t eta_ms);
+int32_t pr_session_set_progress_callback(uint64_t session, pr_session_progress_cb cb);
+
+/* Log forwarding API */
+/*
+   Log callback signature:
+   - level: 1=error, 2=warn, 3=info, 4=debug, 5=trace
+   - target: emitting module, e.g. "probe_rs::flashing::flasher"
+   - message: text with the record's fields appended as "name=value"
+   Both strings are only valid during the call. The callback runs on the logging thread (possibly a
+   library background thread) and must not block.
+ - pr_set_log_callback: forward records up to level (0 = off .. 5 = trace); cb NULL stops forwarding.
+   Debug and trace slow down probe communication. Returns 0, -1 on an invalid level, -2 if the process
+   already installed another tracing subscriber.
+*/
+typedef void (*pr_log_cb)(int32_t level, const char* target, const char* message);
+int32_t pr_set_log_callback(int32_t level, pr_log_cb cb);
+/*
+ - pr_enable_file_logging: write records up to level (as above) to the file at path, one line per record
+   with UTC timestamp, level and target; level 0 or path NULL stops file logging. The file is appended
+   to and rotated at 10 MiB, keeping path.1 (newest) to path.3. Independent of the log callback.
+   Returns 0, -1 on an invalid level or path, -2 if the file cannot be opened, -3 if the process
+   already installed another tracing subscriber.
+*/
+int32_t pr_enable_file_logging(const char* path, int32_t level);
 
 /* Programmer type API */
 /* Programmer type enumeration */
@@ -145,15 +516,94 @@ typedef enum {
     PR_PROG_SIFLI_UART = 7,
     PR_PROG_GLASGOW = 8,
     PR_PROG_CH347_USB_JTAG = 9,
+    PR_PROG_BLACKMAGIC = 10,
 } pr_programmer_type_t;
 
 /* Enum-based programmer type API */
 int32_t pr_set_programmer_type_code(int32_t type_code);
 int32_t pr_get_programmer_type_code(void);
+/*
+ - pr_set_thread_programmer_type_code: programmer type for the calling thread only, taking
+   precedence over pr_set_programmer_type_code; 0 returns to the global type. Returns 0, or -1 on an
+   unsupported code. pr_get_programmer_type_code returns the type in effect on the calling thread.
+*/
+int32_t pr_set_thread_programmer_type_code(int32_t type_code);
 int32_t pr_programmer_type_is_supported_code(int32_t type_code);
 size_t  pr_programmer_type_to_string(int32_t type_code, char* buf, size_t buf_len);
 int32_t pr_programmer_type_from_string(const char* type_name, int32_t* out_code);
 
+/*
+ Driver options (applied when the library opens a probe, before attaching; not by
+ pr_session_open_auto without a programmer type)
+ - pr_probe_driver_option_set: set an option; value NULL or "" removes it. Returns 0, -1 on unknown
+   option or invalid value.
+   FTDI (TCK/TDI/TDO/TMS are fixed to pins 0-3 of the MPSSE channel):
+     "ftdi.channel"   "A" (default) .. "D", the channel JTAG is wired to
+     "ftdi.layout"    "<output>,<direction>", initial pin levels/directions (1 = output), bits 0-7
+                      xDBUS, 8-15 xCBUS, e.g. "0x0c08,0x0f1b"; replaces the built-in layouts
+     "ftdi.reset_pin" GPIO 4-15 wired to the target reset, "!" prefix for active low, e.g. "!9"
+   J-Link:
+     "jlink.target_power" "on"/"off", 5 V target supply on pin 19 (not on all models); the target
+                          gets 100 ms to power up
+     "jlink.swo_speed"    SWO capture baud rate, replacing the SWO configuration's (firmware that
+                          sets up the TPIU itself)
+     "jlink.interface"    "swd"/"jtag"; a protocol passed to the open call takes precedence
+   Glasgow (pins are fixed when the Glasgow toolkit builds the applet):
+     "glasgow.port"    "A" (default), "B" or "AB", the I/O banks the target is wired to
+     "glasgow.voltage" I/O voltage of those banks in volts, 1.65-5.0 (e.g. "3.3"), "0" = off;
+                       USB connected probes only
+   CMSIS-DAP:
+     "cmsisdap.transport" "v1" (HID) or "v2" (bulk); by default v2 with fallback to the far slower v1,
+                          a forced transport that cannot be opened fails instead
+ - pr_probe_driver_options_clear: remove all options.
+*/
+int32_t pr_probe_driver_option_set(const char* key, const char* value);
+void    pr_probe_driver_options_clear(void);
+
+/*
+ Timeouts and WAIT retries (applied like the driver options, to sessions and flash calls made afterwards)
+ - pr_set_timeouts: in milliseconds, 0 restores the default. attach_ms: waiting for the cores to halt
+   when attaching under reset (100). halt_ms: halting a core before flashing (500) and in the other
+   calls (100). flash_sector_ms: erasing one sector / programming one page (from the flash algorithm).
+   Always returns 0.
+ - pr_set_wait_retries: retries of a transfer answered with WAIT, negative restores the driver
+   default. Supported by CMSIS-DAP, ST-Link, J-Link, FTDI, CH347 and Black Magic Probe. FAULT
+   responses are not retried. Returns 0, -1 if retries is 0.
+*/
+int32_t pr_set_timeouts(uint32_t attach_ms, uint32_t halt_ms, uint32_t flash_sector_ms);
+int32_t pr_set_wait_retries(int32_t retries);
+
+/*
+ Hardware reset and attaching under reset
+ - pr_set_attach_under_reset: 1 = hold the probe's reset line while connecting, so the cores are
+   halted before the firmware runs (firmware that remaps the SWD pins or sleeps at once), 0 = attach
+   normally (default). Applies to sessions, pr_flash_*, pr_chip_erase and reconnects made afterwards;
+   without a programmer type or selector the first probe found is used. Returns 0, -1 if enable is
+   not 0 or 1.
+ - pr_probe_hw_reset: pulse the reset line for pulse_ms (0 = 100 ms) without attaching, e.g. a
+   restart between production steps. selector "VID:PID[:SN]", or NULL for the first probe of the
+   programmer type; the probe must not be in use by a session. Returns 0, -1 invalid selector or no
+   probe, -2 reset line not supported by the probe or reset failed.
+*/
+int32_t pr_set_attach_under_reset(int32_t enable);
+int32_t pr_probe_hw_reset(const char* selector, uint32_t pulse_ms);
+
+/*
+ Debug authentication (credentials kept in an HSM or signing service)
+ - pr_set_debug_auth_callback: before attaching to a chip with debug authentication (LPC55Sxx
+   debug mailbox), the library checks whether debug access is closed and if so calls cb with the
+   chip name and its challenge (the LPC55 DAC of DBG_AUTH_START). The callback writes the response
+   (the LPC55 DAR, whole words) to response, at most response_cap = 4096 bytes, stores its length
+   in out_response_len and returns 0 to send it, anything else to fail the attach. It runs on the
+   thread attaching, for sessions, pr_flash_* and reconnects made afterwards. NULL removes it.
+   STM32H5 debug authentication is not supported yet: with a callback set, attaching to an STM32H5
+   fails.
+*/
+typedef int32_t (*pr_debug_auth_cb)(void* user_data, const char* chip, const uint8_t* challenge,
+                                    size_t challenge_len, uint8_t* response, size_t response_cap,
+                                    size_t* out_response_len);
+void pr_set_debug_auth_callback(pr_debug_auth_cb cb, void* user_data);
+
 /* String-based API removed: use enum-based APIs above, and conversion helpers */
 /*
  * Parameters for pr_flash_elf:
@@ -172,6 +622,345 @@ int32_t pr_flash_hex(const char* chip, const char* path, int32_t verify, int32_t
 int32_t pr_flash_bin(const char* chip, const char* path, uint64_t base_address, uint32_t skip, int32_t verify, int32_t preverify, int32_t chip_erase, uint32_t speed_khz, int32_t protocol_code);
 /* Auto-detect format (by file extension): .elf/.axf => ELF, .hex/.ihex => HEX, .bin => BIN (requires base_address) */
 int32_t pr_flash_auto(const char* chip, const char* path, uint64_t base_address, uint32_t skip, int32_t verify, int32_t preverify, int32_t chip_erase, uint32_t speed_khz, int32_t protocol_code);
+/*
+ Per-call progress: the _cb variants report to cb(user_data, operation, percent, status, eta_ms)
+ (arguments as pr_progress_cb) instead of the global/session callback; cb NULL reports nothing. The
+ callback runs before the call returns, on the calling thread except for the estimated chip erase
+ reports (see pr_progress_indeterminate).
+*/
+typedef void (*pr_progress_cb_ex)(void* user_data, int32_t operation, float percent, const char* status, int32_t eta_ms);
+int32_t pr_flash_auto_cb(const char* chip, const char* path, uint64_t base_address, uint32_t skip, int32_t verify, int32_t preverify, int32_t chip_erase, uint32_t speed_khz, int32_t protocol_code,
+                         pr_progress_cb_ex cb, void* user_data);
+
+/*
+ UTF-16 variants for Windows hosts: same as the calls without _w, with NUL terminated UTF-16 strings
+ (wchar_t* on Windows, LPWStr in C#) for non-ASCII paths and names. Invalid UTF-16 fails like an
+ invalid argument of the regular call.
+*/
+int32_t  pr_flash_auto_w(const uint16_t* chip, const uint16_t* path, uint64_t base_address, uint32_t skip, int32_t verify, int32_t preverify, int32_t chip_erase, uint32_t speed_khz, int32_t protocol_code);
+int32_t  pr_session_flash_w(uint64_t session, const uint16_t* path, uint64_t base_address, uint32_t skip,
+                            int32_t verify, int32_t preverify, int32_t chip_erase);
+uint64_t pr_session_open_auto_w(const uint16_t* chip, uint32_t speed_khz, int32_t protocol_code);
+uint64_t pr_session_open_with_probe_w(const uint16_t* selector, const uint16_t* chip, uint32_t speed_khz, int32_t protocol_code);
+/*
+ Session-based flashing (no re-attach per call)
+ - pr_session_flash: like pr_flash_auto on an open session. Returns 0 on success, 1 on invalid input/handle, 2 on flash error.
+ - pr_session_set_flash_algo_cache: keep the flash algorithm resident in RAM between pr_session_flash calls
+   (skips downloading the algorithm code while all of it in RAM is intact; the core is still reset and the
+   algorithm's data rewritten). Returns 0, or -1 on invalid handle.
+*/
+int32_t pr_session_flash(uint64_t session, const char* path, uint64_t base_address, uint32_t skip,
+                         int32_t verify, int32_t preverify, int32_t chip_erase);
+int32_t pr_session_flash_cb(uint64_t session, const char* path, uint64_t base_address, uint32_t skip,
+                            int32_t verify, int32_t preverify, int32_t chip_erase,
+                            pr_progress_cb_ex cb, void* user_data);
+int32_t pr_session_set_flash_algo_cache(uint64_t session, int32_t enable);
+
+/*
+ Flash timing report
+ - pr_flash_last_timing: JSON report of the last pr_flash_*, pr_session_flash or layout flash call on
+   the calling thread, successful or not: {"success", "total_ms", "speed_khz", "verify_mode", "erase",
+   "program", "verify", "fill", "regions"}, each phase {"time_ms", "bytes", "bytes_per_sec"} or null if
+   it did not run; speed_khz is null when the probe default was used, verify_mode ("pages", "written",
+   "sectors", see pr_flash_option_verify_mode) null when the call did not verify. "regions" is the
+   flash wear of the call per flash region touched, {"name", "sectors_erased", "bytes_erased",
+   "pages_programmed", "bytes_programmed"}, every sector of the region after a chip erase; the counts
+   are those planned, a failed call may have done less. Returns the required size including NUL, or 0
+   if this thread has not flashed yet.
+*/
+size_t pr_flash_last_timing(char* out_json, size_t out_json_len);
+
+/*
+ Espressif (ESP32 series)
+ - pr_esp_read_mac: factory MAC address from eFuse into out_mac (6 bytes, transmission order) and,
+   if out_text is not NULL, as "aa:bb:cc:dd:ee:ff". Returns 0, -1 invalid arguments, -2 read error,
+   -3 not an ESP32 series chip.
+ - pr_esp_set_flash_loader: flash loader for later flashing of the session. The ESP loaders work
+   like esptool's stub (ROM SPI flash functions, compressed transfers). 0 = raise the CPU clock for
+   speed (default), 1 = keep the default clocks. Returns 0, -1 invalid handle or mode, -3 unavailable.
+*/
+int32_t pr_esp_read_mac(uint64_t session, uint8_t* out_mac, char* out_text, size_t out_text_len);
+int32_t pr_esp_set_flash_loader(uint64_t session, int32_t mode);
+
+/*
+ Target preparation before flashing: the flasher runs the chip family's debug sequence after
+ halting the core (e.g. disabling watchdogs that would reset the target mid-flash); failures are
+ reported through pr_last_error() with their cause.
+ - pr_session_prepare_flashing: run that preparation on demand for a halted core.
+   Returns 0, -1 on invalid handle/core, -2 if the sequence failed.
+*/
+int32_t pr_session_prepare_flashing(uint64_t session, uint32_t core_index);
+
+/*
+ Post-flash behavior for pr_flash_*, pr_session_flash and pr_gang_flash (core 0):
+   0 = none (default, the core stays halted), 1 = reset and run, 2 = reset and halt,
+   3 = reset and start at the ELF entry point (ELF images only; checked before flashing).
+ Returns 0, or -1 on an unknown mode.
+*/
+int32_t pr_flash_set_after(int32_t mode);
+
+/*
+ Preserve ranges (bootloader, NVM configuration sectors) across chip erases
+ - pr_flash_option_preserve_range: keep len bytes at start intact. A chip erase requested by
+   pr_flash_*, pr_session_flash, pr_gang_flash or pr_chip_erase then erases only the sectors that
+   do not overlap any preserved range; images writing into a preserved range are rejected.
+   Returns 0, or -1 on an empty/overflowing range.
+ - pr_flash_clear_preserve_ranges: remove all preserved ranges.
+*/
+int32_t pr_flash_option_preserve_range(uint64_t start, uint64_t len);
+void pr_flash_clear_preserve_ranges(void);
+
+/*
+ Flash algorithm RAM (targets whose boot ROM or DMA buffers use part of the RAM)
+ - pr_flash_option_algorithm_ram: run the flash algorithm in the size bytes of RAM at address in the
+   sessions opened afterwards and in pr_flash_* and pr_gang_flash, instead of the RAM probe-rs picks.
+   Code, stack and page buffers all go there; if the region cannot hold a page, pages are programmed
+   in halves, down to 64 bytes. Algorithms linked to a fixed address elsewhere cannot be moved. size
+   0 restores the default placement. Returns 0, or -1 on an unaligned or overflowing range; a range
+   outside the chip's RAM or too small for its algorithm makes opening the session fail.
+*/
+int32_t pr_flash_option_algorithm_ram(uint64_t address, uint64_t size);
+
+/*
+ Verify mode of pr_flash_*, pr_session_flash and pr_gang_flash: what is compared after programming.
+   0 = the programmed pages (default, probe-rs' verify; page bytes outside the image are skipped
+   unless preserved ranges had them rewritten), 1 = exactly the bytes of the image, 2 = every sector
+   the image touches, expecting the erased value around the image (not with preserved ranges).
+   The mode used is the "verify_mode" of pr_flash_last_timing.
+ Returns 0, or -1 on an unknown mode.
+*/
+int32_t pr_flash_option_verify_mode(int32_t mode);
+
+/*
+ Compressed page transfer: algorithms taking compressed pages (the ESP flash loaders; see "compressed"
+ in pr_flash_sector_layout) get zlib compressed data, less USB traffic on full-speed probes.
+ - pr_flash_option_compression: how the flash algorithms of the sessions opened afterwards and of
+   pr_flash_* and pr_gang_flash are chosen: 0 = the chip's defaults (default), 1 = an algorithm taking
+   compressed pages wherever the flash has one. Returns 0, or -1 on an unknown mode.
+*/
+int32_t pr_flash_option_compression(int32_t mode);
+
+/*
+ Page buffering of later flashing calls (pr_flash_*, pr_session_flash, pr_gang_flash, GDB loads), for
+ targets that only program reliably with one page buffer or smaller pages.
+ - pr_flash_option_buffering: double_buffering 0 = transfer a page only after the previous one is
+   programmed, 1 = double buffer when the algorithm RAM holds two pages (default). max_page_size
+   makes the sessions opened afterwards program pages of at most that many bytes (the algorithm's page
+   halved, down to 64 bytes), 0 = the algorithm's page size. Returns 0, or -1 on an invalid mode or a
+   page size below 64 or not a multiple of 4.
+*/
+int32_t pr_flash_option_buffering(int32_t double_buffering, uint32_t max_page_size);
+
+/*
+ Resumable flash jobs (very large, e.g. external, flash parts)
+ - pr_flash_job: flash an image like pr_flash_auto without a chip erase, sectors erased and programmed
+   in checkpoints of at least 64 KiB. The job and the end of the last programmed checkpoint are kept
+   in the JSON file state_path, removed once the job has completed. Flash patches, the verify mode
+   and pr_flash_set_after apply; preserved ranges are not supported.
+ - pr_flash_resume: continue the job of state_path after an interruption (host power loss, USB
+   drop) from its first unprogrammed sector, with the chip, speed and protocol it started with. The
+   image and flash patches must be unchanged. Progress of both goes to the global callback.
+ Both return 0 on success, 1 on invalid arguments, an unreadable state file, a changed image or if
+ no target could be attached, 2 on flash error (the state file is kept to resume).
+*/
+int32_t pr_flash_job(const char* chip, const char* path, uint64_t base_address, uint32_t skip, int32_t verify, uint32_t speed_khz, int32_t protocol_code, const char* state_path);
+int32_t pr_flash_resume(const char* state_path);
+
+/*
+ Differential flashing (incremental updates over the debug probe)
+ - pr_flash_diff: flash new_path like pr_flash_auto without a chip erase, programming only the flash
+   sectors whose contents differ from old_path, the image the target was last flashed with. The
+   sectors are computed from the two files alone, nothing is read back: the target must hold
+   old_path exactly. base_address and skip apply to both if they are BIN files. Data outside flash
+   (RAM) is always written; flash patches, preserved ranges, the verify mode and pr_flash_set_after
+   apply. out_sectors (may be NULL) receives the number of sectors programmed, 0 if the flash
+   contents are the same. Returns 0 on success, 1 on invalid arguments or if no target could be
+   attached, 2 on flash error.
+*/
+int32_t pr_flash_diff(const char* chip, const char* old_path, const char* new_path, uint64_t base_address, uint32_t skip, int32_t verify, uint32_t speed_khz, int32_t protocol_code, uint32_t* out_sectors);
+
+/*
+ Per-device data injection (serial numbers, MAC addresses, calibration data)
+ - pr_flash_set_patch: write len bytes of data at address over every image flashed afterwards by
+   pr_flash_*, pr_session_flash and pr_gang_flash. Replaces the image contents at those addresses;
+   bytes outside the image are programmed too. A patch at the same address replaces the previous
+   one, len 0 removes it. Returns 0, or -1 on invalid arguments.
+ - pr_flash_clear_patches: remove all patches.
+*/
+int32_t pr_flash_set_patch(uint64_t address, const uint8_t* data, size_t len);
+void pr_flash_clear_patches(void);
+
+/*
+ OTP (provisioning keys and board IDs into one-time programmable memory)
+ - The OTP regions are the memory map regions named OTP (STM32F2/F4/F7 ...), listed by
+   pr_flash_sector_layout; region is such a name (case-insensitive) or NULL for the first.
+ - pr_otp_read: read len bytes at offset of the region into out. Returns 0, -1 invalid
+   handle/region/range, -2 target error, -3 the chip has no OTP region.
+ - pr_otp_write: burn len bytes of data at offset with the chip's OTP flash algorithm and verify
+   them. Permanent, so flags must include PR_OTP_ALLOW_BURN. Bytes already holding data are no
+   error; data needing a programmed (0) bit back at 1 is rejected before anything is written.
+   Returns 0, -1 invalid handle/region/range/flags or data that cannot be burnt, -2 target or
+   programming error, -3 no OTP region or no flash algorithm for it.
+*/
+#define PR_OTP_ALLOW_BURN 0x00000001u
+int32_t pr_otp_read(uint64_t session, const char* region, uint32_t offset, uint8_t* out, size_t len);
+int32_t pr_otp_write(uint64_t session, const char* region, uint32_t offset, const uint8_t* data, size_t len, uint32_t flags);
+
+/*
+ Dual-bank flash (A/B firmware updates)
+ - pr_flash_bank_info: JSON {"banks": [{"name", "start", "end"}], "swap_supported": bool,
+   "active": bank mapped at the flash base or null, "boot": bank selected for the next boot or null}.
+   Returns the required size including NUL, or 0 on error.
+ - pr_flash_bank_select: boot from bank 1 or 2 (BFB2 option bit; STM32L4/L4+/G47x/G48x,
+   STM32F42x/F43x/F469/F479). L4/G4 reload the option bytes at once, which resets the device and may
+   require reopening the session; F4 applies the selection at the next reset.
+   Returns 0, -1 invalid handle/bank, -2 target error, -3 not supported for this chip.
+*/
+size_t  pr_flash_bank_info(uint64_t session, char* out_json, size_t out_json_len);
+int32_t pr_flash_bank_select(uint64_t session, uint32_t bank);
+
+/*
+ Flash write protection (locking the bootloader during provisioning)
+ - pr_flash_get_protection: JSON {"mechanism": "stm32-wrp" | "nrf-bprot" | "nrf-acl" | "nrf-spu" |
+   "fprot" | null, "persistent": bool, "max_level", "regions": [{"index", "start", "end", "level"}]}.
+   A region is the unit protected as a whole (STM32F2/F4 sector, nRF52 4 KiB block or page, SPU
+   flash region, 1/32 of a Kinetis/S32K1 program flash); level 0 = unprotected, 1 = write
+   protected, 2 = read and write protected. mechanism is null, with no regions, for other chips.
+   Returns the required size including NUL, or 0 on error.
+ - pr_flash_set_protection: set the level of a region. STM32 nWRP option bits persist and apply
+   after the next reset; BPROT, ACL, SPU and FPROT apply at once until the next reset and can only
+   become stricter (FPROT's reset value is the flash configuration field at 0x408 of the image).
+   Returns 0, -1 invalid handle/region/level, -2 target error (also for lowering a level that only
+   a reset clears), -3 not supported for this chip or level.
+*/
+size_t  pr_flash_get_protection(uint64_t session, char* out_json, size_t out_json_len);
+int32_t pr_flash_set_protection(uint64_t session, uint32_t region, uint32_t level);
+
+/*
+ Security state (closing devices down at the end of a factory flow)
+ - pr_target_security_state: JSON {"mechanism": "stm32-rdp" | "nrf-approtect" | null, "state",
+   "raw", "states": [states pr_target_set_security_state takes]}. stm32-rdp (STM32F2/F4/F7/L4/G0/G4)
+   states are "level0", "level1", "level2" with raw the RDP option byte; nrf-approtect
+   (nRF52/nRF5340/nRF91) states "open" and "protected" with raw UICR.APPROTECT. An erased UICR is
+   "protected" on nRF5340/nRF91x1 but "open" on nRF52, which is only correct before revision 3
+   (later revisions protect in hardware unless APPROTECT is 0x5A). Returns the required size
+   including NUL, or 0 on error.
+ - pr_target_set_security_state: move to state. Each consequence must be confirmed in confirm:
+   PR_SECURITY_CONFIRM_ERASE if the flash is erased (RDP level 1 to 0), PR_SECURITY_CONFIRM_LOCKOUT if
+   the debugger loses flash access until an erase (RDP level 1 or 2, APPROTECT),
+   PR_SECURITY_CONFIRM_PERMANENT if it can never be undone (RDP level 2). Applies at the next reset;
+   STM32L4/G0/G4 reload the option bytes at once, which resets the device. Leaving RDP level 2 or
+   APPROTECT (needs an erase all, pr_chip_erase) is not supported.
+   Returns 0 (also if already in state), -1 invalid handle/state, -2 target error, -3 chip or
+   transition not supported, -4 confirmation flag missing.
+*/
+#define PR_SECURITY_CONFIRM_ERASE     0x00000001u
+#define PR_SECURITY_CONFIRM_LOCKOUT   0x00000002u
+#define PR_SECURITY_CONFIRM_PERMANENT 0x00000004u
+size_t  pr_target_security_state(uint64_t session, char* out_json, size_t out_json_len);
+int32_t pr_target_set_security_state(uint64_t session, const char* state, uint32_t confirm);
+
+/*
+ Flash layout and external (QSPI/SPI NOR) flash
+ - pr_flash_sector_layout: JSON array of the chip's flash regions {"name", "start", "end", "external",
+   "algorithms": [names], "compressed": [names], "page_size", "sectors": [{"address", "size"}]}.
+   "external" regions are not in the memory map but served by a flash algorithm (e.g. SPI NOR behind a
+   QSPI/SPIM controller) and are named after it; "compressed" lists the algorithms taking compressed
+   pages; each sector group extends to the next group or the region end.
+   Returns the required size including NUL, or 0 on error.
+ - pr_flash_bin_to_region: flash a raw binary at the start of the region named region_name, verifying
+   it afterwards. For an external region, or when a flash algorithm name is given, that algorithm is
+   used for this call only; later flashes of the session use the target's own algorithms again.
+   Returns 0 ok, 1 invalid arguments, 2 flash error, -3 unknown region.
+*/
+size_t  pr_flash_sector_layout(const char* chip, char* out_json, size_t out_json_len);
+int32_t pr_flash_bin_to_region(uint64_t session, const char* region_name, const char* path);
+
+/*
+ Image fit check (no hardware needed)
+ - pr_flash_check_fit: map an image onto the chip's memory map. format is "elf", "hex" or "bin", or
+   NULL/"" to detect it from the extension; base is the load address of BIN images. The JSON report
+   {"fits", "bytes", "placed", "overflows", "non_writable", "misaligned"} lists {"start", "end",
+   "region"} spans for data in flash/RAM and data outside every region; non_writable spans carry a
+   "reason" ("read-only", "no flash algorithm", "not memory"). "misaligned" ({"address",
+   "alignment"}) lists data blocks not starting on a flash page boundary and is only a warning.
+   "fits" is false on overflows or non-writable spans. Returns the required size including NUL, or 0
+   on error.
+*/
+size_t pr_flash_check_fit(const char* chip, const char* path, const char* format, uint64_t base,
+                          char* out_report_json, size_t out_report_json_len);
+
+/*
+ Flash plan (no hardware needed)
+ - pr_flash_plan: what flashing an image would do. format and base as for pr_flash_check_fit,
+   chip_erase as for pr_session_flash. JSON {"chip_erase", "erase", "pages", "fills", "ram"}, lists of
+   {"address", "size"}: the sectors erased, the flash pages programmed (following the flash algorithm
+   of each region), the parts of those pages without image data (programmed with the erased value)
+   and the data written to RAM. A chip erase covers the whole memory map regions the image touches.
+   Preserve ranges are not taken into account. Returns the required size including NUL, or 0 on
+   error (e.g. image data outside flash and RAM).
+ - pr_flash_erase_plan: JSON array of the sectors ({"address", "size"}) pr_session_erase_range would
+   erase for [start, end); 0..UINT64_MAX lists all sectors of the memory map flash. Returns the required
+   size including NUL, or 0 on error or a range without flash.
+*/
+size_t pr_flash_plan(const char* chip, const char* path, const char* format, uint64_t base,
+                     int32_t chip_erase, char* out_json, size_t out_json_len);
+size_t pr_flash_erase_plan(const char* chip, uint64_t start, uint64_t end, char* out_json,
+                           size_t out_json_len);
+
+/*
+ Image statistics (no hardware needed)
+ - pr_image_info: JSON {"bytes", "entry", "segments": [{"address", "size"}], "sections": [{"name",
+   "address", "load_address", "size"}], "regions": [...]}. format and base as for pr_flash_check_fit.
+   "sections" lists the allocated ELF sections (empty for HEX/BIN; load_address is null for .bss).
+   "entry" is null for BIN images. With a chip name (may be NULL), "regions" lists {"name", "kind":
+   "flash"|"ram", "start", "end", "used", "percent"}; RAM usage of ELF images includes .bss.
+   Returns the required size including NUL, or 0 on error.
+*/
+size_t pr_image_info(const char* path, const char* format, uint64_t base, const char* chip,
+                     char* out_json, size_t out_json_len);
+
+/*
+ Verifying the target against an image (nothing is programmed or halted)
+ - pr_session_verify: read back every byte of the image through core core_index and compare.
+   format and base as for pr_flash_check_fit; the flash patches (pr_flash_set_patch) are applied to
+   the image first, as when flashing. Returns 0 contents match, 1 mismatch (first differing address
+   in out_address, may be NULL), -1 invalid handle/arguments/image, -2 target read error.
+*/
+int32_t pr_session_verify(uint64_t session, uint32_t core_index, const char* path, const char* format,
+                          uint64_t base, uint64_t* out_address);
+
+/*
+ Calling target routines
+ - pr_call_function: call the routine at address with args[0..3] (args may be NULL) in r0-r3 (ARM)
+   or a0-a3 (RISC-V). It runs on the current stack and returns to a breakpoint placed just below the
+   stack pointer; afterwards the stack and all core registers are restored, and a running core is
+   resumed. The return value (r0/a0) is written to out_return (may be NULL). Cortex-M and RISC-V only.
+   Returns 0, -1 invalid handle/core, -2 target error/unsupported core/stopped elsewhere, -3 timeout.
+*/
+int32_t pr_call_function(uint64_t session, uint32_t core_index, uint64_t address, const uint64_t args[4],
+                         uint32_t timeout_ms, uint64_t* out_return);
+
+/*
+ Gang programming: flash several boards concurrently, one worker thread per probe.
+ - jobs_json: JSON array of {"probe": "VID:PID[:SN]", "chip": "...", "path": "..."} with optional
+   "base_address" (required for .bin, number or "0x..." string), "skip", "verify", "preverify",
+   "chip_erase", "speed_khz", "protocol" (0 = Auto, 1 = SWD, 2 = JTAG). Each job needs its own probe.
+   "patches": [{"address": "0x0800fff0", "data": "<hex bytes>"}] are applied like pr_flash_set_patch
+   for that board only.
+ - cb: per-job progress (job_index plus the pr_progress_cb arguments), may be NULL. It is called
+   from the worker threads and must be thread-safe. The global progress callback is not used.
+ - out_json: receives [{"index", "probe", "ok", "error", "timing"}] once all jobs are done (may
+   be NULL, truncated to out_json_len); timing is the job's pr_flash_last_timing report, null if it
+   failed before flashing.
+ Returns the number of failed jobs (0 = all succeeded), or -1 on invalid job JSON.
+ - pr_gang_flash_results: the whole results JSON of the calling thread's last pr_gang_flash. Returns
+   the required size including NUL, or 0 if the thread has not run one yet.
+*/
+typedef void (*pr_gang_progress_cb)(uint32_t job_index, int32_t operation, float percent,
+                                    const char* status, int32_t eta_ms);
+int32_t pr_gang_flash(const char* jobs_json, pr_gang_progress_cb cb, char* out_json, size_t out_json_len);
+size_t  pr_gang_flash_results(char* out_json, size_t out_json_len);
 
 /*
  * Perform a chip-wide erase.
@@ -185,6 +974,15 @@ int32_t pr_flash_auto(const char* chip, const char* path, uint64_t base_address,
  */
 int32_t pr_chip_erase(const char* chip, uint32_t speed_khz, int32_t protocol_code);
 
+/*
+ Erase part of the flash of an open session
+ - pr_session_erase_range: erase the flash sectors overlapping [start, end), widened to whole sectors
+   (end = start + 1 erases the sector holding start). Only flash of the memory map is erased, no
+   external regions; preserve ranges do not apply. The erased range is written to out_start/out_end
+   (either may be NULL). Returns 0, -1 on invalid handle or a range without flash, -2 on erase error.
+*/
+int32_t pr_session_erase_range(uint64_t session, uint64_t start, uint64_t end, uint64_t* out_start, uint64_t* out_end);
+
 /* Chip database and detection */
 /*
    Manufacturer & Chip Listing
@@ -194,21 +992,282 @@ int32_t pr_chip_erase(const char* chip, uint32_t speed_khz, int32_t protocol_cod
      - pr_chip_manufacturer_count(): Return the number of manufacturers.
      - pr_chip_manufacturer_name(index, buf, buf_len): Get manufacturer name by index.
        If buf==NULL or buf_len==0, returns required size (including NUL).
+     - pr_chip_manufacturer_jep106(index, &cc, &id): Write the JEP106 continuation/identity code
+       of the manufacturer (either pointer may be NULL). Returns 0, 1 if it has none (generic
+       targets), -1 on invalid index.
+     - pr_jep106_name(cc, id, buf, buf_len): Manufacturer name of a JEP106 code, e.g. sniffed from
+       an IDCODE or ROM table (the parity bit of id is ignored). Returns 0 for unknown codes.
      - pr_chip_model_count(manu_index): Return number of chip models for the manufacturer.
      - pr_chip_model_name(manu_index, chip_index, buf, buf_len): Get chip model name.
        Same size semantics as above.
      - pr_chip_model_specs(manu_index, chip_index, buf, buf_len): Return a JSON string
-       of spec details (architecture, cores, memory regions, algorithms).
+       of spec details (architecture, cores, memory regions, algorithms, and "flash_banks":
+       [{"name", "start", "end"}] for the BANK_<n> flash regions of dual-bank devices).
+       Debug metadata: "rtt_scan" {"mode": "ram"|"ranges"|"exact", "ranges": [{"start", "end"}],
+       "address"} (where RTT control blocks are searched), "reset" {"method": "sysresetreq"|
+       "debug_sequence", "debug_sequence"} (vendor sequences may reset differently), "protocols"
+       {"swd", "jtag"} (jtag is null if unknown: ARM targets without a described scan chain),
+       "core_details" [{"name", "type", "ap", "debug_base", "cti_base", "jtag_tap"} for ARM,
+       {"name", "type", "hart_id", "jtag_tap"} for RISC-V] and "boot_address" (start of the boot
+       flash, where ARM vector tables with the initial stack and entry are; null if unknown).
      - pr_chip_specs_by_name(name, buf, buf_len): Return a JSON spec string for a given name.
+     - pr_chip_family_count(manu_index): Number of families (STM32F4, nRF52, ...) of the
+       manufacturer, sorted by name; every chip of the manufacturer is in one of them.
+     - pr_chip_family_name(manu_index, family_index, buf, buf_len): Get family name.
+     - pr_chip_model_count_in_family(manu_index, family_index): Number of chips in the family.
+     - pr_chip_model_name_in_family(manu_index, family_index, index, buf, buf_len): Get the
+       name of a chip of the family (sorted by name).
    Error handling: On invalid index or name, functions return 0 and set pr_last_error().
+   The database is built on first use, which takes a noticeable moment; these calls wait for it.
+     - pr_chip_db_prefetch(): start building it in the background (nothing else does until a chip
+       database call), e.g. at startup of a host that will show a chip picker.
+     - pr_chip_db_ready(): 1 if built, else 0 after starting to build it in the background (as
+       pr_chip_db_prefetch does), so a GUI can show a spinner and poll instead of blocking.
 */
 uint32_t pr_chip_manufacturer_count(void);
 size_t   pr_chip_manufacturer_name(uint32_t index, char* buf, size_t buf_len);
+int32_t  pr_chip_manufacturer_jep106(uint32_t index, uint8_t* out_cc, uint8_t* out_id);
+size_t   pr_jep106_name(uint8_t cc, uint8_t id, char* buf, size_t buf_len);
 uint32_t pr_chip_model_count(uint32_t manu_index);
 size_t   pr_chip_model_name(uint32_t manu_index, uint32_t chip_index, char* buf, size_t buf_len);
 size_t pr_chip_model_specs(uint32_t manu_index, uint32_t chip_index, char *buf, size_t buf_len);
 size_t pr_chip_specs_by_name(const char *name, char *buf, size_t buf_len);
+uint32_t pr_chip_family_count(uint32_t manu_index);
+size_t   pr_chip_family_name(uint32_t manu_index, uint32_t family_index, char* buf, size_t buf_len);
+uint32_t pr_chip_model_count_in_family(uint32_t manu_index, uint32_t family_index);
+size_t   pr_chip_model_name_in_family(uint32_t manu_index, uint32_t family_index, uint32_t index,
+                                      char* buf, size_t buf_len);
+void    pr_chip_db_prefetch(void);
+int32_t pr_chip_db_ready(void);
+
+/*
+ Paged chip list (chip pickers with search-as-you-type)
+ - pr_chip_list_json: {"total", "offset", "chips": [{"name", "family", "manufacturer",
+   "manufacturer_index", "chip_index"}]} in pr_chip_model_name order. manufacturer_filter matches
+   manufacturer names, family_filter family or chip names, as case-insensitive substrings (NULL/""
+   matches all). total counts all matches; chips holds up to limit (0 = all) of them from offset.
+   Returns the required size including NUL, or 0 on error.
+*/
+size_t pr_chip_list_json(const char* manufacturer_filter, const char* family_filter, uint32_t offset,
+                         uint32_t limit, char* out_json, size_t out_json_len);
+
+/*
+ Chip compatibility check (catch flashing the wrong variant)
+ - pr_chip_is_compatible: compare the selected chip with what was detected on the target.
+   detected_json is {"chip", "manufacturer": {"cc", "id"}, "part", "flash_size"}, any field may be left
+   out: the auto-detected chip name, JEP106 code and part number (e.g. from the ROM table) and main flash
+   size in bytes (boot and BANK_<n> regions, no UICR/OTP). A detected chip of the database also counts
+   as its flash size. The report is {"compatible", "requested", "mismatches": [{"field", "expected",
+   "detected", "message"}]} with field "chip" (wrong variant or different chip), "manufacturer", "part"
+   or "flash_size". Returns the required size including NUL, or 0 on error (unknown chip, bad JSON).
+*/
+size_t pr_chip_is_compatible(const char* requested_name, const char* detected_json, char* out_json,
+                             size_t out_json_len);
 
+/*
+ ELF symbol lookup
+ - pr_elf_symbol_address: resolve a symbol name to its address (Thumb bit cleared on ARM functions).
+   Returns 0 on success, -1 on invalid input/unreadable file, -2 if the symbol does not exist.
+ - pr_elf_address_symbol: find the symbol containing an address. Returns required size
+   (including NUL); if out_name==NULL or out_name_len==0 only the size is returned. Returns 0 if not found.
+*/
+int32_t pr_elf_symbol_address(const char* elf_path, const char* name, uint64_t* out_address);
+size_t  pr_elf_address_symbol(const char* elf_path, uint64_t address, char* out_name, size_t out_name_len);
+/*
+ Symbolic variable access (DWARF)
+ - name addresses globals/statics, struct members and array elements, e.g. "g_config.mode" or "bufs[1].len".
+ - pr_var_read writes a JSON object {"name","type","address","size","value"} to out_json.
+   Returns required size (including NUL), or 0 on error.
+ - pr_var_write parses value according to the variable's base type (integer, float, bool, char).
+   Returns 0 on success, -1 on invalid input/lookup failure, -2 if the write failed.
+*/
+size_t  pr_var_read(uint64_t session, uint32_t core_index, const char* elf_path, const char* name,
+                    char* out_json, size_t out_json_len);
+int32_t pr_var_write(uint64_t session, uint32_t core_index, const char* elf_path, const char* name,
+                     const char* value);
+
+/*
+ Disassembly
+ - Decodes count (1..4096) instructions at address for the core's current instruction set
+   (Thumb/ARM/AArch64/RV32/RV32C). Xtensa code is split into instructions but shown as ".byte" data.
+ - Writes a JSON array of {"address","bytes","mnemonic","operands"} to out_json.
+   Returns required size (including NUL), or 0 on error.
+*/
+size_t pr_disassemble(uint64_t session, uint32_t core_index, uint64_t address, uint32_t count,
+                      char* out_json, size_t out_json_len);
+
+/*
+ Core dump (probe-rs coredump format, loadable with probe_rs::CoreDump::load)
+ - core_mask: bit N selects core N. With several cores, each file gets a "_core<N>" suffix before the extension.
+ - memory_ranges_json: e.g. [{"start":"0x20000000","size":4096}] (numbers or hex strings); NULL/"" for registers only.
+ - Running cores are halted for the dump and resumed afterwards.
+ Returns 0 on success, -1 on invalid input/handle, -2 if dumping or writing failed.
+*/
+int32_t pr_core_dump(uint64_t session, uint32_t core_mask, const char* memory_ranges_json, const char* path);
+
+/*
+ Live memory polling (the core keeps running; values are read through the debug port, see pr_read_mem_nonstop)
+ - pr_poll_create: sample a 1/2/4/8-byte value every interval_ms (0 = as fast as possible).
+   Returns a non-zero poll handle, or 0 on error.
+ - pr_poll_read: take up to max_samples buffered samples (oldest first); timestamps are microseconds
+   since creation. Either output array may be NULL. Returns the count, or -1 on invalid handle.
+   Each poller buffers up to 65536 samples and drops the oldest when full.
+ - pr_poll_destroy: stop the poller. Pollers are also stopped by pr_session_close.
+*/
+uint64_t pr_poll_create(uint64_t session, uint32_t core_index, uint64_t address, uint32_t width, uint32_t interval_ms);
+int32_t  pr_poll_read(uint64_t poll, uint64_t* out_timestamps_us, uint64_t* out_values, uint32_t max_samples);
+int32_t  pr_poll_destroy(uint64_t poll);
+
+/*
+ Core status monitor (replaces polling pr_core_status from the host)
+ - pr_monitor_start: poll the status of every core every interval_ms and call
+   callback(session, core_index, status, previous) on changes, from the monitor thread and without
+   the session lock held. status: 0 unknown, 1 halted, 2 running, 3 locked up, 4 sleeping,
+   -2 status read failed. The first poll reports every core with previous = -1.
+   Returns a non-zero monitor handle, or 0 on error.
+ - pr_monitor_stop: stop the monitor (not from within the callback). Monitors are also stopped by
+   pr_session_close. Returns 0, or -1 on invalid handle.
+*/
+typedef void (*pr_monitor_cb)(uint64_t session, uint32_t core_index, int32_t status, int32_t previous);
+uint64_t pr_monitor_start(uint64_t session, uint32_t interval_ms, pr_monitor_cb callback);
+int32_t  pr_monitor_stop(uint64_t monitor);
+
+/*
+ RTT terminal (interactive console to the firmware; the core keeps running)
+ - pr_terminal_open: attach to the RTT control block in the RAM of core 0 and pump up channel rtt_up
+   (firmware output) and down channel rtt_down (host input) in a background thread.
+   Returns a non-zero terminal handle, or 0 on error (no control block, unknown channel).
+ - pr_terminal_open_defmt: as pr_terminal_open, with the up channel decoded as defmt using the tables
+   of elf_path (the running firmware): one line per log message with the firmware timestamp (if
+   any), level, text and source location (if the ELF has debug info). Also 0 if the ELF cannot be
+   read or has no defmt data; malformed data that cannot be resynchronized makes the next read -2.
+ - pr_terminal_read_line: take the next complete output line without "\n"/"\r\n". Returns the
+   required size including NUL, 0 if no complete line is buffered; if out_len is too small the line
+   stays buffered.
+ - pr_terminal_read: take up to out_len raw output bytes, including an incomplete line (prompts).
+   Returns the byte count.
+ - pr_terminal_write: queue raw input (nothing is appended); pr_terminal_write_line appends "\n".
+   Both return the number of input bytes not yet sent.
+ - pr_terminal_close: stop the terminal. Terminals are also closed by pr_session_close.
+ Output beyond 1 MiB drops the oldest bytes. -1 = invalid handle; the read functions return -2 once
+ after a channel access failed (pumping continues).
+*/
+uint64_t pr_terminal_open(uint64_t session, uint32_t rtt_up, uint32_t rtt_down);
+uint64_t pr_terminal_open_defmt(uint64_t session, uint32_t rtt_up, uint32_t rtt_down, const char* elf_path);
+int32_t  pr_terminal_read_line(uint64_t terminal, char* out, size_t out_len);
+int32_t  pr_terminal_read(uint64_t terminal, uint8_t* out, size_t out_len);
+int32_t  pr_terminal_write(uint64_t terminal, const uint8_t* data, size_t len);
+int32_t  pr_terminal_write_line(uint64_t terminal, const char* line);
+int32_t  pr_terminal_close(uint64_t terminal);
+
+/*
+ Semihosting (on-target test runners: console output and exit code of the firmware)
+ - pr_semihosting_poll: serve the semihosting request core_index is halted on, if any, and resume
+   it; call periodically while the firmware runs. Console text (SYS_WRITE0, SYS_WRITEC, SYS_WRITE
+   to ":tt") is queued for pr_semihosting_read; host files cannot be opened. Returns 0 while
+   running, 1 once the firmware exited (SYS_EXIT/SYS_EXIT_EXTENDED) with its exit code written to
+   out_exit_code (may be NULL; the core stays halted), -1 on invalid handle/core, -2 if the core
+   halted for another reason or could not be accessed.
+ - pr_semihosting_read: take up to out_len bytes of queued console output. Returns the byte count,
+   -1 on invalid handle. The queue is dropped by pr_session_close.
+*/
+int32_t pr_semihosting_poll(uint64_t session, uint32_t core_index, int32_t* out_exit_code);
+int32_t pr_semihosting_read(uint64_t session, uint8_t* out, size_t out_len);
+
+/*
+ SWO trace (ITM printf output where RTT is not available)
+ - pr_swo_start: configure core core_index and the probe for SWO in UART (NRZ) mode at baud, the
+   prescaler derived from tpiu_clock_hz (the trace clock, usually the core clock); DWT and ITM are
+   enabled with all stimulus ports. itm_ports 0 makes pr_swo_read return the raw SWO bytes, else the
+   ITM stream is decoded and only the payload of the stimulus ports in the mask (bit n = port n) is
+   returned. Returns 0 ok, -1 invalid handle, core or baud (0 or above the clock), -2 if the probe or
+   target cannot trace over SWO.
+ - pr_swo_read: data received since the last call, up to out_len bytes, without waiting; call it
+   periodically. Returns the byte count, -1 invalid handle/NULL buffer/not started, -2 read error.
+ - pr_swo_stop: disable the DWT and drop unread data. Returns 0 ok, -1 not started, -2 access error.
+ Capture state is dropped when the session is closed.
+*/
+int32_t pr_swo_start(uint64_t session, uint32_t core_index, uint32_t tpiu_clock_hz, uint32_t baud,
+                     uint32_t itm_ports);
+int32_t pr_swo_read(uint64_t session, uint8_t* out, size_t out_len);
+int32_t pr_swo_stop(uint64_t session);
+
+/*
+ WCH SDI print (CH32 firmware printing through the debug module; needs no RAM buffer like RTT)
+ - pr_sdi_print_start: enable SDI print on the session's WCH-LinkE (firmware 2.10+) and open the
+   probe's serial port, which must not be open elsewhere. Returns a non-zero handle, or 0 on error.
+ - pr_sdi_print_read: read up to out_len output bytes, waiting at most 10 ms. Returns the byte count,
+   -1 on invalid handle, -2 on serial port error.
+ - pr_sdi_print_stop: disable SDI print and close the port; also done by pr_session_close.
+*/
+uint64_t pr_sdi_print_start(uint64_t session);
+int32_t  pr_sdi_print_read(uint64_t handle, uint8_t* out, size_t out_len);
+int32_t  pr_sdi_print_stop(uint64_t handle);
+
+/*
+ SVD peripheral registers
+ - pr_svd_load: parse a CMSIS-SVD file for the session (replaces a previous one; dropped on pr_session_close).
+ - pr_svd_peripheral_list: JSON array of {"name","group","base_address","description","registers":[...]}.
+ - pr_svd_register_read: name is "PERIPH.REG" (case-insensitive) or "PERIPH.REG.FIELD"; writes
+   {"name","address","size","value","description","fields":[{"name","bit_offset","bit_width","value","enum","description"}]}.
+ - pr_svd_register_write: writes the whole register, or read-modify-writes a single field for "PERIPH.REG.FIELD".
+ JSON functions return required size (including NUL), or 0 on error. Others return 0 on success,
+ -1 on invalid input/unknown register, -2 on file or access error.
+*/
+int32_t pr_svd_load(uint64_t session, const char* path);
+size_t  pr_svd_peripheral_list(uint64_t session, char* out_json, size_t out_json_len);
+size_t  pr_svd_register_read(uint64_t session, uint32_t core_index, const char* name, char* out_json, size_t out_json_len);
+int32_t pr_svd_register_write(uint64_t session, uint32_t core_index, const char* name, uint64_t value);
+
+/*
+ PC-sampling profiler
+ - pr_profile_start: sample the PC sample_rate times per second (0 = as fast as possible). ARMv7-M/ARMv8-M
+   use DWT_PCSR without stopping the core; other cores are halted briefly per sample. One profiler per session.
+   Returns 0 on success, -1 on invalid handle/core, -2 if already running or setup failed.
+ - pr_profile_start_swo: as pr_profile_start, but ARMv7-M/ARMv8-M cores emit PC sample packets over SWO
+   (set up at baud as by pr_swo_start; tpiu_clock_hz is the trace/core clock) every
+   tpiu_clock_hz / sample_rate cycles, rounded to 64..16384 (sample_rate 0 = every 64 cycles). Each
+   sample takes 5 bytes of SWO bandwidth; samples beyond it are lost. Falls back to DWT_PCSR polling if
+   SWO cannot be set up; "method" in the report is "swo", "pcsr" or "halt". Returns as pr_profile_start,
+   also -1 for a baud of 0 or above tpiu_clock_hz and -2 while an SWO capture runs.
+ - pr_profile_stop: stop and write {"method","samples","duration_ms","histogram":[{"address","count"}]}
+   (sorted by count). If out_json is NULL/too small the report is kept for a repeated call.
+   Returns required size (including NUL), or 0 on error.
+*/
+int32_t pr_profile_start(uint64_t session, uint32_t core_index, uint32_t sample_rate);
+int32_t pr_profile_start_swo(uint64_t session, uint32_t core_index, uint32_t tpiu_clock_hz, uint32_t baud,
+                             uint32_t sample_rate);
+size_t  pr_profile_stop(uint64_t session, char* out_json, size_t out_json_len);
+
+/*
+ JSON-RPC server (drive the library from any language or a remote test controller)
+ - pr_server_start: serve JSON-RPC 2.0 over a WebSocket at ws://127.0.0.1:port/ (port 0 picks a free
+   one); one request per text message. Returns the port, -1 if already running, -2 if the port cannot
+   be bound. Only localhost is served; tunnel or proxy it for remote access.
+ - pr_server_token: the random token of the running server (new every start), written with the C string
+   size protocol; returns the size incl. NUL, or 0 if no server runs. Clients must connect to
+   ws://127.0.0.1:port/?token=<token>. Handshakes with an Origin header (browsers) are refused, so web
+   pages cannot drive the hardware.
+ - pr_server_stop: stop the server; connections end within 100 ms. Returns the port, or -1 if not running.
+ Methods (params; "core" defaults to 0):
+   version -> {"version","abi":[major,minor]};  probe.list -> as pr_probe_list_json
+   chip.manufacturers -> [name];  chip.models {manufacturer} -> [chip]
+   session.open {chip, selector?, speed_khz?, protocol? "swd"|"jtag"} -> session
+   session.close {session, detach? "run"|"halt"|"reset"};  session.list -> [session];  session.cores {session} -> count
+   flash {chip, path, base_address?, skip?, verify?, preverify?, chip_erase?, speed_khz?, protocol?}
+   session.flash {session, path, base_address?, skip?, verify?, preverify?, chip_erase?}
+   memory.read {session, core?, address, length} -> hex string;  memory.write {session, core?, address, data: hex}
+   memory.read32 {session, core?, address, count} -> [word];  memory.write32 {session, core?, address, words}
+   core.halt {session, core?, timeout_ms?};  core.run / core.reset {session, core?}
+   rtt.open {session, up?, down?} -> terminal;  rtt.read {terminal} -> text;  rtt.write {terminal, data} -> pending bytes;
+   rtt.close {terminal}
+ Errors: -32700 parse error, -32600 invalid request, -32601 unknown method, -32602 invalid params,
+ -32000 the call failed (message as from pr_last_error). Sessions a connection opened and did not
+ close are closed (left running) when it disconnects.
+*/
+int32_t pr_server_start(uint16_t port);
+size_t pr_server_token(char* out_token, size_t out_token_len);
+int32_t pr_server_stop(void);
 
 #ifdef __cplusplus
 }
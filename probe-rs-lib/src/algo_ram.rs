@@ -0,0 +1,221 @@
+//! The RAM the flash algorithm runs in, for targets that reserve part of their RAM (DMA
+//! buffers, boot ROM data) where probe-rs would place the algorithm by default.
+//!
+//! probe-rs places the algorithm in the largest executable RAM region of the session's target
+//! description, so the override is applied to the target when attaching: other RAM loses its
+//! execute permission, and pages too large for the region are split.
+
+use crate::{registry, set_error};
+use probe_rs::config::{MemoryRegion, RamRegion, Target, TargetSelector};
+use probe_rs::flashing::FlashAlgorithm;
+use std::ops::Range;
+use std::sync::{Mutex, OnceLock};
+
+/// Smallest page the algorithm is asked to program when pages are split to fit the region.
+const MIN_PAGE_SIZE: u32 = 64;
+
+static ALGORITHM_RAM: OnceLock<Mutex<Option<Range<u64>>>> = OnceLock::new();
+
+fn algorithm_ram_lock() -> &'static Mutex<Option<Range<u64>>> {
+    ALGORITHM_RAM.get_or_init(|| Mutex::new(None))
+}
+
+/// Keep `ram` as the only executable RAM: the region containing it is split around it.
+fn restrict_memory_map(target: &mut Target, ram: &Range<u64>) -> Result<RamRegion, String> {
+    let mut scratch = None;
+    let mut map = Vec::with_capacity(target.memory_map.len() + 2);
+    for region in target.memory_map.drain(..) {
+        let MemoryRegion::Ram(mut region) = region else {
+            map.push(region);
+            continue;
+        };
+        let inside = region.range.start <= ram.start && ram.end <= region.range.end;
+        if inside && scratch.is_none() {
+            let mut access = region.access();
+            access.execute = true;
+            let piece = |range: Range<u64>, execute: bool| RamRegion {
+                range,
+                access: Some(probe_rs::config::MemoryAccess { execute, ..access }),
+                ..region.clone()
+            };
+            let middle = piece(ram.clone(), true);
+            for range in [region.range.start..ram.start, ram.end..region.range.end] {
+                if !range.is_empty() {
+                    map.push(MemoryRegion::Ram(piece(range, false)));
+                }
+            }
+            map.push(MemoryRegion::Ram(middle.clone()));
+            scratch = Some(middle);
+        } else {
+            let mut access = region.access();
+            access.execute = false;
+            region.access = Some(access);
+            map.push(MemoryRegion::Ram(region));
+        }
+    }
+    map.sort_by_key(|r| r.address_range().start);
+    target.memory_map = map;
+    scratch.ok_or_else(|| {
+        format!(
+            "algorithm RAM {:#x}..{:#x} is not inside a RAM region of {}",
+            ram.start, ram.end, target.name
+        )
+    })
+}
+
+/// Place the flash algorithms of `target` in `ram`, halving their page size until the code,
+/// stack and a page buffer fit.
+fn restrict(target: &mut Target, ram: &Range<u64>) -> Result<(), String> {
+    let scratch = restrict_memory_map(target, ram)?;
+    let reference = target.clone();
+    for algo in &mut target.flash_algorithms {
+        if let Some(address) = algo.load_address.filter(|a| !ram.contains(a)) {
+            // Not position independent: it cannot run elsewhere, so flashing with it fails.
+            tracing::warn!(
+                "flash algorithm {} is linked to run at {:#x}, outside the algorithm RAM",
+                algo.name,
+                address
+            );
+            continue;
+        }
+        if algo.data_load_address.is_some_and(|a| !ram.contains(&a)) {
+            algo.data_load_address = None;
+        }
+        let page_size = algo.flash_properties.page_size;
+        loop {
+            match FlashAlgorithm::assemble_from_raw_with_data(algo, &scratch, &scratch, &reference)
+            {
+                Ok(_) => break,
+                Err(e) => {
+                    let half = algo.flash_properties.page_size / 2;
+                    if half < MIN_PAGE_SIZE || half % 4 != 0 {
+                        return Err(format!(
+                            "flash algorithm {} does not fit in {} bytes of RAM: {}",
+                            algo.name,
+                            ram.end - ram.start,
+                            e
+                        ));
+                    }
+                    algo.flash_properties.page_size = half;
+                }
+            }
+        }
+        if algo.flash_properties.page_size != page_size {
+            tracing::info!(
+                "flash algorithm {} programs {} byte pages to fit the algorithm RAM",
+                algo.name,
+                algo.flash_properties.page_size
+            );
+        }
+    }
+    Ok(())
+}
+
+/// `target` with the algorithm RAM of `pr_flash_option_algorithm_ram` applied, if one is set.
+/// Autodetected targets are left alone, there is no description to change before attaching.
+pub(crate) fn apply(target: TargetSelector) -> Result<TargetSelector, String> {
+    let Some(ram) = algorithm_ram_lock().lock().unwrap().clone() else {
+        return Ok(target);
+    };
+    let mut target = match target {
+        TargetSelector::Unspecified(name) => registry()
+            .get_target_by_name(&name)
+            .map_err(|e| format!("attach error: {}", e))?,
+        TargetSelector::Specified(target) => target,
+        TargetSelector::Auto => return Ok(TargetSelector::Auto),
+    };
+    restrict(&mut target, &ram)?;
+    Ok(TargetSelector::Specified(target))
+}
+
+/// Run the flash algorithm in the `size` bytes of RAM at `address` in the sessions opened
+/// afterwards (and `pr_flash_*`, `pr_gang_flash`), instead of the RAM probe-rs picks, e.g.
+/// when the boot ROM or DMA buffers of the firmware use part of it. The code, stack and page
+/// buffers all go there; pages are programmed in smaller pieces if the region cannot hold
+/// one, down to 64 bytes. Algorithms linked to a fixed address elsewhere cannot be moved.
+/// `size` 0 restores the default placement.
+///
+/// Returns 0 on success, -1 on an unaligned or overflowing range. A range outside the RAM of
+/// the chip, or too small for its algorithm, makes opening the session fail.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_option_algorithm_ram(address: u64, size: u64) -> i32 {
+    if size == 0 {
+        *algorithm_ram_lock().lock().unwrap() = None;
+        return 0;
+    }
+    let Some(end) = address.checked_add(size) else {
+        set_error("algorithm RAM exceeds the address space".to_string());
+        return -1;
+    };
+    if !address.is_multiple_of(4) || !size.is_multiple_of(4) {
+        set_error(format!(
+            "algorithm RAM {:#x}..{:#x} is not word aligned",
+            address, end
+        ));
+        return -1;
+    }
+    *algorithm_ram_lock().lock().unwrap() = Some(address..end);
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn f407() -> Target {
+        registry().get_target_by_name("STM32F407VGTx").unwrap()
+    }
+
+    #[test]
+    fn only_the_algorithm_ram_is_executable() {
+        let mut target = f407();
+        let ram = 0x2000_1000..0x2000_3000;
+        let scratch = restrict_memory_map(&mut target, &ram).unwrap();
+        assert_eq!(scratch.range, ram);
+        let executable: Vec<Range<u64>> = target
+            .memory_map
+            .iter()
+            .filter_map(MemoryRegion::as_ram_region)
+            .filter(|r| r.is_executable())
+            .map(|r| r.range.clone())
+            .collect();
+        assert_eq!(executable, vec![ram]);
+        let main = target
+            .memory_map
+            .iter()
+            .filter_map(MemoryRegion::as_ram_region);
+        assert!(main.clone().any(|r| r.range == (0x2000_0000..0x2000_1000)));
+        assert!(main.clone().any(|r| r.range.start == 0x2000_3000));
+
+        let mut target = f407();
+        assert!(restrict_memory_map(&mut target, &(0x0800_0000..0x0800_1000)).is_err());
+    }
+
+    #[test]
+    fn pages_are_split_to_fit() {
+        let mut target = f407();
+        let page_size = target.flash_algorithms[0].flash_properties.page_size;
+        restrict(&mut target, &(0x2000_0000..0x2001_0000)).unwrap();
+        assert_eq!(
+            target.flash_algorithms[0].flash_properties.page_size,
+            page_size
+        );
+
+        // The code and stack take most of 1 KiB, the page size.
+        let mut target = f407();
+        restrict(&mut target, &(0x2000_0000..0x2000_0400)).unwrap();
+        let algo = &target.flash_algorithms[0];
+        assert!(algo.flash_properties.page_size < page_size);
+        assert!(algo.flash_properties.page_size >= MIN_PAGE_SIZE);
+
+        let mut target = f407();
+        assert!(restrict(&mut target, &(0x2000_0000..0x2000_0100)).is_err());
+    }
+
+    #[test]
+    fn invalid_ranges_are_rejected() {
+        assert_eq!(pr_flash_option_algorithm_ram(0x2000_0002, 0x100), -1);
+        assert_eq!(pr_flash_option_algorithm_ram(u64::MAX - 3, 0x100), -1);
+        assert_eq!(pr_flash_option_algorithm_ram(0, 0), 0);
+    }
+}
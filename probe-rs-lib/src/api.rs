@@ -0,0 +1,365 @@
+//! Safe Rust interface of the library: the session manager, flashing and the chip database
+//! behind the C functions, for Rust programs that want the same behavior (driver options,
+//! timeouts, flash patches, reconnecting, ...) without going through raw pointers.
+//!
+//! Sessions are identified by the handles of the C API, so both can be mixed in one process.
+//! Settings without a function here (driver options, flash patches, ...) are made through the
+//! C functions, which are plain Rust functions as well.
+
+use crate::reconnect::{self, CoreOpError, OpenParams};
+use crate::remote;
+use crate::reset::{self, configure};
+use crate::{
+    batch, breakpoint, flash_image, gdb_server, get_session, info_matches_type, make_handle,
+    monitor, poll, profile, programmer_type, sdi, semihosting, session_progress_cbs, sessions, svd,
+    swo, terminal,
+};
+use probe_rs::probe::list::Lister;
+use probe_rs::probe::{DebugProbeInfo, Probe};
+use probe_rs::{Core, MemoryInterface};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+pub use probe_rs::flashing::{DownloadOptions, Format};
+pub use probe_rs::probe::{DebugProbeSelector, WireProtocol};
+pub use probe_rs::{DetachMode, Session, SessionConfig};
+
+/// Why a flash call failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlashError {
+    /// Invalid arguments or handle, or no probe or target could be attached.
+    Setup(String),
+    /// Loading or programming the image failed.
+    Flash(String),
+}
+
+impl FlashError {
+    /// The return code of the C flash functions.
+    pub(crate) fn code(&self) -> i32 {
+        match self {
+            FlashError::Setup(_) => 1,
+            FlashError::Flash(_) => 2,
+        }
+    }
+
+    pub(crate) fn message(self) -> String {
+        match self {
+            FlashError::Setup(msg) | FlashError::Flash(msg) => msg,
+        }
+    }
+}
+
+impl fmt::Display for FlashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlashError::Setup(msg) | FlashError::Flash(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl std::error::Error for FlashError {}
+
+/// The first probe of the programmer type in effect (`pr_set_programmer_type_code`), or `None`
+/// to let probe-rs pick one if no type is set.
+fn typed_probe() -> Result<Option<DebugProbeInfo>, String> {
+    let Some(ty) = programmer_type() else {
+        return Ok(None);
+    };
+    remote::list_all()
+        .into_iter()
+        .find(|i| info_matches_type(i, ty))
+        .map(Some)
+        .ok_or_else(|| "no probe matching programmer type".to_string())
+}
+
+fn open_typed(info: &DebugProbeInfo) -> Result<Probe, String> {
+    info.open().map_err(|e| format!("open probe error: {}", e))
+}
+
+fn auto_attach(
+    chip: &str,
+    speed_khz: Option<u32>,
+    protocol: Option<WireProtocol>,
+) -> Result<Session, String> {
+    let config = SessionConfig {
+        permissions: Default::default(),
+        speed: speed_khz,
+        protocol,
+    };
+    reset::auto_attach(chip, config)
+}
+
+fn attach(probe: Probe, chip: &str) -> Result<Session, String> {
+    reset::attach(probe, chip, Default::default())
+}
+
+/// Open a session on `chip` through the first probe of the programmer type in effect, or any
+/// probe if none is set (`pr_session_open_auto`). `speed_khz` `None` keeps the probe's default.
+pub fn open_session_auto(
+    chip: &str,
+    speed_khz: Option<u32>,
+    protocol: Option<WireProtocol>,
+) -> Result<u64, String> {
+    let info = typed_probe()?;
+    let params = OpenParams {
+        selector: info.as_ref().map(Into::into),
+        chip: chip.to_string(),
+        speed_khz: speed_khz.unwrap_or(0),
+        protocol,
+    };
+    let session = match info {
+        Some(info) => {
+            let mut probe = open_typed(&info)?;
+            configure(&mut probe, speed_khz, protocol)?;
+            attach(probe, chip)?
+        }
+        None => auto_attach(chip, speed_khz, protocol)?,
+    };
+    Ok(reconnect::remember(make_handle(session), params))
+}
+
+/// Open a session on `chip` through the probe `selector` (`VID:PID[:SN]`,
+/// `pr_session_open_with_probe`). The probe must be of the programmer type in effect, if any.
+pub fn open_session_with_probe(
+    selector: &str,
+    chip: &str,
+    speed_khz: Option<u32>,
+    protocol: Option<WireProtocol>,
+) -> Result<u64, String> {
+    let selector = remote::parse_selector(selector)?;
+    let params = OpenParams {
+        selector: Some(selector.clone()),
+        chip: chip.to_string(),
+        speed_khz: speed_khz.unwrap_or(0),
+        protocol,
+    };
+    let mut probe = Lister::new()
+        .open(selector.clone())
+        .map_err(|e| format!("open probe error: {}", e))?;
+    if let Some(ty) = programmer_type() {
+        let info = remote::find(&selector).ok_or_else(|| "probe not found".to_string())?;
+        if !info_matches_type(&info, ty) {
+            return Err("programmer type mismatch".to_string());
+        }
+    }
+    configure(&mut probe, speed_khz, protocol)?;
+    let session = attach(probe, chip)?;
+    Ok(reconnect::remember(make_handle(session), params))
+}
+
+/// The session behind `handle`; lock it for as short as possible, other calls on the session
+/// wait for it.
+pub fn session(handle: u64) -> Result<Arc<Mutex<Session>>, String> {
+    get_session(handle)
+}
+
+/// Handles of all open sessions, oldest first.
+pub fn session_handles() -> Vec<u64> {
+    let mut handles: Vec<u64> = sessions().lock().unwrap().keys().copied().collect();
+    handles.sort_unstable();
+    handles
+}
+
+/// Close a session, detaching as `mode` says (`pr_session_close_ex`). Background pollers,
+/// monitors, profilers, terminals and SDI print of the session are stopped, open batches are
+/// dropped and software breakpoints are removed first.
+pub fn close_session(handle: u64, mode: DetachMode) -> Result<(), String> {
+    // Release the session map before stopping background threads: callbacks may look up sessions.
+    let removed = sessions().lock().unwrap().remove(&handle);
+    let arc = removed.ok_or_else(|| "invalid session handle".to_string())?;
+    reconnect::forget(handle);
+    batch::forget(handle);
+    session_progress_cbs().lock().unwrap().remove(&handle);
+    gdb_server::stop_for_session(handle);
+    monitor::stop_for_session(handle);
+    poll::stop_for_session(handle);
+    profile::stop_for_session(handle);
+    terminal::stop_for_session(handle);
+    semihosting::forget(handle);
+    swo::forget(handle);
+    svd::unload(handle);
+    let mut lock = arc.lock().unwrap();
+    // Leave the target code as we found it; closing must not fail because of this.
+    let _ = breakpoint::restore_all(handle, &mut lock);
+    sdi::stop_for_session(handle, &mut lock);
+    // The session detaches as configured when it is dropped.
+    lock.set_detach_mode(mode);
+    Ok(())
+}
+
+/// The format of `path` from its extension, as the C flash functions detect it; `.bin` needs
+/// `base_address`.
+pub fn detect_format(path: &str, base_address: Option<u64>, skip: u32) -> Result<Format, String> {
+    crate::detect_format_from_path(path, base_address, skip)
+}
+
+/// Attach to `chip` as `open_session_auto` does, flash `path`, and detach (`pr_flash_auto`).
+/// Flash patches and the after flash behavior apply, and the timing is kept for
+/// `pr_flash_last_timing`.
+pub fn flash(
+    chip: &str,
+    path: &str,
+    format: Format,
+    opts: DownloadOptions<'static>,
+    speed_khz: Option<u32>,
+    protocol: Option<WireProtocol>,
+) -> Result<(), FlashError> {
+    let (mut session, used_speed) = attach_for_flash(chip, speed_khz, protocol)?;
+    flash_image(&mut session, path, format, opts, &[], used_speed).map_err(FlashError::Flash)
+}
+
+/// Attach to `chip` for a flash call that does not keep the session, with the probe speed in
+/// effect for the timing report.
+pub(crate) fn attach_for_flash(
+    chip: &str,
+    speed_khz: Option<u32>,
+    protocol: Option<WireProtocol>,
+) -> Result<(Session, Option<u32>), FlashError> {
+    Ok(match typed_probe().map_err(FlashError::Setup)? {
+        Some(info) => {
+            let mut probe = open_typed(&info).map_err(FlashError::Setup)?;
+            configure(&mut probe, speed_khz, protocol).map_err(FlashError::Setup)?;
+            // Probes pick a supported speed near the requested one.
+            let used_speed = probe.speed_khz();
+            (
+                attach(probe, chip).map_err(FlashError::Setup)?,
+                Some(used_speed),
+            )
+        }
+        None => (
+            auto_attach(chip, speed_khz, protocol).map_err(FlashError::Setup)?,
+            speed_khz,
+        ),
+    })
+}
+
+/// Flash `path` through the open session `handle` without attaching again
+/// (`pr_session_flash`).
+pub fn flash_session(
+    handle: u64,
+    path: &str,
+    format: Format,
+    opts: DownloadOptions<'static>,
+) -> Result<(), FlashError> {
+    let sess = get_session(handle).map_err(FlashError::Setup)?;
+    let mut lock = sess.lock().unwrap();
+    flash_image(&mut lock, path, format, opts, &[], None).map_err(FlashError::Flash)
+}
+
+/// Run `op` on core `core` of session `handle`, reconnecting after USB errors if enabled
+/// (`pr_set_auto_reconnect`). `what` names the operation in the error.
+fn with_core<T>(
+    handle: u64,
+    core: usize,
+    what: &str,
+    op: impl FnMut(&mut Core<'_>) -> Result<T, probe_rs::Error>,
+) -> Result<T, String> {
+    let sess = get_session(handle)?;
+    let mut lock = sess.lock().unwrap();
+    reconnect::with_core(handle, &mut lock, core, op).map_err(|e| match e {
+        CoreOpError::Op(e) => format!("{} error: {}", what, e),
+        CoreOpError::Core(e) => format!("core access error: {}", e),
+    })
+}
+
+/// Read `len` bytes at `address` (`pr_read_8`).
+pub fn read_memory(handle: u64, core: usize, address: u64, len: usize) -> Result<Vec<u8>, String> {
+    with_core(handle, core, "read_8", |c| {
+        let mut data = vec![0u8; len];
+        c.read_8(address, &mut data)?;
+        Ok(data)
+    })
+}
+
+/// Write `data` at `address` (`pr_write_8`).
+pub fn write_memory(handle: u64, core: usize, address: u64, data: &[u8]) -> Result<(), String> {
+    with_core(handle, core, "write_8", |c| c.write_8(address, data))
+}
+
+/// Read `count` 32-bit words at `address` (`pr_read_32`).
+pub fn read_32(handle: u64, core: usize, address: u64, count: usize) -> Result<Vec<u32>, String> {
+    with_core(handle, core, "read_32", |c| {
+        let mut words = vec![0u32; count];
+        c.read_32(address, &mut words)?;
+        Ok(words)
+    })
+}
+
+/// Write 32-bit `words` at `address` (`pr_write_32`).
+pub fn write_32(handle: u64, core: usize, address: u64, words: &[u32]) -> Result<(), String> {
+    with_core(handle, core, "write_32", |c| c.write_32(address, words))
+}
+
+/// Halt `core`, waiting up to `timeout` (`pr_core_halt`).
+pub fn halt_core(handle: u64, core: usize, timeout: Duration) -> Result<(), String> {
+    with_core(handle, core, "halt", |c| c.halt(timeout).map(|_| ()))
+}
+
+/// Resume `core`, stepping over a software breakpoint at the current address (`pr_core_run`).
+pub fn run_core(handle: u64, core: usize) -> Result<(), String> {
+    with_core(handle, core, "run", |c| {
+        breakpoint::step_over_patched(handle, core as u32, c)?;
+        c.run()
+    })
+}
+
+/// Reset `core` and let it run (`pr_core_reset`).
+pub fn reset_core(handle: u64, core: usize) -> Result<(), String> {
+    with_core(handle, core, "reset", |c| c.reset())
+}
+
+/// Manufacturers of the built-in chip database, in `pr_chip_manufacturer_name` order.
+pub fn chip_manufacturers() -> Vec<&'static str> {
+    crate::chip_db()
+        .manufacturers
+        .iter()
+        .map(|m| m.name.as_str())
+        .collect()
+}
+
+/// Chips of the manufacturer at `manufacturer_index`, sorted by name.
+pub fn chip_models(manufacturer_index: usize) -> Option<&'static [String]> {
+    crate::chip_db()
+        .manufacturers
+        .get(manufacturer_index)
+        .map(|m| m.chips.as_slice())
+}
+
+/// Manufacturer of `chip` (exact name as in the database).
+pub fn chip_manufacturer(chip: &str) -> Option<&'static str> {
+    let db = crate::chip_db();
+    let (manufacturer, _) = db.name_to_index.get(chip)?;
+    db.manufacturers
+        .get(*manufacturer as usize)
+        .map(|m| m.name.as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn chip_database_is_consistent() {
+        let manufacturers = chip_manufacturers();
+        assert!(!manufacturers.is_empty());
+        let (index, chip) = (0..manufacturers.len())
+            .find_map(|i| Some((i, chip_models(i)?.first()?.clone())))
+            .unwrap();
+        assert_eq!(chip_manufacturer(&chip), Some(manufacturers[index]));
+        assert_eq!(chip_models(manufacturers.len()), None);
+    }
+
+    #[test]
+    fn invalid_handles_are_rejected() {
+        assert!(session(0xdead).is_err());
+        assert!(close_session(0xdead, DetachMode::Run).is_err());
+        let format = detect_format("firmware.hex", None, 0).unwrap();
+        let err = flash_session(0xdead, "firmware.hex", format, DownloadOptions::default());
+        assert!(matches!(err, Err(FlashError::Setup(_))));
+        assert_eq!(
+            read_memory(0xdead, 0, 0x2000_0000, 4),
+            Err("invalid session handle".to_string())
+        );
+    }
+}
@@ -0,0 +1,301 @@
+//! Dual-bank flash: bank layout, and querying/selecting the bank the device boots from, for
+//! A/B firmware update development.
+//!
+//! Bank swapping is device specific; it is implemented for the STM32 families that select the
+//! boot bank with the BFB2 option bit.
+
+use crate::{get_session, set_error, write_c_str};
+use probe_rs::config::Target;
+use probe_rs::{Core, MemoryInterface};
+use probe_rs_target::MemoryRegion;
+use serde::Serialize;
+use std::ffi::c_char;
+use std::time::{Duration, Instant};
+
+pub(crate) const FLASH_KEY1: u32 = 0x4567_0123;
+pub(crate) const FLASH_KEY2: u32 = 0xCDEF_89AB;
+pub(crate) const OPT_KEY1: u32 = 0x0819_2A3B;
+pub(crate) const OPT_KEY2: u32 = 0x4C5D_6E7F;
+const SR_BSY: u32 = 1 << 16;
+/// `FB_MODE`/`UFB_MODE` in SYSCFG_MEMRMP: bank 2 is mapped at the flash base address.
+const MEMRMP_FB_MODE: u32 = 1 << 8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Family {
+    /// STM32L4/L4+ and STM32G47x/G48x: BFB2 in FLASH_OPTR, applied by OBL_LAUNCH.
+    L4,
+    /// STM32F42x/F43x/F469/F479: BFB2 in FLASH_OPTCR, applied by the next reset.
+    F4,
+}
+
+impl Family {
+    fn for_target(name: &str) -> Option<Self> {
+        const L4: &[&str] = &[
+            "STM32L47", "STM32L48", "STM32L49", "STM32L4A", "STM32L4P", "STM32L4Q", "STM32L4R",
+            "STM32L4S", "STM32G47", "STM32G48",
+        ];
+        const F4: &[&str] = &["STM32F42", "STM32F43", "STM32F469", "STM32F479"];
+        let name = name.to_ascii_uppercase();
+        if L4.iter().any(|p| name.starts_with(p)) {
+            Some(Family::L4)
+        } else if F4.iter().any(|p| name.starts_with(p)) {
+            Some(Family::F4)
+        } else {
+            None
+        }
+    }
+
+    fn syscfg_memrmp(self) -> u64 {
+        match self {
+            Family::L4 => 0x4001_0000,
+            Family::F4 => 0x4001_3800,
+        }
+    }
+}
+
+// STM32L4 flash registers.
+pub(crate) const L4_FLASH_KEYR: u64 = 0x4002_2008;
+pub(crate) const L4_FLASH_OPTKEYR: u64 = 0x4002_200C;
+pub(crate) const L4_FLASH_SR: u64 = 0x4002_2010;
+pub(crate) const L4_FLASH_CR: u64 = 0x4002_2014;
+pub(crate) const L4_FLASH_OPTR: u64 = 0x4002_2020;
+pub(crate) const L4_CR_OPTSTRT: u32 = 1 << 17;
+pub(crate) const L4_CR_OBL_LAUNCH: u32 = 1 << 27;
+pub(crate) const L4_CR_OPTLOCK: u32 = 1 << 30;
+pub(crate) const L4_CR_LOCK: u32 = 1 << 31;
+const L4_OPTR_BFB2: u32 = 1 << 20;
+
+// STM32F4 flash registers.
+pub(crate) const F4_FLASH_OPTKEYR: u64 = 0x4002_3C08;
+pub(crate) const F4_FLASH_SR: u64 = 0x4002_3C0C;
+pub(crate) const F4_FLASH_OPTCR: u64 = 0x4002_3C14;
+pub(crate) const F4_OPTCR_OPTLOCK: u32 = 1 << 0;
+pub(crate) const F4_OPTCR_OPTSTRT: u32 = 1 << 1;
+const F4_OPTCR_BFB2: u32 = 1 << 4;
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct FlashBank {
+    name: String,
+    start: u64,
+    end: u64,
+}
+
+#[derive(Serialize)]
+struct BankInfo {
+    banks: Vec<FlashBank>,
+    /// Whether `pr_flash_bank_select` supports this chip.
+    swap_supported: bool,
+    /// Bank currently mapped at the flash base address.
+    active: Option<u32>,
+    /// Bank the device boots from after the next reset (BFB2 option).
+    boot: Option<u32>,
+}
+
+/// Flash banks of a target: its `BANK_<n>` flash regions, in address order.
+pub(crate) fn flash_banks(target: &Target) -> Vec<FlashBank> {
+    let mut banks: Vec<FlashBank> = target
+        .memory_map
+        .iter()
+        .filter_map(MemoryRegion::as_nvm_region)
+        .filter(|r| !r.is_alias)
+        .filter_map(|r| {
+            let name = r.name.as_deref()?;
+            name.to_ascii_uppercase()
+                .starts_with("BANK")
+                .then(|| FlashBank {
+                    name: name.to_string(),
+                    start: r.range.start,
+                    end: r.range.end,
+                })
+        })
+        .collect();
+    banks.sort_by_key(|b| b.start);
+    banks
+}
+
+fn mapped_bank(core: &mut Core<'_>, family: Family) -> Result<u32, probe_rs::Error> {
+    let memrmp = core.read_word_32(family.syscfg_memrmp())?;
+    Ok(if memrmp & MEMRMP_FB_MODE != 0 { 2 } else { 1 })
+}
+
+fn boot_bank(core: &mut Core<'_>, family: Family) -> Result<u32, probe_rs::Error> {
+    let bfb2 = match family {
+        Family::L4 => core.read_word_32(L4_FLASH_OPTR)? & L4_OPTR_BFB2 != 0,
+        Family::F4 => core.read_word_32(F4_FLASH_OPTCR)? & F4_OPTCR_BFB2 != 0,
+    };
+    Ok(if bfb2 { 2 } else { 1 })
+}
+
+pub(crate) fn wait_not_busy(core: &mut Core<'_>, sr: u64) -> Result<(), probe_rs::Error> {
+    wait_not_busy_for(core, sr, Duration::from_secs(2))
+}
+
+/// `wait_not_busy` for option changes that take longer, like those erasing the flash.
+pub(crate) fn wait_not_busy_for(
+    core: &mut Core<'_>,
+    sr: u64,
+    timeout: Duration,
+) -> Result<(), probe_rs::Error> {
+    let start = Instant::now();
+    while core.read_word_32(sr)? & SR_BSY != 0 {
+        if start.elapsed() > timeout {
+            return Err(probe_rs::Error::Other(
+                "timeout waiting for option byte programming".to_string(),
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    Ok(())
+}
+
+fn select_boot_bank(core: &mut Core<'_>, family: Family, bank: u32) -> Result<(), probe_rs::Error> {
+    match family {
+        Family::L4 => {
+            if core.read_word_32(L4_FLASH_CR)? & L4_CR_LOCK != 0 {
+                core.write_word_32(L4_FLASH_KEYR, FLASH_KEY1)?;
+                core.write_word_32(L4_FLASH_KEYR, FLASH_KEY2)?;
+            }
+            if core.read_word_32(L4_FLASH_CR)? & L4_CR_OPTLOCK != 0 {
+                core.write_word_32(L4_FLASH_OPTKEYR, OPT_KEY1)?;
+                core.write_word_32(L4_FLASH_OPTKEYR, OPT_KEY2)?;
+            }
+            wait_not_busy(core, L4_FLASH_SR)?;
+            let optr = core.read_word_32(L4_FLASH_OPTR)? & !L4_OPTR_BFB2;
+            let bfb2 = if bank == 2 { L4_OPTR_BFB2 } else { 0 };
+            core.write_word_32(L4_FLASH_OPTR, optr | bfb2)?;
+            let cr = core.read_word_32(L4_FLASH_CR)?;
+            core.write_word_32(L4_FLASH_CR, cr | L4_CR_OPTSTRT)?;
+            wait_not_busy(core, L4_FLASH_SR)?;
+            // Reloading the option bytes resets the device, so the write may not be acknowledged.
+            let cr = core.read_word_32(L4_FLASH_CR)?;
+            let _ = core.write_word_32(L4_FLASH_CR, cr | L4_CR_OBL_LAUNCH);
+        }
+        Family::F4 => {
+            if core.read_word_32(F4_FLASH_OPTCR)? & F4_OPTCR_OPTLOCK != 0 {
+                core.write_word_32(F4_FLASH_OPTKEYR, OPT_KEY1)?;
+                core.write_word_32(F4_FLASH_OPTKEYR, OPT_KEY2)?;
+            }
+            wait_not_busy(core, F4_FLASH_SR)?;
+            let optcr = core.read_word_32(F4_FLASH_OPTCR)? & !F4_OPTCR_BFB2;
+            let bfb2 = if bank == 2 { F4_OPTCR_BFB2 } else { 0 };
+            core.write_word_32(F4_FLASH_OPTCR, optcr | bfb2)?;
+            core.write_word_32(F4_FLASH_OPTCR, optcr | bfb2 | F4_OPTCR_OPTSTRT)?;
+            wait_not_busy(core, F4_FLASH_SR)?;
+            let optcr = core.read_word_32(F4_FLASH_OPTCR)?;
+            core.write_word_32(F4_FLASH_OPTCR, optcr | F4_OPTCR_OPTLOCK)?;
+        }
+    }
+    Ok(())
+}
+
+/// Describe the flash banks of the session's target as JSON:
+/// `{"banks": [{"name", "start", "end"}], "swap_supported", "active", "boot"}`.
+///
+/// `active` is the bank currently mapped at the flash base address and `boot` the bank
+/// selected for the next boot; both are null if `swap_supported` is false.
+///
+/// Returns the required size including NUL, or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_bank_info(
+    session: u64,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return 0;
+    };
+    let mut lock = sess.lock().unwrap();
+    let banks = flash_banks(lock.target());
+    let family = Family::for_target(&lock.target().name);
+    let mut info = BankInfo {
+        banks,
+        swap_supported: family.is_some(),
+        active: None,
+        boot: None,
+    };
+    if let Some(family) = family {
+        let res = lock.core(0).and_then(|mut core| {
+            Ok((
+                mapped_bank(&mut core, family)?,
+                boot_bank(&mut core, family)?,
+            ))
+        });
+        match res {
+            Ok((active, boot)) => {
+                info.active = Some(active);
+                info.boot = Some(boot);
+            }
+            Err(e) => {
+                set_error(format!("read bank state error: {}", e));
+                return 0;
+            }
+        }
+    }
+    match serde_json::to_string(&info) {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e.to_string());
+            0
+        }
+    }
+}
+
+/// Select the flash bank (1 or 2) the device boots from, by programming the BFB2 option bit.
+///
+/// On STM32L4/L4+/G4 the option bytes are reloaded immediately, which resets the device and
+/// may require reopening the session; on STM32F4 the selection applies at the next reset.
+///
+/// Returns 0 on success, -1 on invalid handle or bank, -2 on target error, -3 if bank
+/// selection is not supported for this chip.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_bank_select(session: u64, bank: u32) -> i32 {
+    if !matches!(bank, 1 | 2) {
+        set_error(format!("invalid bank {}, expected 1 or 2", bank));
+        return -1;
+    }
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let Some(family) = Family::for_target(&lock.target().name) else {
+        set_error(format!(
+            "bank selection is not supported for {}",
+            lock.target().name
+        ));
+        return -3;
+    };
+    let res = lock
+        .core(0)
+        .and_then(|mut core| select_boot_bank(&mut core, family, bank));
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(format!("bank select error: {}", e));
+            -2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn families_by_name() {
+        assert_eq!(Family::for_target("STM32L476RGTx"), Some(Family::L4));
+        assert_eq!(Family::for_target("stm32g474re"), Some(Family::L4));
+        assert_eq!(Family::for_target("STM32F429ZITx"), Some(Family::F4));
+        assert_eq!(Family::for_target("STM32F407VG"), None);
+    }
+
+    #[test]
+    fn banks_from_memory_map() {
+        let target = crate::registry().get_target_by_name("STM32L476RG").unwrap();
+        let banks = flash_banks(&target);
+        assert_eq!(banks.len(), 2);
+        assert_eq!(banks[1].name, "BANK_2");
+        assert_eq!(banks[1].start, 0x0808_0000);
+    }
+}
@@ -0,0 +1,418 @@
+//! Batched memory transactions: reads and writes are queued on the host and run by one commit,
+//! with the session locked and the core attached once. Only contiguous accesses of the same
+//! kind and width are coalesced, into block accesses the probe drivers send as transfer blocks
+//! (CMSIS-DAP) or multi-word reads (J-Link): a polling loop over consecutive registers costs one
+//! USB round-trip, but every read at a scattered address still costs its own.
+
+use crate::{get_session, set_error};
+use probe_rs::{Core, MemoryInterface};
+use std::collections::HashMap;
+use std::ffi::c_void;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+#[derive(Clone, Debug, PartialEq)]
+enum Access {
+    Read {
+        count: usize,
+    },
+    /// Elements in host byte order.
+    Write {
+        data: Vec<u8>,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Op {
+    address: u64,
+    /// Element size in bytes: 1, 2, 4 or 8.
+    width: usize,
+    access: Access,
+    /// The queued operations this one stands for, after merging.
+    queued: u32,
+}
+
+impl Op {
+    fn len(&self) -> usize {
+        match &self.access {
+            Access::Read { count } => count * self.width,
+            Access::Write { data } => data.len(),
+        }
+    }
+
+    /// Append `next` if it continues this access at the following address.
+    fn merge(&mut self, next: &Op) -> bool {
+        if self.width != next.width || self.address + self.len() as u64 != next.address {
+            return false;
+        }
+        match (&mut self.access, &next.access) {
+            (Access::Read { count }, Access::Read { count: more }) => *count += more,
+            (Access::Write { data }, Access::Write { data: more }) => data.extend_from_slice(more),
+            _ => return false,
+        }
+        self.queued += next.queued;
+        true
+    }
+}
+
+struct Batch {
+    session: u64,
+    core: usize,
+    ops: Vec<Op>,
+}
+
+static BATCHES: OnceLock<Mutex<HashMap<u64, Batch>>> = OnceLock::new();
+static NEXT_BATCH_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+fn batches() -> &'static Mutex<HashMap<u64, Batch>> {
+    BATCHES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Drop the open batches of a session, e.g. when it is closed.
+pub(crate) fn forget(session: u64) {
+    batches()
+        .lock()
+        .unwrap()
+        .retain(|_, b| b.session != session);
+}
+
+/// `ops` with each run of adjacent accesses merged into one.
+fn merged(ops: &[Op]) -> Vec<Op> {
+    let mut out: Vec<Op> = Vec::with_capacity(ops.len());
+    for op in ops {
+        if !out.last_mut().is_some_and(|last| last.merge(op)) {
+            out.push(op.clone());
+        }
+    }
+    out
+}
+
+/// Bytes of read results the batch produces.
+fn results_size(ops: &[Op]) -> usize {
+    ops.iter()
+        .filter(|op| matches!(op.access, Access::Read { .. }))
+        .map(Op::len)
+        .sum()
+}
+
+/// Run one access, appending read elements to `results` in host byte order.
+fn run(core: &mut Core<'_>, op: &Op, results: &mut Vec<u8>) -> Result<(), probe_rs::Error> {
+    let address = op.address;
+    match (&op.access, op.width) {
+        (Access::Read { count }, 1) => {
+            let mut buf = vec![0u8; *count];
+            core.read_8(address, &mut buf)?;
+            results.extend_from_slice(&buf);
+        }
+        (Access::Read { count }, 2) => {
+            let mut buf = vec![0u16; *count];
+            core.read_16(address, &mut buf)?;
+            results.extend(buf.iter().flat_map(|v| v.to_ne_bytes()));
+        }
+        (Access::Read { count }, 4) => {
+            let mut buf = vec![0u32; *count];
+            core.read_32(address, &mut buf)?;
+            results.extend(buf.iter().flat_map(|v| v.to_ne_bytes()));
+        }
+        (Access::Read { count }, _) => {
+            let mut buf = vec![0u64; *count];
+            core.read_64(address, &mut buf)?;
+            results.extend(buf.iter().flat_map(|v| v.to_ne_bytes()));
+        }
+        (Access::Write { data }, 1) => core.write_8(address, data)?,
+        (Access::Write { data }, 2) => {
+            let words: Vec<u16> = data
+                .chunks_exact(2)
+                .map(|c| u16::from_ne_bytes([c[0], c[1]]))
+                .collect();
+            core.write_16(address, &words)?;
+        }
+        (Access::Write { data }, 4) => {
+            let words: Vec<u32> = data
+                .chunks_exact(4)
+                .map(|c| u32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+                .collect();
+            core.write_32(address, &words)?;
+        }
+        (Access::Write { data }, _) => {
+            let words: Vec<u64> = data
+                .chunks_exact(8)
+                .map(|c| u64::from_ne_bytes(c.try_into().unwrap()))
+                .collect();
+            core.write_64(address, &words)?;
+        }
+    }
+    Ok(())
+}
+
+/// Queue `op` on `batch`, returning its index.
+fn queue(batch: u64, op: Op) -> i32 {
+    let mut map = batches().lock().unwrap();
+    let Some(b) = map.get_mut(&batch) else {
+        set_error("invalid batch handle".to_string());
+        return -1;
+    };
+    b.ops.push(op);
+    (b.ops.len() - 1) as i32
+}
+
+fn check_width(width: u32) -> bool {
+    if matches!(width, 1 | 2 | 4 | 8) {
+        return true;
+    }
+    set_error(format!("invalid width {}, expected 1, 2, 4 or 8", width));
+    false
+}
+
+/// Start a batch of memory accesses on a core of the session. Nothing is sent to the target
+/// until `pr_batch_commit`.
+///
+/// Returns a non-zero batch handle, or 0 on invalid session handle or core index.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_batch_begin(session: u64, core_index: u32) -> u64 {
+    let sess = match get_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let cores = sess.lock().unwrap().list_cores().len();
+    if core_index as usize >= cores {
+        set_error(format!("invalid core index {}", core_index));
+        return 0;
+    }
+    let handle = NEXT_BATCH_HANDLE.fetch_add(1, Ordering::Relaxed);
+    batches().lock().unwrap().insert(
+        handle,
+        Batch {
+            session,
+            core: core_index as usize,
+            ops: Vec::new(),
+        },
+    );
+    handle
+}
+
+/// Queue a read of `count` elements of `width` bytes (1, 2, 4 or 8) at `address`. Its data is
+/// placed in the results of `pr_batch_commit` after that of the reads queued before it.
+///
+/// Returns the index of the access in the batch, or -1 on invalid handle or arguments.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_batch_queue_read(batch: u64, address: u64, width: u32, count: u32) -> i32 {
+    if !check_width(width) {
+        return -1;
+    }
+    let op = Op {
+        address,
+        width: width as usize,
+        access: Access::Read {
+            count: count as usize,
+        },
+        queued: 1,
+    };
+    queue(batch, op)
+}
+
+/// Queue a write of the `count` elements of `width` bytes (1, 2, 4 or 8, host byte order) at
+/// `data` to `address`. The data is copied, so the buffer may be reused right away.
+///
+/// Returns the index of the access in the batch, or -1 on invalid handle or arguments.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_batch_queue_write(
+    batch: u64,
+    address: u64,
+    width: u32,
+    data: *const c_void,
+    count: u32,
+) -> i32 {
+    if !check_width(width) {
+        return -1;
+    }
+    if data.is_null() && count > 0 {
+        set_error("data is null".to_string());
+        return -1;
+    }
+    let len = width as usize * count as usize;
+    let data = if len == 0 {
+        Vec::new()
+    } else {
+        unsafe { std::slice::from_raw_parts(data as *const u8, len) }.to_vec()
+    };
+    let op = Op {
+        address,
+        width: width as usize,
+        access: Access::Write { data },
+        queued: 1,
+    };
+    queue(batch, op)
+}
+
+/// Bytes of read results `pr_batch_commit` writes for the accesses queued so far, or 0 on
+/// invalid handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_batch_results_size(batch: u64) -> usize {
+    match batches().lock().unwrap().get(&batch) {
+        Some(b) => results_size(&b.ops),
+        None => {
+            set_error("invalid batch handle".to_string());
+            0
+        }
+    }
+}
+
+/// Run the queued accesses in order and end the batch. The data of the reads is written to
+/// `out_results` one after the other in queue order, each element in host byte order; it must
+/// hold `pr_batch_results_size` bytes (it may be NULL if there are no reads).
+/// `out_completed` (may be NULL) receives how many of the queued accesses ran; on a target
+/// error the results of those are valid and the rest were not run.
+///
+/// Returns 0 on success, -1 on invalid handle or too small buffer (the batch is kept so the
+/// commit can be retried), -2 on target error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_batch_commit(
+    batch: u64,
+    out_results: *mut c_void,
+    results_len: usize,
+    out_completed: *mut u32,
+) -> i32 {
+    if !out_completed.is_null() {
+        unsafe { *out_completed = 0 };
+    }
+    let b = {
+        let mut map = batches().lock().unwrap();
+        let Some(b) = map.get(&batch) else {
+            set_error("invalid batch handle".to_string());
+            return -1;
+        };
+        let size = results_size(&b.ops);
+        if size > 0 && (out_results.is_null() || results_len < size) {
+            set_error(format!("results need {} bytes, got {}", size, results_len));
+            return -1;
+        }
+        map.remove(&batch).unwrap()
+    };
+    let sess = match get_session(b.session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let mut lock = sess.lock().unwrap();
+    let mut core = match lock.core(b.core) {
+        Ok(core) => core,
+        Err(e) => {
+            set_error(format!("core access error: {}", e));
+            return -2;
+        }
+    };
+    let mut results = Vec::with_capacity(results_size(&b.ops));
+    let mut completed = 0u32;
+    let mut res = Ok(());
+    for op in merged(&b.ops) {
+        res = run(&mut core, &op, &mut results);
+        if res.is_err() {
+            break;
+        }
+        completed += op.queued;
+    }
+    // Writes may still be queued in the probe driver; make sure they reached the target.
+    if res.is_ok() {
+        res = core.flush();
+    }
+    if !results.is_empty() {
+        unsafe {
+            std::ptr::copy_nonoverlapping(results.as_ptr(), out_results as *mut u8, results.len())
+        };
+    }
+    if !out_completed.is_null() {
+        unsafe { *out_completed = completed };
+    }
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(format!("batch error: {}", e));
+            -2
+        }
+    }
+}
+
+/// End a batch without running it. Returns 0 on success, -1 on invalid handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_batch_discard(batch: u64) -> i32 {
+    match batches().lock().unwrap().remove(&batch) {
+        Some(_) => 0,
+        None => {
+            set_error("invalid batch handle".to_string());
+            -1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(address: u64, width: usize, count: usize) -> Op {
+        Op {
+            address,
+            width,
+            access: Access::Read { count },
+            queued: 1,
+        }
+    }
+
+    fn write(address: u64, width: usize, data: &[u8]) -> Op {
+        Op {
+            address,
+            width,
+            access: Access::Write {
+                data: data.to_vec(),
+            },
+            queued: 1,
+        }
+    }
+
+    #[test]
+    fn adjacent_accesses_are_merged() {
+        let ops = [
+            read(0x4000_0000, 4, 1),
+            read(0x4000_0004, 4, 2),
+            read(0x4000_0010, 4, 1),
+            read(0x4000_0014, 2, 1),
+            write(0x2000_0000, 1, &[1, 2]),
+            write(0x2000_0002, 1, &[3]),
+            read(0x2000_0003, 1, 1),
+        ];
+        let mut first = read(0x4000_0000, 4, 3);
+        first.queued = 2;
+        let mut written = write(0x2000_0000, 1, &[1, 2, 3]);
+        written.queued = 2;
+        assert_eq!(
+            merged(&ops),
+            vec![
+                first,
+                read(0x4000_0010, 4, 1),
+                read(0x4000_0014, 2, 1),
+                written,
+                read(0x2000_0003, 1, 1),
+            ]
+        );
+        assert_eq!(results_size(&ops), 12 + 4 + 2 + 1);
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        assert_eq!(pr_batch_begin(0xdead, 0), 0);
+        assert_eq!(pr_batch_queue_read(0xdead, 0, 4, 1), -1);
+        assert_eq!(pr_batch_queue_read(0xdead, 0, 3, 1), -1);
+        assert_eq!(pr_batch_queue_write(0xdead, 0, 4, std::ptr::null(), 1), -1);
+        assert_eq!(pr_batch_results_size(0xdead), 0);
+        assert_eq!(
+            pr_batch_commit(0xdead, std::ptr::null_mut(), 0, std::ptr::null_mut()),
+            -1
+        );
+        assert_eq!(pr_batch_discard(0xdead), -1);
+    }
+}
@@ -0,0 +1,280 @@
+//! Memory access throughput of a probe and target, to attach to "flashing is slow" reports:
+//! RAM is written and read back in blocks of several sizes at the speed the probe settled on.
+
+use crate::reset::{self, configure, open_probe};
+use crate::{cstr_to_string, protocol_from_int, set_error, write_c_str};
+use probe_rs::config::{MemoryRegion, Target};
+use probe_rs::{MemoryInterface, Permissions};
+use serde::Serialize;
+use std::ffi::c_char;
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// Block sizes measured, in bytes; those above the benchmark size are skipped.
+const BLOCK_SIZES: [u64; 5] = [4, 64, 1024, 16 * 1024, 64 * 1024];
+
+/// Benchmark size when none is given, if the RAM region is large enough.
+const DEFAULT_SIZE: u64 = 64 * 1024;
+
+#[derive(Serialize, Debug)]
+struct Throughput {
+    block_size: u64,
+    write_bytes_per_sec: u64,
+    read_bytes_per_sec: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct Benchmark {
+    probe: String,
+    protocol: Option<String>,
+    /// Requested and effective probe clock: probes pick a supported speed near the requested
+    /// one.
+    requested_speed_khz: Option<u32>,
+    speed_khz: u32,
+    target: String,
+    address: u64,
+    size: u64,
+    results: Vec<Throughput>,
+}
+
+/// The first writable RAM region core `core` can access.
+fn scratch_region(target: &Target, core: usize) -> Option<Range<u64>> {
+    let name = &target.cores.get(core)?.name;
+    target.memory_map.iter().find_map(|region| match region {
+        MemoryRegion::Ram(ram) if ram.accessible_by(name) && ram.is_writable() => {
+            Some(ram.range.clone())
+        }
+        _ => None,
+    })
+}
+
+fn block_sizes(size: u64) -> Vec<u64> {
+    BLOCK_SIZES.into_iter().filter(|b| *b <= size).collect()
+}
+
+/// Pseudo-random words (xorshift), so a stuck or shifted readback does not compare equal.
+fn pattern(words: usize, seed: u32) -> Vec<u32> {
+    let mut x = seed | 1;
+    (0..words)
+        .map(|_| {
+            x ^= x << 13;
+            x ^= x >> 17;
+            x ^= x << 5;
+            x
+        })
+        .collect()
+}
+
+fn rate(bytes: u64, elapsed: Duration) -> u64 {
+    (bytes as f64 / elapsed.as_secs_f64().max(1e-9)) as u64
+}
+
+/// Write and read back `size` bytes at `address` in blocks of each size.
+fn measure(
+    core: &mut probe_rs::Core<'_>,
+    address: u64,
+    size: u64,
+) -> Result<Vec<Throughput>, String> {
+    let mut results = Vec::new();
+    for (seed, block) in block_sizes(size).into_iter().enumerate() {
+        let words = (block / 4) as usize;
+        let blocks = size / block;
+        let data = pattern(words * blocks as usize, seed as u32 + 0x5eed);
+        let mut readback = vec![0u32; words];
+
+        let start = Instant::now();
+        for (i, chunk) in data.chunks(words).enumerate() {
+            core.write_32(address + i as u64 * block, chunk)
+                .map_err(|e| format!("write error: {}", e))?;
+        }
+        let write = start.elapsed();
+
+        let mut read = Duration::ZERO;
+        for (i, chunk) in data.chunks(words).enumerate() {
+            let at = address + i as u64 * block;
+            let start = Instant::now();
+            core.read_32(at, &mut readback)
+                .map_err(|e| format!("read error: {}", e))?;
+            read += start.elapsed();
+            if let Some(n) = readback.iter().zip(chunk).position(|(r, w)| r != w) {
+                return Err(format!(
+                    "readback mismatch at {:#x} with {} byte blocks",
+                    at + n as u64 * 4,
+                    block
+                ));
+            }
+        }
+        results.push(Throughput {
+            block_size: block,
+            write_bytes_per_sec: rate(blocks * block, write),
+            read_bytes_per_sec: rate(blocks * block, read),
+        });
+    }
+    Ok(results)
+}
+
+#[allow(clippy::too_many_arguments)]
+fn benchmark(
+    chip: &str,
+    selector: Option<&str>,
+    speed_khz: Option<u32>,
+    protocol: i32,
+    core_index: usize,
+    address: Option<u64>,
+    size: Option<u64>,
+) -> Result<Benchmark, String> {
+    let mut probe = open_probe(selector)?;
+    configure(&mut probe, speed_khz, protocol_from_int(protocol))?;
+    let probe_name = probe.get_name();
+    let protocol = probe.protocol().map(|p| p.to_string());
+    let speed = probe.speed_khz();
+    let mut session = reset::attach(probe, chip, Permissions::new())?;
+
+    let target = session.target();
+    if core_index >= target.cores.len() {
+        return Err(format!("invalid core index {}", core_index));
+    }
+    let region = scratch_region(target, core_index);
+    let address = match address.or(region.as_ref().map(|r| r.start)) {
+        Some(address) => address,
+        None => return Err("no RAM region to benchmark, pass an address".to_string()),
+    };
+    let size = match size {
+        Some(size) => size,
+        None => region
+            .as_ref()
+            .filter(|r| r.contains(&address))
+            .map_or(DEFAULT_SIZE, |r| (r.end - address).min(DEFAULT_SIZE) & !3),
+    };
+    if address % 4 != 0 || size < 4 || size % 4 != 0 {
+        return Err(format!(
+            "benchmark needs a word aligned address and size, got {:#x} and {}",
+            address, size
+        ));
+    }
+    let target_name = target.name.clone();
+
+    let mut core = session
+        .core(core_index)
+        .map_err(|e| format!("core error: {}", e))?;
+    let was_running = !core
+        .core_halted()
+        .map_err(|e| format!("core status error: {}", e))?;
+    if was_running {
+        core.halt(Duration::from_millis(500))
+            .map_err(|e| format!("halt error: {}", e))?;
+    }
+    let mut saved = vec![0u32; (size / 4) as usize];
+    core.read_32(address, &mut saved)
+        .map_err(|e| format!("read error: {}", e))?;
+    let results = measure(&mut core, address, size);
+    // Leave the RAM and the core as they were, even after a failed measurement.
+    let restored = core
+        .write_32(address, &saved)
+        .map_err(|e| format!("restore error: {}", e));
+    if was_running && let Err(e) = core.run() {
+        tracing::debug!("resume after benchmark failed: {}", e);
+    }
+    let results = results?;
+    restored?;
+
+    Ok(Benchmark {
+        probe: probe_name,
+        protocol,
+        requested_speed_khz: speed_khz,
+        speed_khz: speed,
+        target: target_name,
+        address,
+        size,
+        results,
+    })
+}
+
+/// Measure the memory throughput between the probe and `chip`: `size` bytes of RAM at `address`
+/// are written and read back in blocks of 4 bytes up to 64 KiB through core `core_index`, which
+/// is halted meanwhile. The RAM contents are restored afterwards and a core that was running is
+/// resumed.
+///
+/// `selector` is `VID:PID[:SN]`, or NULL for the first probe of the programmer type in effect;
+/// `speed_khz` 0 keeps the probe's default and `protocol_code` is 0 auto, 1 SWD, 2 JTAG.
+/// `address` 0 uses the first writable RAM region of the core and `size` 0 up to 64 KiB of it.
+///
+/// The result is JSON: `{"probe", "protocol", "requested_speed_khz", "speed_khz", "target",
+/// "address", "size", "results": [{"block_size", "write_bytes_per_sec",
+/// "read_bytes_per_sec"}]}`, where `speed_khz` is the clock the probe actually runs at.
+///
+/// Returns the required size including NUL, or 0 on error (including a readback mismatch);
+/// see `pr_last_error()`.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub extern "C" fn pr_benchmark(
+    chip: *const c_char,
+    selector: *const c_char,
+    speed_khz: u32,
+    protocol_code: i32,
+    core_index: u32,
+    address: u64,
+    size: u64,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let chip = match cstr_to_string(chip) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let selector = if selector.is_null() {
+        None
+    } else {
+        match cstr_to_string(selector) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                set_error(e);
+                return 0;
+            }
+        }
+    };
+    let json = benchmark(
+        &chip,
+        selector.as_deref(),
+        Some(speed_khz).filter(|s| *s != 0),
+        protocol_code,
+        core_index as usize,
+        Some(address).filter(|a| *a != 0),
+        Some(size).filter(|s| *s != 0),
+    )
+    .and_then(|found| serde_json::to_string(&found).map_err(|e| e.to_string()));
+    match json {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e);
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry;
+
+    #[test]
+    fn scratch_ram_and_block_sizes() {
+        let target = registry().get_target_by_name("STM32F407VGTx").unwrap();
+        let ram = scratch_region(&target, 0).unwrap();
+        // The CCM RAM comes first in the F407 memory map.
+        assert_eq!(ram, 0x1000_0000..0x1001_0000);
+        assert_eq!(scratch_region(&target, 1), None);
+
+        assert_eq!(block_sizes(DEFAULT_SIZE), BLOCK_SIZES.to_vec());
+        assert_eq!(block_sizes(1000), vec![4, 64]);
+        assert_ne!(pattern(4, 1), pattern(4, 2));
+        let null = std::ptr::null();
+        assert_eq!(
+            pr_benchmark(null, null, 0, 0, 0, 0, 0, std::ptr::null_mut(), 0),
+            0
+        );
+    }
+}
@@ -0,0 +1,313 @@
+//! Software breakpoints: the instruction at the breakpoint address is replaced by the
+//! architecture's break instruction and restored when the breakpoint is cleared.
+//!
+//! This only works for code in writable memory (RAM); flash-resident code needs hardware
+//! breakpoints.
+
+use crate::{get_session, set_error};
+use probe_rs::{Core, CoreInterface, Endian, Error, InstructionSet, MemoryInterface};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+/// Original instruction bytes keyed by session handle, then by (core index, address).
+type PatchMap = HashMap<u64, HashMap<(u32, u64), Vec<u8>>>;
+
+static SW_BREAKPOINTS: OnceLock<Mutex<PatchMap>> = OnceLock::new();
+
+fn sw_breakpoints() -> &'static Mutex<PatchMap> {
+    SW_BREAKPOINTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Encoding of the break instruction for an instruction set, in target memory byte order.
+fn encode_break(isa: InstructionSet, endian: Endian) -> Vec<u8> {
+    let (bytes, swap): (Vec<u8>, bool) = match isa {
+        // BKPT #0
+        InstructionSet::Thumb2 => (0xBE00u16.to_le_bytes().to_vec(), true),
+        // BKPT #0
+        InstructionSet::A32 => (0xE120_0070u32.to_le_bytes().to_vec(), true),
+        // BRK #0 (A64 instructions are always little endian)
+        InstructionSet::A64 => (0xD420_0000u32.to_le_bytes().to_vec(), false),
+        // EBREAK
+        InstructionSet::RV32 => (0x0010_0073u32.to_le_bytes().to_vec(), false),
+        // C.EBREAK, safe to place over both 16 and 32 bit instructions
+        InstructionSet::RV32C => (0x9002u16.to_le_bytes().to_vec(), false),
+        // BREAK 0, 0
+        InstructionSet::Xtensa => (vec![0x00, 0x40, 0x00], false),
+    };
+    if swap && endian == Endian::Big {
+        bytes.into_iter().rev().collect()
+    } else {
+        bytes
+    }
+}
+
+fn break_instruction(core: &mut Core<'_>) -> Result<Vec<u8>, Error> {
+    Ok(encode_break(core.instruction_set()?, core.endianness()?))
+}
+
+pub(crate) fn insert(
+    session: u64,
+    core_index: u32,
+    core: &mut Core<'_>,
+    address: u64,
+) -> Result<(), String> {
+    // The map is only locked around lookups, not across target accesses; calls on the same
+    // session are serialized by the session lock.
+    if is_set(session, core_index, address) {
+        return Ok(());
+    }
+
+    let patch = break_instruction(core).map_err(|e| format!("instruction set error: {}", e))?;
+    let mut original = vec![0u8; patch.len()];
+    core.read_8(address, &mut original)
+        .map_err(|e| format!("read instruction error: {}", e))?;
+    core.write_8(address, &patch)
+        .map_err(|e| format!("write breakpoint error: {}", e))?;
+
+    let mut readback = vec![0u8; patch.len()];
+    let armed = match core.read_8(address, &mut readback) {
+        Err(e) => Err(format!("read instruction error: {}", e)),
+        Ok(()) if readback != patch => Err(format!(
+            "address {:#010x} is not writable; software breakpoints require code in RAM",
+            address
+        )),
+        // RISC-V only enters debug mode on EBREAK when dcsr.ebreak* are set.
+        Ok(()) => core
+            .debug_on_sw_breakpoint(true)
+            .map_err(|e| format!("enable sw breakpoints error: {}", e)),
+    };
+    if let Err(e) = armed {
+        // Don't leave a break instruction behind that nothing knows about.
+        let _ = core.write_8(address, &original);
+        return Err(e);
+    }
+
+    sw_breakpoints()
+        .lock()
+        .unwrap()
+        .entry(session)
+        .or_default()
+        .insert((core_index, address), original);
+    Ok(())
+}
+
+pub(crate) fn remove(
+    session: u64,
+    core_index: u32,
+    core: &mut Core<'_>,
+    address: u64,
+) -> Result<(), String> {
+    let Some(original) = sw_breakpoints()
+        .lock()
+        .unwrap()
+        .get(&session)
+        .and_then(|m| m.get(&(core_index, address)).cloned())
+    else {
+        return Err(format!("no software breakpoint at {:#010x}", address));
+    };
+    core.write_8(address, &original)
+        .map_err(|e| format!("restore instruction error: {}", e))?;
+    if let Some(m) = sw_breakpoints().lock().unwrap().get_mut(&session) {
+        m.remove(&(core_index, address));
+    }
+    Ok(())
+}
+
+pub(crate) fn is_set(session: u64, core_index: u32, address: u64) -> bool {
+    sw_breakpoints()
+        .lock()
+        .unwrap()
+        .get(&session)
+        .is_some_and(|m| m.contains_key(&(core_index, address)))
+}
+
+/// Read code memory as the program sees it, i.e. with patched break instructions replaced
+/// by the saved original bytes.
+pub(crate) fn read_code(
+    session: u64,
+    core_index: u32,
+    core: &mut Core<'_>,
+    address: u64,
+    buf: &mut [u8],
+) -> Result<(), Error> {
+    core.read_8(address, buf)?;
+    let map = sw_breakpoints().lock().unwrap();
+    let Some(per_session) = map.get(&session) else {
+        return Ok(());
+    };
+    for ((c, bp_addr), original) in per_session {
+        if *c != core_index {
+            continue;
+        }
+        for (i, byte) in original.iter().enumerate() {
+            let a = bp_addr + i as u64;
+            if a >= address && a < address + buf.len() as u64 {
+                buf[(a - address) as usize] = *byte;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// If the halted core sits on one of our patched instructions, execute the original
+/// instruction with a single step and re-insert the breakpoint afterwards.
+///
+/// Returns `true` if a step was performed.
+pub(crate) fn step_over_patched(
+    session: u64,
+    core_index: u32,
+    core: &mut Core<'_>,
+) -> Result<bool, Error> {
+    let patched: HashMap<u64, Vec<u8>> = sw_breakpoints()
+        .lock()
+        .unwrap()
+        .get(&session)
+        .map(|m| {
+            m.iter()
+                .filter(|((c, _), _)| *c == core_index)
+                .map(|((_, address), bytes)| (*address, bytes.clone()))
+                .collect()
+        })
+        .unwrap_or_default();
+    if patched.is_empty() || !core.core_halted()? {
+        return Ok(false);
+    }
+    let pc: u64 = core
+        .read_core_reg(core.program_counter())
+        .unwrap_or(u64::MAX);
+    let Some(bytes) = patched.get(&pc) else {
+        return Ok(false);
+    };
+
+    let patch = break_instruction(core)?;
+    core.write_8(pc, bytes)?;
+    // Writing the PC marks it as user-modified, so the driver does not skip over the
+    // (now restored) instruction as if it were still a break instruction.
+    core.write_core_reg(core.program_counter(), pc)?;
+    let step = core.step();
+    core.write_8(pc, &patch)?;
+    step.map(|_| true)
+}
+
+/// Restore every patched instruction of a session, e.g. before it is closed.
+pub(crate) fn restore_all(session: u64, target: &mut probe_rs::Session) -> Result<(), String> {
+    let patches = sw_breakpoints()
+        .lock()
+        .unwrap()
+        .remove(&session)
+        .unwrap_or_default();
+    let mut first_err = None;
+    for ((core_index, address), original) in patches {
+        let res = target
+            .core(core_index as usize)
+            .and_then(|mut core| core.write_8(address, &original));
+        if let Err(e) = res {
+            first_err.get_or_insert(format!(
+                "restore instruction at {:#010x} error: {}",
+                address, e
+            ));
+        }
+    }
+    first_err.map_or(Ok(()), Err)
+}
+
+/// Set a software breakpoint by patching a break instruction (BKPT/EBREAK/BREAK) at `address`.
+///
+/// The original instruction is saved and restored by `pr_clear_sw_breakpoint`. Resuming or
+/// stepping from a software breakpoint executes the original instruction transparently.
+///
+/// Returns 0 on success, -1 on invalid handle/core, -2 if the target memory could not be
+/// patched (e.g. the address is in flash).
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_set_sw_breakpoint(session: u64, core_index: u32, address: u64) -> i32 {
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    match lock.core(core_index as usize) {
+        Ok(mut core) => match insert(session, core_index, &mut core, address) {
+            Ok(()) => 0,
+            Err(e) => {
+                set_error(format!("set sw bp error: {}", e));
+                -2
+            }
+        },
+        Err(e) => {
+            set_error(format!("core access error: {}", e));
+            -1
+        }
+    }
+}
+
+/// Clear a software breakpoint and restore the original instruction.
+///
+/// Returns 0 on success, -1 on invalid handle/core, -2 if no breakpoint was set at `address`
+/// or the instruction could not be restored.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_clear_sw_breakpoint(session: u64, core_index: u32, address: u64) -> i32 {
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    match lock.core(core_index as usize) {
+        Ok(mut core) => match remove(session, core_index, &mut core, address) {
+            Ok(()) => 0,
+            Err(e) => {
+                set_error(format!("clear sw bp error: {}", e));
+                -2
+            }
+        },
+        Err(e) => {
+            set_error(format!("core access error: {}", e));
+            -1
+        }
+    }
+}
+
+/// Clear all software breakpoints of a session on every core.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_clear_all_sw_breakpoints(session: u64) -> i32 {
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    match restore_all(session, &mut lock) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(format!("clear all sw bp error: {}", e));
+            -2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn break_encodings() {
+        assert_eq!(
+            encode_break(InstructionSet::Thumb2, Endian::Little),
+            [0x00, 0xBE]
+        );
+        assert_eq!(
+            encode_break(InstructionSet::Thumb2, Endian::Big),
+            [0xBE, 0x00]
+        );
+        assert_eq!(
+            encode_break(InstructionSet::RV32, Endian::Little),
+            [0x73, 0x00, 0x10, 0x00]
+        );
+        assert_eq!(
+            encode_break(InstructionSet::RV32C, Endian::Little),
+            [0x02, 0x90]
+        );
+        assert_eq!(
+            encode_break(InstructionSet::A64, Endian::Big),
+            [0x00, 0x00, 0x20, 0xD4]
+        );
+    }
+}
@@ -0,0 +1,103 @@
+//! The page buffering of the flash algorithm: probe-rs double buffers when the algorithm RAM
+//! holds two page buffers, transferring the next page while the algorithm programs the last.
+//! Some targets only program reliably with one buffer, or with smaller pages.
+
+use crate::{registry, set_error};
+use probe_rs::config::{Target, TargetSelector};
+use probe_rs::flashing::DownloadOptions;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+
+/// Smallest page size accepted, as for the algorithm RAM override.
+const MIN_PAGE_SIZE: u32 = 64;
+
+static SINGLE_BUFFER: AtomicBool = AtomicBool::new(false);
+
+/// Largest page programmed at once, 0 for the algorithm's page size.
+static MAX_PAGE_SIZE: AtomicU32 = AtomicU32::new(0);
+
+/// Apply the buffering of `pr_flash_option_buffering` to flash download options.
+pub(crate) fn apply_to(opts: &mut DownloadOptions<'_>) {
+    opts.disable_double_buffering = SINGLE_BUFFER.load(Ordering::Relaxed);
+}
+
+/// Halve the page size of the algorithms of `target` down to `max` bytes; halving keeps the
+/// pages aligned with the algorithm's own.
+fn limit_pages(target: &mut Target, max: u32) {
+    for algo in &mut target.flash_algorithms {
+        let page_size = &mut algo.flash_properties.page_size;
+        while *page_size > max && *page_size / 2 >= MIN_PAGE_SIZE && *page_size % 8 == 0 {
+            *page_size /= 2;
+        }
+    }
+}
+
+/// `target` with the page size of `pr_flash_option_buffering` applied, if one is set.
+pub(crate) fn apply(target: TargetSelector) -> Result<TargetSelector, String> {
+    let max = MAX_PAGE_SIZE.load(Ordering::Relaxed);
+    if max == 0 {
+        return Ok(target);
+    }
+    let mut target = match target {
+        TargetSelector::Unspecified(name) => registry()
+            .get_target_by_name(&name)
+            .map_err(|e| format!("attach error: {}", e))?,
+        TargetSelector::Specified(target) => target,
+        TargetSelector::Auto => return Ok(TargetSelector::Auto),
+    };
+    limit_pages(&mut target, max);
+    Ok(TargetSelector::Specified(target))
+}
+
+/// Set how later flashing calls (`pr_flash_*`, `pr_session_flash`, `pr_gang_flash`, GDB loads)
+/// buffer pages: `double_buffering` 0 transfers a page only after the previous one is
+/// programmed, for targets that fail with double buffering; 1 double buffers when the algorithm
+/// RAM holds two pages (default). `max_page_size` makes the sessions opened afterwards program
+/// pages of at most that many bytes (halving the algorithm's page, down to 64 bytes), 0 the
+/// algorithm's page size.
+///
+/// Returns 0 on success, -1 on an invalid mode or a page size below 64 or not a multiple of 4.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_option_buffering(double_buffering: i32, max_page_size: u32) -> i32 {
+    if !(0..=1).contains(&double_buffering) {
+        set_error(format!(
+            "invalid double buffering mode {}",
+            double_buffering
+        ));
+        return -1;
+    }
+    if max_page_size != 0 && (max_page_size < MIN_PAGE_SIZE || !max_page_size.is_multiple_of(4)) {
+        set_error(format!("invalid page size {}", max_page_size));
+        return -1;
+    }
+    SINGLE_BUFFER.store(double_buffering == 0, Ordering::Relaxed);
+    MAX_PAGE_SIZE.store(max_page_size, Ordering::Relaxed);
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_are_halved_to_the_limit() {
+        let mut target = registry().get_target_by_name("STM32F407VGTx").unwrap();
+        let page_size = target.flash_algorithms[0].flash_properties.page_size;
+        limit_pages(&mut target, page_size);
+        assert_eq!(
+            target.flash_algorithms[0].flash_properties.page_size,
+            page_size
+        );
+        limit_pages(&mut target, 300);
+        assert_eq!(target.flash_algorithms[0].flash_properties.page_size, 256);
+        limit_pages(&mut target, 4);
+        assert_eq!(target.flash_algorithms[0].flash_properties.page_size, 64);
+    }
+
+    #[test]
+    fn invalid_options_are_rejected() {
+        assert_eq!(pr_flash_option_buffering(2, 0), -1);
+        assert_eq!(pr_flash_option_buffering(1, 32), -1);
+        assert_eq!(pr_flash_option_buffering(1, 66), -1);
+        assert_eq!(pr_flash_option_buffering(1, 0), 0);
+    }
+}
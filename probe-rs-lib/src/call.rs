@@ -0,0 +1,187 @@
+//! Calling routines that are already in target memory (e.g. vendor OTP or calibration helpers)
+//! from the host, like the flash loader calls flash algorithm functions.
+
+use crate::{get_session, set_error, timeouts};
+use probe_rs::{Core, CoreRegister, CoreType, Error, MemoryInterface};
+use std::time::Duration;
+
+/// `BKPT` twice, as one little-endian word.
+const ARM_BKPT: u32 = 0xBE00_BE00;
+/// `EBREAK`.
+const RISCV_EBREAK: u32 = 0x0010_0073;
+
+enum CallError {
+    Target(Error),
+    Unsupported(String),
+    Timeout,
+}
+
+impl From<Error> for CallError {
+    fn from(e: Error) -> Self {
+        CallError::Target(e)
+    }
+}
+
+/// The instruction the routine returns to, and whether the return address needs the Thumb bit.
+fn trampoline(core_type: CoreType) -> Option<(u32, bool)> {
+    match core_type {
+        CoreType::Armv6m | CoreType::Armv7m | CoreType::Armv7em | CoreType::Armv8m => {
+            Some((ARM_BKPT, true))
+        }
+        CoreType::Riscv => Some((RISCV_EBREAK, false)),
+        // A-profile cores need HLT or a mode-dependent BKPT, Xtensa a windowed call frame.
+        _ => None,
+    }
+}
+
+/// Stack pointer for the call: the trampoline is placed in the 16 bytes below the caller's
+/// stack pointer, which keeps the stack aligned for both ARM (8) and RISC-V (16).
+fn call_sp(sp: u64) -> u64 {
+    (sp - 16) & !0xF
+}
+
+fn call(
+    core: &mut Core<'_>,
+    address: u64,
+    args: [u64; 4],
+    timeout: Duration,
+) -> Result<u64, CallError> {
+    let Some((instruction, thumb)) = trampoline(core.core_type()) else {
+        return Err(CallError::Unsupported(format!(
+            "calling functions is not supported on {:?} cores",
+            core.core_type()
+        )));
+    };
+    let was_running = !core.core_halted()?;
+    if was_running {
+        core.halt(timeouts::halt_timeout())?;
+    }
+
+    // Save every register that can be read, to restore the interrupted context afterwards.
+    let registers: Vec<&'static CoreRegister> = core.registers().core_registers().collect();
+    let saved: Vec<(&CoreRegister, u64)> = registers
+        .into_iter()
+        .filter_map(|r| core.read_core_reg::<u64>(r).ok().map(|v| (r, v)))
+        .collect();
+    let sp: u64 = core.read_core_reg(core.stack_pointer())?;
+    let frame = call_sp(sp);
+    let mut saved_stack = [0u32; 4];
+    core.read_32(frame, &mut saved_stack)?;
+
+    let result = (|| {
+        core.write_32(frame, &[instruction; 4])?;
+        let regs = core.registers();
+        for (i, arg) in args.iter().enumerate() {
+            core.write_core_reg(regs.argument_register(i), *arg)?;
+        }
+        core.write_core_reg(core.stack_pointer(), frame)?;
+        core.write_core_reg(core.return_address(), frame | thumb as u64)?;
+        let pc = if thumb { address & !1 } else { address };
+        core.write_core_reg(core.program_counter(), pc)?;
+        core.run()?;
+
+        if core.wait_for_core_halted(timeout).is_err() {
+            core.halt(timeouts::halt_timeout())?;
+            return Err(CallError::Timeout);
+        }
+        let pc: u64 = core.read_core_reg(core.program_counter())?;
+        if !(frame..frame + 16).contains(&pc) {
+            return Err(CallError::Unsupported(format!(
+                "routine stopped at {:#x} before returning",
+                pc
+            )));
+        }
+        Ok(core.read_core_reg::<u64>(core.registers().result_register(0))?)
+    })();
+
+    // Restore the stack and the registers even if the call failed.
+    core.write_32(frame, &saved_stack)?;
+    for (register, value) in saved {
+        // Some registers (e.g. read-only status registers) cannot be written back.
+        let _ = core.write_core_reg(register, value);
+    }
+    if was_running {
+        core.run()?;
+    }
+    result
+}
+
+/// Call the routine at `address` on a core with up to four arguments (`args` may be NULL for
+/// none), following the calling convention of the core (r0-r3 on ARM, a0-a3 on RISC-V).
+///
+/// The routine runs on the current stack and returns to a breakpoint instruction placed just
+/// below the stack pointer. Afterwards the stack contents and all core registers are restored;
+/// a core that was running is resumed. The routine's return value is written to `out_return`
+/// (may be NULL).
+///
+/// Supported on Cortex-M and RISC-V cores. Returns 0 on success, -1 on invalid handle/core,
+/// -2 on target error, unsupported core, or if the routine stopped elsewhere, -3 if it did not
+/// return within `timeout_ms` (it is halted and the state restored).
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_call_function(
+    session: u64,
+    core_index: u32,
+    address: u64,
+    args: *const u64,
+    timeout_ms: u32,
+    out_return: *mut u64,
+) -> i32 {
+    let mut call_args = [0u64; 4];
+    if !args.is_null() {
+        unsafe { std::ptr::copy_nonoverlapping(args, call_args.as_mut_ptr(), 4) };
+    }
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let mut core = match lock.core(core_index as usize) {
+        Ok(core) => core,
+        Err(e) => {
+            set_error(format!("core access error: {}", e));
+            return -1;
+        }
+    };
+    let timeout = Duration::from_millis(timeout_ms as u64);
+    match call(&mut core, address, call_args, timeout) {
+        Ok(value) => {
+            if !out_return.is_null() {
+                unsafe { *out_return = value };
+            }
+            0
+        }
+        Err(CallError::Target(e)) => {
+            set_error(format!("call function error: {}", e));
+            -2
+        }
+        Err(CallError::Unsupported(e)) => {
+            set_error(format!("call function error: {}", e));
+            -2
+        }
+        Err(CallError::Timeout) => {
+            set_error(format!(
+                "call function error: routine did not return within {} ms",
+                timeout_ms
+            ));
+            -3
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn trampolines_per_core_type() {
+        assert_eq!(trampoline(CoreType::Armv7em), Some((ARM_BKPT, true)));
+        assert_eq!(trampoline(CoreType::Riscv), Some((RISCV_EBREAK, false)));
+        assert_eq!(trampoline(CoreType::Xtensa), None);
+    }
+
+    #[test]
+    fn call_frame_is_below_and_aligned() {
+        assert_eq!(call_sp(0x2000_5000), 0x2000_4FF0);
+        assert_eq!(call_sp(0x2000_4FFC), 0x2000_4FE0);
+    }
+}
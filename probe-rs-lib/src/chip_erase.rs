@@ -0,0 +1,153 @@
+//! Estimated progress of chip erases: a mass erase reports nothing until it has finished, so
+//! progress is derived from the typical erase time of the target meanwhile.
+
+use crate::{PROGRESS_BYTES, PROGRESS_INDETERMINATE};
+use probe_rs::config::Target;
+use std::cell::Cell;
+use std::ffi::{CString, c_char};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Typical mass erase times from the datasheets, by target name prefix; the first match wins.
+/// The STM32F2/F4/F7 times are those of 1 MiB at x32 parallelism, the probe-rs algorithms'.
+const ERASE_TIMES_MS: &[(&str, u64)] = &[
+    ("STM32F0", 40),
+    ("STM32F1", 40),
+    ("STM32F2", 8000),
+    ("STM32F3", 40),
+    ("STM32F4", 8000),
+    ("STM32F7", 8000),
+    ("STM32G0", 25),
+    ("STM32G4", 25),
+    ("STM32L4", 25),
+    ("nRF52", 200),
+];
+
+/// Time between estimated reports.
+const INTERVAL: Duration = Duration::from_millis(250);
+
+/// Estimates stop short of the end, which only the erase finishing reports.
+const MAX_PERCENT: f32 = 99.0;
+
+/// Status text of the reports of a chip erase.
+pub(crate) const STATUS: &str = "erasing chip";
+
+thread_local! {
+    /// Typical chip erase time of the target being flashed on this thread.
+    static EXPECTED: Cell<Option<Duration>> = const { Cell::new(None) };
+}
+
+fn erase_time(name: &str) -> Option<Duration> {
+    let name = name.to_ascii_lowercase();
+    ERASE_TIMES_MS
+        .iter()
+        .find(|(prefix, _)| name.starts_with(&prefix.to_ascii_lowercase()))
+        .map(|(_, ms)| Duration::from_millis(*ms))
+}
+
+/// Base the estimates of the chip erases on this thread on the erase time of `target`.
+pub(crate) fn expect(target: &Target) {
+    EXPECTED.set(erase_time(&target.name));
+}
+
+/// Typical erase time of the target of the flash operation running on this thread, if known.
+pub(crate) fn expected() -> Option<Duration> {
+    EXPECTED.get()
+}
+
+/// Percent and ETA after `elapsed` of an erase taking `estimate`; the ETA is unknown once the
+/// estimate is exceeded.
+fn estimate_at(elapsed: Duration, estimate: Duration) -> (f32, i32) {
+    let percent = (elapsed.as_secs_f64() / estimate.as_secs_f64().max(1e-3) * 100.0) as f32;
+    let eta_ms = match estimate.checked_sub(elapsed) {
+        Some(left) if !left.is_zero() => left.as_millis().min(i32::MAX as u128) as i32,
+        _ => -1,
+    };
+    (percent.min(MAX_PERCENT), eta_ms)
+}
+
+/// Reports estimated erase progress to a progress callback from a helper thread until dropped.
+pub(crate) struct Ticker {
+    stop: mpsc::Sender<()>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Ticker {
+    pub(crate) fn start<F>(cb: Arc<Mutex<F>>, estimate: Duration) -> Self
+    where
+        F: FnMut(i32, f32, *const c_char, i32) + Send + 'static,
+    {
+        let (stop, stopped) = mpsc::channel();
+        let started = Instant::now();
+        let thread = std::thread::spawn(move || {
+            let status = CString::new(STATUS).unwrap();
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(INTERVAL) {
+                let (percent, eta_ms) = estimate_at(started.elapsed(), estimate);
+                PROGRESS_BYTES.set(Some((0, None)));
+                PROGRESS_INDETERMINATE.set(true);
+                let mut cb = cb.lock().unwrap();
+                (*cb)(1, percent, status.as_ptr(), eta_ms);
+            }
+        });
+        Ticker {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Drop for Ticker {
+    /// Stop reporting; returns once the last estimated report has been delivered.
+    fn drop(&mut self) {
+        let _ = self.stop.send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn erase_times_and_estimates() {
+        assert_eq!(
+            erase_time("STM32F407VGTx"),
+            Some(Duration::from_millis(8000))
+        );
+        assert_eq!(
+            erase_time("nrf52840_xxAA"),
+            Some(Duration::from_millis(200))
+        );
+        assert_eq!(erase_time("RP2040"), None);
+
+        let estimate = Duration::from_secs(8);
+        assert_eq!(estimate_at(Duration::from_secs(2), estimate), (25.0, 6000));
+        assert_eq!(estimate_at(Duration::from_secs(9), estimate), (99.0, -1));
+    }
+
+    #[test]
+    fn ticker_reports_until_stopped() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let seen = reports.clone();
+        let cb = Arc::new(Mutex::new(move |op, percent, _: *const c_char, eta_ms| {
+            let indeterminate = PROGRESS_INDETERMINATE.get();
+            seen.lock()
+                .unwrap()
+                .push((op, percent, eta_ms, indeterminate));
+        }));
+        let ticker = Ticker::start(cb, Duration::from_secs(10));
+        std::thread::sleep(INTERVAL * 3);
+        drop(ticker);
+        let count = reports.lock().unwrap().len();
+        assert!(count >= 1);
+        let (op, percent, eta_ms, indeterminate) = reports.lock().unwrap()[0];
+        assert!(op == 1 && percent > 0.0 && percent < 10.0 && eta_ms > 9000 && indeterminate);
+
+        std::thread::sleep(INTERVAL * 2);
+        assert_eq!(reports.lock().unwrap().len(), count);
+    }
+}
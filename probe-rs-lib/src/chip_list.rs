@@ -0,0 +1,148 @@
+//! The chip database as filtered, paged JSON, so chip pickers can fill search-as-you-type
+//! lists with one call instead of `pr_chip_model_name` per chip.
+
+use crate::image::optional_str;
+use crate::{chip_db, set_error, write_c_str};
+use serde::Serialize;
+use std::ffi::c_char;
+
+#[derive(Serialize)]
+struct ChipEntry<'a> {
+    name: &'a str,
+    family: Option<&'a str>,
+    manufacturer: &'a str,
+    manufacturer_index: usize,
+    chip_index: usize,
+}
+
+#[derive(Serialize)]
+struct ChipPage<'a> {
+    /// Matching chips before paging.
+    total: usize,
+    offset: usize,
+    chips: Vec<ChipEntry<'a>>,
+}
+
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack.to_lowercase().contains(needle)
+}
+
+fn chip_page(
+    manufacturer: Option<&str>,
+    family: Option<&str>,
+    offset: usize,
+    limit: usize,
+) -> ChipPage<'static> {
+    let db = chip_db();
+    let manufacturer = manufacturer
+        .filter(|f| !f.is_empty())
+        .map(str::to_lowercase);
+    let family = family.filter(|f| !f.is_empty()).map(str::to_lowercase);
+    let matching = db
+        .manufacturers
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| {
+            manufacturer
+                .as_deref()
+                .is_none_or(|f| contains_ignore_case(&m.name, f))
+        })
+        .flat_map(|(mi, m)| {
+            m.chips.iter().enumerate().map(move |(ci, name)| ChipEntry {
+                name,
+                family: db.family_of.get(name).map(String::as_str),
+                manufacturer: &m.name,
+                manufacturer_index: mi,
+                chip_index: ci,
+            })
+        })
+        .filter(|c| {
+            family.as_deref().is_none_or(|f| {
+                contains_ignore_case(c.name, f)
+                    || c.family.is_some_and(|n| contains_ignore_case(n, f))
+            })
+        });
+    let mut total = 0;
+    let mut chips = Vec::new();
+    for (i, chip) in matching.enumerate() {
+        total += 1;
+        if i >= offset && (limit == 0 || chips.len() < limit) {
+            chips.push(chip);
+        }
+    }
+    ChipPage {
+        total,
+        offset,
+        chips,
+    }
+}
+
+/// List chips as `{"total", "offset", "chips": [{"name", "family", "manufacturer",
+/// "manufacturer_index", "chip_index"}]}`, in `pr_chip_model_name` order.
+///
+/// `manufacturer_filter` matches manufacturer names and `family_filter` family or chip names,
+/// both as case-insensitive substrings; NULL or empty matches everything. `total` counts all
+/// matches; `chips` holds at most `limit` of them (0 = all) starting at `offset`.
+///
+/// Returns the required size including NUL, or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_chip_list_json(
+    manufacturer_filter: *const c_char,
+    family_filter: *const c_char,
+    offset: u32,
+    limit: u32,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let filters =
+        optional_str(manufacturer_filter).and_then(|m| Ok((m, optional_str(family_filter)?)));
+    let (manufacturer, family) = match filters {
+        Ok(f) => f,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let page = chip_page(
+        manufacturer.as_deref(),
+        family.as_deref(),
+        offset as usize,
+        limit as usize,
+    );
+    match serde_json::to_string(&page) {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e.to_string());
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pages_and_filters() {
+        let all = chip_page(None, None, 0, 0);
+        let count: usize = chip_db().manufacturers.iter().map(|m| m.chips.len()).sum();
+        assert_eq!(all.total, count);
+        assert_eq!(all.chips.len(), count);
+
+        let stm = chip_page(Some("stmicro"), Some("stm32f4"), 0, 0);
+        assert!(stm.total > 0);
+        assert!(
+            stm.chips
+                .iter()
+                .all(|c| c.name.to_lowercase().contains("stm32f4")
+                    || c.family.unwrap().to_lowercase().contains("stm32f4"))
+        );
+
+        let page = chip_page(Some("stmicro"), Some("stm32f4"), 2, 3);
+        assert_eq!(page.total, stm.total);
+        assert_eq!(page.chips.len(), 3);
+        assert_eq!(page.chips[0].name, stm.chips[2].name);
+
+        assert_eq!(chip_page(Some("no such vendor"), None, 0, 0).total, 0);
+    }
+}
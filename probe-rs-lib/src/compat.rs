@@ -0,0 +1,232 @@
+//! Checking a chip picked by the user against what was detected on the target, before
+//! flashing the wrong variant of a family.
+
+use crate::{cstr_to_string, registry, set_error, write_c_str};
+use probe_rs_target::{Chip, ChipFamily, MemoryRegion, NvmRegion};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::ffi::c_char;
+
+/// What was read from the target; every field is optional.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct Detected {
+    /// Chip name found by auto-detection.
+    chip: Option<String>,
+    /// JEP106 code of the manufacturer, e.g. from the ROM table.
+    manufacturer: Option<Jep106>,
+    /// Part number, e.g. from the ROM table.
+    part: Option<u16>,
+    /// Flash size in bytes, e.g. from a flash size register.
+    flash_size: Option<u64>,
+}
+
+#[derive(Deserialize, Clone, Copy, PartialEq)]
+struct Jep106 {
+    cc: u8,
+    id: u8,
+}
+
+#[derive(Serialize)]
+struct Mismatch {
+    field: &'static str,
+    expected: Value,
+    detected: Value,
+    message: String,
+}
+
+#[derive(Serialize)]
+struct Report {
+    compatible: bool,
+    requested: String,
+    mismatches: Vec<Mismatch>,
+}
+
+/// The family and chip of a target name, including package variants.
+fn find_chip(name: &str) -> Option<(&'static ChipFamily, &'static Chip)> {
+    registry().families().iter().find_map(|family| {
+        family
+            .variants
+            .iter()
+            .find(|chip| {
+                chip.name.eq_ignore_ascii_case(name)
+                    || chip
+                        .package_variants
+                        .iter()
+                        .any(|p| p.eq_ignore_ascii_case(name))
+            })
+            .map(|chip| (family, chip))
+    })
+}
+
+/// Size of the main flash: the boot flash and the `BANK_<n>` regions of dual-bank devices, or
+/// all flash if there are none; not alias, UICR or OTP regions.
+fn flash_size(chip: &Chip) -> u64 {
+    let nvm: Vec<&NvmRegion> = chip
+        .memory_map
+        .iter()
+        .filter_map(|r| match r {
+            MemoryRegion::Nvm(r) if !r.is_alias => Some(r),
+            _ => None,
+        })
+        .collect();
+    let is_main = |r: &NvmRegion| {
+        r.is_boot_memory() || r.name.as_deref().is_some_and(|n| n.starts_with("BANK_"))
+    };
+    let any_main = nvm.iter().any(|r| is_main(r));
+    nvm.iter()
+        .filter(|r| !any_main || is_main(r))
+        .map(|r| r.range.end - r.range.start)
+        .sum()
+}
+
+fn check(requested: &str, detected: &Detected) -> Result<Report, String> {
+    let (family, chip) =
+        find_chip(requested).ok_or_else(|| format!("unknown chip {}", requested))?;
+    let mut mismatches = Vec::new();
+    let expected_flash = flash_size(chip);
+    let mut detected_flash = detected.flash_size;
+
+    if let Some(name) = &detected.chip {
+        match find_chip(name) {
+            Some((_, found)) if std::ptr::eq(found, chip) => {}
+            Some((found_family, found)) => {
+                let message = if std::ptr::eq(found_family, family) {
+                    format!(
+                        "wrong variant: {} of the {} is connected",
+                        name, family.name
+                    )
+                } else {
+                    format!(
+                        "different chip: {} of the {} is connected",
+                        name, found_family.name
+                    )
+                };
+                mismatches.push(Mismatch {
+                    field: "chip",
+                    expected: json!(requested),
+                    detected: json!(name),
+                    message,
+                });
+                detected_flash = detected_flash.or(Some(flash_size(found)));
+            }
+            None => mismatches.push(Mismatch {
+                field: "chip",
+                expected: json!(requested),
+                detected: json!(name),
+                message: format!("detected chip {} is not in the database", name),
+            }),
+        }
+    }
+    if let (Some(found), Some(code)) = (detected.manufacturer, family.manufacturer)
+        && (found.cc, found.id) != (code.cc, code.id)
+    {
+        let name = jep106::JEP106Code::new(found.cc, found.id)
+            .get()
+            .unwrap_or("unknown manufacturer");
+        mismatches.push(Mismatch {
+            field: "manufacturer",
+            expected: json!({ "cc": code.cc, "id": code.id }),
+            detected: json!({ "cc": found.cc, "id": found.id }),
+            message: format!(
+                "{} is made by {}, the target by {}",
+                requested,
+                code.get().unwrap_or("unknown manufacturer"),
+                name
+            ),
+        });
+    }
+    if let (Some(found), Some(part)) = (detected.part, chip.part)
+        && found != part
+    {
+        mismatches.push(Mismatch {
+            field: "part",
+            expected: json!(part),
+            detected: json!(found),
+            message: format!("part number {:#x} instead of {:#x}", found, part),
+        });
+    }
+    if let Some(found) = detected_flash
+        && found != expected_flash
+    {
+        mismatches.push(Mismatch {
+            field: "flash_size",
+            expected: json!(expected_flash),
+            detected: json!(found),
+            message: format!(
+                "{} KiB of flash instead of {} KiB",
+                found / 1024,
+                expected_flash / 1024
+            ),
+        });
+    }
+    Ok(Report {
+        compatible: mismatches.is_empty(),
+        requested: chip.name.clone(),
+        mismatches,
+    })
+}
+
+/// Compare the chip a user selected with what was detected on the target.
+///
+/// `detected_json` is `{"chip", "manufacturer": {"cc", "id"}, "part", "flash_size"}` with any
+/// fields left out: the auto-detected chip name, the JEP106 code and part number (e.g. from
+/// the ROM table) and the size of the main flash in bytes (boot and `BANK_<n>` regions, no
+/// UICR or OTP areas). A detected chip of the database also counts as
+/// its flash size. The report is `{"compatible", "requested", "mismatches": [{"field",
+/// "expected", "detected", "message"}]}`, where `field` is "chip", "manufacturer", "part" or
+/// "flash_size".
+///
+/// Returns the required size including NUL, or 0 on error (unknown requested chip, invalid
+/// JSON); see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_chip_is_compatible(
+    requested_name: *const c_char,
+    detected_json: *const c_char,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let report = cstr_to_string(requested_name).and_then(|requested| {
+        let detected: Detected = serde_json::from_str(&cstr_to_string(detected_json)?)
+            .map_err(|e| format!("invalid detected JSON: {}", e))?;
+        check(&requested, &detected)
+    });
+    match report.and_then(|r| serde_json::to_string(&r).map_err(|e| e.to_string())) {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e);
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detected(json: &str) -> Detected {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn variants_and_flash_sizes() {
+        let same = check("nrf52832_xxaa", &detected(r#"{"chip": "nRF52832_xxAA"}"#)).unwrap();
+        assert!(same.compatible);
+        assert_eq!(same.requested, "nRF52832_xxAA");
+
+        let variant = check("nRF52832_xxAA", &detected(r#"{"chip": "nRF52832_xxAB"}"#)).unwrap();
+        let fields: Vec<&str> = variant.mismatches.iter().map(|m| m.field).collect();
+        assert_eq!(fields, ["chip", "flash_size"]);
+        assert!(variant.mismatches[0].message.starts_with("wrong variant"));
+
+        let vendor = check(
+            "nRF52832_xxAA",
+            &detected(r#"{"manufacturer": {"cc": 0, "id": 32}, "flash_size": 524288}"#),
+        )
+        .unwrap();
+        let fields: Vec<&str> = vendor.mismatches.iter().map(|m| m.field).collect();
+        assert_eq!(fields, ["manufacturer"]);
+
+        assert!(check("no such chip", &Detected::default()).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+//! Compressed page transfer: flash algorithms declaring the miniz transfer encoding (the ESP
+//! flash loaders, like esptool's stub) take zlib compressed pages, which cuts the USB traffic
+//! that dominates flashing time on full-speed probes. probe-rs compresses for such algorithms
+//! on its own; where a region has several algorithms, the default one need not be one of them.
+
+use crate::{registry, set_error};
+use probe_rs::config::{Target, TargetSelector};
+use probe_rs_target::{MemoryRange, RawFlashAlgorithm, TransferEncoding};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static PREFER: AtomicBool = AtomicBool::new(false);
+
+/// Whether `algo` takes compressed pages.
+pub(crate) fn is_compressed(algo: &RawFlashAlgorithm) -> bool {
+    algo.transfer_encoding == Some(TransferEncoding::Miniz)
+}
+
+/// Make an algorithm taking compressed pages the default of the flash it covers, where the
+/// default one takes raw pages.
+fn prefer_compressed(target: &mut Target) {
+    for i in 0..target.flash_algorithms.len() {
+        let algo = &target.flash_algorithms[i];
+        if !is_compressed(algo) {
+            continue;
+        }
+        let range = algo.flash_properties.address_range.clone();
+        let sharing =
+            |a: &RawFlashAlgorithm| a.flash_properties.address_range.intersects_range(&range);
+        let covered = target
+            .flash_algorithms
+            .iter()
+            .any(|a| sharing(a) && a.default && is_compressed(a));
+        if covered {
+            continue;
+        }
+        let name = target.flash_algorithms[i].name.clone();
+        for algo in target.flash_algorithms.iter_mut().filter(|a| sharing(a)) {
+            algo.default = algo.name == name;
+        }
+        tracing::info!("flash algorithm {} sends compressed pages", name);
+    }
+}
+
+/// `target` with the algorithms taking compressed pages as defaults if
+/// `pr_flash_option_compression` asks for them.
+pub(crate) fn apply(target: TargetSelector) -> Result<TargetSelector, String> {
+    if !PREFER.load(Ordering::Relaxed) {
+        return Ok(target);
+    }
+    let mut target = match target {
+        TargetSelector::Unspecified(name) => registry()
+            .get_target_by_name(&name)
+            .map_err(|e| format!("attach error: {}", e))?,
+        TargetSelector::Specified(target) => target,
+        TargetSelector::Auto => return Ok(TargetSelector::Auto),
+    };
+    prefer_compressed(&mut target);
+    Ok(TargetSelector::Specified(target))
+}
+
+/// Choose the flash algorithms of the sessions opened afterwards (and `pr_flash_*`,
+/// `pr_gang_flash`) by their page transfer: 0 = the chip's default algorithms (default), 1 =
+/// prefer an algorithm that takes compressed pages wherever the flash has one, so less data
+/// goes over USB. Which algorithms do is the `compressed` list of `pr_flash_sector_layout`.
+///
+/// Returns 0 on success, -1 on an unknown mode.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_option_compression(mode: i32) -> i32 {
+    match mode {
+        0 | 1 => {
+            PREFER.store(mode == 1, Ordering::Relaxed);
+            0
+        }
+        _ => {
+            set_error(format!("invalid compression mode {}", mode));
+            -1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_algorithm_becomes_default() {
+        let mut target = registry().get_target_by_name("STM32F407VGTx").unwrap();
+        let mut compressed = target.flash_algorithms[0].clone();
+        compressed.name = "compressed".to_string();
+        compressed.default = false;
+        compressed.transfer_encoding = Some(TransferEncoding::Miniz);
+        target.flash_algorithms[0].default = true;
+        target.flash_algorithms.push(compressed);
+
+        prefer_compressed(&mut target);
+        let defaults: Vec<&str> = target
+            .flash_algorithms
+            .iter()
+            .filter(|a| a.default)
+            .map(|a| a.name.as_str())
+            .collect();
+        assert_eq!(defaults, vec!["compressed"]);
+
+        let mut plain = registry().get_target_by_name("STM32F407VGTx").unwrap();
+        let before: Vec<bool> = plain.flash_algorithms.iter().map(|a| a.default).collect();
+        prefer_compressed(&mut plain);
+        let after: Vec<bool> = plain.flash_algorithms.iter().map(|a| a.default).collect();
+        assert_eq!(before, after);
+        assert_eq!(pr_flash_option_compression(2), -1);
+    }
+}
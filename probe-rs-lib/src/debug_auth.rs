@@ -0,0 +1,318 @@
+//! Debug authentication: on chips whose debug access is closed until the debugger answers a
+//! challenge, the challenge is passed to a host callback and its response sent back before
+//! attaching, so the credentials can stay in an HSM or signing service.
+//!
+//! Implemented for the LPC55Sxx debug mailbox (DM-AP): the callback receives the debug
+//! authentication challenge (DAC) of `DBG_AUTH_START` and returns the debug authentication
+//! response (DAR). STM32H5 debug authentication is not supported yet; attaching to those
+//! chips with a callback set fails instead of going ahead unauthenticated.
+
+use probe_rs::architecture::arm::dp::DpAddress;
+use probe_rs::architecture::arm::sequences::DefaultArmSequence;
+use probe_rs::architecture::arm::{ArmDebugInterface, FullyQualifiedApAddress};
+use probe_rs::config::TargetSelector;
+use probe_rs::probe::Probe;
+use std::ffi::{CString, c_char, c_void};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// `(user_data, chip, challenge, challenge_len, response, response_cap, out_response_len)`;
+/// returns 0 to send the response, anything else to give up.
+type DebugAuthCb = unsafe extern "C" fn(
+    *mut c_void,
+    *const c_char,
+    *const u8,
+    usize,
+    *mut u8,
+    usize,
+    *mut usize,
+) -> i32;
+
+/// Largest response accepted from the callback (a DAR with a 4096 bit RSA certificate).
+const MAX_RESPONSE: usize = 4096;
+
+// LPC55 debug mailbox AP and its registers.
+const LPC55_DM_AP: u8 = 2;
+const DM_CSW: u64 = 0x00;
+const DM_REQUEST: u64 = 0x04;
+const DM_RETURN: u64 = 0x08;
+/// `RESYNCH_REQ | CHIP_RESET_REQ`: restart the mailbox with a chip reset.
+const DM_CSW_RESYNC: u32 = 0x21;
+const DM_ACK_TOKEN: u32 = 0xA5A5;
+const DM_START_DEBUG_MAILBOX: u32 = 0x01;
+const DM_EXIT_DEBUG_MAILBOX: u32 = 0x04;
+const DM_DBG_AUTH_START: u32 = 0x10;
+const DM_DBG_AUTH_RESP: u32 = 0x11;
+/// `DbgStatus` of the core AHB-AP CSW: debug access is open.
+const AHB_CSW_DBG_STATUS: u32 = 0x40;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Family {
+    /// LPC55Sxx: debug mailbox commands on AP 2.
+    Lpc55,
+    /// STM32H5: debug authentication is not supported yet.
+    Stm32H5,
+}
+
+impl Family {
+    fn for_target(name: &str) -> Option<Self> {
+        let name = name.to_ascii_uppercase();
+        if name.starts_with("LPC55S") {
+            Some(Family::Lpc55)
+        } else if name.starts_with("STM32H5") {
+            Some(Family::Stm32H5)
+        } else {
+            None
+        }
+    }
+}
+
+/// The callback and the caller's context passed to it.
+#[derive(Clone, Copy)]
+struct Callback {
+    cb: DebugAuthCb,
+    user_data: *mut c_void,
+}
+
+unsafe impl Send for Callback {}
+
+static CALLBACK: OnceLock<Mutex<Option<Callback>>> = OnceLock::new();
+
+fn callback_lock() -> &'static Mutex<Option<Callback>> {
+    CALLBACK.get_or_init(|| Mutex::new(None))
+}
+
+/// Whether a callback is set, so attaching must open the probe itself.
+pub(crate) fn enabled() -> bool {
+    callback_lock().lock().unwrap().is_some()
+}
+
+/// Ask the callback for the response to `challenge`.
+fn respond(chip: &str, challenge: &[u8]) -> Result<Vec<u8>, String> {
+    // The callback may take long (an HSM round-trip) or set a new one, so it runs unlocked.
+    let Some(callback) = *callback_lock().lock().unwrap() else {
+        return Err("no debug authentication callback".to_string());
+    };
+    let chip = CString::new(chip).map_err(|e| e.to_string())?;
+    let mut response = vec![0u8; MAX_RESPONSE];
+    let mut len = 0usize;
+    let code = unsafe {
+        (callback.cb)(
+            callback.user_data,
+            chip.as_ptr(),
+            challenge.as_ptr(),
+            challenge.len(),
+            response.as_mut_ptr(),
+            response.len(),
+            &mut len,
+        )
+    };
+    if code != 0 {
+        return Err(format!("debug authentication callback failed ({})", code));
+    }
+    if len > MAX_RESPONSE {
+        return Err(format!("debug authentication response of {} bytes", len));
+    }
+    response.truncate(len);
+    Ok(response)
+}
+
+/// Debug mailbox register read, retried while the ROM is busy and the AP answers with errors.
+fn dm_read(
+    interface: &mut dyn ArmDebugInterface,
+    ap: &FullyQualifiedApAddress,
+) -> Result<u32, String> {
+    let start = Instant::now();
+    loop {
+        match interface.read_raw_ap_register(ap, DM_RETURN) {
+            Ok(value) => return Ok(value),
+            Err(e) if start.elapsed() > Duration::from_secs(1) => {
+                return Err(format!("debug mailbox read error: {}", e));
+            }
+            Err(_) => std::thread::sleep(Duration::from_millis(1)),
+        }
+    }
+}
+
+fn dm_write(
+    interface: &mut dyn ArmDebugInterface,
+    ap: &FullyQualifiedApAddress,
+    value: u32,
+) -> Result<(), String> {
+    interface
+        .write_raw_ap_register(ap, DM_REQUEST, value)
+        .map_err(|e| format!("debug mailbox write error: {}", e))
+}
+
+/// Run debug mailbox command `id` with `params`, returning the words of its response.
+fn dm_command(
+    interface: &mut dyn ArmDebugInterface,
+    ap: &FullyQualifiedApAddress,
+    id: u32,
+    params: &[u32],
+) -> Result<Vec<u32>, String> {
+    dm_write(interface, ap, ((params.len() as u32) << 16) | id)?;
+    for (i, word) in params.iter().enumerate() {
+        let ack = dm_read(interface, ap)?;
+        if ack & 0xffff != DM_ACK_TOKEN || (ack >> 16) as usize != i + 1 {
+            return Err(format!(
+                "debug mailbox command {:#x}: unexpected acknowledge {:#010x}",
+                id, ack
+            ));
+        }
+        dm_write(interface, ap, *word)?;
+    }
+    let header = dm_read(interface, ap)?;
+    let status = header & 0xffff;
+    if status != 0 {
+        return Err(format!(
+            "debug mailbox command {:#x} failed with status {:#x}",
+            id, status
+        ));
+    }
+    let count = (header >> 16) & 0x7fff;
+    let mut words = Vec::with_capacity(count as usize);
+    for i in 0..count {
+        dm_write(interface, ap, (i << 16) | DM_ACK_TOKEN)?;
+        words.push(dm_read(interface, ap)?);
+    }
+    Ok(words)
+}
+
+/// The words of `bytes`, which must be whole words.
+fn to_words(bytes: &[u8]) -> Result<Vec<u32>, String> {
+    if !bytes.len().is_multiple_of(4) {
+        return Err(format!(
+            "debug authentication response of {} bytes is not whole words",
+            bytes.len()
+        ));
+    }
+    Ok(bytes
+        .chunks_exact(4)
+        .map(|w| u32::from_le_bytes([w[0], w[1], w[2], w[3]]))
+        .collect())
+}
+
+/// Authenticate through the LPC55 debug mailbox if the core AP reports closed debug access;
+/// returns whether it did.
+fn lpc55_authenticate(interface: &mut dyn ArmDebugInterface, chip: &str) -> Result<bool, String> {
+    let dp = DpAddress::Default;
+    let core_ap = FullyQualifiedApAddress::v1_with_dp(dp, 0);
+    let open = interface
+        .read_raw_ap_register(&core_ap, 0x00)
+        .is_ok_and(|csw| csw & AHB_CSW_DBG_STATUS != 0);
+    if open {
+        return Ok(false);
+    }
+    tracing::info!("{}: debug access is closed, authenticating", chip);
+    let dm = FullyQualifiedApAddress::v1_with_dp(dp, LPC55_DM_AP);
+    interface
+        .write_raw_ap_register(&dm, DM_CSW, DM_CSW_RESYNC)
+        .map_err(|e| format!("debug mailbox error: {}", e))?;
+    std::thread::sleep(Duration::from_millis(30));
+    let _ = interface.read_raw_ap_register(&dm, DM_CSW);
+
+    dm_command(interface, &dm, DM_START_DEBUG_MAILBOX, &[])?;
+    let challenge: Vec<u8> = dm_command(interface, &dm, DM_DBG_AUTH_START, &[])?
+        .iter()
+        .flat_map(|w| w.to_le_bytes())
+        .collect();
+    let response = to_words(&respond(chip, &challenge)?)?;
+    dm_command(interface, &dm, DM_DBG_AUTH_RESP, &response)
+        .map_err(|e| format!("debug authentication rejected: {}", e))?;
+    dm_command(interface, &dm, DM_EXIT_DEBUG_MAILBOX, &[])?;
+    Ok(true)
+}
+
+/// Run the debug authentication of `target` on `probe` before attaching to it, if a callback
+/// is set and the chip requires one; the probe is returned detached.
+pub(crate) fn authenticate(mut probe: Probe, target: &TargetSelector) -> Result<Probe, String> {
+    let chip = match target {
+        TargetSelector::Unspecified(name) => name.clone(),
+        TargetSelector::Specified(target) => target.name.clone(),
+        TargetSelector::Auto => return Ok(probe),
+    };
+    let Some(family) = Family::for_target(&chip) else {
+        return Ok(probe);
+    };
+    if !enabled() || !probe.has_arm_debug_interface() {
+        return Ok(probe);
+    }
+    if family == Family::Stm32H5 {
+        // The caller expects authentication; attaching without it would hide that.
+        return Err(format!(
+            "{}: STM32H5 debug authentication is not supported",
+            chip
+        ));
+    }
+    probe
+        .attach_to_unspecified()
+        .map_err(|e| format!("attach error: {}", e))?;
+    let mut interface = probe
+        .try_into_arm_debug_interface(DefaultArmSequence::create())
+        .map_err(|(_, e)| format!("attach error: {}", e))?;
+    let result = lpc55_authenticate(&mut *interface, &chip);
+    let mut probe = interface.close();
+    let _ = probe.detach();
+    if result? {
+        tracing::info!("{}: debug authentication succeeded", chip);
+    }
+    Ok(probe)
+}
+
+/// Set the callback answering debug authentication challenges, or remove it with NULL.
+///
+/// Before attaching to a chip that supports debug authentication (LPC55Sxx debug mailbox),
+/// the library checks whether debug access is closed; if so it calls `cb(user_data, chip,
+/// challenge, challenge_len, response, response_cap, out_response_len)` with the chip's
+/// challenge (the LPC55 DAC) and sends the `*out_response_len` bytes written to `response` (the
+/// LPC55 DAR, whole words, at most `response_cap` = 4096 bytes) back. The callback returns 0 to
+/// send the response, anything else to fail the attach. It runs on the thread attaching.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_set_debug_auth_callback(cb: Option<DebugAuthCb>, user_data: *mut c_void) {
+    *callback_lock().lock().unwrap() = cb.map(|cb| Callback { cb, user_data });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    unsafe extern "C" fn answer(
+        user_data: *mut c_void,
+        _chip: *const c_char,
+        challenge: *const u8,
+        challenge_len: usize,
+        response: *mut u8,
+        _response_cap: usize,
+        out_len: *mut usize,
+    ) -> i32 {
+        let challenge = unsafe { std::slice::from_raw_parts(challenge, challenge_len) };
+        let response = unsafe { std::slice::from_raw_parts_mut(response, challenge_len) };
+        for (r, c) in response.iter_mut().zip(challenge) {
+            *r = !c;
+        }
+        unsafe { *out_len = challenge_len };
+        user_data as i32
+    }
+
+    #[test]
+    fn callback_answers_challenge() {
+        assert_eq!(Family::for_target("LPC55S69JBD100"), Some(Family::Lpc55));
+        assert_eq!(Family::for_target("LPC5526"), None);
+        assert_eq!(Family::for_target("STM32H573IIKx"), Some(Family::Stm32H5));
+
+        pr_set_debug_auth_callback(Some(answer), std::ptr::null_mut());
+        assert!(enabled());
+        assert_eq!(
+            respond("LPC55S69", &[1, 2, 3, 4]).unwrap(),
+            vec![!1, !2, !3, !4]
+        );
+        assert_eq!(to_words(&[1, 0, 0, 0, 2, 0, 0, 0]).unwrap(), vec![1, 2]);
+        assert!(to_words(&[1, 2, 3]).is_err());
+
+        pr_set_debug_auth_callback(Some(answer), 5 as *mut c_void);
+        assert!(respond("LPC55S69", &[1]).is_err());
+        pr_set_debug_auth_callback(None, std::ptr::null_mut());
+        assert!(!enabled());
+    }
+}
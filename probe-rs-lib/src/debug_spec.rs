@@ -0,0 +1,139 @@
+//! Debug metadata of a target for the chip spec JSON, so host tools can set up RTT, reset and
+//! the wire protocol from one `pr_chip_specs_by_name` call.
+
+use probe_rs::Architecture;
+use probe_rs::config::{DebugSequence, Target};
+use probe_rs::rtt::ScanRegion;
+use probe_rs_target::{CoreAccessOptions, MemoryRegion};
+use serde_json::{Value, json};
+use std::ops::Range;
+
+fn ranges(ranges: impl Iterator<Item = Range<u64>>) -> Value {
+    ranges
+        .map(|r| json!({ "start": r.start, "end": r.end }))
+        .collect()
+}
+
+/// Where RTT control blocks are searched for: all RAM (the listed ranges), the listed ranges
+/// only, or one address.
+fn rtt_scan(target: &Target) -> Value {
+    match &target.rtt_scan_regions {
+        ScanRegion::Ram => json!({
+            "mode": "ram",
+            "ranges": ranges(target.memory_map.iter().filter_map(|r| match r {
+                MemoryRegion::Ram(r) => Some(r.range.clone()),
+                _ => None,
+            })),
+        }),
+        ScanRegion::Ranges(list) => {
+            json!({ "mode": "ranges", "ranges": ranges(list.iter().cloned()) })
+        }
+        ScanRegion::Exact(address) => json!({ "mode": "exact", "address": address }),
+    }
+}
+
+/// The debug sequence type name, e.g. `DefaultArmSequence` or `Nrf52`.
+fn sequence_name(sequence: &DebugSequence) -> String {
+    let text = match sequence {
+        DebugSequence::Arm(s) => format!("{:?}", s),
+        DebugSequence::Riscv(s) => format!("{:?}", s),
+        DebugSequence::Xtensa(s) => format!("{:?}", s),
+    };
+    text.chars()
+        .take_while(|c| c.is_alphanumeric() || *c == '_')
+        .collect()
+}
+
+/// How a system reset is done: SYSRESETREQ for ARM targets without a vendor sequence,
+/// otherwise whatever the named debug sequence does.
+fn reset(target: &Target) -> Value {
+    let sequence = sequence_name(&target.debug_sequence);
+    let method = if sequence == "DefaultArmSequence" {
+        "sysresetreq"
+    } else {
+        "debug_sequence"
+    };
+    json!({ "method": method, "debug_sequence": sequence })
+}
+
+/// Wire protocols; JTAG on ARM targets is only known to work if a scan chain is described.
+fn protocols(target: &Target) -> Value {
+    match target.architecture() {
+        Architecture::Arm => json!({
+            "swd": true,
+            "jtag": target.jtag.as_ref().and_then(|j| j.scan_chain.as_ref()).map(|_| true),
+        }),
+        Architecture::Riscv | Architecture::Xtensa => json!({ "swd": false, "jtag": true }),
+    }
+}
+
+fn core_details(target: &Target) -> Value {
+    target
+        .cores
+        .iter()
+        .map(|core| {
+            let mut entry = json!({
+                "name": core.name,
+                "type": format!("{:?}", core.core_type),
+            });
+            let access = match &core.core_access_options {
+                CoreAccessOptions::Arm(o) => json!({
+                    "ap": o.ap,
+                    "debug_base": o.debug_base,
+                    "cti_base": o.cti_base,
+                    "jtag_tap": o.jtag_tap,
+                }),
+                CoreAccessOptions::Riscv(o) => {
+                    json!({ "hart_id": o.hart_id, "jtag_tap": o.jtag_tap })
+                }
+                CoreAccessOptions::Xtensa(o) => json!({ "jtag_tap": o.jtag_tap }),
+            };
+            if let (Value::Object(entry), Value::Object(access)) = (&mut entry, access) {
+                entry.extend(access);
+            }
+            entry
+        })
+        .collect()
+}
+
+/// Start of the memory the chip boots from, where the vector table (initial stack pointer and
+/// entry point of ARM images) is expected.
+fn boot_address(target: &Target) -> Option<u64> {
+    target.memory_map.iter().find_map(|r| match r {
+        MemoryRegion::Nvm(n) if n.is_boot_memory() => Some(n.range.start),
+        _ => None,
+    })
+}
+
+/// The debug fields of the spec JSON, in order.
+pub(crate) fn spec_fields(target: &Target) -> Vec<(&'static str, Value)> {
+    vec![
+        ("rtt_scan", rtt_scan(target)),
+        ("reset", reset(target)),
+        ("protocols", protocols(target)),
+        ("core_details", core_details(target)),
+        ("boot_address", json!(boot_address(target))),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nrf52_debug_fields() {
+        let target = crate::registry()
+            .get_target_by_name("nRF52840_xxAA")
+            .unwrap();
+        let fields: serde_json::Map<String, Value> = spec_fields(&target)
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect();
+        assert_eq!(fields["rtt_scan"]["mode"], "ram");
+        assert!(!fields["rtt_scan"]["ranges"].as_array().unwrap().is_empty());
+        assert_eq!(fields["reset"]["method"], "debug_sequence");
+        assert_eq!(fields["protocols"]["swd"], true);
+        assert_eq!(fields["core_details"][0]["ap"], json!({ "v1": 0 }));
+        assert_eq!(fields["boot_address"], 0);
+    }
+}
@@ -0,0 +1,104 @@
+//! Decoding the defmt frames of an RTT up channel into log lines, with the tables of the
+//! firmware's ELF file.
+
+use defmt_decoder::log::format::{Formatter, FormatterConfig, FormatterFormat};
+use defmt_decoder::{DecodeError, Locations, StreamDecoder, Table};
+use std::fmt::Write;
+
+/// The defmt tables of one firmware image.
+pub(crate) struct Defmt {
+    table: Table,
+    /// Source locations of the log statements, if the ELF has complete debug info.
+    locs: Option<Locations>,
+}
+
+impl Defmt {
+    pub(crate) fn from_elf(path: &str) -> Result<Defmt, String> {
+        let elf = std::fs::read(path).map_err(|e| format!("read {} error: {}", path, e))?;
+        let table = Table::parse(&elf)
+            .map_err(|e| format!("defmt data error: {}", e))?
+            .ok_or_else(|| format!("no defmt data in {}", path))?;
+        let locs = table
+            .get_locations(&elf)
+            .ok()
+            .filter(|locs| table.indices().all(|i| locs.contains_key(&(i as u64))));
+        Ok(Defmt { table, locs })
+    }
+
+    pub(crate) fn decoder(&self) -> Decoder<'_> {
+        let format = FormatterFormat::OneLine {
+            with_location: self.locs.is_some(),
+        };
+        Decoder {
+            defmt: self,
+            stream: self.table.new_stream_decoder(),
+            formatter: Formatter::new(FormatterConfig {
+                format,
+                is_timestamp_available: self.table.has_timestamp(),
+            }),
+        }
+    }
+}
+
+/// Stream decoder of a channel; frames may be split across reads.
+pub(crate) struct Decoder<'a> {
+    defmt: &'a Defmt,
+    stream: Box<dyn StreamDecoder + 'a>,
+    formatter: Formatter,
+}
+
+impl Decoder<'_> {
+    /// Feed bytes read from the channel and append the frames completed by them to `out`, one
+    /// line each. Fails if the data is malformed and the encoding cannot resynchronize; the
+    /// lines decoded before are kept.
+    pub(crate) fn decode(&mut self, data: &[u8], out: &mut String) -> Result<(), String> {
+        self.stream.received(data);
+        loop {
+            match self.stream.decode() {
+                Ok(frame) => {
+                    let loc = self
+                        .defmt
+                        .locs
+                        .as_ref()
+                        .and_then(|locs| locs.get(&frame.index()));
+                    let file = loc.map(|l| l.file.display().to_string());
+                    let line = loc.and_then(|l| u32::try_from(l.line).ok());
+                    let module = loc.map(|l| l.module.as_str());
+                    let text = self
+                        .formatter
+                        .format_frame(frame, file.as_deref(), line, module);
+                    let _ = writeln!(out, "{}", text);
+                }
+                Err(DecodeError::UnexpectedEof) => return Ok(()),
+                Err(DecodeError::Malformed) if self.defmt.table.encoding().can_recover() => {}
+                Err(DecodeError::Malformed) => {
+                    return Err("malformed defmt data, output may be lost".to_string());
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_elf(name: &str) -> String {
+        format!(
+            "{}/../probe-rs-debug/tests/debug-unwind-tests/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        )
+    }
+
+    #[test]
+    fn tables_are_read_from_the_elf() {
+        let defmt = Defmt::from_elf(&test_elf("esp32s3_esp_hal_panic.elf")).unwrap();
+        let mut out = String::new();
+        defmt.decoder().decode(&[], &mut out).unwrap();
+        assert!(out.is_empty());
+
+        let err = Defmt::from_elf(&test_elf("RP2040_full_unwind.elf")).err();
+        assert!(err.unwrap().starts_with("no defmt data"));
+    }
+}
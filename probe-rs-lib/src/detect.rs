@@ -0,0 +1,272 @@
+//! Identifying an unknown target: the debug port and ROM table information read over the ARM
+//! debug interface, probe-rs' autodetection, and the registry targets that match them.
+
+use crate::reset::{self, open_probe};
+use crate::{cstr_to_string, protocol_from_int, registry, set_error, write_c_str};
+use jep106::JEP106Code;
+use probe_rs::Permissions;
+use probe_rs::architecture::arm::dp::{DPIDR, DebugPortId, DpAccess, DpAddress};
+use probe_rs::architecture::arm::memory::Component;
+use probe_rs::architecture::arm::sequences::DefaultArmSequence;
+use probe_rs::config::{Registry, TargetSelector};
+use probe_rs::probe::Probe;
+use serde::Serialize;
+use std::ffi::c_char;
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Jep106 {
+    cc: u8,
+    id: u8,
+    /// Manufacturer name, if the code is known.
+    name: Option<String>,
+}
+
+impl From<JEP106Code> for Jep106 {
+    fn from(code: JEP106Code) -> Self {
+        Jep106 {
+            cc: code.cc,
+            id: code.id,
+            name: code.get().map(str::to_string),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct DebugPort {
+    dpidr: u32,
+    version: String,
+    part: u8,
+    revision: u8,
+    designer: Jep106,
+}
+
+#[derive(Serialize, Debug)]
+struct RomTable {
+    /// Access port the ROM table was found through, and its base address.
+    access_port: String,
+    address: u64,
+    manufacturer: Jep106,
+    part: u16,
+}
+
+#[derive(Serialize, Debug)]
+struct Core {
+    name: String,
+    #[serde(rename = "type")]
+    core_type: String,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct Detection {
+    protocol: Option<String>,
+    debug_port: Option<DebugPort>,
+    rom_table: Option<RomTable>,
+    /// Target identified by probe-rs' autodetection, which also asks the vendor specific
+    /// identification registers; null if it found none.
+    target: Option<String>,
+    architecture: Option<String>,
+    cores: Vec<Core>,
+    /// Registry targets matching the detection, best first: the identified target, then those
+    /// whose manufacturer and part number match the ROM table.
+    candidates: Vec<String>,
+}
+
+/// `detected`, then the registry targets of the ROM table's manufacturer and part number.
+fn candidates(
+    registry: &Registry,
+    rom: Option<(JEP106Code, u16)>,
+    detected: Option<&str>,
+) -> Vec<String> {
+    let mut names: Vec<String> = detected.map(str::to_string).into_iter().collect();
+    let Some((manufacturer, part)) = rom else {
+        return names;
+    };
+    for family in registry
+        .families()
+        .iter()
+        .filter(|f| f.manufacturer == Some(manufacturer))
+    {
+        for chip in family.variants.iter().filter(|c| c.part == Some(part)) {
+            if !names.contains(&chip.name) {
+                names.push(chip.name.clone());
+            }
+        }
+    }
+    names
+}
+
+/// Read the debug port and the first class 1 ROM table that names a manufacturer. Errors only
+/// mean that no ARM debug interface answered.
+fn read_arm(probe: Probe, found: &mut Detection) -> Probe {
+    if !probe.has_arm_debug_interface() {
+        return probe;
+    }
+    let mut interface = match probe.try_into_arm_debug_interface(DefaultArmSequence::create()) {
+        Ok(interface) => interface,
+        Err((probe, e)) => {
+            tracing::debug!("no ARM debug interface: {}", e);
+            return probe;
+        }
+    };
+    let dp = DpAddress::Default;
+    if let Err(e) = interface.select_debug_port(dp) {
+        tracing::debug!("no ARM debug port: {}", e);
+        return interface.close();
+    }
+    if let Ok(dpidr) = interface.read_dp_register::<DPIDR>(dp) {
+        let raw = u32::from(dpidr.clone());
+        let id = DebugPortId::from(dpidr);
+        found.debug_port = Some(DebugPort {
+            dpidr: raw,
+            version: format!("{:?}", id.version),
+            part: id.part_no,
+            revision: id.revision,
+            designer: id.designer.into(),
+        });
+    }
+    let access_ports = interface.access_ports(dp).unwrap_or_default();
+    for ap in &access_ports {
+        let Ok(mut memory) = interface.memory_interface(ap) else {
+            continue;
+        };
+        let Ok(address) = memory.base_address() else {
+            continue;
+        };
+        if let Ok(Component::Class1RomTable(id, _)) = Component::try_parse(&mut *memory, address)
+            && let Some(manufacturer) = id.peripheral_id().jep106()
+        {
+            found.rom_table = Some(RomTable {
+                access_port: format!("{:?}", ap.ap()),
+                address,
+                manufacturer: manufacturer.into(),
+                part: id.peripheral_id().part(),
+            });
+            break;
+        }
+    }
+    interface.close()
+}
+
+fn detect(
+    selector: Option<&str>,
+    speed_khz: Option<u32>,
+    protocol: i32,
+) -> Result<Detection, String> {
+    let open = || -> Result<Probe, String> {
+        let mut probe = open_probe(selector)?;
+        if let Some(p) = protocol_from_int(protocol) {
+            probe
+                .select_protocol(p)
+                .map_err(|e| format!("select protocol error: {}", e))?;
+        }
+        if let Some(speed) = speed_khz {
+            probe
+                .set_speed(speed)
+                .map_err(|e| format!("set speed error: {}", e))?;
+        }
+        Ok(probe)
+    };
+
+    let mut found = Detection::default();
+    let mut probe = open()?;
+    probe
+        .attach_to_unspecified()
+        .map_err(|e| format!("attach error: {}", e))?;
+    found.protocol = probe.protocol().map(|p| p.to_string());
+    drop(read_arm(probe, &mut found));
+
+    // A fresh probe for the session: the raw accesses above leave the debug port configured
+    // for the default sequence.
+    match reset::attach(open()?, TargetSelector::Auto, Permissions::new()) {
+        Ok(session) => {
+            let target = session.target();
+            found.target = Some(target.name.clone());
+            found.architecture = Some(format!("{:?}", target.architecture()));
+            found.cores = target
+                .cores
+                .iter()
+                .map(|c| Core {
+                    name: c.name.clone(),
+                    core_type: format!("{:?}", c.core_type),
+                })
+                .collect();
+        }
+        Err(e) => tracing::debug!("autodetection failed: {}", e),
+    }
+    let rom = found.rom_table.as_ref().map(|r| {
+        (
+            JEP106Code::new(r.manufacturer.cc, r.manufacturer.id),
+            r.part,
+        )
+    });
+    found.candidates = candidates(registry(), rom, found.target.as_deref());
+    Ok(found)
+}
+
+/// Identify the target connected to a probe without naming the chip. `selector` is
+/// `VID:PID[:SN]`, or NULL for the first probe of the programmer type in effect; `speed_khz`
+/// 0 keeps the probe's default and `protocol_code` is 0 auto, 1 SWD, 2 JTAG.
+///
+/// The result is JSON: `{"protocol", "debug_port", "rom_table", "target", "architecture",
+/// "cores", "candidates"}`. `debug_port` (`{"dpidr", "version", "part", "revision",
+/// "designer"}`) and `rom_table` (`{"access_port", "address", "manufacturer", "part"}`) are
+/// read over the ARM debug interface and null on other architectures; JEP106 codes are
+/// `{"cc", "id", "name"}`. `target` is what probe-rs' autodetection identified, with its
+/// `architecture` and `cores`; `candidates` lists that target and the registry targets whose
+/// manufacturer and part number match the ROM table.
+///
+/// Returns the required size including NUL, or 0 if the probe cannot be opened or does not
+/// connect; see `pr_last_error()`. An unidentified target is not an error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_target_detect(
+    selector: *const c_char,
+    speed_khz: u32,
+    protocol_code: i32,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let selector = if selector.is_null() {
+        None
+    } else {
+        match cstr_to_string(selector) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                set_error(e);
+                return 0;
+            }
+        }
+    };
+    let speed = Some(speed_khz).filter(|s| *s != 0);
+    let json = detect(selector.as_deref(), speed, protocol_code)
+        .and_then(|found| serde_json::to_string(&found).map_err(|e| e.to_string()));
+    match json {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e);
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rom_table_part_selects_candidates() {
+        let (family, chip) = registry()
+            .families()
+            .iter()
+            .find_map(|f| {
+                let chip = f.variants.iter().find(|c| c.part.is_some())?;
+                Some((f, chip))
+            })
+            .expect("a target with a part number");
+        let rom = Some((family.manufacturer.unwrap(), chip.part.unwrap()));
+        let names = candidates(registry(), rom, None);
+        assert!(names.contains(&chip.name));
+        let names = candidates(registry(), rom, Some("detected"));
+        assert_eq!(names[0], "detected");
+        assert_eq!(candidates(registry(), None, None), Vec::<String>::new());
+    }
+}
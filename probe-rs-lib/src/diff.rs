@@ -0,0 +1,201 @@
+//! Differential flashing: of a new image, only the flash sectors whose contents differ from a
+//! previous image are programmed. The sectors are found from the two files, without reading the
+//! target back, which is slow for a full image over a debug probe.
+
+use crate::api::{self, FlashError};
+use crate::image::Segment;
+use crate::job::clip;
+use crate::{
+    after_flash_for, apply_after_flash, commit_loader, cstr_to_string, detect_format_from_path,
+    download_options, error_chain, layout, loader_segments, patched_loader, protocol_from_int,
+    set_error, timeouts, timing, verify,
+};
+use probe_rs::Session;
+use probe_rs::config::Target;
+use probe_rs::flashing::{DownloadOptions, Format};
+use std::ffi::c_char;
+use std::ops::Range;
+
+/// The bytes of `segments` in `range`, None where there is no data.
+fn contents(segments: &[Segment], range: &Range<u64>) -> Vec<Option<u8>> {
+    let mut bytes = vec![None; (range.end - range.start) as usize];
+    for part in clip(segments, range) {
+        let start = (part.address - range.start) as usize;
+        for (byte, value) in bytes[start..].iter_mut().zip(part.data) {
+            *byte = Some(value);
+        }
+    }
+    bytes
+}
+
+/// The sectors holding flash data of `new` whose contents differ from `old`. Sectors only `old`
+/// has data in are left alone, as flashing `new` without a chip erase would.
+fn changed_sectors(target: &Target, old: &[Segment], new: &[Segment]) -> Vec<Range<u64>> {
+    let mut sectors: Vec<Range<u64>> = new
+        .iter()
+        .flat_map(|segment| layout::flash_sectors(target, &segment.range()))
+        .collect();
+    sectors.sort_by_key(|s| s.start);
+    sectors.dedup();
+    sectors.retain(|sector| contents(old, sector) != contents(new, sector));
+    sectors
+}
+
+/// Program the sectors of the `new` image that differ from the `old` one, and the data outside
+/// flash (RAM) of `new`; returns the number of sectors programmed.
+fn flash_changed(
+    session: &mut Session,
+    (old_path, old_format): (&str, Format),
+    (new_path, new_format): (&str, Format),
+    opts: DownloadOptions<'_>,
+) -> Result<u32, String> {
+    let (after, entry) = after_flash_for(new_path, &new_format)?;
+    // The flash patches apply to both, so patched bytes never count as changes.
+    let old = loader_segments(&patched_loader(session, old_path, old_format, &[])?);
+    let new = loader_segments(&patched_loader(session, new_path, new_format, &[])?);
+
+    let target = session.target();
+    let sectors = changed_sectors(target, &old, &new);
+    let parts: Vec<Segment> = sectors.iter().flat_map(|s| clip(&new, s)).collect();
+    let outside = new
+        .iter()
+        .filter(|segment| layout::flash_sectors(target, &segment.range()).is_empty());
+    let mut loader = target.flash_loader();
+    for segment in parts.iter().chain(outside) {
+        loader
+            .add_data(segment.address, &segment.data)
+            .map_err(|e| format!("flash error: {}", error_chain(&e)))?;
+    }
+    tracing::info!("{} flash sectors changed", sectors.len());
+    if loader.data().next().is_some() {
+        commit_loader(session, &mut loader, opts)?;
+    }
+    apply_after_flash(session, after, entry)?;
+    Ok(sectors.len() as u32)
+}
+
+/// Flash `new_path` as `pr_flash_auto` does without a chip erase, programming only the flash
+/// sectors whose contents differ from `old_path`, the image the target was last flashed with.
+/// The changed sectors are computed from the two files alone, nothing is read from the target:
+/// the target must hold `old_path` exactly. Both images use `base_address` and `skip` if they
+/// are BIN files. Data outside flash (RAM) is always written; flash patches, preserved ranges,
+/// the verify mode and the post-flash behavior apply.
+///
+/// `out_sectors` (may be NULL) receives the number of sectors programmed, 0 if the images have
+/// the same flash contents.
+///
+/// Returns 0 on success, 1 on invalid arguments or if no target could be attached, 2 on
+/// flashing error.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub extern "C" fn pr_flash_diff(
+    chip: *const c_char,
+    old_path: *const c_char,
+    new_path: *const c_char,
+    base_address: u64,
+    skip: u32,
+    verify: i32,
+    speed_khz: u32,
+    protocol_code: i32,
+    out_sectors: *mut u32,
+) -> i32 {
+    let args = (
+        cstr_to_string(chip),
+        cstr_to_string(old_path),
+        cstr_to_string(new_path),
+    );
+    let (Ok(chip), Ok(old_path), Ok(new_path)) = args else {
+        set_error("invalid chip or image path".to_string());
+        return 1;
+    };
+    let base = Some(base_address).filter(|b| *b != 0);
+    let formats = (
+        detect_format_from_path(&old_path, base, skip),
+        detect_format_from_path(&new_path, base, skip),
+    );
+    let (old_format, new_format) = match formats {
+        (Ok(old), Ok(new)) => (old, new),
+        (Err(e), _) | (_, Err(e)) => {
+            set_error(e);
+            return 1;
+        }
+    };
+    let speed = Some(speed_khz).filter(|s| *s != 0);
+    let result = api::attach_for_flash(&chip, speed, protocol_from_int(protocol_code)).and_then(
+        |(mut session, used_speed)| {
+            timeouts::apply_to_session(&mut session);
+            let mut opts = download_options(None, verify, 0, 0);
+            let timing = timing::instrument(&mut opts.progress);
+            let verify_mode = opts.verify.then(|| verify::mode().name());
+            let old = (old_path.as_str(), old_format);
+            let new = (new_path.as_str(), new_format);
+            let result = flash_changed(&mut session, old, new, opts);
+            timing::finish(
+                &timing,
+                result.is_ok(),
+                used_speed,
+                verify_mode,
+                session.target(),
+            );
+            result.map_err(FlashError::Flash)
+        },
+    );
+    match result {
+        Ok(sectors) => {
+            if !out_sectors.is_null() {
+                unsafe { *out_sectors = sectors };
+            }
+            0
+        }
+        Err(e) => {
+            let code = e.code();
+            set_error(e.message());
+            code
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry;
+
+    fn segment(address: u64, data: Vec<u8>) -> Segment {
+        Segment { address, data }
+    }
+
+    #[test]
+    fn only_differing_sectors_change() {
+        let target = registry().get_target_by_name("STM32F407VGTx").unwrap();
+        let old = [segment(0x0800_0000, vec![0x11; 0x1_0000])];
+        // The second 16 KiB sector has a changed byte, the fourth other data around a gap.
+        let mut data = vec![0x11; 0x1_0000];
+        data[0x4000] = 0x22;
+        data.truncate(0xc000);
+        let new = [
+            segment(0x0800_0000, data),
+            segment(0x0800_c000, vec![0x11; 0x2000]),
+            segment(0x0800_f000, vec![0x33; 4]),
+        ];
+        assert_eq!(
+            changed_sectors(&target, &old, &new),
+            vec![0x0800_4000..0x0800_8000, 0x0800_c000..0x0801_0000]
+        );
+        // Split differently, the same bytes are no change.
+        let split = [
+            segment(0x0800_0000, vec![0x11; 0x6000]),
+            segment(0x0800_6000, vec![0x11; 0xa000]),
+        ];
+        assert!(changed_sectors(&target, &old, &split).is_empty());
+        assert!(changed_sectors(&target, &new, &[]).is_empty());
+    }
+
+    #[test]
+    fn contents_mark_missing_bytes() {
+        let image = [segment(0x100, vec![1, 2]), segment(0x104, vec![3])];
+        assert_eq!(
+            contents(&image, &(0x100..0x106)),
+            vec![Some(1), Some(2), None, None, Some(3), None]
+        );
+    }
+}
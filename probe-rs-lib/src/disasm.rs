@@ -0,0 +1,224 @@
+//! Disassembly of target memory for the instruction set the core is currently executing.
+
+use crate::{breakpoint, get_session, set_error, write_c_str};
+use capstone::arch::arm::ArchMode as ArmMode;
+use capstone::arch::arm64::ArchMode as Arm64Mode;
+use capstone::arch::riscv::ArchMode as RiscvMode;
+use capstone::{Capstone, Endian, prelude::*};
+use probe_rs::{Core, CoreType, InstructionSet};
+use serde::Serialize;
+use std::ffi::c_char;
+
+/// Upper bound for a single request, to keep the memory read bounded.
+const MAX_INSTRUCTIONS: u32 = 4096;
+
+#[derive(Serialize)]
+struct Instruction {
+    address: u64,
+    bytes: String,
+    mnemonic: String,
+    operands: String,
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn capstone_for(isa: InstructionSet, core_type: CoreType) -> Result<Capstone, String> {
+    let cs = match isa {
+        InstructionSet::Thumb2 => {
+            let mut builder = Capstone::new()
+                .arm()
+                .mode(ArmMode::Thumb)
+                .endian(Endian::Little);
+            if core_type == CoreType::Armv8m {
+                builder =
+                    builder.extra_mode(std::iter::once(capstone::arch::arm::ArchExtraMode::V8));
+            }
+            builder.build()
+        }
+        InstructionSet::A32 => Capstone::new()
+            .arm()
+            .mode(ArmMode::Arm)
+            .endian(Endian::Little)
+            .build(),
+        InstructionSet::A64 => Capstone::new()
+            .arm64()
+            .mode(Arm64Mode::Arm)
+            .endian(Endian::Little)
+            .build(),
+        InstructionSet::RV32 => Capstone::new()
+            .riscv()
+            .mode(RiscvMode::RiscV32)
+            .endian(Endian::Little)
+            .build(),
+        InstructionSet::RV32C => Capstone::new()
+            .riscv()
+            .mode(RiscvMode::RiscV32)
+            .endian(Endian::Little)
+            .extra_mode(std::iter::once(
+                capstone::arch::riscv::ArchExtraMode::RiscVC,
+            ))
+            .build(),
+        InstructionSet::Xtensa => return Err("no disassembler for Xtensa".to_string()),
+    };
+    let mut cs = cs.map_err(|e| format!("disassembler init error: {}", e))?;
+    // Render undecodable words as data instead of stopping at them.
+    let _ = cs.set_skipdata(true);
+    Ok(cs)
+}
+
+fn disassemble_bytes(
+    isa: InstructionSet,
+    core_type: CoreType,
+    code: &[u8],
+    address: u64,
+    count: usize,
+) -> Result<Vec<Instruction>, String> {
+    if isa == InstructionSet::Xtensa {
+        return Ok(xtensa_raw(code, address, count));
+    }
+    let cs = capstone_for(isa, core_type)?;
+    let insns = cs
+        .disasm_count(code, address, count)
+        .map_err(|e| format!("disassembly error: {}", e))?;
+    Ok(insns
+        .iter()
+        .map(|i| Instruction {
+            address: i.address(),
+            bytes: hex(i.bytes()),
+            mnemonic: i.mnemonic().unwrap_or("").to_string(),
+            operands: i.op_str().unwrap_or("").to_string(),
+        })
+        .collect())
+}
+
+/// Capstone has no Xtensa backend; split the stream into narrow (2 byte) and wide (3 byte)
+/// instructions by their `op0` field so the view still lines up with the real instructions.
+fn xtensa_raw(code: &[u8], address: u64, count: usize) -> Vec<Instruction> {
+    let mut out = Vec::new();
+    let mut offset = 0;
+    while out.len() < count && offset < code.len() {
+        let len = if code[offset] & 0x08 != 0 { 2 } else { 3 };
+        let Some(bytes) = code.get(offset..offset + len) else {
+            break;
+        };
+        out.push(Instruction {
+            address: address + offset as u64,
+            bytes: hex(bytes),
+            mnemonic: ".byte".to_string(),
+            operands: bytes
+                .iter()
+                .map(|b| format!("{:#04x}", b))
+                .collect::<Vec<_>>()
+                .join(", "),
+        });
+        offset += len;
+    }
+    out
+}
+
+fn disassemble(
+    session: u64,
+    core_index: u32,
+    core: &mut Core<'_>,
+    address: u64,
+    count: u32,
+) -> Result<Vec<Instruction>, String> {
+    let isa = core
+        .instruction_set()
+        .map_err(|e| format!("instruction set error: {}", e))?;
+    // Every supported instruction set has instructions of at most 4 bytes.
+    let mut code = vec![0u8; count as usize * 4];
+    breakpoint::read_code(session, core_index, core, address, &mut code)
+        .map_err(|e| format!("read memory error: {}", e))?;
+    disassemble_bytes(isa, core.core_type(), &code, address, count as usize)
+}
+
+/// Disassemble `count` instructions starting at `address`, decoded for the instruction set
+/// the core is currently executing (e.g. Thumb on Cortex-M, RV32C on RISC-V with the C
+/// extension). Software breakpoints set through this library are shown as the original code.
+///
+/// Writes a JSON array of `{"address", "bytes", "mnemonic", "operands"}` objects to `out_json`.
+/// Xtensa code is split into instructions but rendered as `.byte` data.
+///
+/// Returns the required size including NUL (only the size if `out_json` is NULL or
+/// `out_json_len` is 0), or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_disassemble(
+    session: u64,
+    core_index: u32,
+    address: u64,
+    count: u32,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    if count == 0 || count > MAX_INSTRUCTIONS {
+        set_error(format!("count must be between 1 and {}", MAX_INSTRUCTIONS));
+        return 0;
+    }
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return 0;
+    };
+    let mut lock = sess.lock().unwrap();
+    let mut core = match lock.core(core_index as usize) {
+        Ok(core) => core,
+        Err(e) => {
+            set_error(format!("core access error: {}", e));
+            return 0;
+        }
+    };
+    let res = disassemble(session, core_index, &mut core, address, count)
+        .and_then(|insns| serde_json::to_string(&insns).map_err(|e| e.to_string()));
+    match res {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e);
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn thumb_and_riscv() {
+        // push {r7, lr}; bkpt #0
+        let insns = disassemble_bytes(
+            InstructionSet::Thumb2,
+            CoreType::Armv6m,
+            &[0x80, 0xB5, 0x00, 0xBE],
+            0x1000,
+            2,
+        )
+        .unwrap();
+        assert_eq!(insns.len(), 2);
+        assert_eq!(insns[0].mnemonic, "push");
+        assert_eq!(insns[1].address, 0x1002);
+        assert_eq!(insns[1].mnemonic, "bkpt");
+
+        // c.ebreak; ebreak
+        let insns = disassemble_bytes(
+            InstructionSet::RV32C,
+            CoreType::Riscv,
+            &[0x02, 0x90, 0x73, 0x00, 0x10, 0x00],
+            0,
+            2,
+        )
+        .unwrap();
+        assert_eq!(insns[1].address, 2);
+        assert_eq!(insns[1].bytes, "73001000");
+    }
+
+    #[test]
+    fn xtensa_lengths() {
+        // nop.n (2 bytes), then a 3 byte instruction
+        let insns = xtensa_raw(&[0x3D, 0xF0, 0x00, 0x40, 0x00], 0x4000_0000, 4);
+        assert_eq!(insns.len(), 2);
+        assert_eq!(insns[1].address, 0x4000_0002);
+        assert_eq!(insns[1].bytes, "004000");
+    }
+}
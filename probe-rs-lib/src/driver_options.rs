@@ -0,0 +1,426 @@
+//! Driver specific probe settings (FTDI wiring, ...), applied whenever the library opens a
+//! probe, before attaching to the target.
+
+use crate::{cstr_to_string, set_error};
+use probe_rs::probe::cmsisdap::{CmsisDap, CmsisDapTransport};
+use probe_rs::probe::ftdi::{FtdiChannel, FtdiProbe, FtdiResetPin};
+use probe_rs::probe::glasgow::{Glasgow, GlasgowPort};
+use probe_rs::probe::jlink::JLink;
+use probe_rs::probe::{Probe, WireProtocol};
+use std::collections::BTreeMap;
+use std::ffi::c_char;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq)]
+enum DriverOption {
+    Ftdi(FtdiOption),
+    JLink(JLinkOption),
+    Glasgow(GlasgowOption),
+    CmsisDap(CmsisDapOption),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum FtdiOption {
+    Channel(FtdiChannel),
+    Layout { output: u16, direction: u16 },
+    ResetPin(FtdiResetPin),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum JLinkOption {
+    TargetPower(bool),
+    SwoSpeed(u32),
+    Interface(WireProtocol),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum GlasgowOption {
+    Ports(Vec<GlasgowPort>),
+    Voltage(u16),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum CmsisDapOption {
+    Transport(CmsisDapTransport),
+}
+
+/// Time for the target to come up after the probe switched on its supply.
+const POWER_UP_DELAY: Duration = Duration::from_millis(100);
+
+const KEYS: &[&str] = &[
+    "ftdi.channel",
+    "ftdi.layout",
+    "ftdi.reset_pin",
+    "jlink.target_power",
+    "jlink.swo_speed",
+    "jlink.interface",
+    "glasgow.port",
+    "glasgow.voltage",
+    "cmsisdap.transport",
+];
+
+static DRIVER_OPTIONS: OnceLock<Mutex<BTreeMap<String, DriverOption>>> = OnceLock::new();
+
+fn driver_options() -> &'static Mutex<BTreeMap<String, DriverOption>> {
+    DRIVER_OPTIONS.get_or_init(|| Mutex::new(BTreeMap::new()))
+}
+
+fn parse_u16(value: &str) -> Result<u16, String> {
+    let parsed = match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse(),
+    };
+    parsed.map_err(|_| format!("invalid number {}", value))
+}
+
+fn parse(key: &str, value: &str) -> Result<DriverOption, String> {
+    let value = value.trim();
+    match key {
+        "ftdi.channel" => match value.to_ascii_uppercase().as_str() {
+            "A" => Ok(DriverOption::Ftdi(FtdiOption::Channel(FtdiChannel::A))),
+            "B" => Ok(DriverOption::Ftdi(FtdiOption::Channel(FtdiChannel::B))),
+            "C" => Ok(DriverOption::Ftdi(FtdiOption::Channel(FtdiChannel::C))),
+            "D" => Ok(DriverOption::Ftdi(FtdiOption::Channel(FtdiChannel::D))),
+            _ => Err(format!("invalid FTDI channel {}", value)),
+        },
+        "ftdi.layout" => {
+            let (output, direction) = value
+                .split_once(',')
+                .ok_or_else(|| "FTDI layout must be \"<output>,<direction>\"".to_string())?;
+            Ok(DriverOption::Ftdi(FtdiOption::Layout {
+                output: parse_u16(output.trim())?,
+                direction: parse_u16(direction.trim())?,
+            }))
+        }
+        "ftdi.reset_pin" => {
+            let (active_low, pin) = match value.strip_prefix('!') {
+                Some(pin) => (true, pin),
+                None => (false, value),
+            };
+            let pin = parse_u16(pin)?;
+            if !(4..=15).contains(&pin) {
+                return Err(format!("FTDI pin {} cannot be used for reset", pin));
+            }
+            Ok(DriverOption::Ftdi(FtdiOption::ResetPin(FtdiResetPin {
+                pin: pin as u8,
+                active_low,
+            })))
+        }
+        "jlink.target_power" => match value.to_ascii_lowercase().as_str() {
+            "on" | "1" => Ok(DriverOption::JLink(JLinkOption::TargetPower(true))),
+            "off" | "0" => Ok(DriverOption::JLink(JLinkOption::TargetPower(false))),
+            _ => Err(format!(
+                "invalid target power {}, expected on or off",
+                value
+            )),
+        },
+        "jlink.swo_speed" => match value.parse() {
+            Ok(baud) if baud > 0 => Ok(DriverOption::JLink(JLinkOption::SwoSpeed(baud))),
+            _ => Err(format!("invalid SWO speed {}", value)),
+        },
+        "jlink.interface" => match value.to_ascii_lowercase().as_str() {
+            "swd" => Ok(DriverOption::JLink(JLinkOption::Interface(
+                WireProtocol::Swd,
+            ))),
+            "jtag" => Ok(DriverOption::JLink(JLinkOption::Interface(
+                WireProtocol::Jtag,
+            ))),
+            _ => Err(format!("invalid interface {}, expected swd or jtag", value)),
+        },
+        "glasgow.port" => {
+            let mut ports = Vec::new();
+            for c in value.to_ascii_uppercase().chars() {
+                let port = match c {
+                    'A' => GlasgowPort::A,
+                    'B' => GlasgowPort::B,
+                    _ => return Err(format!("invalid Glasgow port {}", value)),
+                };
+                if !ports.contains(&port) {
+                    ports.push(port);
+                }
+            }
+            if ports.is_empty() {
+                return Err(format!("invalid Glasgow port {}", value));
+            }
+            Ok(DriverOption::Glasgow(GlasgowOption::Ports(ports)))
+        }
+        "glasgow.voltage" => match value.parse::<f32>() {
+            Ok(volts) if volts == 0.0 || (1.65..=5.0).contains(&volts) => Ok(
+                DriverOption::Glasgow(GlasgowOption::Voltage((volts * 1000.0).round() as u16)),
+            ),
+            _ => Err(format!(
+                "invalid Glasgow I/O voltage {}, expected 0 or 1.65-5.0",
+                value
+            )),
+        },
+        "cmsisdap.transport" => match value.to_ascii_lowercase().as_str() {
+            "v1" | "hid" => Ok(DriverOption::CmsisDap(CmsisDapOption::Transport(
+                CmsisDapTransport::V1,
+            ))),
+            "v2" | "bulk" => Ok(DriverOption::CmsisDap(CmsisDapOption::Transport(
+                CmsisDapTransport::V2,
+            ))),
+            _ => Err(format!(
+                "invalid CMSIS-DAP transport {}, expected v1 or v2",
+                value
+            )),
+        },
+        _ => Err(format!("unknown driver option {}", key)),
+    }
+}
+
+/// Apply the configured options for the driver of `probe`, options of other drivers are
+/// skipped, and the configured attach timeout and WAIT retries.
+pub(crate) fn apply(probe: &mut Probe) -> Result<(), String> {
+    let options: Vec<DriverOption> = driver_options().lock().unwrap().values().cloned().collect();
+    if let Some(ftdi) = probe.try_into::<FtdiProbe>() {
+        // The channel first: switching it reopens the device.
+        for option in &options {
+            if let DriverOption::Ftdi(FtdiOption::Channel(channel)) = option {
+                ftdi.set_channel(*channel)
+                    .map_err(|e| format!("FTDI channel error: {}", e))?;
+            }
+        }
+        for option in &options {
+            match option {
+                DriverOption::Ftdi(FtdiOption::Layout { output, direction }) => {
+                    ftdi.set_pin_layout(*output, *direction)
+                }
+                DriverOption::Ftdi(FtdiOption::ResetPin(reset)) => ftdi
+                    .set_reset_pin(Some(*reset))
+                    .map_err(|e| format!("FTDI reset pin error: {}", e))?,
+                _ => {}
+            }
+        }
+    }
+    if let Some(jlink) = probe.try_into::<JLink>() {
+        for option in &options {
+            match option {
+                DriverOption::JLink(JLinkOption::TargetPower(on)) => {
+                    jlink
+                        .set_kickstart_power(*on)
+                        .map_err(|e| format!("J-Link target power error: {}", e))?;
+                    if *on {
+                        std::thread::sleep(POWER_UP_DELAY);
+                    }
+                }
+                DriverOption::JLink(JLinkOption::SwoSpeed(baud)) => {
+                    jlink.set_swo_speed(Some(*baud))
+                }
+                _ => {}
+            }
+        }
+        let interface = options.iter().find_map(|o| match o {
+            DriverOption::JLink(JLinkOption::Interface(protocol)) => Some(*protocol),
+            _ => None,
+        });
+        if let Some(protocol) = interface {
+            probe
+                .select_protocol(protocol)
+                .map_err(|e| format!("J-Link interface error: {}", e))?;
+        }
+    }
+    if let Some(glasgow) = probe.try_into::<Glasgow>() {
+        let ports = options
+            .iter()
+            .find_map(|o| match o {
+                DriverOption::Glasgow(GlasgowOption::Ports(ports)) => Some(ports.clone()),
+                _ => None,
+            })
+            .unwrap_or_else(|| vec![GlasgowPort::A]);
+        let voltage = options.iter().find_map(|o| match o {
+            DriverOption::Glasgow(GlasgowOption::Voltage(millivolts)) => Some(*millivolts),
+            _ => None,
+        });
+        if let Some(millivolts) = voltage {
+            glasgow
+                .set_io_voltage(&ports, millivolts)
+                .map_err(|e| format!("Glasgow I/O voltage error: {}", e))?;
+            if millivolts > 0 {
+                std::thread::sleep(POWER_UP_DELAY);
+            }
+        }
+    }
+    if let Some(cmsisdap) = probe.try_into::<CmsisDap>() {
+        for option in &options {
+            let DriverOption::CmsisDap(CmsisDapOption::Transport(transport)) = option else {
+                continue;
+            };
+            cmsisdap
+                .set_transport(*transport)
+                .map_err(|e| format!("CMSIS-DAP transport error: {}", e))?;
+        }
+    }
+    // Last, the CMSIS-DAP transport reopens the probe.
+    crate::timeouts::apply_to_probe(probe)
+}
+
+/// Set a driver option for probes opened afterwards; a NULL or empty `value` removes it.
+///
+/// FTDI (the MPSSE engine fixes TCK, TDI, TDO and TMS to pins 0-3 of the channel):
+/// - `ftdi.channel`: `A` (default), `B`, `C` or `D`, the channel JTAG is wired to.
+/// - `ftdi.layout`: `"<output>,<direction>"`, initial levels and directions (1 = output) of
+///   the channel's pins, bits 0-7 `xDBUS`, 8-15 `xCBUS`, e.g. `"0x0c08,0x0f1b"` to drive
+///   the buffer enables of a board. Replaces the built-in layout of known adapters.
+/// - `ftdi.reset_pin`: GPIO 4-15 wired to the target's reset, `!` prefix for active low
+///   (`"!9"`); enables resets through the probe.
+///
+/// J-Link:
+/// - `jlink.target_power`: `on` or `off`, the 5 V target supply on pin 19 (not on all
+///   models). The target gets 100 ms to power up before attaching.
+/// - `jlink.swo_speed`: SWO capture baud rate, replacing the one of the SWO configuration, for
+///   firmware that sets up the TPIU itself.
+/// - `jlink.interface`: `swd` or `jtag`; a protocol passed to the open call takes precedence.
+///
+/// Glasgow (the applet's pins are fixed when the Glasgow toolkit builds it):
+/// - `glasgow.port`: `A` (default), `B` or `AB`, the I/O banks the target is wired to.
+/// - `glasgow.voltage`: I/O voltage of those banks in volts, 1.65-5.0, matching the target's
+///   logic level (`"3.3"`); `0` turns the supply off. USB connected probes only.
+///
+/// CMSIS-DAP:
+/// - `cmsisdap.transport`: `v1` (HID) or `v2` (bulk). By default v2 is used where the probe
+///   and its USB driver allow it, falling back to the much slower v1; a forced transport that
+///   cannot be opened fails instead. `pr_probe_cmsisdap_transport` reports the one in use.
+///
+/// Options apply to probes opened by the library through a selector or programmer type;
+/// `pr_session_open_auto` without a programmer type opens the probe without them.
+///
+/// Returns 0 on success, -1 on unknown option or invalid value.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_driver_option_set(key: *const c_char, value: *const c_char) -> i32 {
+    let key = match cstr_to_string(key) {
+        Ok(k) => k,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let value = if value.is_null() {
+        String::new()
+    } else {
+        match cstr_to_string(value) {
+            Ok(v) => v,
+            Err(e) => {
+                set_error(e);
+                return -1;
+            }
+        }
+    };
+    if value.is_empty() {
+        if !KEYS.contains(&key.as_str()) {
+            set_error(format!("unknown driver option {}", key));
+            return -1;
+        }
+        driver_options().lock().unwrap().remove(&key);
+        return 0;
+    }
+    match parse(&key, &value) {
+        Ok(option) => {
+            driver_options().lock().unwrap().insert(key, option);
+            0
+        }
+        Err(e) => {
+            set_error(e);
+            -1
+        }
+    }
+}
+
+/// Remove all driver options.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_driver_options_clear() {
+    driver_options().lock().unwrap().clear();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ftdi_options_are_parsed() {
+        assert_eq!(
+            parse("ftdi.channel", "b"),
+            Ok(DriverOption::Ftdi(FtdiOption::Channel(FtdiChannel::B)))
+        );
+        assert_eq!(
+            parse("ftdi.layout", "0x0c08, 0x0f1b"),
+            Ok(DriverOption::Ftdi(FtdiOption::Layout {
+                output: 0x0c08,
+                direction: 0x0f1b
+            }))
+        );
+        assert_eq!(
+            parse("ftdi.reset_pin", "!9"),
+            Ok(DriverOption::Ftdi(FtdiOption::ResetPin(FtdiResetPin {
+                pin: 9,
+                active_low: true
+            })))
+        );
+        assert!(parse("ftdi.reset_pin", "2").is_err());
+        assert!(parse("ftdi.channel", "E").is_err());
+        assert!(parse("ftdi.layout", "0x08").is_err());
+        assert!(parse("ftdi.speed", "1").is_err());
+    }
+
+    #[test]
+    fn jlink_options_are_parsed() {
+        assert_eq!(
+            parse("jlink.target_power", "ON"),
+            Ok(DriverOption::JLink(JLinkOption::TargetPower(true)))
+        );
+        assert_eq!(
+            parse("jlink.swo_speed", "2000000"),
+            Ok(DriverOption::JLink(JLinkOption::SwoSpeed(2_000_000)))
+        );
+        assert_eq!(
+            parse("jlink.interface", "swd"),
+            Ok(DriverOption::JLink(JLinkOption::Interface(
+                WireProtocol::Swd
+            )))
+        );
+        assert!(parse("jlink.swo_speed", "0").is_err());
+        assert!(parse("jlink.interface", "spi").is_err());
+    }
+
+    #[test]
+    fn glasgow_options_are_parsed() {
+        assert_eq!(
+            parse("glasgow.port", "ba"),
+            Ok(DriverOption::Glasgow(GlasgowOption::Ports(vec![
+                GlasgowPort::B,
+                GlasgowPort::A
+            ])))
+        );
+        assert_eq!(
+            parse("glasgow.voltage", "3.3"),
+            Ok(DriverOption::Glasgow(GlasgowOption::Voltage(3300)))
+        );
+        assert_eq!(
+            parse("glasgow.voltage", "0"),
+            Ok(DriverOption::Glasgow(GlasgowOption::Voltage(0)))
+        );
+        assert!(parse("glasgow.port", "C").is_err());
+        assert!(parse("glasgow.voltage", "12").is_err());
+    }
+
+    #[test]
+    fn cmsisdap_options_are_parsed() {
+        assert_eq!(
+            parse("cmsisdap.transport", "HID"),
+            Ok(DriverOption::CmsisDap(CmsisDapOption::Transport(
+                CmsisDapTransport::V1
+            )))
+        );
+        assert_eq!(
+            parse("cmsisdap.transport", "v2"),
+            Ok(DriverOption::CmsisDap(CmsisDapOption::Transport(
+                CmsisDapTransport::V2
+            )))
+        );
+        assert!(parse("cmsisdap.transport", "v3").is_err());
+    }
+}
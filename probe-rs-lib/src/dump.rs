@@ -0,0 +1,193 @@
+//! Core dumps in the probe-rs coredump format, for offline analysis of field failures
+//! (`probe_rs::CoreDump::load` plus `probe-rs-debug` for unwinding and variables).
+
+use crate::{cstr_to_string, get_session, set_error, timeouts};
+use probe_rs::CoreDump;
+use serde::Deserialize;
+use std::ffi::c_char;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// A number given either as JSON integer or as decimal/`0x` hex string.
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub(crate) enum JsonU64 {
+    Int(u64),
+    Str(String),
+}
+
+impl JsonU64 {
+    pub(crate) fn value(&self) -> Result<u64, String> {
+        match self {
+            JsonU64::Int(v) => Ok(*v),
+            JsonU64::Str(s) => {
+                let s = s.trim();
+                let parsed = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+                    Some(hex) => u64::from_str_radix(hex, 16),
+                    None => s.parse(),
+                };
+                parsed.map_err(|_| format!("invalid number: {}", s))
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RangeSpec {
+    start: JsonU64,
+    size: JsonU64,
+}
+
+fn parse_ranges(json: &str) -> Result<Vec<Range<u64>>, String> {
+    if json.trim().is_empty() {
+        return Ok(Vec::new());
+    }
+    let specs: Vec<RangeSpec> =
+        serde_json::from_str(json).map_err(|e| format!("invalid memory ranges: {}", e))?;
+    specs
+        .iter()
+        .map(|r| {
+            let start = r.start.value()?;
+            let size = r.size.value()?;
+            let end = start
+                .checked_add(size)
+                .ok_or_else(|| format!("memory range overflows at {:#x}", start))?;
+            Ok(start..end)
+        })
+        .collect()
+}
+
+/// File name for one core: `path` itself for single-core dumps, otherwise `_core<N>` is
+/// inserted before the extension.
+fn core_dump_path(path: &Path, core_index: usize, multiple: bool) -> PathBuf {
+    if !multiple {
+        return path.to_path_buf();
+    }
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}_core{}.{}", stem, core_index, ext.to_string_lossy()),
+        None => format!("{}_core{}", stem, core_index),
+    };
+    path.with_file_name(name)
+}
+
+fn dump_cores(
+    session: u64,
+    core_mask: u32,
+    ranges: Vec<Range<u64>>,
+    path: &Path,
+) -> Result<(), String> {
+    let sess = get_session(session)?;
+    let mut lock = sess.lock().unwrap();
+    let cores: Vec<usize> = lock
+        .list_cores()
+        .iter()
+        .map(|(i, _)| *i)
+        .filter(|i| *i < 32 && core_mask & (1 << i) != 0)
+        .collect();
+    if cores.is_empty() {
+        return Err("core mask selects no cores".to_string());
+    }
+
+    for &index in &cores {
+        let mut core = lock
+            .core(index)
+            .map_err(|e| format!("core access error: {}", e))?;
+        // Registers can only be read consistently from a halted core; restore the
+        // previous run state afterwards.
+        let was_running = !core
+            .core_halted()
+            .map_err(|e| format!("core {} status error: {}", index, e))?;
+        if was_running {
+            core.halt(timeouts::halt_timeout())
+                .map_err(|e| format!("core {} halt error: {}", index, e))?;
+        }
+        let dump = CoreDump::dump_core(&mut core, ranges.clone());
+        if was_running {
+            let _ = core.run();
+        }
+        let dump = dump.map_err(|e| format!("core {} dump error: {}", index, e))?;
+        dump.store(&core_dump_path(path, index, cores.len() > 1))
+            .map_err(|e| format!("core {} store error: {}", index, e))?;
+    }
+    Ok(())
+}
+
+/// Write a probe-rs coredump of the cores selected by `core_mask` (bit N = core N) to `path`.
+///
+/// Each dump has all core registers plus the memory listed in `memory_ranges_json`, a JSON
+/// array like `[{"start": "0x20000000", "size": 4096}]` (numbers or decimal/hex strings;
+/// NULL or empty for registers only). Running cores are halted briefly and resumed. With
+/// more than one core selected, each file gets a `_core<N>` suffix before the extension.
+///
+/// Returns 0 on success, -1 on invalid arguments or handle, -2 if dumping or writing failed.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_core_dump(
+    session: u64,
+    core_mask: u32,
+    memory_ranges_json: *const c_char,
+    path: *const c_char,
+) -> i32 {
+    let Ok(path) = cstr_to_string(path) else {
+        set_error("invalid path".to_string());
+        return -1;
+    };
+    let ranges_json = if memory_ranges_json.is_null() {
+        String::new()
+    } else {
+        match cstr_to_string(memory_ranges_json) {
+            Ok(s) => s,
+            Err(_) => {
+                set_error("invalid memory ranges".to_string());
+                return -1;
+            }
+        }
+    };
+    let ranges = match parse_ranges(&ranges_json) {
+        Ok(r) => r,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    if get_session(session).is_err() {
+        set_error("invalid session handle".to_string());
+        return -1;
+    }
+    match dump_cores(session, core_mask, ranges, Path::new(&path)) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(e);
+            -2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_accept_numbers_and_hex() {
+        let ranges = parse_ranges(
+            r#"[{"start": "0x20000000", "size": 16}, {"start": 4096, "size": "0x10"}]"#,
+        )
+        .unwrap();
+        assert_eq!(ranges, vec![0x2000_0000..0x2000_0010, 0x1000..0x1010]);
+        assert!(parse_ranges("").unwrap().is_empty());
+        assert!(parse_ranges(r#"[{"start": "zz", "size": 1}]"#).is_err());
+    }
+
+    #[test]
+    fn per_core_file_names() {
+        let p = Path::new("/tmp/fail.dump");
+        assert_eq!(core_dump_path(p, 1, false), p);
+        assert_eq!(
+            core_dump_path(p, 1, true),
+            PathBuf::from("/tmp/fail_core1.dump")
+        );
+    }
+}
@@ -0,0 +1,183 @@
+//! ELF symbol table lookups for host tools that need addresses of functions and
+//! variables without linking their own ELF parser.
+
+use crate::{cstr_to_string, set_error, write_c_str};
+use object::{Architecture, Object, ObjectSymbol, SymbolKind};
+use std::ffi::c_char;
+
+/// A defined symbol with its address normalized for use with the debugger.
+struct ElfSymbol {
+    name: String,
+    address: u64,
+    size: u64,
+}
+
+fn load_symbols(path: &str) -> Result<Vec<ElfSymbol>, String> {
+    let data = std::fs::read(path).map_err(|e| format!("failed to read elf: {}", e))?;
+    let file = object::File::parse(&*data).map_err(|e| format!("failed to parse elf: {}", e))?;
+    let is_arm = file.architecture() == Architecture::Arm;
+
+    let mut symbols = Vec::new();
+    for sym in file.symbols() {
+        if sym.is_undefined() || !matches!(sym.kind(), SymbolKind::Text | SymbolKind::Data) {
+            continue;
+        }
+        let Ok(name) = sym.name() else {
+            continue;
+        };
+        if name.is_empty() {
+            continue;
+        }
+        // Thumb function symbols carry the mode in bit 0; breakpoints need the real address.
+        let address = if is_arm && sym.kind() == SymbolKind::Text {
+            sym.address() & !1
+        } else {
+            sym.address()
+        };
+        symbols.push(ElfSymbol {
+            name: name.to_string(),
+            address,
+            size: sym.size(),
+        });
+    }
+    Ok(symbols)
+}
+
+/// The ELF entry point, with the Thumb bit cleared on ARM.
+pub(crate) fn entry_point(path: &str) -> Result<u64, String> {
+    let data = std::fs::read(path).map_err(|e| format!("failed to read elf: {}", e))?;
+    let file = object::File::parse(&*data).map_err(|e| format!("failed to parse elf: {}", e))?;
+    Ok(if file.architecture() == Architecture::Arm {
+        file.entry() & !1
+    } else {
+        file.entry()
+    })
+}
+
+fn symbol_address(path: &str, name: &str) -> Result<Option<u64>, String> {
+    let symbols = load_symbols(path)?;
+    Ok(symbols.iter().find(|s| s.name == name).map(|s| s.address))
+}
+
+fn address_symbol(path: &str, address: u64) -> Result<Option<String>, String> {
+    let symbols = load_symbols(path)?;
+    let hit = symbols
+        .iter()
+        .filter(|s| {
+            if s.size == 0 {
+                s.address == address
+            } else {
+                address >= s.address && address - s.address < s.size
+            }
+        })
+        // Prefer the tightest enclosing symbol when several overlap.
+        .min_by_key(|s| s.size);
+    Ok(hit.map(|s| s.name.clone()))
+}
+
+/// Resolve the address of a named symbol in an ELF file.
+///
+/// For ARM targets the Thumb bit is cleared on function symbols, so the result can be
+/// passed directly to `pr_set_hw_breakpoint`.
+///
+/// Returns 0 on success, -1 on invalid arguments or a file that cannot be parsed,
+/// and -2 if no defined symbol with that name exists.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_elf_symbol_address(
+    elf_path: *const c_char,
+    name: *const c_char,
+    out_address: *mut u64,
+) -> i32 {
+    if out_address.is_null() {
+        set_error("out_address is null".to_string());
+        return -1;
+    }
+    let Ok(path) = cstr_to_string(elf_path) else {
+        set_error("invalid elf path".to_string());
+        return -1;
+    };
+    let Ok(name) = cstr_to_string(name) else {
+        set_error("invalid symbol name".to_string());
+        return -1;
+    };
+    match symbol_address(&path, &name) {
+        Ok(Some(addr)) => {
+            unsafe { *out_address = addr };
+            0
+        }
+        Ok(None) => {
+            set_error(format!("symbol not found: {}", name));
+            -2
+        }
+        Err(e) => {
+            set_error(e);
+            -1
+        }
+    }
+}
+
+/// Find the symbol containing `address` in an ELF file.
+///
+/// Writes the symbol name into `out_name` and returns the required size (including NUL).
+/// If `out_name` is NULL or `out_name_len` is 0, only the size is returned. Returns 0 if the
+/// file cannot be parsed or no symbol covers the address; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_elf_address_symbol(
+    elf_path: *const c_char,
+    address: u64,
+    out_name: *mut c_char,
+    out_name_len: usize,
+) -> usize {
+    let Ok(path) = cstr_to_string(elf_path) else {
+        set_error("invalid elf path".to_string());
+        return 0;
+    };
+    match address_symbol(&path, address) {
+        Ok(Some(name)) => write_c_str(&name, out_name, out_name_len),
+        Ok(None) => {
+            set_error(format!("no symbol at address {:#010x}", address));
+            0
+        }
+        Err(e) => {
+            set_error(e);
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> String {
+        format!(
+            "{}/../probe-rs-debug/tests/debug-unwind-tests/RP2040_full_unwind.elf",
+            env!("CARGO_MANIFEST_DIR")
+        )
+    }
+
+    #[test]
+    fn main_address_has_thumb_bit_cleared() {
+        let addr = symbol_address(&fixture(), "main").unwrap().unwrap();
+        assert_eq!(addr & 1, 0);
+        assert!(
+            symbol_address(&fixture(), "no_such_symbol")
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[test]
+    fn entry_point_is_reset_handler() {
+        let entry = entry_point(&fixture()).unwrap();
+        assert_ne!(entry, 0);
+        assert_eq!(entry & 1, 0);
+    }
+
+    #[test]
+    fn address_maps_back_to_symbol() {
+        let addr = symbol_address(&fixture(), "main").unwrap().unwrap();
+        let name = address_symbol(&fixture(), addr + 2).unwrap();
+        assert_eq!(name.as_deref(), Some("main"));
+    }
+}
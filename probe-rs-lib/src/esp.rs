@@ -0,0 +1,173 @@
+//! Espressif specifics: the factory MAC address from eFuse, and the choice between the flash
+//! loader variants of the ESP targets.
+//!
+//! The ESP flash loaders run on the target like esptool's stub: they program through the ROM SPI
+//! flash functions and take miniz compressed data. The default variant raises the CPU clock
+//! first; the `-default-clocks` variant leaves it alone for boards where that fails.
+
+use crate::{get_session, set_error, write_c_str};
+use probe_rs::MemoryInterface;
+use std::ffi::c_char;
+
+/// Chip families by target name prefix, most specific first, with the eFuse word holding the
+/// low 32 bits of the factory MAC; the next word holds the high 16 bits.
+const MAC_EFUSE: &[(&str, u64)] = &[
+    ("esp32s2", 0x3f41_a044),
+    ("esp32s3", 0x6000_7044),
+    ("esp32c2", 0x6000_8840),
+    ("esp32c3", 0x6000_8844),
+    ("esp32c5", 0x600b_4844),
+    ("esp32c6", 0x600b_0844),
+    ("esp32h2", 0x600b_0844),
+    ("esp32", 0x3ff5_a004),
+];
+
+fn family(target_name: &str) -> Option<(&'static str, u64)> {
+    let name = target_name.to_ascii_lowercase();
+    MAC_EFUSE
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .copied()
+}
+
+/// The MAC in transmission order, as esptool prints it.
+fn mac_bytes(low: u32, high: u32) -> [u8; 6] {
+    let [_, _, h1, h0] = high.to_be_bytes();
+    let [l3, l2, l1, l0] = low.to_be_bytes();
+    [h1, h0, l3, l2, l1, l0]
+}
+
+/// Read the factory MAC address of an ESP32 series chip from eFuse into `out_mac` (6 bytes),
+/// and optionally as text `aa:bb:cc:dd:ee:ff` into `out_text`.
+///
+/// Returns 0 on success, -1 on invalid handle or arguments, -2 on read error, -3 if the target
+/// is not an ESP32 series chip.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_esp_read_mac(
+    session: u64,
+    out_mac: *mut u8,
+    out_text: *mut c_char,
+    out_text_len: usize,
+) -> i32 {
+    if out_mac.is_null() {
+        set_error("out_mac is null".to_string());
+        return -1;
+    }
+    let sess = match get_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let mut lock = sess.lock().unwrap();
+    let Some((_, address)) = family(&lock.target().name) else {
+        set_error(format!(
+            "{} is not an ESP32 series chip",
+            lock.target().name
+        ));
+        return -3;
+    };
+    let mut words = [0u32; 2];
+    let read = lock
+        .core(0)
+        .and_then(|mut core| core.read_32(address, &mut words));
+    if let Err(e) = read {
+        set_error(format!("eFuse read error: {}", e));
+        return -2;
+    }
+    let mac = mac_bytes(words[0], words[1]);
+    unsafe { std::ptr::copy_nonoverlapping(mac.as_ptr(), out_mac, mac.len()) };
+    let text = mac
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":");
+    write_c_str(&text, out_text, out_text_len);
+    0
+}
+
+/// Choose the flash loader used by later flashing calls of an ESP session: 0 = the fast loader
+/// that raises the CPU clock (default), 1 = the loader that keeps the default clocks, for boards
+/// whose clock setup fails with the fast one.
+///
+/// Returns 0 on success, -1 on invalid handle or mode, -3 if the target has no such loader.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_esp_set_flash_loader(session: u64, mode: i32) -> i32 {
+    let suffix = match mode {
+        0 => "-flashloader",
+        1 => "-flashloader-default-clocks",
+        _ => {
+            set_error(format!("invalid flash loader mode {}", mode));
+            return -1;
+        }
+    };
+    let sess = match get_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let mut lock = sess.lock().unwrap();
+    let Some((family, _)) = family(&lock.target().name) else {
+        set_error(format!(
+            "{} is not an ESP32 series chip",
+            lock.target().name
+        ));
+        return -3;
+    };
+    let name = format!("{}{}", family, suffix);
+    if !lock
+        .target()
+        .flash_algorithms
+        .iter()
+        .any(|a| a.name == name)
+    {
+        set_error(format!("flash loader {} not available", name));
+        return -3;
+    }
+    match lock.map_flash_algorithm_region(&name) {
+        Ok(_) => 0,
+        Err(e) => {
+            set_error(format!("flash loader error: {}", e));
+            -3
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn families_match_most_specific_prefix() {
+        assert_eq!(family("esp32c3").map(|f| f.0), Some("esp32c3"));
+        assert_eq!(family("ESP32").map(|f| f.0), Some("esp32"));
+        assert_eq!(family("esp32c6_lp").map(|f| f.0), Some("esp32c6"));
+        assert_eq!(family("nrf52840_xxAA"), None);
+    }
+
+    #[test]
+    fn mac_is_assembled_high_word_first() {
+        assert_eq!(
+            mac_bytes(0x8e_12_34_56, 0x0000_7cdf),
+            [0x7c, 0xdf, 0x8e, 0x12, 0x34, 0x56]
+        );
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let mut mac = [0u8; 6];
+        assert_eq!(
+            pr_esp_read_mac(0xdead, mac.as_mut_ptr(), std::ptr::null_mut(), 0),
+            -1
+        );
+        assert_eq!(
+            pr_esp_read_mac(0xdead, std::ptr::null_mut(), std::ptr::null_mut(), 0),
+            -1
+        );
+        assert_eq!(pr_esp_set_flash_loader(0xdead, 0), -1);
+        assert_eq!(pr_esp_set_flash_loader(0xdead, 7), -1);
+    }
+}
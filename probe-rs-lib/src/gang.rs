@@ -0,0 +1,284 @@
+//! Gang programming: flash several boards at once, one worker thread per probe.
+
+use crate::dump::JsonU64;
+use crate::timing::{self, TimingReport};
+use crate::{
+    FlashPatch, cstr_to_string, detect_format_from_path, download_options, driver_options,
+    flash_image, progress_handler, protocol_from_int, reset, set_error, write_c_str,
+};
+use probe_rs::probe::DebugProbeSelector;
+use probe_rs::probe::list::Lister;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::ffi::c_char;
+
+/// Per-job progress: `(job_index, operation, percent, status, eta_ms)`, otherwise the same as
+/// the global progress callback.
+type GangProgressCb = unsafe extern "C" fn(u32, i32, f32, *const c_char, i32);
+
+thread_local! {
+    /// Results JSON of this thread's last `pr_gang_flash`.
+    static LAST_RESULTS: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+#[derive(Deserialize)]
+struct Job {
+    probe: String,
+    chip: String,
+    path: String,
+    base_address: Option<JsonU64>,
+    #[serde(default)]
+    skip: u32,
+    #[serde(default)]
+    verify: bool,
+    #[serde(default)]
+    preverify: bool,
+    #[serde(default)]
+    chip_erase: bool,
+    #[serde(default)]
+    speed_khz: u32,
+    #[serde(default)]
+    protocol: i32,
+    /// Per-board data such as serial numbers, applied after the `pr_flash_set_patch` patches.
+    #[serde(default)]
+    patches: Vec<PatchSpec>,
+}
+
+#[derive(Deserialize)]
+struct PatchSpec {
+    address: JsonU64,
+    /// Hex string, e.g. `"0badc0de"`.
+    data: String,
+}
+
+impl PatchSpec {
+    fn parse(&self) -> Result<FlashPatch, String> {
+        let hex = self.data.trim();
+        if !hex.is_ascii() || !hex.len().is_multiple_of(2) {
+            return Err(format!("invalid patch data: {}", hex));
+        }
+        let data = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| format!("invalid patch data: {}", hex))?;
+        Ok((self.address.value()?, data))
+    }
+}
+
+#[derive(Serialize)]
+struct JobResult {
+    index: usize,
+    probe: String,
+    ok: bool,
+    error: Option<String>,
+    /// Null if the job failed before flashing.
+    timing: Option<TimingReport>,
+}
+
+fn parse_jobs(json: &str) -> Result<Vec<Job>, String> {
+    let jobs: Vec<Job> = serde_json::from_str(json).map_err(|e| format!("invalid jobs: {}", e))?;
+    if jobs.is_empty() {
+        return Err("no jobs given".to_string());
+    }
+    for (i, a) in jobs.iter().enumerate() {
+        if jobs[..i].iter().any(|b| b.probe == a.probe) {
+            return Err(format!("probe {} is used by more than one job", a.probe));
+        }
+    }
+    Ok(jobs)
+}
+
+fn run_job(index: usize, job: &Job, cb: Option<GangProgressCb>) -> Result<(), String> {
+    let base = job.base_address.as_ref().map(|b| b.value()).transpose()?;
+    let format = detect_format_from_path(&job.path, base, job.skip)?;
+    let patches = job
+        .patches
+        .iter()
+        .map(PatchSpec::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    let selector: DebugProbeSelector = job
+        .probe
+        .parse()
+        .map_err(|e| format!("selector parse error: {}", e))?;
+    let mut probe = Lister::new()
+        .open(selector)
+        .map_err(|e| format!("open probe error: {}", e))?;
+    driver_options::apply(&mut probe)?;
+    if let Some(p) = protocol_from_int(job.protocol) {
+        probe
+            .select_protocol(p)
+            .map_err(|e| format!("select protocol error: {}", e))?;
+    }
+    if job.speed_khz > 0 {
+        probe
+            .set_speed(job.speed_khz)
+            .map_err(|e| format!("set speed error: {}", e))?;
+    }
+    let speed_khz = probe.speed_khz();
+    let mut session = reset::attach(probe, job.chip.as_str(), Default::default())?;
+
+    let mut opts = download_options(
+        None,
+        job.verify as i32,
+        job.preverify as i32,
+        job.chip_erase as i32,
+    );
+    opts.progress = match cb {
+        Some(cb) => progress_handler(move |op, pct, status, eta| unsafe {
+            cb(index as u32, op, pct, status, eta)
+        }),
+        None => Default::default(),
+    };
+    flash_image(
+        &mut session,
+        &job.path,
+        format,
+        opts,
+        &patches,
+        Some(speed_khz),
+    )
+}
+
+/// Flash several boards concurrently. `jobs_json` is a JSON array of jobs:
+/// `{"probe": "VID:PID[:SN]", "chip", "path"}` plus the optional `base_address` (required for
+/// `.bin`), `skip`, `verify`, `preverify`, `chip_erase`, `speed_khz` and `protocol`
+/// (0 = auto, 1 = SWD, 2 = JTAG). Every job needs its own probe. A job's `patches`, e.g.
+/// `[{"address": "0x0800fff0", "data": "00000103"}]`, are applied like `pr_flash_set_patch`
+/// for that board only.
+///
+/// Each job runs on its own worker thread; `cb` (may be NULL) is called from those threads
+/// with the job's index and must be thread-safe. The global progress callback is not used.
+///
+/// When all jobs are done, a JSON array of `{"index", "probe", "ok", "error", "timing"}`
+/// (`timing` as for `pr_flash_last_timing`) is written to
+/// `out_json` (may be NULL), truncated to `out_json_len`; `pr_gang_flash_results` returns it
+/// whole. Returns the number of failed jobs, or -1 on invalid job JSON.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_gang_flash(
+    jobs_json: *const c_char,
+    cb: Option<GangProgressCb>,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> i32 {
+    let jobs = match cstr_to_string(jobs_json).and_then(|s| parse_jobs(&s)) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+
+    let results: Vec<JobResult> = std::thread::scope(|scope| {
+        let workers: Vec<_> = jobs
+            .iter()
+            .enumerate()
+            .map(|(index, job)| scope.spawn(move || (run_job(index, job, cb), timing::last())))
+            .collect();
+        workers
+            .into_iter()
+            .zip(&jobs)
+            .enumerate()
+            .map(|(index, (worker, job))| {
+                let (res, timing) = worker
+                    .join()
+                    .unwrap_or_else(|_| (Err("worker thread panicked".to_string()), None));
+                JobResult {
+                    index,
+                    probe: job.probe.clone(),
+                    ok: res.is_ok(),
+                    error: res.err(),
+                    timing,
+                }
+            })
+            .collect()
+    });
+
+    let failed = results.iter().filter(|r| !r.ok).count();
+    if let Some(first) = results.iter().find_map(|r| r.error.as_ref()) {
+        set_error(format!(
+            "{} of {} jobs failed: {}",
+            failed,
+            results.len(),
+            first
+        ));
+    }
+    if let Ok(json) = serde_json::to_string(&results) {
+        write_c_str(&json, out_json, out_json_len);
+        LAST_RESULTS.with_borrow_mut(|last| *last = Some(json));
+    }
+    failed as i32
+}
+
+/// The results JSON of the calling thread's last `pr_gang_flash`, as written to its `out_json`,
+/// for when that buffer was too small.
+///
+/// Returns the required size including NUL, or 0 if this thread has not run a gang flash yet.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_gang_flash_results(out_json: *mut c_char, out_json_len: usize) -> usize {
+    LAST_RESULTS.with_borrow(|last| match last {
+        Some(json) => write_c_str(json, out_json, out_json_len),
+        None => {
+            set_error("no gang flash on this thread".to_string());
+            0
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    #[test]
+    fn jobs_parse_with_defaults() {
+        let jobs = parse_jobs(
+            r#"[{"probe": "0483:3748:A", "chip": "STM32F103C8", "path": "fw.elf"},
+                {"probe": "0483:3748:B", "chip": "STM32F103C8", "path": "fw.bin",
+                 "base_address": "0x08000000", "verify": true,
+                 "patches": [{"address": "0x0800fff0", "data": "0a0B"}]}]"#,
+        )
+        .unwrap();
+        assert_eq!(jobs.len(), 2);
+        assert!(!jobs[0].verify && jobs[0].base_address.is_none());
+        assert_eq!(
+            jobs[1].base_address.as_ref().unwrap().value(),
+            Ok(0x0800_0000)
+        );
+        assert!(jobs[1].verify);
+        assert_eq!(
+            jobs[1].patches[0].parse(),
+            Ok((0x0800_fff0, vec![0x0a, 0x0b]))
+        );
+    }
+
+    #[test]
+    fn results_are_kept_whole() {
+        assert_eq!(pr_gang_flash_results(std::ptr::null_mut(), 0), 0);
+        let jobs =
+            CString::new(r#"[{"probe": "ffff:ffff:none", "chip": "a", "path": "x.hex"}]"#).unwrap();
+        let mut small = [0 as c_char; 4];
+        assert_eq!(
+            pr_gang_flash(jobs.as_ptr(), None, small.as_mut_ptr(), small.len()),
+            1
+        );
+        let need = pr_gang_flash_results(std::ptr::null_mut(), 0);
+        assert!(need > small.len());
+        let mut buf = vec![0 as c_char; need];
+        assert_eq!(pr_gang_flash_results(buf.as_mut_ptr(), buf.len()), need);
+        let json = unsafe { std::ffi::CStr::from_ptr(buf.as_ptr()) };
+        assert!(json.to_str().unwrap().contains(r#""ok":false"#));
+    }
+
+    #[test]
+    fn jobs_reject_shared_probe() {
+        assert!(parse_jobs("[]").is_err());
+        assert!(
+            parse_jobs(
+                r#"[{"probe": "1:2", "chip": "a", "path": "x.hex"},
+                    {"probe": "1:2", "chip": "a", "path": "y.hex"}]"#
+            )
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,891 @@
+//! A GDB remote protocol client, to flash and poke a target through a GDB server that owns the
+//! probe (`probe-rs gdb`, OpenOCD, a J-Link GDB server, ...) instead of opening the probe, e.g.
+//! to flash from an IDE tool while the debug server keeps running.
+//!
+//! Flashing uses the `vFlashErase` / `vFlashWrite` / `vFlashDone` packets with the flash blocks
+//! of the server's memory map, like GDB's `load`. Handles come from the same counter as
+//! session handles, so the two are never confused.
+
+use crate::image::{self, optional_str};
+use crate::{NEXT_HANDLE, cstr_to_string, set_error, write_c_str};
+use std::collections::HashMap;
+use std::ffi::c_char;
+use std::io::{BufReader, ErrorKind, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::ops::Range;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+/// Erasing and programming are done by the server while it holds the reply.
+const FLASH_TIMEOUT: Duration = Duration::from_secs(300);
+/// Packet size assumed if the server does not announce one.
+const DEFAULT_PACKET_SIZE: usize = 0x400;
+pub(crate) const INTERRUPT: u8 = 0x03;
+
+/// Packet framing over a TCP stream, in acknowledged mode.
+struct Link {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+fn io_err(e: std::io::Error) -> String {
+    if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) {
+        "GDB server did not answer in time".to_string()
+    } else {
+        format!("GDB connection error: {}", e)
+    }
+}
+
+impl Link {
+    fn new(stream: TcpStream) -> Result<Self, String> {
+        // Every packet waits for an acknowledgement.
+        stream.set_nodelay(true).map_err(io_err)?;
+        let writer = stream.try_clone().map_err(io_err)?;
+        Ok(Self {
+            reader: BufReader::new(stream),
+            writer,
+        })
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        let mut b = [0];
+        self.reader.read_exact(&mut b).map_err(io_err)?;
+        Ok(b[0])
+    }
+
+    /// Send `payload` (escaped here, so it may be binary) and wait for the acknowledgement.
+    fn send(&mut self, payload: &[u8]) -> Result<(), String> {
+        let frame = frame(payload);
+        for _ in 0..3 {
+            self.writer.write_all(&frame).map_err(io_err)?;
+            match self.byte()? {
+                b'+' => return Ok(()),
+                b'-' => continue,
+                other => return Err(format!("unexpected GDB acknowledgement {:#04x}", other)),
+            }
+        }
+        Err("GDB server rejected the packet".to_string())
+    }
+
+    /// The next packet with run-length encoding and escapes removed. An interrupt byte outside
+    /// of a packet is returned as the packet `[0x03]` (only sent by clients).
+    fn recv(&mut self) -> Result<Vec<u8>, String> {
+        loop {
+            match self.byte()? {
+                b'$' => {}
+                INTERRUPT => return Ok(vec![INTERRUPT]),
+                _ => continue,
+            }
+            let mut raw = Vec::new();
+            loop {
+                match self.byte()? {
+                    b'#' => break,
+                    b => raw.push(b),
+                }
+            }
+            let sum = [self.byte()?, self.byte()?];
+            let expected = raw.iter().fold(0u8, |s, b| s.wrapping_add(*b));
+            let valid = std::str::from_utf8(&sum)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                == Some(expected);
+            self.writer
+                .write_all(if valid { b"+" } else { b"-" })
+                .map_err(io_err)?;
+            if valid {
+                return Ok(decode(&raw));
+            }
+        }
+    }
+
+    fn set_timeout(&mut self, timeout: Duration) -> Result<(), String> {
+        self.writer.set_read_timeout(Some(timeout)).map_err(io_err)
+    }
+}
+
+/// `payload` as a packet: escaped, with `$` and the checksum around it.
+pub(crate) fn frame(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(payload.len() + 4);
+    frame.push(b'$');
+    for &b in payload {
+        if matches!(b, b'$' | b'#' | b'}' | b'*') {
+            frame.extend([b'}', b ^ 0x20]);
+        } else {
+            frame.push(b);
+        }
+    }
+    let sum = frame[1..].iter().fold(0u8, |s, b| s.wrapping_add(*b));
+    frame.extend(format!("#{:02x}", sum).bytes());
+    frame
+}
+
+/// The packet data between `$` and `#` with run-length encoding and escapes removed.
+pub(crate) fn decode(raw: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::with_capacity(raw.len());
+    let mut bytes = raw.iter();
+    while let Some(&b) = bytes.next() {
+        match b {
+            b'}' => out.extend(bytes.next().map(|n| n ^ 0x20)),
+            b'*' => {
+                if let (Some(&n), Some(&last)) = (bytes.next(), out.last()) {
+                    out.extend(std::iter::repeat_n(last, n.saturating_sub(29) as usize));
+                }
+            }
+            _ => out.push(b),
+        }
+    }
+    out
+}
+
+pub(crate) fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn unhex(text: &[u8]) -> Result<Vec<u8>, String> {
+    if !text.len().is_multiple_of(2) {
+        return Err("odd length hex in GDB packet".to_string());
+    }
+    text.chunks(2)
+        .map(|pair| {
+            std::str::from_utf8(pair)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok())
+                .ok_or_else(|| "invalid hex in GDB packet".to_string())
+        })
+        .collect()
+}
+
+/// A flash region of the server's memory map.
+#[derive(Debug, PartialEq)]
+struct FlashRegion {
+    range: Range<u64>,
+    block_size: u64,
+}
+
+fn parse_number(text: &str) -> Option<u64> {
+    match text.strip_prefix("0x").or_else(|| text.strip_prefix("0X")) {
+        Some(h) => u64::from_str_radix(h, 16).ok(),
+        None => text.parse().ok(),
+    }
+}
+
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let start = tag.find(&format!("{}=\"", name))? + name.len() + 2;
+    let len = tag[start..].find('"')?;
+    Some(&tag[start..start + len])
+}
+
+/// The flash regions of a GDB memory map document.
+fn flash_regions(xml: &str) -> Vec<FlashRegion> {
+    let mut regions = Vec::new();
+    for entry in xml.split("<memory ").skip(1) {
+        let tag = &entry[..entry.find('>').unwrap_or(entry.len())];
+        if attribute(tag, "type") != Some("flash") {
+            continue;
+        }
+        let block_size = entry
+            .find("name=\"blocksize\"")
+            .and_then(|i| {
+                let value = &entry[i..];
+                let value = &value[value.find('>')? + 1..];
+                parse_number(value[..value.find('<')?].trim())
+            })
+            .filter(|b| *b > 0);
+        let start = attribute(tag, "start").and_then(parse_number);
+        let length = attribute(tag, "length").and_then(parse_number);
+        if let (Some(start), Some(length), Some(block_size)) = (start, length, block_size) {
+            regions.push(FlashRegion {
+                range: start..start + length,
+                block_size,
+            });
+        }
+    }
+    regions
+}
+
+/// The block-aligned ranges to erase before writing `data` into `regions`, merged.
+fn erase_ranges(regions: &[FlashRegion], data: &[Range<u64>]) -> Vec<Range<u64>> {
+    let mut ranges: Vec<Range<u64>> = Vec::new();
+    for region in regions {
+        for d in data {
+            let (start, end) = (d.start.max(region.range.start), d.end.min(region.range.end));
+            if start >= end {
+                continue;
+            }
+            let base = region.range.start;
+            let bs = region.block_size;
+            let aligned_start = base + (start - base) / bs * bs;
+            let aligned_end = (base + (end - base).div_ceil(bs) * bs).min(region.range.end);
+            ranges.push(aligned_start..aligned_end);
+        }
+    }
+    ranges.sort_by_key(|r| r.start);
+    let mut merged: Vec<Range<u64>> = Vec::new();
+    for r in ranges {
+        match merged.last_mut() {
+            Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+            _ => merged.push(r),
+        }
+    }
+    merged
+}
+
+struct GdbClient {
+    link: Link,
+    timeout: Duration,
+    packet_size: usize,
+    /// Continued with `c`; the stop reply comes after an interrupt.
+    running: bool,
+    /// Output of the last monitor command that did not fit the caller's buffer.
+    pending_monitor: Option<(String, String)>,
+}
+
+impl GdbClient {
+    fn connect(address: &str, timeout: Duration) -> Result<Self, String> {
+        let addr = address
+            .to_socket_addrs()
+            .map_err(|e| format!("cannot resolve {}: {}", address, e))?
+            .next()
+            .ok_or_else(|| format!("cannot resolve {}", address))?;
+        let stream = TcpStream::connect_timeout(&addr, timeout)
+            .map_err(|e| format!("cannot connect to GDB server {}: {}", address, e))?;
+        let mut client = Self {
+            link: Link::new(stream)?,
+            timeout,
+            packet_size: DEFAULT_PACKET_SIZE,
+            running: false,
+            pending_monitor: None,
+        };
+        let features = client.request(b"qSupported:swbreak+;hwbreak+")?;
+        if let Some(size) = String::from_utf8_lossy(&features)
+            .split(';')
+            .find_map(|f| f.strip_prefix("PacketSize="))
+            .and_then(|s| usize::from_str_radix(s, 16).ok())
+        {
+            client.packet_size = size.max(64);
+        }
+        let status = client.request(b"?")?;
+        if matches!(status.first(), Some(b'W' | b'X')) {
+            return Err("the target of the GDB server has exited".to_string());
+        }
+        Ok(client)
+    }
+
+    fn exchange(&mut self, payload: &[u8], timeout: Duration) -> Result<Vec<u8>, String> {
+        self.link.set_timeout(timeout)?;
+        self.link.send(payload)?;
+        let reply = self.link.recv()?;
+        if reply.len() == 3 && reply[0] == b'E' {
+            return Err(format!(
+                "GDB server error {} for {}",
+                String::from_utf8_lossy(&reply[1..]),
+                packet_name(payload)
+            ));
+        }
+        Ok(reply)
+    }
+
+    fn request(&mut self, payload: &[u8]) -> Result<Vec<u8>, String> {
+        self.exchange(payload, self.timeout)
+    }
+
+    fn expect_ok(&mut self, payload: &[u8], timeout: Duration) -> Result<(), String> {
+        match self.exchange(payload, timeout)?.as_slice() {
+            b"OK" => Ok(()),
+            b"" => Err(format!(
+                "GDB server does not support {}",
+                packet_name(payload)
+            )),
+            other => Err(format!(
+                "unexpected GDB reply {} to {}",
+                String::from_utf8_lossy(other),
+                packet_name(payload)
+            )),
+        }
+    }
+
+    fn ensure_halted(&self) -> Result<(), String> {
+        if self.running {
+            return Err("target is running; halt it first".to_string());
+        }
+        Ok(())
+    }
+
+    /// Largest data chunk so that a hex encoded packet with its header fits.
+    fn hex_chunk(&self) -> usize {
+        (self.packet_size.saturating_sub(32) / 2).max(1)
+    }
+
+    fn read_memory(&mut self, address: u64, len: usize) -> Result<Vec<u8>, String> {
+        self.ensure_halted()?;
+        let mut data = Vec::with_capacity(len);
+        while data.len() < len {
+            let n = (len - data.len()).min(self.hex_chunk());
+            let addr = address + data.len() as u64;
+            let reply = self.request(format!("m{:x},{:x}", addr, n).as_bytes())?;
+            let chunk = unhex(&reply)?;
+            if chunk.is_empty() {
+                return Err(format!("cannot read memory at {:#x}", addr));
+            }
+            data.extend_from_slice(&chunk[..chunk.len().min(n)]);
+        }
+        Ok(data)
+    }
+
+    fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<(), String> {
+        self.ensure_halted()?;
+        let chunk_len = self.hex_chunk();
+        for (i, chunk) in data.chunks(chunk_len).enumerate() {
+            let addr = address + (i * chunk_len) as u64;
+            let packet = format!("M{:x},{:x}:{}", addr, chunk.len(), hex(chunk));
+            self.expect_ok(packet.as_bytes(), self.timeout)?;
+        }
+        Ok(())
+    }
+
+    fn halt(&mut self) -> Result<(), String> {
+        if !self.running {
+            return Ok(());
+        }
+        self.link.set_timeout(self.timeout)?;
+        self.link.writer.write_all(&[INTERRUPT]).map_err(io_err)?;
+        self.link.recv()?;
+        self.running = false;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<(), String> {
+        self.ensure_halted()?;
+        self.link.set_timeout(self.timeout)?;
+        self.link.send(b"c")?;
+        self.running = true;
+        Ok(())
+    }
+
+    fn monitor(&mut self, command: &str) -> Result<String, String> {
+        self.ensure_halted()?;
+        let packet = format!("qRcmd,{}", hex(command.as_bytes()));
+        self.link.set_timeout(FLASH_TIMEOUT)?;
+        self.link.send(packet.as_bytes())?;
+        let mut output = Vec::new();
+        loop {
+            let reply = self.link.recv()?;
+            match reply.as_slice() {
+                b"OK" => break,
+                b"" => return Err("GDB server does not support monitor commands".to_string()),
+                [b'O', text @ ..] => output.extend(unhex(text)?),
+                [b'E', code @ ..] => {
+                    return Err(format!(
+                        "monitor command failed: error {}",
+                        String::from_utf8_lossy(code)
+                    ));
+                }
+                other => output.extend(unhex(other)?),
+            }
+        }
+        Ok(String::from_utf8_lossy(&output).into_owned())
+    }
+
+    fn memory_map(&mut self) -> Result<String, String> {
+        let chunk = self.hex_chunk();
+        let mut xml = Vec::new();
+        loop {
+            let packet = format!("qXfer:memory-map:read::{:x},{:x}", xml.len(), chunk);
+            let reply = self.request(packet.as_bytes())?;
+            match reply.split_first() {
+                Some((b'm', data)) if !data.is_empty() => xml.extend_from_slice(data),
+                Some((b'l', data)) => {
+                    xml.extend_from_slice(data);
+                    break;
+                }
+                _ => return Err("GDB server provides no memory map".to_string()),
+            }
+        }
+        String::from_utf8(xml).map_err(|_| "GDB memory map is not text".to_string())
+    }
+
+    fn flash(&mut self, path: &str, format: Option<&str>, base: u64) -> Result<(), String> {
+        self.ensure_halted()?;
+        let image = image::load_image(path, format, base)?;
+        let regions = flash_regions(&self.memory_map()?);
+        let in_flash = |a: u64| regions.iter().any(|r| r.range.contains(&a));
+        let ranges: Vec<Range<u64>> = image.segments.iter().map(|s| s.range()).collect();
+        for erase in erase_ranges(&regions, &ranges) {
+            let packet = format!(
+                "vFlashErase:{:x},{:x}",
+                erase.start,
+                erase.end - erase.start
+            );
+            self.expect_ok(packet.as_bytes(), FLASH_TIMEOUT)?;
+        }
+        // Escaping can double binary data.
+        let chunk_len = self.hex_chunk();
+        let mut flashed = false;
+        for segment in &image.segments {
+            let mut offset = 0;
+            while offset < segment.data.len() {
+                let address = segment.address + offset as u64;
+                // Split at flash region boundaries so each chunk is in or outside flash.
+                let in_region = in_flash(address);
+                let mut end = (offset + chunk_len).min(segment.data.len());
+                if let Some(boundary) = regions
+                    .iter()
+                    .flat_map(|r| [r.range.start, r.range.end])
+                    .filter(|b| *b > address && *b < segment.address + end as u64)
+                    .min()
+                {
+                    end = (boundary - segment.address) as usize;
+                }
+                let chunk = &segment.data[offset..end];
+                if in_region {
+                    let mut packet = format!("vFlashWrite:{:x}:", address).into_bytes();
+                    packet.extend_from_slice(chunk);
+                    self.expect_ok(&packet, self.timeout)?;
+                    flashed = true;
+                } else {
+                    self.write_memory(address, chunk)?;
+                }
+                offset = end;
+            }
+        }
+        if flashed {
+            self.expect_ok(b"vFlashDone", FLASH_TIMEOUT)?;
+        }
+        Ok(())
+    }
+
+    fn detach(&mut self) -> Result<(), String> {
+        self.halt()?;
+        self.expect_ok(b"D", self.timeout)
+    }
+}
+
+fn packet_name(payload: &[u8]) -> String {
+    let end = payload
+        .iter()
+        .position(|b| matches!(b, b':' | b',') || b.is_ascii_digit())
+        .unwrap_or(payload.len())
+        .max(1)
+        .min(payload.len());
+    String::from_utf8_lossy(&payload[..end]).into_owned()
+}
+
+static CLIENTS: OnceLock<Mutex<HashMap<u64, Arc<Mutex<GdbClient>>>>> = OnceLock::new();
+
+fn clients() -> &'static Mutex<HashMap<u64, Arc<Mutex<GdbClient>>>> {
+    CLIENTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn client(handle: u64) -> Result<Arc<Mutex<GdbClient>>, String> {
+    clients()
+        .lock()
+        .unwrap()
+        .get(&handle)
+        .cloned()
+        .ok_or_else(|| "invalid GDB connection handle".to_string())
+}
+
+/// Run `op` on the connection `handle`: 0 on success, -1 on an invalid handle, -2 on a
+/// connection, protocol or target error.
+fn with_client(handle: u64, op: impl FnOnce(&mut GdbClient) -> Result<(), String>) -> i32 {
+    let client = match client(handle) {
+        Ok(c) => c,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let mut client = client.lock().unwrap();
+    match op(&mut client) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(e);
+            -2
+        }
+    }
+}
+
+/// [`with_client`] for operations that need a stopped target; -1 if it is running.
+fn with_halted(handle: u64, op: impl FnOnce(&mut GdbClient) -> Result<(), String>) -> i32 {
+    let mut running = false;
+    let result = with_client(handle, |c| {
+        running = c.running;
+        if running { Ok(()) } else { op(c) }
+    });
+    if running {
+        set_error("target is running; halt it first".to_string());
+        return -1;
+    }
+    result
+}
+
+/// Connect to a GDB server at `address` ("host:port") and stop the target as GDB would.
+/// `timeout_ms` bounds the connection and every reply (0 = 5000); flashing waits longer.
+///
+/// Returns a connection handle, or 0 on error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_gdb_attach(address: *const c_char, timeout_ms: u32) -> u64 {
+    let address = match cstr_to_string(address) {
+        Ok(a) => a,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let timeout = match timeout_ms {
+        0 => DEFAULT_TIMEOUT,
+        ms => Duration::from_millis(ms as u64),
+    };
+    match GdbClient::connect(&address, timeout) {
+        Ok(client) => {
+            let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
+            clients()
+                .lock()
+                .unwrap()
+                .insert(handle, Arc::new(Mutex::new(client)));
+            handle
+        }
+        Err(e) => {
+            set_error(e);
+            0
+        }
+    }
+}
+
+/// Detach from the GDB server (the server decides whether the target runs) and close the
+/// connection. The handle is invalid afterwards, even on error.
+///
+/// Returns 0 on success, -1 on an invalid handle, -2 if the server did not acknowledge.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_gdb_detach(gdb: u64) -> i32 {
+    let result = with_client(gdb, GdbClient::detach);
+    clients().lock().unwrap().remove(&gdb);
+    result
+}
+
+/// Stop the target after `pr_gdb_continue`; a no-op if it is stopped.
+///
+/// Returns 0 on success, -1 on an invalid handle, -2 on a connection error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_gdb_halt(gdb: u64) -> i32 {
+    with_client(gdb, GdbClient::halt)
+}
+
+/// Let the target run. Until `pr_gdb_halt`, other calls fail with -1.
+///
+/// Returns 0 on success, -1 on an invalid handle or a running target, -2 on a connection
+/// error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_gdb_continue(gdb: u64) -> i32 {
+    with_halted(gdb, GdbClient::resume)
+}
+
+/// Read `len` bytes at `address` into `out`.
+///
+/// Returns 0 on success, -1 on invalid arguments or a running target, -2 on a connection or
+/// target error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_gdb_read_memory(gdb: u64, address: u64, out: *mut u8, len: usize) -> i32 {
+    if out.is_null() && len > 0 {
+        set_error("null buffer".to_string());
+        return -1;
+    }
+    with_halted(gdb, |c| {
+        let data = c.read_memory(address, len)?;
+        if len > 0 {
+            unsafe { std::slice::from_raw_parts_mut(out, len) }.copy_from_slice(&data);
+        }
+        Ok(())
+    })
+}
+
+/// Write `len` bytes from `data` at `address` (RAM or registers; use `pr_gdb_flash` for
+/// flash).
+///
+/// Returns 0 on success, -1 on invalid arguments or a running target, -2 on a connection or
+/// target error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_gdb_write_memory(gdb: u64, address: u64, data: *const u8, len: usize) -> i32 {
+    if data.is_null() && len > 0 {
+        set_error("null buffer".to_string());
+        return -1;
+    }
+    let data = if len > 0 {
+        unsafe { std::slice::from_raw_parts(data, len) }.to_vec()
+    } else {
+        Vec::new()
+    };
+    with_halted(gdb, |c| c.write_memory(address, &data))
+}
+
+/// Run a monitor command (`monitor reset` in GDB, e.g. "reset" or "reset halt" with
+/// `probe-rs gdb`) and write its output to `out`. If `out` is NULL or too small the output is
+/// kept, and a repeated call with the same command returns it without running it again.
+///
+/// Returns the required size including NUL, or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_gdb_monitor(
+    gdb: u64,
+    command: *const c_char,
+    out: *mut c_char,
+    out_len: usize,
+) -> usize {
+    let command = match cstr_to_string(command) {
+        Ok(c) => c,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let mut need = 0;
+    let result = with_halted(gdb, |c| {
+        let output = match c.pending_monitor.take() {
+            Some((cmd, output)) if cmd == command => output,
+            _ => c.monitor(&command)?,
+        };
+        need = write_c_str(&output, out, out_len);
+        if out.is_null() || out_len < need {
+            c.pending_monitor = Some((command, output));
+        }
+        Ok(())
+    });
+    if result == 0 { need } else { 0 }
+}
+
+/// Flash an image through the GDB server, erasing the flash blocks of its memory map that the
+/// image touches. Data outside of flash is written as memory. `format` and `base` are as for
+/// `pr_flash_check_fit`. The target is not reset; use `pr_gdb_monitor` for that.
+///
+/// Returns 0 on success, 1 on invalid arguments or a running target, 2 on a flash error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_gdb_flash(
+    gdb: u64,
+    path: *const c_char,
+    format: *const c_char,
+    base: u64,
+) -> i32 {
+    let args = cstr_to_string(path).and_then(|p| Ok((p, optional_str(format)?)));
+    let (path, format) = match args {
+        Ok(a) => a,
+        Err(e) => {
+            set_error(e);
+            return 1;
+        }
+    };
+    match with_halted(gdb, |c| c.flash(&path, format.as_deref(), base)) {
+        0 => 0,
+        -1 => 1,
+        _ => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+    use std::net::TcpListener;
+
+    const FLASH_SIZE: usize = 0x1000;
+    const RAM: u64 = 0x2000_0000;
+    const MEMORY_MAP: &str = concat!(
+        r#"<memory-map><memory type="ram" start="0x20000000" length="0x100"/>"#,
+        r#"<memory type="flash" start="0x0" length="0x1000">"#,
+        r#"<property name="blocksize">0x400</property></memory></memory-map>"#
+    );
+
+    fn parse_args(args: &[u8]) -> (u64, usize, &[u8]) {
+        let text = String::from_utf8_lossy(args);
+        let (addr, rest) = text.split_once(',').unwrap_or((&text, ""));
+        let len = rest.split(':').next().unwrap_or("0");
+        let data_start = args
+            .iter()
+            .position(|b| *b == b':')
+            .map_or(args.len(), |i| i + 1);
+        (
+            u64::from_str_radix(addr, 16).unwrap(),
+            usize::from_str_radix(len, 16).unwrap_or(0),
+            &args[data_start..],
+        )
+    }
+
+    struct Memory {
+        flash: Vec<u8>,
+        ram: Vec<u8>,
+    }
+
+    impl Memory {
+        fn get(&mut self, addr: u64, len: usize) -> Option<&mut [u8]> {
+            let (mem, offset) = if addr >= RAM {
+                (&mut self.ram, (addr - RAM) as usize)
+            } else {
+                (&mut self.flash, addr as usize)
+            };
+            mem.get_mut(offset..offset + len)
+        }
+    }
+
+    /// A GDB server with 4 KiB of flash at 0 and 256 bytes of RAM.
+    fn fake_server(stream: TcpStream) {
+        let mut link = Link::new(stream).unwrap();
+        let mut memory = Memory {
+            flash: vec![0u8; FLASH_SIZE],
+            ram: vec![0u8; 0x100],
+        };
+        let mut erased: Vec<Range<usize>> = Vec::new();
+        loop {
+            let Ok(packet) = link.recv() else { return };
+            let reply: Vec<u8> = match packet.as_slice() {
+                [INTERRUPT] => b"S02".to_vec(),
+                b"c" => continue,
+                b"?" => b"S05".to_vec(),
+                b"D" => {
+                    link.send(b"OK").unwrap();
+                    return;
+                }
+                b"vFlashDone" => b"OK".to_vec(),
+                p if p.starts_with(b"qSupported") => b"PacketSize=60".to_vec(),
+                p if p.starts_with(b"qXfer:memory-map:read::") => {
+                    let (offset, len, _) = parse_args(&p[23..]);
+                    let rest = &MEMORY_MAP.as_bytes()[offset as usize..];
+                    let (kind, part) = if rest.len() > len {
+                        (b'm', &rest[..len])
+                    } else {
+                        (b'l', rest)
+                    };
+                    [&[kind], part].concat()
+                }
+                p if p.starts_with(b"qRcmd,") => {
+                    link.send(format!("O{}", hex(b"Resetting target\n")).as_bytes())
+                        .unwrap();
+                    b"OK".to_vec()
+                }
+                [b'm', args @ ..] => {
+                    let (addr, len, _) = parse_args(args);
+                    match memory.get(addr, len) {
+                        Some(m) => hex(m).into_bytes(),
+                        None => b"E01".to_vec(),
+                    }
+                }
+                [b'M', args @ ..] => {
+                    let (addr, len, data) = parse_args(args);
+                    let data = unhex(data).unwrap();
+                    match memory.get(addr, len) {
+                        Some(m) if addr >= RAM => {
+                            m.copy_from_slice(&data);
+                            b"OK".to_vec()
+                        }
+                        _ => b"E01".to_vec(),
+                    }
+                }
+                p if p.starts_with(b"vFlashErase:") => {
+                    let (addr, len, _) = parse_args(&p[12..]);
+                    memory.get(addr, len).unwrap().fill(0xff);
+                    erased.push(addr as usize..addr as usize + len);
+                    b"OK".to_vec()
+                }
+                p if p.starts_with(b"vFlashWrite:") => {
+                    let rest = &p[12..];
+                    let colon = rest.iter().position(|b| *b == b':').unwrap();
+                    let addr =
+                        u64::from_str_radix(std::str::from_utf8(&rest[..colon]).unwrap(), 16)
+                            .unwrap();
+                    let data = &rest[colon + 1..];
+                    let range = addr as usize..addr as usize + data.len();
+                    if erased
+                        .iter()
+                        .any(|e| e.start <= range.start && range.end <= e.end)
+                    {
+                        memory.get(addr, data.len()).unwrap().copy_from_slice(data);
+                        b"OK".to_vec()
+                    } else {
+                        b"E02".to_vec()
+                    }
+                }
+                _ => Vec::new(),
+            };
+            if link.send(&reply).is_err() {
+                return;
+            }
+        }
+    }
+
+    fn start_fake_server() -> (String, std::thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let thread = std::thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            fake_server(stream);
+        });
+        (address, thread)
+    }
+
+    #[test]
+    fn memory_map_parsing() {
+        let regions = flash_regions(MEMORY_MAP);
+        assert_eq!(
+            regions,
+            vec![FlashRegion {
+                range: 0..0x1000,
+                block_size: 0x400
+            }]
+        );
+        assert_eq!(
+            erase_ranges(&regions, &[0x100..0x600, 0x7ff..0x801, 0x2000..0x2100]),
+            vec![0..0xc00]
+        );
+        assert_eq!(decode(b"0* }]"), b"0000}".to_vec());
+    }
+
+    #[test]
+    fn flash_and_memory_through_a_gdb_server() {
+        let (address, server) = start_fake_server();
+        let address = CString::new(address).unwrap();
+        let gdb = pr_gdb_attach(address.as_ptr(), 0);
+        assert_ne!(gdb, 0, "{}", crate::LAST_ERROR.with(|l| l.borrow().clone()));
+
+        // Binary data with bytes that need escaping.
+        let image: Vec<u8> = (0..0x500u32).map(|i| (i * 7) as u8 | 0x20).collect();
+        let path = std::env::temp_dir().join(format!("pr-gdb-{}.bin", std::process::id()));
+        std::fs::write(&path, &image).unwrap();
+        let path_c = CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(
+            pr_gdb_flash(gdb, path_c.as_ptr(), std::ptr::null(), 0x100),
+            0
+        );
+        std::fs::remove_file(&path).unwrap();
+
+        let mut readback = vec![0u8; image.len()];
+        assert_eq!(
+            pr_gdb_read_memory(gdb, 0x100, readback.as_mut_ptr(), readback.len()),
+            0
+        );
+        assert_eq!(readback, image);
+        let mut erased = [0u8; 4];
+        assert_eq!(pr_gdb_read_memory(gdb, 0x7fc, erased.as_mut_ptr(), 4), 0);
+        assert_eq!(erased, [0xff; 4]);
+
+        let words = [1u8, 2, 3, 4];
+        assert_eq!(pr_gdb_write_memory(gdb, RAM, words.as_ptr(), 4), 0);
+        let mut ram = [0u8; 4];
+        assert_eq!(pr_gdb_read_memory(gdb, RAM, ram.as_mut_ptr(), 4), 0);
+        assert_eq!(ram, words);
+
+        let reset = CString::new("reset").unwrap();
+        let need = pr_gdb_monitor(gdb, reset.as_ptr(), std::ptr::null_mut(), 0);
+        let mut out = vec![0u8; need];
+        assert_eq!(
+            pr_gdb_monitor(gdb, reset.as_ptr(), out.as_mut_ptr() as *mut c_char, need),
+            need
+        );
+        assert_eq!(&out[..need - 1], b"Resetting target\n");
+
+        assert_eq!(pr_gdb_continue(gdb), 0);
+        assert_eq!(pr_gdb_read_memory(gdb, RAM, ram.as_mut_ptr(), 4), -1);
+        assert_eq!(pr_gdb_halt(gdb), 0);
+        assert_eq!(pr_gdb_detach(gdb), 0);
+        assert_eq!(pr_gdb_halt(gdb), -1);
+        server.join().unwrap();
+    }
+}
@@ -0,0 +1,755 @@
+//! A GDB remote protocol server on an open session, so GDB or an IDE can debug the target
+//! through the library that flashed it instead of a separate GDB server owning the probe.
+//!
+//! One core is served, as a single thread, to one client at a time. Registers are those of the
+//! core's main group (no FPU registers); breakpoints are hardware breakpoints. GDB's `load`
+//! flashes through the `vFlash` packets with the memory map's flash regions, and `monitor reset`
+//! resets and halts the core.
+
+use crate::gdb_remote::{INTERRUPT, decode, frame, hex, unhex};
+use crate::{buffering, get_session, set_error};
+use probe_rs::flashing::{DownloadOptions, FlashLoader};
+use probe_rs::{
+    CoreRegister, CoreRegisters, CoreStatus, CoreType, InstructionSet, MemoryInterface,
+    RegisterValue, Session,
+};
+use probe_rs_target::MemoryRegion;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::io::{ErrorKind, Read, Write};
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// How often blocked accepts and reads look at the stop flag, and a running core at its state.
+const STOP_POLL: Duration = Duration::from_millis(100);
+const HALT_TIMEOUT: Duration = Duration::from_millis(500);
+/// Announced to GDB, in bytes of packet data.
+const PACKET_SIZE: usize = 0x4000;
+
+struct Server {
+    port: u16,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+static SERVERS: OnceLock<Mutex<HashMap<u64, Server>>> = OnceLock::new();
+
+fn servers() -> &'static Mutex<HashMap<u64, Server>> {
+    SERVERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Stop the server of a session, e.g. before it is closed.
+pub(crate) fn stop_for_session(session: u64) {
+    let server = servers().lock().unwrap().remove(&session);
+    if let Some(server) = server {
+        server.stop.store(true, Ordering::Relaxed);
+        let _ = server.thread.join();
+    }
+}
+
+/// The registers GDB sees, in its register numbering, with the names GDB expects.
+struct Register {
+    name: String,
+    reg: &'static CoreRegister,
+}
+
+impl Register {
+    fn size(&self) -> usize {
+        self.reg.size_in_bits() / 8
+    }
+}
+
+fn architecture(
+    core_type: CoreType,
+    isa: InstructionSet,
+) -> Result<(&'static str, &'static str), String> {
+    Ok(match (core_type, isa) {
+        (CoreType::Armv6m, _) => ("armv6-m", "org.gnu.gdb.arm.m-profile"),
+        (CoreType::Armv7m, _) => ("armv7", "org.gnu.gdb.arm.m-profile"),
+        (CoreType::Armv7em, _) => ("armv7e-m", "org.gnu.gdb.arm.m-profile"),
+        (CoreType::Armv8m, _) => ("armv8-m.main", "org.gnu.gdb.arm.m-profile"),
+        (CoreType::Armv7a, _) => ("armv7", "org.gnu.gdb.arm.core"),
+        (CoreType::Armv8a, InstructionSet::A64) => ("aarch64", "org.gnu.gdb.aarch64.core"),
+        (CoreType::Armv8a, _) => ("armv8-a", "org.gnu.gdb.arm.core"),
+        (CoreType::Riscv, _) => ("riscv:rv32", "org.gnu.gdb.riscv.cpu"),
+        (CoreType::Xtensa, _) => return Err("GDB server does not support Xtensa cores".to_string()),
+    })
+}
+
+/// The main register group: the core registers with the program counter, then the status
+/// register, then the other core registers (e.g. MSP and PSP).
+fn registers(regs: &'static CoreRegisters) -> Vec<Register> {
+    let psr = regs.psr();
+    let mut list: Vec<&'static CoreRegister> = regs
+        .core_registers()
+        .filter(|r| Some(r.id()) != psr.map(|p| p.id()))
+        .collect();
+    let pc = match list
+        .iter()
+        .position(|r| regs.pc().is_some_and(|pc| pc.id() == r.id()))
+    {
+        Some(pc) => pc,
+        None => {
+            list.extend(regs.pc());
+            list.len() - 1
+        }
+    };
+    if let Some(psr) = psr {
+        list.insert(pc + 1, psr);
+    }
+    list.into_iter()
+        .map(|reg| {
+            let name = match reg.name() {
+                "R13" => "sp",
+                "R14" => "lr",
+                "R15" => "pc",
+                "PSTATE" => "cpsr",
+                name => name,
+            };
+            Register {
+                name: name.to_lowercase(),
+                reg,
+            }
+        })
+        .collect()
+}
+
+fn target_xml(arch: &str, feature: &str, regs: &[Register]) -> String {
+    let mut xml = format!(
+        "<?xml version=\"1.0\"?><!DOCTYPE target SYSTEM \"gdb-target.dtd\"><target version=\"1.0\">\
+         <architecture>{}</architecture><feature name=\"{}\">",
+        arch, feature
+    );
+    for reg in regs {
+        let ty = match reg.name.as_str() {
+            "pc" => "code_ptr",
+            "sp" => "data_ptr",
+            _ => "int",
+        };
+        let _ = write!(
+            xml,
+            "<reg name=\"{}\" bitsize=\"{}\" type=\"{}\"/>",
+            reg.name,
+            reg.reg.size_in_bits(),
+            ty
+        );
+    }
+    xml.push_str("</feature></target>");
+    xml
+}
+
+/// The memory map of the target: NVM covered by a flash algorithm is flash, with the size of
+/// its first sector as block size, other NVM is read-only.
+fn memory_map_xml(session: &Session) -> String {
+    let target = session.target();
+    let mut xml = "<?xml version=\"1.0\"?><!DOCTYPE memory-map PUBLIC \"+//IDN gnu.org//DTD GDB \
+                   Memory Map V1.0//EN\" \"http://sourceware.org/gdb/gdb-memory-map.dtd\">\
+                   <memory-map>"
+        .to_string();
+    for region in &target.memory_map {
+        let range = region.address_range();
+        let (start, length) = (range.start, range.end - range.start);
+        let block_size = target
+            .flash_algorithms
+            .iter()
+            .filter(|a| a.flash_properties.address_range.contains(&start))
+            .find_map(|a| a.flash_properties.sectors.first())
+            .map(|s| s.size);
+        let _ = match (region, block_size) {
+            (MemoryRegion::Nvm(_), Some(block_size)) => write!(
+                xml,
+                "<memory type=\"flash\" start=\"{:#x}\" length=\"{:#x}\">\
+                 <property name=\"blocksize\">{:#x}</property></memory>",
+                start, length, block_size
+            ),
+            (MemoryRegion::Ram(_), _) => write!(
+                xml,
+                "<memory type=\"ram\" start=\"{:#x}\" length=\"{:#x}\"/>",
+                start, length
+            ),
+            _ => write!(
+                xml,
+                "<memory type=\"rom\" start=\"{:#x}\" length=\"{:#x}\"/>",
+                start, length
+            ),
+        };
+    }
+    xml.push_str("</memory-map>");
+    xml
+}
+
+/// Take the next packet or interrupt out of `buf`, dropping acknowledgements and noise.
+/// Returns `Some(Err(()))` for a packet with a bad checksum.
+fn take_packet(buf: &mut Vec<u8>) -> Option<Result<Vec<u8>, ()>> {
+    let start = buf.iter().position(|&b| b == b'$' || b == INTERRUPT);
+    let Some(start) = start else {
+        buf.clear();
+        return None;
+    };
+    buf.drain(..start);
+    if buf[0] == INTERRUPT {
+        buf.remove(0);
+        return Some(Ok(vec![INTERRUPT]));
+    }
+    let end = buf.iter().position(|&b| b == b'#')?;
+    if buf.len() < end + 3 {
+        return None;
+    }
+    let raw = &buf[1..end];
+    let sum = std::str::from_utf8(&buf[end + 1..end + 3])
+        .ok()
+        .and_then(|s| u8::from_str_radix(s, 16).ok());
+    let packet = match sum == Some(raw.iter().fold(0u8, |s, b| s.wrapping_add(*b))) {
+        true => Ok(decode(raw)),
+        false => Err(()),
+    };
+    buf.drain(..end + 3);
+    Some(packet)
+}
+
+fn parse_hex(text: &[u8]) -> Option<u64> {
+    u64::from_str_radix(std::str::from_utf8(text).ok()?, 16).ok()
+}
+
+/// `addr,len` of `m`, `M`, `X`, `Z` and the vFlash packets.
+fn address_length(args: &[u8]) -> Option<(u64, u64)> {
+    let comma = args.iter().position(|&b| b == b',')?;
+    Some((parse_hex(&args[..comma])?, parse_hex(&args[comma + 1..])?))
+}
+
+/// The `offset,length` window of a `qXfer` document, `m` if more follows, `l` if it is the last.
+fn xfer_chunk(document: &str, args: &[u8]) -> Vec<u8> {
+    let Some((offset, length)) = address_length(args) else {
+        return b"E01".to_vec();
+    };
+    let data = document.as_bytes();
+    let start = (offset as usize).min(data.len());
+    let end = start.saturating_add(length as usize).min(data.len());
+    let mut reply = vec![if end < data.len() { b'm' } else { b'l' }];
+    reply.extend_from_slice(&data[start..end]);
+    reply
+}
+
+struct Connection {
+    stream: TcpStream,
+    buf: Vec<u8>,
+    /// Acknowledge packets, until GDB asks for `QStartNoAckMode`.
+    ack: bool,
+}
+
+impl Connection {
+    fn send(&mut self, payload: &[u8]) -> std::io::Result<()> {
+        self.stream.write_all(&frame(payload))
+    }
+
+    /// The next packet, or None if none arrived within [`STOP_POLL`].
+    fn recv(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        loop {
+            match take_packet(&mut self.buf) {
+                Some(Ok(packet)) => {
+                    if self.ack && packet != [INTERRUPT] {
+                        self.stream.write_all(b"+")?;
+                    }
+                    return Ok(Some(packet));
+                }
+                Some(Err(())) => self.stream.write_all(b"-")?,
+                None => break,
+            }
+        }
+        let mut chunk = [0u8; 4096];
+        match self.stream.read(&mut chunk) {
+            Ok(0) => Err(ErrorKind::ConnectionAborted.into()),
+            Ok(n) => {
+                self.buf.extend_from_slice(&chunk[..n]);
+                Ok(None)
+            }
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// What to do after a packet.
+enum Next {
+    Reply(Vec<u8>),
+    /// The core was resumed; the stop reply is sent when it halts.
+    Running,
+    /// The client detached or killed the target.
+    End(Option<Vec<u8>>),
+}
+
+fn reply(text: impl AsRef<[u8]>) -> Result<Next, probe_rs::Error> {
+    Ok(Next::Reply(text.as_ref().to_vec()))
+}
+
+struct Target {
+    session: Arc<Mutex<Session>>,
+    core: usize,
+    regs: Vec<Register>,
+    target_xml: String,
+    memory_map: String,
+    /// Data of `vFlashWrite` packets, programmed by `vFlashDone`.
+    loader: Option<FlashLoader>,
+}
+
+impl Target {
+    fn new(session: Arc<Mutex<Session>>, core: usize) -> Result<Self, String> {
+        let (regs, target_xml, memory_map) = {
+            let mut lock = session.lock().unwrap();
+            let memory_map = memory_map_xml(&lock);
+            let mut core = lock
+                .core(core)
+                .map_err(|e| format!("core access error: {}", e))?;
+            let isa = core
+                .instruction_set()
+                .map_err(|e| format!("core access error: {}", e))?;
+            let (arch, feature) = architecture(core.core_type(), isa)?;
+            let regs = registers(core.registers());
+            let xml = target_xml(arch, feature, &regs);
+            (regs, xml, memory_map)
+        };
+        Ok(Self {
+            session,
+            core,
+            regs,
+            target_xml,
+            memory_map,
+            loader: None,
+        })
+    }
+
+    fn with_core<T>(
+        &self,
+        op: impl FnOnce(&mut probe_rs::Core) -> Result<T, probe_rs::Error>,
+    ) -> Result<T, probe_rs::Error> {
+        let mut lock = self.session.lock().unwrap();
+        op(&mut lock.core(self.core)?)
+    }
+
+    fn read_register(
+        &self,
+        core: &mut probe_rs::Core,
+        reg: &Register,
+    ) -> Result<String, probe_rs::Error> {
+        let bytes = match core.read_core_reg::<RegisterValue>(reg.reg)? {
+            RegisterValue::U32(v) => v.to_le_bytes().to_vec(),
+            RegisterValue::U64(v) => v.to_le_bytes().to_vec(),
+            RegisterValue::U128(v) => v.to_le_bytes().to_vec(),
+        };
+        Ok(hex(&bytes[..reg.size().min(bytes.len())]))
+    }
+
+    fn write_register(
+        &self,
+        core: &mut probe_rs::Core,
+        reg: &Register,
+        data: &[u8],
+    ) -> Result<(), probe_rs::Error> {
+        let mut bytes = [0u8; 16];
+        let n = data.len().min(16);
+        bytes[..n].copy_from_slice(&data[..n]);
+        let value = match reg.size() {
+            4 => RegisterValue::U32(u32::from_le_bytes(bytes[..4].try_into().unwrap())),
+            8 => RegisterValue::U64(u64::from_le_bytes(bytes[..8].try_into().unwrap())),
+            _ => RegisterValue::U128(u128::from_le_bytes(bytes)),
+        };
+        core.write_core_reg(reg.reg, value)
+    }
+
+    fn handle(&mut self, packet: &[u8]) -> Result<Next, probe_rs::Error> {
+        let bad = || Ok(Next::Reply(b"E01".to_vec()));
+        let (&kind, args) = match packet.split_first() {
+            Some(split) => split,
+            None => return reply(""),
+        };
+        match kind {
+            b'?' => reply("S05"),
+            b'g' => {
+                let text = self.with_core(|core| {
+                    let mut text = String::new();
+                    for reg in &self.regs {
+                        text.push_str(&self.read_register(core, reg)?);
+                    }
+                    Ok(text)
+                })?;
+                reply(text)
+            }
+            b'G' => {
+                let Ok(data) = unhex(args) else { return bad() };
+                self.with_core(|core| {
+                    let mut offset = 0;
+                    for reg in &self.regs {
+                        let end = offset + reg.size();
+                        if end > data.len() {
+                            break;
+                        }
+                        self.write_register(core, reg, &data[offset..end])?;
+                        offset = end;
+                    }
+                    Ok(())
+                })?;
+                reply("OK")
+            }
+            b'p' => {
+                let Some(reg) = parse_hex(args).and_then(|n| self.regs.get(n as usize)) else {
+                    return bad();
+                };
+                reply(self.with_core(|core| self.read_register(core, reg))?)
+            }
+            b'P' => {
+                let Some(eq) = args.iter().position(|&b| b == b'=') else {
+                    return bad();
+                };
+                let reg = parse_hex(&args[..eq]).and_then(|n| self.regs.get(n as usize));
+                let (Some(reg), Ok(data)) = (reg, unhex(&args[eq + 1..])) else {
+                    return bad();
+                };
+                self.with_core(|core| self.write_register(core, reg, &data))?;
+                reply("OK")
+            }
+            b'm' => {
+                let Some((address, len)) = address_length(args) else {
+                    return bad();
+                };
+                let mut data = vec![0u8; (len as usize).min(PACKET_SIZE / 2)];
+                self.with_core(|core| core.read(address, &mut data))?;
+                reply(hex(&data))
+            }
+            b'M' | b'X' => {
+                let Some(colon) = args.iter().position(|&b| b == b':') else {
+                    return bad();
+                };
+                let Some((address, len)) = address_length(&args[..colon]) else {
+                    return bad();
+                };
+                let data = match kind {
+                    b'M' => match unhex(&args[colon + 1..]) {
+                        Ok(data) => data,
+                        Err(_) => return bad(),
+                    },
+                    _ => args[colon + 1..].to_vec(),
+                };
+                if data.len() as u64 != len {
+                    return bad();
+                }
+                self.with_core(|core| core.write(address, &data))?;
+                reply("OK")
+            }
+            b'c' => {
+                self.with_core(|core| core.run())?;
+                Ok(Next::Running)
+            }
+            b's' => {
+                self.with_core(|core| core.step())?;
+                reply("S05")
+            }
+            b'Z' | b'z' => {
+                let (Some(&ty), Some(args)) = (args.first(), args.get(2..)) else {
+                    return bad();
+                };
+                let Some((address, _)) = address_length(args) else {
+                    return bad();
+                };
+                if !matches!(ty, b'0' | b'1') {
+                    // Watchpoints are not supported.
+                    return reply("");
+                }
+                self.with_core(|core| match kind {
+                    b'Z' => core.set_hw_breakpoint(address),
+                    _ => core.clear_hw_breakpoint(address),
+                })?;
+                reply("OK")
+            }
+            b'D' => {
+                self.with_core(|core| {
+                    core.clear_all_hw_breakpoints()?;
+                    core.run()
+                })?;
+                Ok(Next::End(Some(b"OK".to_vec())))
+            }
+            b'k' => Ok(Next::End(None)),
+            b'H' | b'T' => reply("OK"),
+            b'q' | b'Q' => self.query(packet),
+            b'v' => self.v_packet(packet),
+            _ => reply(""),
+        }
+    }
+
+    fn query(&mut self, packet: &[u8]) -> Result<Next, probe_rs::Error> {
+        if packet.starts_with(b"qSupported") {
+            return reply(format!(
+                "PacketSize={:x};qXfer:features:read+;qXfer:memory-map:read+;\
+                 QStartNoAckMode+;vContSupported+",
+                PACKET_SIZE
+            ));
+        }
+        if let Some(args) = packet.strip_prefix(b"qXfer:features:read:target.xml:") {
+            return Ok(Next::Reply(xfer_chunk(&self.target_xml, args)));
+        }
+        if let Some(args) = packet.strip_prefix(b"qXfer:memory-map:read::") {
+            return Ok(Next::Reply(xfer_chunk(&self.memory_map, args)));
+        }
+        if let Some(command) = packet.strip_prefix(b"qRcmd,") {
+            let Ok(command) = unhex(command) else {
+                return reply("E01");
+            };
+            return match String::from_utf8_lossy(&command).trim() {
+                "reset" | "reset halt" => {
+                    self.with_core(|core| core.reset_and_halt(HALT_TIMEOUT))?;
+                    reply(hex(b"Target reset and halted\n"))
+                }
+                other => reply(hex(format!("Unknown command: {}\n", other).as_bytes())),
+            };
+        }
+        match packet {
+            b"qAttached" => reply("1"),
+            b"qC" => reply("QC1"),
+            b"qfThreadInfo" => reply("m1"),
+            b"qsThreadInfo" => reply("l"),
+            _ => reply(""),
+        }
+    }
+
+    fn v_packet(&mut self, packet: &[u8]) -> Result<Next, probe_rs::Error> {
+        if packet == b"vCont?" {
+            return reply("vCont;c;C;s;S");
+        }
+        if let Some(actions) = packet.strip_prefix(b"vCont;") {
+            // One thread: the first action applies to it.
+            return match actions.first() {
+                Some(b'c' | b'C') => self.handle(b"c"),
+                Some(b's' | b'S') => self.handle(b"s"),
+                _ => reply("E01"),
+            };
+        }
+        if packet.starts_with(b"vFlashErase:") {
+            // The loader erases the sectors it programs.
+            self.loader
+                .get_or_insert_with(|| self.session.lock().unwrap().target().flash_loader());
+            return reply("OK");
+        }
+        if let Some(args) = packet.strip_prefix(b"vFlashWrite:") {
+            let Some(colon) = args.iter().position(|&b| b == b':') else {
+                return reply("E01");
+            };
+            let Some(address) = parse_hex(&args[..colon]) else {
+                return reply("E01");
+            };
+            let session = &self.session;
+            let loader = self
+                .loader
+                .get_or_insert_with(|| session.lock().unwrap().target().flash_loader());
+            return match loader.add_data(address, &args[colon + 1..]) {
+                Ok(()) => reply("OK"),
+                Err(e) => {
+                    tracing::warn!("GDB flash write at {:#x}: {}", address, e);
+                    reply("E.memtype")
+                }
+            };
+        }
+        if packet == b"vFlashDone" {
+            let Some(loader) = self.loader.take() else {
+                return reply("OK");
+            };
+            let mut lock = self.session.lock().unwrap();
+            let mut opts = DownloadOptions::default();
+            buffering::apply_to(&mut opts);
+            return match loader.commit(&mut lock, opts) {
+                Ok(()) => reply("OK"),
+                Err(e) => {
+                    tracing::warn!("GDB flash error: {}", e);
+                    reply("E01")
+                }
+            };
+        }
+        reply("")
+    }
+}
+
+fn serve(target: &mut Target, stream: TcpStream, stop: &AtomicBool) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_nodelay(true)?;
+    stream.set_read_timeout(Some(STOP_POLL))?;
+    let mut conn = Connection {
+        stream,
+        buf: Vec::new(),
+        ack: true,
+    };
+    // GDB expects a stopped target when it attaches.
+    if let Err(e) = target.with_core(|core| core.halt(HALT_TIMEOUT)) {
+        tracing::warn!("GDB server cannot halt the core: {}", e);
+    }
+    let mut running = false;
+    while !stop.load(Ordering::Relaxed) {
+        if let Some(packet) = conn.recv()? {
+            if packet == [INTERRUPT] {
+                if running {
+                    running = false;
+                    let halted = target.with_core(|core| core.halt(HALT_TIMEOUT));
+                    conn.send(if halted.is_ok() { b"S02" } else { b"E01" })?;
+                }
+                continue;
+            }
+            if packet == b"QStartNoAckMode" {
+                conn.send(b"OK")?;
+                conn.ack = false;
+                continue;
+            }
+            match target.handle(&packet) {
+                Ok(Next::Reply(payload)) => conn.send(&payload)?,
+                Ok(Next::Running) => running = true,
+                Ok(Next::End(payload)) => {
+                    if let Some(payload) = payload {
+                        conn.send(&payload)?;
+                    }
+                    return Ok(());
+                }
+                Err(e) => {
+                    tracing::debug!("GDB packet failed: {}", e);
+                    conn.send(b"E01")?;
+                }
+            }
+        } else if running {
+            match target.with_core(|core| core.status()) {
+                Ok(CoreStatus::Halted(_)) => {
+                    running = false;
+                    conn.send(b"S05")?;
+                }
+                Ok(_) => {}
+                Err(e) => tracing::debug!("GDB server core status: {}", e),
+            }
+        }
+    }
+    Ok(())
+}
+
+fn accept_loop(listener: TcpListener, mut target: Target, stop: Arc<AtomicBool>) {
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                if let Err(e) = serve(&mut target, stream, &stop) {
+                    tracing::debug!("GDB connection from {} ended: {}", peer, e);
+                }
+                target.loader = None;
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(STOP_POLL),
+            Err(e) => {
+                tracing::warn!("GDB server accept error: {}", e);
+                std::thread::sleep(STOP_POLL);
+            }
+        }
+    }
+}
+
+/// Serve core `core_index` of a session to GDB on `127.0.0.1:port` (0 picks a free port), e.g.
+/// `target extended-remote :1337`. The core is halted when a client connects; the session
+/// stays usable from other calls, which share the probe with the server.
+///
+/// Returns the port listened on, -1 on invalid handle or core, an unsupported architecture or
+/// if the session is served already, -2 if the port cannot be bound.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_gdb_server_start(session: u64, core_index: u32, port: u16) -> i32 {
+    let sess = match get_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let mut map = servers().lock().unwrap();
+    if map.contains_key(&session) {
+        set_error("GDB server already running for this session".to_string());
+        return -1;
+    }
+    let target = match Target::new(sess, core_index as usize) {
+        Ok(target) => target,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            set_error(format!("bind error: {}", e));
+            return -2;
+        }
+    };
+    let port = match listener
+        .set_nonblocking(true)
+        .and_then(|_| listener.local_addr())
+    {
+        Ok(addr) => addr.port(),
+        Err(e) => {
+            set_error(format!("listen error: {}", e));
+            return -2;
+        }
+    };
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let stop = stop.clone();
+        std::thread::spawn(move || accept_loop(listener, target, stop))
+    };
+    map.insert(session, Server { port, stop, thread });
+    port.into()
+}
+
+/// Stop the GDB server of a session; a connected client is dropped within 100 ms. Closing the
+/// session stops it too.
+///
+/// Returns the port it listened on, or -1 if the session is not served.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_gdb_server_stop(session: u64) -> i32 {
+    let Some(port) = servers().lock().unwrap().get(&session).map(|s| s.port) else {
+        set_error("GDB server not running for this session".to_string());
+        return -1;
+    };
+    stop_for_session(session);
+    port.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use probe_rs::architecture::arm::core::registers::cortex_m::CORTEX_M_CORE_REGISTERS;
+
+    #[test]
+    fn packets_are_split_from_the_stream() {
+        let mut buf = b"+$m0,4#fd\x03$g#00$qC".to_vec();
+        assert_eq!(take_packet(&mut buf), Some(Ok(b"m0,4".to_vec())));
+        assert_eq!(take_packet(&mut buf), Some(Ok(vec![INTERRUPT])));
+        assert_eq!(take_packet(&mut buf), Some(Err(())));
+        assert_eq!(take_packet(&mut buf), None);
+        buf.extend_from_slice(b"#b4");
+        assert_eq!(take_packet(&mut buf), Some(Ok(b"qC".to_vec())));
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn cortex_m_description() {
+        let regs = registers(&CORTEX_M_CORE_REGISTERS);
+        let names: Vec<&str> = regs.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(
+            names[..17],
+            [
+                "r0", "r1", "r2", "r3", "r4", "r5", "r6", "r7", "r8", "r9", "r10", "r11", "r12",
+                "sp", "lr", "pc", "xpsr"
+            ]
+        );
+        assert_eq!(names.iter().filter(|n| **n == "xpsr").count(), 1);
+        let (arch, feature) = architecture(CoreType::Armv7em, InstructionSet::Thumb2).unwrap();
+        let xml = target_xml(arch, feature, &regs);
+        assert!(xml.contains("<feature name=\"org.gnu.gdb.arm.m-profile\">"));
+        assert!(xml.contains("<reg name=\"pc\" bitsize=\"32\" type=\"code_ptr\"/>"));
+        assert!(architecture(CoreType::Xtensa, InstructionSet::Xtensa).is_err());
+
+        assert_eq!(xfer_chunk("abcdef", b"0,4"), b"mabcd");
+        assert_eq!(xfer_chunk("abcdef", b"4,100"), b"lef");
+        assert_eq!(address_length(b"20000000,10"), Some((0x2000_0000, 0x10)));
+        assert_eq!(pr_gdb_server_start(0, 0, 0), -1);
+        assert_eq!(pr_gdb_server_stop(0), -1);
+    }
+}
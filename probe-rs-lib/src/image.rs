@@ -0,0 +1,611 @@
+//! Image files (ELF, Intel HEX, BIN) parsed on the host, for checks that run before any
+//! target is attached.
+
+use crate::{cstr_to_string, detect_format_kind, registry, set_error, write_c_str};
+use object::elf::{EM_ARM, FileHeader32, FileHeader64, PT_LOAD, SHF_ALLOC};
+use object::read::elf::{ElfFile, FileHeader, ProgramHeader, SectionHeader};
+use object::{Endianness, Object, ObjectSection};
+use probe_rs::config::Target;
+use probe_rs::flashing::FormatKind;
+use probe_rs_target::MemoryRegion;
+use serde::Serialize;
+use std::ffi::c_char;
+use std::ops::Range;
+use std::str::FromStr;
+
+/// A contiguous chunk of image data at its load address.
+pub(crate) struct Segment {
+    pub(crate) address: u64,
+    pub(crate) data: Vec<u8>,
+}
+
+impl Segment {
+    pub(crate) fn range(&self) -> Range<u64> {
+        self.address..self.address + self.data.len() as u64
+    }
+}
+
+/// Resolve the image format from its name (`"elf"`, `"hex"`, `"bin"`, ...), or from the file
+/// extension if no name is given.
+fn format_kind(path: &str, format: Option<&str>) -> Result<FormatKind, String> {
+    match format.filter(|f| !f.is_empty()) {
+        Some(name) => FormatKind::from_str(name),
+        None => detect_format_kind(path)
+            .or_else(|| {
+                path.to_ascii_lowercase()
+                    .ends_with(".bin")
+                    .then_some(FormatKind::Bin)
+            })
+            .ok_or_else(|| "unsupported file format extension".to_string()),
+    }
+}
+
+/// An allocated ELF section.
+#[derive(Serialize, Debug)]
+pub(crate) struct Section {
+    name: String,
+    /// Run-time (virtual) address.
+    address: u64,
+    /// Address the contents are programmed at; null for sections without file data (`.bss`).
+    load_address: Option<u64>,
+    size: u64,
+}
+
+/// The loadable contents of an image file.
+pub(crate) struct Image {
+    pub(crate) segments: Vec<Segment>,
+    /// Allocated sections; only known for ELF images.
+    pub(crate) sections: Vec<Section>,
+    pub(crate) entry: Option<u64>,
+}
+
+fn parse_elf<T: FileHeader<Endian = Endianness>>(data: &[u8]) -> Result<Image, String> {
+    let err = |e: object::Error| format!("failed to parse elf: {}", e);
+    let header = T::parse(data).map_err(err)?;
+    let endian = header.endian().map_err(err)?;
+    let file = ElfFile::<T>::parse(data).map_err(err)?;
+    let loads: Vec<_> = header
+        .program_headers(endian, data)
+        .map_err(err)?
+        .iter()
+        .filter(|ph| ph.p_type(endian) == PT_LOAD)
+        .collect();
+
+    let mut segments = Vec::new();
+    for ph in &loads {
+        let Ok(bytes) = ph.data(endian, data) else {
+            continue;
+        };
+        if bytes.is_empty() {
+            continue;
+        }
+        // Like the flash loader, only program segments that contain sections with data.
+        let (offset, size) = ph.file_range(endian);
+        let has_sections = file.sections().any(|s| {
+            s.file_range()
+                .is_some_and(|(start, len)| start >= offset && start + len <= offset + size)
+        });
+        if has_sections {
+            segments.push(Segment {
+                address: ph.p_paddr(endian).into(),
+                data: bytes.to_vec(),
+            });
+        }
+    }
+
+    let mut sections = Vec::new();
+    for section in file.sections() {
+        let flags = section.elf_section_header().sh_flags(endian).into();
+        if flags & u64::from(SHF_ALLOC) == 0 || section.size() == 0 {
+            continue;
+        }
+        let load_address = section.file_range().and_then(|(start, _)| {
+            loads.iter().find_map(|ph| {
+                let (offset, size) = ph.file_range(endian);
+                (start >= offset && start < offset + size)
+                    .then(|| ph.p_paddr(endian).into() + (start - offset))
+            })
+        });
+        sections.push(Section {
+            name: section.name().unwrap_or_default().to_string(),
+            address: section.address(),
+            load_address,
+            size: section.size(),
+        });
+    }
+
+    let entry = if header.e_machine(endian) == EM_ARM {
+        file.entry() & !1
+    } else {
+        file.entry()
+    };
+    Ok(Image {
+        segments,
+        sections,
+        entry: Some(entry),
+    })
+}
+
+fn parse_hex(text: &str) -> Result<Image, String> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut entry = None;
+    let mut base = 0u64;
+    for record in ihex::Reader::new(text) {
+        match record.map_err(|e| format!("failed to parse hex: {}", e))? {
+            ihex::Record::Data { offset, value } => {
+                let address = base + offset as u64;
+                match segments.last_mut() {
+                    Some(last) if last.range().end == address => last.data.extend(value),
+                    _ => segments.push(Segment {
+                        address,
+                        data: value,
+                    }),
+                }
+            }
+            ihex::Record::ExtendedSegmentAddress(address) => base = (address as u64) * 16,
+            ihex::Record::ExtendedLinearAddress(address) => base = (address as u64) << 16,
+            ihex::Record::StartLinearAddress(address) => entry = Some(address as u64),
+            _ => {}
+        }
+    }
+    Ok(Image {
+        segments,
+        sections: Vec::new(),
+        entry,
+    })
+}
+
+/// Load an image file. `base` is the load address of BIN images.
+pub(crate) fn load_image(path: &str, format: Option<&str>, base: u64) -> Result<Image, String> {
+    let kind = format_kind(path, format)?;
+    let data = std::fs::read(path).map_err(|e| format!("failed to read image: {}", e))?;
+    let image = match kind {
+        FormatKind::Elf => match object::FileKind::parse(&*data) {
+            Ok(object::FileKind::Elf32) => parse_elf::<FileHeader32<Endianness>>(&data)?,
+            Ok(object::FileKind::Elf64) => parse_elf::<FileHeader64<Endianness>>(&data)?,
+            _ => return Err("failed to parse elf: not an ELF file".to_string()),
+        },
+        FormatKind::Hex => {
+            let text = String::from_utf8(data).map_err(|_| "hex file is not text".to_string())?;
+            parse_hex(&text)?
+        }
+        FormatKind::Bin => Image {
+            segments: vec![Segment {
+                address: base,
+                data,
+            }],
+            sections: Vec::new(),
+            entry: None,
+        },
+        other => return Err(format!("{:?} images are not supported here", other)),
+    };
+    if image.segments.is_empty() {
+        return Err("image contains no loadable data".to_string());
+    }
+    Ok(image)
+}
+
+#[derive(Serialize, Debug)]
+struct Span {
+    start: u64,
+    end: u64,
+    region: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct Blocked {
+    start: u64,
+    end: u64,
+    region: Option<String>,
+    reason: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+struct Misaligned {
+    address: u64,
+    alignment: u64,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct FitReport {
+    fits: bool,
+    bytes: u64,
+    /// Image data that lands in flash or RAM.
+    placed: Vec<Span>,
+    /// Image data outside of every memory region.
+    overflows: Vec<Span>,
+    /// Image data in regions that cannot be programmed.
+    non_writable: Vec<Blocked>,
+    /// Data blocks that do not start on a flash page boundary (warnings only).
+    misaligned: Vec<Misaligned>,
+}
+
+fn region_name(region: &MemoryRegion) -> Option<String> {
+    match region {
+        MemoryRegion::Nvm(r) => r.name.clone(),
+        MemoryRegion::Ram(r) => r.name.clone(),
+        MemoryRegion::Generic(r) => r.name.clone(),
+    }
+}
+
+/// The address ranges of the segments in address order, adjacent and overlapping segments
+/// merged: they are programmed as one block.
+pub(crate) fn blocks(segments: &[Segment]) -> Vec<Range<u64>> {
+    let mut blocks: Vec<Range<u64>> = segments.iter().map(Segment::range).collect();
+    blocks.sort_by_key(|b| b.start);
+    blocks.dedup_by(|next, prev| {
+        if next.start <= prev.end {
+            prev.end = prev.end.max(next.end);
+            true
+        } else {
+            false
+        }
+    });
+    blocks
+}
+
+fn check_fit(target: &Target, segments: &[Segment]) -> FitReport {
+    let blocks = blocks(segments);
+    let mut report = FitReport {
+        bytes: segments.iter().map(|s| s.data.len() as u64).sum(),
+        ..Default::default()
+    };
+    for block in &blocks {
+        let mut address = block.start;
+        while address < block.end {
+            let Some(region) = target.memory_map.iter().find(|r| r.contains(address)) else {
+                let end = target
+                    .memory_map
+                    .iter()
+                    .map(|r| r.address_range().start)
+                    .filter(|&start| start > address)
+                    .min()
+                    .unwrap_or(block.end)
+                    .min(block.end);
+                report.overflows.push(Span {
+                    start: address,
+                    end,
+                    region: None,
+                });
+                address = end;
+                continue;
+            };
+            let end = region.address_range().end.min(block.end);
+            let blocked = |reason| Blocked {
+                start: address,
+                end,
+                region: region_name(region),
+                reason,
+            };
+            match region {
+                MemoryRegion::Nvm(nvm) => {
+                    let algorithm = target.flash_algorithms.iter().find(|a| {
+                        a.flash_properties.address_range.start <= nvm.range.start
+                            && a.flash_properties.address_range.end >= nvm.range.end
+                    });
+                    match algorithm {
+                        None => report.non_writable.push(blocked("no flash algorithm")),
+                        Some(algorithm) => {
+                            let page = algorithm.flash_properties.page_size as u64;
+                            if address == block.start && page > 0 && address % page != 0 {
+                                report.misaligned.push(Misaligned {
+                                    address,
+                                    alignment: page,
+                                });
+                            }
+                            report.placed.push(Span {
+                                start: address,
+                                end,
+                                region: region_name(region),
+                            });
+                        }
+                    }
+                }
+                MemoryRegion::Ram(ram) if !ram.is_writable() => {
+                    report.non_writable.push(blocked("read-only"))
+                }
+                MemoryRegion::Ram(_) => report.placed.push(Span {
+                    start: address,
+                    end,
+                    region: region_name(region),
+                }),
+                MemoryRegion::Generic(_) => report.non_writable.push(blocked("not memory")),
+            }
+            address = end;
+        }
+    }
+    report.fits = report.overflows.is_empty() && report.non_writable.is_empty();
+    report
+}
+
+pub(crate) fn optional_str(ptr: *const c_char) -> Result<Option<String>, String> {
+    if ptr.is_null() {
+        Ok(None)
+    } else {
+        cstr_to_string(ptr).map(Some)
+    }
+}
+
+/// Check an image against a chip's memory map without touching hardware.
+///
+/// `format` is `"elf"`, `"hex"` or `"bin"`, or NULL/empty to detect it from the extension;
+/// `base` is the load address of BIN images. The report is JSON:
+/// `{"fits", "bytes", "placed", "overflows", "non_writable", "misaligned"}`, where `placed`
+/// and `overflows` are `{"start", "end", "region"}` spans, `non_writable` spans also carry a
+/// `reason` ("read-only", "no flash algorithm", "not memory"), and `misaligned` lists
+/// `{"address", "alignment"}` of data blocks not starting on a flash page boundary, which is
+/// only a warning. `fits` is false if there are overflows or non-writable spans.
+///
+/// Returns the required size including NUL, or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_check_fit(
+    chip: *const c_char,
+    path: *const c_char,
+    format: *const c_char,
+    base: u64,
+    out_report_json: *mut c_char,
+    out_report_json_len: usize,
+) -> usize {
+    let (chip, path) = match (cstr_to_string(chip), cstr_to_string(path)) {
+        (Ok(c), Ok(p)) => (c, p),
+        (Err(e), _) | (_, Err(e)) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let format = match optional_str(format) {
+        Ok(f) => f,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let target = match registry().get_target_by_name(&chip) {
+        Ok(t) => t,
+        Err(e) => {
+            set_error(format!("get_target_by_name error: {}", e));
+            return 0;
+        }
+    };
+    let segments = match load_image(&path, format.as_deref(), base) {
+        Ok(image) => image.segments,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    match serde_json::to_string(&check_fit(&target, &segments)) {
+        Ok(json) => write_c_str(&json, out_report_json, out_report_json_len),
+        Err(e) => {
+            set_error(e.to_string());
+            0
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SegmentInfo {
+    address: u64,
+    size: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct RegionUsage {
+    name: Option<String>,
+    kind: &'static str,
+    start: u64,
+    end: u64,
+    used: u64,
+    percent: f64,
+}
+
+#[derive(Serialize)]
+struct ImageInfo<'a> {
+    bytes: u64,
+    entry: Option<u64>,
+    segments: Vec<SegmentInfo>,
+    sections: &'a [Section],
+    regions: Vec<RegionUsage>,
+}
+
+fn overlap(a: &Range<u64>, b: &Range<u64>) -> u64 {
+    a.end.min(b.end).saturating_sub(a.start.max(b.start))
+}
+
+/// Usage of the target's flash and RAM regions. Flash usage counts the programmed data; RAM
+/// usage counts the allocated sections placed there (including `.bss`) when they are known,
+/// otherwise the data loaded into RAM.
+fn region_usage(target: &Target, image: &Image) -> Vec<RegionUsage> {
+    let loaded: Vec<Range<u64>> = image.segments.iter().map(Segment::range).collect();
+    let allocated: Vec<Range<u64>> = image
+        .sections
+        .iter()
+        .map(|s| s.address..s.address + s.size)
+        .collect();
+    let ram_ranges = if allocated.is_empty() {
+        &loaded
+    } else {
+        &allocated
+    };
+    target
+        .memory_map
+        .iter()
+        .filter_map(|region| {
+            let (kind, ranges) = match region {
+                MemoryRegion::Nvm(r) if !r.is_alias => ("flash", &loaded),
+                MemoryRegion::Ram(_) => ("ram", ram_ranges),
+                _ => return None,
+            };
+            let range = region.address_range();
+            let used: u64 = ranges.iter().map(|r| overlap(r, &range)).sum();
+            let size = range.end - range.start;
+            Some(RegionUsage {
+                name: region_name(region),
+                kind,
+                start: range.start,
+                end: range.end,
+                used,
+                percent: if size == 0 {
+                    0.0
+                } else {
+                    used as f64 * 100.0 / size as f64
+                },
+            })
+        })
+        .collect()
+}
+
+/// Describe an image as JSON: `{"bytes", "entry", "segments", "sections", "regions"}`.
+///
+/// `format` and `base` are interpreted as by `pr_flash_check_fit`. `bytes` is the total amount
+/// of data programmed, `segments` lists `{"address", "size"}` of the loadable data, and for ELF
+/// images `sections` lists the allocated sections as `{"name", "address", "load_address",
+/// "size"}` (`load_address` is null for `.bss`-like sections). `entry` is the entry point, or
+/// null if the image has none (BIN).
+///
+/// If `chip` is not NULL, `regions` gives `{"name", "kind": "flash"|"ram", "start", "end",
+/// "used", "percent"}` for each of its flash and RAM regions; RAM usage of ELF images counts the
+/// allocated sections, including `.bss`.
+///
+/// Returns the required size including NUL, or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_image_info(
+    path: *const c_char,
+    format: *const c_char,
+    base: u64,
+    chip: *const c_char,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let args =
+        cstr_to_string(path).and_then(|p| Ok((p, optional_str(format)?, optional_str(chip)?)));
+    let (path, format, chip) = match args {
+        Ok(a) => a,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let image = match load_image(&path, format.as_deref(), base) {
+        Ok(image) => image,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let regions = match chip {
+        Some(chip) => match registry().get_target_by_name(&chip) {
+            Ok(target) => region_usage(&target, &image),
+            Err(e) => {
+                set_error(format!("get_target_by_name error: {}", e));
+                return 0;
+            }
+        },
+        None => Vec::new(),
+    };
+    let info = ImageInfo {
+        bytes: image.segments.iter().map(|s| s.data.len() as u64).sum(),
+        entry: image.entry,
+        segments: image
+            .segments
+            .iter()
+            .map(|s| SegmentInfo {
+                address: s.address,
+                size: s.data.len() as u64,
+            })
+            .collect(),
+        sections: &image.sections,
+        regions,
+    };
+    match serde_json::to_string(&info) {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e.to_string());
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn segment(address: u64, len: usize) -> Segment {
+        Segment {
+            address,
+            data: vec![0; len],
+        }
+    }
+
+    #[test]
+    fn hex_records_merge_into_segments() {
+        let text = ":020000040800F2\n:0400000001020304F2\n:0400040005060708DE\n\
+                    :0400100009090909C8\n:00000001FF\n";
+        let segments = parse_hex(text).unwrap().segments;
+        assert_eq!(segments.len(), 2);
+        assert_eq!(segments[0].range(), 0x0800_0000..0x0800_0008);
+        assert_eq!(segments[1].address, 0x0800_0010);
+    }
+
+    #[test]
+    fn fit_reports_overflow_and_misalignment() {
+        // STM32F103C8: 64 KiB flash at 0x08000000, 1 KiB pages.
+        let target = registry().get_target_by_name("STM32F103C8").unwrap();
+        let ok = check_fit(&target, &[segment(0x0800_0000, 0x400)]);
+        assert!(ok.fits && ok.misaligned.is_empty());
+        assert_eq!(ok.placed.len(), 1);
+
+        let report = check_fit(
+            &target,
+            &[segment(0x0800_0000, 0x1_0000), segment(0x0800_F000, 0x2000)],
+        );
+        assert!(!report.fits);
+        assert_eq!(report.overflows[0].start, 0x0801_0000);
+        assert_eq!(report.overflows[0].end, 0x0801_1000);
+
+        let report = check_fit(&target, &[segment(0x0800_0102, 4)]);
+        assert!(report.fits);
+        assert_eq!(report.misaligned[0].address, 0x0800_0102);
+    }
+
+    #[test]
+    fn format_from_name_or_extension() {
+        assert_eq!(format_kind("a.bin", None), Ok(FormatKind::Bin));
+        assert_eq!(format_kind("a.dat", Some("ihex")), Ok(FormatKind::Hex));
+        assert!(format_kind("a.dat", None).is_err());
+    }
+
+    #[test]
+    fn usage_counts_flash_data_and_ram_sections() {
+        let target = registry().get_target_by_name("STM32F103C8").unwrap();
+        let image = Image {
+            segments: vec![segment(0x0800_0000, 0x4000)],
+            sections: vec![Section {
+                name: ".bss".to_string(),
+                address: 0x2000_0000,
+                load_address: None,
+                size: 0x1400,
+            }],
+            entry: Some(0x0800_0100),
+        };
+        let usage = region_usage(&target, &image);
+        let flash = usage.iter().find(|r| r.kind == "flash").unwrap();
+        assert_eq!(flash.used, 0x4000);
+        assert_eq!(flash.percent, 25.0);
+        let ram = usage.iter().find(|r| r.kind == "ram").unwrap();
+        assert_eq!(ram.percent, 25.0);
+    }
+
+    #[test]
+    fn elf_sections_and_entry() {
+        let path = format!(
+            "{}/../probe-rs-debug/tests/debug-unwind-tests/RP2040_full_unwind.elf",
+            env!("CARGO_MANIFEST_DIR")
+        );
+        let image = load_image(&path, None, 0).unwrap();
+        assert!(!image.segments.is_empty());
+        assert_eq!(image.entry, crate::elf::entry_point(&path).ok());
+        let text = image.sections.iter().find(|s| s.name == ".text").unwrap();
+        assert_eq!(text.load_address, Some(text.address));
+    }
+}
@@ -0,0 +1,474 @@
+//! Resumable flash jobs for large (external) flash parts: the image is programmed in
+//! checkpoints of whole sectors and the progress is kept in a state file, so a job cut short by
+//! a host power loss or a USB drop continues from the first unprogrammed sector.
+
+use crate::api::{self, FlashError};
+use crate::buffering;
+use crate::image::Segment;
+use crate::verify::{self, VerifyMode};
+use crate::{
+    after_flash_for, apply_after_flash, cstr_to_string, detect_format_from_path, download_options,
+    error_chain, layout, loader_segments, patched_loader, preserve_ranges_lock, protocol_from_int,
+    set_error, timeouts, timing,
+};
+use probe_rs::Session;
+use probe_rs::flashing::{DownloadOptions, FlashProgress, ProgressEvent, ProgressOperation};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::ffi::c_char;
+use std::ops::Range;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Smallest checkpoint: sectors are grouped up to this size, so that small sectors do not
+/// cost a flash algorithm start each.
+const CHECKPOINT_BYTES: u64 = 64 * 1024;
+
+const STATE_VERSION: u32 = 1;
+
+/// The state file of a job.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct JobState {
+    version: u32,
+    chip: String,
+    image: String,
+    base_address: Option<u64>,
+    skip: u32,
+    verify: bool,
+    speed_khz: Option<u32>,
+    protocol: i32,
+    /// FNV-1a hash of the data to program, patches included: a job only resumes with the
+    /// image it started with.
+    fingerprint: u64,
+    /// End of the last programmed checkpoint.
+    programmed_to: Option<u64>,
+}
+
+impl JobState {
+    fn load(path: &Path) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path)
+            .map_err(|e| format!("cannot read job state {}: {}", path.display(), e))?;
+        let state: JobState = serde_json::from_str(&text)
+            .map_err(|e| format!("invalid job state {}: {}", path.display(), e))?;
+        if state.version != STATE_VERSION {
+            return Err(format!("unsupported job state version {}", state.version));
+        }
+        Ok(state)
+    }
+
+    /// Write the state through a temporary file, so that an interruption leaves either the
+    /// old or the new state.
+    fn save(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string(self).map_err(|e| e.to_string())?;
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        std::fs::write(&temporary, json)
+            .and_then(|_| std::fs::rename(&temporary, path))
+            .map_err(|e| format!("cannot write job state {}: {}", path.display(), e))
+    }
+}
+
+fn fingerprint(segments: &[Segment]) -> u64 {
+    let mut hash = 0xcbf2_9ce4_8422_2325u64;
+    for segment in segments {
+        let bytes = segment.address.to_le_bytes();
+        for byte in bytes.iter().chain(&segment.data) {
+            hash ^= u64::from(*byte);
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// The sectors holding the flash data of `segments`, grouped in address order to at least
+/// `CHECKPOINT_BYTES` each, and the segments outside flash (RAM).
+fn checkpoints(
+    target: &probe_rs::config::Target,
+    segments: &[Segment],
+) -> (Vec<Range<u64>>, Vec<Range<u64>>) {
+    let mut sectors = Vec::new();
+    let mut outside = Vec::new();
+    for segment in segments {
+        let found = layout::flash_sectors(target, &segment.range());
+        if found.is_empty() {
+            outside.push(segment.range());
+        }
+        sectors.extend(found);
+    }
+    sectors.sort_by_key(|s| s.start);
+    let mut groups: Vec<Range<u64>> = Vec::new();
+    for sector in sectors {
+        match groups.last_mut() {
+            // Sectors shared by two segments, and small groups.
+            Some(last) if sector.start < last.end || last.end - last.start < CHECKPOINT_BYTES => {
+                last.end = last.end.max(sector.end)
+            }
+            _ => groups.push(sector),
+        }
+    }
+    (groups, outside)
+}
+
+/// The parts of `segments` inside `range`.
+pub(crate) fn clip(segments: &[Segment], range: &Range<u64>) -> Vec<Segment> {
+    segments
+        .iter()
+        .filter_map(|segment| {
+            let start = segment.address.max(range.start);
+            let end = segment.range().end.min(range.end);
+            (start < end).then(|| Segment {
+                address: start,
+                data: segment.data
+                    [(start - segment.address) as usize..(end - segment.address) as usize]
+                    .to_vec(),
+            })
+        })
+        .collect()
+}
+
+fn operation_index(operation: ProgressOperation) -> usize {
+    match operation {
+        ProgressOperation::Erase => 0,
+        ProgressOperation::Program => 1,
+        ProgressOperation::Verify => 2,
+        ProgressOperation::Fill => 3,
+    }
+}
+
+/// The progress of all checkpoints as one flash operation: each phase starts with the first
+/// checkpoint and finishes after the last one.
+struct JobProgress {
+    inner: FlashProgress<'static>,
+    started: [Option<ProgressOperation>; 4],
+}
+
+impl JobProgress {
+    fn emit(&mut self, event: ProgressEvent) {
+        match event {
+            // Announced per checkpoint; the job announces its totals once.
+            ProgressEvent::AddProgressBar { .. } => {}
+            ProgressEvent::Started(operation) => {
+                let started = &mut self.started[operation_index(operation)];
+                if started.is_none() {
+                    *started = Some(operation);
+                    self.inner.emit(event);
+                }
+            }
+            ProgressEvent::Finished(_) => {}
+            event => self.inner.emit(event),
+        }
+    }
+
+    fn finish(&mut self) {
+        for operation in self.started.iter_mut().filter_map(Option::take) {
+            self.inner.emit(ProgressEvent::Finished(operation));
+        }
+    }
+}
+
+/// Program the checkpoints of the job in `state` that are not done yet, saving the state at
+/// `state_path` after each one.
+fn run(
+    session: &mut Session,
+    state: &mut JobState,
+    state_path: &Path,
+    progress: FlashProgress<'static>,
+) -> Result<(), FlashError> {
+    if !preserve_ranges_lock().lock().unwrap().is_empty() {
+        let msg = "resumable flash jobs do not support preserved ranges";
+        return Err(FlashError::Setup(msg.to_string()));
+    }
+    let base = state.base_address;
+    let format =
+        detect_format_from_path(&state.image, base, state.skip).map_err(FlashError::Setup)?;
+    let (after, entry) = after_flash_for(&state.image, &format).map_err(FlashError::Setup)?;
+    let loader = patched_loader(session, &state.image, format, &[]).map_err(FlashError::Flash)?;
+    let segments = loader_segments(&loader);
+    let hash = fingerprint(&segments);
+    if state.programmed_to.is_some() && state.fingerprint != hash {
+        return Err(FlashError::Setup(format!(
+            "{} or the flash patches changed since the job started",
+            state.image
+        )));
+    }
+    state.fingerprint = hash;
+    state.save(state_path).map_err(FlashError::Setup)?;
+    program(session, state, state_path, &segments, progress).map_err(FlashError::Flash)?;
+    apply_after_flash(session, after, entry).map_err(FlashError::Flash)?;
+    std::fs::remove_file(state_path).map_err(|e| {
+        FlashError::Flash(format!(
+            "cannot remove job state {}: {}",
+            state_path.display(),
+            e
+        ))
+    })
+}
+
+fn program(
+    session: &mut Session,
+    state: &mut JobState,
+    state_path: &Path,
+    segments: &[Segment],
+    progress: FlashProgress<'static>,
+) -> Result<(), String> {
+    let (groups, outside) = checkpoints(session.target(), segments);
+    let remaining: Vec<Range<u64>> = groups
+        .into_iter()
+        .filter(|g| state.programmed_to.is_none_or(|done| g.start >= done))
+        .collect();
+    let left: Vec<Segment> = remaining.iter().flat_map(|g| clip(segments, g)).collect();
+    let (erase_size, program_size) = layout::flash_sizes(session.target(), &left)?;
+
+    let mut inner = progress;
+    inner.emit(ProgressEvent::AddProgressBar {
+        operation: ProgressOperation::Erase,
+        total: Some(erase_size),
+    });
+    inner.emit(ProgressEvent::AddProgressBar {
+        operation: ProgressOperation::Program,
+        total: Some(program_size),
+    });
+    if state.verify {
+        inner.emit(ProgressEvent::AddProgressBar {
+            operation: ProgressOperation::Verify,
+            total: Some(program_size),
+        });
+    }
+    let shared = Rc::new(RefCell::new(JobProgress {
+        inner,
+        started: [None; 4],
+    }));
+    let verify_mode = Some(verify::mode()).filter(|m| state.verify && *m != VerifyMode::Pages);
+
+    // Data outside flash (RAM) is loaded again on every run, after the flash.
+    let steps = remaining
+        .iter()
+        .map(|g| (Some(g), clip(segments, g)))
+        .chain(std::iter::once((
+            None,
+            outside
+                .iter()
+                .flat_map(|r| clip(segments, r))
+                .collect::<Vec<_>>(),
+        )));
+    for (group, data) in steps {
+        if data.is_empty() {
+            continue;
+        }
+        let mut part = session.target().flash_loader();
+        for segment in &data {
+            part.add_data(segment.address, &segment.data)
+                .map_err(|e| format!("flash error: {}", error_chain(&e)))?;
+        }
+        let mut opts = DownloadOptions::default();
+        opts.verify = state.verify && verify_mode.is_none();
+        buffering::apply_to(&mut opts);
+        opts.progress = {
+            let shared = shared.clone();
+            FlashProgress::new(move |event| shared.borrow_mut().emit(event))
+        };
+        part.commit(session, opts).map_err(|e| {
+            format!(
+                "flash error: {}; resume the job with pr_flash_resume",
+                error_chain(&e)
+            )
+        })?;
+        if let Some(mode) = verify_mode {
+            let mut inner = FlashProgress::new(|event| shared.borrow_mut().emit(event));
+            verify::verify_flashed(session, &data, mode, &mut inner)?;
+        }
+        if let Some(group) = group {
+            state.programmed_to = Some(group.end);
+            state.save(state_path)?;
+        }
+    }
+    shared.borrow_mut().finish();
+    Ok(())
+}
+
+/// Attach to the chip of `state`, run the job and keep its timing report.
+fn attach_and_run(
+    state: &mut JobState,
+    state_path: &Path,
+    opts: DownloadOptions<'static>,
+) -> Result<(), FlashError> {
+    let speed = state.speed_khz;
+    let (mut session, used_speed) =
+        api::attach_for_flash(&state.chip, speed, protocol_from_int(state.protocol))?;
+    timeouts::apply_to_session(&mut session);
+    let mut progress = opts.progress;
+    let timing = timing::instrument(&mut progress);
+    let result = run(&mut session, state, state_path, progress);
+    let verify_mode = state.verify.then(|| verify::mode().name());
+    timing::finish(
+        &timing,
+        result.is_ok(),
+        used_speed,
+        verify_mode,
+        session.target(),
+    );
+    result
+}
+
+fn code_of(result: Result<(), FlashError>) -> i32 {
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            let code = e.code();
+            set_error(e.message());
+            code
+        }
+    }
+}
+
+/// Flash an image as `pr_flash_auto` does, as a job that `pr_flash_resume` can continue if it
+/// is interrupted: sectors are erased and programmed in checkpoints of at least 64 KiB, and the
+/// job and the end of the last programmed checkpoint are kept in the JSON file `state_path`,
+/// which is removed once the job has completed. There is no chip erase; flash patches, the
+/// verify mode and the post-flash behavior apply, preserved ranges are not supported.
+///
+/// Returns 0 on success, 1 on invalid arguments or if no target could be attached, 2 on
+/// flashing error (the state file is kept to resume).
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub extern "C" fn pr_flash_job(
+    chip: *const c_char,
+    path: *const c_char,
+    base_address: u64,
+    skip: u32,
+    verify: i32,
+    speed_khz: u32,
+    protocol_code: i32,
+    state_path: *const c_char,
+) -> i32 {
+    let args = (
+        cstr_to_string(chip),
+        cstr_to_string(path),
+        cstr_to_string(state_path),
+    );
+    let (Ok(chip), Ok(image), Ok(state_path)) = args else {
+        set_error("invalid chip, image or job state path".to_string());
+        return 1;
+    };
+    let mut state = JobState {
+        version: STATE_VERSION,
+        chip,
+        image,
+        base_address: Some(base_address).filter(|b| *b != 0),
+        skip,
+        verify: verify != 0,
+        speed_khz: Some(speed_khz).filter(|s| *s != 0),
+        protocol: protocol_code,
+        fingerprint: 0,
+        programmed_to: None,
+    };
+    if let Err(e) = detect_format_from_path(&state.image, state.base_address, skip) {
+        set_error(e);
+        return 1;
+    }
+    let opts = download_options(None, verify, 0, 0);
+    code_of(attach_and_run(&mut state, Path::new(&state_path), opts))
+}
+
+/// Continue the flash job of `state_path` (see `pr_flash_job`) from its first unprogrammed
+/// sector, attaching to the chip with the speed and protocol the job started with. The image
+/// file and flash patches must not have changed since. Progress goes to the global callback.
+///
+/// Returns 0 on success, 1 on an unreadable state file, a changed image or if no target could
+/// be attached, 2 on flashing error (the state file is kept to resume again).
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_resume(state_path: *const c_char) -> i32 {
+    let state_path = match cstr_to_string(state_path) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return 1;
+        }
+    };
+    let path = Path::new(&state_path);
+    let mut state = match JobState::load(path) {
+        Ok(state) => state,
+        Err(e) => {
+            set_error(e);
+            return 1;
+        }
+    };
+    let opts = download_options(None, state.verify as i32, 0, 0);
+    code_of(attach_and_run(&mut state, path, opts))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry;
+
+    fn segment(address: u64, len: usize) -> Segment {
+        Segment {
+            address,
+            data: (0..len).map(|i| i as u8).collect(),
+        }
+    }
+
+    #[test]
+    fn sectors_are_grouped_into_checkpoints() {
+        let target = registry().get_target_by_name("STM32F407VGTx").unwrap();
+        let image = [segment(0x0800_0000, 0x2_8000), segment(0x2000_0000, 16)];
+        let (groups, outside) = checkpoints(&target, &image);
+        // Four 16 KiB sectors, then the 64 KiB and a 128 KiB sector.
+        assert_eq!(
+            groups,
+            vec![
+                0x0800_0000..0x0801_0000,
+                0x0801_0000..0x0802_0000,
+                0x0802_0000..0x0804_0000,
+            ]
+        );
+        assert_eq!(outside, vec![0x2000_0000..0x2000_0010]);
+
+        let part = clip(&image, &(0x0801_fffe..0x0804_0000));
+        assert_eq!(part.len(), 1);
+        assert_eq!(part[0].address, 0x0801_fffe);
+        assert_eq!(part[0].data.len(), 0x8002);
+        assert_eq!(part[0].data[..2], [0xfe, 0xff]);
+    }
+
+    #[test]
+    fn fingerprint_covers_addresses_and_data() {
+        let image = [segment(0x0800_0000, 64)];
+        let mut changed = [segment(0x0800_0000, 64)];
+        changed[0].data[10] ^= 1;
+        assert_eq!(
+            fingerprint(&image),
+            fingerprint(&[segment(0x0800_0000, 64)])
+        );
+        assert_ne!(fingerprint(&image), fingerprint(&changed));
+        assert_ne!(
+            fingerprint(&image),
+            fingerprint(&[segment(0x0800_0004, 64)])
+        );
+    }
+
+    #[test]
+    fn state_roundtrip() {
+        let path = std::env::temp_dir().join(format!("pr-job-{}.json", std::process::id()));
+        let state = JobState {
+            version: STATE_VERSION,
+            chip: "STM32F407VGTx".to_string(),
+            image: "firmware.bin".to_string(),
+            base_address: Some(0x0800_0000),
+            skip: 0,
+            verify: true,
+            speed_khz: None,
+            protocol: 1,
+            fingerprint: 42,
+            programmed_to: Some(0x0802_0000),
+        };
+        state.save(&path).unwrap();
+        assert_eq!(JobState::load(&path).unwrap(), state);
+        std::fs::remove_file(&path).unwrap();
+
+        assert!(JobState::load(&path).is_err());
+        let c_path = std::ffi::CString::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(pr_flash_resume(c_path.as_ptr()), 1);
+    }
+}
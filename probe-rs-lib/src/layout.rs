@@ -0,0 +1,707 @@
+//! Flash layout of a target, including external (e.g. QSPI) flash that is only reachable
+//! through a dedicated flash algorithm, and flashing raw images into a named region.
+
+use crate::image::{self, Segment, optional_str};
+use crate::{
+    compression, cstr_to_string, download_options, flash_image, get_session, registry, set_error,
+    write_c_str,
+};
+use probe_rs::Session;
+use probe_rs::config::Target;
+use probe_rs::flashing::{self, BinOptions, FlashProgress, Format};
+use probe_rs_target::{MemoryRange, MemoryRegion, RawFlashAlgorithm};
+use serde::Serialize;
+use std::ffi::c_char;
+use std::ops::Range;
+
+#[derive(Serialize, Debug, PartialEq)]
+struct SectorGroup {
+    /// Absolute address of the first sector of the group.
+    address: u64,
+    size: u64,
+}
+
+#[derive(Serialize, Debug)]
+struct FlashRegion {
+    name: String,
+    start: u64,
+    end: u64,
+    /// Not part of the memory map: only programmable through one of `algorithms`.
+    external: bool,
+    algorithms: Vec<String>,
+    /// Those of `algorithms` taking compressed pages.
+    compressed: Vec<String>,
+    page_size: Option<u32>,
+    /// Sectors of `size` bytes from `address` up to the next group or the region end.
+    sectors: Vec<SectorGroup>,
+}
+
+fn region_entry(target: &Target, name: String, range: Range<u64>, external: bool) -> FlashRegion {
+    let algorithms: Vec<&RawFlashAlgorithm> = target
+        .flash_algorithms
+        .iter()
+        .filter(|a| a.flash_properties.address_range.contains_range(&range))
+        .collect();
+    // Describe the sectors with the algorithm that would be used for the region.
+    let chosen = algorithms.iter().find(|a| a.default).or(algorithms.first());
+    let (page_size, sectors) = match chosen {
+        Some(a) => {
+            let props = &a.flash_properties;
+            let base = props.address_range.start;
+            let ends = props
+                .sectors
+                .iter()
+                .skip(1)
+                .map(|s| base + s.address)
+                .chain([props.address_range.end]);
+            // Groups overlapping the range, starting at their first sector inside it.
+            let sectors = props
+                .sectors
+                .iter()
+                .zip(ends)
+                .filter_map(|(s, end)| {
+                    let group = base + s.address;
+                    let skip = range.start.saturating_sub(group).div_ceil(s.size);
+                    let address = group + skip * s.size;
+                    (address < end.min(range.end)).then_some(SectorGroup {
+                        address,
+                        size: s.size,
+                    })
+                })
+                .collect();
+            (Some(props.page_size), sectors)
+        }
+        None => (None, Vec::new()),
+    };
+    FlashRegion {
+        name,
+        start: range.start,
+        end: range.end,
+        external,
+        algorithms: algorithms.iter().map(|a| a.name.clone()).collect(),
+        compressed: algorithms
+            .iter()
+            .filter(|a| compression::is_compressed(a))
+            .map(|a| a.name.clone())
+            .collect(),
+        page_size,
+        sectors,
+    }
+}
+
+/// Flash regions of a target: the flash regions of its memory map, followed by the ranges of
+/// flash algorithms that no memory map region covers (external flash).
+fn flash_regions(target: &Target) -> Vec<FlashRegion> {
+    let nvm: Vec<_> = target
+        .memory_map
+        .iter()
+        .filter_map(MemoryRegion::as_nvm_region)
+        .filter(|r| !r.is_alias)
+        .collect();
+    let mut regions: Vec<FlashRegion> = nvm
+        .iter()
+        .map(|r| {
+            let name = r
+                .name
+                .clone()
+                .unwrap_or_else(|| format!("{:#010x}", r.range.start));
+            region_entry(target, name, r.range.clone(), false)
+        })
+        .collect();
+
+    let mut external: Vec<Range<u64>> = Vec::new();
+    for algo in &target.flash_algorithms {
+        let range = &algo.flash_properties.address_range;
+        if nvm.iter().any(|r| r.range.intersects_range(range)) || external.contains(range) {
+            continue;
+        }
+        external.push(range.clone());
+        let mut entry = region_entry(target, algo.name.clone(), range.clone(), true);
+        // Several loaders may drive the same external flash (e.g. different pin mappings);
+        // name the entry after the one that is used by default.
+        if let Some(default) = target
+            .flash_algorithms
+            .iter()
+            .find(|a| a.default && &a.flash_properties.address_range == range)
+        {
+            entry.name = default.name.clone();
+        }
+        regions.push(entry);
+    }
+    regions
+}
+
+/// The sector of `region` that holds `address`.
+fn sector_of(region: &FlashRegion, address: u64) -> Option<Range<u64>> {
+    let i = region.sectors.iter().rposition(|g| g.address <= address)?;
+    let group = &region.sectors[i];
+    let group_end = region.sectors.get(i + 1).map_or(region.end, |g| g.address);
+    if group.size == 0 {
+        return None;
+    }
+    let start = group.address + (address - group.address) / group.size * group.size;
+    Some(start..(start + group.size).min(group_end))
+}
+
+/// `range` widened to whole sectors, one range per flash region of the memory map it overlaps.
+fn sector_aligned(regions: &[FlashRegion], range: &Range<u64>) -> Vec<Range<u64>> {
+    regions
+        .iter()
+        .filter(|r| !r.external && r.start < range.end && r.end > range.start)
+        .filter_map(|r| {
+            let first = sector_of(r, range.start.max(r.start))?;
+            let last = sector_of(r, range.end.min(r.end) - 1)?;
+            Some(first.start..last.end)
+        })
+        .collect()
+}
+
+/// `range` widened to whole sectors of the internal flash of `target`, with the erased value of
+/// each span; empty outside flash.
+pub(crate) fn sector_spans(target: &Target, range: &Range<u64>) -> Vec<(Range<u64>, u8)> {
+    sector_aligned(&flash_regions(target), range)
+        .into_iter()
+        .map(|span| {
+            let erased = target
+                .flash_algorithms
+                .iter()
+                .find(|a| a.flash_properties.address_range.contains(&span.start))
+                .map_or(0xff, |a| a.flash_properties.erased_byte_value);
+            (span, erased)
+        })
+        .collect()
+}
+
+/// Names and address ranges of the flash regions of `target`, external flash included.
+pub(crate) fn region_ranges(target: &Target) -> Vec<(String, Range<u64>)> {
+    flash_regions(target)
+        .into_iter()
+        .map(|r| (r.name, r.start..r.end))
+        .collect()
+}
+
+/// The sectors of the flash of `target` overlapping `range`, external flash included.
+pub(crate) fn flash_sectors(target: &Target, range: &Range<u64>) -> Vec<Range<u64>> {
+    let mut found: Vec<Range<u64>> = flash_regions(target)
+        .iter()
+        .flat_map(|region| sectors(region, range))
+        .collect();
+    found.sort_by_key(|s| s.start);
+    found
+}
+
+/// The sectors of `region` overlapping `range`, in address order.
+fn sectors(region: &FlashRegion, range: &Range<u64>) -> Vec<Range<u64>> {
+    let mut sectors = Vec::new();
+    let mut address = range.start.max(region.start);
+    while address < range.end.min(region.end) {
+        let Some(sector) = sector_of(region, address) else {
+            break;
+        };
+        address = sector.end;
+        sectors.push(sector);
+    }
+    sectors
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Extent {
+    address: u64,
+    size: u64,
+}
+
+impl From<Range<u64>> for Extent {
+    fn from(range: Range<u64>) -> Self {
+        Extent {
+            address: range.start,
+            size: range.end - range.start,
+        }
+    }
+}
+
+/// What flashing an image does, worked out from the image and the target description alone.
+#[derive(Serialize, Debug, Default)]
+struct FlashPlan {
+    chip_erase: bool,
+    /// Sectors that are erased, in address order.
+    erase: Vec<Extent>,
+    /// Flash pages that are programmed.
+    pages: Vec<Extent>,
+    /// Parts of `pages` without image data, programmed with the erased value.
+    fills: Vec<Extent>,
+    /// Image data written to RAM.
+    ram: Vec<Extent>,
+}
+
+/// Sector erase list of a plan: the sectors of `regions` that hold `blocks`. A chip erase
+/// erases the whole of the memory map flash regions the image is programmed into, but not
+/// external flash or untouched regions such as OTP areas.
+fn erase_list(regions: &[FlashRegion], blocks: &[Range<u64>], chip_erase: bool) -> Vec<Extent> {
+    let mut erase: Vec<Range<u64>> = Vec::new();
+    for region in regions {
+        let touched = blocks
+            .iter()
+            .any(|b| b.start < region.end && b.end > region.start);
+        if chip_erase && touched && !region.external {
+            erase.extend(sectors(region, &(region.start..region.end)));
+            continue;
+        }
+        for block in blocks {
+            for sector in sectors(region, block) {
+                if !erase.contains(&sector) {
+                    erase.push(sector);
+                }
+            }
+        }
+    }
+    erase.sort_by_key(|s| s.start);
+    erase.into_iter().map(Extent::from).collect()
+}
+
+fn flash_plan(target: &Target, image: &[Segment], chip_erase: bool) -> Result<FlashPlan, String> {
+    let regions = flash_regions(target);
+    let blocks = image::blocks(image);
+    let mut pages: Vec<Range<u64>> = Vec::new();
+    let mut ram = Vec::new();
+    for block in &blocks {
+        let mut address = block.start;
+        while address < block.end {
+            let Some(region) = regions
+                .iter()
+                .find(|r| r.start <= address && address < r.end)
+            else {
+                let end = match target.memory_map.iter().find(|r| r.contains(address)) {
+                    Some(MemoryRegion::Ram(r)) if r.is_writable() => r.range.end.min(block.end),
+                    _ => {
+                        return Err(format!(
+                            "image data at {:#x} is not in flash or RAM",
+                            address
+                        ));
+                    }
+                };
+                ram.push(Extent::from(address..end));
+                address = end;
+                continue;
+            };
+            let Some(page_size) = region.page_size.filter(|&p| p > 0).map(u64::from) else {
+                return Err(format!("no flash algorithm for region {}", region.name));
+            };
+            let end = block.end.min(region.end);
+            while address < end {
+                let sector = sector_of(region, address)
+                    .ok_or_else(|| format!("no flash sector at {:#x}", address))?;
+                let start = sector.start + (address - sector.start) / page_size * page_size;
+                let page = start..(start + page_size).min(sector.end);
+                address = page.end;
+                if pages.last() != Some(&page) {
+                    pages.push(page);
+                }
+            }
+        }
+    }
+
+    let mut fills = Vec::new();
+    for page in &pages {
+        let mut covered = page.start;
+        for block in blocks
+            .iter()
+            .filter(|b| b.start < page.end && b.end > page.start)
+        {
+            if block.start > covered {
+                fills.push(Extent::from(covered..block.start));
+            }
+            covered = covered.max(block.end);
+        }
+        if covered < page.end {
+            fills.push(Extent::from(covered..page.end));
+        }
+    }
+    Ok(FlashPlan {
+        chip_erase,
+        erase: erase_list(&regions, &blocks, chip_erase),
+        pages: pages.into_iter().map(Extent::from).collect(),
+        fills,
+        ram,
+    })
+}
+
+/// Bytes of the sectors erased and the pages programmed when flashing `image` without a chip
+/// erase, the totals of probe-rs' erase and program progress.
+pub(crate) fn flash_sizes(target: &Target, image: &[Segment]) -> Result<(u64, u64), String> {
+    let plan = flash_plan(target, image, false)?;
+    let sum = |extents: &[Extent]| extents.iter().map(|e| e.size).sum();
+    Ok((sum(&plan.erase), sum(&plan.pages)))
+}
+
+/// Describe the flash layout of a chip as a JSON array of regions:
+/// `{"name", "start", "end", "external", "algorithms", "compressed", "page_size", "sectors"}`.
+///
+/// `external` regions are not in the chip's memory map but are served by a flash algorithm,
+/// such as SPI NOR flash behind a QSPI/SPIM controller or an option byte area; they are named
+/// after the algorithm.
+/// `compressed` lists the algorithms that take compressed pages (`pr_flash_option_compression`).
+/// `sectors` lists groups of equally sized sectors, `{"address", "size"}`, each extending to
+/// the next group or the end of the region.
+///
+/// Returns the required size including NUL, or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_sector_layout(
+    chip: *const c_char,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let chip = match cstr_to_string(chip) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let target = match registry().get_target_by_name(&chip) {
+        Ok(t) => t,
+        Err(e) => {
+            set_error(format!("get_target_by_name error: {}", e));
+            return 0;
+        }
+    };
+    match serde_json::to_string(&flash_regions(&target)) {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e.to_string());
+            0
+        }
+    }
+}
+
+/// Flash a raw binary at the start of a flash region, selected by the name reported by
+/// `pr_flash_sector_layout`. For an external region, or when a flash algorithm name is given,
+/// that algorithm is used to program the region; only for this call, the memory map and default
+/// algorithms of the session are restored afterwards.
+///
+/// The image is verified after programming. Preserve ranges, patches and the post-flash
+/// behavior apply as for `pr_session_flash`.
+///
+/// Returns 0 on success, 1 on invalid arguments, 2 on flash error, -3 if the region is unknown.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_bin_to_region(
+    session: u64,
+    region_name: *const c_char,
+    path: *const c_char,
+) -> i32 {
+    let (name, path) = match (cstr_to_string(region_name), cstr_to_string(path)) {
+        (Ok(n), Ok(p)) => (n, p),
+        (Err(e), _) | (_, Err(e)) => {
+            set_error(e);
+            return 1;
+        }
+    };
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return 1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let in_memory_map = lock
+        .target()
+        .memory_map
+        .iter()
+        .filter_map(MemoryRegion::as_nvm_region)
+        .find(|r| r.name.as_deref() == Some(name.as_str()))
+        .map(|r| r.range.start);
+    let flash = |lock: &mut Session, base| {
+        let format = Format::Bin(BinOptions {
+            base_address: Some(base),
+            skip: 0,
+        });
+        let opts = download_options(Some(session), 1, 0, 0);
+        match flash_image(lock, &path, format, opts, &[], None) {
+            Ok(()) => 0,
+            Err(e) => {
+                set_error(e);
+                2
+            }
+        }
+    };
+    match in_memory_map {
+        Some(start) => flash(&mut lock, start),
+        None if lock
+            .target()
+            .flash_algorithms
+            .iter()
+            .any(|a| a.name == name) =>
+        {
+            // Only for this call, so later flashes keep the target's own algorithms.
+            match lock
+                .with_flash_algorithm_region(&name, |lock, region| flash(lock, region.range.start))
+            {
+                Ok(code) => code,
+                Err(e) => {
+                    set_error(format!("map flash region error: {}", e));
+                    2
+                }
+            }
+        }
+        None => {
+            set_error(format!("unknown flash region {}", name));
+            -3
+        }
+    }
+}
+
+/// Erase the flash sectors overlapping `start..end` (`end` exclusive), widened to whole
+/// sectors: `end = start + 1` erases the sector holding `start`. Only flash of the memory map
+/// is erased, no external regions, and preserve ranges do not apply.
+///
+/// The erased range, from the start of the first to the end of the last sector, is written to
+/// `out_start` and `out_end`; either may be NULL.
+///
+/// Returns 0 on success, -1 on invalid handle or a range without flash, -2 on erase error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_erase_range(
+    session: u64,
+    start: u64,
+    end: u64,
+    out_start: *mut u64,
+    out_end: *mut u64,
+) -> i32 {
+    if start >= end {
+        set_error(format!("empty erase range {:#x}..{:#x}", start, end));
+        return -1;
+    }
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let ranges = sector_aligned(&flash_regions(lock.target()), &(start..end));
+    let (Some(first), Some(last)) = (
+        ranges.iter().map(|r| r.start).min(),
+        ranges.iter().map(|r| r.end).max(),
+    ) else {
+        set_error(format!("no flash in {:#x}..{:#x}", start, end));
+        return -1;
+    };
+    let mut progress = FlashProgress::empty();
+    for range in &ranges {
+        if let Err(e) = flashing::erase(&mut lock, &mut progress, range.start, range.end) {
+            set_error(format!("erase error: {}", e));
+            return -2;
+        }
+    }
+    unsafe {
+        if !out_start.is_null() {
+            *out_start = first;
+        }
+        if !out_end.is_null() {
+            *out_end = last;
+        }
+    }
+    0
+}
+
+/// Work out what flashing an image would do, from the chip description and the image alone:
+/// nothing is attached or written. `format` and `base` are as for `pr_flash_check_fit`,
+/// `chip_erase` as for `pr_session_flash`.
+///
+/// The plan is JSON: `{"chip_erase", "erase", "pages", "fills", "ram"}`, lists of
+/// `{"address", "size"}` with the sectors that are erased, the flash pages that are
+/// programmed, the parts of those pages without image data (programmed with the erased value)
+/// and the image data written to RAM. Pages follow the flash algorithm of each region, as
+/// reported by `pr_flash_sector_layout`; preserve ranges are not taken into account.
+///
+/// Returns the required size including NUL, or 0 on error (e.g. image data outside flash and
+/// RAM); see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_plan(
+    chip: *const c_char,
+    path: *const c_char,
+    format: *const c_char,
+    base: u64,
+    chip_erase: i32,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let (chip, path) = match (cstr_to_string(chip), cstr_to_string(path)) {
+        (Ok(c), Ok(p)) => (c, p),
+        (Err(e), _) | (_, Err(e)) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let format = match optional_str(format) {
+        Ok(f) => f,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let target = match registry().get_target_by_name(&chip) {
+        Ok(t) => t,
+        Err(e) => {
+            set_error(format!("get_target_by_name error: {}", e));
+            return 0;
+        }
+    };
+    let plan = image::load_image(&path, format.as_deref(), base)
+        .and_then(|image| flash_plan(&target, &image.segments, chip_erase != 0));
+    match plan.and_then(|plan| serde_json::to_string(&plan).map_err(|e| e.to_string())) {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e);
+            0
+        }
+    }
+}
+
+/// The sectors `pr_session_erase_range` would erase for `start..end`, as a JSON array of
+/// `{"address", "size"}`; `0..UINT64_MAX` lists every sector of the chip's flash. Nothing is
+/// attached.
+///
+/// Returns the required size including NUL, or 0 on error or a range without flash; see
+/// `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_erase_plan(
+    chip: *const c_char,
+    start: u64,
+    end: u64,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let chip = match cstr_to_string(chip) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let target = match registry().get_target_by_name(&chip) {
+        Ok(t) => t,
+        Err(e) => {
+            set_error(format!("get_target_by_name error: {}", e));
+            return 0;
+        }
+    };
+    let regions = flash_regions(&target);
+    let internal: Vec<&FlashRegion> = regions.iter().filter(|r| !r.external).collect();
+    let erase: Vec<Extent> = internal
+        .iter()
+        .flat_map(|r| sectors(r, &(start..end)))
+        .map(Extent::from)
+        .collect();
+    if erase.is_empty() {
+        set_error(format!("no flash in {:#x}..{:#x}", start, end));
+        return 0;
+    }
+    match serde_json::to_string(&erase) {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e.to_string());
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compressed_algorithms_are_listed() {
+        let target = registry().get_target_by_name("esp32c3").unwrap();
+        let regions = flash_regions(&target);
+        assert!(regions.iter().any(|r| !r.compressed.is_empty()));
+        let target = registry().get_target_by_name("STM32F407VGTx").unwrap();
+        assert!(
+            flash_regions(&target)
+                .iter()
+                .all(|r| r.compressed.is_empty())
+        );
+    }
+
+    #[test]
+    fn external_loader_ranges_are_listed() {
+        let target = registry().get_target_by_name("AT32F403ACGU7").unwrap();
+        let regions = flash_regions(&target);
+        let internal = regions.iter().find(|r| !r.external).unwrap();
+        assert_eq!(internal.start, 0x0800_0000);
+        assert!(!internal.sectors.is_empty());
+        let ext = regions.iter().find(|r| r.start == 0x0840_0000).unwrap();
+        assert!(ext.external);
+        assert!(ext.algorithms.len() > 1);
+        assert!(ext.algorithms.contains(&ext.name));
+    }
+
+    #[test]
+    fn sector_groups_are_clamped_to_the_region() {
+        let target = registry().get_target_by_name("STM32F407VGTx").unwrap();
+        let region = region_entry(&target, "part".to_string(), 0x0804_0000..0x0808_0000, false);
+        assert_eq!(
+            region.sectors,
+            vec![SectorGroup {
+                address: 0x0804_0000,
+                size: 0x2_0000
+            }]
+        );
+    }
+
+    #[test]
+    fn erase_ranges_cover_whole_sectors() {
+        let target = registry().get_target_by_name("STM32F407VGTx").unwrap();
+        let regions = flash_regions(&target);
+        // 4 x 16 KiB, 64 KiB, then 128 KiB sectors
+        assert_eq!(
+            sector_aligned(&regions, &(0x0800_4001..0x0800_4002)),
+            vec![Range {
+                start: 0x0800_4000,
+                end: 0x0800_8000
+            }]
+        );
+        assert_eq!(
+            sector_aligned(&regions, &(0x0800_c000..0x0801_0001)),
+            vec![Range {
+                start: 0x0800_c000,
+                end: 0x0802_0000
+            }]
+        );
+        assert!(sector_aligned(&regions, &(0x2000_0000..0x2000_1000)).is_empty());
+    }
+
+    #[test]
+    fn plan_erases_touched_sectors_and_programs_pages() {
+        let target = registry().get_target_by_name("STM32F407VGTx").unwrap();
+        let segments = [
+            Segment {
+                address: 0x0800_0000,
+                data: vec![0; 0x100],
+            },
+            Segment {
+                address: 0x0800_4010,
+                data: vec![0; 0x10],
+            },
+            Segment {
+                address: 0x2000_0000,
+                data: vec![0; 4],
+            },
+        ];
+        let plan = flash_plan(&target, &segments, false).unwrap();
+        let extent = |address, size| Extent { address, size };
+        assert_eq!(
+            plan.erase,
+            vec![extent(0x0800_0000, 0x4000), extent(0x0800_4000, 0x4000)]
+        );
+        let page = plan.pages[0].size;
+        assert_eq!(plan.pages.len(), 2);
+        assert_eq!(plan.pages[1].address, 0x0800_4000);
+        assert_eq!(plan.fills[0], extent(0x0800_0100, page - 0x100));
+        assert_eq!(plan.fills[1], extent(0x0800_4000, 0x10));
+        assert_eq!(plan.ram, vec![extent(0x2000_0000, 4)]);
+
+        let chip = flash_plan(&target, &segments[..1], true).unwrap();
+        // 4 x 16 KiB, 64 KiB and 7 x 128 KiB, not the OTP area
+        assert_eq!(chip.erase.len(), 12);
+        let outside = [Segment {
+            address: 0x1000_0000_0000,
+            data: vec![0; 4],
+        }];
+        assert!(flash_plan(&target, &outside, false).is_err());
+    }
+}
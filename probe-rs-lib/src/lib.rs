@@ -1,27 +1,140 @@
+// Every exported function takes raw pointers from C callers; validity is part of the
+// documented C contract rather than expressed through `unsafe fn`.
+#![allow(clippy::not_unsafe_ptr_arg_deref)]
+
 use probe_rs::config::Registry;
 use probe_rs::flashing::{
-    self, BinOptions, DownloadOptions, FlashProgress, Format, FormatKind, ProgressEvent,
-    ProgressOperation,
+    self, BinOptions, DownloadOptions, FlashLoader, FlashProgress, Format, FormatKind,
+    ProgressEvent, ProgressOperation,
 };
-use probe_rs::probe::{DebugProbeSelector, WireProtocol, list::Lister};
+use probe_rs::probe::WireProtocol;
 use probe_rs::probe::{
-    ch347usbjtag::Ch347UsbJtagFactory, cmsisdap::CmsisDapFactory, espusbjtag::EspUsbJtagFactory,
-    ftdi::FtdiProbeFactory, glasgow::GlasgowFactory, jlink::JLinkFactory,
-    sifliuart::SifliUartFactory, stlink::StLinkFactory, wlink::WchLinkFactory,
+    blackmagic::BlackMagicProbeFactory, ch347usbjtag::Ch347UsbJtagFactory,
+    cmsisdap::CmsisDapFactory, espusbjtag::EspUsbJtagFactory, ftdi::FtdiProbeFactory,
+    glasgow::GlasgowFactory, jlink::JLinkFactory, sifliuart::SifliUartFactory,
+    stlink::StLinkFactory, wlink::WchLinkFactory,
 };
-use probe_rs::{CoreStatus, MemoryInterface, Permissions, Session, SessionConfig};
+use probe_rs::{CoreStatus, DetachMode, MemoryInterface, Permissions, Session};
 use probe_rs_target::MemoryRegion;
-use std::collections::HashMap;
-use std::ffi::{CStr, c_char};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::{CStr, c_char, c_void};
+use std::ops::Range;
+use std::rc::Rc;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Mutex, OnceLock};
 
-static LAST_ERROR: OnceLock<Mutex<String>> = OnceLock::new();
+use image::Segment;
+use reconnect::CoreOpError;
+use verify::VerifyMode;
+
+mod algo_ram;
+pub mod api;
+mod bank;
+mod batch;
+mod bench;
+mod breakpoint;
+mod buffering;
+mod call;
+mod chip_erase;
+mod chip_list;
+mod compat;
+mod compression;
+mod debug_auth;
+mod debug_spec;
+mod defmt;
+mod detect;
+mod diff;
+mod disasm;
+mod driver_options;
+mod dump;
+mod elf;
+mod esp;
+mod gang;
+mod gdb_remote;
+mod gdb_server;
+mod image;
+mod job;
+mod layout;
+mod logging;
+mod manifest;
+mod mem_ap;
+mod monitor;
+mod otp;
+mod poll;
+mod probe_list;
+mod probe_version;
+mod profile;
+mod protection;
+#[cfg(feature = "python")]
+mod python;
+mod ramtest;
+mod reconnect;
+mod remote;
+mod reset;
+mod sdi;
+mod security;
+mod semihosting;
+mod serial_ports;
+mod server;
+mod stepping;
+mod strings;
+mod svd;
+mod swo;
+mod terminal;
+mod timeouts;
+mod timing;
+mod translate;
+mod var;
+mod verify;
+mod wide;
+
+// C functions of submodules that Rust users of the rlib call, e.g. the statically linked CLI.
+pub use algo_ram::pr_flash_option_algorithm_ram;
+pub use batch::{
+    pr_batch_begin, pr_batch_commit, pr_batch_discard, pr_batch_queue_read, pr_batch_queue_write,
+    pr_batch_results_size,
+};
+pub use bench::pr_benchmark;
+pub use buffering::pr_flash_option_buffering;
+pub use compression::pr_flash_option_compression;
+pub use debug_auth::pr_set_debug_auth_callback;
+pub use detect::pr_target_detect;
+pub use diff::pr_flash_diff;
+pub use gdb_remote::{pr_gdb_attach, pr_gdb_detach, pr_gdb_flash, pr_gdb_monitor};
+pub use gdb_server::{pr_gdb_server_start, pr_gdb_server_stop};
+pub use job::{pr_flash_job, pr_flash_resume};
+pub use layout::{
+    pr_flash_erase_plan, pr_flash_plan, pr_flash_sector_layout, pr_session_erase_range,
+};
+pub use logging::{pr_enable_file_logging, pr_set_log_callback};
+pub use mem_ap::{pr_read_mem_ap, pr_write_mem_ap};
+pub use otp::{pr_otp_read, pr_otp_write};
+pub use protection::{pr_flash_get_protection, pr_flash_set_protection};
+pub use reset::{pr_probe_hw_reset, pr_set_attach_under_reset};
+pub use security::{pr_target_security_state, pr_target_set_security_state};
+pub use semihosting::{pr_semihosting_poll, pr_semihosting_read};
+pub use swo::{pr_swo_read, pr_swo_start, pr_swo_stop};
+pub use terminal::{
+    pr_terminal_close, pr_terminal_open, pr_terminal_open_defmt, pr_terminal_read,
+    pr_terminal_write_line,
+};
+pub use timing::pr_flash_last_timing;
+pub use translate::{pr_core_translate_address, pr_read_mem_virtual, pr_write_mem_virtual};
+pub use verify::{pr_flash_option_verify_mode, pr_session_verify};
+
 static SESSIONS: OnceLock<Mutex<HashMap<u64, Arc<Mutex<Session>>>>> = OnceLock::new();
 static NEXT_HANDLE: AtomicU64 = AtomicU64::new(1);
 type ProgressCb = unsafe extern "C" fn(i32, f32, *const c_char, i32);
 static PROGRESS_CB: OnceLock<Mutex<Option<ProgressCb>>> = OnceLock::new();
+/// `ProgressCb` with the caller's `user_data` in front, for callbacks passed per call.
+type ProgressCbEx = unsafe extern "C" fn(*mut c_void, i32, f32, *const c_char, i32);
+/// `ProgressCb` with the session handle in front.
+type SessionProgressCb = unsafe extern "C" fn(u64, i32, f32, *const c_char, i32);
+/// Progress callbacks of single sessions (`pr_session_set_progress_callback`), taking precedence
+/// over the global one.
+static SESSION_PROGRESS_CBS: OnceLock<Mutex<HashMap<u64, SessionProgressCb>>> = OnceLock::new();
 #[derive(Clone, Copy)]
 enum ProgrammerType {
     CmsisDap,
@@ -33,33 +146,83 @@ enum ProgrammerType {
     SifliUart,
     Glasgow,
     Ch347UsbJtag,
+    BlackMagic,
 }
 static PROGRAMMER_TYPE: OnceLock<Mutex<Option<ProgrammerType>>> = OnceLock::new();
+thread_local! {
+    /// Error of the calling thread's last failing call, so that threads working on different
+    /// probes do not overwrite each other's error.
+    static LAST_ERROR: RefCell<String> = const { RefCell::new(String::new()) };
+    /// Programmer type of the calling thread (`pr_set_thread_programmer_type_code`), taking
+    /// precedence over the global one.
+    static THREAD_PROGRAMMER_TYPE: Cell<Option<ProgrammerType>> = const { Cell::new(None) };
+    /// Bytes done and announced total of the progress report being delivered on this thread
+    /// (`pr_progress_bytes`).
+    static PROGRESS_BYTES: Cell<Option<(u64, Option<u64>)>> = const { Cell::new(None) };
+    /// Whether that report is estimated rather than measured (`pr_progress_indeterminate`).
+    static PROGRESS_INDETERMINATE: Cell<bool> = const { Cell::new(false) };
+}
+/// What the target does once flashing has finished (`pr_flash_set_after`).
+#[derive(Clone, Copy, PartialEq, Debug)]
+enum AfterFlash {
+    /// Leave the core as the flash algorithm left it (halted).
+    None,
+    /// Reset and let the firmware run.
+    Reset,
+    /// Reset and halt at the reset vector.
+    Halt,
+    /// Reset and halt, then start at the ELF entry point.
+    Run,
+}
+// The flash options are process-wide, like those in `algo_ram`, `buffering`, `compression` and
+// `verify`; the header's thread paragraph lists them as shared.
+static AFTER_FLASH: OnceLock<Mutex<AfterFlash>> = OnceLock::new();
+/// Address and bytes written over a flashed image.
+type FlashPatch = (u64, Vec<u8>);
+/// Data patched over every flashed image (`pr_flash_set_patch`).
+static FLASH_PATCHES: OnceLock<Mutex<Vec<FlashPatch>>> = OnceLock::new();
+/// Flash kept intact by chip erases (`pr_flash_option_preserve_range`).
+static PRESERVE_RANGES: OnceLock<Mutex<Vec<Range<u64>>>> = OnceLock::new();
+/// Immutable once built, so threads share it without locking.
 static REGISTRY: OnceLock<Registry> = OnceLock::new();
 
 #[derive(Clone)]
 struct ManuEntry {
     name: String,
+    /// JEP106 continuation and identity code; `None` for generic targets.
+    jep106: Option<(u8, u8)>,
     chips: Vec<String>,
+    /// Families by name, each with the indices of its chips in `chips`.
+    families: Vec<FamilyEntry>,
+}
+
+#[derive(Clone)]
+struct FamilyEntry {
+    name: String,
+    chips: Vec<u32>,
 }
 
 struct ChipDb {
     manufacturers: Vec<ManuEntry>,
     name_to_index: HashMap<String, (u32, u32)>,
+    /// Family name of every chip.
+    family_of: HashMap<String, String>,
 }
 
 static CHIP_DB: OnceLock<ChipDb> = OnceLock::new();
 
 fn registry() -> &'static Registry {
-    REGISTRY.get_or_init(|| Registry::from_builtin_families())
+    REGISTRY.get_or_init(Registry::from_builtin_families)
 }
 
 fn build_chip_db() -> ChipDb {
     let reg = registry();
     let mut manu_map: HashMap<(u8, u8), usize> = HashMap::new();
     let mut manufacturers: Vec<ManuEntry> = Vec::new();
+    let mut family_of: HashMap<String, String> = HashMap::new();
 
     for family in reg.families() {
+        let jep106 = family.manufacturer.map(|code| (code.cc, code.id));
         let (cc, id, mname) = match family.manufacturer {
             Some(code) => {
                 let name = code.get().unwrap_or("<unknown>").to_string();
@@ -71,19 +234,37 @@ fn build_chip_db() -> ChipDb {
             let i = manufacturers.len();
             manufacturers.push(ManuEntry {
                 name: mname.clone(),
+                jep106,
                 chips: Vec::new(),
+                families: Vec::new(),
             });
             i
         });
         let targets = reg
             .get_targets_by_family_name(&family.name)
             .unwrap_or_default();
+        for t in &targets {
+            family_of.insert(t.clone(), family.name.clone());
+        }
         manufacturers[idx].chips.extend(targets);
     }
 
     for m in manufacturers.iter_mut() {
         m.chips.sort();
         m.chips.dedup();
+        let mut families: BTreeMap<&str, Vec<u32>> = BTreeMap::new();
+        for (ci, c) in m.chips.iter().enumerate() {
+            if let Some(family) = family_of.get(c) {
+                families.entry(family).or_default().push(ci as u32);
+            }
+        }
+        m.families = families
+            .into_iter()
+            .map(|(name, chips)| FamilyEntry {
+                name: name.to_string(),
+                chips,
+            })
+            .collect();
     }
 
     let mut name_to_index: HashMap<String, (u32, u32)> = HashMap::new();
@@ -96,13 +277,13 @@ fn build_chip_db() -> ChipDb {
     ChipDb {
         manufacturers,
         name_to_index,
+        family_of,
     }
 }
 
 fn do_chip_erase(chip: &str, speed_khz: u32, proto: Option<WireProtocol>) -> i32 {
-    let lister = Lister::new();
-    let mut probes = lister.list_all();
-    if let Some(ty) = *programmer_type_lock().lock().unwrap() {
+    let mut probes = remote::list_all();
+    if let Some(ty) = programmer_type() {
         probes.retain(|p| info_matches_type(p, ty));
     }
     if probes.is_empty() {
@@ -125,40 +306,93 @@ fn do_chip_erase(chip: &str, speed_khz: u32, proto: Option<WireProtocol>) -> i32
             return -1;
         }
     };
+    if let Err(e) = driver_options::apply(&mut probe) {
+        set_error(e);
+        return -1;
+    }
 
-    if let Some(p) = proto {
-        if let Err(e) = probe.select_protocol(p) {
-            set_error(format!("failed to select protocol: {}", e));
-            return -1;
-        }
+    if let Some(p) = proto
+        && let Err(e) = probe.select_protocol(p)
+    {
+        set_error(format!("failed to select protocol: {}", e));
+        return -1;
     }
 
-    if speed_khz > 0 {
-        if let Err(e) = probe.set_speed(speed_khz) {
-            set_error(format!("failed to set speed: {}", e));
-            return -1;
-        }
+    if speed_khz > 0
+        && let Err(e) = probe.set_speed(speed_khz)
+    {
+        set_error(format!("failed to set speed: {}", e));
+        return -1;
     }
 
-    let mut session = match probe.attach(target, Permissions::new()) {
+    let mut session = match reset::attach(probe, target, Permissions::new()) {
         Ok(s) => s,
         Err(e) => {
-            set_error(format!("failed to attach: {}", e));
+            set_error(e);
             return -1;
         }
     };
 
-    let mut progress = FlashProgress::new(|_| {});
-    let res = flashing::erase_all(&mut session, &mut progress);
+    let preserved = preserve_ranges_lock().lock().unwrap().clone();
+    let res = if preserved.is_empty() {
+        let mut progress = FlashProgress::new(|_| {});
+        flashing::erase_all(&mut session, &mut progress).map_err(|e| e.to_string())
+    } else {
+        erase_preserving(&mut session, &preserved)
+    };
     match res {
         Ok(_) => 0,
         Err(e) => {
-            set_error(e.to_string());
+            set_error(e);
             -1
         }
     }
 }
 
+/// Parts of `region` outside all `preserved` ranges, in ascending order.
+fn unpreserved_ranges(region: Range<u64>, preserved: &[Range<u64>]) -> Vec<Range<u64>> {
+    let mut preserved: Vec<&Range<u64>> = preserved
+        .iter()
+        .filter(|p| p.start < region.end && p.end > region.start)
+        .collect();
+    preserved.sort_by_key(|p| p.start);
+
+    let mut out = Vec::new();
+    let mut cursor = region.start;
+    for p in preserved {
+        if p.start > cursor {
+            out.push(cursor..p.start);
+        }
+        cursor = cursor.max(p.end);
+    }
+    if cursor < region.end {
+        out.push(cursor..region.end);
+    }
+    out
+}
+
+/// Replacement for a chip erase: erase every flash sector that does not overlap a
+/// preserved range.
+fn erase_preserving(session: &mut Session, preserved: &[Range<u64>]) -> Result<(), String> {
+    let regions: Vec<Range<u64>> = session
+        .target()
+        .memory_map
+        .iter()
+        .filter_map(MemoryRegion::as_nvm_region)
+        .filter(|r| !r.is_alias)
+        .map(|r| r.range.clone())
+        .collect();
+    let mut progress = FlashProgress::empty();
+    for region in regions {
+        // `flashing::erase` only erases sectors that lie completely inside the range.
+        for range in unpreserved_ranges(region, preserved) {
+            flashing::erase(session, &mut progress, range.start, range.end)
+                .map_err(|e| format!("erase error: {}", e))?;
+        }
+    }
+    Ok(())
+}
+
 /// Erase the entire flash memory of a target chip.
 ///
 /// This function attempts to connect to a target chip and erase its entire
@@ -196,6 +430,39 @@ fn chip_db() -> &'static ChipDb {
     CHIP_DB.get_or_init(build_chip_db)
 }
 
+/// Build the chip database on a background thread, once; callers of `chip_db` in the meantime
+/// wait for it instead of building it again.
+fn start_chip_db_build() {
+    static STARTED: std::sync::Once = std::sync::Once::new();
+    STARTED.call_once(|| {
+        if CHIP_DB.get().is_none() {
+            std::thread::spawn(chip_db);
+        }
+    });
+}
+
+/// Start building the chip database in the background, so it is ready by the time a host
+/// needs it; does nothing if it is built or being built. Hosts that never use it need not
+/// call this and pay nothing for it.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_chip_db_prefetch() {
+    start_chip_db_build();
+}
+
+/// Whether the chip database is built, so a GUI can show a spinner instead of blocking in
+/// `pr_chip_manufacturer_count` and the other chip database calls. Starts building it in the
+/// background if `pr_chip_db_prefetch` has not already.
+///
+/// Returns 1 if ready, 0 if still building.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_chip_db_ready() -> i32 {
+    if CHIP_DB.get().is_some() {
+        return 1;
+    }
+    start_chip_db_build();
+    0
+}
+
 fn make_target_spec_string(manufacturer: &str, chip_name: &str) -> Result<String, String> {
     let target = match registry().get_target_by_name(chip_name) {
         Ok(t) => t,
@@ -247,9 +514,15 @@ fn make_target_spec_string(manufacturer: &str, chip_name: &str) -> Result<String
         .collect::<Vec<_>>()
         .join(", ");
     let default_fmt = target.default_format.clone().unwrap_or_default();
+    let flash_banks =
+        serde_json::to_string(&bank::flash_banks(&target)).map_err(|e| e.to_string())?;
+    let debug_fields: String = debug_spec::spec_fields(&target)
+        .into_iter()
+        .map(|(key, value)| format!(",\"{}\":{}", key, value))
+        .collect();
 
     let s = format!(
-        "{{\"manufacturer\":\"{}\",\"chip\":\"{}\",\"architecture\":\"{}\",\"cores\":\"{}\",\"ram_bytes\":{},\"nvm_bytes\":{},\"regions\":\"{}\",\"flash_algorithms\":\"{}\",\"default_format\":\"{}\"}}",
+        "{{\"manufacturer\":\"{}\",\"chip\":\"{}\",\"architecture\":\"{}\",\"cores\":\"{}\",\"ram_bytes\":{},\"nvm_bytes\":{},\"regions\":\"{}\",\"flash_algorithms\":\"{}\",\"default_format\":\"{}\",\"flash_banks\":{}{}}}",
         manufacturer,
         chip_name,
         arch,
@@ -258,7 +531,9 @@ fn make_target_spec_string(manufacturer: &str, chip_name: &str) -> Result<String
         nvm_total,
         regions.join(";"),
         flash_algos,
-        default_fmt
+        default_fmt,
+        flash_banks,
+        debug_fields
     );
     Ok(s)
 }
@@ -290,6 +565,48 @@ pub extern "C" fn pr_chip_manufacturer_name(index: u32, buf: *mut c_char, buf_le
     need
 }
 
+/// Write the JEP106 code of manufacturer `index`.
+///
+/// Returns 0 on success, 1 if the manufacturer has no code (generic targets), -1 on an invalid
+/// index.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_chip_manufacturer_jep106(index: u32, out_cc: *mut u8, out_id: *mut u8) -> i32 {
+    let Some(m) = chip_db().manufacturers.get(index as usize) else {
+        set_error("manufacturer index out of range".to_string());
+        return -1;
+    };
+    let Some((cc, id)) = m.jep106 else {
+        return 1;
+    };
+    if !out_cc.is_null() {
+        unsafe { *out_cc = cc };
+    }
+    if !out_id.is_null() {
+        unsafe { *out_id = id };
+    }
+    0
+}
+
+/// Name of the manufacturer with JEP106 continuation code `cc` and identity code `id`, from the
+/// table the chip database uses. The parity bit (bit 7) of `id` is ignored, so the code can be
+/// taken straight from an IDCODE or ROM table.
+///
+/// Returns the required size including NUL, or 0 for an unknown code.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_jep106_name(cc: u8, id: u8, buf: *mut c_char, buf_len: usize) -> usize {
+    match jep106::JEP106Code::new(cc, id & 0x7f).get() {
+        Some(name) => write_c_str(name, buf, buf_len),
+        None => {
+            set_error(format!(
+                "unknown JEP106 code cc={:#x} id={:#x}",
+                cc,
+                id & 0x7f
+            ));
+            0
+        }
+    }
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn pr_chip_model_count(manufacturer_index: u32) -> u32 {
     let db = chip_db();
@@ -331,6 +648,76 @@ pub extern "C" fn pr_chip_model_name(
     need
 }
 
+fn chip_family(manufacturer_index: u32, family_index: u32) -> Result<&'static FamilyEntry, String> {
+    let m = chip_db()
+        .manufacturers
+        .get(manufacturer_index as usize)
+        .ok_or("manufacturer index out of range")?;
+    m.families
+        .get(family_index as usize)
+        .ok_or_else(|| "family index out of range".to_string())
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_chip_family_count(manufacturer_index: u32) -> u32 {
+    let db = chip_db();
+    let Some(m) = db.manufacturers.get(manufacturer_index as usize) else {
+        set_error("manufacturer index out of range".to_string());
+        return 0;
+    };
+    m.families.len() as u32
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_chip_family_name(
+    manufacturer_index: u32,
+    family_index: u32,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> usize {
+    match chip_family(manufacturer_index, family_index) {
+        Ok(f) => write_c_str(&f.name, buf, buf_len),
+        Err(e) => {
+            set_error(e);
+            0
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_chip_model_count_in_family(manufacturer_index: u32, family_index: u32) -> u32 {
+    match chip_family(manufacturer_index, family_index) {
+        Ok(f) => f.chips.len() as u32,
+        Err(e) => {
+            set_error(e);
+            0
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_chip_model_name_in_family(
+    manufacturer_index: u32,
+    family_index: u32,
+    index: u32,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> usize {
+    let chip_index = chip_family(manufacturer_index, family_index).and_then(|f| {
+        f.chips
+            .get(index as usize)
+            .copied()
+            .ok_or_else(|| "chip index out of range".to_string())
+    });
+    match chip_index {
+        Ok(ci) => pr_chip_model_name(manufacturer_index, ci, buf, buf_len),
+        Err(e) => {
+            set_error(e);
+            0
+        }
+    }
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn pr_chip_model_specs(
     manufacturer_index: u32,
@@ -415,15 +802,62 @@ pub extern "C" fn pr_chip_specs_by_name(
 }
 
 fn set_error(msg: String) {
-    let lock = LAST_ERROR.get_or_init(|| Mutex::new(String::new()));
-    let mut s = lock.lock().unwrap();
-    *s = msg;
+    LAST_ERROR.with(|last| *last.borrow_mut() = msg);
+}
+
+/// `error` followed by all its sources, so nested causes (e.g. a failing debug sequence)
+/// reach `pr_last_error`.
+fn error_chain(error: &dyn std::error::Error) -> String {
+    let mut msg = error.to_string();
+    let mut source = error.source();
+    while let Some(e) = source {
+        msg.push_str(": ");
+        msg.push_str(&e.to_string());
+        source = e.source();
+    }
+    msg
+}
+
+/// Copy `s` into a caller-provided buffer as a NUL-terminated UTF-8 string.
+///
+/// Returns the required size including NUL. If `buf` is null or `buf_len` is 0,
+/// nothing is written; longer strings are truncated to fit.
+fn write_c_str(s: &str, buf: *mut c_char, buf_len: usize) -> usize {
+    let bytes = s.as_bytes();
+    let need = bytes.len().saturating_add(1);
+    if buf.is_null() || buf_len == 0 {
+        return need;
+    }
+    let copy = need.min(buf_len);
+    unsafe {
+        let slice = std::slice::from_raw_parts_mut(buf as *mut u8, copy);
+        let n = copy.saturating_sub(1);
+        slice[..n].copy_from_slice(&bytes[..n]);
+        slice[n] = 0;
+    }
+    need
 }
 
 fn progress_cb_lock() -> &'static Mutex<Option<ProgressCb>> {
     PROGRESS_CB.get_or_init(|| Mutex::new(None))
 }
 
+fn session_progress_cbs() -> &'static Mutex<HashMap<u64, SessionProgressCb>> {
+    SESSION_PROGRESS_CBS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn after_flash_lock() -> &'static Mutex<AfterFlash> {
+    AFTER_FLASH.get_or_init(|| Mutex::new(AfterFlash::None))
+}
+
+fn preserve_ranges_lock() -> &'static Mutex<Vec<Range<u64>>> {
+    PRESERVE_RANGES.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+fn flash_patches_lock() -> &'static Mutex<Vec<FlashPatch>> {
+    FLASH_PATCHES.get_or_init(|| Mutex::new(Vec::new()))
+}
+
 fn op_code(op: ProgressOperation) -> i32 {
     match op {
         ProgressOperation::Erase => 1,
@@ -464,6 +898,7 @@ fn parse_programmer_type(name: &str) -> Option<ProgrammerType> {
         "sifli-uart" | "sifliuart" => Some(ProgrammerType::SifliUart),
         "glasgow" => Some(ProgrammerType::Glasgow),
         "ch347-usb-jtag" | "ch347usbjtag" => Some(ProgrammerType::Ch347UsbJtag),
+        "blackmagic" | "bmp" => Some(ProgrammerType::BlackMagic),
         _ => None,
     }
 }
@@ -472,6 +907,13 @@ fn programmer_type_lock() -> &'static Mutex<Option<ProgrammerType>> {
     PROGRAMMER_TYPE.get_or_init(|| Mutex::new(None))
 }
 
+/// The programmer type probes are selected by on the calling thread: its own, else the global one.
+fn programmer_type() -> Option<ProgrammerType> {
+    THREAD_PROGRAMMER_TYPE
+        .get()
+        .or_else(|| *programmer_type_lock().lock().unwrap())
+}
+
 fn type_to_code(ty: ProgrammerType) -> i32 {
     match ty {
         ProgrammerType::CmsisDap => 1,
@@ -483,6 +925,7 @@ fn type_to_code(ty: ProgrammerType) -> i32 {
         ProgrammerType::SifliUart => 7,
         ProgrammerType::Glasgow => 8,
         ProgrammerType::Ch347UsbJtag => 9,
+        ProgrammerType::BlackMagic => 10,
     }
 }
 
@@ -497,6 +940,7 @@ fn code_to_type(code: i32) -> Option<ProgrammerType> {
         7 => Some(ProgrammerType::SifliUart),
         8 => Some(ProgrammerType::Glasgow),
         9 => Some(ProgrammerType::Ch347UsbJtag),
+        10 => Some(ProgrammerType::BlackMagic),
         _ => None,
     }
 }
@@ -512,6 +956,7 @@ fn type_to_str(ty: ProgrammerType) -> &'static str {
         ProgrammerType::SifliUart => "sifli-uart",
         ProgrammerType::Glasgow => "glasgow",
         ProgrammerType::Ch347UsbJtag => "ch347-usb-jtag",
+        ProgrammerType::BlackMagic => "blackmagic",
     }
 }
 
@@ -526,6 +971,7 @@ fn info_matches_type(info: &probe_rs::probe::DebugProbeInfo, ty: ProgrammerType)
         ProgrammerType::SifliUart => info.is_probe_type::<SifliUartFactory>(),
         ProgrammerType::Glasgow => info.is_probe_type::<GlasgowFactory>(),
         ProgrammerType::Ch347UsbJtag => info.is_probe_type::<Ch347UsbJtagFactory>(),
+        ProgrammerType::BlackMagic => info.is_probe_type::<BlackMagicProbeFactory>(),
     }
 }
 
@@ -567,45 +1013,45 @@ fn detect_format_from_path(path: &str, base: Option<u64>, skip: u32) -> Result<F
     }
 }
 
-fn do_flash(
-    chip: &str,
-    path: &str,
-    format: Format,
-    verify: i32,
-    preverify: i32,
-    chip_erase: i32,
-    speed_khz: u32,
-    proto: Option<WireProtocol>,
-) -> i32 {
-    let mut opts = DownloadOptions::default();
-    opts.verify = verify != 0;
-    opts.preverify = preverify != 0;
-    opts.do_chip_erase = chip_erase != 0;
+/// Translate probe-rs progress events into `(op, percent, status, eta_ms)` callbacks. While a
+/// chip erase runs, estimated reports are made from a helper thread (`chip_erase::Ticker`).
+fn progress_handler(
+    cb: impl FnMut(i32, f32, *const c_char, i32) + Send + 'static,
+) -> FlashProgress<'static> {
+    use std::time::Duration;
 
-    if let Some(cb) = *progress_cb_lock().lock().unwrap() {
-        use std::time::Duration;
-
-        let mut t_erase: Option<u64> = None;
-        let mut d_erase: u64 = 0;
-        let mut tm_erase: Duration = Duration::ZERO;
-        let mut t_prog: Option<u64> = None;
-        let mut d_prog: u64 = 0;
-        let mut tm_prog: Duration = Duration::ZERO;
-        let mut t_verify: Option<u64> = None;
-        let mut d_verify: u64 = 0;
-        let mut tm_verify: Duration = Duration::ZERO;
-        let mut t_fill: Option<u64> = None;
-        let mut d_fill: u64 = 0;
-        let mut tm_fill: Duration = Duration::ZERO;
-        let mut last_erase_pct: f32 = -1.0;
-        let mut last_prog_pct: f32 = -1.0;
-        let mut last_verify_pct: f32 = -1.0;
-        let mut last_fill_pct: f32 = -1.0;
-
-        opts.progress = FlashProgress::new(move |event| match event {
+    let shared = Arc::new(Mutex::new(cb));
+    let cb = {
+        let shared = shared.clone();
+        move |op, pct, status, eta| (*shared.lock().unwrap())(op, pct, status, eta)
+    };
+    let mut mass_erase = false;
+    let mut ticker: Option<chip_erase::Ticker> = None;
+
+    let mut t_erase: Option<u64> = None;
+    let mut d_erase: u64 = 0;
+    let mut tm_erase: Duration = Duration::ZERO;
+    let mut t_prog: Option<u64> = None;
+    let mut d_prog: u64 = 0;
+    let mut tm_prog: Duration = Duration::ZERO;
+    let mut t_verify: Option<u64> = None;
+    let mut d_verify: u64 = 0;
+    let mut tm_verify: Duration = Duration::ZERO;
+    let mut t_fill: Option<u64> = None;
+    let mut d_fill: u64 = 0;
+    let mut tm_fill: Duration = Duration::ZERO;
+    let mut last_erase_pct: f32 = -1.0;
+    let mut last_prog_pct: f32 = -1.0;
+    let mut last_verify_pct: f32 = -1.0;
+    let mut last_fill_pct: f32 = -1.0;
+
+    FlashProgress::new(move |event| {
+        match event {
             ProgressEvent::AddProgressBar { operation, total } => {
                 match operation {
                     ProgressOperation::Erase => {
+                        // Only a chip erase announces no size.
+                        mass_erase = total.is_none();
                         t_erase = total;
                         d_erase = 0;
                         tm_erase = Duration::ZERO;
@@ -642,9 +1088,29 @@ fn do_flash(
                 }
             }
             ProgressEvent::Started(op) => {
-                let st = status_text(op);
+                let estimated = matches!(op, ProgressOperation::Erase) && mass_erase;
+                let st = if estimated {
+                    chip_erase::STATUS
+                } else {
+                    status_text(op)
+                };
                 let cs = std::ffi::CString::new(st).unwrap();
-                unsafe { cb(op_code(op), 0.0, cs.as_ptr(), -1) };
+                let total = match op {
+                    ProgressOperation::Erase => t_erase,
+                    ProgressOperation::Program => t_prog,
+                    ProgressOperation::Verify => t_verify,
+                    ProgressOperation::Fill => t_fill,
+                };
+                PROGRESS_BYTES.set(Some((0, total)));
+                PROGRESS_INDETERMINATE.set(estimated);
+                let eta = match chip_erase::expected().filter(|_| estimated) {
+                    Some(estimate) => estimate.as_millis().min(i32::MAX as u128) as i32,
+                    None => -1,
+                };
+                cb(op_code(op), 0.0, cs.as_ptr(), eta);
+                if let Some(estimate) = chip_erase::expected().filter(|_| estimated) {
+                    ticker = Some(chip_erase::Ticker::start(shared.clone(), estimate));
+                }
                 match op {
                     ProgressOperation::Erase => {
                         last_erase_pct = 0.0;
@@ -701,11 +1167,17 @@ fn do_flash(
                 let pct = percent.min(100.0);
                 let changed = (pct - *last).abs() >= 0.1 || pct >= 100.0;
                 if changed {
-                    unsafe { cb(op_code(operation), pct, cs.as_ptr(), eta_ms) };
+                    PROGRESS_BYTES.set(Some((*d_ref, *total_opt)));
+                    PROGRESS_INDETERMINATE.set(false);
+                    cb(op_code(operation), pct, cs.as_ptr(), eta_ms);
                     *last = pct;
                 }
             }
             ProgressEvent::Finished(op) => {
+                if matches!(op, ProgressOperation::Erase) {
+                    // Dropping the ticker waits for its last report.
+                    drop(ticker.take());
+                }
                 let st = status_text(op);
                 let cs = std::ffi::CString::new(st).unwrap();
                 let last = match op {
@@ -715,14 +1187,34 @@ fn do_flash(
                     ProgressOperation::Fill => &mut last_fill_pct,
                 };
                 if *last < 100.0 {
-                    unsafe { cb(op_code(op), 100.0, cs.as_ptr(), 0) };
+                    let (done, total) = match op {
+                        ProgressOperation::Erase => (d_erase, t_erase),
+                        ProgressOperation::Program => (d_prog, t_prog),
+                        ProgressOperation::Verify => (d_verify, t_verify),
+                        ProgressOperation::Fill => (d_fill, t_fill),
+                    };
+                    PROGRESS_BYTES.set(Some((done.max(total.unwrap_or(0)), total)));
+                    PROGRESS_INDETERMINATE.set(false);
+                    cb(op_code(op), 100.0, cs.as_ptr(), 0);
                     *last = 100.0;
                 }
             }
             ProgressEvent::Failed(op) => {
+                if matches!(op, ProgressOperation::Erase) {
+                    // Dropping the ticker waits for its last report.
+                    drop(ticker.take());
+                }
                 let st = status_text(op);
                 let cs = std::ffi::CString::new(st).unwrap();
-                unsafe { cb(op_code(op), 0.0, cs.as_ptr(), -1) };
+                let bytes = match op {
+                    ProgressOperation::Erase => (d_erase, t_erase),
+                    ProgressOperation::Program => (d_prog, t_prog),
+                    ProgressOperation::Verify => (d_verify, t_verify),
+                    ProgressOperation::Fill => (d_fill, t_fill),
+                };
+                PROGRESS_BYTES.set(Some(bytes));
+                PROGRESS_INDETERMINATE.set(false);
+                cb(op_code(op), 0.0, cs.as_ptr(), -1);
                 match op {
                     ProgressOperation::Erase => {
                         last_erase_pct = 0.0;
@@ -739,65 +1231,243 @@ fn do_flash(
                 }
             }
             ProgressEvent::FlashLayoutReady { .. } | ProgressEvent::DiagnosticMessage { .. } => {}
+        }
+    })
+}
+
+/// Download options with the flags set and the progress callback (if any) attached: that of
+/// `session` if it has one, else the global one.
+fn download_options(
+    session: Option<u64>,
+    verify: i32,
+    preverify: i32,
+    chip_erase: i32,
+) -> DownloadOptions<'static> {
+    let mut opts = DownloadOptions::default();
+    opts.verify = verify != 0;
+    opts.preverify = preverify != 0;
+    opts.do_chip_erase = chip_erase != 0;
+    buffering::apply_to(&mut opts);
+
+    let session_cb = session.and_then(|handle| {
+        let cb = session_progress_cbs()
+            .lock()
+            .unwrap()
+            .get(&handle)
+            .copied()?;
+        Some((handle, cb))
+    });
+    if let Some((handle, cb)) = session_cb {
+        opts.progress = progress_handler(move |op, pct, status, eta| unsafe {
+            cb(handle, op, pct, status, eta)
         });
+    } else if let Some(cb) = *progress_cb_lock().lock().unwrap() {
+        opts.progress =
+            progress_handler(move |op, pct, status, eta| unsafe { cb(op, pct, status, eta) });
     }
+    opts
+}
 
-    let session_cfg = SessionConfig {
-        permissions: Default::default(),
-        speed: if speed_khz == 0 {
-            None
-        } else {
-            Some(speed_khz)
-        },
-        protocol: proto,
-    };
-    let mut session = if let Some(ty) = *programmer_type_lock().lock().unwrap() {
-        let lister = Lister::new();
-        let list = lister.list_all();
-        let Some(info) = list.into_iter().find(|i| info_matches_type(i, ty)) else {
-            set_error("no probe matching programmer type".to_string());
-            return 1;
-        };
-        let mut probe = match info.open() {
-            Ok(p) => p,
-            Err(e) => {
-                set_error(format!("open probe error: {}", e));
-                return 1;
-            }
-        };
-        if let Some(p) = proto {
-            if let Err(e) = probe.select_protocol(p) {
-                set_error(format!("select protocol error: {}", e));
-                return 1;
-            }
+/// Progress reported to `cb(user_data, op, percent, status, eta_ms)`, or none if `cb` is NULL.
+fn user_progress(cb: Option<ProgressCbEx>, user_data: *mut c_void) -> FlashProgress<'static> {
+    /// The caller's context; it may be used from the helper thread of chip erase estimates.
+    struct UserData(*mut c_void);
+    unsafe impl Send for UserData {}
+    impl UserData {
+        fn get(&self) -> *mut c_void {
+            self.0
         }
-        if speed_khz > 0 {
-            if let Err(e) = probe.set_speed(speed_khz) {
-                set_error(format!("set speed error: {}", e));
-                return 1;
-            }
+    }
+
+    let user_data = UserData(user_data);
+    match cb {
+        Some(cb) => progress_handler(move |op, pct, status, eta| unsafe {
+            cb(user_data.get(), op, pct, status, eta)
+        }),
+        None => FlashProgress::empty(),
+    }
+}
+
+fn after_flash_from_code(code: i32) -> Option<AfterFlash> {
+    match code {
+        0 => Some(AfterFlash::None),
+        1 => Some(AfterFlash::Reset),
+        2 => Some(AfterFlash::Halt),
+        3 => Some(AfterFlash::Run),
+        _ => None,
+    }
+}
+
+fn apply_after_flash(
+    session: &mut Session,
+    after: AfterFlash,
+    entry: Option<u64>,
+) -> Result<(), String> {
+    if after == AfterFlash::None {
+        return Ok(());
+    }
+    let timeout = std::time::Duration::from_millis(500);
+    let mut core = session
+        .core(0)
+        .map_err(|e| format!("core access error: {}", e))?;
+    let res = match after {
+        AfterFlash::None => Ok(()),
+        AfterFlash::Reset => core.reset(),
+        AfterFlash::Halt => core.reset_and_halt(timeout).map(|_| ()),
+        AfterFlash::Run => core.reset_and_halt(timeout).and_then(|_| {
+            let pc = core.program_counter();
+            core.write_core_reg(pc, entry.unwrap_or_default())?;
+            core.run()
+        }),
+    };
+    res.map_err(|e| format!("post-flash {:?} error: {}", after, e))
+}
+
+/// Load an image, apply the global flash patches plus `extra_patches` on top, program it and
+/// apply the `pr_flash_set_after` behavior. The timing is kept for `pr_flash_last_timing`, with
+/// `speed_khz` as the probe speed used.
+fn flash_image(
+    session: &mut Session,
+    path: &str,
+    format: Format,
+    mut opts: DownloadOptions<'static>,
+    extra_patches: &[FlashPatch],
+    speed_khz: Option<u32>,
+) -> Result<(), String> {
+    timeouts::apply_to_session(session);
+    let timing = timing::instrument(&mut opts.progress);
+    chip_erase::expect(session.target());
+    let verify_mode = opts.verify.then(|| verify::mode().name());
+    let result = load_and_commit(session, path, format, opts, extra_patches);
+    timing::finish(
+        &timing,
+        result.is_ok(),
+        speed_khz,
+        verify_mode,
+        session.target(),
+    );
+    result
+}
+
+/// The post-flash behavior in effect and, to start at the ELF entry point, the entry point of
+/// `path`; resolved up front so a bad image fails before anything is erased.
+fn after_flash_for(path: &str, format: &Format) -> Result<(AfterFlash, Option<u64>), String> {
+    let after = *after_flash_lock().lock().unwrap();
+    let entry = match (after, format) {
+        (AfterFlash::Run, Format::Elf(_)) => Some(elf::entry_point(path)?),
+        (AfterFlash::Run, _) => {
+            return Err("starting at the entry point requires an ELF image".to_string());
         }
-        match probe.attach(chip, Default::default()) {
-            Ok(sess) => sess,
-            Err(e) => {
-                set_error(format!("attach error: {}", e));
-                return 1;
+        _ => None,
+    };
+    Ok((after, entry))
+}
+
+/// The flash loader of `path` with the flash patches and `extra_patches` on top.
+fn patched_loader(
+    session: &mut Session,
+    path: &str,
+    format: Format,
+    extra_patches: &[FlashPatch],
+) -> Result<FlashLoader, String> {
+    let mut loader = flashing::build_loader(session, path, format, None)
+        .map_err(|e| format!("flash error: {}", error_chain(&e)))?;
+    let patches = flash_patches_lock().lock().unwrap().clone();
+    for (address, data) in patches.iter().chain(extra_patches) {
+        loader
+            .patch_data(*address, data)
+            .map_err(|e| format!("patch error at {:#x}: {}", address, e))?;
+    }
+    Ok(loader)
+}
+
+/// The data of `loader` as segments.
+fn loader_segments(loader: &FlashLoader) -> Vec<Segment> {
+    loader
+        .data()
+        .map(|(address, data)| Segment {
+            address,
+            data: data.to_vec(),
+        })
+        .collect()
+}
+
+fn load_and_commit(
+    session: &mut Session,
+    path: &str,
+    format: Format,
+    opts: DownloadOptions<'_>,
+    extra_patches: &[FlashPatch],
+) -> Result<(), String> {
+    let (after, entry) = after_flash_for(path, &format)?;
+    let mut loader = patched_loader(session, path, format, extra_patches)?;
+    commit_loader(session, &mut loader, opts)?;
+    apply_after_flash(session, after, entry)
+}
+
+/// Program `loader` with the preserved ranges and the verify mode in effect.
+fn commit_loader(
+    session: &mut Session,
+    loader: &mut FlashLoader,
+    opts: DownloadOptions<'_>,
+) -> Result<(), String> {
+    let mut opts = opts;
+    let preserved = preserve_ranges_lock().lock().unwrap().clone();
+    if !preserved.is_empty() {
+        for (address, data) in loader.data() {
+            let end = address + data.len() as u64;
+            if let Some(p) = preserved.iter().find(|p| p.start < end && p.end > address) {
+                return Err(format!(
+                    "image data at {:#x}..{:#x} overlaps preserved range {:#x}..{:#x}",
+                    address, end, p.start, p.end
+                ));
             }
         }
-    } else {
-        match Session::auto_attach(chip, session_cfg) {
-            Ok(s) => s,
-            Err(e) => {
-                set_error(format!("attach error: {}", e));
-                return 1;
-            }
+        if opts.do_chip_erase {
+            erase_preserving(session, &preserved)?;
+            opts.do_chip_erase = false;
         }
+        // Sectors shared between the image and a preserved range are read back and rewritten.
+        opts.keep_unwritten_bytes = true;
+    }
+
+    // Verify modes other than the programmed pages run after the commit, on the same progress.
+    let verify_mode = Some(verify::mode()).filter(|m| opts.verify && *m != VerifyMode::Pages);
+    if verify_mode == Some(VerifyMode::Sectors) && opts.keep_unwritten_bytes {
+        return Err("sector verify cannot be combined with preserved ranges".to_string());
+    }
+    let progress = Rc::new(RefCell::new(std::mem::take(&mut opts.progress)));
+    opts.progress = {
+        let progress = progress.clone();
+        FlashProgress::new(move |event| progress.borrow_mut().emit(event))
     };
-    match flashing::download_file_with_options(&mut session, path, format, opts) {
-        Ok(_) => 0,
+    opts.verify &= verify_mode.is_none();
+
+    loader
+        .commit(session, opts)
+        .map_err(|e| format!("flash error: {}", error_chain(&e)))?;
+    if let Some(mode) = verify_mode {
+        let segments = loader_segments(loader);
+        verify::verify_flashed(session, &segments, mode, &mut progress.borrow_mut())?;
+    }
+    Ok(())
+}
+
+fn do_flash(
+    chip: &str,
+    path: &str,
+    format: Format,
+    opts: DownloadOptions<'static>,
+    speed_khz: u32,
+    proto: Option<WireProtocol>,
+) -> i32 {
+    let speed = Some(speed_khz).filter(|s| *s != 0);
+    match api::flash(chip, path, format, opts, speed, proto) {
+        Ok(()) => 0,
         Err(e) => {
-            set_error(format!("flash error: {}", e));
-            2
+            let code = e.code();
+            set_error(e.message());
+            code
         }
     }
 }
@@ -806,7 +1476,8 @@ fn sessions() -> &'static Mutex<HashMap<u64, Arc<Mutex<Session>>>> {
     SESSIONS.get_or_init(|| Mutex::new(HashMap::new()))
 }
 
-fn make_handle(session: Session) -> u64 {
+fn make_handle(mut session: Session) -> u64 {
+    timeouts::apply_to_session(&mut session);
     let handle = NEXT_HANDLE.fetch_add(1, Ordering::Relaxed);
     sessions()
         .lock()
@@ -826,10 +1497,7 @@ fn get_session(handle: u64) -> Result<Arc<Mutex<Session>>, String> {
 
 #[unsafe(no_mangle)]
 pub extern "C" fn pr_last_error(buf: *mut c_char, buf_len: usize) -> usize {
-    let s = {
-        let lock = LAST_ERROR.get_or_init(|| Mutex::new(String::new()));
-        lock.lock().unwrap().clone()
-    };
+    let s = LAST_ERROR.with(|last| last.borrow().clone());
     let bytes = s.as_bytes();
     let need = bytes.len() + 1;
     if buf.is_null() || buf_len == 0 {
@@ -845,9 +1513,27 @@ pub extern "C" fn pr_last_error(buf: *mut c_char, buf_len: usize) -> usize {
     need
 }
 
+/// Version of the C ABI. The major version changes with every incompatible change (a changed
+/// signature, meaning or removed function), the minor version with every addition.
+const ABI_VERSION_MAJOR: u32 = 1;
+const ABI_VERSION_MINOR: u32 = 38;
+
+/// Write the ABI version (see `PR_ABI_VERSION_MAJOR` in the header); either pointer may be
+/// NULL. A host should refuse a library with another major or a lower minor version than it
+/// was built against.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_abi_version(out_major: *mut u32, out_minor: *mut u32) {
+    if !out_major.is_null() {
+        unsafe { *out_major = ABI_VERSION_MAJOR };
+    }
+    if !out_minor.is_null() {
+        unsafe { *out_minor = ABI_VERSION_MINOR };
+    }
+}
+
 #[unsafe(no_mangle)]
 pub extern "C" fn pr_version(buf: *mut c_char, buf_len: usize) -> usize {
-    let s = format!("{}", env!("CARGO_PKG_VERSION"));
+    let s = env!("CARGO_PKG_VERSION").to_string();
     let bytes = s.as_bytes();
     let need = bytes.len() + 1;
     if buf.is_null() || buf_len == 0 {
@@ -877,29 +1563,108 @@ pub extern "C" fn pr_clear_progress_callback() {
     *l = None;
 }
 
+/// Bytes of the progress report being delivered, for progress callbacks that show sizes: called
+/// from within a callback, writes the bytes done and the total announced for the operation (0 if
+/// unknown) to `out_done` and `out_total` (either may be NULL). Outside a callback it gives the
+/// last report made on the calling thread.
+///
+/// Returns 0, or -1 if no report was made on the calling thread yet.
 #[unsafe(no_mangle)]
-pub extern "C" fn pr_probe_count() -> u32 {
-    let lister = Lister::new();
-    lister.list_all().len() as u32
-}
-
-#[unsafe(no_mangle)]
-pub extern "C" fn pr_probe_info(
-    index: u32,
-    identifier: *mut c_char,
+pub extern "C" fn pr_progress_bytes(out_done: *mut u64, out_total: *mut u64) -> i32 {
+    let Some((done, total)) = PROGRESS_BYTES.get() else {
+        set_error("no progress reported on this thread".to_string());
+        return -1;
+    };
+    unsafe {
+        if !out_done.is_null() {
+            *out_done = done;
+        }
+        if !out_total.is_null() {
+            *out_total = total.unwrap_or(0);
+        }
+    }
+    0
+}
+
+/// Whether the progress report being delivered is estimated rather than measured, for progress
+/// callbacks that can show an indeterminate bar: a chip erase reports nothing until it is done,
+/// so its `percent` and `eta_ms` are derived from the typical erase time of the target (0 and -1
+/// when it is not known), from a helper thread while the flash call waits. Called outside a
+/// callback, gives that of the last report made on the calling thread.
+///
+/// Returns 1 if estimated, 0 if measured, -1 if no report was made on the calling thread yet.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_progress_indeterminate() -> i32 {
+    if PROGRESS_BYTES.get().is_none() {
+        set_error("no progress reported on this thread".to_string());
+        return -1;
+    }
+    PROGRESS_INDETERMINATE.get() as i32
+}
+
+/// Report the flash progress of `session` (`pr_session_flash`, `pr_flash_bin_to_region`) to
+/// `cb(session, op, percent, status, eta_ms)` instead of the global progress callback; NULL
+/// returns to the global one. Sessions flashing on different threads each get their own reports.
+///
+/// Returns 0 on success, -1 on invalid handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_set_progress_callback(
+    session: u64,
+    cb: Option<SessionProgressCb>,
+) -> i32 {
+    if get_session(session).is_err() {
+        set_error("invalid session handle".to_string());
+        return -1;
+    }
+    let mut cbs = session_progress_cbs().lock().unwrap();
+    match cb {
+        Some(cb) => cbs.insert(session, cb),
+        None => cbs.remove(&session),
+    };
+    0
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_count() -> u32 {
+    remote::list_all().len() as u32
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_info(
+    index: u32,
+    identifier: *mut c_char,
     identifier_len: usize,
     vid: *mut u16,
     pid: *mut u16,
     serial: *mut c_char,
     serial_len: usize,
 ) -> i32 {
-    let lister = Lister::new();
-    let probes = lister.list_all();
+    let probes = remote::list_all();
     let Some(info) = probes.get(index as usize) else {
         set_error("probe index out of range".to_string());
         return -1;
     };
+    write_probe_info(
+        info,
+        identifier,
+        identifier_len,
+        vid,
+        pid,
+        serial,
+        serial_len,
+    );
+    0
+}
 
+fn write_probe_info(
+    info: &probe_rs::probe::DebugProbeInfo,
+    identifier: *mut c_char,
+    identifier_len: usize,
+    vid: *mut u16,
+    pid: *mut u16,
+    serial: *mut c_char,
+    serial_len: usize,
+) {
     unsafe {
         if !vid.is_null() {
             *vid = info.vendor_id;
@@ -932,6 +1697,74 @@ pub extern "C" fn pr_probe_info(
             slice[n] = 0;
         }
     }
+}
+
+/// Probes of a programmer type: `type_code` 0 uses the type set with
+/// `pr_set_programmer_type_code` (all probes if none is set), as attaching does.
+fn probes_of_type(type_code: i32) -> Result<Vec<probe_rs::probe::DebugProbeInfo>, String> {
+    let ty = if type_code == 0 {
+        programmer_type()
+    } else {
+        Some(code_to_type(type_code).ok_or("unsupported programmer type code")?)
+    };
+    let probes = remote::list_all();
+    Ok(match ty {
+        Some(ty) => probes
+            .into_iter()
+            .filter(|i| info_matches_type(i, ty))
+            .collect(),
+        None => probes,
+    })
+}
+
+/// Number of connected probes of a programmer type; `type_code` 0 = the configured type (see
+/// `pr_set_programmer_type_code`), so the count matches the probes sessions can attach with.
+/// Returns -1 on an unsupported type code.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_count_filtered(type_code: i32) -> i32 {
+    match probes_of_type(type_code) {
+        Ok(probes) => probes.len() as i32,
+        Err(e) => {
+            set_error(e);
+            -1
+        }
+    }
+}
+
+/// Like `pr_probe_info`, with `index` counting only probes of the programmer type, see
+/// `pr_probe_count_filtered`.
+#[unsafe(no_mangle)]
+#[allow(clippy::too_many_arguments)]
+pub extern "C" fn pr_probe_info_filtered(
+    type_code: i32,
+    index: u32,
+    identifier: *mut c_char,
+    identifier_len: usize,
+    vid: *mut u16,
+    pid: *mut u16,
+    serial: *mut c_char,
+    serial_len: usize,
+) -> i32 {
+    let probes = match probes_of_type(type_code) {
+        Ok(probes) => probes,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let Some(info) = probes.get(index as usize) else {
+        set_error("probe index out of range".to_string());
+        return -1;
+    };
+    write_probe_info(
+        info,
+        identifier,
+        identifier_len,
+        vid,
+        pid,
+        serial,
+        serial_len,
+    );
     0
 }
 
@@ -941,8 +1774,7 @@ pub extern "C" fn pr_probe_features(
     out_driver_flags: *mut u32,
     out_feature_flags: *mut u32,
 ) -> i32 {
-    let lister = Lister::new();
-    let probes = lister.list_all();
+    let probes = remote::list_all();
     let Some(info) = probes.get(index as usize) else {
         set_error("probe index out of range".to_string());
         return -1;
@@ -976,6 +1808,9 @@ pub extern "C" fn pr_probe_features(
     if info.is_probe_type::<Ch347UsbJtagFactory>() {
         driver_flags |= 0x00000100;
     }
+    if info.is_probe_type::<BlackMagicProbeFactory>() {
+        driver_flags |= 0x00000200;
+    }
 
     let mut feature_flags: u32 = 0;
     let mut probe = match info.open() {
@@ -1021,8 +1856,7 @@ pub extern "C" fn pr_probe_features(
 
 #[unsafe(no_mangle)]
 pub extern "C" fn pr_probe_check_target(index: u32) -> i32 {
-    let lister = Lister::new();
-    let probes = lister.list_all();
+    let probes = remote::list_all();
     let Some(info) = probes.get(index as usize) else {
         set_error("probe index out of range".to_string());
         return -1;
@@ -1035,6 +1869,10 @@ pub extern "C" fn pr_probe_check_target(index: u32) -> i32 {
             return -1;
         }
     };
+    if let Err(e) = driver_options::apply(&mut probe) {
+        set_error(e);
+        return -1;
+    }
 
     let mut last_err: Option<String> = None;
     for proto in [WireProtocol::Swd, WireProtocol::Jtag] {
@@ -1068,59 +1906,11 @@ pub extern "C" fn pr_session_open_auto(
         set_error("invalid chip".to_string());
         return 0;
     };
-    let proto = protocol_from_int(protocol_code);
-    if let Some(ty) = *programmer_type_lock().lock().unwrap() {
-        let lister = Lister::new();
-        let list = lister.list_all();
-        let Some(info) = list.into_iter().find(|i| info_matches_type(i, ty)) else {
-            set_error("no probe matching programmer type".to_string());
-            return 0;
-        };
-        match info.open() {
-            Ok(mut probe) => {
-                if let Some(p) = proto {
-                    if let Err(e) = probe.select_protocol(p) {
-                        set_error(format!("select protocol error: {}", e));
-                        return 0;
-                    }
-                }
-                if speed_khz > 0 {
-                    if let Err(e) = probe.set_speed(speed_khz) {
-                        set_error(format!("set speed error: {}", e));
-                        return 0;
-                    }
-                }
-                match probe.attach(chip, Default::default()) {
-                    Ok(sess) => make_handle(sess),
-                    Err(e) => {
-                        set_error(format!("attach error: {}", e));
-                        0
-                    }
-                }
-            }
-            Err(e) => {
-                set_error(format!("open probe error: {}", e));
-                0
-            }
-        }
-    } else {
-        let session_cfg = SessionConfig {
-            permissions: Default::default(),
-            speed: if speed_khz == 0 {
-                None
-            } else {
-                Some(speed_khz)
-            },
-            protocol: proto,
-        };
-        match Session::auto_attach(chip, session_cfg) {
-            Ok(sess) => make_handle(sess),
-            Err(e) => {
-                set_error(format!("attach error: {}", e));
-                0
-            }
-        }
-    }
+    let speed = Some(speed_khz).filter(|s| *s != 0);
+    api::open_session_auto(&chip, speed, protocol_from_int(protocol_code)).unwrap_or_else(|e| {
+        set_error(e);
+        0
+    })
 }
 
 #[unsafe(no_mangle)]
@@ -1138,80 +1928,86 @@ pub extern "C" fn pr_session_open_with_probe(
         set_error("invalid chip".to_string());
         return 0;
     };
-    let lister = Lister::new();
-    let selector: DebugProbeSelector = match sel.parse() {
-        Ok(s) => s,
-        Err(e) => {
-            set_error(format!("selector parse error: {}", e));
-            return 0;
+    let speed = Some(speed_khz).filter(|s| *s != 0);
+    api::open_session_with_probe(&sel, &chip, speed, protocol_from_int(protocol_code))
+        .unwrap_or_else(|e| {
+            set_error(e);
+            0
+        })
+}
+
+/// Close a session; halted cores are resumed (`pr_session_close_ex` with detach mode 0).
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_close(session: u64) -> i32 {
+    pr_session_close_ex(session, 0)
+}
+
+/// Close a session and detach from the target in a defined way:
+/// 0 = leave running (halted cores are resumed), 1 = leave halted, 2 = reset and run.
+///
+/// Background pollers, monitors, profilers, terminals and SDI print of the session are stopped and
+/// software breakpoints are removed first. Hardware breakpoints are always cleared.
+///
+/// Returns 0 on success, -1 on invalid handle or detach mode.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_close_ex(session: u64, detach_mode: i32) -> i32 {
+    let mode = match detach_mode {
+        0 => DetachMode::Run,
+        1 => DetachMode::Halt,
+        2 => DetachMode::Reset,
+        _ => {
+            set_error(format!("invalid detach mode {}", detach_mode));
+            return -1;
         }
     };
-    let v = selector.vendor_id;
-    let p = selector.product_id;
-    let sn = selector.serial_number.clone();
-    match lister.open(selector) {
-        Ok(mut probe) => {
-            if let Some(ty) = *programmer_type_lock().lock().unwrap() {
-                let probes = Lister::new().list_all();
-                let maybe_info = probes.into_iter().find(|i| {
-                    i.vendor_id == v
-                        && i.product_id == p
-                        && match (&sn, &i.serial_number) {
-                            (Some(a), Some(b)) => a == b,
-                            (Some(_), None) => false,
-                            (None, _) => true,
-                        }
-                });
-                if let Some(info) = maybe_info {
-                    if !info_matches_type(&info, ty) {
-                        set_error("programmer type mismatch".to_string());
-                        return 0;
-                    }
-                } else {
-                    set_error("probe not found".to_string());
-                    return 0;
-                }
-            }
-            if let Some(p) = protocol_from_int(protocol_code) {
-                if let Err(e) = probe.select_protocol(p) {
-                    set_error(format!("select protocol error: {}", e));
-                    return 0;
-                }
-            }
-            if speed_khz > 0 {
-                if let Err(e) = probe.set_speed(speed_khz) {
-                    set_error(format!("set speed error: {}", e));
-                    return 0;
-                }
-            }
-            match probe.attach(chip, Default::default()) {
-                Ok(sess) => make_handle(sess),
-                Err(e) => {
-                    set_error(format!("attach error: {}", e));
-                    0
-                }
-            }
-        }
+    match api::close_session(session, mode) {
+        Ok(()) => 0,
         Err(e) => {
-            set_error(format!("open probe error: {}", e));
-            0
+            set_error(e);
+            -1
         }
     }
 }
 
+/// Number of open sessions, for hosts checking that they close what they open.
 #[unsafe(no_mangle)]
-pub extern "C" fn pr_session_close(session: u64) -> i32 {
-    let mut map = sessions().lock().unwrap();
-    match map.remove(&session) {
-        Some(arc) => {
-            drop(arc);
-            0
+pub extern "C" fn pr_session_count() -> u32 {
+    sessions().lock().unwrap().len() as u32
+}
+
+/// Write the handles of up to `max` open sessions, oldest first, to `out_handles` (may be NULL
+/// when `max` is 0).
+///
+/// Returns the number of open sessions, which may be larger than `max`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_list(out_handles: *mut u64, max: u32) -> u32 {
+    let handles = api::session_handles();
+    if !out_handles.is_null() {
+        for (i, handle) in handles.iter().take(max as usize).enumerate() {
+            unsafe { *out_handles.add(i) = *handle };
         }
-        None => {
-            set_error("invalid session handle".to_string());
-            -1
+    }
+    handles.len() as u32
+}
+
+/// Close every open session as `pr_session_close_ex` with `detach_mode` does, e.g. before
+/// unloading the library.
+///
+/// Returns the number of sessions closed, or -1 on an invalid detach mode.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_close_all(detach_mode: i32) -> i32 {
+    if !(0..=2).contains(&detach_mode) {
+        set_error(format!("invalid detach mode {}", detach_mode));
+        return -1;
+    }
+    let mut closed = 0;
+    for handle in api::session_handles() {
+        // Another thread may have closed it meanwhile.
+        if pr_session_close_ex(handle, detach_mode) == 0 {
+            closed += 1;
         }
     }
+    closed
 }
 
 #[unsafe(no_mangle)]
@@ -1253,7 +2049,9 @@ pub extern "C" fn pr_core_run(session: u64, core_index: u32) -> i32 {
     };
     let mut lock = sess.lock().unwrap();
     match lock.core(core_index as usize) {
-        Ok(mut core) => match core.run() {
+        Ok(mut core) => match breakpoint::step_over_patched(session, core_index, &mut core)
+            .and_then(|_| core.run())
+        {
             Ok(_) => 0,
             Err(e) => {
                 set_error(format!("run error: {}", e));
@@ -1275,7 +2073,14 @@ pub extern "C" fn pr_core_step(session: u64, core_index: u32) -> i32 {
     };
     let mut lock = sess.lock().unwrap();
     match lock.core(core_index as usize) {
-        Ok(mut core) => match core.step() {
+        Ok(mut core) => match breakpoint::step_over_patched(session, core_index, &mut core)
+            .and_then(|stepped| {
+                if stepped {
+                    Ok(())
+                } else {
+                    core.step().map(|_| ())
+                }
+            }) {
             Ok(_) => 0,
             Err(e) => {
                 set_error(format!("step error: {}", e));
@@ -1379,20 +2184,20 @@ pub extern "C" fn pr_read_8(
     };
     let mut lock = sess.lock().unwrap();
     let mut tmp = vec![0u8; len as usize];
-    match lock.core(core_index as usize) {
-        Ok(mut core) => match core.read_8(address, &mut tmp) {
-            Ok(_) => {
-                unsafe {
-                    std::ptr::copy_nonoverlapping(tmp.as_ptr(), buf, len as usize);
-                }
-                0
-            }
-            Err(e) => {
-                set_error(format!("read_8 error: {}", e));
-                -2
+    match reconnect::with_core(session, &mut lock, core_index as usize, |core| {
+        core.read_8(address, &mut tmp)
+    }) {
+        Ok(_) => {
+            unsafe {
+                std::ptr::copy_nonoverlapping(tmp.as_ptr(), buf, len as usize);
             }
-        },
-        Err(e) => {
+            0
+        }
+        Err(CoreOpError::Op(e)) => {
+            set_error(format!("read_8 error: {}", e));
+            -2
+        }
+        Err(CoreOpError::Core(e)) => {
             set_error(format!("core access error: {}", e));
             -1
         }
@@ -1417,15 +2222,15 @@ pub extern "C" fn pr_write_8(
     };
     let mut lock = sess.lock().unwrap();
     let slice = unsafe { std::slice::from_raw_parts(buf, len as usize) };
-    match lock.core(core_index as usize) {
-        Ok(mut core) => match core.write_8(address, slice) {
-            Ok(_) => 0,
-            Err(e) => {
-                set_error(format!("write_8 error: {}", e));
-                -2
-            }
-        },
-        Err(e) => {
+    match reconnect::with_core(session, &mut lock, core_index as usize, |core| {
+        core.write_8(address, slice)
+    }) {
+        Ok(_) => 0,
+        Err(CoreOpError::Op(e)) => {
+            set_error(format!("write_8 error: {}", e));
+            -2
+        }
+        Err(CoreOpError::Core(e)) => {
             set_error(format!("core access error: {}", e));
             -1
         }
@@ -1450,20 +2255,20 @@ pub extern "C" fn pr_read_16(
     };
     let mut lock = sess.lock().unwrap();
     let mut tmp = vec![0u16; len_words as usize];
-    match lock.core(core_index as usize) {
-        Ok(mut core) => match core.read_16(address, &mut tmp) {
-            Ok(_) => {
-                unsafe {
-                    std::ptr::copy_nonoverlapping(tmp.as_ptr(), buf, len_words as usize);
-                }
-                0
+    match reconnect::with_core(session, &mut lock, core_index as usize, |core| {
+        core.read_16(address, &mut tmp)
+    }) {
+        Ok(_) => {
+            unsafe {
+                std::ptr::copy_nonoverlapping(tmp.as_ptr(), buf, len_words as usize);
             }
-            Err(e) => {
-                set_error(format!("read_16 error: {}", e));
-                -2
-            }
-        },
-        Err(e) => {
+            0
+        }
+        Err(CoreOpError::Op(e)) => {
+            set_error(format!("read_16 error: {}", e));
+            -2
+        }
+        Err(CoreOpError::Core(e)) => {
             set_error(format!("core access error: {}", e));
             -1
         }
@@ -1488,15 +2293,15 @@ pub extern "C" fn pr_write_16(
     };
     let mut lock = sess.lock().unwrap();
     let slice = unsafe { std::slice::from_raw_parts(buf, len_words as usize) };
-    match lock.core(core_index as usize) {
-        Ok(mut core) => match core.write_16(address, slice) {
-            Ok(_) => 0,
-            Err(e) => {
-                set_error(format!("write_16 error: {}", e));
-                -2
-            }
-        },
-        Err(e) => {
+    match reconnect::with_core(session, &mut lock, core_index as usize, |core| {
+        core.write_16(address, slice)
+    }) {
+        Ok(_) => 0,
+        Err(CoreOpError::Op(e)) => {
+            set_error(format!("write_16 error: {}", e));
+            -2
+        }
+        Err(CoreOpError::Core(e)) => {
             set_error(format!("core access error: {}", e));
             -1
         }
@@ -1521,20 +2326,20 @@ pub extern "C" fn pr_read_32(
     };
     let mut lock = sess.lock().unwrap();
     let mut tmp = vec![0u32; len_words as usize];
-    match lock.core(core_index as usize) {
-        Ok(mut core) => match core.read_32(address, &mut tmp) {
-            Ok(_) => {
-                unsafe {
-                    std::ptr::copy_nonoverlapping(tmp.as_ptr(), buf, len_words as usize);
-                }
-                0
-            }
-            Err(e) => {
-                set_error(format!("read_32 error: {}", e));
-                -2
+    match reconnect::with_core(session, &mut lock, core_index as usize, |core| {
+        core.read_32(address, &mut tmp)
+    }) {
+        Ok(_) => {
+            unsafe {
+                std::ptr::copy_nonoverlapping(tmp.as_ptr(), buf, len_words as usize);
             }
-        },
-        Err(e) => {
+            0
+        }
+        Err(CoreOpError::Op(e)) => {
+            set_error(format!("read_32 error: {}", e));
+            -2
+        }
+        Err(CoreOpError::Core(e)) => {
             set_error(format!("core access error: {}", e));
             -1
         }
@@ -1559,64 +2364,287 @@ pub extern "C" fn pr_write_32(
     };
     let mut lock = sess.lock().unwrap();
     let slice = unsafe { std::slice::from_raw_parts(buf, len_words as usize) };
-    match lock.core(core_index as usize) {
-        Ok(mut core) => match core.write_32(address, slice) {
-            Ok(_) => 0,
-            Err(e) => {
-                set_error(format!("write_32 error: {}", e));
-                -2
-            }
-        },
-        Err(e) => {
+    match reconnect::with_core(session, &mut lock, core_index as usize, |core| {
+        core.write_32(address, slice)
+    }) {
+        Ok(_) => 0,
+        Err(CoreOpError::Op(e)) => {
+            set_error(format!("write_32 error: {}", e));
+            -2
+        }
+        Err(CoreOpError::Core(e)) => {
             set_error(format!("core access error: {}", e));
             -1
         }
     }
 }
 
-#[unsafe(no_mangle)]
-pub extern "C" fn pr_registers_count(session: u64, core_index: u32) -> u32 {
-    let Ok(sess) = get_session(session) else {
-        set_error("invalid session handle".to_string());
-        return 0;
+/// `value` with the bits of `clear_mask` cleared and then those of `set_mask` set, truncated to
+/// `width` bytes.
+fn modify_bits(value: u64, width: u32, clear_mask: u64, set_mask: u64) -> u64 {
+    let mask = if width >= 8 {
+        u64::MAX
+    } else {
+        (1u64 << (width * 8)) - 1
     };
-    let mut lock = sess.lock().unwrap();
-    match lock.core(core_index as usize) {
-        Ok(core) => core.registers().all_registers().count() as u32,
-        Err(_) => 0,
-    }
+    ((value & !clear_mask) | set_mask) & mask
 }
 
+/// Clear `clear_mask` and then set `set_mask` in the `width`-byte (1, 2, 4 or 8) value at
+/// `address`, as one read-modify-write with the session locked, so no other user of the
+/// session (pollers, RTT, other threads) can access the target in between.
+///
+/// Returns 0 on success, -1 on invalid arguments/handle, -2 on memory access error.
 #[unsafe(no_mangle)]
-pub extern "C" fn pr_register_info(
+pub extern "C" fn pr_memory_modify_bits(
     session: u64,
     core_index: u32,
-    reg_index: u32,
-    reg_id: *mut u16,
-    bit_size: *mut u32,
-    name: *mut c_char,
-    name_len: usize,
+    address: u64,
+    width: u32,
+    clear_mask: u64,
+    set_mask: u64,
 ) -> i32 {
+    if !matches!(width, 1 | 2 | 4 | 8) {
+        set_error(format!("invalid width {}, expected 1, 2, 4 or 8", width));
+        return -1;
+    }
     let Ok(sess) = get_session(session) else {
         set_error("invalid session handle".to_string());
         return -1;
     };
     let mut lock = sess.lock().unwrap();
-    let Ok(core) = lock.core(core_index as usize) else {
-        set_error("core access error".to_string());
-        return -1;
-    };
-    let regs = core.registers();
-    let Some(desc) = regs.all_registers().nth(reg_index as usize) else {
-        set_error("reg index out of range".to_string());
-        return -1;
-    };
-    unsafe {
-        if !reg_id.is_null() {
-            *reg_id = desc.id.0;
+    let mut core = match lock.core(core_index as usize) {
+        Ok(core) => core,
+        Err(e) => {
+            set_error(format!("core access error: {}", e));
+            return -1;
         }
-        if !bit_size.is_null() {
-            *bit_size = match desc.data_type {
+    };
+    let res = (|| -> Result<(), probe_rs::Error> {
+        match width {
+            1 => {
+                let v = core.read_word_8(address)? as u64;
+                core.write_word_8(address, modify_bits(v, 1, clear_mask, set_mask) as u8)
+            }
+            2 => {
+                let v = core.read_word_16(address)? as u64;
+                core.write_word_16(address, modify_bits(v, 2, clear_mask, set_mask) as u16)
+            }
+            4 => {
+                let v = core.read_word_32(address)? as u64;
+                core.write_word_32(address, modify_bits(v, 4, clear_mask, set_mask) as u32)
+            }
+            _ => {
+                let v = core.read_word_64(address)?;
+                core.write_word_64(address, modify_bits(v, 8, clear_mask, set_mask))
+            }
+        }
+    })();
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(format!("modify_bits error: {}", e));
+            -2
+        }
+    }
+}
+
+/// Count the bytes of `actual` that differ from `expected`, remembering the first differing
+/// address given that both start at `address`.
+fn count_diffs(
+    address: u64,
+    expected: &[u8],
+    actual: &[u8],
+    first_diff: &mut Option<u64>,
+    diff_count: &mut u64,
+) {
+    for (i, (e, a)) in expected.iter().zip(actual).enumerate() {
+        if e != a {
+            first_diff.get_or_insert(address + i as u64);
+            *diff_count += 1;
+        }
+    }
+}
+
+/// Compare target memory at `address` (read through core 0) with the contents of the file at
+/// `path`, e.g. a filesystem or asset region against a golden image. The whole file is
+/// compared byte by byte.
+///
+/// `out_first_diff` receives the address of the first differing byte (only written if there is
+/// one) and `out_diff_count` the number of differing bytes; both may be NULL.
+///
+/// Returns 0 if memory matches, 1 if it differs, -1 on invalid arguments/handle or unreadable
+/// file, -2 on memory read error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_memory_compare_file(
+    session: u64,
+    address: u64,
+    path: *const c_char,
+    out_first_diff: *mut u64,
+    out_diff_count: *mut u64,
+) -> i32 {
+    const CHUNK: usize = 4096;
+    let expected = match cstr_to_string(path)
+        .and_then(|p| std::fs::read(&p).map_err(|e| format!("failed to read {}: {}", p, e)))
+    {
+        Ok(data) => data,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let mut core = match lock.core(0) {
+        Ok(core) => core,
+        Err(e) => {
+            set_error(format!("core access error: {}", e));
+            return -1;
+        }
+    };
+    let mut first_diff = None;
+    let mut diff_count = 0;
+    let mut actual = vec![0u8; CHUNK];
+    for (i, chunk) in expected.chunks(CHUNK).enumerate() {
+        let chunk_address = address + (i * CHUNK) as u64;
+        let actual = &mut actual[..chunk.len()];
+        if let Err(e) = core.read_8(chunk_address, actual) {
+            set_error(format!("read error at {:#x}: {}", chunk_address, e));
+            return -2;
+        }
+        count_diffs(
+            chunk_address,
+            chunk,
+            actual,
+            &mut first_diff,
+            &mut diff_count,
+        );
+    }
+    unsafe {
+        if !out_first_diff.is_null()
+            && let Some(first) = first_diff
+        {
+            *out_first_diff = first;
+        }
+        if !out_diff_count.is_null() {
+            *out_diff_count = diff_count;
+        }
+    }
+    if diff_count == 0 { 0 } else { 1 }
+}
+
+/// Whether memory of this core can be read without halting it.
+///
+/// ARM cores are read through the memory access port while running. The RISC-V and Xtensa
+/// drivers may transparently halt the core for memory access, so running cores of those
+/// architectures are refused.
+fn can_access_running(core: &mut probe_rs::Core<'_>) -> Result<bool, probe_rs::Error> {
+    Ok(core.architecture() == probe_rs::Architecture::Arm || core.core_halted()?)
+}
+
+/// Read memory without ever halting the core.
+///
+/// Returns 0 on success, -1 on invalid arguments/handle, -2 on read error, and -3 if the core is
+/// running and this architecture/probe cannot read memory without halting it.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_read_mem_nonstop(
+    session: u64,
+    core_index: u32,
+    address: u64,
+    buf: *mut u8,
+    len: u32,
+) -> i32 {
+    if buf.is_null() {
+        set_error("buf is null".to_string());
+        return -1;
+    }
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let mut core = match lock.core(core_index as usize) {
+        Ok(core) => core,
+        Err(e) => {
+            set_error(format!("core access error: {}", e));
+            return -1;
+        }
+    };
+    match can_access_running(&mut core) {
+        Ok(true) => {}
+        Ok(false) => {
+            set_error(format!(
+                "{:?} core is running and cannot be read without halting",
+                core.architecture()
+            ));
+            return -3;
+        }
+        Err(e) => {
+            set_error(format!("core status error: {}", e));
+            return -2;
+        }
+    }
+    let mut tmp = vec![0u8; len as usize];
+    match core.read_8(address, &mut tmp) {
+        Ok(_) => {
+            unsafe {
+                std::ptr::copy_nonoverlapping(tmp.as_ptr(), buf, len as usize);
+            }
+            0
+        }
+        Err(e) => {
+            set_error(format!("read_mem_nonstop error: {}", e));
+            -2
+        }
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_registers_count(session: u64, core_index: u32) -> u32 {
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return 0;
+    };
+    let mut lock = sess.lock().unwrap();
+    match lock.core(core_index as usize) {
+        Ok(core) => core.registers().all_registers().count() as u32,
+        Err(_) => 0,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_register_info(
+    session: u64,
+    core_index: u32,
+    reg_index: u32,
+    reg_id: *mut u16,
+    bit_size: *mut u32,
+    name: *mut c_char,
+    name_len: usize,
+) -> i32 {
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let Ok(core) = lock.core(core_index as usize) else {
+        set_error("core access error".to_string());
+        return -1;
+    };
+    let regs = core.registers();
+    let Some(desc) = regs.all_registers().nth(reg_index as usize) else {
+        set_error("reg index out of range".to_string());
+        return -1;
+    };
+    unsafe {
+        if !reg_id.is_null() {
+            *reg_id = desc.id.0;
+        }
+        if !bit_size.is_null() {
+            *bit_size = match desc.data_type {
                 probe_rs::RegisterDataType::UnsignedInteger(bits) => bits as u32,
                 probe_rs::RegisterDataType::FloatingPoint(bits) => bits as u32,
             };
@@ -1653,20 +2681,20 @@ pub extern "C" fn pr_read_reg_u64(
         return -1;
     };
     let mut lock = sess.lock().unwrap();
-    match lock.core(core_index as usize) {
-        Ok(mut core) => match core.read_core_reg::<u64>(probe_rs::RegisterId(reg_id)) {
-            Ok(v) => {
-                unsafe {
-                    *out_value = v;
-                }
-                0
-            }
-            Err(e) => {
-                set_error(format!("read reg error: {}", e));
-                -2
+    match reconnect::with_core(session, &mut lock, core_index as usize, |core| {
+        core.read_core_reg::<u64>(probe_rs::RegisterId(reg_id))
+    }) {
+        Ok(v) => {
+            unsafe {
+                *out_value = v;
             }
-        },
-        Err(e) => {
+            0
+        }
+        Err(CoreOpError::Op(e)) => {
+            set_error(format!("read reg error: {}", e));
+            -2
+        }
+        Err(CoreOpError::Core(e)) => {
             set_error(format!("core access error: {}", e));
             -1
         }
@@ -1680,15 +2708,15 @@ pub extern "C" fn pr_write_reg_u64(session: u64, core_index: u32, reg_id: u16, v
         return -1;
     };
     let mut lock = sess.lock().unwrap();
-    match lock.core(core_index as usize) {
-        Ok(mut core) => match core.write_core_reg(probe_rs::RegisterId(reg_id), value) {
-            Ok(()) => 0,
-            Err(e) => {
-                set_error(format!("write reg error: {}", e));
-                -2
-            }
-        },
-        Err(e) => {
+    match reconnect::with_core(session, &mut lock, core_index as usize, |core| {
+        core.write_core_reg(probe_rs::RegisterId(reg_id), value)
+    }) {
+        Ok(()) => 0,
+        Err(CoreOpError::Op(e)) => {
+            set_error(format!("write reg error: {}", e));
+            -2
+        }
+        Err(CoreOpError::Core(e)) => {
             set_error(format!("core access error: {}", e));
             -1
         }
@@ -1819,9 +2847,7 @@ pub extern "C" fn pr_flash_elf(
         &chip,
         &path,
         fmt,
-        verify,
-        preverify,
-        chip_erase,
+        download_options(None, verify, preverify, chip_erase),
         speed_khz,
         protocol_from_int(protocol_code),
     )
@@ -1856,9 +2882,7 @@ pub extern "C" fn pr_flash_hex(
         &chip,
         &path,
         fmt,
-        verify,
-        preverify,
-        chip_erase,
+        download_options(None, verify, preverify, chip_erase),
         speed_khz,
         protocol_from_int(protocol_code),
     )
@@ -1898,9 +2922,7 @@ pub extern "C" fn pr_flash_bin(
         &chip,
         &path,
         fmt,
-        verify,
-        preverify,
-        chip_erase,
+        download_options(None, verify, preverify, chip_erase),
         speed_khz,
         protocol_from_int(protocol_code),
     )
@@ -1917,6 +2939,58 @@ pub extern "C" fn pr_flash_auto(
     chip_erase: i32,
     speed_khz: u32,
     protocol_code: i32,
+) -> i32 {
+    let opts = download_options(None, verify, preverify, chip_erase);
+    flash_auto(
+        chip,
+        path,
+        base_address,
+        skip,
+        opts,
+        speed_khz,
+        protocol_code,
+    )
+}
+
+/// `pr_flash_auto` reporting progress to `cb(user_data, op, percent, status, eta_ms)` (NULL for
+/// none) instead of the global progress callback, so concurrent calls each get their own
+/// reports. The callback runs before the call returns, on the calling thread except for the
+/// estimated reports of a chip erase (`pr_progress_indeterminate`).
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_auto_cb(
+    chip: *const c_char,
+    path: *const c_char,
+    base_address: u64,
+    skip: u32,
+    verify: i32,
+    preverify: i32,
+    chip_erase: i32,
+    speed_khz: u32,
+    protocol_code: i32,
+    cb: Option<ProgressCbEx>,
+    user_data: *mut c_void,
+) -> i32 {
+    let mut opts = download_options(None, verify, preverify, chip_erase);
+    opts.progress = user_progress(cb, user_data);
+    flash_auto(
+        chip,
+        path,
+        base_address,
+        skip,
+        opts,
+        speed_khz,
+        protocol_code,
+    )
+}
+
+fn flash_auto(
+    chip: *const c_char,
+    path: *const c_char,
+    base_address: u64,
+    skip: u32,
+    opts: DownloadOptions<'static>,
+    speed_khz: u32,
+    protocol_code: i32,
 ) -> i32 {
     let chip = match cstr_to_string(chip) {
         Ok(s) => s,
@@ -1943,14 +3017,283 @@ pub extern "C" fn pr_flash_auto(
         &chip,
         &path,
         fmt,
-        verify,
-        preverify,
-        chip_erase,
+        opts,
         speed_khz,
         protocol_from_int(protocol_code),
     )
 }
 
+/// Flash a file through an already open session (format detected from the extension like
+/// `pr_flash_auto`; `base_address` is required for .bin).
+///
+/// Unlike the `pr_flash_*` calls this does not attach again, and together with
+/// `pr_session_set_flash_algo_cache` the flash algorithm stays loaded in RAM between calls.
+/// Returns 0 on success, 1 on invalid arguments/handle, 2 on flashing error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_flash(
+    session: u64,
+    path: *const c_char,
+    base_address: u64,
+    skip: u32,
+    verify: i32,
+    preverify: i32,
+    chip_erase: i32,
+) -> i32 {
+    let opts = download_options(Some(session), verify, preverify, chip_erase);
+    session_flash(session, path, base_address, skip, opts)
+}
+
+/// `pr_session_flash` reporting progress to `cb(user_data, ...)` like `pr_flash_auto_cb`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_flash_cb(
+    session: u64,
+    path: *const c_char,
+    base_address: u64,
+    skip: u32,
+    verify: i32,
+    preverify: i32,
+    chip_erase: i32,
+    cb: Option<ProgressCbEx>,
+    user_data: *mut c_void,
+) -> i32 {
+    let mut opts = download_options(None, verify, preverify, chip_erase);
+    opts.progress = user_progress(cb, user_data);
+    session_flash(session, path, base_address, skip, opts)
+}
+
+fn session_flash(
+    session: u64,
+    path: *const c_char,
+    base_address: u64,
+    skip: u32,
+    opts: DownloadOptions<'static>,
+) -> i32 {
+    let path = match cstr_to_string(path) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return 1;
+        }
+    };
+    let fmt = match detect_format_from_path(&path, Some(base_address).filter(|v| *v != 0), skip) {
+        Ok(f) => f,
+        Err(msg) => {
+            set_error(msg);
+            return 1;
+        }
+    };
+    match api::flash_session(session, &path, fmt, opts) {
+        Ok(()) => 0,
+        Err(e) => {
+            let code = e.code();
+            set_error(e.message());
+            code
+        }
+    }
+}
+
+/// Keep the flash algorithm loaded in target RAM across `pr_session_flash` calls of this
+/// session (enable != 0), skipping the download of the algorithm code as long as all of it is
+/// still intact in RAM. The core is reset and the algorithm's data written again as without
+/// the cache. Disabled by default.
+///
+/// Returns 0 on success, -1 on invalid handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_set_flash_algo_cache(session: u64, enable: i32) -> i32 {
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    sess.lock().unwrap().set_keep_flash_algorithm(enable != 0);
+    0
+}
+
+/// Patch `len` bytes from `data` over every image flashed afterwards, starting at `address`
+/// (e.g. a serial number, MAC address or calibration blob). Patched bytes replace the image
+/// contents there; bytes outside the image are programmed as well. A patch at the same address
+/// replaces the previous one, `len` 0 removes it. Patches stay active until
+/// `pr_flash_clear_patches`.
+///
+/// Returns 0 on success, -1 on invalid arguments.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_set_patch(address: u64, data: *const u8, len: usize) -> i32 {
+    if len > 0 && data.is_null() {
+        set_error("invalid data".to_string());
+        return -1;
+    }
+    if address.checked_add(len as u64).is_none() {
+        set_error("patch exceeds the address space".to_string());
+        return -1;
+    }
+    let mut patches = flash_patches_lock().lock().unwrap();
+    patches.retain(|(a, _)| *a != address);
+    if len > 0 {
+        let bytes = unsafe { std::slice::from_raw_parts(data, len) };
+        patches.push((address, bytes.to_vec()));
+    }
+    0
+}
+
+/// Remove all patches set with `pr_flash_set_patch`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_clear_patches() {
+    flash_patches_lock().lock().unwrap().clear();
+}
+
+/// Select what the target does after programming finished, for all `pr_flash_*`,
+/// `pr_session_flash` and `pr_gang_flash` calls: 0 = nothing (default; the core stays halted),
+/// 1 = reset and run, 2 = reset and halt, 3 = reset and start at the ELF entry point (ELF
+/// images only). Applies to core 0.
+///
+/// Returns 0 on success, -1 on an unknown mode.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_set_after(mode: i32) -> i32 {
+    let Some(after) = after_flash_from_code(mode) else {
+        set_error(format!("unknown post-flash mode {}", mode));
+        return -1;
+    };
+    *after_flash_lock().lock().unwrap() = after;
+    0
+}
+
+/// Keep `len` bytes at `start` (e.g. a bootloader or configuration sector) intact when a
+/// chip erase is requested by `pr_flash_*`, `pr_session_flash`, `pr_gang_flash` or
+/// `pr_chip_erase`: the chip erase is replaced by erasing every sector that does not overlap a
+/// preserved range. Images that write into a preserved range are rejected. Ranges stay active
+/// until `pr_flash_clear_preserve_ranges`.
+///
+/// Returns 0 on success, -1 on invalid range.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_option_preserve_range(start: u64, len: u64) -> i32 {
+    let Some(end) = start.checked_add(len).filter(|_| len > 0) else {
+        set_error("invalid preserve range".to_string());
+        return -1;
+    };
+    preserve_ranges_lock().lock().unwrap().push(start..end);
+    0
+}
+
+/// Remove all ranges set with `pr_flash_option_preserve_range`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_clear_preserve_ranges() {
+    preserve_ranges_lock().lock().unwrap().clear();
+}
+
+/// Run the target's preparation sequence for flashing (e.g. disabling watchdogs) on a core,
+/// as the flasher does before starting the flash algorithm. The core should be halted.
+///
+/// Returns 0 on success, -1 on invalid handle/core, -2 if the sequence failed.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_prepare_flashing(session: u64, core_index: u32) -> i32 {
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    if let Err(e) = lock.core(core_index as usize) {
+        set_error(format!("core access error: {}", e));
+        return -1;
+    }
+    match lock.prepare_flashing(core_index as usize) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(format!("prepare flashing error: {}", error_chain(&e)));
+            -2
+        }
+    }
+}
+
+// removed string-based programmer type setters/getters; use enum-based APIs and conversion helpers
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_set_programmer_type_code(type_code: i32) -> i32 {
+    let Some(ty) = code_to_type(type_code) else {
+        set_error("unsupported programmer type code".to_string());
+        return -1;
+    };
+    let lock = programmer_type_lock();
+    let mut l = lock.lock().unwrap();
+    *l = Some(ty);
+    0
+}
+
+/// Set the programmer type for the calling thread only, so threads opening different kinds of
+/// probes do not race on the global type; 0 returns to the global type.
+///
+/// Returns 0 on success, -1 on an unsupported type code.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_set_thread_programmer_type_code(type_code: i32) -> i32 {
+    if type_code == 0 {
+        THREAD_PROGRAMMER_TYPE.set(None);
+        return 0;
+    }
+    let Some(ty) = code_to_type(type_code) else {
+        set_error("unsupported programmer type code".to_string());
+        return -1;
+    };
+    THREAD_PROGRAMMER_TYPE.set(Some(ty));
+    0
+}
+
+/// The programmer type in effect on the calling thread, -1 if none is set.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_get_programmer_type_code() -> i32 {
+    match programmer_type() {
+        Some(t) => type_to_code(t),
+        None => -1,
+    }
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_programmer_type_is_supported_code(type_code: i32) -> i32 {
+    code_to_type(type_code).map(|_| 1).unwrap_or(0)
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_programmer_type_to_string(
+    type_code: i32,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> usize {
+    let s = match code_to_type(type_code) {
+        Some(t) => type_to_str(t),
+        None => "",
+    };
+    let bytes = s.as_bytes();
+    let need = bytes.len() + 1;
+    if buf.is_null() || buf_len == 0 {
+        return need;
+    }
+    let copy = need.min(buf_len);
+    unsafe {
+        let slice = std::slice::from_raw_parts_mut(buf as *mut u8, copy);
+        let n = copy.saturating_sub(1);
+        slice[..n].copy_from_slice(&bytes[..n]);
+        slice[n] = 0;
+    }
+    need
+}
+
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_programmer_type_from_string(
+    type_name: *const c_char,
+    out_code: *mut i32,
+) -> i32 {
+    if out_code.is_null() {
+        return -1;
+    }
+    let Ok(name) = cstr_to_string(type_name) else {
+        return -1;
+    };
+    match parse_programmer_type(&name) {
+        Some(t) => {
+            unsafe { *out_code = type_to_code(t) };
+            0
+        }
+        None => -1,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1965,6 +3308,255 @@ mod tests {
         assert_eq!(wrote, need);
     }
 
+    #[test]
+    fn chip_db_builds_in_background() {
+        pr_chip_db_prefetch();
+        let deadline = std::time::Instant::now() + std::time::Duration::from_secs(60);
+        while pr_chip_db_ready() == 0 {
+            assert!(std::time::Instant::now() < deadline);
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(pr_chip_manufacturer_count() > 0);
+    }
+
+    #[test]
+    fn jep106_codes_resolve_to_manufacturers() {
+        let mut name = [0u8; 64];
+        let need = pr_jep106_name(0x2, 0x44 | 0x80, name.as_mut_ptr() as *mut c_char, 64);
+        assert_eq!(&name[..need - 1], b"Nordic VLSI ASA");
+        assert_eq!(pr_jep106_name(0x7f, 0x7f, std::ptr::null_mut(), 0), 0);
+
+        let db = chip_db();
+        let nordic = db
+            .manufacturers
+            .iter()
+            .position(|m| m.name.starts_with("Nordic"))
+            .unwrap() as u32;
+        let (mut cc, mut id) = (0u8, 0u8);
+        assert_eq!(pr_chip_manufacturer_jep106(nordic, &mut cc, &mut id), 0);
+        assert_eq!((cc, id), (0x2, 0x44));
+        let count = pr_chip_manufacturer_count();
+        assert_eq!(pr_chip_manufacturer_jep106(count, &mut cc, &mut id), -1);
+    }
+
+    #[test]
+    fn chip_families_cover_each_manufacturer() {
+        let db = chip_db();
+        let st = db
+            .manufacturers
+            .iter()
+            .position(|m| m.name.starts_with("STMicro"))
+            .unwrap() as u32;
+        let families = pr_chip_family_count(st);
+        assert!(families > 1);
+        let in_families: u32 = (0..families)
+            .map(|f| pr_chip_model_count_in_family(st, f))
+            .sum();
+        assert_eq!(in_families, pr_chip_model_count(st));
+
+        let mut name = [0u8; 64];
+        let need = pr_chip_family_name(st, 0, name.as_mut_ptr() as *mut c_char, name.len());
+        let family = std::str::from_utf8(&name[..need - 1]).unwrap().to_string();
+        let need =
+            pr_chip_model_name_in_family(st, 0, 0, name.as_mut_ptr() as *mut c_char, name.len());
+        let chip = std::str::from_utf8(&name[..need - 1]).unwrap();
+        assert_eq!(db.family_of[chip], family);
+        assert_eq!(
+            pr_chip_family_name(st, families, std::ptr::null_mut(), 0),
+            0
+        );
+    }
+
+    #[test]
+    fn flash_patch_replace_and_remove() {
+        let serial = [0x12u8, 0x34];
+        assert_eq!(pr_flash_set_patch(0x0800_fff0, serial.as_ptr(), 2), 0);
+        assert_eq!(pr_flash_set_patch(0x0800_fff0, serial.as_ptr(), 1), 0);
+        assert_eq!(
+            *flash_patches_lock().lock().unwrap(),
+            vec![(0x0800_fff0, vec![0x12])]
+        );
+        assert_eq!(pr_flash_set_patch(0x0800_fff0, std::ptr::null(), 0), 0);
+        assert!(flash_patches_lock().lock().unwrap().is_empty());
+        assert_eq!(pr_flash_set_patch(0, std::ptr::null(), 4), -1);
+        pr_flash_clear_patches();
+    }
+
+    #[test]
+    fn after_flash_codes() {
+        assert_eq!(after_flash_from_code(0), Some(AfterFlash::None));
+        assert_eq!(after_flash_from_code(3), Some(AfterFlash::Run));
+        assert_eq!(pr_flash_set_after(4), -1);
+    }
+
+    #[test]
+    fn chip_erase_reports_are_indeterminate() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let seen = reports.clone();
+        let mut progress = progress_handler(move |op, pct, status, _| {
+            let status = unsafe { CStr::from_ptr(status) }
+                .to_str()
+                .unwrap()
+                .to_string();
+            let indeterminate = pr_progress_indeterminate();
+            seen.lock().unwrap().push((op, pct, status, indeterminate));
+        });
+        progress.emit(ProgressEvent::AddProgressBar {
+            operation: ProgressOperation::Erase,
+            total: None,
+        });
+        progress.emit(ProgressEvent::Started(ProgressOperation::Erase));
+        progress.emit(ProgressEvent::Finished(ProgressOperation::Erase));
+        progress.emit(ProgressEvent::AddProgressBar {
+            operation: ProgressOperation::Program,
+            total: Some(1024),
+        });
+        progress.emit(ProgressEvent::Started(ProgressOperation::Program));
+
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports[0], (1, 0.0, "erasing chip".to_string(), 1));
+        assert_eq!(reports[1], (1, 100.0, "erasing".to_string(), 0));
+        assert_eq!(reports[2], (2, 0.0, "programming".to_string(), 0));
+    }
+
+    #[test]
+    fn filtered_probe_listing_rejects_unknown_types() {
+        assert_eq!(pr_probe_count_filtered(42), -1);
+        assert!(pr_probe_count_filtered(1) >= 0);
+        assert_eq!(
+            pr_probe_info_filtered(
+                1,
+                u32::MAX,
+                std::ptr::null_mut(),
+                0,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                0
+            ),
+            -1
+        );
+    }
+
+    #[test]
+    fn last_error_is_per_thread() {
+        set_error("main thread error".to_string());
+        std::thread::spawn(|| {
+            set_error("worker error".to_string());
+            assert_eq!(LAST_ERROR.with(|e| e.borrow().clone()), "worker error");
+        })
+        .join()
+        .unwrap();
+        assert_eq!(LAST_ERROR.with(|e| e.borrow().clone()), "main thread error");
+    }
+
+    #[test]
+    fn thread_programmer_type_overrides_global() {
+        std::thread::spawn(|| {
+            assert_eq!(pr_set_thread_programmer_type_code(42), -1);
+            assert_eq!(pr_set_thread_programmer_type_code(3), 0);
+            assert_eq!(pr_get_programmer_type_code(), 3);
+            assert_eq!(pr_set_thread_programmer_type_code(0), 0);
+        })
+        .join()
+        .unwrap();
+        assert_eq!(pr_session_set_progress_callback(0xdead, None), -1);
+    }
+
+    #[test]
+    fn flash_cb_variants_reject_invalid_arguments() {
+        let user_data = std::ptr::null_mut();
+        assert_eq!(
+            pr_flash_auto_cb(
+                std::ptr::null(),
+                c"firmware.hex".as_ptr(),
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                0,
+                None,
+                user_data
+            ),
+            1
+        );
+        assert_eq!(
+            pr_session_flash_cb(
+                0xdead,
+                c"firmware.hex".as_ptr(),
+                0,
+                0,
+                0,
+                0,
+                0,
+                None,
+                user_data
+            ),
+            1
+        );
+    }
+
+    #[test]
+    fn session_list_reports_count() {
+        let count = pr_session_list(std::ptr::null_mut(), 0);
+        let mut handles = vec![0u64; count as usize];
+        assert!(pr_session_list(handles.as_mut_ptr(), count) >= count);
+        assert!(handles.iter().all(|h| *h != 0));
+    }
+
+    #[test]
+    fn session_close_rejects_invalid_arguments() {
+        assert_eq!(pr_session_close_ex(0xdead, 0), -1);
+        assert_eq!(pr_session_close_ex(0xdead, 3), -1);
+        assert_eq!(pr_session_close(0xdead), -1);
+        assert_eq!(pr_session_close_all(3), -1);
+    }
+
+    #[test]
+    fn unpreserved_ranges_skip_preserved() {
+        let preserved = [0x0800_0000..0x0800_4000, 0x0807_c000..0x0808_0000];
+        assert_eq!(
+            unpreserved_ranges(0x0800_0000..0x0808_0000, &preserved),
+            vec![0x0800_4000..0x0807_c000]
+        );
+        assert_eq!(
+            unpreserved_ranges(0x1000..0x2000, std::slice::from_ref(&(0x1800..0x1900))),
+            vec![0x1000..0x1800, 0x1900..0x2000]
+        );
+        assert!(unpreserved_ranges(0x1000..0x2000, &[0..0x1800, 0x1700..0x3000]).is_empty());
+        assert_eq!(pr_flash_option_preserve_range(0x1000, 0), -1);
+    }
+
+    #[test]
+    fn modify_bits_clears_then_sets_within_width() {
+        assert_eq!(modify_bits(0xF0, 1, 0x30, 0x01), 0xC1);
+        assert_eq!(modify_bits(0x1234, 2, 0, 0xF_0000), 0x1234);
+        assert_eq!(modify_bits(u64::MAX, 8, 0xFF, 0), u64::MAX << 8);
+        assert_eq!(pr_memory_modify_bits(0xdead, 0, 0x4000_0000, 3, 0, 1), -1);
+    }
+
+    #[test]
+    fn diffs_are_counted_across_chunks() {
+        let mut first = None;
+        let mut count = 0;
+        count_diffs(0x100, &[1, 2, 3], &[1, 2, 3], &mut first, &mut count);
+        assert_eq!((first, count), (None, 0));
+        count_diffs(0x200, &[1, 2, 3, 4], &[1, 0, 3, 0], &mut first, &mut count);
+        count_diffs(0x300, &[5], &[6], &mut first, &mut count);
+        assert_eq!((first, count), (Some(0x201), 3));
+    }
+
+    #[test]
+    fn error_chain_includes_sources() {
+        let inner = probe_rs::Error::Other("watchdog register locked".to_string());
+        let e = flashing::FlashError::PrepareFlashing(inner);
+        let msg = error_chain(&e);
+        assert!(msg.starts_with("Failed to prepare the target"));
+        assert!(msg.ends_with("watchdog register locked"));
+    }
+
     #[test]
     fn invalid_chip_sets_error() {
         let chip = CString::new("not_a_real_chip").unwrap();
@@ -1992,8 +3584,8 @@ mod tests {
             detect_format_kind("image.ihex"),
             Some(FormatKind::Hex)
         ));
-        assert!(matches!(detect_format_kind("blob.bin"), None));
-        assert!(matches!(detect_format_kind("unknown.xyz"), None));
+        assert!(detect_format_kind("blob.bin").is_none());
+        assert!(detect_format_kind("unknown.xyz").is_none());
     }
 
     #[test]
@@ -2028,6 +3620,9 @@ mod tests {
         assert_eq!(wrote, need);
         let s = String::from_utf8_lossy(&buf);
         assert!(s.contains("\"chip\":"));
+        let spec: serde_json::Value =
+            serde_json::from_slice(&buf[..need - 1]).expect("spec is JSON");
+        assert_eq!(spec["core_details"][0]["name"], "main");
     }
 
     #[test]
@@ -2044,83 +3639,10 @@ mod tests {
                 let wrote = pr_chip_model_name(mi, 0, buf.as_mut_ptr() as *mut i8, buf.len());
                 assert_eq!(wrote, need);
                 let cname = String::from_utf8_lossy(&buf);
-                assert!(cname.trim_end_matches('\0').len() > 0);
+                assert!(!cname.trim_end_matches('\0').is_empty());
                 return;
             }
         }
         panic!("no manufacturer with models found");
     }
 }
-// removed string-based programmer type setters/getters; use enum-based APIs and conversion helpers
-
-#[unsafe(no_mangle)]
-pub extern "C" fn pr_set_programmer_type_code(type_code: i32) -> i32 {
-    let Some(ty) = code_to_type(type_code) else {
-        set_error("unsupported programmer type code".to_string());
-        return -1;
-    };
-    let lock = programmer_type_lock();
-    let mut l = lock.lock().unwrap();
-    *l = Some(ty);
-    0
-}
-
-#[unsafe(no_mangle)]
-pub extern "C" fn pr_get_programmer_type_code() -> i32 {
-    let lock = programmer_type_lock();
-    let l = lock.lock().unwrap();
-    match *l {
-        Some(t) => type_to_code(t),
-        None => -1,
-    }
-}
-
-#[unsafe(no_mangle)]
-pub extern "C" fn pr_programmer_type_is_supported_code(type_code: i32) -> i32 {
-    code_to_type(type_code).map(|_| 1).unwrap_or(0)
-}
-
-#[unsafe(no_mangle)]
-pub extern "C" fn pr_programmer_type_to_string(
-    type_code: i32,
-    buf: *mut c_char,
-    buf_len: usize,
-) -> usize {
-    let s = match code_to_type(type_code) {
-        Some(t) => type_to_str(t),
-        None => "",
-    };
-    let bytes = s.as_bytes();
-    let need = bytes.len() + 1;
-    if buf.is_null() || buf_len == 0 {
-        return need;
-    }
-    let copy = need.min(buf_len);
-    unsafe {
-        let slice = std::slice::from_raw_parts_mut(buf as *mut u8, copy);
-        let n = copy.saturating_sub(1);
-        slice[..n].copy_from_slice(&bytes[..n]);
-        slice[n] = 0;
-    }
-    need
-}
-
-#[unsafe(no_mangle)]
-pub extern "C" fn pr_programmer_type_from_string(
-    type_name: *const c_char,
-    out_code: *mut i32,
-) -> i32 {
-    if out_code.is_null() {
-        return -1;
-    }
-    let Ok(name) = cstr_to_string(type_name) else {
-        return -1;
-    };
-    match parse_programmer_type(&name) {
-        Some(t) => {
-            unsafe { *out_code = type_to_code(t) };
-            0
-        }
-        None => -1,
-    }
-}
@@ -0,0 +1,329 @@
+//! Forwarding of probe-rs log records (attach negotiation, probe transfers, flash algorithm
+//! output) to the host application, which otherwise never sees them inside the library, and
+//! to a size rotated log file for support bundles.
+
+use crate::{cstr_to_string, set_error};
+use std::ffi::{CString, c_char};
+use std::fmt::Write as _;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write as _};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::sync::{Mutex, OnceLock};
+use time::OffsetDateTime;
+use time::format_description::well_known::Rfc3339;
+use tracing::field::{Field, Visit};
+use tracing::subscriber::Interest;
+use tracing::{Event, Level, Metadata, Subscriber};
+use tracing_subscriber::layer::{Context, Layer, SubscriberExt};
+
+type LogCb = unsafe extern "C" fn(i32, *const c_char, *const c_char);
+
+static LOG_CB: OnceLock<Mutex<Option<LogCb>>> = OnceLock::new();
+static LOG_FILE: OnceLock<Mutex<Option<LogFile>>> = OnceLock::new();
+/// Most verbose level passed to the callback and written to the file, 0 = nothing.
+static CALLBACK_LEVEL: AtomicI32 = AtomicI32::new(0);
+static FILE_LEVEL: AtomicI32 = AtomicI32::new(0);
+/// Whether our subscriber became the global default.
+static INSTALLED: OnceLock<bool> = OnceLock::new();
+
+fn log_cb_lock() -> &'static Mutex<Option<LogCb>> {
+    LOG_CB.get_or_init(|| Mutex::new(None))
+}
+
+fn log_file_lock() -> &'static Mutex<Option<LogFile>> {
+    LOG_FILE.get_or_init(|| Mutex::new(None))
+}
+
+/// Size from which the log file is rotated.
+const MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+/// Rotated files kept besides the current one, `<path>.1` being the newest.
+const KEPT_FILES: u32 = 3;
+
+struct LogFile {
+    path: PathBuf,
+    max_size: u64,
+    /// Only `None` while rotating.
+    file: Option<File>,
+    size: u64,
+}
+
+fn rotated_path(path: &Path, n: u32) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", n));
+    PathBuf::from(name)
+}
+
+impl LogFile {
+    fn open(path: PathBuf, max_size: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path,
+            max_size,
+            file: Some(file),
+            size,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        if self.size > 0 && self.size + line.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let Some(file) = self.file.as_mut() else {
+            return Ok(());
+        };
+        file.write_all(line.as_bytes())?;
+        self.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        // Closed first, Windows cannot rename open files.
+        self.file = None;
+        for n in (1..KEPT_FILES).rev() {
+            let _ = fs::rename(rotated_path(&self.path, n), rotated_path(&self.path, n + 1));
+        }
+        let renamed = fs::rename(&self.path, rotated_path(&self.path, 1));
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.size = file.metadata()?.len();
+        self.file = Some(file);
+        renamed
+    }
+}
+
+fn level_code(level: &Level) -> i32 {
+    match *level {
+        Level::ERROR => 1,
+        Level::WARN => 2,
+        Level::INFO => 3,
+        Level::DEBUG => 4,
+        Level::TRACE => 5,
+    }
+}
+
+/// The event's message followed by its other fields as `name=value`.
+#[derive(Default)]
+struct MessageVisitor(String);
+
+impl MessageVisitor {
+    fn push(&mut self, field: &Field, value: std::fmt::Arguments) {
+        if field.name() == "message" {
+            let fields = std::mem::take(&mut self.0);
+            let _ = write!(self.0, "{}{}", value, fields);
+        } else {
+            let _ = write!(self.0, " {}={}", field.name(), value);
+        }
+    }
+}
+
+impl Visit for MessageVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.push(field, format_args!("{}", value));
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        self.push(field, format_args!("{:?}", value));
+    }
+}
+
+struct ForwardLayer;
+
+impl<S: Subscriber> Layer<S> for ForwardLayer {
+    fn register_callsite(&self, _metadata: &'static Metadata<'static>) -> Interest {
+        // The level can change at any time, so ask `enabled` for every record.
+        Interest::sometimes()
+    }
+
+    fn enabled(&self, metadata: &Metadata<'_>, _ctx: Context<'_, S>) -> bool {
+        let level = level_code(metadata.level());
+        level <= CALLBACK_LEVEL.load(Ordering::Relaxed)
+            || level <= FILE_LEVEL.load(Ordering::Relaxed)
+    }
+
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        let metadata = event.metadata();
+        let level = level_code(metadata.level());
+        let mut message = MessageVisitor::default();
+        event.record(&mut message);
+
+        if level <= FILE_LEVEL.load(Ordering::Relaxed)
+            && let Some(file) = log_file_lock().lock().unwrap().as_mut()
+        {
+            let timestamp = OffsetDateTime::now_utc()
+                .format(&Rfc3339)
+                .unwrap_or_default();
+            let line = format!(
+                "{} {:>5} {}: {}\n",
+                timestamp,
+                metadata.level(),
+                metadata.target(),
+                message.0
+            );
+            // Nowhere to report a failing log file to.
+            let _ = file.write_line(&line);
+        }
+
+        if level > CALLBACK_LEVEL.load(Ordering::Relaxed) {
+            return;
+        }
+        // Copied out so a callback logging through the library does not deadlock.
+        let Some(cb) = *log_cb_lock().lock().unwrap() else {
+            return;
+        };
+        let target = CString::new(metadata.target()).unwrap_or_default();
+        let message = CString::new(message.0.replace('\0', "")).unwrap_or_default();
+        unsafe { cb(level, target.as_ptr(), message.as_ptr()) };
+    }
+}
+
+fn install() -> bool {
+    *INSTALLED.get_or_init(|| {
+        tracing::subscriber::set_global_default(tracing_subscriber::registry().with(ForwardLayer))
+            .is_ok()
+    })
+}
+
+/// Forward log records up to `level` (0 = off, 1 = error, 2 = warn, 3 = info, 4 = debug,
+/// 5 = trace) to `cb(level, target, message)`; a NULL `cb` stops forwarding. `target` is the
+/// emitting module (`probe_rs::flashing::...`), `message` the text with its fields appended as
+/// `name=value`. Both strings are only valid during the call.
+///
+/// The callback runs on the thread that logs, which may be a background thread of the library,
+/// and must not block. Debug and trace are verbose and slow down probe communication.
+///
+/// Returns 0 on success, -1 on an invalid level, -2 if the process already installed another
+/// `tracing` subscriber.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_set_log_callback(level: i32, cb: Option<LogCb>) -> i32 {
+    if !(0..=5).contains(&level) {
+        set_error(format!("invalid log level {}", level));
+        return -1;
+    }
+    if !install() {
+        set_error("another tracing subscriber is already installed".to_string());
+        return -2;
+    }
+    *log_cb_lock().lock().unwrap() = cb;
+    CALLBACK_LEVEL.store(if cb.is_some() { level } else { 0 }, Ordering::Relaxed);
+    tracing::callsite::rebuild_interest_cache();
+    0
+}
+
+/// Write log records up to `level` (as for `pr_set_log_callback`) to the file at `path`, one
+/// line per record with UTC timestamp, level and target; level 0 or a NULL `path` stops file
+/// logging. The file is appended to, and rotated at 10 MiB to `<path>.1`, keeping
+/// `<path>.1` to `<path>.3`, so a support bundle is the file and its rotations.
+///
+/// Independent of the log callback; both may be active with different levels.
+///
+/// Returns 0 on success, -1 on an invalid level or path, -2 if the file cannot be opened, -3 if
+/// the process already installed another `tracing` subscriber.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_enable_file_logging(path: *const c_char, level: i32) -> i32 {
+    if !(0..=5).contains(&level) {
+        set_error(format!("invalid log level {}", level));
+        return -1;
+    }
+    if path.is_null() || level == 0 {
+        FILE_LEVEL.store(0, Ordering::Relaxed);
+        *log_file_lock().lock().unwrap() = None;
+        tracing::callsite::rebuild_interest_cache();
+        return 0;
+    }
+    let path = match cstr_to_string(path) {
+        Ok(p) if !p.is_empty() => PathBuf::from(p),
+        Ok(_) => {
+            set_error("empty log file path".to_string());
+            return -1;
+        }
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    if !install() {
+        set_error("another tracing subscriber is already installed".to_string());
+        return -3;
+    }
+    let file = match LogFile::open(path, MAX_FILE_SIZE) {
+        Ok(f) => f,
+        Err(e) => {
+            set_error(format!("open log file error: {}", e));
+            return -2;
+        }
+    };
+    *log_file_lock().lock().unwrap() = Some(file);
+    FILE_LEVEL.store(level, Ordering::Relaxed);
+    tracing::callsite::rebuild_interest_cache();
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    static RECEIVED: Mutex<Vec<(i32, String, String)>> = Mutex::new(Vec::new());
+
+    unsafe extern "C" fn collect(level: i32, target: *const c_char, message: *const c_char) {
+        let target = unsafe { CStr::from_ptr(target) }
+            .to_string_lossy()
+            .into_owned();
+        let message = unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned();
+        RECEIVED.lock().unwrap().push((level, target, message));
+    }
+
+    #[test]
+    fn invalid_level_is_rejected() {
+        assert_eq!(pr_set_log_callback(6, None), -1);
+        assert_eq!(pr_set_log_callback(-1, None), -1);
+        assert_eq!(pr_enable_file_logging(c"probe-rs.log".as_ptr(), 6), -1);
+        assert_eq!(pr_enable_file_logging(c"".as_ptr(), 3), -1);
+    }
+
+    #[test]
+    fn log_file_is_rotated() {
+        let dir = std::env::temp_dir().join(format!("probe-rs-lib-log-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("probe-rs.log");
+        let mut file = LogFile::open(path.clone(), 16).unwrap();
+        for line in ["first line\n", "second line\n", "third line\n"] {
+            file.write_line(line).unwrap();
+        }
+        drop(file);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third line\n");
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 1)).unwrap(),
+            "second line\n"
+        );
+        assert_eq!(
+            fs::read_to_string(rotated_path(&path, 2)).unwrap(),
+            "first line\n"
+        );
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn records_are_forwarded_up_to_level() {
+        assert_eq!(pr_set_log_callback(2, Some(collect)), 0);
+        tracing::warn!(address = 0x2000_0000, "flash algorithm timed out");
+        tracing::info!("not forwarded");
+        assert_eq!(pr_set_log_callback(0, None), 0);
+        tracing::warn!("after removal");
+
+        let received = RECEIVED.lock().unwrap();
+        assert!(received.contains(&(
+            2,
+            "probe_rs_lib::logging::tests".to_string(),
+            "flash algorithm timed out address=536870912".to_string()
+        )));
+        assert!(!received.iter().any(|(_, _, m)| m == "not forwarded"));
+        assert!(!received.iter().any(|(_, _, m)| m == "after removal"));
+    }
+}
@@ -0,0 +1,305 @@
+//! Machine readable description of the C API (functions, callback types, enums and constants),
+//! taken from the shipped header, so binding generators for Python, C# or Java need not
+//! transcribe the header by hand.
+
+use crate::{set_error, write_c_str};
+use serde::Serialize;
+use std::ffi::c_char;
+use std::sync::OnceLock;
+
+const HEADER: &str = include_str!("../include/probe_rs_lib.h");
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Param {
+    name: String,
+    #[serde(rename = "type")]
+    ty: String,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Function {
+    name: String,
+    #[serde(rename = "return")]
+    ret: String,
+    params: Vec<Param>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct EnumValue {
+    name: String,
+    value: i64,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Enum {
+    name: String,
+    values: Vec<EnumValue>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Constant {
+    name: String,
+    value: u64,
+}
+
+#[derive(Serialize, Debug, Default)]
+struct Manifest {
+    version: &'static str,
+    functions: Vec<Function>,
+    callbacks: Vec<Function>,
+    enums: Vec<Enum>,
+    constants: Vec<Constant>,
+}
+
+/// `text` without `/* */` and `//` comments.
+fn strip_comments(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find("/*") {
+        out.push_str(&rest[..start]);
+        rest = rest[start + 2..]
+            .find("*/")
+            .map_or("", |end| &rest[start + 2 + end + 2..]);
+    }
+    out.push_str(rest);
+    out.lines()
+        .map(|line| line.find("//").map_or(line, |i| &line[..i]))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn parse_int(value: &str) -> Option<u64> {
+    let value = value.trim().trim_end_matches(['u', 'U', 'l', 'L']);
+    match value
+        .strip_prefix("0x")
+        .or_else(|| value.strip_prefix("0X"))
+    {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
+
+fn squash(s: &str) -> String {
+    s.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// Split `const char* name` or `uint64_t args[4]` into name and type.
+fn parse_param(param: &str) -> Param {
+    let param = squash(param);
+    let (decl, array) = match param.find('[') {
+        Some(i) => (param[..i].trim_end(), &param[i..]),
+        None => (param.as_str(), ""),
+    };
+    let split = decl
+        .rfind(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .map_or(0, |i| i + 1);
+    Param {
+        name: decl[split..].to_string(),
+        ty: format!("{}{}", decl[..split].trim_end(), array),
+    }
+}
+
+fn parse_params(params: &str) -> Vec<Param> {
+    let params = params.trim();
+    if params.is_empty() || params == "void" {
+        return Vec::new();
+    }
+    params.split(',').map(parse_param).collect()
+}
+
+/// `ret name(params)`, or `ret (*name)(params)` for callback typedefs.
+fn parse_function(decl: &str) -> Option<Function> {
+    let open = decl.find('(')?;
+    let (head, tail) = (&decl[..open], &decl[open..]);
+    if let Some(ret) = head.strip_prefix("typedef") {
+        let name_end = tail.find(')')?;
+        let name = tail[1..name_end].trim().trim_start_matches('*').trim();
+        let params = tail[name_end + 1..].trim();
+        return Some(Function {
+            name: name.to_string(),
+            ret: squash(ret),
+            params: parse_params(params.strip_prefix('(')?.strip_suffix(')')?),
+        });
+    }
+    let head = squash(head);
+    let split = head.rfind(' ')?;
+    Some(Function {
+        name: head[split + 1..].to_string(),
+        ret: head[..split].to_string(),
+        params: parse_params(tail.strip_prefix('(')?.strip_suffix(')')?),
+    })
+}
+
+/// `typedef enum { A = 0, B = 1, } name`
+fn parse_enum(decl: &str) -> Option<Enum> {
+    let body = &decl[decl.find('{')? + 1..decl.rfind('}')?];
+    let mut next = 0;
+    let values = body
+        .split(',')
+        .map(str::trim)
+        .filter(|v| !v.is_empty())
+        .map(|v| {
+            let (name, value) = match v.split_once('=') {
+                Some((name, value)) => (name.trim(), value.trim().parse().unwrap_or(next)),
+                None => (v, next),
+            };
+            next = value + 1;
+            EnumValue {
+                name: name.to_string(),
+                value,
+            }
+        })
+        .collect();
+    Some(Enum {
+        name: decl[decl.rfind('}')? + 1..].trim().to_string(),
+        values,
+    })
+}
+
+fn parse_header(header: &str) -> Manifest {
+    let mut manifest = Manifest {
+        version: env!("CARGO_PKG_VERSION"),
+        ..Default::default()
+    };
+    let mut code = String::new();
+    for line in strip_comments(header).lines() {
+        let line = line.trim();
+        if let Some(define) = line.strip_prefix("#define") {
+            let mut parts = define.split_whitespace();
+            if let (Some(name), Some(value)) = (parts.next(), parts.next())
+                && let Some(value) = parse_int(value)
+            {
+                manifest.constants.push(Constant {
+                    name: name.to_string(),
+                    value,
+                });
+            }
+        } else if !line.starts_with('#') && line != "extern \"C\" {" && line != "}" {
+            code.push_str(line);
+            code.push('\n');
+        }
+    }
+    for decl in code.split(';').map(squash).filter(|d| !d.is_empty()) {
+        if decl.starts_with("typedef enum") {
+            manifest.enums.extend(parse_enum(&decl));
+        } else if decl.starts_with("typedef") {
+            manifest.callbacks.extend(parse_function(&decl));
+        } else {
+            manifest.functions.extend(parse_function(&decl));
+        }
+    }
+    manifest
+}
+
+fn manifest_json() -> &'static str {
+    static JSON: OnceLock<String> = OnceLock::new();
+    JSON.get_or_init(|| serde_json::to_string(&parse_header(HEADER)).unwrap_or_default())
+}
+
+/// Describe the C API as JSON: `{"version", "functions": [{"name", "return", "params":
+/// [{"name", "type"}]}], "callbacks": [...], "enums": [{"name", "values": [{"name",
+/// "value"}]}], "constants": [{"name", "value"}]}`, types spelled as in `probe_rs_lib.h`.
+/// `callbacks` are the function pointer typedefs, in the same form as `functions`.
+///
+/// Returns the required size including NUL, or 0 on error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_get_api_manifest_json(out_json: *mut c_char, out_json_len: usize) -> usize {
+    let json = manifest_json();
+    if json.is_empty() {
+        set_error("failed to build the API manifest".to_string());
+        return 0;
+    }
+    write_c_str(json, out_json, out_json_len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    #[test]
+    fn declarations_are_parsed() {
+        let manifest = parse_header(HEADER);
+        let flash = manifest
+            .functions
+            .iter()
+            .find(|f| f.name == "pr_session_flash")
+            .unwrap();
+        assert_eq!(flash.ret, "int32_t");
+        assert_eq!(flash.params[1].name, "path");
+        assert_eq!(flash.params[1].ty, "const char*");
+
+        let call = manifest
+            .functions
+            .iter()
+            .find(|f| f.name == "pr_call_function")
+            .unwrap();
+        assert_eq!(call.params[3].ty, "const uint64_t[4]");
+
+        let log_cb = manifest
+            .callbacks
+            .iter()
+            .find(|c| c.name == "pr_log_cb")
+            .unwrap();
+        assert_eq!(log_cb.params.len(), 3);
+
+        let types = manifest
+            .enums
+            .iter()
+            .find(|e| e.name == "pr_programmer_type_t")
+            .unwrap();
+        assert!(types.values.contains(&EnumValue {
+            name: "PR_PROG_STLINK".to_string(),
+            value: 2
+        }));
+        assert!(
+            manifest
+                .constants
+                .iter()
+                .any(|c| c.name == "PR_FEATURE_SWO")
+        );
+    }
+
+    #[test]
+    fn header_abi_version_matches() {
+        let constants = parse_header(HEADER).constants;
+        let value = |name: &str| constants.iter().find(|c| c.name == name).unwrap().value;
+        assert_eq!(
+            value("PR_ABI_VERSION_MAJOR"),
+            crate::ABI_VERSION_MAJOR as u64
+        );
+        assert_eq!(
+            value("PR_ABI_VERSION_MINOR"),
+            crate::ABI_VERSION_MINOR as u64
+        );
+    }
+
+    /// The header, and with it the manifest, must declare exactly the exported functions.
+    #[test]
+    fn header_matches_exports() {
+        let src = concat!(env!("CARGO_MANIFEST_DIR"), "/src");
+        let mut exported = BTreeSet::new();
+        for entry in std::fs::read_dir(src).unwrap() {
+            let text = std::fs::read_to_string(entry.unwrap().path()).unwrap();
+            for part in text.split("pub extern \"C\" fn ").skip(1) {
+                let name = part.split(['(', '<']).next().unwrap().trim();
+                exported.insert(name.to_string());
+            }
+        }
+        let declared: BTreeSet<String> = parse_header(HEADER)
+            .functions
+            .into_iter()
+            .map(|f| f.name)
+            .collect();
+        assert_eq!(
+            exported.difference(&declared).collect::<Vec<_>>(),
+            Vec::<&String>::new(),
+            "exported but not declared"
+        );
+        assert_eq!(
+            declared.difference(&exported).collect::<Vec<_>>(),
+            Vec::<&String>::new(),
+            "declared but not exported"
+        );
+    }
+}
@@ -0,0 +1,445 @@
+//! Memory access through a chosen MEM-AP with chosen bus attributes: the CSW protection bits
+//! (HNONSEC, HPROT privileged/cacheable/bufferable) are set per access, and any AHB-AP of the
+//! debug port can be used, e.g. a system AP instead of the core's. Needed to look at memory as
+//! Non-secure or unprivileged code sees it on TrustZone parts, or to bypass caches when
+//! debugging DMA coherency.
+//!
+//! The accesses are raw AP register transfers; the AP's CSW is restored afterwards, so the
+//! normal memory functions keep their attributes.
+
+use crate::{get_session, set_error};
+use probe_rs::architecture::arm::dp::DpAddress;
+use probe_rs::architecture::arm::{ArmDebugInterface, ArmError, FullyQualifiedApAddress};
+use probe_rs_target::{ApAddress, CoreAccessOptions};
+
+/// `PR_MEM_ATTR_NONSECURE`: Non-secure access (HNONSEC = 1).
+const ATTR_NONSECURE: u32 = 1;
+/// `PR_MEM_ATTR_SECURE`: Secure access (HNONSEC = 0), needs secure debug to be enabled.
+const ATTR_SECURE: u32 = 2;
+/// `PR_MEM_ATTR_UNPRIVILEGED`: user mode access (HPROT[1] = 0).
+const ATTR_UNPRIVILEGED: u32 = 4;
+/// `PR_MEM_ATTR_NONCACHEABLE`: non-cacheable access (HPROT[3] = 0).
+const ATTR_NONCACHEABLE: u32 = 8;
+/// `PR_MEM_ATTR_BUFFERABLE`: bufferable access (HPROT[2] = 1).
+const ATTR_BUFFERABLE: u32 = 0x10;
+const ATTR_ALL: u32 =
+    ATTR_NONSECURE | ATTR_SECURE | ATTR_UNPRIVILEGED | ATTR_NONCACHEABLE | ATTR_BUFFERABLE;
+
+// MEM-AP registers.
+const AP_CSW: u64 = 0x00;
+const AP_TAR: u64 = 0x04;
+const AP_DRW: u64 = 0x0C;
+const AP_IDR: u64 = 0xFC;
+
+const CSW_DBG_SW_ENABLE: u32 = 1 << 31;
+const CSW_HNONSEC: u32 = 1 << 30;
+const CSW_MASTER_TYPE: u32 = 1 << 29;
+const CSW_CACHEABLE: u32 = 1 << 27;
+const CSW_BUFFERABLE: u32 = 1 << 26;
+const CSW_PRIVILEGED: u32 = 1 << 25;
+const CSW_DATA: u32 = 1 << 24;
+const CSW_SPIDEN: u32 = 1 << 23;
+const CSW_ADDR_INC_SINGLE: u32 = 0x10;
+const CSW_SIZE_8: u32 = 0;
+const CSW_SIZE_32: u32 = 2;
+/// The CSW fields set for an access; the others are kept.
+const CSW_CONTROL: u32 = 0xFF00_0037;
+
+/// TAR auto-increment is only guaranteed within 1 KiB blocks.
+const AUTO_INCREMENT_BLOCK: u64 = 0x400;
+
+/// IDR class of a MEM-AP.
+const IDR_CLASS_MEM_AP: u32 = 0x8;
+/// IDR types of the AHB-APs, whose CSW carries HNONSEC and HPROT at the same bits.
+const IDR_TYPES_AHB: [u32; 3] = [0x1, 0x5, 0x8];
+
+/// The CSW value for an access of `size` with `attrs`, starting from the AP's `current` CSW.
+/// Without attributes it is what the normal memory functions use.
+fn csw_for(current: u32, attrs: u32, size: u32) -> Result<u32, (i32, String)> {
+    if attrs & !ATTR_ALL != 0 {
+        return Err((
+            -1,
+            format!("unknown attribute flags {:#x}", attrs & !ATTR_ALL),
+        ));
+    }
+    if attrs & ATTR_NONSECURE != 0 && attrs & ATTR_SECURE != 0 {
+        return Err((-1, "secure and non-secure access requested".to_string()));
+    }
+    let secure_enabled = current & CSW_SPIDEN != 0;
+    if attrs & ATTR_SECURE != 0 && !secure_enabled {
+        return Err((
+            -3,
+            "secure debug is disabled on this AP (SPIDEN = 0)".to_string(),
+        ));
+    }
+    let mut csw = (current & !CSW_CONTROL)
+        | CSW_DBG_SW_ENABLE
+        | CSW_MASTER_TYPE
+        | CSW_DATA
+        | CSW_ADDR_INC_SINGLE
+        | size;
+    let nonsecure = match attrs & (ATTR_NONSECURE | ATTR_SECURE) {
+        ATTR_NONSECURE => true,
+        ATTR_SECURE => false,
+        _ => !secure_enabled,
+    };
+    if nonsecure {
+        csw |= CSW_HNONSEC;
+    }
+    if attrs & ATTR_UNPRIVILEGED == 0 {
+        csw |= CSW_PRIVILEGED;
+    }
+    if attrs & ATTR_NONCACHEABLE == 0 {
+        csw |= CSW_CACHEABLE;
+    }
+    if attrs & ATTR_BUFFERABLE != 0 {
+        csw |= CSW_BUFFERABLE;
+    }
+    Ok(csw)
+}
+
+/// `address..address + len` split into `(address, len, whole words)` transfers: single bytes up
+/// to word alignment and after the last whole word, and word runs that stay within one
+/// auto-increment block.
+fn transfers(address: u64, len: usize) -> Vec<(u64, usize, bool)> {
+    let end = address + len as u64;
+    let mut out = Vec::new();
+    let mut at = address;
+    while at < end {
+        if !at.is_multiple_of(4) || end - at < 4 {
+            out.push((at, 1, false));
+            at += 1;
+            continue;
+        }
+        let block_end = (at / AUTO_INCREMENT_BLOCK + 1) * AUTO_INCREMENT_BLOCK;
+        let run_end = block_end.min(end - (end - at) % 4);
+        out.push((at, (run_end - at) as usize, true));
+        at = run_end;
+    }
+    out
+}
+
+/// A MEM-AP prepared for accesses with some attributes.
+struct MemAp<'a> {
+    interface: &'a mut dyn ArmDebugInterface,
+    ap: FullyQualifiedApAddress,
+    original_csw: u32,
+    attrs: u32,
+    csw: u32,
+}
+
+impl<'a> MemAp<'a> {
+    fn open(
+        interface: &'a mut dyn ArmDebugInterface,
+        ap: FullyQualifiedApAddress,
+        attrs: u32,
+    ) -> Result<Self, (i32, String)> {
+        let idr = interface
+            .read_raw_ap_register(&ap, AP_IDR)
+            .map_err(ap_error)?;
+        if (idr >> 13) & 0xf != IDR_CLASS_MEM_AP || !IDR_TYPES_AHB.contains(&(idr & 0xf)) {
+            return Err((
+                -3,
+                format!("AP {:?} is not an AHB-AP (IDR {:#010x})", ap.ap(), idr),
+            ));
+        }
+        let original_csw = interface
+            .read_raw_ap_register(&ap, AP_CSW)
+            .map_err(ap_error)?;
+        // Check the attributes before anything is written.
+        csw_for(original_csw, attrs, CSW_SIZE_32)?;
+        Ok(Self {
+            interface,
+            ap,
+            original_csw,
+            attrs,
+            csw: original_csw,
+        })
+    }
+
+    fn set_size(&mut self, size: u32) -> Result<(), (i32, String)> {
+        let csw = csw_for(self.original_csw, self.attrs, size)?;
+        if csw != self.csw {
+            self.write(AP_CSW, csw)?;
+            self.csw = csw;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, register: u64, value: u32) -> Result<(), (i32, String)> {
+        self.interface
+            .write_raw_ap_register(&self.ap, register, value)
+            .map_err(ap_error)
+    }
+
+    fn read(&mut self, register: u64) -> Result<u32, (i32, String)> {
+        self.interface
+            .read_raw_ap_register(&self.ap, register)
+            .map_err(ap_error)
+    }
+
+    fn read_memory(&mut self, address: u64, buf: &mut [u8]) -> Result<(), (i32, String)> {
+        for (at, len, words) in transfers(address, buf.len()) {
+            let out = &mut buf[(at - address) as usize..][..len];
+            if words {
+                self.set_size(CSW_SIZE_32)?;
+                self.write(AP_TAR, at as u32)?;
+                let mut values = vec![0u32; len / 4];
+                self.interface
+                    .read_raw_ap_register_repeated(&self.ap, AP_DRW, &mut values)
+                    .map_err(ap_error)?;
+                for (chunk, value) in out.chunks_exact_mut(4).zip(values) {
+                    chunk.copy_from_slice(&value.to_le_bytes());
+                }
+            } else {
+                self.set_size(CSW_SIZE_8)?;
+                self.write(AP_TAR, at as u32)?;
+                out[0] = (self.read(AP_DRW)? >> (8 * (at % 4))) as u8;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_memory(&mut self, address: u64, data: &[u8]) -> Result<(), (i32, String)> {
+        for (at, len, words) in transfers(address, data.len()) {
+            let data = &data[(at - address) as usize..][..len];
+            if words {
+                self.set_size(CSW_SIZE_32)?;
+                self.write(AP_TAR, at as u32)?;
+                let values: Vec<u32> = data
+                    .chunks_exact(4)
+                    .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                    .collect();
+                self.interface
+                    .write_raw_ap_register_repeated(&self.ap, AP_DRW, &values)
+                    .map_err(ap_error)?;
+            } else {
+                self.set_size(CSW_SIZE_8)?;
+                self.write(AP_TAR, at as u32)?;
+                self.write(AP_DRW, u32::from(data[0]) << (8 * (at % 4)))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Put the CSW back as the normal memory functions left it.
+    fn close(mut self) -> Result<(), (i32, String)> {
+        if self.csw != self.original_csw {
+            self.write(AP_CSW, self.original_csw)?;
+        }
+        self.interface.flush().map_err(ap_error)
+    }
+}
+
+fn ap_error(e: ArmError) -> (i32, String) {
+    (-2, format!("AP access error: {}", e))
+}
+
+/// Run `op` on AP `ap_index` (the AP of core `core_index` if negative) of the debug port of core
+/// `core_index`, returning the C result code.
+fn with_mem_ap(
+    session: u64,
+    core_index: u32,
+    ap_index: i32,
+    address: u64,
+    len: usize,
+    attrs: u32,
+    op: impl FnOnce(&mut MemAp<'_>) -> Result<(), (i32, String)>,
+) -> i32 {
+    let end = address.saturating_add(len as u64);
+    if end > 1 << 32 {
+        set_error(format!(
+            "{:#x}..{:#x} is outside the 32-bit address space of the AP",
+            address, end
+        ));
+        return -1;
+    }
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let Some(core) = lock.target().cores.get(core_index as usize) else {
+        set_error(format!("invalid core index {}", core_index));
+        return -1;
+    };
+    let CoreAccessOptions::Arm(options) = &core.core_access_options else {
+        set_error("AP access needs an ARM core".to_string());
+        return -3;
+    };
+    let dp = options
+        .targetsel
+        .map_or(DpAddress::Default, DpAddress::Multidrop);
+    let ap = match (ap_index, &options.ap) {
+        (0..=255, _) => ap_index as u8,
+        (0.., _) => {
+            set_error(format!("invalid AP index {}", ap_index));
+            return -1;
+        }
+        (_, ApAddress::V1(ap)) => *ap,
+        (_, ApAddress::V2(_)) => {
+            set_error("ADIv6 access ports are not supported".to_string());
+            return -3;
+        }
+    };
+    let interface = match lock.get_arm_interface() {
+        Ok(interface) => interface,
+        Err(e) => {
+            set_error(format!("ARM interface error: {}", e));
+            return -3;
+        }
+    };
+    let res = MemAp::open(
+        interface,
+        FullyQualifiedApAddress::v1_with_dp(dp, ap),
+        attrs,
+    )
+    .and_then(|mut mem_ap| {
+        let res = op(&mut mem_ap);
+        let closed = mem_ap.close();
+        res.and(closed)
+    });
+    match res {
+        Ok(()) => 0,
+        Err((code, e)) => {
+            set_error(e);
+            code
+        }
+    }
+}
+
+/// Read `len` bytes at `address` through the MEM-AP `ap_index` of the debug port of core
+/// `core_index` (the core's own AP if `ap_index` is negative) with the bus attributes `attrs`:
+/// `PR_MEM_ATTR_NONSECURE` (1), `PR_MEM_ATTR_SECURE` (2), `PR_MEM_ATTR_UNPRIVILEGED` (4),
+/// `PR_MEM_ATTR_NONCACHEABLE` (8), `PR_MEM_ATTR_BUFFERABLE` (0x10). With 0 the attributes are
+/// those of `pr_read_8`: privileged, cacheable, secure if secure debug is enabled.
+///
+/// Returns 0 on success, -1 on invalid handle, index, flags or address, -2 on AP or memory
+/// error, -3 if the AP is not an AHB-AP (ADIv5) or secure access is requested but disabled.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_read_mem_ap(
+    session: u64,
+    core_index: u32,
+    ap_index: i32,
+    address: u64,
+    buf: *mut u8,
+    len: u32,
+    attrs: u32,
+) -> i32 {
+    if buf.is_null() && len > 0 {
+        set_error("buf is null".to_string());
+        return -1;
+    }
+    let len = len as usize;
+    with_mem_ap(session, core_index, ap_index, address, len, attrs, |ap| {
+        if len == 0 {
+            return Ok(());
+        }
+        let buf = unsafe { std::slice::from_raw_parts_mut(buf, len) };
+        ap.read_memory(address, buf)
+    })
+}
+
+/// Write `len` bytes of `buf` to `address` through a MEM-AP with bus attributes, as
+/// `pr_read_mem_ap` reads.
+///
+/// Returns 0 on success, -1 on invalid handle, index, flags or address, -2 on AP or memory
+/// error, -3 if the AP is not an AHB-AP (ADIv5) or secure access is requested but disabled.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_write_mem_ap(
+    session: u64,
+    core_index: u32,
+    ap_index: i32,
+    address: u64,
+    buf: *const u8,
+    len: u32,
+    attrs: u32,
+) -> i32 {
+    if buf.is_null() && len > 0 {
+        set_error("buf is null".to_string());
+        return -1;
+    }
+    let len = len as usize;
+    with_mem_ap(session, core_index, ap_index, address, len, attrs, |ap| {
+        if len == 0 {
+            return Ok(());
+        }
+        let data = unsafe { std::slice::from_raw_parts(buf, len) };
+        ap.write_memory(address, data)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csw_from_attributes() {
+        // SPIDEN set, size and increment left over from another access.
+        let current = 0x2380_0052 | CSW_SPIDEN;
+        let default = csw_for(current, 0, CSW_SIZE_32).unwrap();
+        assert_eq!(default, 0xAB80_0052);
+        assert_eq!(
+            csw_for(current, ATTR_NONSECURE, CSW_SIZE_32).unwrap(),
+            default | CSW_HNONSEC
+        );
+        assert_eq!(
+            csw_for(
+                current,
+                ATTR_UNPRIVILEGED | ATTR_NONCACHEABLE | ATTR_BUFFERABLE,
+                CSW_SIZE_8
+            )
+            .unwrap(),
+            0xA580_0050
+        );
+        // Without secure debug default accesses are non-secure and secure ones impossible.
+        assert_eq!(csw_for(0, 0, CSW_SIZE_32).unwrap(), 0xEB00_0012);
+        assert_eq!(csw_for(0, ATTR_SECURE, CSW_SIZE_32).unwrap_err().0, -3);
+        assert_eq!(
+            csw_for(current, ATTR_SECURE | ATTR_NONSECURE, CSW_SIZE_32)
+                .unwrap_err()
+                .0,
+            -1
+        );
+        assert_eq!(csw_for(current, 0x20, CSW_SIZE_32).unwrap_err().0, -1);
+    }
+
+    #[test]
+    fn transfers_split_at_alignment_and_blocks() {
+        assert_eq!(
+            transfers(0x2000_0001, 10),
+            vec![
+                (0x2000_0001, 1, false),
+                (0x2000_0002, 1, false),
+                (0x2000_0003, 1, false),
+                (0x2000_0004, 4, true),
+                (0x2000_0008, 1, false),
+                (0x2000_0009, 1, false),
+                (0x2000_000a, 1, false),
+            ]
+        );
+        assert_eq!(
+            transfers(0x2000_03f8, 0x10),
+            vec![(0x2000_03f8, 8, true), (0x2000_0400, 8, true)]
+        );
+        assert_eq!(transfers(0x2000_0000, 2).len(), 2);
+        assert!(transfers(0x2000_0000, 0).is_empty());
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            pr_read_mem_ap(0xdead, 0, -1, 0x2000_0000, buf.as_mut_ptr(), 4, 0),
+            -1
+        );
+        assert_eq!(
+            pr_read_mem_ap(0xdead, 0, -1, 0xffff_fffe, buf.as_mut_ptr(), 4, 0),
+            -1
+        );
+        assert_eq!(
+            pr_write_mem_ap(0xdead, 0, 0, 0x2000_0000, std::ptr::null(), 4, 0),
+            -1
+        );
+    }
+}
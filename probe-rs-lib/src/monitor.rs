@@ -0,0 +1,195 @@
+//! Background core status monitoring: a thread polls the status of every core of a session and
+//! reports transitions through a callback, so hosts don't have to poll `pr_core_status`.
+
+use crate::{get_session, set_error};
+use probe_rs::{CoreStatus, Session};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// `(session, core index, status, previous status)`.
+type MonitorCb = unsafe extern "C" fn(u64, u32, i32, i32);
+
+/// Previous status of the first report for a core.
+const STATUS_NONE: i32 = -1;
+/// The status could not be read, e.g. the probe was disconnected.
+const STATUS_ERROR: i32 = -2;
+
+struct Monitor {
+    session: u64,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Monitor {
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+static MONITORS: OnceLock<Mutex<HashMap<u64, Monitor>>> = OnceLock::new();
+static NEXT_MONITOR_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+fn monitors() -> &'static Mutex<HashMap<u64, Monitor>> {
+    MONITORS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Status codes as returned by `pr_core_status`, extended by the states it reports as unknown.
+fn status_code(status: &CoreStatus) -> i32 {
+    match status {
+        CoreStatus::Unknown => 0,
+        CoreStatus::Halted(_) => 1,
+        CoreStatus::Running => 2,
+        CoreStatus::LockedUp => 3,
+        CoreStatus::Sleeping => 4,
+    }
+}
+
+fn read_statuses(session: &Mutex<Session>) -> Vec<i32> {
+    let mut lock = session.lock().unwrap();
+    let cores = lock.list_cores().len();
+    (0..cores)
+        .map(|i| match lock.core(i).and_then(|mut core| core.status()) {
+            Ok(status) => status_code(&status),
+            Err(_) => STATUS_ERROR,
+        })
+        .collect()
+}
+
+/// Core indices whose status changed, with the new and the previous status.
+fn transitions(previous: &[i32], current: &[i32]) -> Vec<(u32, i32, i32)> {
+    current
+        .iter()
+        .enumerate()
+        .map(|(i, &status)| (i, status, previous.get(i).copied().unwrap_or(STATUS_NONE)))
+        .filter(|(_, status, previous)| status != previous)
+        .map(|(i, status, previous)| (i as u32, status, previous))
+        .collect()
+}
+
+/// Stop and remove every monitor of a session, e.g. before it is closed.
+pub(crate) fn stop_for_session(session: u64) {
+    let mut stopped: Vec<Monitor> = {
+        let mut map = monitors().lock().unwrap();
+        let handles: Vec<u64> = map
+            .iter()
+            .filter(|(_, m)| m.session == session)
+            .map(|(h, _)| *h)
+            .collect();
+        handles.iter().filter_map(|h| map.remove(h)).collect()
+    };
+    for m in &mut stopped {
+        m.shutdown();
+    }
+}
+
+/// Poll the status of every core of a session every `interval_ms` milliseconds and call
+/// `callback(session, core_index, status, previous)` when it changes.
+///
+/// Status codes: 0 = unknown, 1 = halted, 2 = running, 3 = locked up, 4 = sleeping, -2 = the
+/// status could not be read. The first poll reports every core with `previous` = -1.
+///
+/// The callback runs on the monitor thread without the session lock held, so it may call
+/// other functions of the session, but must not call `pr_monitor_stop`. The monitor is stopped
+/// by `pr_monitor_stop` or when the session is closed.
+///
+/// Returns a non-zero monitor handle, or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_monitor_start(
+    session: u64,
+    interval_ms: u32,
+    callback: Option<MonitorCb>,
+) -> u64 {
+    let Some(callback) = callback else {
+        set_error("callback is null".to_string());
+        return 0;
+    };
+    let sess = match get_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let stop = stop.clone();
+        let interval = Duration::from_millis(interval_ms as u64);
+        std::thread::spawn(move || {
+            let mut previous: Vec<i32> = Vec::new();
+            while !stop.load(Ordering::Relaxed) {
+                let current = read_statuses(&sess);
+                for (core, status, before) in transitions(&previous, &current) {
+                    unsafe { callback(session, core, status, before) };
+                }
+                previous = current;
+                std::thread::sleep(interval);
+            }
+        })
+    };
+
+    let handle = NEXT_MONITOR_HANDLE.fetch_add(1, Ordering::Relaxed);
+    monitors().lock().unwrap().insert(
+        handle,
+        Monitor {
+            session,
+            stop,
+            thread: Some(thread),
+        },
+    );
+    handle
+}
+
+/// Stop a monitor; no callback runs after this returns. Returns 0 on success, -1 on invalid
+/// handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_monitor_stop(monitor: u64) -> i32 {
+    let removed = monitors().lock().unwrap().remove(&monitor);
+    match removed {
+        Some(mut m) => {
+            m.shutdown();
+            0
+        }
+        None => {
+            set_error("invalid monitor handle".to_string());
+            -1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn first_poll_reports_every_core() {
+        assert_eq!(
+            transitions(&[], &[2, 1]),
+            vec![(0, 2, STATUS_NONE), (1, 1, STATUS_NONE)]
+        );
+    }
+
+    #[test]
+    fn only_changes_are_reported() {
+        assert_eq!(transitions(&[2, 2], &[2, 3]), vec![(1, 3, 2)]);
+        assert_eq!(transitions(&[1], &[1]), vec![]);
+        assert_eq!(
+            transitions(&[2], &[STATUS_ERROR]),
+            vec![(0, STATUS_ERROR, 2)]
+        );
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        unsafe extern "C" fn cb(_: u64, _: u32, _: i32, _: i32) {}
+        assert_eq!(pr_monitor_start(0xdead, 10, Some(cb)), 0);
+        assert_eq!(pr_monitor_start(0xdead, 10, None), 0);
+        assert_eq!(pr_monitor_stop(0xdead), -1);
+    }
+}
@@ -0,0 +1,268 @@
+//! One-time programmable memory: reading and burning the OTP areas that chips expose through
+//! debug access (the `OTP` regions of the memory map, e.g. STM32F2/F4/F7), for provisioning keys
+//! and board IDs. Burning goes through the chip's OTP flash algorithm and cannot be undone, so
+//! it requires an explicit flag.
+
+use crate::{cstr_to_string, download_options, error_chain, get_session, set_error};
+use probe_rs::MemoryInterface;
+use probe_rs::config::Target;
+use probe_rs_target::{MemoryRange, MemoryRegion};
+use std::ffi::c_char;
+use std::ops::Range;
+
+/// `PR_OTP_ALLOW_BURN`: the caller confirms that the write is permanent.
+const ALLOW_BURN: u32 = 1;
+
+/// The OTP regions of `target`, in memory map order.
+fn otp_regions(target: &Target) -> Vec<(String, Range<u64>)> {
+    target
+        .memory_map
+        .iter()
+        .filter_map(MemoryRegion::as_nvm_region)
+        .filter(|r| !r.is_alias)
+        .filter_map(|r| {
+            let name = r.name.as_deref()?;
+            name.to_ascii_uppercase()
+                .contains("OTP")
+                .then(|| (name.to_string(), r.range.clone()))
+        })
+        .collect()
+}
+
+/// The range `offset..offset + len` of the OTP region `name` (the first one if None).
+fn otp_range(
+    target: &Target,
+    name: Option<&str>,
+    offset: u32,
+    len: usize,
+) -> Result<Range<u64>, (i32, String)> {
+    let regions = otp_regions(target);
+    let region = match name {
+        Some(name) => regions.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)),
+        None => regions.first(),
+    };
+    let Some((name, range)) = region else {
+        let code = if regions.is_empty() { -3 } else { -1 };
+        return Err((code, format!("no OTP region {}", name.unwrap_or(""))));
+    };
+    let start = range.start + u64::from(offset);
+    let end = start + len as u64;
+    if end > range.end {
+        return Err((
+            -1,
+            format!(
+                "{:#x}..{:#x} is outside {} ({:#x}..{:#x})",
+                start, end, name, range.start, range.end
+            ),
+        ));
+    }
+    Ok(start..end)
+}
+
+/// Whether burning `data` over `current` changes anything. Programming only clears bits, so a
+/// bit set in `data` but cleared in `current` cannot be written.
+fn burn_needed(start: u64, current: &[u8], data: &[u8]) -> Result<bool, String> {
+    if let Some(i) = current.iter().zip(data).position(|(c, d)| c & d != *d) {
+        return Err(format!(
+            "OTP byte at {:#x} is {:#04x}, {:#04x} cannot be written over it",
+            start + i as u64,
+            current[i],
+            data[i]
+        ));
+    }
+    Ok(current != data)
+}
+
+/// Optional region name: NULL for the first OTP region.
+fn region_name(region: *const c_char) -> Result<Option<String>, String> {
+    if region.is_null() {
+        Ok(None)
+    } else {
+        cstr_to_string(region).map(Some)
+    }
+}
+
+/// Read `len` bytes at `offset` of the OTP region `region` (its memory map name as listed by
+/// `pr_flash_sector_layout`, NULL for the first) into `out`.
+///
+/// Returns 0 on success, -1 on invalid handle, region or range, -2 on target error, -3 if the
+/// chip has no OTP region.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_otp_read(
+    session: u64,
+    region: *const c_char,
+    offset: u32,
+    out: *mut u8,
+    len: usize,
+) -> i32 {
+    if out.is_null() && len > 0 {
+        set_error("null buffer".to_string());
+        return -1;
+    }
+    let name = match region_name(region) {
+        Ok(name) => name,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let range = match otp_range(lock.target(), name.as_deref(), offset, len) {
+        Ok(range) => range,
+        Err((code, e)) => {
+            set_error(e);
+            return code;
+        }
+    };
+    if len == 0 {
+        return 0;
+    }
+    let buf = unsafe { std::slice::from_raw_parts_mut(out, len) };
+    match lock
+        .core(0)
+        .and_then(|mut core| core.read(range.start, buf))
+    {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(format!("read OTP error: {}", e));
+            -2
+        }
+    }
+}
+
+/// Burn `len` bytes of `data` at `offset` of the OTP region `region` (NULL for the first) with
+/// the chip's OTP flash algorithm, and verify them. This is permanent: `flags` must include
+/// `PR_OTP_ALLOW_BURN` (1). Bytes already holding `data` are no error; data needing a
+/// programmed (0) bit back at 1 is rejected before anything is written.
+///
+/// Returns 0 on success, -1 on invalid handle, region, range or flags or on data that cannot be
+/// burnt, -2 on target or programming error, -3 if the chip has no OTP region or no flash
+/// algorithm for it.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_otp_write(
+    session: u64,
+    region: *const c_char,
+    offset: u32,
+    data: *const u8,
+    len: usize,
+    flags: u32,
+) -> i32 {
+    if flags & ALLOW_BURN == 0 {
+        set_error("OTP writes are permanent, pass PR_OTP_ALLOW_BURN to burn".to_string());
+        return -1;
+    }
+    if data.is_null() || len == 0 {
+        set_error("no data".to_string());
+        return -1;
+    }
+    let name = match region_name(region) {
+        Ok(name) => name,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let range = match otp_range(lock.target(), name.as_deref(), offset, len) {
+        Ok(range) => range,
+        Err((code, e)) => {
+            set_error(e);
+            return code;
+        }
+    };
+    let has_algorithm = lock
+        .target()
+        .flash_algorithms
+        .iter()
+        .any(|a| a.flash_properties.address_range.contains_range(&range));
+    if !has_algorithm {
+        set_error(format!(
+            "no flash algorithm programs OTP at {:#x}",
+            range.start
+        ));
+        return -3;
+    }
+    let data = unsafe { std::slice::from_raw_parts(data, len) };
+    let mut current = vec![0u8; len];
+    if let Err(e) = lock
+        .core(0)
+        .and_then(|mut core| core.read(range.start, &mut current))
+    {
+        set_error(format!("read OTP error: {}", e));
+        return -2;
+    }
+    match burn_needed(range.start, &current, data) {
+        Ok(true) => {}
+        Ok(false) => return 0,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    }
+    tracing::warn!("burning {} OTP bytes at {:#x}", len, range.start);
+    let mut loader = lock.target().flash_loader();
+    let opts = download_options(Some(session), 1, 0, 0);
+    let res = loader
+        .add_data(range.start, data)
+        .and_then(|()| loader.commit(&mut lock, opts));
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(format!("burn OTP error: {}", error_chain(&e)));
+            -2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry;
+
+    #[test]
+    fn otp_regions_of_memory_map() {
+        let target = registry().get_target_by_name("STM32F407VGTx").unwrap();
+        assert_eq!(
+            otp_regions(&target),
+            vec![("OTP".to_string(), 0x1fff_7800..0x1fff_7a10)]
+        );
+        assert_eq!(
+            otp_range(&target, Some("otp"), 0x10, 4),
+            Ok(0x1fff_7810..0x1fff_7814)
+        );
+        assert_eq!(otp_range(&target, None, 0x200, 0x11).unwrap_err().0, -1);
+        assert_eq!(otp_range(&target, Some("KEYS"), 0, 4).unwrap_err().0, -1);
+
+        let nrf = registry().get_target_by_name("nRF52840_xxAA").unwrap();
+        assert_eq!(otp_range(&nrf, None, 0, 4).unwrap_err().0, -3);
+    }
+
+    #[test]
+    fn burning_only_clears_bits() {
+        assert_eq!(burn_needed(0, &[0xff, 0xff], &[0x12, 0xff]), Ok(true));
+        assert_eq!(burn_needed(0, &[0x12, 0x00], &[0x12, 0x00]), Ok(false));
+        assert_eq!(burn_needed(0, &[0x1f], &[0x10]), Ok(true));
+        assert!(burn_needed(0x100, &[0xff, 0x10], &[0xff, 0x30]).is_err());
+    }
+
+    #[test]
+    fn burning_requires_the_flag() {
+        let data = [0u8; 4];
+        assert_eq!(
+            pr_otp_write(1, std::ptr::null(), 0, data.as_ptr(), data.len(), 0),
+            -1
+        );
+        assert_eq!(
+            pr_otp_write(0xdead, std::ptr::null(), 0, data.as_ptr(), 4, ALLOW_BURN),
+            -1
+        );
+    }
+}
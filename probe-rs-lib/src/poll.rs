@@ -0,0 +1,236 @@
+//! Background sampling of target memory while the core runs, for live plotting.
+//!
+//! Each poller owns a thread that periodically reads one value through the memory access
+//! port (the core is not halted) and appends it to a bounded ring buffer.
+
+use crate::{can_access_running, get_session, set_error};
+use probe_rs::{MemoryInterface, Session};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// Samples kept per poller; older samples are dropped when the reader falls behind.
+const RING_CAPACITY: usize = 65536;
+
+struct Poller {
+    session: u64,
+    stop: Arc<AtomicBool>,
+    samples: Arc<Mutex<VecDeque<(u64, u64)>>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Poller {
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+static POLLERS: OnceLock<Mutex<HashMap<u64, Poller>>> = OnceLock::new();
+static NEXT_POLL_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+fn pollers() -> &'static Mutex<HashMap<u64, Poller>> {
+    POLLERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn read_value(
+    session: &Mutex<Session>,
+    core_index: usize,
+    address: u64,
+    width: u32,
+) -> Result<u64, probe_rs::Error> {
+    let mut lock = session.lock().unwrap();
+    let mut core = lock.core(core_index)?;
+    if !can_access_running(&mut core)? {
+        return Err(probe_rs::Error::Other(
+            "memory cannot be read without halting".to_string(),
+        ));
+    }
+    Ok(match width {
+        1 => core.read_word_8(address)? as u64,
+        2 => core.read_word_16(address)? as u64,
+        4 => core.read_word_32(address)? as u64,
+        _ => core.read_word_64(address)?,
+    })
+}
+
+fn push_sample(samples: &Mutex<VecDeque<(u64, u64)>>, sample: (u64, u64)) {
+    let mut ring = samples.lock().unwrap();
+    if ring.len() == RING_CAPACITY {
+        ring.pop_front();
+    }
+    ring.push_back(sample);
+}
+
+/// Stop and remove every poller of a session, e.g. before it is closed.
+pub(crate) fn stop_for_session(session: u64) {
+    let mut stopped: Vec<Poller> = {
+        let mut map = pollers().lock().unwrap();
+        let handles: Vec<u64> = map
+            .iter()
+            .filter(|(_, p)| p.session == session)
+            .map(|(h, _)| *h)
+            .collect();
+        handles.iter().filter_map(|h| map.remove(h)).collect()
+    };
+    for p in &mut stopped {
+        p.shutdown();
+    }
+}
+
+/// Start sampling a `width`-byte value (1, 2, 4 or 8) at `address` every `interval_ms`
+/// milliseconds (0 = as fast as the probe allows) while the core keeps running.
+///
+/// Samples are read back with `pr_poll_read`. Failed reads are skipped, as are samples that
+/// would require halting the core (see `pr_read_mem_nonstop`). The poller is stopped
+/// by `pr_poll_destroy` or when the session is closed.
+///
+/// Returns a non-zero poll handle, or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_poll_create(
+    session: u64,
+    core_index: u32,
+    address: u64,
+    width: u32,
+    interval_ms: u32,
+) -> u64 {
+    if !matches!(width, 1 | 2 | 4 | 8) {
+        set_error(format!("invalid width {}, expected 1, 2, 4 or 8", width));
+        return 0;
+    }
+    let sess = match get_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    // Validate the core index up front rather than failing every sample.
+    if let Err(e) = sess.lock().unwrap().core(core_index as usize) {
+        set_error(format!("core access error: {}", e));
+        return 0;
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let samples = Arc::new(Mutex::new(VecDeque::new()));
+    let thread = {
+        let stop = stop.clone();
+        let samples = samples.clone();
+        let interval = Duration::from_millis(interval_ms as u64);
+        std::thread::spawn(move || {
+            let start = Instant::now();
+            let mut next = start;
+            while !stop.load(Ordering::Relaxed) {
+                if let Ok(value) = read_value(&sess, core_index as usize, address, width) {
+                    push_sample(&samples, (start.elapsed().as_micros() as u64, value));
+                }
+                next += interval;
+                let now = Instant::now();
+                if next > now {
+                    std::thread::sleep(next - now);
+                } else {
+                    // Fell behind (slow probe); don't try to catch up with a burst.
+                    next = now;
+                    std::thread::yield_now();
+                }
+            }
+        })
+    };
+
+    let handle = NEXT_POLL_HANDLE.fetch_add(1, Ordering::Relaxed);
+    pollers().lock().unwrap().insert(
+        handle,
+        Poller {
+            session,
+            stop,
+            samples,
+            thread: Some(thread),
+        },
+    );
+    handle
+}
+
+/// Take up to `max_samples` of the oldest buffered samples of a poller.
+///
+/// `out_timestamps_us` receives the sample time in microseconds since `pr_poll_create`,
+/// `out_values` the value read (zero-extended). Either pointer may be NULL if not needed.
+///
+/// Returns the number of samples taken (0 if none are pending), or -1 on invalid handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_poll_read(
+    poll: u64,
+    out_timestamps_us: *mut u64,
+    out_values: *mut u64,
+    max_samples: u32,
+) -> i32 {
+    let samples = {
+        let map = pollers().lock().unwrap();
+        match map.get(&poll) {
+            Some(p) => p.samples.clone(),
+            None => {
+                set_error("invalid poll handle".to_string());
+                return -1;
+            }
+        }
+    };
+    let mut ring = samples.lock().unwrap();
+    let n = ring.len().min(max_samples.min(i32::MAX as u32) as usize);
+    for (i, (ts, value)) in ring.drain(..n).enumerate() {
+        unsafe {
+            if !out_timestamps_us.is_null() {
+                *out_timestamps_us.add(i) = ts;
+            }
+            if !out_values.is_null() {
+                *out_values.add(i) = value;
+            }
+        }
+    }
+    n as i32
+}
+
+/// Stop a poller and free its buffer. Returns 0 on success, -1 on invalid handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_poll_destroy(poll: u64) -> i32 {
+    let removed = pollers().lock().unwrap().remove(&poll);
+    match removed {
+        Some(mut p) => {
+            p.shutdown();
+            0
+        }
+        None => {
+            set_error("invalid poll handle".to_string());
+            -1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ring_drops_oldest() {
+        let samples = Mutex::new(VecDeque::new());
+        for i in 0..RING_CAPACITY as u64 + 2 {
+            push_sample(&samples, (i, i));
+        }
+        let ring = samples.lock().unwrap();
+        assert_eq!(ring.len(), RING_CAPACITY);
+        assert_eq!(ring.front(), Some(&(2, 2)));
+    }
+
+    #[test]
+    fn invalid_handles() {
+        assert_eq!(pr_poll_create(0xdead, 0, 0x2000_0000, 4, 10), 0);
+        assert_eq!(pr_poll_create(0xdead, 0, 0x2000_0000, 3, 10), 0);
+        assert_eq!(
+            pr_poll_read(0xdead, std::ptr::null_mut(), std::ptr::null_mut(), 1),
+            -1
+        );
+        assert_eq!(pr_poll_destroy(0xdead), -1);
+    }
+}
@@ -0,0 +1,154 @@
+//! All connected probes as one JSON document, for device tables that would otherwise need
+//! `pr_probe_count`, `pr_probe_info` and `pr_probe_features` per probe.
+
+use crate::{
+    ProgrammerType, code_to_type, info_matches_type, set_error, type_to_code, type_to_str,
+    write_c_str,
+};
+use probe_rs::probe::DebugProbeInfo;
+use serde::Serialize;
+use std::ffi::c_char;
+
+/// What a driver can do; single probe models may support less (a CMSIS-DAP probe without
+/// SWO, say), which only opening the probe tells.
+#[derive(Serialize, Debug, Default, Clone, Copy, PartialEq)]
+struct Capabilities {
+    swd: bool,
+    jtag: bool,
+    arm: bool,
+    riscv: bool,
+    xtensa: bool,
+    swo: bool,
+}
+
+#[derive(Serialize)]
+struct ProbeEntry<'a> {
+    index: usize,
+    identifier: &'a str,
+    vid: u16,
+    pid: u16,
+    vid_pid: String,
+    serial: Option<&'a str>,
+    driver: Option<&'static str>,
+    driver_code: i32,
+    capabilities: Capabilities,
+}
+
+fn driver_type(info: &DebugProbeInfo) -> Option<ProgrammerType> {
+    (1..)
+        .map_while(code_to_type)
+        .find(|ty| info_matches_type(info, *ty))
+}
+
+fn capabilities(ty: ProgrammerType) -> Capabilities {
+    let all = Capabilities {
+        swd: true,
+        jtag: true,
+        arm: true,
+        riscv: true,
+        xtensa: true,
+        swo: true,
+    };
+    let none = Capabilities::default();
+    match ty {
+        ProgrammerType::CmsisDap | ProgrammerType::JLink => all,
+        ProgrammerType::StLink => Capabilities {
+            riscv: false,
+            xtensa: false,
+            ..all
+        },
+        ProgrammerType::Ftdi | ProgrammerType::Ch347UsbJtag => Capabilities {
+            swd: false,
+            swo: false,
+            ..all
+        },
+        ProgrammerType::EspUsbJtag => Capabilities {
+            jtag: true,
+            riscv: true,
+            xtensa: true,
+            ..none
+        },
+        ProgrammerType::WchLink => Capabilities {
+            jtag: true,
+            riscv: true,
+            ..none
+        },
+        ProgrammerType::BlackMagic => Capabilities { swo: false, ..all },
+        ProgrammerType::SifliUart | ProgrammerType::Glasgow => Capabilities {
+            swd: true,
+            arm: true,
+            ..none
+        },
+    }
+}
+
+fn entry(index: usize, info: &DebugProbeInfo) -> ProbeEntry<'_> {
+    let ty = driver_type(info);
+    ProbeEntry {
+        index,
+        identifier: &info.identifier,
+        vid: info.vendor_id,
+        pid: info.product_id,
+        vid_pid: format!("{:04x}:{:04x}", info.vendor_id, info.product_id),
+        serial: info.serial_number.as_deref(),
+        driver: ty.map(type_to_str),
+        driver_code: ty.map(type_to_code).unwrap_or(0),
+        capabilities: ty.map(capabilities).unwrap_or_default(),
+    }
+}
+
+/// List all connected probes as a JSON array of `{"index", "identifier", "vid", "pid",
+/// "vid_pid", "serial", "driver", "driver_code", "capabilities": {"swd", "jtag", "arm",
+/// "riscv", "xtensa", "swo"}}`, in `pr_probe_count` order.
+///
+/// `driver` is the programmer type name (`"cmsis-dap"`, ...) and `driver_code` its code, or
+/// null and 0 for drivers without one. `capabilities` are those of the driver; the probes are
+/// not opened, use `pr_probe_features` for what a single probe supports.
+///
+/// Returns the required size including NUL, or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_list_json(out_json: *mut c_char, out_json_len: usize) -> usize {
+    match list_value() {
+        Ok(json) => write_c_str(&json.to_string(), out_json, out_json_len),
+        Err(e) => {
+            set_error(e);
+            0
+        }
+    }
+}
+
+/// The connected probes as `pr_probe_list_json` lists them.
+pub(crate) fn list_value() -> Result<serde_json::Value, String> {
+    let probes = crate::remote::list_all();
+    let entries: Vec<ProbeEntry> = probes
+        .iter()
+        .enumerate()
+        .map(|(index, info)| entry(index, info))
+        .collect();
+    serde_json::to_value(&entries).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn capabilities_follow_driver() {
+        let stlink = capabilities(ProgrammerType::StLink);
+        assert!(stlink.swd && stlink.arm && stlink.swo);
+        assert!(!stlink.riscv);
+        let wlink = capabilities(ProgrammerType::WchLink);
+        assert!(wlink.jtag && wlink.riscv);
+        assert!(!wlink.swd && !wlink.arm);
+    }
+
+    #[test]
+    fn list_is_a_json_array() {
+        let needed = pr_probe_list_json(std::ptr::null_mut(), 0);
+        assert!(needed >= 3);
+        let mut buf = vec![0u8; needed];
+        pr_probe_list_json(buf.as_mut_ptr() as *mut c_char, buf.len());
+        let json = std::ffi::CStr::from_bytes_until_nul(&buf).unwrap();
+        assert!(json.to_str().unwrap().starts_with('['));
+    }
+}
@@ -0,0 +1,299 @@
+//! Firmware and hardware versions of connected probes, and known-bad firmware, for support
+//! tooling.
+
+use crate::{driver_options, set_error, write_c_str};
+use probe_rs::probe::cmsisdap::{CmsisDap, CmsisDapFactory, CmsisDapTransport};
+use probe_rs::probe::stlink::{StLinkFactory, StlinkError};
+use probe_rs::probe::{
+    DebugProbeError, DebugProbeInfo, Probe, ProbeCreationError, ProbeVersionInfo,
+};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::ffi::c_char;
+
+#[derive(Serialize)]
+struct VersionReport<'a> {
+    name: String,
+    identifier: &'a str,
+    serial: Option<&'a str>,
+    firmware: Option<String>,
+    hardware: Option<String>,
+    details: BTreeMap<String, String>,
+}
+
+/// ST-Link V2 firmware from which on multiple APs and DP bank selection are supported; older
+/// firmware fails on multi-core and ARMv8-M targets.
+const STLINK_V2_RECOMMENDED_JTAG: u32 = 32;
+/// DAPLink builds before this one have SWD transfer and flash programming bugs.
+const DAPLINK_RECOMMENDED_BUILD: u32 = 254;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Driver {
+    StLink,
+    CmsisDap,
+    Other,
+}
+
+fn driver(info: &DebugProbeInfo) -> Driver {
+    if info.is_probe_type::<StLinkFactory>() {
+        Driver::StLink
+    } else if info.is_probe_type::<CmsisDapFactory>() {
+        Driver::CmsisDap
+    } else {
+        Driver::Other
+    }
+}
+
+enum OpenError {
+    /// An ST-Link whose firmware is older than the minimum JTAG version the driver accepts.
+    OutdatedStLink(u8),
+    Other(String),
+}
+
+fn open_probe(index: u32) -> Result<(DebugProbeInfo, probe_rs::probe::Probe), OpenError> {
+    let probes = crate::remote::list_all();
+    let info = probes
+        .get(index as usize)
+        .ok_or_else(|| OpenError::Other("probe index out of range".to_string()))?;
+    match info.open() {
+        Ok(probe) => Ok((info.clone(), probe)),
+        Err(e) => Err(match outdated_stlink(&e) {
+            Some(minimum) => OpenError::OutdatedStLink(minimum),
+            None => OpenError::Other(format!("open probe error: {}", e)),
+        }),
+    }
+}
+
+/// Open the probe at `index` of `pr_probe_count` and ask it for its versions.
+fn probe_versions(index: u32) -> Result<(DebugProbeInfo, String, ProbeVersionInfo), String> {
+    let (info, mut probe) = open_probe(index).map_err(|e| match e {
+        OpenError::OutdatedStLink(minimum) => format!(
+            "ST-Link firmware is older than the minimum supported J{}, the probe cannot be opened",
+            minimum
+        ),
+        OpenError::Other(e) => e,
+    })?;
+    Ok((info, probe.get_name(), probe.version_info()))
+}
+
+fn outdated_stlink(error: &DebugProbeError) -> Option<u8> {
+    let boxed = match error {
+        DebugProbeError::ProbeSpecific(b)
+        | DebugProbeError::ProbeCouldNotBeCreated(ProbeCreationError::ProbeSpecific(b)) => b,
+        _ => return None,
+    };
+    match boxed.downcast_ref::<StlinkError>() {
+        Some(StlinkError::ProbeFirmwareOutdated(minimum)) => Some(*minimum),
+        _ => None,
+    }
+}
+
+fn detail<'a>(version: &'a ProbeVersionInfo, name: &str) -> Option<&'a str> {
+    version
+        .details
+        .iter()
+        .find(|(n, _)| n == name)
+        .map(|(_, v)| v.as_str())
+}
+
+/// Why the firmware of a probe should be updated, or `None` if no problem is known.
+fn update_advice(driver: Driver, version: &ProbeVersionInfo) -> Option<String> {
+    match driver {
+        Driver::StLink => {
+            let jtag: u32 = detail(version, "jtag_version")?.parse().ok()?;
+            (version.hardware.as_deref() == Some("V2") && jtag < STLINK_V2_RECOMMENDED_JTAG).then(
+                || {
+                    format!(
+                        "ST-Link firmware V2J{} does not support multiple APs and DP bank \
+                         selection; update to V2J{} or later",
+                        jtag, STLINK_V2_RECOMMENDED_JTAG
+                    )
+                },
+            )
+        }
+        Driver::CmsisDap => {
+            // DAPLink reports its build number as four digits, e.g. "0257".
+            let firmware = version.firmware.as_deref()?;
+            if firmware.len() != 4 || !firmware.bytes().all(|b| b.is_ascii_digit()) {
+                return None;
+            }
+            let build: u32 = firmware.parse().ok()?;
+            (build < DAPLINK_RECOMMENDED_BUILD).then(|| {
+                format!(
+                    "DAPLink firmware {} has known SWD and flashing bugs; update to {:04} or later",
+                    firmware, DAPLINK_RECOMMENDED_BUILD
+                )
+            })
+        }
+        Driver::Other => None,
+    }
+}
+
+/// Report the firmware and hardware version of the probe at `index` (see `pr_probe_count`)
+/// as JSON: `{"name", "identifier", "serial", "firmware", "hardware", "details": {..}}`.
+///
+/// `firmware` uses the vendor's notation (ST-Link `V2J45`, the CMSIS-DAP firmware version
+/// string, the J-Link firmware banner); `details` holds driver specific entries such as the
+/// CMSIS-DAP interface version and capabilities. Fields a driver does not report are null.
+///
+/// The probe is opened for the query, so it must not be in use. Returns the required size
+/// including NUL, or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_version_info(
+    index: u32,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let (info, name, version) = match probe_versions(index) {
+        Ok(v) => v,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let report = VersionReport {
+        name,
+        identifier: &info.identifier,
+        serial: info.serial_number.as_deref(),
+        firmware: version.firmware,
+        hardware: version.hardware,
+        details: version.details.into_iter().collect(),
+    };
+    match serde_json::to_string(&report) {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e.to_string());
+            0
+        }
+    }
+}
+
+/// Check the probe at `index` (see `pr_probe_count`) against known-bad firmware: ST-Link V2
+/// before V2J32 (and any ST-Link too old to be opened), DAPLink before build 0254.
+///
+/// The reason is written to `out_reason` (may be NULL), cut off at `out_reason_len`.
+/// The probe is opened for the check, so it must not be in use.
+///
+/// Returns 1 if an update is recommended, 0 if no problem is known, -1 if the probe could not
+/// be queried.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_needs_firmware_update(
+    index: u32,
+    out_reason: *mut c_char,
+    out_reason_len: usize,
+) -> i32 {
+    let advice = match open_probe(index) {
+        Ok((info, mut probe)) => update_advice(driver(&info), &probe.version_info()),
+        // The ST-Link driver refuses to open probes with too old firmware.
+        Err(OpenError::OutdatedStLink(minimum)) => Some(format!(
+            "ST-Link firmware is older than J{}, the minimum probe-rs supports; update it with \
+             the ST-Link upgrade utility",
+            minimum
+        )),
+        Err(OpenError::Other(e)) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    match advice {
+        Some(reason) => {
+            write_c_str(&reason, out_reason, out_reason_len);
+            1
+        }
+        None => {
+            write_c_str("", out_reason, out_reason_len);
+            0
+        }
+    }
+}
+
+/// Report the transport the CMSIS-DAP probe at `index` (see `pr_probe_count`) is opened with,
+/// after applying the driver options (`cmsisdap.transport`). The probe is opened for the
+/// query, so it must not be in use.
+///
+/// Returns 1 for v1 (HID), 2 for v2 (bulk), -1 if the probe could not be opened, -3 if it is
+/// not a CMSIS-DAP probe.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_cmsisdap_transport(index: u32) -> i32 {
+    let (info, mut probe) = match open_probe(index) {
+        Ok(p) => p,
+        Err(OpenError::OutdatedStLink(_)) => {
+            set_error("not a CMSIS-DAP probe".to_string());
+            return -3;
+        }
+        Err(OpenError::Other(e)) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    if driver(&info) != Driver::CmsisDap {
+        set_error("not a CMSIS-DAP probe".to_string());
+        return -3;
+    }
+    if let Err(e) = driver_options::apply(&mut probe) {
+        set_error(e);
+        return -1;
+    }
+    match Probe::try_into::<CmsisDap>(&mut probe).map(|p| p.transport()) {
+        Some(CmsisDapTransport::V1) => 1,
+        Some(CmsisDapTransport::V2) => 2,
+        None => {
+            set_error("not a CMSIS-DAP probe".to_string());
+            -3
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(firmware: &str, hardware: Option<&str>, jtag: Option<u32>) -> ProbeVersionInfo {
+        ProbeVersionInfo {
+            firmware: Some(firmware.to_string()),
+            hardware: hardware.map(str::to_string),
+            details: jtag
+                .map(|j| vec![("jtag_version".to_string(), j.to_string())])
+                .unwrap_or_default(),
+        }
+    }
+
+    #[test]
+    fn old_stlink_v2_firmware_needs_update() {
+        let old = version("V2J29", Some("V2"), Some(29));
+        assert!(
+            update_advice(Driver::StLink, &old)
+                .unwrap()
+                .contains("V2J32")
+        );
+        let current = version("V2J45", Some("V2"), Some(45));
+        assert_eq!(update_advice(Driver::StLink, &current), None);
+        let v3 = version("V3J7", Some("V3"), Some(7));
+        assert_eq!(update_advice(Driver::StLink, &v3), None);
+    }
+
+    #[test]
+    fn old_daplink_builds_need_update() {
+        let old = version("0241", None, None);
+        assert!(update_advice(Driver::CmsisDap, &old).is_some());
+        assert_eq!(
+            update_advice(Driver::CmsisDap, &version("0257", None, None)),
+            None
+        );
+        // Other CMSIS-DAP firmware reports versions like "2.1.0".
+        assert_eq!(
+            update_advice(Driver::CmsisDap, &version("2.1.0", None, None)),
+            None
+        );
+    }
+
+    #[test]
+    fn out_of_range_index_fails() {
+        assert_eq!(pr_probe_version_info(u32::MAX, std::ptr::null_mut(), 0), 0);
+        assert_eq!(
+            pr_probe_needs_firmware_update(u32::MAX, std::ptr::null_mut(), 0),
+            -1
+        );
+        assert_eq!(pr_probe_cmsisdap_transport(u32::MAX), -1);
+    }
+}
@@ -0,0 +1,443 @@
+//! Statistical PC-sampling profiler.
+//!
+//! On ARMv7-M/ARMv8-M the DWT program counter sample register (DWT_PCSR) is read while the
+//! core runs, or with `pr_profile_start_swo` the DWT emits periodic PC sample packets over
+//! SWO, which samples far faster than one probe round-trip per sample. Other cores fall back
+//! to halting briefly for every sample.
+
+use crate::swo::{self, ItmDecoder};
+use crate::{get_session, set_error, write_c_str};
+use probe_rs::architecture::arm::SwoConfig;
+use probe_rs::architecture::arm::component::{TraceSink, disable_swv, enable_tracing};
+use probe_rs::{Core, CoreType, MemoryInterface, Session};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::c_char;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+const DWT_CTRL: u64 = 0xE000_1000;
+const DWT_PCSR: u64 = 0xE000_101C;
+const DWT_CTRL_CYCCNTENA: u32 = 1 << 0;
+const DWT_CTRL_POSTPRESET_SHIFT: u32 = 1;
+const DWT_CTRL_CYCTAP: u32 = 1 << 9;
+const DWT_CTRL_PCSAMPLENA: u32 = 1 << 12;
+const DWT_CTRL_EXCTRCENA: u32 = 1 << 16;
+/// How often the SWO data is picked up from the probe.
+const SWO_POLL: Duration = Duration::from_millis(10);
+
+#[derive(Clone, Copy, PartialEq)]
+enum Method {
+    Pcsr,
+    Halt,
+    Swo,
+}
+
+#[derive(Default)]
+struct Histogram {
+    samples: u64,
+    counts: HashMap<u64, u64>,
+}
+
+#[derive(Serialize)]
+struct Bucket {
+    address: u64,
+    count: u64,
+}
+
+#[derive(Serialize)]
+struct ProfileReport {
+    method: &'static str,
+    samples: u64,
+    duration_ms: u64,
+    /// Sorted by descending count.
+    histogram: Vec<Bucket>,
+}
+
+struct Profiler {
+    method: Method,
+    started: Instant,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Histogram>,
+}
+
+static PROFILERS: OnceLock<Mutex<HashMap<u64, Profiler>>> = OnceLock::new();
+
+fn profilers() -> &'static Mutex<HashMap<u64, Profiler>> {
+    PROFILERS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Finished reports that did not fit the caller's buffer yet.
+static REPORTS: OnceLock<Mutex<HashMap<u64, String>>> = OnceLock::new();
+
+fn reports() -> &'static Mutex<HashMap<u64, String>> {
+    REPORTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn sample_pc(session: &Mutex<Session>, core_index: usize, method: Method) -> Option<u64> {
+    let mut lock = session.lock().unwrap();
+    let mut core = lock.core(core_index).ok()?;
+    match method {
+        Method::Pcsr => match core.read_word_32(DWT_PCSR) {
+            // All ones: the core is halted or in a state where no sample is available.
+            Ok(0xFFFF_FFFF) | Err(_) => None,
+            Ok(pc) => Some(pc as u64),
+        },
+        Method::Halt => {
+            if core.core_halted().ok()? {
+                return None;
+            }
+            core.halt(Duration::from_millis(10)).ok()?;
+            let pc = core.read_core_reg(core.program_counter()).ok();
+            let _ = core.run();
+            pc
+        }
+        // Samples arrive over SWO, see `swo_samples`.
+        Method::Swo => None,
+    }
+}
+
+/// DWT_CTRL `(CYCTAP, POSTPRESET)` for a PC sample about every `cycles` cycles: every
+/// `(POSTPRESET + 1) * 64` cycles, or `* 1024` with CYCTAP.
+fn pc_sample_period(cycles: u32) -> (bool, u32) {
+    let (cyctap, tap) = if cycles > 16 * 64 {
+        (true, 1024)
+    } else {
+        (false, 64)
+    };
+    let preset = (cycles + tap / 2) / tap;
+    (cyctap, preset.clamp(1, 16) - 1)
+}
+
+/// Add the PC samples received over SWO to `hist`.
+fn swo_samples(session: &Mutex<Session>, decoder: &mut ItmDecoder, hist: &mut Histogram) {
+    let Ok(data) = session.lock().unwrap().read_trace_data() else {
+        return;
+    };
+    decoder.decode(&data, &mut Vec::new());
+    for pc in decoder.take_pc_samples() {
+        hist.samples += 1;
+        *hist.counts.entry(u64::from(pc)).or_default() += 1;
+    }
+}
+
+/// Turn the PC sample packets of the DWT on or off, keeping the other DWT_CTRL fields; the
+/// exception trace `setup_tracing` enables is dropped to leave SWO bandwidth to the samples.
+fn set_pc_sampling(
+    core: &mut Core<'_>,
+    period: Option<(bool, u32)>,
+) -> Result<(), probe_rs::Error> {
+    let mut ctrl = core.read_word_32(DWT_CTRL)?
+        & !(DWT_CTRL_PCSAMPLENA
+            | DWT_CTRL_CYCTAP
+            | (0xf << DWT_CTRL_POSTPRESET_SHIFT)
+            | DWT_CTRL_EXCTRCENA);
+    if let Some((cyctap, preset)) = period {
+        ctrl |= DWT_CTRL_PCSAMPLENA | DWT_CTRL_CYCCNTENA | (preset << DWT_CTRL_POSTPRESET_SHIFT);
+        if cyctap {
+            ctrl |= DWT_CTRL_CYCTAP;
+        }
+    }
+    core.write_word_32(DWT_CTRL, ctrl)
+}
+
+fn report(method: Method, elapsed: Duration, hist: Histogram) -> ProfileReport {
+    let mut histogram: Vec<Bucket> = hist
+        .counts
+        .into_iter()
+        .map(|(address, count)| Bucket { address, count })
+        .collect();
+    histogram.sort_by(|a, b| b.count.cmp(&a.count).then(a.address.cmp(&b.address)));
+    ProfileReport {
+        method: match method {
+            Method::Pcsr => "pcsr",
+            Method::Halt => "halt",
+            Method::Swo => "swo",
+        },
+        samples: hist.samples,
+        duration_ms: elapsed.as_millis() as u64,
+        histogram,
+    }
+}
+
+/// Stop a session's profiler without reporting, e.g. before it is closed.
+pub(crate) fn stop_for_session(session: u64) {
+    if let Some(p) = profilers().lock().unwrap().remove(&session) {
+        p.stop.store(true, Ordering::Relaxed);
+        let _ = p.thread.join();
+    }
+    reports().lock().unwrap().remove(&session);
+}
+
+/// Start sampling the program counter of a core `sample_rate` times per second
+/// (0 = as fast as the probe allows) until `pr_profile_stop`.
+///
+/// ARMv7-M/ARMv8-M cores are sampled through DWT_PCSR without stopping the core; other cores
+/// are halted for each sample, which perturbs timing. One profiler can run per session.
+///
+/// Returns 0 on success, -1 on invalid handle/core, -2 if a profiler is already running or
+/// sampling cannot be set up.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_profile_start(session: u64, core_index: u32, sample_rate: u32) -> i32 {
+    let sess = match get_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    if profilers().lock().unwrap().contains_key(&session) {
+        set_error("profiler already running for this session".to_string());
+        return -2;
+    }
+    reports().lock().unwrap().remove(&session);
+
+    let method = {
+        let mut lock = sess.lock().unwrap();
+        let mut core = match lock.core(core_index as usize) {
+            Ok(core) => core,
+            Err(e) => {
+                set_error(format!("core access error: {}", e));
+                return -1;
+            }
+        };
+        if matches!(
+            core.core_type(),
+            CoreType::Armv7m | CoreType::Armv7em | CoreType::Armv8m
+        ) {
+            // DWT_PCSR only samples with TRCENA set in DEMCR.
+            if let Err(e) = enable_tracing(&mut core) {
+                set_error(format!("enable DWT error: {}", e));
+                return -2;
+            }
+            Method::Pcsr
+        } else {
+            Method::Halt
+        }
+    };
+
+    spawn(session, sess, core_index as usize, method, sample_rate);
+    0
+}
+
+/// Start the sampling thread of a profiler. SWO profilers turn PC sampling and SWV off again
+/// when they stop.
+fn spawn(session: u64, sess: Arc<Mutex<Session>>, core_index: usize, method: Method, rate: u32) {
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let stop = stop.clone();
+        let interval = match method {
+            Method::Swo => SWO_POLL,
+            _ if rate == 0 => Duration::ZERO,
+            _ => Duration::from_secs(1) / rate,
+        };
+        std::thread::spawn(move || {
+            let mut hist = Histogram::default();
+            let mut decoder = ItmDecoder::new(0);
+            let mut next = Instant::now();
+            while !stop.load(Ordering::Relaxed) {
+                if method == Method::Swo {
+                    swo_samples(&sess, &mut decoder, &mut hist);
+                } else if let Some(pc) = sample_pc(&sess, core_index, method) {
+                    hist.samples += 1;
+                    *hist.counts.entry(pc).or_default() += 1;
+                }
+                next += interval;
+                let now = Instant::now();
+                if next > now {
+                    std::thread::sleep(next - now);
+                } else {
+                    next = now;
+                }
+            }
+            if method == Method::Swo {
+                swo_samples(&sess, &mut decoder, &mut hist);
+                if let Ok(mut core) = sess.lock().unwrap().core(core_index) {
+                    let _ = set_pc_sampling(&mut core, None);
+                    let _ = disable_swv(&mut core);
+                }
+            }
+            hist
+        })
+    };
+
+    profilers().lock().unwrap().insert(
+        session,
+        Profiler {
+            method,
+            started: Instant::now(),
+            stop,
+            thread,
+        },
+    );
+}
+
+/// Start sampling the program counter of a core about `sample_rate` times per second over SWO
+/// (0 = every 64 cycles), until `pr_profile_stop`: the DWT emits PC sample packets every
+/// `tpiu_clock_hz / sample_rate` cycles of the trace clock (taken as the core clock), rounded
+/// to what DWT_CTRL can express (64 to 16384 cycles), and the SWO stream is set up at `baud`
+/// as by `pr_swo_start`. A sample takes 5 bytes of SWO bandwidth; samples beyond it are lost.
+///
+/// If the probe or target cannot trace over SWO, the core is sampled through DWT_PCSR as by
+/// `pr_profile_start`; cores other than ARMv7-M/ARMv8-M are halted for each sample. The
+/// report's `method` tells which was used.
+///
+/// Returns 0 on success, -1 on invalid handle, core or baud rate, -2 if a profiler or SWO
+/// capture is already running or sampling cannot be set up.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_profile_start_swo(
+    session: u64,
+    core_index: u32,
+    tpiu_clock_hz: u32,
+    baud: u32,
+    sample_rate: u32,
+) -> i32 {
+    if baud == 0 || baud > tpiu_clock_hz {
+        set_error(format!(
+            "invalid SWO baud rate {} for a {} Hz trace clock",
+            baud, tpiu_clock_hz
+        ));
+        return -1;
+    }
+    let sess = match get_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    if profilers().lock().unwrap().contains_key(&session) || swo::capturing(session) {
+        set_error("profiler or SWO capture already running for this session".to_string());
+        return -2;
+    }
+    let core_type = {
+        let mut lock = sess.lock().unwrap();
+        match lock.core(core_index as usize) {
+            Ok(core) => core.core_type(),
+            Err(e) => {
+                set_error(format!("core access error: {}", e));
+                return -1;
+            }
+        }
+    };
+    if !matches!(
+        core_type,
+        CoreType::Armv7m | CoreType::Armv7em | CoreType::Armv8m
+    ) {
+        return pr_profile_start(session, core_index, sample_rate);
+    }
+
+    let config = SwoConfig::new(tpiu_clock_hz).set_baud(baud).set_mode_uart();
+    let traced = sess
+        .lock()
+        .unwrap()
+        .setup_tracing(core_index as usize, TraceSink::Swo(config));
+    if let Err(e) = traced {
+        tracing::info!("SWO unavailable ({}), sampling DWT_PCSR", e);
+        return pr_profile_start(session, core_index, sample_rate);
+    }
+    let cycles = match sample_rate {
+        0 => 64,
+        rate => tpiu_clock_hz / rate,
+    };
+    let enabled = sess
+        .lock()
+        .unwrap()
+        .core(core_index as usize)
+        .and_then(|mut core| set_pc_sampling(&mut core, Some(pc_sample_period(cycles))));
+    if let Err(e) = enabled {
+        set_error(format!("enable PC sampling error: {}", e));
+        return -2;
+    }
+    reports().lock().unwrap().remove(&session);
+    spawn(session, sess, core_index as usize, Method::Swo, sample_rate);
+    0
+}
+
+/// Stop the session's profiler and write the flat profile as JSON:
+/// `{"method": "pcsr"|"halt"|"swo", "samples", "duration_ms", "histogram": [{"address", "count"}]}`
+/// with the histogram sorted by descending count. Addresses can be mapped to functions with
+/// `pr_elf_address_symbol`.
+///
+/// If `out_json` is NULL or too small, the profiler is still stopped and the report is kept,
+/// so the call can be repeated with a larger buffer. Returns the required size including NUL,
+/// or 0 on error (e.g. no profiler was started).
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_profile_stop(
+    session: u64,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let running = profilers().lock().unwrap().remove(&session);
+    if let Some(p) = running {
+        p.stop.store(true, Ordering::Relaxed);
+        let elapsed = p.started.elapsed();
+        let Ok(hist) = p.thread.join() else {
+            set_error("profiler thread panicked".to_string());
+            return 0;
+        };
+        match serde_json::to_string(&report(p.method, elapsed, hist)) {
+            Ok(json) => {
+                reports().lock().unwrap().insert(session, json);
+            }
+            Err(e) => {
+                set_error(e.to_string());
+                return 0;
+            }
+        }
+    }
+
+    let mut reports = reports().lock().unwrap();
+    let Some(json) = reports.get(&session) else {
+        set_error("no profiler running for this session".to_string());
+        return 0;
+    };
+    let need = write_c_str(json, out_json, out_json_len);
+    if !out_json.is_null() && out_json_len >= need {
+        reports.remove(&session);
+    }
+    need
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_sorted_by_count() {
+        let mut hist = Histogram::default();
+        for pc in [0x100, 0x200, 0x200, 0x300, 0x200, 0x100] {
+            hist.samples += 1;
+            *hist.counts.entry(pc).or_default() += 1;
+        }
+        let r = report(Method::Pcsr, Duration::from_millis(5), hist);
+        assert_eq!(r.samples, 6);
+        let order: Vec<(u64, u64)> = r.histogram.iter().map(|b| (b.address, b.count)).collect();
+        assert_eq!(order, vec![(0x200, 3), (0x100, 2), (0x300, 1)]);
+    }
+
+    #[test]
+    fn pc_sample_periods() {
+        assert_eq!(pc_sample_period(0), (false, 0));
+        assert_eq!(pc_sample_period(64), (false, 0));
+        assert_eq!(pc_sample_period(1000), (false, 15));
+        // 64 MHz at 10 kHz: 6400 cycles, 6 x 1024
+        assert_eq!(pc_sample_period(6400), (true, 5));
+        assert_eq!(pc_sample_period(1_000_000), (true, 15));
+    }
+
+    #[test]
+    fn swo_invalid_arguments() {
+        assert_eq!(pr_profile_start_swo(0xdead, 0, 16_000_000, 0, 1000), -1);
+        assert_eq!(
+            pr_profile_start_swo(0xdead, 0, 1_000_000, 2_000_000, 1000),
+            -1
+        );
+        assert_eq!(
+            pr_profile_start_swo(0xdead, 0, 16_000_000, 2_000_000, 1000),
+            -1
+        );
+    }
+}
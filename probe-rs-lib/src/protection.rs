@@ -0,0 +1,550 @@
+//! Flash write protection: querying and setting the protection of flash sectors with the vendor
+//! mechanisms, so provisioning flows can lock the bootloader right after programming it.
+//!
+//! STM32F2/F4 protect sectors with the nWRP option bits, which persist. The Nordic and NXP
+//! mechanisms are registers that the firmware sets after every reset and that only a reset
+//! clears: BPROT (nRF52810/nRF52811/nRF52832), ACL (nRF52820/nRF52833/nRF52840), SPU
+//! (nRF5340/nRF91) and FPROT (Kinetis, S32K1), whose reset value is the flash configuration
+//! field at 0x408.
+
+use crate::bank::{
+    F4_FLASH_OPTCR, F4_FLASH_OPTKEYR, F4_FLASH_SR, F4_OPTCR_OPTLOCK, F4_OPTCR_OPTSTRT, OPT_KEY1,
+    OPT_KEY2, wait_not_busy,
+};
+use crate::{get_session, layout, set_error, write_c_str};
+use probe_rs::config::Target;
+use probe_rs::{Core, MemoryInterface};
+use probe_rs_target::MemoryRegion;
+use serde::Serialize;
+use std::ffi::c_char;
+use std::ops::Range;
+
+// STM32F2/F4: nWRP in bits 16..27 of FLASH_OPTCR, and of FLASH_OPTCR1 for sectors 12 to 23.
+const F4_FLASH_OPTCR1: u64 = 0x4002_3C18;
+const F4_NWRP_SHIFT: u32 = 16;
+const F4_NWRP_SECTORS: usize = 12;
+/// `SPRMOD`: the nWRP bits select PCROP (read protection) instead.
+const F4_OPTCR_SPRMOD: u32 = 1 << 31;
+const STM32_FLASH: Range<u64> = 0x0800_0000..0x0820_0000;
+
+// nRF52 BPROT: one bit per 4 KiB block in CONFIG0..CONFIG3.
+const BPROT_CONFIG: [u64; 4] = [0x4000_0600, 0x4000_0604, 0x4000_0610, 0x4000_0614];
+
+// nRF52 ACL: eight address ranges with permissions, each writable once per reset.
+const ACL_BASE: u64 = 0x4001_E800;
+const ACL_REGIONS: u64 = 8;
+const ACL_PERM_WRITE: u32 = 1 << 1;
+const ACL_PERM_READ: u32 = 1 << 2;
+
+// nRF5340/nRF91 SPU FLASHREGION[n].PERM.
+const SPU_FLASHREGION_PERM: u64 = 0x5000_3600;
+const SPU_PERM_WRITE: u32 = 1 << 1;
+const SPU_PERM_READ: u32 = 1 << 2;
+const SPU_PERM_LOCK: u32 = 1 << 8;
+
+/// FTFA/FTFC/FTFE FPROT3..FPROT0: PROT[7:0] at the lowest address, 0 = protected.
+const FTF_FPROT3: u64 = 0x4002_0010;
+
+/// Protection blocks of the nRF52 BPROT and ACL.
+const NRF52_BLOCK: u64 = 0x1000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mechanism {
+    /// STM32F2/F4 nWRP option bits, one per sector.
+    Stm32Wrp,
+    /// nRF52810/nRF52811/nRF52832 BPROT, one bit per 4 KiB block.
+    NrfBprot,
+    /// nRF52820/nRF52833/nRF52840 ACL, protecting 4 KiB pages here.
+    NrfAcl,
+    /// nRF5340/nRF91 SPU flash regions of `region_size` bytes.
+    NrfSpu { region_size: u64 },
+    /// Kinetis/S32K1 FPROT, one bit per 32nd of the program flash.
+    Fprot,
+}
+
+impl Mechanism {
+    fn for_target(name: &str) -> Option<Self> {
+        let name = name.to_ascii_uppercase();
+        let any = |prefixes: &[&str]| prefixes.iter().any(|p| name.starts_with(p));
+        if any(&["STM32F2", "STM32F4"]) {
+            Some(Mechanism::Stm32Wrp)
+        } else if any(&["NRF52810", "NRF52811", "NRF52832"]) {
+            Some(Mechanism::NrfBprot)
+        } else if any(&["NRF52820", "NRF52833", "NRF52840"]) {
+            Some(Mechanism::NrfAcl)
+        } else if any(&["NRF5340"]) {
+            Some(Mechanism::NrfSpu {
+                region_size: 0x4000,
+            })
+        } else if any(&["NRF91"]) {
+            Some(Mechanism::NrfSpu {
+                region_size: 0x8000,
+            })
+        } else if any(&["S32K1"]) || (name.starts_with("MK") && !name.starts_with("MKE")) {
+            Some(Mechanism::Fprot)
+        } else {
+            None
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Mechanism::Stm32Wrp => "stm32-wrp",
+            Mechanism::NrfBprot => "nrf-bprot",
+            Mechanism::NrfAcl => "nrf-acl",
+            Mechanism::NrfSpu { .. } => "nrf-spu",
+            Mechanism::Fprot => "fprot",
+        }
+    }
+
+    /// Highest protection level: 1 = write protected, 2 = read and write protected.
+    fn max_level(self) -> u8 {
+        match self {
+            Mechanism::NrfAcl | Mechanism::NrfSpu { .. } => 2,
+            _ => 1,
+        }
+    }
+
+    /// The ranges protected as a whole, in the order of the region indices.
+    fn regions(self, target: &Target) -> Vec<Range<u64>> {
+        if self == Mechanism::Stm32Wrp {
+            let mut sectors = layout::flash_sectors(target, &STM32_FLASH);
+            sectors.truncate(2 * F4_NWRP_SECTORS);
+            return sectors;
+        }
+        let Some(flash) = program_flash(target) else {
+            return Vec::new();
+        };
+        let size = match self {
+            Mechanism::NrfBprot | Mechanism::NrfAcl => NRF52_BLOCK,
+            Mechanism::NrfSpu { region_size } => region_size,
+            _ => (flash.end - flash.start) / 32,
+        };
+        let mut regions: Vec<Range<u64>> = (flash.start..flash.end)
+            .step_by(size as usize)
+            .map(|start| start..start + size)
+            .collect();
+        if self == Mechanism::NrfBprot {
+            regions.truncate(32 * BPROT_CONFIG.len());
+        }
+        regions
+    }
+}
+
+/// The on-chip flash at address 0 of the Nordic and NXP parts.
+fn program_flash(target: &Target) -> Option<Range<u64>> {
+    target
+        .memory_map
+        .iter()
+        .filter_map(MemoryRegion::as_nvm_region)
+        .find(|r| !r.is_alias && r.range.start == 0)
+        .map(|r| r.range.clone())
+}
+
+#[derive(Serialize)]
+struct ProtectedRegion {
+    index: u32,
+    start: u64,
+    end: u64,
+    level: u8,
+}
+
+#[derive(Serialize)]
+struct ProtectionInfo {
+    mechanism: Option<&'static str>,
+    /// Whether the protection survives a reset.
+    persistent: bool,
+    max_level: u8,
+    regions: Vec<ProtectedRegion>,
+}
+
+/// The level of each of `regions` given the `(address, size, perm)` of the ACL entries.
+fn acl_levels(entries: &[(u64, u64, u32)], regions: &[Range<u64>]) -> Vec<u8> {
+    regions
+        .iter()
+        .map(|region| {
+            entries
+                .iter()
+                .filter(|(address, size, _)| {
+                    *size != 0 && *address < region.end && region.start < address + size
+                })
+                .map(|(_, _, perm)| match perm {
+                    p if p & ACL_PERM_READ != 0 => 2,
+                    p if p & ACL_PERM_WRITE != 0 => 1,
+                    _ => 0,
+                })
+                .max()
+                .unwrap_or(0)
+        })
+        .collect()
+}
+
+fn acl_entries(core: &mut Core<'_>) -> Result<Vec<(u64, u64, u32)>, probe_rs::Error> {
+    (0..ACL_REGIONS)
+        .map(|n| {
+            let base = ACL_BASE + n * 0x10;
+            Ok((
+                core.read_word_32(base)? as u64,
+                core.read_word_32(base + 4)? as u64,
+                core.read_word_32(base + 8)?,
+            ))
+        })
+        .collect()
+}
+
+fn spu_level(perm: u32) -> u8 {
+    if perm & SPU_PERM_READ == 0 {
+        2
+    } else if perm & SPU_PERM_WRITE == 0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// The SPU permissions `perm` changed to protection `level`.
+fn spu_perm(perm: u32, level: u8) -> u32 {
+    let access = SPU_PERM_READ | SPU_PERM_WRITE;
+    match level {
+        0 => perm | access,
+        1 => (perm | SPU_PERM_READ) & !SPU_PERM_WRITE,
+        _ => perm & !access,
+    }
+}
+
+fn read_fprot(core: &mut Core<'_>) -> Result<u32, probe_rs::Error> {
+    let mut prot = [0u8; 4];
+    core.read_8(FTF_FPROT3, &mut prot)?;
+    Ok(u32::from_le_bytes(prot))
+}
+
+fn read_levels(
+    core: &mut Core<'_>,
+    mechanism: Mechanism,
+    regions: &[Range<u64>],
+) -> Result<Vec<u8>, probe_rs::Error> {
+    let count = regions.len();
+    let bit_levels = |bits: u64, protected_if: bool| {
+        (0..count)
+            .map(|i| u8::from((bits >> i) & 1 == u64::from(protected_if)))
+            .collect()
+    };
+    Ok(match mechanism {
+        Mechanism::Stm32Wrp => {
+            let optcr = core.read_word_32(F4_FLASH_OPTCR)?;
+            if optcr & F4_OPTCR_SPRMOD != 0 {
+                return Err(probe_rs::Error::Other(
+                    "the sectors are in PCROP mode (SPRMOD)".to_string(),
+                ));
+            }
+            let mut nwrp = u64::from((optcr >> F4_NWRP_SHIFT) & 0xfff);
+            if count > F4_NWRP_SECTORS {
+                let optcr1 = core.read_word_32(F4_FLASH_OPTCR1)?;
+                nwrp |= u64::from((optcr1 >> F4_NWRP_SHIFT) & 0xfff) << F4_NWRP_SECTORS;
+            }
+            bit_levels(nwrp, false)
+        }
+        Mechanism::NrfBprot => {
+            let mut bits = 0u128;
+            for (i, config) in BPROT_CONFIG.iter().enumerate() {
+                bits |= u128::from(core.read_word_32(*config)?) << (32 * i);
+            }
+            (0..count).map(|i| ((bits >> i) & 1) as u8).collect()
+        }
+        Mechanism::NrfAcl => acl_levels(&acl_entries(core)?, regions),
+        Mechanism::NrfSpu { .. } => (0..count as u64)
+            .map(|n| Ok(spu_level(core.read_word_32(SPU_FLASHREGION_PERM + 4 * n)?)))
+            .collect::<Result<_, probe_rs::Error>>()?,
+        Mechanism::Fprot => bit_levels(u64::from(read_fprot(core)?), false),
+    })
+}
+
+fn until_reset(what: &str) -> probe_rs::Error {
+    probe_rs::Error::Other(format!("{} stays protected until the next reset", what))
+}
+
+fn set_level(
+    core: &mut Core<'_>,
+    mechanism: Mechanism,
+    regions: &[Range<u64>],
+    index: usize,
+    level: u8,
+) -> Result<(), probe_rs::Error> {
+    let current = read_levels(core, mechanism, regions)?[index];
+    match mechanism {
+        Mechanism::Stm32Wrp => {
+            let (register, bit) = if index < F4_NWRP_SECTORS {
+                (F4_FLASH_OPTCR, index)
+            } else {
+                (F4_FLASH_OPTCR1, index - F4_NWRP_SECTORS)
+            };
+            if core.read_word_32(F4_FLASH_OPTCR)? & F4_OPTCR_OPTLOCK != 0 {
+                core.write_word_32(F4_FLASH_OPTKEYR, OPT_KEY1)?;
+                core.write_word_32(F4_FLASH_OPTKEYR, OPT_KEY2)?;
+            }
+            wait_not_busy(core, F4_FLASH_SR)?;
+            let nwrp = 1 << (F4_NWRP_SHIFT as usize + bit);
+            let value = core.read_word_32(register)?;
+            let value = if level == 0 {
+                value | nwrp
+            } else {
+                value & !nwrp
+            };
+            core.write_word_32(register, value)?;
+            let optcr = core.read_word_32(F4_FLASH_OPTCR)?;
+            core.write_word_32(F4_FLASH_OPTCR, optcr | F4_OPTCR_OPTSTRT)?;
+            wait_not_busy(core, F4_FLASH_SR)?;
+            let optcr = core.read_word_32(F4_FLASH_OPTCR)?;
+            core.write_word_32(F4_FLASH_OPTCR, optcr | F4_OPTCR_OPTLOCK)?;
+        }
+        Mechanism::NrfBprot => {
+            if level < current {
+                return Err(until_reset("a BPROT block"));
+            }
+            let config = BPROT_CONFIG[index / 32];
+            // Writing 0 bits has no effect, so only the block's bit is written.
+            core.write_word_32(config, 1 << (index % 32))?;
+        }
+        Mechanism::NrfAcl => {
+            if level < current {
+                return Err(until_reset("a page in an ACL region"));
+            }
+            if level == current {
+                return Ok(());
+            }
+            let entries = acl_entries(core)?;
+            let Some(free) = entries.iter().position(|(_, size, _)| *size == 0) else {
+                return Err(probe_rs::Error::Other(format!(
+                    "all {} ACL regions are in use",
+                    ACL_REGIONS
+                )));
+            };
+            let region = &regions[index];
+            let base = ACL_BASE + free as u64 * 0x10;
+            let perm = if level == 2 {
+                ACL_PERM_READ | ACL_PERM_WRITE
+            } else {
+                ACL_PERM_WRITE
+            };
+            core.write_word_32(base, region.start as u32)?;
+            core.write_word_32(base + 8, perm)?;
+            // Writing SIZE enables the region.
+            core.write_word_32(base + 4, (region.end - region.start) as u32)?;
+        }
+        Mechanism::NrfSpu { .. } => {
+            let address = SPU_FLASHREGION_PERM + 4 * index as u64;
+            let perm = core.read_word_32(address)?;
+            if perm & SPU_PERM_LOCK != 0 {
+                return Err(probe_rs::Error::Other(
+                    "the SPU flash region is locked until the next reset".to_string(),
+                ));
+            }
+            core.write_word_32(address, spu_perm(perm, level))?;
+        }
+        Mechanism::Fprot => {
+            if level < current {
+                return Err(until_reset(
+                    "an FPROT segment (its reset value is the flash configuration field at 0x408)",
+                ));
+            }
+            let prot = read_fprot(core)? & !(1 << index);
+            core.write_word_8(FTF_FPROT3 + index as u64 / 8, prot.to_le_bytes()[index / 8])?;
+        }
+    }
+    Ok(())
+}
+
+/// Describe the flash write protection of the session's target as JSON:
+/// `{"mechanism", "persistent", "max_level", "regions": [{"index", "start", "end", "level"}]}`.
+///
+/// `mechanism` is `stm32-wrp`, `nrf-bprot`, `nrf-acl`, `nrf-spu`, `fprot`, or null with no
+/// regions if protection is not supported for the chip. A region is the unit protected as a
+/// whole (a sector, block or segment); `level` is 0 unprotected, 1 write protected, 2 read and
+/// write protected.
+///
+/// Returns the required size including NUL, or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_get_protection(
+    session: u64,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return 0;
+    };
+    let mut lock = sess.lock().unwrap();
+    let mechanism = Mechanism::for_target(&lock.target().name);
+    let mut info = ProtectionInfo {
+        mechanism: mechanism.map(Mechanism::name),
+        persistent: mechanism == Some(Mechanism::Stm32Wrp),
+        max_level: mechanism.map_or(0, Mechanism::max_level),
+        regions: Vec::new(),
+    };
+    if let Some(mechanism) = mechanism {
+        let regions = mechanism.regions(lock.target());
+        let res = lock
+            .core(0)
+            .and_then(|mut core| read_levels(&mut core, mechanism, &regions));
+        match res {
+            Ok(levels) => {
+                info.regions = regions
+                    .into_iter()
+                    .zip(levels)
+                    .enumerate()
+                    .map(|(index, (range, level))| ProtectedRegion {
+                        index: index as u32,
+                        start: range.start,
+                        end: range.end,
+                        level,
+                    })
+                    .collect();
+            }
+            Err(e) => {
+                set_error(format!("read protection error: {}", e));
+                return 0;
+            }
+        }
+    }
+    match serde_json::to_string(&info) {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e.to_string());
+            0
+        }
+    }
+}
+
+/// Set the protection of flash region `region` (an index of `pr_flash_get_protection`) to
+/// `level`: 0 unprotected, 1 write protected, 2 read and write protected (nRF ACL/SPU only).
+///
+/// STM32 option bytes are programmed and apply after the next reset. The other mechanisms apply
+/// at once and until the next reset, and can become stricter only: lowering the level fails
+/// (BPROT, ACL, FPROT), as does changing an SPU region that is locked.
+///
+/// Returns 0 on success, -1 on invalid handle, region or level, -2 on target error, -3 if
+/// protection (or the level) is not supported for this chip.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_set_protection(session: u64, region: u32, level: u32) -> i32 {
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let Some(mechanism) = Mechanism::for_target(&lock.target().name) else {
+        set_error(format!(
+            "flash protection is not supported for {}",
+            lock.target().name
+        ));
+        return -3;
+    };
+    if level > 2 {
+        set_error(format!("invalid protection level {}", level));
+        return -1;
+    }
+    if level > u32::from(mechanism.max_level()) {
+        set_error(format!(
+            "protection level {} is not supported by {}",
+            level,
+            mechanism.name()
+        ));
+        return -3;
+    }
+    let regions = mechanism.regions(lock.target());
+    let index = region as usize;
+    if index >= regions.len() {
+        set_error(format!(
+            "invalid region {}, the chip has {}",
+            region,
+            regions.len()
+        ));
+        return -1;
+    }
+    let res = lock
+        .core(0)
+        .and_then(|mut core| set_level(&mut core, mechanism, &regions, index, level as u8));
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(format!("set protection error: {}", e));
+            -2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry;
+
+    #[test]
+    fn mechanisms_by_name() {
+        assert_eq!(
+            Mechanism::for_target("STM32F407VGTx"),
+            Some(Mechanism::Stm32Wrp)
+        );
+        assert_eq!(
+            Mechanism::for_target("nRF52832_xxAA"),
+            Some(Mechanism::NrfBprot)
+        );
+        assert_eq!(
+            Mechanism::for_target("nRF52840_xxAA"),
+            Some(Mechanism::NrfAcl)
+        );
+        assert_eq!(
+            Mechanism::for_target("nRF9160_xxAA"),
+            Some(Mechanism::NrfSpu {
+                region_size: 0x8000
+            })
+        );
+        assert_eq!(
+            Mechanism::for_target("S32K144HAxxxLLx"),
+            Some(Mechanism::Fprot)
+        );
+        assert_eq!(Mechanism::for_target("MKE02Z64VLD2"), None);
+        assert_eq!(Mechanism::for_target("STM32L476RG"), None);
+    }
+
+    #[test]
+    fn regions_from_memory_map() {
+        let stm32 = registry().get_target_by_name("STM32F407VGTx").unwrap();
+        let sectors = Mechanism::Stm32Wrp.regions(&stm32);
+        assert_eq!(sectors.len(), 12);
+        assert_eq!(sectors[4], 0x0801_0000..0x0802_0000);
+
+        let s32k = registry().get_target_by_name("S32K144HAxxxLLx").unwrap();
+        let segments = Mechanism::Fprot.regions(&s32k);
+        assert_eq!(segments.len(), 32);
+        assert_eq!(segments[1], 0x4000..0x8000);
+
+        let nrf = registry().get_target_by_name("nRF52840_xxAA").unwrap();
+        assert_eq!(Mechanism::NrfAcl.regions(&nrf).len(), 256);
+    }
+
+    #[test]
+    fn acl_entries_cover_pages() {
+        let regions = [0..0x1000, 0x1000..0x2000, 0x2000..0x3000, 0x3000..0x4000];
+        let entries = [
+            (0x0, 0x2000, ACL_PERM_WRITE),
+            (0x1000, 0x1000, ACL_PERM_READ | ACL_PERM_WRITE),
+            (0x3000, 0, ACL_PERM_WRITE),
+        ];
+        assert_eq!(acl_levels(&entries, &regions), vec![1, 2, 0, 0]);
+    }
+
+    #[test]
+    fn spu_permissions_follow_level() {
+        let all = 0x17;
+        assert_eq!(spu_level(all), 0);
+        assert_eq!(spu_perm(all, 1), 0x15);
+        assert_eq!(spu_level(spu_perm(all, 1)), 1);
+        assert_eq!(spu_level(spu_perm(all, 2)), 2);
+        assert_eq!(spu_perm(spu_perm(all, 2), 0), all);
+    }
+
+    #[test]
+    fn invalid_session_is_rejected() {
+        assert_eq!(pr_flash_set_protection(0xdead, 0, 1), -1);
+        assert_eq!(pr_flash_get_protection(0xdead, std::ptr::null_mut(), 0), 0);
+    }
+}
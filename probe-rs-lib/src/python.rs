@@ -0,0 +1,281 @@
+//! Python module `probe_rs_lib` (feature `python`), the session, flash and memory calls of
+//! [`crate::api`] for factory automation scripts that would otherwise declare the C functions
+//! through ctypes. Sessions are the handles of the C API.
+//!
+//! Errors raise `probe_rs_lib.ProbeRsError`; the GIL is released while talking to the probe.
+
+use crate::api::{self, DetachMode, FlashError, WireProtocol};
+use crate::{download_options, progress_handler};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::ffi::CStr;
+use std::time::Duration;
+
+create_exception!(probe_rs_lib, ProbeRsError, PyException);
+
+fn py_err(msg: impl ToString) -> PyErr {
+    ProbeRsError::new_err(msg.to_string())
+}
+
+fn protocol(name: Option<&str>) -> PyResult<Option<WireProtocol>> {
+    name.map(|n| n.parse().map_err(py_err)).transpose()
+}
+
+fn detach_mode(name: &str) -> PyResult<DetachMode> {
+    match name {
+        "run" => Ok(DetachMode::Run),
+        "halt" => Ok(DetachMode::Halt),
+        "reset" => Ok(DetachMode::Reset),
+        _ => Err(py_err(format!("invalid detach mode {}", name))),
+    }
+}
+
+/// Flash options with a Python `progress(op, percent, status, eta_ms)` callable, if given, in
+/// place of the C progress callbacks.
+fn flash_options(
+    session: Option<u64>,
+    verify: bool,
+    preverify: bool,
+    chip_erase: bool,
+    progress: Option<Py<PyAny>>,
+) -> api::DownloadOptions<'static> {
+    let mut opts = download_options(session, verify as i32, preverify as i32, chip_erase as i32);
+    if let Some(cb) = progress {
+        opts.progress = progress_handler(move |op, pct, status, eta| {
+            let status = unsafe { CStr::from_ptr(status) }
+                .to_string_lossy()
+                .into_owned();
+            Python::attach(|py| {
+                // A failing callback must not abort the flash; Python prints the exception.
+                if let Err(e) = cb.call1(py, (op, pct, status, eta)) {
+                    e.print(py);
+                }
+            });
+        });
+    }
+    opts
+}
+
+fn flash_result(result: Result<(), FlashError>) -> PyResult<()> {
+    result.map_err(py_err)
+}
+
+/// Open a session on `chip` through the first probe of the programmer type set with
+/// `pr_set_programmer_type_code`, or any probe. `protocol` is "swd" or "jtag".
+#[pyfunction]
+#[pyo3(signature = (chip, speed_khz=None, protocol=None))]
+fn open_session_auto(
+    py: Python<'_>,
+    chip: &str,
+    speed_khz: Option<u32>,
+    protocol: Option<&str>,
+) -> PyResult<u64> {
+    let protocol = self::protocol(protocol)?;
+    py.detach(|| api::open_session_auto(chip, speed_khz, protocol))
+        .map_err(py_err)
+}
+
+/// Open a session on `chip` through the probe `selector` ("VID:PID[:SN]" or "tcp:host:port").
+#[pyfunction]
+#[pyo3(signature = (selector, chip, speed_khz=None, protocol=None))]
+fn open_session_with_probe(
+    py: Python<'_>,
+    selector: &str,
+    chip: &str,
+    speed_khz: Option<u32>,
+    protocol: Option<&str>,
+) -> PyResult<u64> {
+    let protocol = self::protocol(protocol)?;
+    py.detach(|| api::open_session_with_probe(selector, chip, speed_khz, protocol))
+        .map_err(py_err)
+}
+
+/// Close a session; `detach` is "run", "halt" or "reset".
+#[pyfunction]
+#[pyo3(signature = (session, detach="run"))]
+fn close_session(py: Python<'_>, session: u64, detach: &str) -> PyResult<()> {
+    let mode = detach_mode(detach)?;
+    py.detach(|| api::close_session(session, mode))
+        .map_err(py_err)
+}
+
+/// Handles of the open sessions, oldest first.
+#[pyfunction]
+fn sessions() -> Vec<u64> {
+    api::session_handles()
+}
+
+/// Attach to `chip`, flash `path` (format from the extension; `.bin` needs `base_address`) and
+/// detach.
+#[pyfunction]
+#[pyo3(signature = (
+    chip, path, base_address=None, skip=0, verify=false, preverify=false, chip_erase=false,
+    speed_khz=None, protocol=None, progress=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn flash(
+    py: Python<'_>,
+    chip: &str,
+    path: &str,
+    base_address: Option<u64>,
+    skip: u32,
+    verify: bool,
+    preverify: bool,
+    chip_erase: bool,
+    speed_khz: Option<u32>,
+    protocol: Option<&str>,
+    progress: Option<Py<PyAny>>,
+) -> PyResult<()> {
+    let protocol = self::protocol(protocol)?;
+    let format = api::detect_format(path, base_address, skip).map_err(py_err)?;
+    flash_result(py.detach(|| {
+        let opts = flash_options(None, verify, preverify, chip_erase, progress);
+        api::flash(chip, path, format, opts, speed_khz, protocol)
+    }))
+}
+
+/// Flash `path` through the open `session` without attaching again.
+#[pyfunction]
+#[pyo3(signature = (
+    session, path, base_address=None, skip=0, verify=false, preverify=false, chip_erase=false,
+    progress=None
+))]
+#[allow(clippy::too_many_arguments)]
+fn flash_session(
+    py: Python<'_>,
+    session: u64,
+    path: &str,
+    base_address: Option<u64>,
+    skip: u32,
+    verify: bool,
+    preverify: bool,
+    chip_erase: bool,
+    progress: Option<Py<PyAny>>,
+) -> PyResult<()> {
+    let format = api::detect_format(path, base_address, skip).map_err(py_err)?;
+    flash_result(py.detach(|| {
+        let opts = flash_options(Some(session), verify, preverify, chip_erase, progress);
+        api::flash_session(session, path, format, opts)
+    }))
+}
+
+/// Read `length` bytes at `address`.
+#[pyfunction]
+fn read_memory<'py>(
+    py: Python<'py>,
+    session: u64,
+    core: usize,
+    address: u64,
+    length: usize,
+) -> PyResult<Bound<'py, PyBytes>> {
+    let data = py
+        .detach(|| api::read_memory(session, core, address, length))
+        .map_err(py_err)?;
+    Ok(PyBytes::new(py, &data))
+}
+
+/// Write `data` at `address`.
+#[pyfunction]
+fn write_memory(
+    py: Python<'_>,
+    session: u64,
+    core: usize,
+    address: u64,
+    data: &[u8],
+) -> PyResult<()> {
+    py.detach(|| api::write_memory(session, core, address, data))
+        .map_err(py_err)
+}
+
+/// Read `count` 32-bit words at `address`.
+#[pyfunction]
+fn read_32(
+    py: Python<'_>,
+    session: u64,
+    core: usize,
+    address: u64,
+    count: usize,
+) -> PyResult<Vec<u32>> {
+    py.detach(|| api::read_32(session, core, address, count))
+        .map_err(py_err)
+}
+
+/// Write 32-bit `words` at `address`.
+#[pyfunction]
+fn write_32(
+    py: Python<'_>,
+    session: u64,
+    core: usize,
+    address: u64,
+    words: Vec<u32>,
+) -> PyResult<()> {
+    py.detach(|| api::write_32(session, core, address, &words))
+        .map_err(py_err)
+}
+
+/// Halt `core`, waiting up to `timeout_ms`.
+#[pyfunction]
+#[pyo3(signature = (session, core, timeout_ms=100))]
+fn halt(py: Python<'_>, session: u64, core: usize, timeout_ms: u64) -> PyResult<()> {
+    py.detach(|| api::halt_core(session, core, Duration::from_millis(timeout_ms)))
+        .map_err(py_err)
+}
+
+/// Resume `core`, stepping over a software breakpoint at the current address.
+#[pyfunction]
+fn run(py: Python<'_>, session: u64, core: usize) -> PyResult<()> {
+    py.detach(|| api::run_core(session, core)).map_err(py_err)
+}
+
+/// Reset `core` and let it run.
+#[pyfunction]
+fn reset(py: Python<'_>, session: u64, core: usize) -> PyResult<()> {
+    py.detach(|| api::reset_core(session, core)).map_err(py_err)
+}
+
+/// Manufacturers of the built-in chip database.
+#[pyfunction]
+fn chip_manufacturers() -> Vec<&'static str> {
+    api::chip_manufacturers()
+}
+
+/// Chips of `manufacturer`.
+#[pyfunction]
+fn chip_models(manufacturer: &str) -> PyResult<Vec<String>> {
+    let index = api::chip_manufacturers()
+        .iter()
+        .position(|m| *m == manufacturer)
+        .ok_or_else(|| py_err(format!("unknown manufacturer {}", manufacturer)))?;
+    Ok(api::chip_models(index).unwrap_or_default().to_vec())
+}
+
+/// Manufacturer of `chip`, or None if it is not in the database.
+#[pyfunction]
+fn chip_manufacturer(chip: &str) -> Option<&'static str> {
+    api::chip_manufacturer(chip)
+}
+
+#[pymodule]
+fn probe_rs_lib(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("ProbeRsError", m.py().get_type::<ProbeRsError>())?;
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_function(wrap_pyfunction!(open_session_auto, m)?)?;
+    m.add_function(wrap_pyfunction!(open_session_with_probe, m)?)?;
+    m.add_function(wrap_pyfunction!(close_session, m)?)?;
+    m.add_function(wrap_pyfunction!(sessions, m)?)?;
+    m.add_function(wrap_pyfunction!(flash, m)?)?;
+    m.add_function(wrap_pyfunction!(flash_session, m)?)?;
+    m.add_function(wrap_pyfunction!(read_memory, m)?)?;
+    m.add_function(wrap_pyfunction!(write_memory, m)?)?;
+    m.add_function(wrap_pyfunction!(read_32, m)?)?;
+    m.add_function(wrap_pyfunction!(write_32, m)?)?;
+    m.add_function(wrap_pyfunction!(halt, m)?)?;
+    m.add_function(wrap_pyfunction!(run, m)?)?;
+    m.add_function(wrap_pyfunction!(reset, m)?)?;
+    m.add_function(wrap_pyfunction!(chip_manufacturers, m)?)?;
+    m.add_function(wrap_pyfunction!(chip_models, m)?)?;
+    m.add_function(wrap_pyfunction!(chip_manufacturer, m)?)?;
+    Ok(())
+}
@@ -0,0 +1,303 @@
+//! RAM integrity tests for board bring-up (external SDRAM, PSRAM, on-chip SRAM), using block
+//! writes and reads through the debug port.
+
+use crate::{get_session, set_error, timeouts, write_c_str};
+use probe_rs::{Core, MemoryInterface, Session};
+use probe_rs_target::MemoryRegion;
+use serde::Serialize;
+use std::ffi::c_char;
+use std::ops::Range;
+
+/// Words per block transfer.
+const CHUNK_WORDS: usize = 1024;
+
+const MODE_WALKING_ONES: u32 = 1 << 0;
+const MODE_ADDRESS: u32 = 1 << 1;
+
+#[derive(Serialize, Debug, PartialEq)]
+struct Mismatch {
+    address: u64,
+    expected: u32,
+    actual: u32,
+}
+
+#[derive(Serialize, Debug)]
+struct TestResult {
+    name: &'static str,
+    errors: u64,
+    first_error: Option<Mismatch>,
+}
+
+#[derive(Serialize, Debug)]
+struct RamTestReport {
+    start: u64,
+    end: u64,
+    passed: bool,
+    tests: Vec<TestResult>,
+}
+
+/// Block access to 32-bit words of target memory.
+trait WordMemory {
+    type Error;
+    fn write_words(&mut self, address: u64, data: &[u32]) -> Result<(), Self::Error>;
+    fn read_words(&mut self, address: u64, data: &mut [u32]) -> Result<(), Self::Error>;
+}
+
+impl WordMemory for Core<'_> {
+    type Error = probe_rs::Error;
+
+    fn write_words(&mut self, address: u64, data: &[u32]) -> Result<(), Self::Error> {
+        self.write_32(address, data)
+    }
+
+    fn read_words(&mut self, address: u64, data: &mut [u32]) -> Result<(), Self::Error> {
+        self.read_32(address, data)
+    }
+}
+
+/// Write `pattern(word index)` over the whole range, then read it back and count mismatches.
+fn run_pass<M: WordMemory>(
+    mem: &mut M,
+    range: &Range<u64>,
+    name: &'static str,
+    pattern: impl Fn(u64) -> u32,
+) -> Result<TestResult, M::Error> {
+    let words = (range.end - range.start) / 4;
+    let chunks = (0..words).step_by(CHUNK_WORDS).map(|first| {
+        let count = (words - first).min(CHUNK_WORDS as u64);
+        (first, count as usize)
+    });
+
+    let mut buf = vec![0u32; CHUNK_WORDS];
+    for (first, count) in chunks.clone() {
+        for (i, w) in buf[..count].iter_mut().enumerate() {
+            *w = pattern(first + i as u64);
+        }
+        mem.write_words(range.start + first * 4, &buf[..count])?;
+    }
+
+    let mut result = TestResult {
+        name,
+        errors: 0,
+        first_error: None,
+    };
+    for (first, count) in chunks {
+        mem.read_words(range.start + first * 4, &mut buf[..count])?;
+        for (i, &actual) in buf[..count].iter().enumerate() {
+            let index = first + i as u64;
+            let expected = pattern(index);
+            if actual != expected {
+                result.errors += 1;
+                result.first_error.get_or_insert(Mismatch {
+                    address: range.start + index * 4,
+                    expected,
+                    actual,
+                });
+            }
+        }
+    }
+    Ok(result)
+}
+
+fn run_tests<M: WordMemory>(
+    mem: &mut M,
+    range: Range<u64>,
+    mode: u32,
+) -> Result<RamTestReport, M::Error> {
+    let mut tests = Vec::new();
+    if mode & MODE_WALKING_ONES != 0 {
+        // Every data line is driven high on its own, once per 32 consecutive words.
+        tests.push(run_pass(mem, &range, "walking_ones", |i| 1 << (i % 32))?);
+        tests.push(run_pass(mem, &range, "walking_zeros", |i| {
+            !(1 << (i % 32))
+        })?);
+    }
+    if mode & MODE_ADDRESS != 0 {
+        // Aliased or stuck address lines make a word read back another word's address.
+        let start = range.start;
+        tests.push(run_pass(mem, &range, "address_in_address", |i| {
+            (start + i * 4) as u32
+        })?);
+        tests.push(run_pass(mem, &range, "inverted_address", |i| {
+            !((start + i * 4) as u32)
+        })?);
+    }
+    Ok(RamTestReport {
+        start: range.start,
+        end: range.end,
+        passed: tests.iter().all(|t| t.errors == 0),
+        tests,
+    })
+}
+
+fn test_range(
+    session: &Session,
+    region_index: i32,
+    start: u64,
+    size: u64,
+) -> Result<Range<u64>, String> {
+    let range = if region_index >= 0 {
+        session
+            .target()
+            .memory_map
+            .iter()
+            .filter_map(MemoryRegion::as_ram_region)
+            .nth(region_index as usize)
+            .map(|r| r.range.clone())
+            .ok_or_else(|| format!("no RAM region with index {}", region_index))?
+    } else {
+        let end = start
+            .checked_add(size)
+            .ok_or_else(|| "range overflows".to_string())?;
+        start..end
+    };
+    if range.is_empty() || range.start % 4 != 0 || range.end % 4 != 0 {
+        return Err(format!(
+            "RAM test range {:#x}..{:#x} must be non-empty and word aligned",
+            range.start, range.end
+        ));
+    }
+    Ok(range)
+}
+
+/// Test target RAM. The range is the `region_index`-th RAM region of the target's memory map,
+/// or `start`/`size` if `region_index` is negative; it must be word aligned.
+///
+/// `pattern_mode` is a bit mask: 1 = walking ones/zeros (data lines), 2 = address-in-address
+/// and its inverse (address lines); 0 runs all tests. The RAM contents are destroyed and core 0
+/// is halted first so firmware does not use the memory during the test.
+///
+/// The report is JSON: `{"start", "end", "passed", "tests": [{"name", "errors", "first_error"}]}`
+/// with `first_error` as `{"address", "expected", "actual"}` or null. `out_report_len` is the
+/// size of `out_report`; the report is cut off if it is too small.
+///
+/// Returns 0 if all tests passed, 1 if errors were found, -1 on invalid arguments/handle, -2 on
+/// memory access error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_ram_test(
+    session: u64,
+    region_index: i32,
+    start: u64,
+    size: u64,
+    pattern_mode: u32,
+    out_report: *mut c_char,
+    out_report_len: usize,
+) -> i32 {
+    let mode = if pattern_mode == 0 {
+        MODE_WALKING_ONES | MODE_ADDRESS
+    } else {
+        pattern_mode
+    };
+    if mode & !(MODE_WALKING_ONES | MODE_ADDRESS) != 0 {
+        set_error(format!("invalid pattern mode {:#x}", pattern_mode));
+        return -1;
+    }
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let range = match test_range(&lock, region_index, start, size) {
+        Ok(r) => r,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let mut core = match lock.core(0) {
+        Ok(core) => core,
+        Err(e) => {
+            set_error(format!("core access error: {}", e));
+            return -1;
+        }
+    };
+    if let Err(e) = core.halt(timeouts::halt_timeout()) {
+        set_error(format!("halt error: {}", e));
+        return -2;
+    }
+    let report = match run_tests(&mut core, range, mode) {
+        Ok(r) => r,
+        Err(e) => {
+            set_error(format!("ram test error: {}", e));
+            return -2;
+        }
+    };
+    if let Ok(json) = serde_json::to_string(&report) {
+        write_c_str(&json, out_report, out_report_len);
+    }
+    if report.passed { 0 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct FakeRam {
+        base: u64,
+        words: Vec<u32>,
+        /// `(word index, bits)` that always read back as 1.
+        stuck: Option<(usize, u32)>,
+    }
+
+    impl FakeRam {
+        fn new(words: usize, stuck: Option<(usize, u32)>) -> Self {
+            FakeRam {
+                base: 0x2000_0000,
+                words: vec![0; words],
+                stuck,
+            }
+        }
+
+        fn range(&self) -> Range<u64> {
+            self.base..self.base + self.words.len() as u64 * 4
+        }
+    }
+
+    impl WordMemory for FakeRam {
+        type Error = ();
+
+        fn write_words(&mut self, address: u64, data: &[u32]) -> Result<(), ()> {
+            let i = ((address - self.base) / 4) as usize;
+            self.words[i..i + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn read_words(&mut self, address: u64, data: &mut [u32]) -> Result<(), ()> {
+            let i = ((address - self.base) / 4) as usize;
+            data.copy_from_slice(&self.words[i..i + data.len()]);
+            if let Some((word, bits)) = self.stuck
+                && (i..i + data.len()).contains(&word)
+            {
+                data[word - i] |= bits;
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn good_memory_passes_all_tests() {
+        let mut ram = FakeRam::new(3000, None);
+        let range = ram.range();
+        let report = run_tests(&mut ram, range, MODE_WALKING_ONES | MODE_ADDRESS).unwrap();
+        assert!(report.passed);
+        assert_eq!(report.tests.len(), 4);
+    }
+
+    #[test]
+    fn stuck_bit_is_reported() {
+        let mut ram = FakeRam::new(2048, Some((1500, 1 << 31)));
+        let range = ram.range();
+        let report = run_tests(&mut ram, range, MODE_WALKING_ONES).unwrap();
+        assert!(!report.passed);
+        let walking = &report.tests[0];
+        assert_eq!(walking.errors, 1);
+        assert_eq!(
+            walking.first_error,
+            Some(Mismatch {
+                address: 0x2000_0000 + 1500 * 4,
+                expected: 1 << (1500 % 32),
+                actual: (1 << (1500 % 32)) | (1 << 31),
+            })
+        );
+    }
+}
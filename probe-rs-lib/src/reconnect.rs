@@ -0,0 +1,188 @@
+//! Recovering sessions after the probe dropped off the bus (USB glitch, cable hiccup): the
+//! probe is reopened and the target re-attached, keeping the session handle.
+
+use crate::{get_session, reset, set_error};
+use probe_rs::probe::{DebugProbeSelector, WireProtocol, list::Lister};
+use probe_rs::{Core, Session, SessionConfig};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+/// How a session was opened, to open it the same way again.
+#[derive(Clone)]
+pub(crate) struct OpenParams {
+    /// The probe, or `None` for the first probe found (`pr_session_open_auto`).
+    pub(crate) selector: Option<DebugProbeSelector>,
+    pub(crate) chip: String,
+    pub(crate) speed_khz: u32,
+    pub(crate) protocol: Option<WireProtocol>,
+}
+
+static OPEN_PARAMS: OnceLock<Mutex<HashMap<u64, OpenParams>>> = OnceLock::new();
+/// Reconnect attempts of memory and register calls after a transient probe error; 0 = off.
+static AUTO_RECONNECT: AtomicU32 = AtomicU32::new(0);
+/// Time for a re-enumerating probe to show up again between attempts.
+const RETRY_DELAY: Duration = Duration::from_millis(500);
+
+fn open_params() -> &'static Mutex<HashMap<u64, OpenParams>> {
+    OPEN_PARAMS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Record how the session `handle` was opened and return the handle.
+pub(crate) fn remember(handle: u64, params: OpenParams) -> u64 {
+    open_params().lock().unwrap().insert(handle, params);
+    handle
+}
+
+pub(crate) fn forget(handle: u64) {
+    open_params().lock().unwrap().remove(&handle);
+}
+
+/// The probe the session `handle` was opened on, if it was selected explicitly.
+pub(crate) fn selector(handle: u64) -> Option<DebugProbeSelector> {
+    open_params()
+        .lock()
+        .unwrap()
+        .get(&handle)
+        .and_then(|p| p.selector.clone())
+}
+
+fn reopen(params: &OpenParams) -> Result<Session, String> {
+    let speed = (params.speed_khz > 0).then_some(params.speed_khz);
+    let Some(selector) = params.selector.clone() else {
+        let config = SessionConfig {
+            permissions: Default::default(),
+            speed,
+            protocol: params.protocol,
+        };
+        return reset::auto_attach(params.chip.as_str(), config);
+    };
+    let mut probe = Lister::new()
+        .open(selector)
+        .map_err(|e| format!("open probe error: {}", e))?;
+    reset::configure(&mut probe, speed, params.protocol)?;
+    reset::attach(probe, params.chip.as_str(), Default::default())
+}
+
+/// Replace the session behind `handle` by a freshly opened one. Session settings (kept flash
+/// algorithms, detach mode, timeouts) carry over. The probe of the old session is closed first
+/// without touching the target, so it can be opened again and the old session's detach does
+/// not hit the newly attached target; if reopening fails the session stays closed.
+pub(crate) fn reconnect(handle: u64, session: &mut Session) -> Result<(), String> {
+    let params = open_params()
+        .lock()
+        .unwrap()
+        .get(&handle)
+        .cloned()
+        .ok_or_else(|| "session cannot be reconnected".to_string())?;
+    session.close_probe();
+    let mut fresh = reopen(&params)?;
+    fresh.set_keep_flash_algorithm(session.keep_flash_algorithm());
+    fresh.set_detach_mode(session.detach_mode());
+    fresh.set_halt_timeout(session.halt_timeout());
+    fresh.set_flash_sector_timeout(session.flash_sector_timeout());
+    *session = fresh;
+    Ok(())
+}
+
+/// Errors the probe may recover from by reopening it: USB transfer failures.
+fn is_transient(error: &probe_rs::Error) -> bool {
+    let mut source: Option<&dyn std::error::Error> = Some(error);
+    while let Some(e) = source {
+        if e.is::<std::io::Error>() {
+            return true;
+        }
+        source = e.source();
+    }
+    false
+}
+
+pub(crate) enum CoreOpError {
+    /// The core could not be accessed.
+    Core(probe_rs::Error),
+    /// The operation on the core failed.
+    Op(probe_rs::Error),
+}
+
+/// Run `op` on a core of the session. With auto-reconnect enabled, a transient probe error
+/// reconnects the session and retries, up to the configured number of attempts.
+pub(crate) fn with_core<T>(
+    handle: u64,
+    session: &mut Session,
+    core_index: usize,
+    mut op: impl FnMut(&mut Core<'_>) -> Result<T, probe_rs::Error>,
+) -> Result<T, CoreOpError> {
+    let mut attempts = AUTO_RECONNECT.load(Ordering::Relaxed);
+    loop {
+        let result = match session.core(core_index) {
+            Ok(mut core) => op(&mut core).map_err(CoreOpError::Op),
+            Err(e) => Err(CoreOpError::Core(e)),
+        };
+        match result {
+            Err(CoreOpError::Core(ref e) | CoreOpError::Op(ref e))
+                if attempts > 0 && is_transient(e) =>
+            {
+                attempts -= 1;
+                std::thread::sleep(RETRY_DELAY);
+                // The old probe is closed now; keep reopening while the probe is not back yet.
+                while reconnect(handle, session).is_err() && attempts > 0 {
+                    attempts -= 1;
+                    std::thread::sleep(RETRY_DELAY);
+                }
+            }
+            result => return result,
+        }
+    }
+}
+
+/// Reopen the probe of a session and re-attach to the target, keeping the handle; for
+/// recovering after the probe dropped off the bus. The target is not reset.
+///
+/// Background pollers, monitors and terminals continue on the new connection; RTT terminals
+/// may need to be reopened if the target was reset meanwhile.
+///
+/// Returns 0 on success, -1 on invalid handle, -2 if the probe could not be reopened (the old
+/// probe is closed, so target calls fail until a later reconnect succeeds).
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_reconnect(session: u64) -> i32 {
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    match reconnect(session, &mut lock) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(format!("reconnect error: {}", e));
+            -2
+        }
+    }
+}
+
+/// Reconnect automatically when a memory or register access (`pr_read_*`, `pr_write_*`,
+/// `pr_read_reg_u64`, `pr_write_reg_u64`) fails with a USB error, retrying the access up to
+/// `attempts` times; 0 disables it (default). Applies to all sessions. Returns 0.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_set_auto_reconnect(attempts: u32) -> i32 {
+    AUTO_RECONNECT.store(attempts, Ordering::Relaxed);
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use probe_rs::probe::DebugProbeError;
+
+    #[test]
+    fn usb_errors_are_transient() {
+        let usb = DebugProbeError::Usb(std::io::Error::other("pipe error"));
+        assert!(is_transient(&probe_rs::Error::Probe(usb)));
+        assert!(!is_transient(&probe_rs::Error::Other("locked".to_string())));
+    }
+
+    #[test]
+    fn unknown_sessions_cannot_reconnect() {
+        assert_eq!(pr_session_reconnect(0xdead), -1);
+    }
+}
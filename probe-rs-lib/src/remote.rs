@@ -0,0 +1,194 @@
+//! Probes attached over the network, so a CI farm can flash targets wired to a lab machine
+//! from another host:
+//! - `tcp:host:port`: a Black Magic Probe speaking its remote protocol over TCP (a BMP with
+//!   WiFi/Ethernet, or one on the lab machine exposed with a serial-to-TCP bridge).
+//! - `glasgow:tcp:host:port` or `glasgow:unix:path`: a Glasgow running the probe-rs applet.
+//!
+//! These selectors are accepted wherever `VID:PID[:SN]` is. Registered addresses are listed
+//! after the USB probes, so USB probe indices do not change.
+
+use crate::{cstr_to_string, set_error};
+use probe_rs::probe::list::Lister;
+use probe_rs::probe::{DebugProbeInfo, DebugProbeSelector};
+use std::ffi::c_char;
+use std::net::ToSocketAddrs;
+use std::sync::{Mutex, OnceLock};
+
+const BLACK_MAGIC_VID: u16 = 0x1d50;
+const BLACK_MAGIC_PID: u16 = 0x6018;
+const GLASGOW_VID: u16 = 0x20b7;
+const GLASGOW_PID: u16 = 0x9db1;
+
+/// Registered addresses and their selectors, in registration order.
+static REMOTE_PROBES: OnceLock<Mutex<Vec<(String, DebugProbeSelector)>>> = OnceLock::new();
+
+fn remote_probes_lock() -> &'static Mutex<Vec<(String, DebugProbeSelector)>> {
+    REMOTE_PROBES.get_or_init(|| Mutex::new(Vec::new()))
+}
+
+/// The selector of a network probe address, or `None` if `address` is not one.
+fn parse_remote(address: &str) -> Option<Result<DebugProbeSelector, String>> {
+    if let Some(target) = address.strip_prefix("glasgow:") {
+        if !(target.starts_with("tcp:") || target.starts_with("unix:")) {
+            return Some(Err(format!(
+                "invalid Glasgow address {}: expected glasgow:tcp:host:port or glasgow:unix:path",
+                address
+            )));
+        }
+        return Some(Ok(DebugProbeSelector {
+            vendor_id: GLASGOW_VID,
+            product_id: GLASGOW_PID,
+            serial_number: Some(target.to_string()),
+        }));
+    }
+    let host_port = address.strip_prefix("tcp:")?;
+    // The driver only lists numeric addresses, so resolve host names here.
+    let resolved = host_port
+        .to_socket_addrs()
+        .map_err(|e| format!("cannot resolve {}: {}", host_port, e))
+        .and_then(|mut addrs| {
+            addrs
+                .next()
+                .ok_or_else(|| format!("cannot resolve {}", host_port))
+        });
+    Some(resolved.map(|addr| DebugProbeSelector {
+        vendor_id: BLACK_MAGIC_VID,
+        product_id: BLACK_MAGIC_PID,
+        serial_number: Some(addr.to_string()),
+    }))
+}
+
+/// Parse a probe selector: a network probe address or `VID:PID[:SN]`.
+pub(crate) fn parse_selector(selector: &str) -> Result<DebugProbeSelector, String> {
+    match parse_remote(selector) {
+        Some(result) => result,
+        None => selector
+            .parse()
+            .map_err(|e| format!("selector parse error: {}", e)),
+    }
+}
+
+/// The USB probes followed by the registered network probes.
+pub(crate) fn list_all() -> Vec<DebugProbeInfo> {
+    let lister = Lister::new();
+    let mut probes = lister.list_all();
+    let remotes: Vec<DebugProbeSelector> = remote_probes_lock()
+        .lock()
+        .unwrap()
+        .iter()
+        .map(|(_, selector)| selector.clone())
+        .collect();
+    for selector in remotes {
+        probes.extend(lister.list(Some(&selector)));
+    }
+    probes
+}
+
+/// The probe `selector` refers to, registered or not.
+pub(crate) fn find(selector: &DebugProbeSelector) -> Option<DebugProbeInfo> {
+    list_all()
+        .into_iter()
+        .find(|info| selector.matches_probe(info))
+        .or_else(|| Lister::new().list(Some(selector)).into_iter().next())
+}
+
+/// Register a network probe (`tcp:host:port`, `glasgow:tcp:host:port`, `glasgow:unix:path`)
+/// to be listed by `pr_probe_count`, `pr_probe_list_json` and the other listing calls, and
+/// picked by `pr_session_open_auto` if it matches the programmer type. Nothing is connected
+/// until the probe is opened; host names are resolved now.
+///
+/// Returns 0 on success (also if already registered), -1 on an invalid or unresolvable
+/// address.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_add_remote(address: *const c_char) -> i32 {
+    let address = match cstr_to_string(address) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let selector = match parse_remote(&address) {
+        Some(Ok(selector)) => selector,
+        Some(Err(e)) => {
+            set_error(e);
+            return -1;
+        }
+        None => {
+            set_error(format!(
+                "not a network probe address: {} (expected tcp:host:port or glasgow:...)",
+                address
+            ));
+            return -1;
+        }
+    };
+    let mut remotes = remote_probes_lock().lock().unwrap();
+    if !remotes.iter().any(|(a, _)| *a == address) {
+        remotes.push((address, selector));
+    }
+    0
+}
+
+/// Unregister a network probe added with `pr_probe_add_remote`; open sessions on it are not
+/// affected.
+///
+/// Returns 0 on success, -1 if `address` is not registered.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_remove_remote(address: *const c_char) -> i32 {
+    let address = match cstr_to_string(address) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let mut remotes = remote_probes_lock().lock().unwrap();
+    let before = remotes.len();
+    remotes.retain(|(a, _)| *a != address);
+    if remotes.len() == before {
+        set_error(format!("network probe {} is not registered", address));
+        return -1;
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    #[test]
+    fn remote_selectors() {
+        let bmp = parse_selector("tcp:127.0.0.1:2000").unwrap();
+        assert_eq!(
+            (bmp.vendor_id, bmp.product_id),
+            (BLACK_MAGIC_VID, BLACK_MAGIC_PID)
+        );
+        assert_eq!(bmp.serial_number.as_deref(), Some("127.0.0.1:2000"));
+
+        let glasgow = parse_selector("glasgow:tcp:lab-host:2222").unwrap();
+        assert_eq!(glasgow.serial_number.as_deref(), Some("tcp:lab-host:2222"));
+        assert!(parse_selector("glasgow:lab-host").is_err());
+        assert!(parse_selector("tcp:no-port").is_err());
+
+        let usb = parse_selector("0483:374b:ABC").unwrap();
+        assert_eq!(usb.serial_number.as_deref(), Some("ABC"));
+    }
+
+    #[test]
+    fn registered_probes_are_listed() {
+        let address = CString::new("tcp:127.0.0.1:2001").unwrap();
+        assert_eq!(pr_probe_add_remote(address.as_ptr()), 0);
+        assert_eq!(pr_probe_add_remote(address.as_ptr()), 0);
+        let listed: Vec<DebugProbeInfo> = list_all()
+            .into_iter()
+            .filter(|p| p.serial_number.as_deref() == Some("127.0.0.1:2001"))
+            .collect();
+        assert_eq!(listed.len(), 1);
+        assert_eq!(pr_probe_remove_remote(address.as_ptr()), 0);
+        assert_eq!(pr_probe_remove_remote(address.as_ptr()), -1);
+
+        let usb = CString::new("0483:374b").unwrap();
+        assert_eq!(pr_probe_add_remote(usb.as_ptr()), -1);
+    }
+}
@@ -0,0 +1,190 @@
+//! Resetting the target through the probe's reset line, and attaching under reset for targets
+//! whose firmware disables the debug pins or sleeps right after boot.
+
+use crate::{
+    algo_ram, buffering, compression, cstr_to_string, debug_auth, driver_options,
+    info_matches_type, programmer_type, remote, set_error,
+};
+use probe_rs::config::TargetSelector;
+use probe_rs::probe::list::Lister;
+use probe_rs::probe::{Probe, WireProtocol};
+use probe_rs::{Permissions, Session, SessionConfig};
+use std::ffi::c_char;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+/// Reset pulse of `pr_probe_hw_reset` when none is given.
+const DEFAULT_PULSE: Duration = Duration::from_millis(100);
+
+static UNDER_RESET: AtomicBool = AtomicBool::new(false);
+
+/// `target` with the flash options that change its description: the algorithm RAM of
+/// `pr_flash_option_algorithm_ram`, the algorithm choice of `pr_flash_option_compression` and
+/// the page size of `pr_flash_option_buffering`.
+fn flash_target(target: TargetSelector) -> Result<TargetSelector, String> {
+    let target = algo_ram::apply(target)?;
+    let target = compression::apply(target)?;
+    buffering::apply(target)
+}
+
+/// Attach `probe` to `target`, under reset if enabled (`pr_set_attach_under_reset`), with the
+/// flash options of `flash_target` and after the debug authentication of
+/// `pr_set_debug_auth_callback`.
+pub(crate) fn attach(
+    probe: Probe,
+    target: impl Into<TargetSelector>,
+    permissions: Permissions,
+) -> Result<Session, String> {
+    let target = flash_target(target.into())?;
+    let probe = debug_auth::authenticate(probe, &target)?;
+    if UNDER_RESET.load(Ordering::Relaxed) {
+        probe.attach_under_reset(target, permissions)
+    } else {
+        probe.attach(target, permissions)
+    }
+    .map_err(|e| format!("attach error: {}", e))
+}
+
+/// `Session::auto_attach`, or attaching the first probe under reset or with debug authentication
+/// if enabled; only then are the driver options applied.
+pub(crate) fn auto_attach(chip: &str, config: SessionConfig) -> Result<Session, String> {
+    if !UNDER_RESET.load(Ordering::Relaxed) && !debug_auth::enabled() {
+        let target = flash_target(chip.into())?;
+        return Session::auto_attach(target, config).map_err(|e| format!("attach error: {}", e));
+    }
+    let info = remote::list_all()
+        .into_iter()
+        .next()
+        .ok_or_else(|| "no probe found".to_string())?;
+    let mut probe = info
+        .open()
+        .map_err(|e| format!("open probe error: {}", e))?;
+    configure(&mut probe, config.speed, config.protocol)?;
+    attach(probe, chip, config.permissions)
+}
+
+/// Apply the driver options, protocol and speed to a freshly opened `probe`.
+pub(crate) fn configure(
+    probe: &mut Probe,
+    speed_khz: Option<u32>,
+    protocol: Option<WireProtocol>,
+) -> Result<(), String> {
+    driver_options::apply(probe)?;
+    if let Some(p) = protocol {
+        probe
+            .select_protocol(p)
+            .map_err(|e| format!("select protocol error: {}", e))?;
+    }
+    if let Some(speed) = speed_khz {
+        probe
+            .set_speed(speed)
+            .map_err(|e| format!("set speed error: {}", e))?;
+    }
+    Ok(())
+}
+
+/// Open the probe `selector` (`VID:PID[:SN]`), or the first probe of the programmer type in
+/// effect, with the driver options applied.
+pub(crate) fn open_probe(selector: Option<&str>) -> Result<Probe, String> {
+    let mut probe = match selector {
+        Some(selector) => Lister::new().open(remote::parse_selector(selector)?),
+        None => {
+            let mut probes = remote::list_all();
+            if let Some(ty) = programmer_type() {
+                probes.retain(|p| info_matches_type(p, ty));
+            }
+            probes
+                .first()
+                .ok_or_else(|| "no matching probes found".to_string())?
+                .open()
+        }
+    }
+    .map_err(|e| format!("open probe error: {}", e))?;
+    driver_options::apply(&mut probe)?;
+    Ok(probe)
+}
+
+fn pulse_reset(probe: &mut Probe, pulse: Duration) -> Result<(), String> {
+    probe
+        .target_reset_assert()
+        .map_err(|e| format!("reset assert error: {}", e))?;
+    std::thread::sleep(pulse);
+    probe
+        .target_reset_deassert()
+        .map_err(|e| format!("reset deassert error: {}", e))
+}
+
+/// Attach under reset (1) or normally (0, default) in the sessions, flash and erase calls made
+/// afterwards, including reconnects: the reset line is held while connecting, so the cores come
+/// up halted before the firmware runs. The reset line of the probe must be wired to the target;
+/// without a programmer type or selector the first probe found is used.
+///
+/// Returns 0, or -1 if `enable` is not 0 or 1.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_set_attach_under_reset(enable: i32) -> i32 {
+    match enable {
+        0 | 1 => {
+            UNDER_RESET.store(enable == 1, Ordering::Relaxed);
+            0
+        }
+        _ => {
+            set_error(format!("invalid attach mode {}", enable));
+            -1
+        }
+    }
+}
+
+/// Pulse the reset line of a probe for `pulse_ms` milliseconds (0 = 100 ms) without attaching,
+/// e.g. to restart the target between production steps. `selector` is `VID:PID[:SN]`, or NULL
+/// for the first probe of the programmer type in effect. The probe must not be in use by a
+/// session.
+///
+/// Returns 0, -1 on invalid selector or no probe found, -2 if the probe cannot drive the reset
+/// line or the reset failed.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_hw_reset(selector: *const c_char, pulse_ms: u32) -> i32 {
+    let selector = if selector.is_null() {
+        None
+    } else {
+        match cstr_to_string(selector) {
+            Ok(s) => Some(s),
+            Err(e) => {
+                set_error(e);
+                return -1;
+            }
+        }
+    };
+    let pulse = match pulse_ms {
+        0 => DEFAULT_PULSE,
+        ms => Duration::from_millis(ms.into()),
+    };
+    let mut probe = match open_probe(selector.as_deref()) {
+        Ok(p) => p,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    match pulse_reset(&mut probe, pulse) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(e);
+            -2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn attach_mode_is_validated() {
+        assert_eq!(pr_set_attach_under_reset(2), -1);
+        assert!(!UNDER_RESET.load(Ordering::Relaxed));
+        assert_eq!(pr_set_attach_under_reset(1), 0);
+        assert!(UNDER_RESET.load(Ordering::Relaxed));
+        assert_eq!(pr_set_attach_under_reset(0), 0);
+        assert_eq!(pr_probe_hw_reset(c"not a selector".as_ptr(), 0), -1);
+    }
+}
@@ -0,0 +1,158 @@
+//! SDI print of WCH CH32 chips: the firmware prints through the debug module's data registers,
+//! which the WCH-LinkE polls and forwards to its serial port. Unlike RTT it needs no RAM for
+//! buffers on the target.
+
+use crate::serial_ports::usb_device_ports;
+use crate::{get_session, reconnect, set_error};
+use probe_rs::Session;
+use probe_rs::probe::wlink::WchLink;
+use serialport::SerialPort;
+use std::collections::HashMap;
+use std::io::Read;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+const WCH_LINK_VID: u16 = 0x1a86;
+const WCH_LINK_PID: u16 = 0x8010;
+/// The WCH-LinkE forwards SDI print at a fixed 115200 baud.
+const BAUD_RATE: u32 = 115_200;
+const READ_TIMEOUT: Duration = Duration::from_millis(10);
+
+struct SdiPrint {
+    session: u64,
+    port: Box<dyn SerialPort>,
+}
+
+static SDI_PRINTS: OnceLock<Mutex<HashMap<u64, SdiPrint>>> = OnceLock::new();
+static NEXT_SDI_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+fn sdi_prints() -> &'static Mutex<HashMap<u64, SdiPrint>> {
+    SDI_PRINTS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn set_sdi_print(session: &mut Session, enabled: bool) -> Result<(), String> {
+    let wlink = session
+        .probe()
+        .and_then(|p| p.try_into::<WchLink>())
+        .ok_or_else(|| "SDI print requires a WCH-Link probe".to_string())?;
+    wlink
+        .set_sdi_print(enabled)
+        .map_err(|e| format!("SDI print error: {}", e))
+}
+
+/// Open the serial port of the session's WCH-Link.
+fn open_port(session: u64) -> Result<Box<dyn SerialPort>, String> {
+    let serial = reconnect::selector(session).and_then(|s| s.serial_number);
+    let ports = usb_device_ports(WCH_LINK_VID, WCH_LINK_PID, serial.as_deref())?;
+    let port = ports
+        .first()
+        .ok_or_else(|| "WCH-Link serial port not found".to_string())?;
+    serialport::new(&port.port, BAUD_RATE)
+        .timeout(READ_TIMEOUT)
+        .open()
+        .map_err(|e| format!("open serial port {} error: {}", port.port, e))
+}
+
+/// Disable SDI print and close the serial ports of a session, e.g. before it is closed.
+pub(crate) fn stop_for_session(session: u64, sess: &mut Session) {
+    let stopped = {
+        let mut map = sdi_prints().lock().unwrap();
+        let before = map.len();
+        map.retain(|_, p| p.session != session);
+        map.len() < before
+    };
+    if stopped {
+        let _ = set_sdi_print(sess, false);
+    }
+}
+
+/// Enable SDI print on the WCH-LinkE of a session and open the probe's serial port to collect
+/// the output; read it with `pr_sdi_print_read`.
+///
+/// The firmware must print through the SDI (e.g. `SDI_Printf_Enable()` of the WCH SDK).
+/// Requires a WCH-LinkE with firmware 2.10 or newer. The serial port must not be open in
+/// another program.
+///
+/// Returns a non-zero handle, or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_sdi_print_start(session: u64) -> u64 {
+    let sess = match get_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let mut lock = sess.lock().unwrap();
+    if let Err(e) = set_sdi_print(&mut lock, true) {
+        set_error(e);
+        return 0;
+    }
+    let port = match open_port(session) {
+        Ok(port) => port,
+        Err(e) => {
+            let _ = set_sdi_print(&mut lock, false);
+            set_error(e);
+            return 0;
+        }
+    };
+    let handle = NEXT_SDI_HANDLE.fetch_add(1, Ordering::Relaxed);
+    sdi_prints()
+        .lock()
+        .unwrap()
+        .insert(handle, SdiPrint { session, port });
+    handle
+}
+
+/// Read up to `out_len` bytes of SDI print output, waiting at most 10 ms for data.
+///
+/// Returns the number of bytes read (0 if nothing was printed), -1 on invalid handle, -2 on
+/// serial port error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_sdi_print_read(handle: u64, out: *mut u8, out_len: usize) -> i32 {
+    let mut map = sdi_prints().lock().unwrap();
+    let Some(sdi) = map.get_mut(&handle) else {
+        set_error("invalid SDI print handle".to_string());
+        return -1;
+    };
+    if out.is_null() || out_len == 0 {
+        return 0;
+    }
+    let buf = unsafe { std::slice::from_raw_parts_mut(out, out_len.min(i32::MAX as usize)) };
+    match sdi.port.read(buf) {
+        Ok(n) => n as i32,
+        Err(e) if e.kind() == std::io::ErrorKind::TimedOut => 0,
+        Err(e) => {
+            set_error(format!("serial port read error: {}", e));
+            -2
+        }
+    }
+}
+
+/// Disable SDI print and close the serial port. Returns 0 on success, -1 on invalid handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_sdi_print_stop(handle: u64) -> i32 {
+    let removed = sdi_prints().lock().unwrap().remove(&handle);
+    let Some(sdi) = removed else {
+        set_error("invalid SDI print handle".to_string());
+        return -1;
+    };
+    drop(sdi.port);
+    if let Ok(sess) = get_session(sdi.session) {
+        let _ = set_sdi_print(&mut sess.lock().unwrap(), false);
+    }
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn invalid_handles() {
+        assert_eq!(pr_sdi_print_start(0xdead), 0);
+        assert_eq!(pr_sdi_print_read(0xdead, std::ptr::null_mut(), 0), -1);
+        assert_eq!(pr_sdi_print_stop(0xdead), -1);
+    }
+}
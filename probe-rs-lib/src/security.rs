@@ -0,0 +1,462 @@
+//! Device security state: the readout protection level or debug access protection of a chip,
+//! and closing the device as the last step of a factory flow. Transitions that erase the
+//! flash, lock the debugger out or cannot be undone need a confirmation flag each.
+
+use crate::bank::{
+    F4_FLASH_OPTCR, F4_FLASH_OPTKEYR, F4_FLASH_SR, F4_OPTCR_OPTLOCK, F4_OPTCR_OPTSTRT, FLASH_KEY1,
+    FLASH_KEY2, L4_CR_LOCK, L4_CR_OBL_LAUNCH, L4_CR_OPTLOCK, L4_CR_OPTSTRT, L4_FLASH_CR,
+    L4_FLASH_KEYR, L4_FLASH_OPTKEYR, L4_FLASH_OPTR, L4_FLASH_SR, OPT_KEY1, OPT_KEY2, wait_not_busy,
+    wait_not_busy_for,
+};
+use crate::{cstr_to_string, get_session, set_error, write_c_str};
+use probe_rs::{Core, MemoryInterface};
+use serde::Serialize;
+use std::ffi::c_char;
+use std::time::{Duration, Instant};
+
+/// `PR_SECURITY_CONFIRM_ERASE`: the transition erases the flash.
+const CONFIRM_ERASE: u32 = 1 << 0;
+/// `PR_SECURITY_CONFIRM_LOCKOUT`: the debugger loses access until the flash is erased.
+const CONFIRM_LOCKOUT: u32 = 1 << 1;
+/// `PR_SECURITY_CONFIRM_PERMANENT`: the transition can never be undone.
+const CONFIRM_PERMANENT: u32 = 1 << 2;
+
+/// RDP option byte values; any other value is level 1.
+const RDP_LEVEL0: u32 = 0xAA;
+const RDP_LEVEL2: u32 = 0xCC;
+const RDP_LEVEL1: u32 = 0xBB;
+/// RDP in FLASH_OPTCR (F2/F4/F7) and FLASH_OPTR (L4/G0/G4).
+const F4_RDP_SHIFT: u32 = 8;
+const L4_RDP_SHIFT: u32 = 0;
+
+// nRF UICR.APPROTECT, written through the NVMC.
+const APPROTECT_HW_DISABLED: u32 = 0x5A;
+/// The only UICR.APPROTECT value that leaves the access port open on nRF5340 and nRF91x1.
+const APPROTECT_UNPROTECTED: u32 = 0x50FA_50FA;
+const APPROTECT_ENABLED: u32 = 0xFFFF_FF00;
+const NVMC_CONFIG_WEN: u32 = 1;
+
+/// Option programming that erases the flash (RDP level 1 to 0).
+const MASS_ERASE_TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Family {
+    /// STM32F2/F4/F7: RDP in FLASH_OPTCR, applied by the next reset.
+    Stm32F4,
+    /// STM32L4/L4+/G0/G4: RDP in FLASH_OPTR, applied by OBL_LAUNCH.
+    Stm32L4,
+    /// Nordic UICR.APPROTECT with the NVMC at `nvmc`, applied by the next reset. With
+    /// `erased_protected` (nRF5340, nRF91x1) an erased UICR keeps the access port protected in
+    /// hardware.
+    Nrf {
+        approtect: u64,
+        nvmc: u64,
+        erased_protected: bool,
+    },
+}
+
+impl Family {
+    fn for_target(name: &str) -> Option<Self> {
+        let name = name.to_ascii_uppercase();
+        let any = |prefixes: &[&str]| prefixes.iter().any(|p| name.starts_with(p));
+        if any(&["STM32F2", "STM32F4", "STM32F7"]) {
+            Some(Family::Stm32F4)
+        } else if any(&["STM32L4", "STM32G0", "STM32G4"]) {
+            Some(Family::Stm32L4)
+        } else if any(&["NRF52"]) {
+            Some(Family::Nrf {
+                approtect: 0x1000_1208,
+                nvmc: 0x4001_E000,
+                erased_protected: false,
+            })
+        } else if any(&["NRF5340", "NRF91"]) {
+            Some(Family::Nrf {
+                approtect: 0x00FF_8000,
+                nvmc: 0x5003_9000,
+                erased_protected: !any(&["NRF9160"]),
+            })
+        } else {
+            None
+        }
+    }
+
+    fn mechanism(self) -> &'static str {
+        match self {
+            Family::Stm32F4 | Family::Stm32L4 => "stm32-rdp",
+            Family::Nrf { .. } => "nrf-approtect",
+        }
+    }
+
+    /// The states `pr_target_set_security_state` takes.
+    fn states(self) -> &'static [&'static str] {
+        match self {
+            Family::Stm32F4 | Family::Stm32L4 => &["level0", "level1", "level2"],
+            Family::Nrf { .. } => &["open", "protected"],
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SecurityState {
+    mechanism: Option<&'static str>,
+    /// Current state, one of `states`.
+    state: Option<&'static str>,
+    /// The option byte or UICR value the state is read from.
+    raw: Option<u32>,
+    states: &'static [&'static str],
+}
+
+fn rdp_state(rdp: u32) -> &'static str {
+    match rdp {
+        RDP_LEVEL0 => "level0",
+        RDP_LEVEL2 => "level2",
+        _ => "level1",
+    }
+}
+
+/// The state of UICR.APPROTECT. On nRF52 an erased 0xFF reads as open, which holds for the
+/// revisions before 3 only: later ones protect in hardware unless it is HwDisabled (0x5A) and
+/// the firmware opens the port, and the chip revision is not read here.
+fn approtect_state(approtect: u32, erased_protected: bool) -> &'static str {
+    if erased_protected {
+        return match approtect {
+            APPROTECT_UNPROTECTED => "open",
+            _ => "protected",
+        };
+    }
+    match approtect & 0xff {
+        0xff | APPROTECT_HW_DISABLED => "open",
+        _ => "protected",
+    }
+}
+
+/// The current state and its raw value.
+fn read_state(core: &mut Core<'_>, family: Family) -> Result<(&'static str, u32), probe_rs::Error> {
+    Ok(match family {
+        Family::Stm32F4 => {
+            let rdp = (core.read_word_32(F4_FLASH_OPTCR)? >> F4_RDP_SHIFT) & 0xff;
+            (rdp_state(rdp), rdp)
+        }
+        Family::Stm32L4 => {
+            let rdp = (core.read_word_32(L4_FLASH_OPTR)? >> L4_RDP_SHIFT) & 0xff;
+            (rdp_state(rdp), rdp)
+        }
+        Family::Nrf {
+            approtect,
+            erased_protected,
+            ..
+        } => {
+            let value = core.read_word_32(approtect)?;
+            (approtect_state(value, erased_protected), value)
+        }
+    })
+}
+
+/// The confirmation flags needed to go from `from` to `to`, or an error if the library cannot
+/// make that transition.
+fn required_flags(family: Family, from: &str, to: &str) -> Result<u32, String> {
+    if from == to {
+        return Ok(0);
+    }
+    match (family, from, to) {
+        (Family::Nrf { .. }, "protected", "open") => Err(
+            "leaving APPROTECT needs an erase all through the CTRL-AP (pr_chip_erase)".to_string(),
+        ),
+        (Family::Nrf { .. }, _, _) => Ok(CONFIRM_LOCKOUT),
+        (_, "level2", _) => Err("RDP level 2 cannot be left".to_string()),
+        (_, _, "level2") => Ok(CONFIRM_PERMANENT | CONFIRM_LOCKOUT),
+        (_, _, "level0") => Ok(CONFIRM_ERASE),
+        _ => Ok(CONFIRM_LOCKOUT),
+    }
+}
+
+fn flag_names(flags: u32) -> String {
+    [
+        (CONFIRM_ERASE, "PR_SECURITY_CONFIRM_ERASE"),
+        (CONFIRM_LOCKOUT, "PR_SECURITY_CONFIRM_LOCKOUT"),
+        (CONFIRM_PERMANENT, "PR_SECURITY_CONFIRM_PERMANENT"),
+    ]
+    .iter()
+    .filter(|(flag, _)| flags & flag != 0)
+    .map(|(_, name)| *name)
+    .collect::<Vec<_>>()
+    .join(" | ")
+}
+
+fn rdp_value(state: &str) -> u32 {
+    match state {
+        "level0" => RDP_LEVEL0,
+        "level2" => RDP_LEVEL2,
+        _ => RDP_LEVEL1,
+    }
+}
+
+fn wait_nvmc_ready(core: &mut Core<'_>, nvmc: u64) -> Result<(), probe_rs::Error> {
+    let start = Instant::now();
+    while core.read_word_32(nvmc + 0x400)? & 1 == 0 {
+        if start.elapsed() > Duration::from_secs(1) {
+            return Err(probe_rs::Error::Other(
+                "timeout waiting for the NVMC".to_string(),
+            ));
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    Ok(())
+}
+
+fn write_state(core: &mut Core<'_>, family: Family, state: &str) -> Result<(), probe_rs::Error> {
+    match family {
+        Family::Stm32F4 => {
+            if core.read_word_32(F4_FLASH_OPTCR)? & F4_OPTCR_OPTLOCK != 0 {
+                core.write_word_32(F4_FLASH_OPTKEYR, OPT_KEY1)?;
+                core.write_word_32(F4_FLASH_OPTKEYR, OPT_KEY2)?;
+            }
+            wait_not_busy(core, F4_FLASH_SR)?;
+            let optcr = core.read_word_32(F4_FLASH_OPTCR)? & !(0xff << F4_RDP_SHIFT);
+            let optcr = optcr | (rdp_value(state) << F4_RDP_SHIFT);
+            core.write_word_32(F4_FLASH_OPTCR, optcr)?;
+            core.write_word_32(F4_FLASH_OPTCR, optcr | F4_OPTCR_OPTSTRT)?;
+            // Leaving level 1 erases the flash before the option bytes are written.
+            wait_not_busy_for(core, F4_FLASH_SR, MASS_ERASE_TIMEOUT)?;
+            let optcr = core.read_word_32(F4_FLASH_OPTCR)?;
+            core.write_word_32(F4_FLASH_OPTCR, optcr | F4_OPTCR_OPTLOCK)?;
+        }
+        Family::Stm32L4 => {
+            if core.read_word_32(L4_FLASH_CR)? & L4_CR_LOCK != 0 {
+                core.write_word_32(L4_FLASH_KEYR, FLASH_KEY1)?;
+                core.write_word_32(L4_FLASH_KEYR, FLASH_KEY2)?;
+            }
+            if core.read_word_32(L4_FLASH_CR)? & L4_CR_OPTLOCK != 0 {
+                core.write_word_32(L4_FLASH_OPTKEYR, OPT_KEY1)?;
+                core.write_word_32(L4_FLASH_OPTKEYR, OPT_KEY2)?;
+            }
+            wait_not_busy(core, L4_FLASH_SR)?;
+            let optr = core.read_word_32(L4_FLASH_OPTR)? & !(0xff << L4_RDP_SHIFT);
+            core.write_word_32(L4_FLASH_OPTR, optr | (rdp_value(state) << L4_RDP_SHIFT))?;
+            let cr = core.read_word_32(L4_FLASH_CR)?;
+            core.write_word_32(L4_FLASH_CR, cr | L4_CR_OPTSTRT)?;
+            wait_not_busy_for(core, L4_FLASH_SR, MASS_ERASE_TIMEOUT)?;
+            // Reloading the option bytes resets the device, so the write may not be acknowledged.
+            let cr = core.read_word_32(L4_FLASH_CR)?;
+            let _ = core.write_word_32(L4_FLASH_CR, cr | L4_CR_OBL_LAUNCH);
+        }
+        Family::Nrf {
+            approtect, nvmc, ..
+        } => {
+            let config = nvmc + 0x504;
+            core.write_word_32(config, NVMC_CONFIG_WEN)?;
+            wait_nvmc_ready(core, nvmc)?;
+            core.write_word_32(approtect, APPROTECT_ENABLED)?;
+            wait_nvmc_ready(core, nvmc)?;
+            core.write_word_32(config, 0)?;
+        }
+    }
+    Ok(())
+}
+
+/// Describe the security state of the session's target as JSON:
+/// `{"mechanism", "state", "raw", "states"}`.
+///
+/// `mechanism` is `stm32-rdp` (STM32F2/F4/F7/L4/G0/G4 readout protection; `state` `level0`,
+/// `level1` or `level2`, `raw` the RDP option byte) or `nrf-approtect` (nRF52/nRF5340/nRF91;
+/// `open` or `protected`, `raw` UICR.APPROTECT; an erased UICR is `protected` on nRF5340 and
+/// nRF91x1 but `open` on nRF52, which is only right before revision 3), or null with the other fields null or empty if
+/// the chip is not supported. `states` lists the states `pr_target_set_security_state` takes.
+///
+/// Returns the required size including NUL, or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_target_security_state(
+    session: u64,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return 0;
+    };
+    let mut lock = sess.lock().unwrap();
+    let family = Family::for_target(&lock.target().name);
+    let mut info = SecurityState {
+        mechanism: family.map(Family::mechanism),
+        state: None,
+        raw: None,
+        states: family.map_or(&[], Family::states),
+    };
+    if let Some(family) = family {
+        match lock
+            .core(0)
+            .and_then(|mut core| read_state(&mut core, family))
+        {
+            Ok((state, raw)) => {
+                info.state = Some(state);
+                info.raw = Some(raw);
+            }
+            Err(e) => {
+                set_error(format!("read security state error: {}", e));
+                return 0;
+            }
+        }
+    }
+    match serde_json::to_string(&info) {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e.to_string());
+            0
+        }
+    }
+}
+
+/// Move the session's target to security `state` (one of the `states` of
+/// `pr_target_security_state`). Each consequence must be confirmed in `confirm`:
+/// `PR_SECURITY_CONFIRM_ERASE` (1) if the transition erases the flash (RDP level 1 to 0),
+/// `PR_SECURITY_CONFIRM_LOCKOUT` (2) if the debugger loses access to the flash until it is
+/// erased (RDP level 1 or 2, APPROTECT) and `PR_SECURITY_CONFIRM_PERMANENT` (4) if it can never
+/// be undone (RDP level 2). The state applies at the next reset; on STM32L4/G0/G4 the option
+/// bytes are reloaded at once, which resets the device. The session usually has to be reopened.
+///
+/// Returns 0 on success (also if the target is in `state` already), -1 on invalid handle or
+/// state, -2 on target error, -3 if the chip or the transition is not supported, -4 if a
+/// confirmation flag is missing.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_target_set_security_state(
+    session: u64,
+    state: *const c_char,
+    confirm: u32,
+) -> i32 {
+    let state = match cstr_to_string(state) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let Some(family) = Family::for_target(&lock.target().name) else {
+        set_error(format!(
+            "security states are not supported for {}",
+            lock.target().name
+        ));
+        return -3;
+    };
+    let Some(&state) = family.states().iter().find(|s| **s == state) else {
+        set_error(format!(
+            "invalid security state {}, expected one of {}",
+            state,
+            family.states().join(", ")
+        ));
+        return -1;
+    };
+    let mut core = match lock.core(0) {
+        Ok(core) => core,
+        Err(e) => {
+            set_error(format!("set security state error: {}", e));
+            return -2;
+        }
+    };
+    let current = match read_state(&mut core, family) {
+        Ok((current, _)) => current,
+        Err(e) => {
+            set_error(format!("read security state error: {}", e));
+            return -2;
+        }
+    };
+    let required = match required_flags(family, current, state) {
+        Ok(flags) => flags,
+        Err(e) => {
+            set_error(e);
+            return -3;
+        }
+    };
+    if required == 0 {
+        return 0;
+    }
+    let missing = required & !confirm;
+    if missing != 0 {
+        set_error(format!(
+            "{} to {} must be confirmed with {}",
+            current,
+            state,
+            flag_names(missing)
+        ));
+        return -4;
+    }
+    tracing::warn!("changing the security state from {} to {}", current, state);
+    match write_state(&mut core, family, state) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(format!("set security state error: {}", e));
+            -2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn families_by_name() {
+        assert_eq!(Family::for_target("STM32F407VGTx"), Some(Family::Stm32F4));
+        assert_eq!(Family::for_target("STM32G071RBTx"), Some(Family::Stm32L4));
+        assert_eq!(
+            Family::for_target("nRF9160_xxAA"),
+            Some(Family::Nrf {
+                approtect: 0x00FF_8000,
+                nvmc: 0x5003_9000,
+                erased_protected: false
+            })
+        );
+        assert_eq!(
+            Family::for_target("nRF5340_xxAA"),
+            Some(Family::Nrf {
+                approtect: 0x00FF_8000,
+                nvmc: 0x5003_9000,
+                erased_protected: true
+            })
+        );
+        assert_eq!(Family::for_target("STM32H743ZITx"), None);
+    }
+
+    #[test]
+    fn states_from_raw_values() {
+        assert_eq!(rdp_state(0xAA), "level0");
+        assert_eq!(rdp_state(0x55), "level1");
+        assert_eq!(rdp_state(0xCC), "level2");
+        assert_eq!(approtect_state(0xFFFF_FFFF, false), "open");
+        assert_eq!(approtect_state(0xFFFF_FF5A, false), "open");
+        assert_eq!(approtect_state(0xFFFF_FF00, false), "protected");
+        assert_eq!(approtect_state(0xFFFF_FFFF, true), "protected");
+        assert_eq!(approtect_state(0x50FA_50FA, true), "open");
+    }
+
+    #[test]
+    fn transitions_need_their_flags() {
+        let stm32 = Family::Stm32L4;
+        assert_eq!(required_flags(stm32, "level1", "level1"), Ok(0));
+        assert_eq!(
+            required_flags(stm32, "level0", "level1"),
+            Ok(CONFIRM_LOCKOUT)
+        );
+        assert_eq!(required_flags(stm32, "level1", "level0"), Ok(CONFIRM_ERASE));
+        assert_eq!(
+            required_flags(stm32, "level0", "level2"),
+            Ok(CONFIRM_PERMANENT | CONFIRM_LOCKOUT)
+        );
+        assert!(required_flags(stm32, "level2", "level0").is_err());
+
+        let nrf = Family::for_target("nRF52840_xxAA").unwrap();
+        assert_eq!(
+            required_flags(nrf, "open", "protected"),
+            Ok(CONFIRM_LOCKOUT)
+        );
+        assert!(required_flags(nrf, "protected", "open").is_err());
+        assert_eq!(
+            flag_names(CONFIRM_ERASE | CONFIRM_PERMANENT),
+            "PR_SECURITY_CONFIRM_ERASE | PR_SECURITY_CONFIRM_PERMANENT"
+        );
+    }
+}
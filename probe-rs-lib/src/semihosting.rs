@@ -0,0 +1,170 @@
+//! Answering the semihosting requests of a running core, for on-target test runners: console
+//! output is collected for the host and the exit request ends the run with the firmware's code.
+
+use crate::{get_session, set_error};
+use probe_rs::semihosting::{ExitErrorDetails, SemihostingCommand};
+use probe_rs::{BreakpointCause, Core, CoreStatus, HaltReason, MemoryInterface};
+use std::collections::HashMap;
+use std::num::NonZeroU32;
+use std::sync::{Mutex, OnceLock};
+
+/// Handles answered for opening `:tt`, the console: stdout, or stderr when opened for appending.
+const STDOUT: NonZeroU32 = NonZeroU32::new(1).unwrap();
+const STDERR: NonZeroU32 = NonZeroU32::new(2).unwrap();
+
+/// `SYS_WRITEC`, which probe-rs does not decode: the parameter points to the character.
+const SYS_WRITEC: u32 = 0x03;
+
+/// Console output of the sessions not yet taken by `pr_semihosting_read`.
+static OUTPUT: OnceLock<Mutex<HashMap<u64, Vec<u8>>>> = OnceLock::new();
+
+fn output() -> &'static Mutex<HashMap<u64, Vec<u8>>> {
+    OUTPUT.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Drop the output of a session, e.g. when it is closed.
+pub(crate) fn forget(session: u64) {
+    output().lock().unwrap().remove(&session);
+}
+
+/// Exit status of `SYS_EXIT`/`SYS_EXIT_EXTENDED` with a reason other than success: the status
+/// passed to `exit()`, else 1.
+fn exit_code(details: &ExitErrorDetails) -> i32 {
+    details.exit_status.map_or(1, |status| status as i32)
+}
+
+/// Answer the request the core is halted on, if any, and let it continue. Returns the exit code
+/// once the firmware exited; other halts are errors, the run cannot go on without the host.
+fn serve(core: &mut Core, out: &mut Vec<u8>) -> Result<Option<i32>, probe_rs::Error> {
+    let CoreStatus::Halted(reason) = core.status()? else {
+        return Ok(None);
+    };
+    let HaltReason::Breakpoint(BreakpointCause::Semihosting(command)) = reason else {
+        return Err(probe_rs::Error::Other(format!(
+            "core halted without a semihosting request: {:?}",
+            reason
+        )));
+    };
+    match command {
+        SemihostingCommand::ExitSuccess => return Ok(Some(0)),
+        SemihostingCommand::ExitError(details) => return Ok(Some(exit_code(&details))),
+        SemihostingCommand::WriteConsole(request) => {
+            out.extend_from_slice(request.read(core)?.as_bytes());
+        }
+        SemihostingCommand::Open(request) if request.path(core)? == ":tt" => {
+            let handle = if request.mode().starts_with('a') {
+                STDERR
+            } else {
+                STDOUT
+            };
+            request.respond_with_handle(core, handle)?;
+        }
+        SemihostingCommand::Write(request)
+            if [STDOUT.get(), STDERR.get()].contains(&request.file_handle()) =>
+        {
+            out.extend_from_slice(&request.read(core)?);
+            request.write_status(core, 0)?;
+        }
+        SemihostingCommand::Close(request) if request.file_handle() <= STDERR.get() => {
+            request.success(core)?;
+        }
+        SemihostingCommand::Unknown(details) if details.operation == SYS_WRITEC => {
+            out.push(core.read_word_8(details.parameter.into())?);
+        }
+        SemihostingCommand::Time(request) => request.write_current_time(core)?,
+        // No host files: the request is left unanswered, as probe-rs does
+        _ => {}
+    }
+    core.run()?;
+    Ok(None)
+}
+
+/// Serve the semihosting request core `core_index` is halted on, if any, and resume it; call
+/// this periodically while the firmware runs. Text written to the console (`SYS_WRITE0`,
+/// `SYS_WRITEC`, `SYS_WRITE` to `:tt`) is queued for `pr_semihosting_read`; files on the host
+/// cannot be opened. The firmware's exit code is written to `out_exit_code` (may be NULL).
+///
+/// Returns 0 while the firmware runs, 1 once it exited (`SYS_EXIT`, the core stays halted), -1
+/// on invalid handle or core, -2 if the core halted for another reason or accessing it failed.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_semihosting_poll(
+    session: u64,
+    core_index: u32,
+    out_exit_code: *mut i32,
+) -> i32 {
+    let sess = match get_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let mut lock = sess.lock().unwrap();
+    let mut core = match lock.core(core_index as usize) {
+        Ok(core) => core,
+        Err(e) => {
+            set_error(format!("core access error: {}", e));
+            return -1;
+        }
+    };
+    let mut out = Vec::new();
+    let result = serve(&mut core, &mut out);
+    if !out.is_empty() {
+        let mut map = output().lock().unwrap();
+        map.entry(session).or_default().extend(out);
+    }
+    match result {
+        Ok(None) => 0,
+        Ok(Some(code)) => {
+            if !out_exit_code.is_null() {
+                unsafe { *out_exit_code = code };
+            }
+            1
+        }
+        Err(e) => {
+            set_error(format!("semihosting error: {}", e));
+            -2
+        }
+    }
+}
+
+/// Take up to `out_len` bytes of the console output queued by `pr_semihosting_poll`.
+///
+/// Returns the byte count, or -1 on invalid handle or NULL buffer.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_semihosting_read(session: u64, out: *mut u8, out_len: usize) -> i32 {
+    if let Err(e) = get_session(session) {
+        set_error(e);
+        return -1;
+    }
+    if out.is_null() && out_len > 0 {
+        set_error("null output buffer".to_string());
+        return -1;
+    }
+    let mut map = output().lock().unwrap();
+    let Some(queued) = map.get_mut(&session) else {
+        return 0;
+    };
+    let n = queued.len().min(out_len).min(i32::MAX as usize);
+    unsafe { std::ptr::copy_nonoverlapping(queued.as_ptr(), out, n) };
+    queued.drain(..n);
+    n as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exit_status_is_passed_on() {
+        let details = |exit_status| ExitErrorDetails {
+            reason: 0x20026,
+            exit_status,
+            subcode: None,
+        };
+        assert_eq!(exit_code(&details(Some(3))), 3);
+        assert_eq!(exit_code(&details(None)), 1);
+        assert_eq!(pr_semihosting_poll(0, 0, std::ptr::null_mut()), -1);
+        assert_eq!(pr_semihosting_read(0, std::ptr::null_mut(), 0), -1);
+    }
+}
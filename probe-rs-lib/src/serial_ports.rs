@@ -0,0 +1,137 @@
+//! Serial ports (CDC-ACM virtual COM ports) on the same composite USB device as a probe, e.g.
+//! the VCP of an ST-Link V2-1/V3 or the UART bridge of a DAPLink board.
+
+use crate::{set_error, write_c_str};
+use serde::Serialize;
+use serialport::{SerialPortInfo, SerialPortType};
+use std::ffi::c_char;
+
+#[derive(Serialize, Debug, PartialEq)]
+pub(crate) struct AssociatedPort {
+    pub(crate) port: String,
+    /// USB interface number of the port, if the OS reports it.
+    interface: Option<u8>,
+    product: Option<String>,
+}
+
+/// Ports of the USB device with the given IDs. Without a probe serial number, every device
+/// with the same VID:PID matches.
+fn associated_ports(
+    vid: u16,
+    pid: u16,
+    serial: Option<&str>,
+    ports: Vec<SerialPortInfo>,
+) -> Vec<AssociatedPort> {
+    ports
+        .into_iter()
+        .filter_map(|p| match p.port_type {
+            SerialPortType::UsbPort(usb)
+                if usb.vid == vid
+                    && usb.pid == pid
+                    && serial.is_none_or(|s| usb.serial_number.as_deref() == Some(s)) =>
+            {
+                Some(AssociatedPort {
+                    port: p.port_name,
+                    interface: usb.interface,
+                    product: usb.product,
+                })
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Serial ports of the USB device with the given IDs, ordered by interface number.
+pub(crate) fn usb_device_ports(
+    vid: u16,
+    pid: u16,
+    serial: Option<&str>,
+) -> Result<Vec<AssociatedPort>, String> {
+    let ports = serialport::available_ports()
+        .map_err(|e| format!("serial port enumeration error: {}", e))?;
+    let mut associated = associated_ports(vid, pid, serial, ports);
+    associated.sort_by_key(|p| p.interface);
+    Ok(associated)
+}
+
+/// List the serial ports that belong to the probe at `index` (see `pr_probe_count`) as a JSON
+/// array of `{"port", "interface", "product"}`, where `port` is the name to open (`COM7`,
+/// `/dev/ttyACM0`, `/dev/cu.usbmodem1102`).
+///
+/// Ports are matched by the probe's VID, PID and serial number; the probe is not opened.
+/// Returns the required size including NUL, or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_associated_serial_ports(
+    index: u32,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let probes = crate::remote::list_all();
+    let Some(info) = probes.get(index as usize) else {
+        set_error("probe index out of range".to_string());
+        return 0;
+    };
+    let associated = match usb_device_ports(
+        info.vendor_id,
+        info.product_id,
+        info.serial_number.as_deref(),
+    ) {
+        Ok(ports) => ports,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    match serde_json::to_string(&associated) {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e.to_string());
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serialport::UsbPortInfo;
+
+    fn usb_port(name: &str, vid: u16, serial: &str, interface: u8) -> SerialPortInfo {
+        SerialPortInfo {
+            port_name: name.to_string(),
+            port_type: SerialPortType::UsbPort(UsbPortInfo {
+                vid,
+                pid: 0x374b,
+                serial_number: Some(serial.to_string()),
+                manufacturer: None,
+                product: Some("STLink Virtual COM Port".to_string()),
+                interface: Some(interface),
+            }),
+        }
+    }
+
+    #[test]
+    fn ports_are_matched_by_usb_ids_and_serial() {
+        let ports = || {
+            vec![
+                usb_port("/dev/ttyACM0", 0x0483, "0670FF49", 2),
+                usb_port("/dev/ttyACM1", 0x0483, "066DFF55", 2),
+                usb_port("/dev/ttyACM2", 0x1366, "0670FF49", 0),
+                SerialPortInfo {
+                    port_name: "/dev/ttyS0".to_string(),
+                    port_type: SerialPortType::Unknown,
+                },
+            ]
+        };
+        let found = associated_ports(0x0483, 0x374b, Some("0670FF49"), ports());
+        assert_eq!(
+            found,
+            vec![AssociatedPort {
+                port: "/dev/ttyACM0".to_string(),
+                interface: Some(2),
+                product: Some("STLink Virtual COM Port".to_string()),
+            }]
+        );
+        assert_eq!(associated_ports(0x0483, 0x374b, None, ports()).len(), 2);
+    }
+}
@@ -0,0 +1,724 @@
+//! JSON-RPC 2.0 over a WebSocket on localhost, so scripts in any language and remote test
+//! controllers (through an SSH tunnel or a proxy) can drive the library without binary FFI.
+//!
+//! Every text message is one request, answered with one response. Each connection runs in its
+//! own thread; sessions it opened and did not close are closed when it disconnects.
+//!
+//! Only clients that know the server's token may connect, and handshakes carrying an `Origin`
+//! header are refused: browsers send one with every WebSocket, so a web page open on the same
+//! machine cannot reach the hardware (cross-site WebSocket hijacking).
+
+use crate::api::{self, DetachMode, DownloadOptions, WireProtocol};
+use crate::terminal::{pr_terminal_close, pr_terminal_open, pr_terminal_read, pr_terminal_write};
+use crate::{
+    ABI_VERSION_MAJOR, ABI_VERSION_MINOR, LAST_ERROR, download_options, probe_list, set_error,
+    write_c_str,
+};
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Value, json};
+use std::ffi::c_char;
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+use tungstenite::http::StatusCode;
+use tungstenite::{Message, WebSocket};
+
+/// How often blocked accepts and reads look at the stop flag.
+const STOP_POLL: Duration = Duration::from_millis(100);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest `memory.read` / `rtt.read` result.
+const MAX_READ: usize = 1 << 20;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+/// The call was valid but failed (probe, target or file error).
+const CALL_FAILED: i64 = -32000;
+
+/// Bytes of randomness in a server token.
+const TOKEN_BYTES: usize = 16;
+
+struct Server {
+    port: u16,
+    token: Arc<str>,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+static SERVER: OnceLock<Mutex<Option<Server>>> = OnceLock::new();
+
+fn server_lock() -> &'static Mutex<Option<Server>> {
+    SERVER.get_or_init(|| Mutex::new(None))
+}
+
+#[derive(Debug, PartialEq)]
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl ToString) -> Self {
+        RpcError {
+            code,
+            message: message.to_string(),
+        }
+    }
+}
+
+fn failed(message: impl ToString) -> RpcError {
+    RpcError::new(CALL_FAILED, message)
+}
+
+/// The error of the C call that just failed on this thread.
+fn c_error() -> RpcError {
+    failed(LAST_ERROR.with(|last| last.borrow().clone()))
+}
+
+fn params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // Methods without parameters may be called without "params".
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e))
+}
+
+fn protocol(name: Option<&str>) -> Result<Option<WireProtocol>, RpcError> {
+    name.map(|n| n.parse().map_err(|e| RpcError::new(INVALID_PARAMS, e)))
+        .transpose()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(text: &str) -> Result<Vec<u8>, RpcError> {
+    let invalid = || RpcError::new(INVALID_PARAMS, "data must be an even number of hex digits");
+    if !text.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            text.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+fn read_len(len: usize) -> Result<usize, RpcError> {
+    if len > MAX_READ {
+        return Err(RpcError::new(
+            INVALID_PARAMS,
+            format!("at most {} bytes per read", MAX_READ),
+        ));
+    }
+    Ok(len)
+}
+
+#[derive(Deserialize)]
+struct OpenParams {
+    chip: String,
+    selector: Option<String>,
+    speed_khz: Option<u32>,
+    protocol: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct CloseParams {
+    session: u64,
+    #[serde(default)]
+    detach: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct SessionParams {
+    session: u64,
+}
+
+#[derive(Deserialize)]
+struct FlashParams {
+    session: Option<u64>,
+    chip: Option<String>,
+    path: String,
+    base_address: Option<u64>,
+    #[serde(default)]
+    skip: u32,
+    #[serde(default)]
+    verify: bool,
+    #[serde(default)]
+    preverify: bool,
+    #[serde(default)]
+    chip_erase: bool,
+    speed_khz: Option<u32>,
+    protocol: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MemoryParams {
+    session: u64,
+    #[serde(default)]
+    core: usize,
+    address: u64,
+    length: Option<usize>,
+    count: Option<usize>,
+    data: Option<String>,
+    words: Option<Vec<u32>>,
+}
+
+#[derive(Deserialize)]
+struct CoreParams {
+    session: u64,
+    #[serde(default)]
+    core: usize,
+    timeout_ms: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct ManufacturerParams {
+    manufacturer: String,
+}
+
+#[derive(Deserialize)]
+struct RttOpenParams {
+    session: u64,
+    #[serde(default)]
+    up: u32,
+    #[serde(default)]
+    down: u32,
+}
+
+#[derive(Deserialize)]
+struct RttParams {
+    terminal: u64,
+    data: Option<String>,
+}
+
+/// State of one client connection.
+#[derive(Default)]
+struct Connection {
+    /// Sessions opened by the client and not closed yet.
+    sessions: Vec<u64>,
+}
+
+impl Connection {
+    fn call(&mut self, method: &str, p: Value) -> Result<Value, RpcError> {
+        match method {
+            "version" => Ok(json!({
+                "version": env!("CARGO_PKG_VERSION"),
+                "abi": [ABI_VERSION_MAJOR, ABI_VERSION_MINOR],
+            })),
+            "probe.list" => probe_list::list_value().map_err(failed),
+            "chip.manufacturers" => Ok(json!(api::chip_manufacturers())),
+            "chip.models" => {
+                let p: ManufacturerParams = params(p)?;
+                let index = api::chip_manufacturers()
+                    .iter()
+                    .position(|m| *m == p.manufacturer)
+                    .ok_or_else(|| failed(format!("unknown manufacturer {}", p.manufacturer)))?;
+                Ok(json!(api::chip_models(index).unwrap_or_default()))
+            }
+            "session.open" => {
+                let p: OpenParams = params(p)?;
+                let protocol = protocol(p.protocol.as_deref())?;
+                let handle = match p.selector {
+                    Some(selector) => {
+                        api::open_session_with_probe(&selector, &p.chip, p.speed_khz, protocol)
+                    }
+                    None => api::open_session_auto(&p.chip, p.speed_khz, protocol),
+                }
+                .map_err(failed)?;
+                self.sessions.push(handle);
+                Ok(json!(handle))
+            }
+            "session.close" => {
+                let p: CloseParams = params(p)?;
+                let mode = match p.detach.as_deref().unwrap_or("run") {
+                    "run" => DetachMode::Run,
+                    "halt" => DetachMode::Halt,
+                    "reset" => DetachMode::Reset,
+                    other => {
+                        return Err(RpcError::new(
+                            INVALID_PARAMS,
+                            format!("invalid detach mode {}", other),
+                        ));
+                    }
+                };
+                api::close_session(p.session, mode).map_err(failed)?;
+                self.sessions.retain(|h| *h != p.session);
+                Ok(Value::Null)
+            }
+            "session.list" => Ok(json!(api::session_handles())),
+            "session.cores" => {
+                let p: SessionParams = params(p)?;
+                let sess = api::session(p.session).map_err(failed)?;
+                let cores = sess.lock().unwrap().list_cores().len();
+                Ok(json!(cores))
+            }
+            "flash" | "session.flash" => {
+                let p: FlashParams = params(p)?;
+                let format = api::detect_format(&p.path, p.base_address, p.skip).map_err(failed)?;
+                let opts: DownloadOptions<'static> = download_options(
+                    p.session,
+                    p.verify as i32,
+                    p.preverify as i32,
+                    p.chip_erase as i32,
+                );
+                let result = if method == "flash" {
+                    let chip = p
+                        .chip
+                        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing field `chip`"))?;
+                    let protocol = protocol(p.protocol.as_deref())?;
+                    api::flash(&chip, &p.path, format, opts, p.speed_khz, protocol)
+                } else {
+                    let session = p
+                        .session
+                        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing field `session`"))?;
+                    api::flash_session(session, &p.path, format, opts)
+                };
+                result.map_err(failed)?;
+                Ok(Value::Null)
+            }
+            "memory.read" => {
+                let p: MemoryParams = params(p)?;
+                let len = read_len(
+                    p.length
+                        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing field `length`"))?,
+                )?;
+                let data = api::read_memory(p.session, p.core, p.address, len).map_err(failed)?;
+                Ok(json!(hex(&data)))
+            }
+            "memory.write" => {
+                let p: MemoryParams = params(p)?;
+                let data = unhex(
+                    p.data
+                        .as_deref()
+                        .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing field `data`"))?,
+                )?;
+                api::write_memory(p.session, p.core, p.address, &data).map_err(failed)?;
+                Ok(Value::Null)
+            }
+            "memory.read32" => {
+                let p: MemoryParams = params(p)?;
+                let count = p
+                    .count
+                    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing field `count`"))?;
+                read_len(count.saturating_mul(4))?;
+                let words = api::read_32(p.session, p.core, p.address, count).map_err(failed)?;
+                Ok(json!(words))
+            }
+            "memory.write32" => {
+                let p: MemoryParams = params(p)?;
+                let words = p
+                    .words
+                    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "missing field `words`"))?;
+                api::write_32(p.session, p.core, p.address, &words).map_err(failed)?;
+                Ok(Value::Null)
+            }
+            "core.halt" | "core.run" | "core.reset" => {
+                let p: CoreParams = params(p)?;
+                match method {
+                    "core.halt" => {
+                        let timeout = Duration::from_millis(p.timeout_ms.unwrap_or(100));
+                        api::halt_core(p.session, p.core, timeout)
+                    }
+                    "core.run" => api::run_core(p.session, p.core),
+                    _ => api::reset_core(p.session, p.core),
+                }
+                .map_err(failed)?;
+                Ok(Value::Null)
+            }
+            "rtt.open" => {
+                let p: RttOpenParams = params(p)?;
+                match pr_terminal_open(p.session, p.up, p.down) {
+                    0 => Err(c_error()),
+                    terminal => Ok(json!(terminal)),
+                }
+            }
+            "rtt.read" => {
+                let p: RttParams = params(p)?;
+                let mut buf = vec![0u8; MAX_READ];
+                let n = pr_terminal_read(p.terminal, buf.as_mut_ptr(), buf.len());
+                if n < 0 {
+                    return Err(c_error());
+                }
+                Ok(json!(String::from_utf8_lossy(&buf[..n as usize])))
+            }
+            "rtt.write" => {
+                let p: RttParams = params(p)?;
+                let data = p.data.unwrap_or_default();
+                match pr_terminal_write(p.terminal, data.as_ptr(), data.len()) {
+                    n if n < 0 => Err(c_error()),
+                    pending => Ok(json!(pending)),
+                }
+            }
+            "rtt.close" => {
+                let p: RttParams = params(p)?;
+                if pr_terminal_close(p.terminal) != 0 {
+                    return Err(c_error());
+                }
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method {}", method),
+            )),
+        }
+    }
+
+    /// Answer one request message; notifications (no "id") get no answer.
+    fn handle(&mut self, text: &str) -> Option<String> {
+        let request: Value = match serde_json::from_str(text) {
+            Ok(v) => v,
+            Err(e) => return Some(response(Value::Null, Err(RpcError::new(PARSE_ERROR, e)))),
+        };
+        let id = request.get("id").cloned();
+        let method = request.get("method").and_then(Value::as_str);
+        let result = match method {
+            Some(method) if request.get("jsonrpc") == Some(&json!("2.0")) => {
+                let p = request.get("params").cloned().unwrap_or(Value::Null);
+                self.call(method, p)
+            }
+            _ => Err(RpcError::new(INVALID_REQUEST, "not a JSON-RPC 2.0 request")),
+        };
+        id.map(|id| response(id, result))
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        for handle in self.sessions.drain(..) {
+            // The client may have closed it through another connection.
+            let _ = api::close_session(handle, DetachMode::Run);
+        }
+    }
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> String {
+    match result {
+        Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+        Err(e) => json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "error": {"code": e.code, "message": e.message},
+        }),
+    }
+    .to_string()
+}
+
+/// A fresh random token, as hex.
+fn new_token() -> Result<String, String> {
+    let mut bytes = [0u8; TOKEN_BYTES];
+    getrandom::fill(&mut bytes).map_err(|e| format!("random token error: {}", e))?;
+    Ok(hex(&bytes))
+}
+
+/// Why a handshake with `origin` and the URL `query` is refused by a server with `token`.
+fn refuse(
+    origin: Option<&[u8]>,
+    query: Option<&str>,
+    token: &str,
+) -> Option<(StatusCode, &'static str)> {
+    if origin.is_some() {
+        return Some((
+            StatusCode::FORBIDDEN,
+            "connections from web pages are not allowed",
+        ));
+    }
+    let sent = query
+        .into_iter()
+        .flat_map(|q| q.split('&'))
+        .find_map(|pair| pair.strip_prefix("token="));
+    match sent {
+        Some(sent) if sent == token => None,
+        _ => Some((StatusCode::UNAUTHORIZED, "missing or wrong token")),
+    }
+}
+
+fn is_timeout(e: &tungstenite::Error) -> bool {
+    matches!(e, tungstenite::Error::Io(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut))
+}
+
+fn serve(stream: TcpStream, stop: &AtomicBool, token: &str) -> Result<(), String> {
+    stream
+        .set_nonblocking(false)
+        .and_then(|_| stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT)))
+        .map_err(|e| e.to_string())?;
+    let callback = |request: &Request, response: Response| {
+        let origin = request.headers().get("Origin").map(|o| o.as_bytes());
+        match refuse(origin, request.uri().query(), token) {
+            None => Ok(response),
+            Some((status, reason)) => {
+                let mut error = ErrorResponse::new(Some(reason.to_string()));
+                *error.status_mut() = status;
+                Err(error)
+            }
+        }
+    };
+    let mut ws: WebSocket<TcpStream> =
+        tungstenite::accept_hdr(stream, callback).map_err(|e| e.to_string())?;
+    ws.get_ref()
+        .set_read_timeout(Some(STOP_POLL))
+        .map_err(|e| e.to_string())?;
+    let mut conn = Connection::default();
+    while !stop.load(Ordering::Relaxed) {
+        let message = match ws.read() {
+            Ok(message) => message,
+            Err(e) if is_timeout(&e) => continue,
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(e.to_string()),
+        };
+        let reply = match message {
+            Message::Text(text) => conn.handle(&text),
+            Message::Binary(data) => conn.handle(&String::from_utf8_lossy(&data)),
+            Message::Close(_) => continue,
+            _ => None,
+        };
+        if let Some(reply) = reply {
+            ws.send(Message::text(reply)).map_err(|e| e.to_string())?;
+        }
+    }
+    let _ = ws.close(None);
+    let _ = ws.flush();
+    Ok(())
+}
+
+fn accept_loop(listener: TcpListener, stop: Arc<AtomicBool>, token: Arc<str>) {
+    while !stop.load(Ordering::Relaxed) {
+        match listener.accept() {
+            Ok((stream, peer)) => {
+                let stop = stop.clone();
+                let token = token.clone();
+                std::thread::spawn(move || {
+                    if let Err(e) = serve(stream, &stop, &token) {
+                        tracing::debug!("JSON-RPC connection from {} ended: {}", peer, e);
+                    }
+                });
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => std::thread::sleep(STOP_POLL),
+            Err(e) => {
+                tracing::warn!("JSON-RPC server accept error: {}", e);
+                std::thread::sleep(STOP_POLL);
+            }
+        }
+    }
+}
+
+/// Start a JSON-RPC 2.0 server on `ws://127.0.0.1:port/` (0 picks a free port). Methods:
+/// `version`, `probe.list`, `chip.manufacturers`, `chip.models`, `session.open`,
+/// `session.close`, `session.list`, `session.cores`, `flash`, `session.flash`, `memory.read`,
+/// `memory.write`, `memory.read32`, `memory.write32`, `core.halt`, `core.run`, `core.reset`,
+/// `rtt.open`, `rtt.read`, `rtt.write` and `rtt.close`; see the header for their parameters.
+///
+/// Clients connect to `ws://127.0.0.1:port/?token=<token>` with the token of
+/// `pr_server_token`, which is new for every start. Handshakes with an `Origin` header (any
+/// browser) are refused.
+///
+/// Returns the port listened on, -1 if a server is already running, -2 if the port cannot be
+/// bound or no token can be made.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_server_start(port: u16) -> i32 {
+    let mut server = server_lock().lock().unwrap();
+    if server.is_some() {
+        set_error("server already running".to_string());
+        return -1;
+    }
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, port)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            set_error(format!("bind error: {}", e));
+            return -2;
+        }
+    };
+    let port = match listener
+        .set_nonblocking(true)
+        .and_then(|_| listener.local_addr())
+    {
+        Ok(addr) => addr.port(),
+        Err(e) => {
+            set_error(format!("listen error: {}", e));
+            return -2;
+        }
+    };
+    let token: Arc<str> = match new_token() {
+        Ok(token) => token.into(),
+        Err(e) => {
+            set_error(e);
+            return -2;
+        }
+    };
+    let stop = Arc::new(AtomicBool::new(false));
+    let thread = {
+        let stop = stop.clone();
+        let token = token.clone();
+        std::thread::spawn(move || accept_loop(listener, stop, token))
+    };
+    *server = Some(Server {
+        port,
+        token,
+        stop,
+        thread,
+    });
+    port.into()
+}
+
+/// Write the token clients of the running server must send (`?token=` of the URL) to
+/// `out_token` as a C string.
+///
+/// Returns the size including the NUL (larger than `out_token_len` if it was cut off), or 0 if
+/// no server is running.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_server_token(out_token: *mut c_char, out_token_len: usize) -> usize {
+    match server_lock().lock().unwrap().as_ref() {
+        Some(server) => write_c_str(&server.token, out_token, out_token_len),
+        None => {
+            set_error("server not running".to_string());
+            0
+        }
+    }
+}
+
+/// Stop the server. Connections end within 100 ms, closing the sessions they opened; a call in
+/// progress (e.g. flashing) is finished first.
+///
+/// Returns the port it listened on, or -1 if no server is running.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_server_stop() -> i32 {
+    let Some(server) = server_lock().lock().unwrap().take() else {
+        set_error("server not running".to_string());
+        return -1;
+    };
+    server.stop.store(true, Ordering::Relaxed);
+    let _ = server.thread.join();
+    server.port.into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn call(conn: &mut Connection, request: Value) -> Value {
+        serde_json::from_str(&conn.handle(&request.to_string()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn requests_are_answered() {
+        let mut conn = Connection::default();
+        let version = call(
+            &mut conn,
+            json!({"jsonrpc": "2.0", "id": 1, "method": "version"}),
+        );
+        assert_eq!(version["id"], 1);
+        assert_eq!(version["result"]["version"], env!("CARGO_PKG_VERSION"));
+
+        let unknown = call(
+            &mut conn,
+            json!({"jsonrpc": "2.0", "id": "a", "method": "nope"}),
+        );
+        assert_eq!(unknown["error"]["code"], METHOD_NOT_FOUND);
+
+        let bad = call(
+            &mut conn,
+            json!({"jsonrpc": "2.0", "id": 2, "method": "memory.read", "params": {"session": 1}}),
+        );
+        assert_eq!(bad["error"]["code"], INVALID_PARAMS);
+
+        let closed = call(
+            &mut conn,
+            json!({"jsonrpc": "2.0", "id": 3, "method": "session.close", "params": {"session": 0xdead}}),
+        );
+        assert_eq!(closed["error"]["code"], CALL_FAILED);
+        assert_eq!(closed["error"]["message"], "invalid session handle");
+
+        assert!(
+            conn.handle(r#"{"jsonrpc": "2.0", "method": "version"}"#)
+                .is_none()
+        );
+        let garbage: Value = serde_json::from_str(&conn.handle("{").unwrap()).unwrap();
+        assert_eq!(garbage["error"]["code"], PARSE_ERROR);
+    }
+
+    #[test]
+    fn hex_round_trip() {
+        assert_eq!(
+            unhex(&hex(&[0x00, 0xab, 0x7f])).unwrap(),
+            vec![0x00, 0xab, 0x7f]
+        );
+        assert!(unhex("abc").is_err());
+        assert!(unhex("zz").is_err());
+    }
+
+    #[test]
+    fn handshakes_need_token_and_no_origin() {
+        assert_eq!(refuse(None, Some("token=abc"), "abc"), None);
+        assert_eq!(refuse(None, Some("x=1&token=abc"), "abc"), None);
+        assert_eq!(
+            refuse(None, Some("token=abd"), "abc").unwrap().0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            refuse(None, None, "abc").unwrap().0,
+            StatusCode::UNAUTHORIZED
+        );
+        assert_eq!(
+            refuse(Some(b"http://evil.example"), Some("token=abc"), "abc")
+                .unwrap()
+                .0,
+            StatusCode::FORBIDDEN
+        );
+        assert_eq!(new_token().unwrap().len(), 2 * TOKEN_BYTES);
+        assert_ne!(new_token().unwrap(), new_token().unwrap());
+    }
+
+    /// The HTTP status a refused handshake is answered with.
+    fn refused_status(result: tungstenite::Result<impl Sized>) -> StatusCode {
+        match result {
+            Err(tungstenite::Error::Http(response)) => response.status(),
+            Err(e) => panic!("unexpected error {}", e),
+            Ok(_) => panic!("handshake accepted"),
+        }
+    }
+
+    #[test]
+    fn server_answers_over_websocket() {
+        use tungstenite::client::IntoClientRequest;
+
+        assert_eq!(pr_server_token(std::ptr::null_mut(), 0), 0);
+        let port = pr_server_start(0);
+        assert!(port > 0);
+        assert_eq!(pr_server_start(0), -1);
+        let mut token = [0u8; 64];
+        let need = pr_server_token(token.as_mut_ptr() as *mut c_char, token.len());
+        assert_eq!(need, 2 * TOKEN_BYTES + 1);
+        let token = std::str::from_utf8(&token[..need - 1]).unwrap();
+        let url = format!("ws://127.0.0.1:{}/?token={}", port, token);
+
+        assert_eq!(
+            refused_status(tungstenite::connect(format!("ws://127.0.0.1:{}/", port))),
+            StatusCode::UNAUTHORIZED
+        );
+        let mut evil = url.as_str().into_client_request().unwrap();
+        evil.headers_mut()
+            .insert("Origin", "http://evil.example".parse().unwrap());
+        assert_eq!(
+            refused_status(tungstenite::connect(evil)),
+            StatusCode::FORBIDDEN
+        );
+
+        let (mut ws, _) = tungstenite::connect(&url).unwrap();
+        ws.send(Message::text(
+            json!({"jsonrpc": "2.0", "id": 7, "method": "chip.manufacturers"}).to_string(),
+        ))
+        .unwrap();
+        let reply: Value = serde_json::from_str(ws.read().unwrap().to_text().unwrap()).unwrap();
+        assert_eq!(reply["id"], 7);
+        assert!(reply["result"].as_array().is_some_and(|m| !m.is_empty()));
+        assert_eq!(pr_server_stop(), port);
+        assert_eq!(pr_server_stop(), -1);
+    }
+}
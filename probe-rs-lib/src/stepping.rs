@@ -0,0 +1,297 @@
+//! Source-debugger style stepping (step over calls, step out of the current function),
+//! implemented with temporary breakpoints on top of single instruction steps.
+
+use crate::{breakpoint, get_session, set_error, timeouts};
+use probe_rs::{Core, CoreInterface, Error, InstructionSet};
+use std::time::{Duration, Instant};
+
+/// How long a step-over/step-out may run before the core is halted again.
+const STEP_TIMEOUT: Duration = Duration::from_secs(5);
+
+enum StepError {
+    Target(Error),
+    Unsupported(String),
+    Timeout,
+}
+
+impl From<Error> for StepError {
+    fn from(e: Error) -> Self {
+        StepError::Target(e)
+    }
+}
+
+/// If `code` starts with a call instruction (branch with link), return its length in bytes.
+///
+/// `code` is little endian and must hold at least 4 bytes.
+fn call_length(isa: InstructionSet, code: &[u8]) -> Option<usize> {
+    let half = |i: usize| u16::from_le_bytes([code[i], code[i + 1]]);
+    let word = u32::from_le_bytes([code[0], code[1], code[2], code[3]]);
+    match isa {
+        InstructionSet::Thumb2 => {
+            let hw1 = half(0);
+            if hw1 & 0xFF87 == 0x4780 {
+                // BLX Rm
+                return Some(2);
+            }
+            let hw2 = half(2);
+            // BL / BLX imm
+            let is_bl =
+                hw1 & 0xF800 == 0xF000 && (hw2 & 0xD000 == 0xD000 || hw2 & 0xD001 == 0xC000);
+            is_bl.then_some(4)
+        }
+        InstructionSet::A32 => {
+            let is_call = if word >> 28 == 0xF {
+                // BLX imm
+                word & 0x0E00_0000 == 0x0A00_0000
+            } else {
+                // BL / BLX Rm
+                word & 0x0F00_0000 == 0x0B00_0000 || word & 0x0FFF_FFF0 == 0x012F_FF30
+            };
+            is_call.then_some(4)
+        }
+        InstructionSet::A64 => {
+            // BL / BLR
+            let is_call = word & 0xFC00_0000 == 0x9400_0000 || word & 0xFFFF_FC1F == 0xD63F_0000;
+            is_call.then_some(4)
+        }
+        InstructionSet::RV32 | InstructionSet::RV32C => {
+            if code[0] & 0b11 != 0b11 {
+                let hw = half(0);
+                // C.JALR (rs1 != 0; rs1 == 0 is C.EBREAK) / C.JAL
+                let is_call =
+                    (hw & 0xF07F == 0x9002 && (hw >> 7) & 0x1F != 0) || hw & 0xE003 == 0x2001;
+                is_call.then_some(2)
+            } else {
+                // JAL / JALR linking into ra or t0
+                let opcode = word & 0x7F;
+                let rd = (word >> 7) & 0x1F;
+                let is_call = (opcode == 0x6F || opcode == 0x67) && (rd == 1 || rd == 5);
+                is_call.then_some(4)
+            }
+        }
+        InstructionSet::Xtensa => {
+            let insn = word & 0x00FF_FFFF;
+            // CALLn / CALLXn
+            let is_call = insn & 0xF == 0x5 || insn & 0xFFF0CF == 0x0000C0;
+            is_call.then_some(3)
+        }
+    }
+}
+
+fn require_halted(core: &mut Core<'_>) -> Result<(), StepError> {
+    if core.core_halted()? {
+        Ok(())
+    } else {
+        Err(StepError::Unsupported("core is not halted".to_string()))
+    }
+}
+
+/// Single step, executing the original instruction if the core sits on a software breakpoint.
+fn step_instruction(session: u64, core_index: u32, core: &mut Core<'_>) -> Result<(), Error> {
+    if !breakpoint::step_over_patched(session, core_index, core)? {
+        core.step()?;
+    }
+    Ok(())
+}
+
+/// Run until `target` is reached in the current frame or deeper-nested code hits another halt
+/// reason, using a temporary breakpoint at `target`.
+///
+/// Stops that hit `target` in a deeper (recursive) frame, i.e. with a lower stack pointer than
+/// `frame_sp`, are resumed transparently.
+fn run_to(
+    session: u64,
+    core_index: u32,
+    core: &mut Core<'_>,
+    target: u64,
+    frame_sp: u64,
+) -> Result<(), StepError> {
+    let has_hw = CoreInterface::hw_breakpoints(core)?.contains(&Some(target));
+    let has_sw = breakpoint::is_set(session, core_index, target);
+    let mut temp_hw = false;
+    let mut temp_sw = false;
+    if !has_hw && !has_sw {
+        if core.set_hw_breakpoint(target).is_ok() {
+            temp_hw = true;
+        } else {
+            breakpoint::insert(session, core_index, core, target).map_err(|e| {
+                StepError::Unsupported(format!("no breakpoint available at target: {}", e))
+            })?;
+            temp_sw = true;
+        }
+    }
+
+    let res = run_until_frame(session, core_index, core, target, frame_sp);
+
+    if temp_hw {
+        let _ = core.clear_hw_breakpoint(target);
+    }
+    if temp_sw {
+        let _ = breakpoint::remove(session, core_index, core, target);
+    }
+    res
+}
+
+fn run_until_frame(
+    session: u64,
+    core_index: u32,
+    core: &mut Core<'_>,
+    target: u64,
+    frame_sp: u64,
+) -> Result<(), StepError> {
+    let deadline = Instant::now() + STEP_TIMEOUT;
+    loop {
+        breakpoint::step_over_patched(session, core_index, core)?;
+        core.run()?;
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if core.wait_for_core_halted(remaining).is_err() {
+            core.halt(timeouts::halt_timeout())?;
+            return Err(StepError::Timeout);
+        }
+        let pc: u64 = core.read_core_reg(core.program_counter())?;
+        let sp: u64 = core.read_core_reg(core.stack_pointer())?;
+        if pc != target || sp >= frame_sp {
+            return Ok(());
+        }
+    }
+}
+
+fn step_over(session: u64, core_index: u32, core: &mut Core<'_>) -> Result<(), StepError> {
+    require_halted(core)?;
+    let isa = core.instruction_set()?;
+    let pc: u64 = core.read_core_reg(core.program_counter())?;
+    let mut code = [0u8; 4];
+    breakpoint::read_code(session, core_index, core, pc, &mut code)?;
+
+    match call_length(isa, &code) {
+        Some(len) => {
+            let sp: u64 = core.read_core_reg(core.stack_pointer())?;
+            run_to(session, core_index, core, pc + len as u64, sp)
+        }
+        None => Ok(step_instruction(session, core_index, core)?),
+    }
+}
+
+fn step_out(session: u64, core_index: u32, core: &mut Core<'_>) -> Result<(), StepError> {
+    require_halted(core)?;
+    let isa = core.instruction_set()?;
+    let mut ret: u64 = core.read_core_reg(core.return_address())?;
+    if matches!(isa, InstructionSet::Thumb2 | InstructionSet::A32) {
+        if ret & 0xFF00_0000 == 0xFF00_0000 {
+            return Err(StepError::Unsupported(
+                "cannot step out of an exception handler".to_string(),
+            ));
+        }
+        ret &= !1;
+    }
+    if ret == 0 {
+        return Err(StepError::Unsupported("no return address".to_string()));
+    }
+    let sp: u64 = core.read_core_reg(core.stack_pointer())?;
+    run_to(session, core_index, core, ret, sp)
+}
+
+fn run_step(
+    session: u64,
+    core_index: u32,
+    what: &str,
+    f: fn(u64, u32, &mut Core<'_>) -> Result<(), StepError>,
+) -> i32 {
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    match lock.core(core_index as usize) {
+        Ok(mut core) => match f(session, core_index, &mut core) {
+            Ok(()) => 0,
+            Err(StepError::Target(e)) => {
+                set_error(format!("{} error: {}", what, e));
+                -2
+            }
+            Err(StepError::Unsupported(e)) => {
+                set_error(format!("{} error: {}", what, e));
+                -2
+            }
+            Err(StepError::Timeout) => {
+                set_error(format!(
+                    "{} error: not finished within {} s, core halted",
+                    what,
+                    STEP_TIMEOUT.as_secs()
+                ));
+                -3
+            }
+        },
+        Err(e) => {
+            set_error(format!("core access error: {}", e));
+            -1
+        }
+    }
+}
+
+/// Step one instruction, treating a call as a single instruction: if the core is halted on a
+/// call, run until it returns to the following instruction.
+///
+/// Returns 0 on success (the core may also have stopped early at another breakpoint), -1 on
+/// invalid handle/core, -2 on target error or if the core is not halted, -3 if the call did
+/// not return within the step timeout (the core is halted again).
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_core_step_over(session: u64, core_index: u32) -> i32 {
+    run_step(session, core_index, "step over", step_over)
+}
+
+/// Run until the current function returns to its caller.
+///
+/// The return address is taken from the link register (LR/ra), so this is exact in leaf
+/// functions and at function entry; after the function has called others it depends on LR
+/// still holding the return address.
+///
+/// Returns the same codes as `pr_core_step_over`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_core_step_out(session: u64, core_index: u32) -> i32 {
+    run_step(session, core_index, "step out", step_out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_calls() {
+        // bl 0x... (Thumb2)
+        assert_eq!(
+            call_length(InstructionSet::Thumb2, &[0x00, 0xF0, 0x02, 0xF8]),
+            Some(4)
+        );
+        // blx r3
+        assert_eq!(
+            call_length(InstructionSet::Thumb2, &[0x98, 0x47, 0, 0]),
+            Some(2)
+        );
+        // b.n (not a call)
+        assert_eq!(
+            call_length(InstructionSet::Thumb2, &[0xFE, 0xE7, 0, 0]),
+            None
+        );
+        // jal ra, 8
+        assert_eq!(
+            call_length(InstructionSet::RV32C, &0x0080_00EFu32.to_le_bytes()),
+            Some(4)
+        );
+        // c.jalr a0
+        assert_eq!(
+            call_length(InstructionSet::RV32C, &[0x02, 0x95, 0, 0]),
+            Some(2)
+        );
+        // c.ebreak
+        assert_eq!(
+            call_length(InstructionSet::RV32C, &[0x02, 0x90, 0, 0]),
+            None
+        );
+        // bl (A64)
+        assert_eq!(
+            call_length(InstructionSet::A64, &0x9400_0010u32.to_le_bytes()),
+            Some(4)
+        );
+    }
+}
@@ -0,0 +1,272 @@
+//! Strings allocated by the library and freed with `pr_string_free`, for .NET and JNI callers
+//! where asking for the size and then filling a buffer for every string is error prone.
+//!
+//! The `_alloc` variants call the regular function twice, so they are only provided for
+//! functions without side effects.
+
+use crate::bank::pr_flash_bank_info;
+use crate::chip_list::pr_chip_list_json;
+use crate::elf::pr_elf_address_symbol;
+use crate::layout::pr_flash_sector_layout;
+use crate::manifest::pr_get_api_manifest_json;
+use crate::probe_list::pr_probe_list_json;
+use crate::probe_version::pr_probe_version_info;
+use crate::protection::pr_flash_get_protection;
+use crate::serial_ports::pr_probe_associated_serial_ports;
+use crate::svd::{pr_svd_peripheral_list, pr_svd_register_read};
+use crate::timing::pr_flash_last_timing;
+use crate::{
+    pr_chip_family_name, pr_chip_manufacturer_name, pr_chip_model_name,
+    pr_chip_model_name_in_family, pr_jep106_name, pr_last_error, pr_programmer_type_to_string,
+    pr_version,
+};
+use std::alloc::{Layout, alloc_zeroed, dealloc};
+use std::ffi::c_char;
+
+/// Room in front of every allocation for its size.
+const HEADER: usize = std::mem::size_of::<usize>();
+
+fn layout(len: usize) -> Option<Layout> {
+    Layout::from_size_align(len.checked_add(HEADER)?, HEADER).ok()
+}
+
+/// Allocate a zeroed buffer of `len` bytes (at least one) to be freed with `pr_string_free`,
+/// e.g. for passing to a string-writing call.
+///
+/// Returns NULL if `len` is too large.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_string_alloc(len: usize) -> *mut c_char {
+    let len = len.max(1);
+    let Some(layout) = layout(len) else {
+        crate::set_error(format!("cannot allocate {} bytes", len));
+        return std::ptr::null_mut();
+    };
+    unsafe {
+        let base = alloc_zeroed(layout);
+        if base.is_null() {
+            crate::set_error(format!("cannot allocate {} bytes", len));
+            return std::ptr::null_mut();
+        }
+        (base as *mut usize).write(len);
+        base.add(HEADER) as *mut c_char
+    }
+}
+
+/// Free a string or buffer returned by the library (`pr_string_alloc`, `pr_*_alloc`); NULL is
+/// ignored.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_string_free(s: *mut c_char) {
+    if s.is_null() {
+        return;
+    }
+    unsafe {
+        let base = (s as *mut u8).sub(HEADER);
+        let len = (base as *const usize).read();
+        // The size was valid when allocating.
+        dealloc(base, layout(len).unwrap());
+    }
+}
+
+/// The string written by `fill(buf, len)` (a regular size-returning call) in a buffer of its
+/// own, or NULL if `fill` fails.
+fn alloc_with(fill: impl Fn(*mut c_char, usize) -> usize) -> *mut c_char {
+    let mut need = fill(std::ptr::null_mut(), 0);
+    loop {
+        if need == 0 {
+            return std::ptr::null_mut();
+        }
+        let buf = pr_string_alloc(need);
+        if buf.is_null() {
+            return buf;
+        }
+        // The string may have grown in between (e.g. a probe was plugged in).
+        let written = fill(buf, need);
+        if written <= need {
+            return if written == 0 {
+                pr_string_free(buf);
+                std::ptr::null_mut()
+            } else {
+                buf
+            };
+        }
+        pr_string_free(buf);
+        need = written;
+    }
+}
+
+// `extern "C"` functions do not implement `Fn`, whatever clippy says.
+#[allow(clippy::redundant_closure)]
+fn alloc_with_fn(fill: extern "C" fn(*mut c_char, usize) -> usize) -> *mut c_char {
+    alloc_with(|buf, len| fill(buf, len))
+}
+
+/// `pr_last_error` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_last_error_alloc() -> *mut c_char {
+    alloc_with_fn(pr_last_error)
+}
+
+/// `pr_version` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_version_alloc() -> *mut c_char {
+    alloc_with_fn(pr_version)
+}
+
+/// `pr_get_api_manifest_json` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_get_api_manifest_json_alloc() -> *mut c_char {
+    alloc_with_fn(pr_get_api_manifest_json)
+}
+
+/// `pr_probe_list_json` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_list_json_alloc() -> *mut c_char {
+    alloc_with_fn(pr_probe_list_json)
+}
+
+/// `pr_probe_version_info` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_version_info_alloc(index: u32) -> *mut c_char {
+    alloc_with(|buf, len| pr_probe_version_info(index, buf, len))
+}
+
+/// `pr_probe_associated_serial_ports` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_probe_associated_serial_ports_alloc(index: u32) -> *mut c_char {
+    alloc_with(|buf, len| pr_probe_associated_serial_ports(index, buf, len))
+}
+
+/// `pr_programmer_type_to_string` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_programmer_type_to_string_alloc(type_code: i32) -> *mut c_char {
+    alloc_with(|buf, len| pr_programmer_type_to_string(type_code, buf, len))
+}
+
+/// `pr_flash_last_timing` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_last_timing_alloc() -> *mut c_char {
+    alloc_with_fn(pr_flash_last_timing)
+}
+
+/// `pr_flash_bank_info` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_bank_info_alloc(session: u64) -> *mut c_char {
+    alloc_with(|buf, len| pr_flash_bank_info(session, buf, len))
+}
+
+/// `pr_flash_get_protection` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_get_protection_alloc(session: u64) -> *mut c_char {
+    alloc_with(|buf, len| pr_flash_get_protection(session, buf, len))
+}
+
+/// `pr_flash_sector_layout` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_sector_layout_alloc(chip: *const c_char) -> *mut c_char {
+    alloc_with(|buf, len| pr_flash_sector_layout(chip, buf, len))
+}
+
+/// `pr_chip_manufacturer_name` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_chip_manufacturer_name_alloc(index: u32) -> *mut c_char {
+    alloc_with(|buf, len| pr_chip_manufacturer_name(index, buf, len))
+}
+
+/// `pr_chip_model_name` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_chip_model_name_alloc(manu_index: u32, chip_index: u32) -> *mut c_char {
+    alloc_with(|buf, len| pr_chip_model_name(manu_index, chip_index, buf, len))
+}
+
+/// `pr_chip_family_name` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_chip_family_name_alloc(manu_index: u32, family_index: u32) -> *mut c_char {
+    alloc_with(|buf, len| pr_chip_family_name(manu_index, family_index, buf, len))
+}
+
+/// `pr_chip_model_name_in_family` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_chip_model_name_in_family_alloc(
+    manu_index: u32,
+    family_index: u32,
+    index: u32,
+) -> *mut c_char {
+    alloc_with(|buf, len| pr_chip_model_name_in_family(manu_index, family_index, index, buf, len))
+}
+
+/// `pr_jep106_name` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_jep106_name_alloc(cc: u8, id: u8) -> *mut c_char {
+    alloc_with(|buf, len| pr_jep106_name(cc, id, buf, len))
+}
+
+/// `pr_chip_list_json` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_chip_list_json_alloc(
+    manufacturer_filter: *const c_char,
+    family_filter: *const c_char,
+    offset: u32,
+    limit: u32,
+) -> *mut c_char {
+    alloc_with(|buf, len| {
+        pr_chip_list_json(manufacturer_filter, family_filter, offset, limit, buf, len)
+    })
+}
+
+/// `pr_elf_address_symbol` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_elf_address_symbol_alloc(
+    elf_path: *const c_char,
+    address: u64,
+) -> *mut c_char {
+    alloc_with(|buf, len| pr_elf_address_symbol(elf_path, address, buf, len))
+}
+
+/// `pr_svd_peripheral_list` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_svd_peripheral_list_alloc(session: u64) -> *mut c_char {
+    alloc_with(|buf, len| pr_svd_peripheral_list(session, buf, len))
+}
+
+/// `pr_svd_register_read` as an allocated string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_svd_register_read_alloc(
+    session: u64,
+    core_index: u32,
+    name: *const c_char,
+) -> *mut c_char {
+    alloc_with(|buf, len| pr_svd_register_read(session, core_index, name, buf, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+
+    #[test]
+    fn allocated_strings_round_trip() {
+        let version = pr_version_alloc();
+        assert!(!version.is_null());
+        let text = unsafe { CStr::from_ptr(version) }.to_str().unwrap();
+        assert_eq!(text, env!("CARGO_PKG_VERSION"));
+        pr_string_free(version);
+
+        assert!(pr_flash_bank_info_alloc(0xdead).is_null());
+        let error = pr_last_error_alloc();
+        assert_eq!(
+            unsafe { CStr::from_ptr(error) }.to_str().unwrap(),
+            "invalid session handle"
+        );
+        pr_string_free(error);
+        pr_string_free(std::ptr::null_mut());
+    }
+
+    #[test]
+    fn buffers_are_zeroed() {
+        let buf = pr_string_alloc(16);
+        let bytes = unsafe { std::slice::from_raw_parts(buf as *const u8, 16) };
+        assert!(bytes.iter().all(|b| *b == 0));
+        pr_string_free(buf);
+        assert!(pr_string_alloc(usize::MAX).is_null());
+    }
+}
@@ -0,0 +1,468 @@
+//! Peripheral register access by name using a CMSIS-SVD description of the device.
+
+use crate::{cstr_to_string, get_session, set_error, write_c_str};
+use probe_rs::{Core, MemoryInterface};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::ffi::c_char;
+use std::sync::{Arc, Mutex, OnceLock};
+use svd_parser::Config;
+use svd_parser::svd::Device;
+
+struct SvdField {
+    name: String,
+    description: Option<String>,
+    bit_offset: u32,
+    bit_width: u32,
+    enums: Vec<(String, u64)>,
+}
+
+struct SvdRegister {
+    name: String,
+    address: u64,
+    size: u32,
+    reset_value: Option<u64>,
+    readable: bool,
+    writable: bool,
+    description: Option<String>,
+    fields: Vec<SvdField>,
+}
+
+#[derive(Serialize)]
+struct PeripheralReport {
+    name: String,
+    group: Option<String>,
+    base_address: u64,
+    description: Option<String>,
+    registers: Vec<String>,
+}
+
+struct SvdModel {
+    peripherals: Vec<PeripheralReport>,
+    /// Registers by lower-cased `PERIPHERAL.REGISTER` name.
+    registers: HashMap<String, SvdRegister>,
+}
+
+#[derive(Serialize)]
+struct FieldReport<'a> {
+    name: &'a str,
+    bit_offset: u32,
+    bit_width: u32,
+    value: u64,
+    #[serde(rename = "enum")]
+    enum_name: Option<&'a str>,
+    description: Option<&'a str>,
+}
+
+#[derive(Serialize)]
+struct RegisterReport<'a> {
+    name: &'a str,
+    address: u64,
+    size: u32,
+    value: u64,
+    description: Option<&'a str>,
+    fields: Vec<FieldReport<'a>>,
+}
+
+static SVDS: OnceLock<Mutex<HashMap<u64, Arc<SvdModel>>>> = OnceLock::new();
+
+fn svds() -> &'static Mutex<HashMap<u64, Arc<SvdModel>>> {
+    SVDS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn field_mask(width: u32) -> u64 {
+    if width >= 64 {
+        u64::MAX
+    } else {
+        (1u64 << width) - 1
+    }
+}
+
+fn build_model(device: &Device) -> SvdModel {
+    let default_access = device.default_register_properties.access;
+    let default_size = device.default_register_properties.size;
+    let mut peripherals = Vec::new();
+    let mut registers = HashMap::new();
+
+    for peripheral in &device.peripherals {
+        let mut names = Vec::new();
+        for register in peripheral.all_registers() {
+            let name = format!("{}.{}", peripheral.name, register.name);
+            let access = register.properties.access.or(default_access);
+            let fields = register
+                .fields()
+                .map(|f| SvdField {
+                    name: f.name.clone(),
+                    description: f.description.clone(),
+                    bit_offset: f.bit_offset(),
+                    bit_width: f.bit_width(),
+                    enums: f
+                        .enumerated_values
+                        .iter()
+                        .flat_map(|e| e.values.iter())
+                        .filter_map(|v| v.value.map(|value| (v.name.clone(), value)))
+                        .collect(),
+                })
+                .collect();
+            registers.insert(
+                name.to_lowercase(),
+                SvdRegister {
+                    name: name.clone(),
+                    address: peripheral.base_address + register.address_offset as u64,
+                    size: register.properties.size.or(default_size).unwrap_or(32),
+                    reset_value: register.properties.reset_value,
+                    readable: access.is_none_or(|a| a.can_read()),
+                    writable: access.is_none_or(|a| a.can_write()),
+                    description: register.description.clone(),
+                    fields,
+                },
+            );
+            names.push(name);
+        }
+        peripherals.push(PeripheralReport {
+            name: peripheral.name.clone(),
+            group: peripheral.group_name.clone(),
+            base_address: peripheral.base_address,
+            description: peripheral.description.clone(),
+            registers: names,
+        });
+    }
+    SvdModel {
+        peripherals,
+        registers,
+    }
+}
+
+fn parse_svd(xml: &str) -> Result<SvdModel, String> {
+    let device =
+        svd_parser::parse_with_config(xml, &Config::default().expand(true).expand_properties(true))
+            .map_err(|e| format!("failed to parse svd: {:#}", e))?;
+    Ok(build_model(&device))
+}
+
+fn model_for(session: u64) -> Result<Arc<SvdModel>, String> {
+    svds()
+        .lock()
+        .unwrap()
+        .get(&session)
+        .cloned()
+        .ok_or_else(|| "no SVD loaded for this session".to_string())
+}
+
+/// Resolve `PERIPHERAL.REGISTER` or `PERIPHERAL.REGISTER.FIELD`.
+fn resolve<'a>(
+    model: &'a SvdModel,
+    expr: &str,
+) -> Result<(&'a SvdRegister, Option<&'a SvdField>), String> {
+    let key = expr.trim().to_lowercase();
+    if let Some(reg) = model.registers.get(&key) {
+        return Ok((reg, None));
+    }
+    if let Some((reg_name, field_name)) = key.rsplit_once('.')
+        && let Some(reg) = model.registers.get(reg_name)
+        && let Some(field) = reg
+            .fields
+            .iter()
+            .find(|f| f.name.eq_ignore_ascii_case(field_name))
+    {
+        return Ok((reg, Some(field)));
+    }
+    Err(format!("register not found: {}", expr))
+}
+
+fn read_register(core: &mut Core<'_>, reg: &SvdRegister) -> Result<u64, String> {
+    let res = match reg.size {
+        8 => core.read_word_8(reg.address).map(u64::from),
+        16 => core.read_word_16(reg.address).map(u64::from),
+        64 => core.read_word_64(reg.address),
+        _ => core.read_word_32(reg.address).map(u64::from),
+    };
+    res.map_err(|e| format!("read {} error: {}", reg.name, e))
+}
+
+fn write_register(core: &mut Core<'_>, reg: &SvdRegister, value: u64) -> Result<(), String> {
+    let res = match reg.size {
+        8 => core.write_word_8(reg.address, value as u8),
+        16 => core.write_word_16(reg.address, value as u16),
+        64 => core.write_word_64(reg.address, value),
+        _ => core.write_word_32(reg.address, value as u32),
+    };
+    res.map_err(|e| format!("write {} error: {}", reg.name, e))
+}
+
+fn decode<'a>(reg: &'a SvdRegister, value: u64) -> RegisterReport<'a> {
+    RegisterReport {
+        name: &reg.name,
+        address: reg.address,
+        size: reg.size,
+        value,
+        description: reg.description.as_deref(),
+        fields: reg
+            .fields
+            .iter()
+            .map(|f| {
+                let v = (value >> f.bit_offset) & field_mask(f.bit_width);
+                FieldReport {
+                    name: &f.name,
+                    bit_offset: f.bit_offset,
+                    bit_width: f.bit_width,
+                    value: v,
+                    enum_name: f
+                        .enums
+                        .iter()
+                        .find(|(_, ev)| *ev == v)
+                        .map(|(n, _)| n.as_str()),
+                    description: f.description.as_deref(),
+                }
+            })
+            .collect(),
+    }
+}
+
+/// Value to write to the whole register when only `field` is changed.
+fn merge_field(base: u64, field: &SvdField, value: u64) -> Result<u64, String> {
+    let mask = field_mask(field.bit_width);
+    if value & !mask != 0 {
+        return Err(format!(
+            "value {:#x} does not fit in {}-bit field {}",
+            value, field.bit_width, field.name
+        ));
+    }
+    Ok((base & !(mask << field.bit_offset)) | (value << field.bit_offset))
+}
+
+/// Remove the SVD of a session, e.g. when it is closed.
+pub(crate) fn unload(session: u64) {
+    svds().lock().unwrap().remove(&session);
+}
+
+/// Load a CMSIS-SVD file for a session, replacing any previously loaded one.
+///
+/// Returns 0 on success, -1 on invalid arguments/handle, -2 if the file cannot be read or parsed.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_svd_load(session: u64, path: *const c_char) -> i32 {
+    let Ok(path) = cstr_to_string(path) else {
+        set_error("invalid svd path".to_string());
+        return -1;
+    };
+    if get_session(session).is_err() {
+        set_error("invalid session handle".to_string());
+        return -1;
+    }
+    let model = std::fs::read_to_string(&path)
+        .map_err(|e| format!("failed to read svd: {}", e))
+        .and_then(|xml| parse_svd(&xml));
+    match model {
+        Ok(model) => {
+            svds().lock().unwrap().insert(session, Arc::new(model));
+            0
+        }
+        Err(e) => {
+            set_error(e);
+            -2
+        }
+    }
+}
+
+/// List the peripherals of the loaded SVD as JSON: an array of
+/// `{"name", "group", "base_address", "description", "registers": ["PERIPH.REG", ...]}`.
+///
+/// Returns the required size including NUL (only the size if `out_json` is NULL or
+/// `out_json_len` is 0), or 0 on error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_svd_peripheral_list(
+    session: u64,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let res = model_for(session)
+        .and_then(|m| serde_json::to_string(&m.peripherals).map_err(|e| e.to_string()));
+    match res {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e);
+            0
+        }
+    }
+}
+
+/// Read a peripheral register by name (e.g. `"USART1.BRR"`) and decode its fields.
+///
+/// Writes `{"name", "address", "size", "value", "description", "fields": [{"name",
+/// "bit_offset", "bit_width", "value", "enum", "description"}]}` to `out_json`; `enum` is the
+/// name of the matching enumerated value, if any. `PERIPH.REG.FIELD` reads the register and
+/// reports only that field.
+///
+/// Returns the required size including NUL (only the size if `out_json` is NULL or
+/// `out_json_len` is 0), or 0 on error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_svd_register_read(
+    session: u64,
+    core_index: u32,
+    name: *const c_char,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let Ok(expr) = cstr_to_string(name) else {
+        set_error("invalid register name".to_string());
+        return 0;
+    };
+    let res = (|| {
+        let model = model_for(session)?;
+        let (reg, field) = resolve(&model, &expr)?;
+        if !reg.readable {
+            return Err(format!("register {} is not readable", reg.name));
+        }
+        let sess = get_session(session)?;
+        let mut lock = sess.lock().unwrap();
+        let mut core = lock
+            .core(core_index as usize)
+            .map_err(|e| format!("core access error: {}", e))?;
+        let value = read_register(&mut core, reg)?;
+        let mut report = decode(reg, value);
+        if let Some(field) = field {
+            report.fields.retain(|f| f.name == field.name);
+        }
+        serde_json::to_string(&report).map_err(|e| e.to_string())
+    })();
+    match res {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e);
+            0
+        }
+    }
+}
+
+/// Write a peripheral register by name (e.g. `"USART1.BRR"`).
+///
+/// With `PERIPH.REG.FIELD` only that field is changed: the register is read, modified and
+/// written back (write-only registers start from their reset value).
+///
+/// Returns 0 on success, -1 on invalid arguments or unknown register, -2 on access error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_svd_register_write(
+    session: u64,
+    core_index: u32,
+    name: *const c_char,
+    value: u64,
+) -> i32 {
+    let Ok(expr) = cstr_to_string(name) else {
+        set_error("invalid register name".to_string());
+        return -1;
+    };
+    let model = match model_for(session) {
+        Ok(m) => m,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let (reg, field) = match resolve(&model, &expr) {
+        Ok(r) => r,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    if !reg.writable {
+        set_error(format!("register {} is read-only", reg.name));
+        return -1;
+    }
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let mut core = match lock.core(core_index as usize) {
+        Ok(core) => core,
+        Err(e) => {
+            set_error(format!("core access error: {}", e));
+            return -1;
+        }
+    };
+    let res = match field {
+        None => write_register(&mut core, reg, value),
+        Some(field) => {
+            let base = if reg.readable {
+                read_register(&mut core, reg)
+            } else {
+                Ok(reg.reset_value.unwrap_or(0))
+            };
+            base.and_then(|base| merge_field(base, field, value))
+                .and_then(|merged| write_register(&mut core, reg, merged))
+        }
+    };
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(e);
+            -2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SVD: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<device schemaVersion="1.1">
+  <name>TEST</name>
+  <addressUnitBits>8</addressUnitBits>
+  <width>32</width>
+  <size>32</size>
+  <access>read-write</access>
+  <peripherals>
+    <peripheral>
+      <name>USART1</name>
+      <baseAddress>0x40011000</baseAddress>
+      <registers>
+        <register>
+          <name>CR1</name>
+          <addressOffset>0x0C</addressOffset>
+          <fields>
+            <field>
+              <name>UE</name>
+              <bitOffset>13</bitOffset>
+              <bitWidth>1</bitWidth>
+              <enumeratedValues>
+                <enumeratedValue><name>Disabled</name><value>0</value></enumeratedValue>
+                <enumeratedValue><name>Enabled</name><value>1</value></enumeratedValue>
+              </enumeratedValues>
+            </field>
+            <field>
+              <name>M</name>
+              <bitOffset>12</bitOffset>
+              <bitWidth>1</bitWidth>
+            </field>
+          </fields>
+        </register>
+      </registers>
+    </peripheral>
+  </peripherals>
+</device>"#;
+
+    #[test]
+    fn resolves_registers_and_fields() {
+        let model = parse_svd(SVD).unwrap();
+        let (reg, field) = resolve(&model, "usart1.cr1").unwrap();
+        assert_eq!(reg.address, 0x4001_100C);
+        assert!(field.is_none());
+        let (_, field) = resolve(&model, "USART1.CR1.UE").unwrap();
+        assert_eq!(field.unwrap().bit_offset, 13);
+        assert!(resolve(&model, "USART1.CR2").is_err());
+    }
+
+    #[test]
+    fn decodes_and_merges_fields() {
+        let model = parse_svd(SVD).unwrap();
+        let (reg, field) = resolve(&model, "USART1.CR1.UE").unwrap();
+        let report = decode(reg, 1 << 13);
+        assert_eq!(report.fields[0].enum_name, Some("Enabled"));
+        assert_eq!(report.fields[1].value, 0);
+        let field = field.unwrap();
+        assert_eq!(merge_field(0x1000, field, 1).unwrap(), 0x3000);
+        assert!(merge_field(0, field, 2).is_err());
+    }
+}
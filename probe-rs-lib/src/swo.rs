@@ -0,0 +1,318 @@
+//! SWO trace capture, with the ITM stimulus port output of `printf`-over-ITM firmware decoded
+//! for targets where RTT is not an option.
+
+use crate::{get_session, set_error};
+use probe_rs::architecture::arm::SwoConfig;
+use probe_rs::architecture::arm::component::TraceSink;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
+/// Position of the decoder in the ITM packet stream (ARMv7-M Architecture Reference Manual,
+/// appendix D4).
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+enum State {
+    #[default]
+    Header,
+    /// Payload bytes of a packet from instrumentation port `port`.
+    Stimulus { port: u8, left: u8 },
+    /// Payload bytes of a hardware source (DWT) packet with discriminator `id`, `value` so far.
+    Hardware {
+        id: u8,
+        left: u8,
+        size: u8,
+        value: u32,
+    },
+    /// Bytes of a timestamp or extension packet, each but the last with bit 7 set.
+    Continuation,
+}
+
+/// DWT hardware source packet discriminator of periodic PC samples.
+const DWT_PC_SAMPLE: u8 = 2;
+
+/// Decoder of the instrumentation packets of an ITM byte stream that keeps the payload of the
+/// stimulus ports in `ports` (bit n for port n) and the DWT PC samples, and drops everything
+/// else.
+#[derive(Debug)]
+pub(crate) struct ItmDecoder {
+    ports: u32,
+    state: State,
+    /// PC sample packets decoded and not taken yet.
+    pc_samples: Vec<u32>,
+}
+
+impl ItmDecoder {
+    pub(crate) fn new(ports: u32) -> Self {
+        ItmDecoder {
+            ports,
+            state: State::Header,
+            pc_samples: Vec::new(),
+        }
+    }
+
+    /// The program counters of the PC sample packets decoded since the last call; samples of
+    /// a sleeping core (one byte packets) are left out.
+    pub(crate) fn take_pc_samples(&mut self) -> Vec<u32> {
+        std::mem::take(&mut self.pc_samples)
+    }
+
+    pub(crate) fn decode(&mut self, data: &[u8], out: &mut Vec<u8>) {
+        for &byte in data {
+            self.state = match self.state {
+                State::Header => match byte {
+                    // Synchronization (zero bytes, then 0x80) and overflow packets.
+                    0x00 | 0x80 | 0x70 => State::Header,
+                    _ if byte & 0b11 != 0 => {
+                        let left = [0, 1, 2, 4][(byte & 0b11) as usize];
+                        if byte & 0b100 == 0 {
+                            State::Stimulus {
+                                port: byte >> 3,
+                                left,
+                            }
+                        } else {
+                            State::Hardware {
+                                id: byte >> 3,
+                                left,
+                                size: left,
+                                value: 0,
+                            }
+                        }
+                    }
+                    _ if byte & 0x80 != 0 => State::Continuation,
+                    // Single byte local timestamps and extension packets.
+                    _ => State::Header,
+                },
+                State::Stimulus { port, left } => {
+                    if self.ports & (1 << port) != 0 {
+                        out.push(byte);
+                    }
+                    match left {
+                        1 => State::Header,
+                        _ => State::Stimulus {
+                            port,
+                            left: left - 1,
+                        },
+                    }
+                }
+                State::Hardware {
+                    id,
+                    left,
+                    size,
+                    value,
+                } => {
+                    let value = value | u32::from(byte) << (8 * (size - left));
+                    if left > 1 {
+                        State::Hardware {
+                            id,
+                            left: left - 1,
+                            size,
+                            value,
+                        }
+                    } else {
+                        if id == DWT_PC_SAMPLE && size == 4 {
+                            self.pc_samples.push(value);
+                        }
+                        State::Header
+                    }
+                }
+                State::Continuation if byte & 0x80 != 0 => State::Continuation,
+                State::Continuation => State::Header,
+            };
+        }
+    }
+}
+
+/// Capture of a session: raw SWO bytes, or the decoded stimulus output with a decoder.
+#[derive(Debug)]
+struct Capture {
+    core: usize,
+    itm: Option<ItmDecoder>,
+    /// Data not yet taken by `pr_swo_read`.
+    queued: Vec<u8>,
+}
+
+static CAPTURES: OnceLock<Mutex<HashMap<u64, Capture>>> = OnceLock::new();
+
+fn captures() -> &'static Mutex<HashMap<u64, Capture>> {
+    CAPTURES.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Whether `pr_swo_start` capture runs on the session.
+pub(crate) fn capturing(session: u64) -> bool {
+    captures().lock().unwrap().contains_key(&session)
+}
+
+/// Drop the capture of a session, e.g. when it is closed; the target keeps tracing.
+pub(crate) fn forget(session: u64) {
+    captures().lock().unwrap().remove(&session);
+}
+
+/// Configure core `core_index` and the probe for SWO trace in UART (NRZ) mode at `baud`,
+/// deriving the SWO prescaler from `tpiu_clock_hz`, the trace clock (usually the core clock).
+/// DWT and ITM are enabled with all stimulus ports.
+///
+/// With `itm_ports` 0, `pr_swo_read` returns the raw SWO bytes; otherwise the ITM packet
+/// stream is decoded and only the payload written to the stimulus ports in the mask (bit n
+/// for port n, e.g. 1 for the port 0 of `ITM_SendChar`) is returned.
+///
+/// Returns 0 on success, -1 on invalid handle, core or baud rate (0 or above the trace clock),
+/// -2 if the probe or target cannot trace over SWO.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_swo_start(
+    session: u64,
+    core_index: u32,
+    tpiu_clock_hz: u32,
+    baud: u32,
+    itm_ports: u32,
+) -> i32 {
+    if baud == 0 || baud > tpiu_clock_hz {
+        set_error(format!(
+            "invalid SWO baud rate {} for a {} Hz trace clock",
+            baud, tpiu_clock_hz
+        ));
+        return -1;
+    }
+    let sess = match get_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let mut lock = sess.lock().unwrap();
+    let core = core_index as usize;
+    if core >= lock.target().cores.len() {
+        set_error(format!("invalid core index {}", core_index));
+        return -1;
+    }
+    let config = SwoConfig::new(tpiu_clock_hz).set_baud(baud).set_mode_uart();
+    if let Err(e) = lock.setup_tracing(core, TraceSink::Swo(config)) {
+        set_error(format!("SWO setup error: {}", e));
+        return -2;
+    }
+    let capture = Capture {
+        core,
+        itm: (itm_ports != 0).then(|| ItmDecoder::new(itm_ports)),
+        queued: Vec::new(),
+    };
+    captures().lock().unwrap().insert(session, capture);
+    0
+}
+
+/// Read the SWO data received since the last call, up to `out_len` bytes: raw bytes or the
+/// decoded stimulus port output, as chosen with `pr_swo_start`. Does not wait; call it
+/// periodically, the probe's buffer overflows if data is not picked up in time.
+///
+/// Returns the byte count, -1 on invalid handle, NULL buffer or no capture started, -2 on read
+/// error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_swo_read(session: u64, out: *mut u8, out_len: usize) -> i32 {
+    let sess = match get_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    if out.is_null() && out_len > 0 {
+        set_error("null output buffer".to_string());
+        return -1;
+    }
+    let mut map = captures().lock().unwrap();
+    let Some(capture) = map.get_mut(&session) else {
+        set_error("SWO capture not started, see pr_swo_start".to_string());
+        return -1;
+    };
+    let data = match sess.lock().unwrap().read_trace_data() {
+        Ok(data) => data,
+        Err(e) => {
+            set_error(format!("SWO read error: {}", e));
+            return -2;
+        }
+    };
+    match &mut capture.itm {
+        Some(decoder) => decoder.decode(&data, &mut capture.queued),
+        None => capture.queued.extend_from_slice(&data),
+    }
+    let n = capture.queued.len().min(out_len).min(i32::MAX as usize);
+    unsafe { std::ptr::copy_nonoverlapping(capture.queued.as_ptr(), out, n) };
+    capture.queued.drain(..n);
+    n as i32
+}
+
+/// Stop the SWO capture of `pr_swo_start`: the DWT is disabled on the target and data not yet
+/// read is dropped.
+///
+/// Returns 0 on success, -1 on invalid handle or no capture started, -2 on target access error.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_swo_stop(session: u64) -> i32 {
+    let sess = match get_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    let Some(capture) = captures().lock().unwrap().remove(&session) else {
+        set_error("SWO capture not started".to_string());
+        return -1;
+    };
+    match sess.lock().unwrap().disable_swv(capture.core) {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(format!("SWO stop error: {}", e));
+            -2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sync, "H" and "ello" on port 0, "!" on port 1, a local timestamp with continuation
+    // bytes, a DWT event counter packet, an overflow, a short timestamp and "\n" on port 0.
+    const STREAM: &[u8] = &[
+        0x00, 0x00, 0x00, 0x00, 0x00, 0x80, 0x01, b'H', 0x03, b'e', b'l', b'l', b'o', 0x09, b'!',
+        0xc0, 0x85, 0x01, 0x05, 0x2a, 0x70, 0x20, 0x01, b'\n',
+    ];
+
+    #[test]
+    fn stimulus_ports_are_decoded() {
+        let mut out = Vec::new();
+        ItmDecoder::new(1).decode(STREAM, &mut out);
+        assert_eq!(out, b"Hello\n");
+
+        // Packets split across reads
+        let mut decoder = ItmDecoder::new(0b10);
+        let mut out = Vec::new();
+        for byte in STREAM {
+            decoder.decode(std::slice::from_ref(byte), &mut out);
+        }
+        assert_eq!(out, b"!");
+        assert_eq!(decoder.state, State::Header);
+    }
+
+    #[test]
+    fn pc_samples_are_decoded() {
+        // A PC sample, a sleep sample, "A" on port 0 and a PC sample split across reads.
+        let stream = [
+            0x17, 0x34, 0x12, 0x00, 0x08, 0x15, 0x00, 0x01, b'A', 0x17, 0x02, 0x01,
+        ];
+        let mut decoder = ItmDecoder::new(1);
+        let mut out = Vec::new();
+        decoder.decode(&stream, &mut out);
+        decoder.decode(&[0x00, 0x20], &mut out);
+        assert_eq!(out, b"A");
+        assert_eq!(decoder.take_pc_samples(), vec![0x0800_1234, 0x2000_0102]);
+        assert!(decoder.take_pc_samples().is_empty());
+    }
+
+    #[test]
+    fn invalid_arguments_are_rejected() {
+        assert_eq!(pr_swo_start(0, 0, 16_000_000, 0, 1), -1);
+        assert_eq!(pr_swo_start(0, 0, 1_000_000, 2_000_000, 1), -1);
+        assert_eq!(pr_swo_start(0, 0, 16_000_000, 2_000_000, 1), -1);
+        assert_eq!(pr_swo_read(0, std::ptr::null_mut(), 0), -1);
+        assert_eq!(pr_swo_stop(0), -1);
+    }
+}
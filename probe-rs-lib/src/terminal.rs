@@ -0,0 +1,390 @@
+//! Interactive console to the firmware over an RTT channel pair: the up channel carries the
+//! firmware's output, the down channel the host's keystrokes or commands.
+//!
+//! Each terminal owns a thread that pumps both channels through the debug port while the core
+//! runs, collecting output in a bounded buffer that is read back either in lines or raw. With
+//! the firmware's ELF file, defmt output is decoded into one line per log message.
+
+use crate::defmt::{Decoder, Defmt};
+use crate::{cstr_to_string, get_session, set_error};
+use probe_rs::Session;
+use probe_rs::rtt::{Error, Rtt};
+use std::collections::{HashMap, VecDeque};
+use std::ffi::c_char;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+/// Output bytes kept per terminal; older output is dropped when the reader falls behind.
+const OUTPUT_CAPACITY: usize = 1 << 20;
+const PUMP_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Default)]
+struct Buffers {
+    output: VecDeque<u8>,
+    input: VecDeque<u8>,
+    /// Last pump error, reported once by the next read.
+    error: Option<String>,
+}
+
+impl Buffers {
+    fn push_output(&mut self, data: &[u8]) {
+        self.output.extend(data);
+        let excess = self.output.len().saturating_sub(OUTPUT_CAPACITY);
+        self.output.drain(..excess);
+    }
+
+    /// The first complete line of output without its line ending ("\n" or "\r\n"), and the
+    /// number of buffered bytes it spans.
+    fn peek_line(&self) -> Option<(Vec<u8>, usize)> {
+        let newline = self.output.iter().position(|&b| b == b'\n')?;
+        let mut line: Vec<u8> = self.output.range(..newline).copied().collect();
+        if line.last() == Some(&b'\r') {
+            line.pop();
+        }
+        Some((line, newline + 1))
+    }
+}
+
+struct Terminal {
+    session: u64,
+    stop: Arc<AtomicBool>,
+    buffers: Arc<Mutex<Buffers>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Terminal {
+    fn shutdown(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(t) = self.thread.take() {
+            let _ = t.join();
+        }
+    }
+}
+
+static TERMINALS: OnceLock<Mutex<HashMap<u64, Terminal>>> = OnceLock::new();
+static NEXT_TERMINAL_HANDLE: AtomicU64 = AtomicU64::new(1);
+
+fn terminals() -> &'static Mutex<HashMap<u64, Terminal>> {
+    TERMINALS.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+fn terminal_buffers(terminal: u64) -> Option<Arc<Mutex<Buffers>>> {
+    terminals()
+        .lock()
+        .unwrap()
+        .get(&terminal)
+        .map(|t| t.buffers.clone())
+}
+
+/// Move pending output from the up channel into the buffer, decoded if it is defmt, and as
+/// much queued input as the down channel takes.
+fn pump(
+    session: &Mutex<Session>,
+    rtt: &mut Rtt,
+    up: usize,
+    down: usize,
+    buffers: &Mutex<Buffers>,
+    mut decoder: Option<&mut Decoder>,
+) -> Result<(), Error> {
+    let mut lock = session.lock().unwrap();
+    let mut core = lock.core(0)?;
+    let mut buf = [0u8; 1024];
+    loop {
+        let n = rtt
+            .up_channel(up)
+            .ok_or(Error::MissingChannel(up))?
+            .read(&mut core, &mut buf)?;
+        if n == 0 {
+            break;
+        }
+        let mut buffers = buffers.lock().unwrap();
+        match decoder.as_deref_mut() {
+            Some(decoder) => {
+                let mut text = String::new();
+                if let Err(e) = decoder.decode(&buf[..n], &mut text) {
+                    buffers.error = Some(e);
+                }
+                buffers.push_output(text.as_bytes());
+            }
+            None => buffers.push_output(&buf[..n]),
+        }
+    }
+
+    let pending: Vec<u8> = buffers.lock().unwrap().input.iter().copied().collect();
+    if !pending.is_empty() {
+        let written = rtt
+            .down_channel(down)
+            .ok_or(Error::MissingChannel(down))?
+            .write(&mut core, &pending)?;
+        buffers.lock().unwrap().input.drain(..written);
+    }
+    Ok(())
+}
+
+/// Stop and remove every terminal of a session, e.g. before it is closed.
+pub(crate) fn stop_for_session(session: u64) {
+    let mut stopped: Vec<Terminal> = {
+        let mut map = terminals().lock().unwrap();
+        let handles: Vec<u64> = map
+            .iter()
+            .filter(|(_, t)| t.session == session)
+            .map(|(h, _)| *h)
+            .collect();
+        handles.iter().filter_map(|h| map.remove(h)).collect()
+    };
+    for t in &mut stopped {
+        t.shutdown();
+    }
+}
+
+fn open(session: u64, rtt_up: u32, rtt_down: u32, defmt: Option<Defmt>) -> u64 {
+    let sess = match get_session(session) {
+        Ok(s) => s,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    let (up, down) = (rtt_up as usize, rtt_down as usize);
+    let mut rtt = {
+        let mut lock = sess.lock().unwrap();
+        let mut core = match lock.core(0) {
+            Ok(core) => core,
+            Err(e) => {
+                set_error(format!("core access error: {}", e));
+                return 0;
+            }
+        };
+        match Rtt::attach(&mut core) {
+            Ok(rtt) => rtt,
+            Err(e) => {
+                set_error(format!("rtt attach error: {}", e));
+                return 0;
+            }
+        }
+    };
+    if rtt.up_channel(up).is_none() || rtt.down_channel(down).is_none() {
+        set_error(format!(
+            "rtt channels not found: target has {} up and {} down channels",
+            rtt.up_channels().len(),
+            rtt.down_channels().len()
+        ));
+        return 0;
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let buffers = Arc::new(Mutex::new(Buffers::default()));
+    let thread = {
+        let stop = stop.clone();
+        let buffers = buffers.clone();
+        std::thread::spawn(move || {
+            let mut decoder = defmt.as_ref().map(Defmt::decoder);
+            while !stop.load(Ordering::Relaxed) {
+                if let Err(e) = pump(&sess, &mut rtt, up, down, &buffers, decoder.as_mut()) {
+                    buffers.lock().unwrap().error = Some(format!("rtt error: {}", e));
+                }
+                std::thread::sleep(PUMP_INTERVAL);
+            }
+        })
+    };
+
+    let handle = NEXT_TERMINAL_HANDLE.fetch_add(1, Ordering::Relaxed);
+    terminals().lock().unwrap().insert(
+        handle,
+        Terminal {
+            session,
+            stop,
+            buffers,
+            thread: Some(thread),
+        },
+    );
+    handle
+}
+
+/// Open a console on RTT up channel `rtt_up` (firmware output) and down channel `rtt_down`
+/// (host input). The RTT control block is searched for in the RAM of core 0.
+///
+/// Output is collected in the background and taken with `pr_terminal_read_line` or
+/// `pr_terminal_read`; input is queued with `pr_terminal_write` and sent as the firmware
+/// makes room in the down channel. The terminal is closed by `pr_terminal_close` or when the
+/// session is closed.
+///
+/// Returns a non-zero terminal handle, or 0 on error (e.g. no control block, or the channels
+/// do not exist); see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_terminal_open(session: u64, rtt_up: u32, rtt_down: u32) -> u64 {
+    open(session, rtt_up, rtt_down, None)
+}
+
+/// Open a console like `pr_terminal_open` whose up channel carries defmt frames, decoded with
+/// the tables of `elf_path` (the firmware running on the target). Each log message becomes one
+/// line of output: its timestamp if the firmware provides one, level, text, and the source
+/// location if the ELF has debug info. Malformed data that cannot be resynchronized is
+/// reported by the next read.
+///
+/// Returns a non-zero terminal handle, or 0 on error (as `pr_terminal_open`, or the ELF cannot
+/// be read or has no defmt data); see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_terminal_open_defmt(
+    session: u64,
+    rtt_up: u32,
+    rtt_down: u32,
+    elf_path: *const c_char,
+) -> u64 {
+    let defmt = match cstr_to_string(elf_path).and_then(|p| Defmt::from_elf(&p)) {
+        Ok(d) => d,
+        Err(e) => {
+            set_error(e);
+            return 0;
+        }
+    };
+    open(session, rtt_up, rtt_down, Some(defmt))
+}
+
+/// Take the next complete line of output, without its line ending, as a NUL-terminated
+/// string.
+///
+/// Returns the required size including NUL, or 0 if no complete line is buffered yet. If
+/// `out_len` is too small, nothing is written and the line stays buffered.
+/// Returns -1 on invalid handle and -2 if pumping the channels failed since the last read.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_terminal_read_line(terminal: u64, out: *mut c_char, out_len: usize) -> i32 {
+    let Some(buffers) = terminal_buffers(terminal) else {
+        set_error("invalid terminal handle".to_string());
+        return -1;
+    };
+    let mut buffers = buffers.lock().unwrap();
+    if let Some(e) = buffers.error.take() {
+        set_error(e);
+        return -2;
+    }
+    let Some((line, consumed)) = buffers.peek_line() else {
+        return 0;
+    };
+    let needed = line.len() + 1;
+    if !out.is_null() && out_len >= needed {
+        unsafe {
+            std::ptr::copy_nonoverlapping(line.as_ptr(), out as *mut u8, line.len());
+            *out.add(line.len()) = 0;
+        }
+        buffers.output.drain(..consumed);
+    }
+    needed as i32
+}
+
+/// Take up to `out_len` bytes of buffered output as is, including an incomplete last line
+/// (e.g. a shell prompt).
+///
+/// Returns the number of bytes taken, -1 on invalid handle, -2 if pumping the channels failed
+/// since the last read.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_terminal_read(terminal: u64, out: *mut u8, out_len: usize) -> i32 {
+    let Some(buffers) = terminal_buffers(terminal) else {
+        set_error("invalid terminal handle".to_string());
+        return -1;
+    };
+    let mut buffers = buffers.lock().unwrap();
+    if let Some(e) = buffers.error.take() {
+        set_error(e);
+        return -2;
+    }
+    if out.is_null() {
+        return 0;
+    }
+    let n = buffers.output.len().min(out_len).min(i32::MAX as usize);
+    for (i, b) in buffers.output.drain(..n).enumerate() {
+        unsafe { *out.add(i) = b };
+    }
+    n as i32
+}
+
+/// Queue `len` bytes of input for the down channel. Nothing is appended; send "\n" (or what
+/// the firmware's shell expects) to complete a command.
+///
+/// Returns the number of bytes still waiting to be sent including these, or -1 on invalid
+/// handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_terminal_write(terminal: u64, data: *const u8, len: usize) -> i32 {
+    let Some(buffers) = terminal_buffers(terminal) else {
+        set_error("invalid terminal handle".to_string());
+        return -1;
+    };
+    let mut buffers = buffers.lock().unwrap();
+    if !data.is_null() && len > 0 {
+        let data = unsafe { std::slice::from_raw_parts(data, len) };
+        buffers.input.extend(data);
+    }
+    buffers.input.len().min(i32::MAX as usize) as i32
+}
+
+/// Queue a NUL-terminated command followed by "\n" for the down channel.
+///
+/// Returns the number of bytes still waiting to be sent including these, or -1 on invalid
+/// handle or string.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_terminal_write_line(terminal: u64, line: *const c_char) -> i32 {
+    let line = match cstr_to_string(line) {
+        Ok(s) => s + "\n",
+        Err(e) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    pr_terminal_write(terminal, line.as_ptr(), line.len())
+}
+
+/// Stop pumping a terminal and free its buffers; unsent input is dropped.
+/// Returns 0 on success, -1 on invalid handle.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_terminal_close(terminal: u64) -> i32 {
+    let removed = terminals().lock().unwrap().remove(&terminal);
+    match removed {
+        Some(mut t) => {
+            t.shutdown();
+            0
+        }
+        None => {
+            set_error("invalid terminal handle".to_string());
+            -1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn output_is_split_into_lines() {
+        let mut buffers = Buffers::default();
+        buffers.push_output(b"hello\r\nworld\nprom");
+        assert_eq!(buffers.peek_line(), Some((b"hello".to_vec(), 7)));
+        buffers.output.drain(..7);
+        assert_eq!(buffers.peek_line(), Some((b"world".to_vec(), 6)));
+        buffers.output.drain(..6);
+        assert_eq!(buffers.peek_line(), None);
+        buffers.push_output(b"pt> \n");
+        assert_eq!(buffers.peek_line(), Some((b"prompt> ".to_vec(), 9)));
+    }
+
+    #[test]
+    fn output_drops_oldest() {
+        let mut buffers = Buffers::default();
+        buffers.push_output(&vec![b'a'; OUTPUT_CAPACITY]);
+        buffers.push_output(b"bc");
+        assert_eq!(buffers.output.len(), OUTPUT_CAPACITY);
+        assert_eq!(buffers.output.back(), Some(&b'c'));
+    }
+
+    #[test]
+    fn invalid_handles() {
+        assert_eq!(pr_terminal_open(0xdead, 0, 0), 0);
+        assert_eq!(pr_terminal_open_defmt(0xdead, 0, 0, c"no.elf".as_ptr()), 0);
+        assert_eq!(pr_terminal_read_line(0xdead, std::ptr::null_mut(), 0), -1);
+        assert_eq!(pr_terminal_read(0xdead, std::ptr::null_mut(), 0), -1);
+        assert_eq!(pr_terminal_write(0xdead, b"x".as_ptr(), 1), -1);
+        assert_eq!(pr_terminal_close(0xdead), -1);
+    }
+}
@@ -0,0 +1,123 @@
+//! Timeouts and WAIT retries replacing the built-in ones, which are too short for slowly clocked
+//! targets and too long to detect a failing board quickly on a production line.
+
+use crate::set_error;
+use probe_rs::Session;
+use probe_rs::probe::{DebugProbeError, Probe};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+struct Timeouts {
+    attach: Option<Duration>,
+    halt: Option<Duration>,
+    flash_sector: Option<Duration>,
+    wait_retries: Option<usize>,
+}
+
+static TIMEOUTS: OnceLock<Mutex<Timeouts>> = OnceLock::new();
+
+fn timeouts_lock() -> &'static Mutex<Timeouts> {
+    TIMEOUTS.get_or_init(|| Mutex::new(Timeouts::default()))
+}
+
+/// Halt timeout of the library's own halts (stepping, calls, dumps, ...).
+const DEFAULT_HALT_TIMEOUT: Duration = Duration::from_millis(100);
+
+fn millis(ms: u32) -> Option<Duration> {
+    (ms > 0).then(|| Duration::from_millis(ms.into()))
+}
+
+/// Timeout for halting a core, the configured one or the library's default.
+pub(crate) fn halt_timeout() -> Duration {
+    timeouts_lock()
+        .lock()
+        .unwrap()
+        .halt
+        .unwrap_or(DEFAULT_HALT_TIMEOUT)
+}
+
+/// Apply the attach timeout and WAIT retries to a freshly opened `probe`.
+pub(crate) fn apply_to_probe(probe: &mut Probe) -> Result<(), String> {
+    let timeouts = *timeouts_lock().lock().unwrap();
+    probe.set_attach_timeout(timeouts.attach);
+    if let Some(retries) = timeouts.wait_retries {
+        match probe.set_wait_retries(retries) {
+            Ok(()) => {}
+            Err(DebugProbeError::CommandNotSupportedByProbe { .. }) => {
+                tracing::debug!("{} has no WAIT retry setting", probe.get_name());
+            }
+            Err(e) => return Err(format!("set WAIT retries error: {}", e)),
+        }
+    }
+    Ok(())
+}
+
+/// Apply the halt and flash sector timeouts to `session`.
+pub(crate) fn apply_to_session(session: &mut Session) {
+    let timeouts = *timeouts_lock().lock().unwrap();
+    session.set_halt_timeout(timeouts.halt);
+    session.set_flash_sector_timeout(timeouts.flash_sector);
+}
+
+/// Set the timeouts, in milliseconds, for sessions opened and flash calls made afterwards; 0
+/// restores the default:
+/// - `attach_ms`: waiting for the cores to halt when attaching under reset (default 100 ms).
+/// - `halt_ms`: halting a core, before flashing (default 500 ms) and in the library's other
+///   calls (default 100 ms).
+/// - `flash_sector_ms`: the flash algorithm erasing one sector or programming one page
+///   (default from the algorithm's description, usually a few hundred milliseconds to seconds).
+///
+/// Always returns 0.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_set_timeouts(attach_ms: u32, halt_ms: u32, flash_sector_ms: u32) -> i32 {
+    let mut timeouts = timeouts_lock().lock().unwrap();
+    timeouts.attach = millis(attach_ms);
+    timeouts.halt = millis(halt_ms);
+    timeouts.flash_sector = millis(flash_sector_ms);
+    0
+}
+
+/// Set how often a debug port transfer answered with WAIT is retried before it fails, for
+/// probes opened afterwards; negative restores the driver's default. Retrying is done by the
+/// probe (CMSIS-DAP, ST-Link) or the driver (J-Link, FTDI, CH347, Black Magic Probe); other
+/// probes keep their default.
+///
+/// FAULT responses are not retried: the sticky error is cleared and the transfer fails.
+///
+/// Returns 0 on success, -1 if `retries` is 0.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_set_wait_retries(retries: i32) -> i32 {
+    if retries == 0 {
+        set_error("at least one WAIT retry is needed".to_string());
+        return -1;
+    }
+    timeouts_lock().lock().unwrap().wait_retries = usize::try_from(retries).ok();
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn zero_restores_defaults() {
+        assert_eq!(pr_set_timeouts(2000, 250, 0), 0);
+        let timeouts = *timeouts_lock().lock().unwrap();
+        assert_eq!(timeouts.attach, Some(Duration::from_secs(2)));
+        assert_eq!(timeouts.flash_sector, None);
+        assert_eq!(halt_timeout(), Duration::from_millis(250));
+
+        assert_eq!(pr_set_timeouts(0, 0, 0), 0);
+        assert_eq!(halt_timeout(), DEFAULT_HALT_TIMEOUT);
+    }
+
+    #[test]
+    fn wait_retries() {
+        assert_eq!(pr_set_wait_retries(0), -1);
+        assert_eq!(pr_set_wait_retries(50), 0);
+        assert_eq!(timeouts_lock().lock().unwrap().wait_retries, Some(50));
+        assert_eq!(pr_set_wait_retries(-1), 0);
+        assert_eq!(timeouts_lock().lock().unwrap().wait_retries, None);
+    }
+}
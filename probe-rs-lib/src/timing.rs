@@ -0,0 +1,351 @@
+//! Timing report of a flash operation (per phase duration, bytes and throughput), kept after the
+//! transient progress callbacks for production dashboards, with the flash wear of the operation
+//! per region.
+
+use crate::{layout, set_error, write_c_str};
+use probe_rs::config::Target;
+use probe_rs::flashing::{FlashProgress, ProgressEvent, ProgressOperation};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::ffi::c_char;
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Default)]
+struct Phase {
+    started: Option<Instant>,
+    time: Duration,
+    bytes: u64,
+    total: Option<u64>,
+    seen: bool,
+}
+
+impl Phase {
+    fn stop(&mut self, now: Instant) {
+        if let Some(started) = self.started.take() {
+            self.time += now - started;
+        }
+    }
+
+    fn report(&self) -> Option<PhaseReport> {
+        if !self.seen {
+            return None;
+        }
+        // Erasing reports no progress on some targets; the announced size is the best guess.
+        let bytes = if self.bytes > 0 {
+            self.bytes
+        } else {
+            self.total.unwrap_or(0)
+        };
+        let secs = self.time.as_secs_f64();
+        Some(PhaseReport {
+            time_ms: self.time.as_millis() as u64,
+            bytes,
+            bytes_per_sec: if secs > 0.0 {
+                (bytes as f64 / secs) as u64
+            } else {
+                0
+            },
+        })
+    }
+}
+
+/// Collects the progress events of one flash operation.
+pub(crate) struct FlashTiming {
+    started: Instant,
+    erase: Phase,
+    program: Phase,
+    verify: Phase,
+    fill: Phase,
+    /// Sectors erased and pages programmed, from the flash layouts probe-rs announces.
+    sectors: Vec<Range<u64>>,
+    pages: Vec<Range<u64>>,
+    chip_erase: bool,
+}
+
+impl FlashTiming {
+    fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            erase: Phase::default(),
+            program: Phase::default(),
+            verify: Phase::default(),
+            fill: Phase::default(),
+            sectors: Vec::new(),
+            pages: Vec::new(),
+            chip_erase: false,
+        }
+    }
+
+    fn phase(&mut self, operation: ProgressOperation) -> &mut Phase {
+        match operation {
+            ProgressOperation::Erase => &mut self.erase,
+            ProgressOperation::Program => &mut self.program,
+            ProgressOperation::Verify => &mut self.verify,
+            ProgressOperation::Fill => &mut self.fill,
+        }
+    }
+
+    fn record(&mut self, event: &ProgressEvent) {
+        let now = Instant::now();
+        match event {
+            ProgressEvent::AddProgressBar { operation, total } => {
+                // A chip erase is announced without a size.
+                self.chip_erase |= matches!(operation, ProgressOperation::Erase) && total.is_none();
+                let phase = self.phase(*operation);
+                phase.total = Some(phase.total.unwrap_or(0) + total.unwrap_or(0));
+            }
+            ProgressEvent::Started(operation) => {
+                let phase = self.phase(*operation);
+                phase.seen = true;
+                phase.started = Some(now);
+            }
+            ProgressEvent::Progress {
+                operation, size, ..
+            } => self.phase(*operation).bytes += size,
+            ProgressEvent::Finished(operation) | ProgressEvent::Failed(operation) => {
+                self.phase(*operation).stop(now)
+            }
+            ProgressEvent::FlashLayoutReady { flash_layout } => {
+                for layout in flash_layout {
+                    let sectors = layout.sectors().iter();
+                    self.sectors
+                        .extend(sectors.map(|s| s.address()..s.address() + s.size()));
+                    let pages = layout.pages().iter();
+                    self.pages
+                        .extend(pages.map(|p| p.address()..p.address() + u64::from(p.size())));
+                }
+            }
+            ProgressEvent::DiagnosticMessage { .. } => {}
+        }
+    }
+
+    /// The wear of the operation on each flash region of `target` it touched. A chip erase
+    /// erases every sector of those regions.
+    fn wear(&self, target: &Target) -> Vec<RegionWear> {
+        let inside =
+            |range: &Range<u64>, r: &Range<u64>| range.start <= r.start && r.end <= range.end;
+        layout::region_ranges(target)
+            .into_iter()
+            .filter_map(|(name, range)| {
+                let sectors: Vec<&Range<u64>> =
+                    self.sectors.iter().filter(|s| inside(&range, s)).collect();
+                let pages: Vec<&Range<u64>> =
+                    self.pages.iter().filter(|p| inside(&range, p)).collect();
+                if sectors.is_empty() && pages.is_empty() {
+                    return None;
+                }
+                let (sectors_erased, bytes_erased) = if self.chip_erase {
+                    let all = layout::flash_sectors(target, &range);
+                    (all.len(), all.iter().map(|s| s.end - s.start).sum())
+                } else {
+                    (sectors.len(), sectors.iter().map(|s| s.end - s.start).sum())
+                };
+                Some(RegionWear {
+                    name,
+                    sectors_erased: sectors_erased as u64,
+                    bytes_erased,
+                    pages_programmed: pages.len() as u64,
+                    bytes_programmed: pages.iter().map(|p| p.end - p.start).sum(),
+                })
+            })
+            .collect()
+    }
+
+    fn report(
+        &mut self,
+        success: bool,
+        speed_khz: Option<u32>,
+        verify_mode: Option<&'static str>,
+        target: &Target,
+    ) -> TimingReport {
+        let now = Instant::now();
+        for phase in [
+            &mut self.erase,
+            &mut self.program,
+            &mut self.verify,
+            &mut self.fill,
+        ] {
+            phase.stop(now);
+        }
+        TimingReport {
+            success,
+            total_ms: (now - self.started).as_millis() as u64,
+            speed_khz,
+            verify_mode,
+            erase: self.erase.report(),
+            program: self.program.report(),
+            verify: self.verify.report(),
+            fill: self.fill.report(),
+            regions: self.wear(target),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct RegionWear {
+    name: String,
+    sectors_erased: u64,
+    bytes_erased: u64,
+    pages_programmed: u64,
+    bytes_programmed: u64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct PhaseReport {
+    time_ms: u64,
+    bytes: u64,
+    bytes_per_sec: u64,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub(crate) struct TimingReport {
+    success: bool,
+    total_ms: u64,
+    speed_khz: Option<u32>,
+    /// What the verify step compared (see `pr_flash_option_verify_mode`), null without verify.
+    verify_mode: Option<&'static str>,
+    erase: Option<PhaseReport>,
+    program: Option<PhaseReport>,
+    verify: Option<PhaseReport>,
+    fill: Option<PhaseReport>,
+    /// Flash wear per region, for tracking the cumulative erases of a board.
+    regions: Vec<RegionWear>,
+}
+
+thread_local! {
+    static LAST_REPORT: RefCell<Option<TimingReport>> = const { RefCell::new(None) };
+}
+
+/// Route the events of `progress` through a new timing collector as well.
+pub(crate) fn instrument(progress: &mut FlashProgress<'static>) -> Arc<Mutex<FlashTiming>> {
+    let timing = Arc::new(Mutex::new(FlashTiming::new()));
+    let mut inner = std::mem::take(progress);
+    let collector = timing.clone();
+    *progress = FlashProgress::new(move |event| {
+        collector.lock().unwrap().record(&event);
+        inner.emit(event);
+    });
+    timing
+}
+
+/// Finish the report of `timing` on `target` and keep it as the calling thread's last one.
+pub(crate) fn finish(
+    timing: &Mutex<FlashTiming>,
+    success: bool,
+    speed_khz: Option<u32>,
+    verify_mode: Option<&'static str>,
+    target: &Target,
+) {
+    let report = timing
+        .lock()
+        .unwrap()
+        .report(success, speed_khz, verify_mode, target);
+    LAST_REPORT.with(|last| *last.borrow_mut() = Some(report));
+}
+
+/// The calling thread's last report.
+pub(crate) fn last() -> Option<TimingReport> {
+    LAST_REPORT.with(|last| last.borrow().clone())
+}
+
+/// Timing report of the last flash call made on the calling thread (`pr_flash_*`,
+/// `pr_session_flash`, ...), successful or not, as JSON: `{"success", "total_ms", "speed_khz",
+/// "verify_mode", "erase", "program", "verify", "fill", "regions"}`. Each phase is `{"time_ms",
+/// "bytes", "bytes_per_sec"}`, or null if it did not run; `speed_khz` is the probe speed, null if
+/// the probe's default was used. `verify_mode` is `"pages"`, `"written"` or `"sectors"` (see
+/// `pr_flash_option_verify_mode`), null if the call did not verify.
+///
+/// `regions` is the flash wear of the call, for tracking the cumulative wear of reworked boards:
+/// `{"name", "sectors_erased", "bytes_erased", "pages_programmed", "bytes_programmed"}` for each
+/// flash region touched, every sector of the region after a chip erase. The counts are those
+/// probe-rs planned; a call that failed may have erased or programmed less.
+///
+/// Returns the required size including NUL, or 0 if no flash call was made on this thread yet.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_last_timing(out_json: *mut c_char, out_json_len: usize) -> usize {
+    let Some(report) = last() else {
+        set_error("no flash operation on this thread".to_string());
+        return 0;
+    };
+    match serde_json::to_string(&report) {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e.to_string());
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phases_are_summed() {
+        let mut progress = FlashProgress::empty();
+        let timing = instrument(&mut progress);
+        progress.emit(ProgressEvent::AddProgressBar {
+            operation: ProgressOperation::Erase,
+            total: Some(4096),
+        });
+        progress.emit(ProgressEvent::Started(ProgressOperation::Erase));
+        progress.emit(ProgressEvent::Finished(ProgressOperation::Erase));
+        progress.emit(ProgressEvent::Started(ProgressOperation::Program));
+        for _ in 0..2 {
+            progress.emit(ProgressEvent::Progress {
+                operation: ProgressOperation::Program,
+                size: 1024,
+                time: Duration::from_millis(1),
+            });
+        }
+        progress.emit(ProgressEvent::Finished(ProgressOperation::Program));
+
+        let target = crate::registry()
+            .get_target_by_name("STM32F407VGTx")
+            .unwrap();
+        finish(&timing, true, Some(4000), Some("written"), &target);
+        let report = last().unwrap();
+        assert_eq!(report.verify_mode, Some("written"));
+        assert_eq!(report.erase.as_ref().map(|p| p.bytes), Some(4096));
+        assert_eq!(report.program.as_ref().map(|p| p.bytes), Some(2048));
+        assert_eq!(report.verify, None);
+        assert_eq!(report.speed_khz, Some(4000));
+
+        let needed = pr_flash_last_timing(std::ptr::null_mut(), 0);
+        assert!(needed > 0);
+    }
+
+    #[test]
+    fn wear_is_counted_per_region() {
+        let target = crate::registry()
+            .get_target_by_name("STM32F407VGTx")
+            .unwrap();
+        let mut timing = FlashTiming::new();
+        timing.sectors = vec![0x0800_0000..0x0800_4000, 0x0800_4000..0x0800_8000];
+        timing.pages = vec![0x0800_0000..0x0800_0400, 0x0800_4000..0x0800_4400];
+        let wear = timing.wear(&target);
+        assert_eq!(wear.len(), 1);
+        assert_eq!((wear[0].sectors_erased, wear[0].bytes_erased), (2, 0x8000));
+        assert_eq!(
+            (wear[0].pages_programmed, wear[0].bytes_programmed),
+            (2, 0x800)
+        );
+
+        // 4 x 16 KiB, 64 KiB and 7 x 128 KiB sectors.
+        timing.chip_erase = true;
+        let wear = timing.wear(&target);
+        assert_eq!(
+            (wear[0].sectors_erased, wear[0].bytes_erased),
+            (12, 0x10_0000)
+        );
+    }
+
+    #[test]
+    fn no_report_before_flashing() {
+        std::thread::spawn(|| assert_eq!(pr_flash_last_timing(std::ptr::null_mut(), 0), 0))
+            .join()
+            .unwrap();
+    }
+}
@@ -0,0 +1,374 @@
+//! Address translation: the physical address a virtual address of a core maps to, and memory
+//! access "as the core sees it" through that mapping. Debug memory accesses are physical, so
+//! once an MMU is on they silently read something else than the code at the same address.
+//!
+//! RISC-V (RV32) harts halted in S or U mode are translated by walking their Sv32 page table;
+//! M mode and `satp` in Bare mode are untranslated. Cortex-M cores have no MMU (the MPU only
+//! checks accesses), so their addresses are physical.
+//!
+//! Out of scope, reported as unsupported: the Cortex-A (ARMv7-A short-descriptor and LPAE,
+//! ARMv8-A) translation table walks, which need TTBR0/TTBR1/TTBCR read through CP15, the
+//! RV64 Sv39/Sv48 schemes and the Xtensa MMU.
+
+use crate::{get_session, set_error};
+use probe_rs::{Core, CoreType, Error, MemoryInterface, RegisterId, RegisterValue};
+
+const CSR_SATP: u16 = 0x180;
+const CSR_DCSR: u16 = 0x7b0;
+/// `dcsr.prv` of a hart halted in machine mode.
+const PRV_MACHINE: u32 = 3;
+const SATP_MODE_SV32: u32 = 1 << 31;
+/// `satp.MODE` of RV64 harts; 0 is Bare.
+const SATP64_MODE_SHIFT: u32 = 60;
+const SATP_PPN: u32 = 0x003f_ffff;
+
+const PAGE_SIZE: u64 = 4096;
+const PTE_V: u32 = 1 << 0;
+const PTE_R: u32 = 1 << 1;
+const PTE_W: u32 = 1 << 2;
+const PTE_X: u32 = 1 << 3;
+
+enum TranslateError {
+    Target(Error),
+    Unsupported(String),
+    /// Nothing is mapped at the virtual address.
+    Unmapped(u64),
+}
+
+impl From<Error> for TranslateError {
+    fn from(e: Error) -> Self {
+        TranslateError::Target(e)
+    }
+}
+
+/// How the addresses of a core map to physical memory right now.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Translation {
+    Identity,
+    /// Sv32 paging with the root page table of `satp`.
+    Sv32 {
+        satp: u32,
+    },
+}
+
+impl Translation {
+    fn of(core: &mut Core<'_>) -> Result<Self, TranslateError> {
+        match core.core_type() {
+            CoreType::Armv6m | CoreType::Armv7m | CoreType::Armv7em | CoreType::Armv8m => {
+                Ok(Translation::Identity)
+            }
+            CoreType::Riscv => {
+                if !core.core_halted()? {
+                    return Err(TranslateError::Unsupported(
+                        "the hart must be halted to read its address translation".to_string(),
+                    ));
+                }
+                let dcsr: RegisterValue = core.read_core_reg(RegisterId(CSR_DCSR))?;
+                let dcsr: u64 = dcsr.try_into()?;
+                let satp: RegisterValue = core.read_core_reg(RegisterId(CSR_SATP))?;
+                riscv_translation(dcsr, satp)
+            }
+            CoreType::Armv7a | CoreType::Armv8a => Err(TranslateError::Unsupported(
+                "Cortex-A translation table walks are not supported; use physical addresses"
+                    .to_string(),
+            )),
+            other => Err(TranslateError::Unsupported(format!(
+                "address translation is not supported on {:?} cores",
+                other
+            ))),
+        }
+    }
+
+    /// The physical address of `virt`, reading page table entries with `read`.
+    fn translate(
+        self,
+        virt: u64,
+        read: &mut impl FnMut(u64) -> Result<u32, Error>,
+    ) -> Result<u64, TranslateError> {
+        match self {
+            Translation::Identity => Ok(virt),
+            Translation::Sv32 { satp } => {
+                let Ok(va) = u32::try_from(virt) else {
+                    return Err(TranslateError::Unmapped(virt));
+                };
+                sv32_walk(satp, va, read)?.ok_or(TranslateError::Unmapped(virt))
+            }
+        }
+    }
+}
+
+/// The translation of a hart halted with `dcsr` and `satp`; a 64 bit `satp` is an RV64 hart.
+fn riscv_translation(dcsr: u64, satp: RegisterValue) -> Result<Translation, TranslateError> {
+    if dcsr & 0x3 == u64::from(PRV_MACHINE) {
+        return Ok(Translation::Identity);
+    }
+    match satp {
+        RegisterValue::U32(satp) if satp & SATP_MODE_SV32 == 0 => Ok(Translation::Identity),
+        RegisterValue::U32(satp) => Ok(Translation::Sv32 { satp }),
+        RegisterValue::U64(satp) => match satp >> SATP64_MODE_SHIFT {
+            0 => Ok(Translation::Identity),
+            mode => Err(TranslateError::Unsupported(format!(
+                "RV64 address translation (satp mode {}, Sv39/Sv48) is not supported",
+                mode
+            ))),
+        },
+        RegisterValue::U128(_) => Err(TranslateError::Unsupported(
+            "RV128 address translation is not supported".to_string(),
+        )),
+    }
+}
+
+/// Walk the Sv32 page table of `satp` for `va`: the physical address, or None if the walk
+/// ends in an invalid entry. Permissions and A/D bits are not checked, the debugger sees
+/// every mapped page.
+fn sv32_walk(
+    satp: u32,
+    va: u32,
+    read: &mut impl FnMut(u64) -> Result<u32, Error>,
+) -> Result<Option<u64>, Error> {
+    let vpn = [(va >> 12) & 0x3ff, va >> 22];
+    let mut table = u64::from(satp & SATP_PPN) * PAGE_SIZE;
+    for level in [1, 0] {
+        let pte = read(table + u64::from(vpn[level]) * 4)?;
+        if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+            return Ok(None);
+        }
+        let ppn = u64::from(pte >> 10);
+        if pte & (PTE_R | PTE_X) != 0 {
+            return Ok(match level {
+                0 => Some((ppn * PAGE_SIZE) | u64::from(va & 0xfff)),
+                // A misaligned superpage is a page fault.
+                _ if ppn & 0x3ff != 0 => None,
+                _ => Some(((ppn >> 10) << 22) | u64::from(va & 0x003f_ffff)),
+            });
+        }
+        table = ppn * PAGE_SIZE;
+    }
+    Ok(None)
+}
+
+/// The physical pieces `(physical, offset, len)` of the `len` bytes at `virt`, split at page
+/// boundaries. Everything is translated before the caller touches memory.
+fn physical_ranges(
+    core: &mut Core<'_>,
+    virt: u64,
+    len: usize,
+) -> Result<Vec<(u64, usize, usize)>, TranslateError> {
+    let translation = Translation::of(core)?;
+    let mut read = |address| core.read_word_32(address);
+    let mut out = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        let at = virt + offset as u64;
+        let n = ((PAGE_SIZE - at % PAGE_SIZE) as usize).min(len - offset);
+        out.push((translation.translate(at, &mut read)?, offset, n));
+        offset += n;
+    }
+    Ok(out)
+}
+
+/// Run `op` on a core of the session, returning the C result code.
+fn with_core(
+    session: u64,
+    core_index: u32,
+    op: impl FnOnce(&mut Core<'_>) -> Result<(), TranslateError>,
+) -> i32 {
+    let Ok(sess) = get_session(session) else {
+        set_error("invalid session handle".to_string());
+        return -1;
+    };
+    let mut lock = sess.lock().unwrap();
+    let mut core = match lock.core(core_index as usize) {
+        Ok(core) => core,
+        Err(e) => {
+            set_error(format!("core access error: {}", e));
+            return -1;
+        }
+    };
+    match op(&mut core) {
+        Ok(()) => 0,
+        Err(TranslateError::Unmapped(virt)) => {
+            set_error(format!("virtual address {:#x} is not mapped", virt));
+            1
+        }
+        Err(TranslateError::Target(e)) => {
+            set_error(format!("address translation error: {}", e));
+            -2
+        }
+        Err(TranslateError::Unsupported(e)) => {
+            set_error(e);
+            -3
+        }
+    }
+}
+
+/// Translate the virtual address `virt` of a core to the physical address the debugger uses,
+/// by the core's current MMU state: RISC-V Sv32 page tables for harts halted in S or U mode,
+/// identity for M mode, `satp` Bare and Cortex-M.
+///
+/// Returns 0 with `*out_phys` set, 1 if `virt` is not mapped, -1 on invalid handle or
+/// arguments, -2 on target error, -3 if translation is not supported for the core (Cortex-A,
+/// Xtensa, an RV64 hart with Sv39/Sv48 paging, or a running RISC-V hart).
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_core_translate_address(
+    session: u64,
+    core_index: u32,
+    virt: u64,
+    out_phys: *mut u64,
+) -> i32 {
+    if out_phys.is_null() {
+        set_error("out_phys is null".to_string());
+        return -1;
+    }
+    with_core(session, core_index, |core| {
+        let translation = Translation::of(core)?;
+        let phys = translation.translate(virt, &mut |address| core.read_word_32(address))?;
+        unsafe { *out_phys = phys };
+        Ok(())
+    })
+}
+
+/// Read `len` bytes at the virtual address `virt` as the core sees them, translating each page
+/// as `pr_core_translate_address` does.
+///
+/// Returns 0 on success, 1 if part of the range is not mapped (nothing is read), -1 on invalid
+/// handle or arguments, -2 on target error, -3 if translation is not supported for the core.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_read_mem_virtual(
+    session: u64,
+    core_index: u32,
+    virt: u64,
+    buf: *mut u8,
+    len: u32,
+) -> i32 {
+    if buf.is_null() && len > 0 {
+        set_error("buf is null".to_string());
+        return -1;
+    }
+    with_core(session, core_index, |core| {
+        if len == 0 {
+            return Ok(());
+        }
+        let buf = unsafe { std::slice::from_raw_parts_mut(buf, len as usize) };
+        for (phys, offset, n) in physical_ranges(core, virt, buf.len())? {
+            core.read_8(phys, &mut buf[offset..offset + n])?;
+        }
+        Ok(())
+    })
+}
+
+/// Write `len` bytes of `buf` to the virtual address `virt` as the core sees it, translating each
+/// page as `pr_core_translate_address` does.
+///
+/// Returns 0 on success, 1 if part of the range is not mapped (nothing is written), -1 on
+/// invalid handle or arguments, -2 on target error, -3 if translation is not supported for the
+/// core.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_write_mem_virtual(
+    session: u64,
+    core_index: u32,
+    virt: u64,
+    buf: *const u8,
+    len: u32,
+) -> i32 {
+    if buf.is_null() && len > 0 {
+        set_error("buf is null".to_string());
+        return -1;
+    }
+    with_core(session, core_index, |core| {
+        if len == 0 {
+            return Ok(());
+        }
+        let data = unsafe { std::slice::from_raw_parts(buf, len as usize) };
+        for (phys, offset, n) in physical_ranges(core, virt, data.len())? {
+            core.write_8(phys, &data[offset..offset + n])?;
+        }
+        Ok(())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn pte(phys: u64, flags: u32) -> u32 {
+        (((phys / PAGE_SIZE) as u32) << 10) | flags | PTE_V
+    }
+
+    #[test]
+    fn sv32_page_walk() {
+        // Root table at 0x8000_0000, a second level table at 0x8000_1000.
+        let satp = SATP_MODE_SV32 | 0x80000;
+        let memory: HashMap<u64, u32> = [
+            // 0x0040_0000.. -> second level table.
+            (0x8000_0000 + 4, pte(0x8000_1000, 0)),
+            // 0x0040_3000 -> 0x8020_5000, read/write.
+            (0x8000_1000 + 3 * 4, pte(0x8020_5000, PTE_R | PTE_W)),
+            // 0xc000_0000.. -> 4 MiB superpage at 0x8040_0000.
+            (0x8000_0000 + 0x300 * 4, pte(0x8040_0000, PTE_R | PTE_X)),
+            // 0xc040_0000.. -> misaligned superpage.
+            (0x8000_0000 + 0x301 * 4, pte(0x8040_1000, PTE_R)),
+        ]
+        .into_iter()
+        .collect();
+        let mut read = |address| Ok(memory.get(&address).copied().unwrap_or(0));
+        assert_eq!(
+            sv32_walk(satp, 0x0040_3abc, &mut read).unwrap(),
+            Some(0x8020_5abc)
+        );
+        assert_eq!(
+            sv32_walk(satp, 0xc012_3456, &mut read).unwrap(),
+            Some(0x8052_3456)
+        );
+        assert_eq!(sv32_walk(satp, 0x0040_4000, &mut read).unwrap(), None);
+        assert_eq!(sv32_walk(satp, 0x1000_0000, &mut read).unwrap(), None);
+        assert_eq!(sv32_walk(satp, 0xc040_0000, &mut read).unwrap(), None);
+    }
+
+    #[test]
+    fn machine_mode_and_bare_are_untranslated() {
+        let satp = SATP_MODE_SV32 | 0x80000;
+        let of = |dcsr, satp| riscv_translation(dcsr, satp).ok();
+        assert_eq!(of(3, RegisterValue::U32(satp)), Some(Translation::Identity));
+        assert_eq!(
+            of(1, RegisterValue::U32(0x80000)),
+            Some(Translation::Identity)
+        );
+        assert_eq!(
+            of(1, RegisterValue::U32(satp)),
+            Some(Translation::Sv32 { satp })
+        );
+        assert_eq!(
+            of(0, RegisterValue::U32(satp)),
+            Some(Translation::Sv32 { satp })
+        );
+    }
+
+    #[test]
+    fn rv64_paging_is_unsupported() {
+        assert_eq!(
+            riscv_translation(1, RegisterValue::U64(0x8000)).ok(),
+            Some(Translation::Identity)
+        );
+        // Sv39
+        assert!(matches!(
+            riscv_translation(1, RegisterValue::U64((8 << 60) | 0x8000)),
+            Err(TranslateError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let mut phys = 0u64;
+        assert_eq!(pr_core_translate_address(0xdead, 0, 0x1000, &mut phys), -1);
+        assert_eq!(
+            pr_core_translate_address(0xdead, 0, 0x1000, std::ptr::null_mut()),
+            -1
+        );
+        assert_eq!(
+            pr_read_mem_virtual(0xdead, 0, 0x1000, std::ptr::null_mut(), 4),
+            -1
+        );
+    }
+}
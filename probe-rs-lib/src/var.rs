@@ -0,0 +1,226 @@
+//! Symbolic access to global/static variables using the DWARF debug info of the
+//! firmware image, built on the variable cache of `probe-rs-debug`.
+
+use crate::{cstr_to_string, get_session, set_error, write_c_str};
+use probe_rs::Core;
+use probe_rs_debug::{
+    DebugInfo, DebugRegisters, Variable, VariableCache, VariableName, stack_frame::StackFrameInfo,
+};
+use serde::Serialize;
+use std::ffi::c_char;
+
+#[derive(Serialize)]
+struct VarReport {
+    name: String,
+    #[serde(rename = "type")]
+    type_name: String,
+    address: Option<u64>,
+    size: Option<u64>,
+    value: String,
+}
+
+/// Split an expression like `g_config.modes[2].value` into cache lookup names.
+fn parse_var_path(expr: &str) -> Result<Vec<VariableName>, String> {
+    let mut out = Vec::new();
+    for part in expr.split('.') {
+        let part = part.trim();
+        let (name, mut rest) = match part.find('[') {
+            Some(i) => (&part[..i], &part[i..]),
+            None => (part, ""),
+        };
+        if name.is_empty() {
+            return Err(format!("invalid variable path: {}", expr));
+        }
+        out.push(VariableName::Named(name.to_string()));
+        while let Some(stripped) = rest.strip_prefix('[') {
+            let Some(end) = stripped.find(']') else {
+                return Err(format!("unterminated index in: {}", expr));
+            };
+            let index = stripped[..end]
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| format!("invalid index in: {}", expr))?;
+            out.push(VariableName::Indexed(index));
+            rest = &stripped[end + 1..];
+        }
+        if !rest.is_empty() {
+            return Err(format!("invalid variable path: {}", expr));
+        }
+    }
+    Ok(out)
+}
+
+/// Walk the static scope of `debug_info` along `path`, expanding deferred children on demand.
+fn resolve_static(
+    debug_info: &DebugInfo,
+    core: &mut Core<'_>,
+    expr: &str,
+) -> Result<(Variable, VariableCache), String> {
+    let path = parse_var_path(expr)?;
+    let registers = DebugRegisters::from_core(core);
+    let frame_info = StackFrameInfo {
+        registers: &registers,
+        frame_base: None,
+        canonical_frame_address: None,
+    };
+
+    let mut cache = debug_info.create_static_scope_cache();
+    let mut root = cache.root_variable().clone();
+    debug_info
+        .cache_deferred_variables(&mut cache, core, &mut root, frame_info)
+        .map_err(|e| format!("failed to load static variables: {}", e))?;
+
+    let mut current: Option<Variable> = None;
+    for name in &path {
+        let next = match current.as_mut() {
+            None => cache.get_variable_by_name(name),
+            Some(parent) => {
+                if parent.variable_node_type.is_deferred() && !cache.has_children(parent) {
+                    debug_info
+                        .cache_deferred_variables(&mut cache, core, parent, frame_info)
+                        .map_err(|e| format!("failed to expand {}: {}", parent.name, e))?;
+                }
+                cache.get_variable_by_name_and_parent(name, parent.variable_key())
+            }
+        };
+        let Some(next) = next else {
+            return Err(format!("variable not found: {} (at {})", expr, name));
+        };
+        current = Some(next);
+    }
+    let mut var = current.ok_or_else(|| format!("variable not found: {}", expr))?;
+
+    // Expand one level so structs and arrays render with their members.
+    if var.variable_node_type.is_deferred() && !cache.has_children(&var) {
+        debug_info
+            .cache_deferred_variables(&mut cache, core, &mut var, frame_info)
+            .map_err(|e| format!("failed to expand {}: {}", expr, e))?;
+    }
+    Ok((var, cache))
+}
+
+fn with_static_var<T>(
+    session: u64,
+    core_index: u32,
+    elf_path: &str,
+    expr: &str,
+    f: impl FnOnce(&mut Core<'_>, Variable, VariableCache) -> Result<T, String>,
+) -> Result<T, String> {
+    let debug_info =
+        DebugInfo::from_file(elf_path).map_err(|e| format!("failed to load debug info: {}", e))?;
+    let sess = get_session(session)?;
+    let mut lock = sess.lock().unwrap();
+    let mut core = lock
+        .core(core_index as usize)
+        .map_err(|e| format!("core access error: {}", e))?;
+    let (var, cache) = resolve_static(&debug_info, &mut core, expr)?;
+    f(&mut core, var, cache)
+}
+
+/// Read a global/static variable by name using the debug info in `elf_path`.
+///
+/// `name` may address struct members and array elements, e.g. `g_config.mode` or
+/// `buffers[1].len`. On success a JSON object with `name`, `type`, `address`, `size` and the
+/// rendered `value` is written to `out_json`.
+///
+/// Returns the required size including NUL (only the size if `out_json` is NULL or
+/// `out_json_len` is 0), or 0 on error; see `pr_last_error()`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_var_read(
+    session: u64,
+    core_index: u32,
+    elf_path: *const c_char,
+    name: *const c_char,
+    out_json: *mut c_char,
+    out_json_len: usize,
+) -> usize {
+    let Ok(path) = cstr_to_string(elf_path) else {
+        set_error("invalid elf path".to_string());
+        return 0;
+    };
+    let Ok(expr) = cstr_to_string(name) else {
+        set_error("invalid variable name".to_string());
+        return 0;
+    };
+    let res = with_static_var(session, core_index, &path, &expr, |_, var, cache| {
+        let report = VarReport {
+            name: expr.clone(),
+            type_name: var.type_name(),
+            address: var.memory_location.memory_address().ok(),
+            size: var.byte_size,
+            value: var.to_string(&cache),
+        };
+        serde_json::to_string(&report).map_err(|e| e.to_string())
+    });
+    match res {
+        Ok(json) => write_c_str(&json, out_json, out_json_len),
+        Err(e) => {
+            set_error(e);
+            0
+        }
+    }
+}
+
+/// Write a global/static variable by name using the debug info in `elf_path`.
+///
+/// `value` is parsed according to the variable's DWARF type (integers, floats, bools, chars);
+/// compound types cannot be written as a whole, address their members instead.
+///
+/// Returns 0 on success, -1 on invalid arguments or lookup failure, -2 if the value could
+/// not be written.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_var_write(
+    session: u64,
+    core_index: u32,
+    elf_path: *const c_char,
+    name: *const c_char,
+    value: *const c_char,
+) -> i32 {
+    let Ok(path) = cstr_to_string(elf_path) else {
+        set_error("invalid elf path".to_string());
+        return -1;
+    };
+    let Ok(expr) = cstr_to_string(name) else {
+        set_error("invalid variable name".to_string());
+        return -1;
+    };
+    let Ok(value) = cstr_to_string(value) else {
+        set_error("invalid value".to_string());
+        return -1;
+    };
+    let mut write_failed = false;
+    let res = with_static_var(session, core_index, &path, &expr, |core, var, mut cache| {
+        write_failed = true;
+        var.update_value(core, &mut cache, value)
+            .map_err(|e| format!("write {} error: {}", expr, e))
+    });
+    match res {
+        Ok(()) => 0,
+        Err(e) => {
+            set_error(e);
+            if write_failed { -2 } else { -1 }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn var_path_members_and_indexes() {
+        let path = parse_var_path("g_config.modes[2].value").unwrap();
+        assert_eq!(
+            path,
+            vec![
+                VariableName::Named("g_config".to_string()),
+                VariableName::Named("modes".to_string()),
+                VariableName::Indexed(2),
+                VariableName::Named("value".to_string()),
+            ]
+        );
+        assert!(parse_var_path("a..b").is_err());
+        assert!(parse_var_path("a[1").is_err());
+        assert!(parse_var_path("a[x]").is_err());
+    }
+}
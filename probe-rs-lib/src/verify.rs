@@ -0,0 +1,331 @@
+//! Comparing the memory of a target with an image file without programming anything, e.g. to
+//! audit devices after production, and the verify step of flashing when it checks other bytes
+//! than the programmed pages.
+
+use crate::image::{Segment, load_image, optional_str};
+use crate::{FlashPatch, cstr_to_string, flash_patches_lock, get_session, layout, set_error};
+use probe_rs::config::Target;
+use probe_rs::flashing::{FlashProgress, ProgressEvent, ProgressOperation};
+use probe_rs::{MemoryInterface, Session};
+use std::ffi::c_char;
+use std::ops::Range;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::Instant;
+
+/// Bytes read from the target at a time.
+const CHUNK: usize = 4096;
+
+/// What the verify step after programming compares (`pr_flash_option_verify_mode`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum VerifyMode {
+    /// The programmed pages, as probe-rs verifies them: bytes of a page outside the image are
+    /// skipped, unless they were read back and rewritten (preserved ranges).
+    Pages,
+    /// Exactly the bytes of the image.
+    Written,
+    /// Every sector the image touches: the image bytes, and the erased value elsewhere.
+    Sectors,
+}
+
+impl VerifyMode {
+    fn from_code(code: i32) -> Option<Self> {
+        match code {
+            0 => Some(VerifyMode::Pages),
+            1 => Some(VerifyMode::Written),
+            2 => Some(VerifyMode::Sectors),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            VerifyMode::Pages => "pages",
+            VerifyMode::Written => "written",
+            VerifyMode::Sectors => "sectors",
+        }
+    }
+}
+
+static VERIFY_MODE: AtomicI32 = AtomicI32::new(0);
+
+pub(crate) fn mode() -> VerifyMode {
+    VerifyMode::from_code(VERIFY_MODE.load(Ordering::Relaxed)).unwrap_or(VerifyMode::Pages)
+}
+
+/// Put `patches` over the image as flashing does; patch bytes outside the image become
+/// segments of their own.
+fn apply_patches(segments: &mut Vec<Segment>, patches: &[FlashPatch]) {
+    for (address, data) in patches {
+        let patch = Segment {
+            address: *address,
+            data: data.clone(),
+        };
+        for segment in segments.iter_mut() {
+            let start = segment.address.max(patch.address);
+            let end = segment.range().end.min(patch.range().end);
+            if start < end {
+                let (from, to) = (
+                    (start - patch.address) as usize,
+                    (start - segment.address) as usize,
+                );
+                let len = (end - start) as usize;
+                segment.data[to..to + len].copy_from_slice(&patch.data[from..from + len]);
+            }
+        }
+        segments.push(patch);
+    }
+}
+
+/// The first address where `read` returns other data than `segments`.
+fn first_mismatch(
+    segments: &[Segment],
+    mut read: impl FnMut(u64, &mut [u8]) -> Result<(), String>,
+) -> Result<Option<u64>, String> {
+    let mut buf = [0u8; CHUNK];
+    for segment in segments {
+        for (i, expected) in segment.data.chunks(CHUNK).enumerate() {
+            let address = segment.address + (i * CHUNK) as u64;
+            let actual = &mut buf[..expected.len()];
+            read(address, actual)?;
+            if let Some(offset) = expected.iter().zip(actual.iter()).position(|(a, b)| a != b) {
+                return Ok(Some(address + offset as u64));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// `segments` as `mode` verifies them: the touched flash sectors padded with the erased value
+/// for `Sectors`, data outside flash as it is.
+fn expected(target: &Target, segments: &[Segment], mode: VerifyMode) -> Vec<Segment> {
+    let copy = |s: &Segment| Segment {
+        address: s.address,
+        data: s.data.clone(),
+    };
+    if mode != VerifyMode::Sectors {
+        return segments.iter().map(copy).collect();
+    }
+    let mut spans: Vec<(Range<u64>, u8)> = Vec::new();
+    let mut outside = Vec::new();
+    for segment in segments {
+        let found = layout::sector_spans(target, &segment.range());
+        if found.is_empty() {
+            outside.push(copy(segment));
+        }
+        spans.extend(found);
+    }
+    spans.sort_by_key(|(span, _)| span.start);
+    let mut merged: Vec<(Range<u64>, u8)> = Vec::new();
+    for (span, erased) in spans {
+        match merged.last_mut() {
+            Some((last, _)) if span.start < last.end => last.end = last.end.max(span.end),
+            _ => merged.push((span, erased)),
+        }
+    }
+    let mut checked: Vec<Segment> = merged
+        .into_iter()
+        .map(|(span, erased)| {
+            let mut data = vec![erased; (span.end - span.start) as usize];
+            for segment in segments {
+                let start = segment.address.max(span.start);
+                let end = segment.range().end.min(span.end);
+                if start < end {
+                    let from = (start - segment.address) as usize;
+                    let to = (start - span.start) as usize;
+                    let len = (end - start) as usize;
+                    data[to..to + len].copy_from_slice(&segment.data[from..from + len]);
+                }
+            }
+            Segment {
+                address: span.start,
+                data,
+            }
+        })
+        .collect();
+    checked.extend(outside);
+    checked
+}
+
+/// The verify step of flashing for the modes probe-rs does not do: compare `segments`, the
+/// programmed image, as `mode` says through core 0, reporting progress as a verify phase.
+pub(crate) fn verify_flashed(
+    session: &mut Session,
+    segments: &[Segment],
+    mode: VerifyMode,
+    progress: &mut FlashProgress<'_>,
+) -> Result<(), String> {
+    let checked = expected(session.target(), segments, mode);
+    let total = checked.iter().map(|s| s.data.len() as u64).sum();
+    progress.emit(ProgressEvent::AddProgressBar {
+        operation: ProgressOperation::Verify,
+        total: Some(total),
+    });
+    progress.emit(ProgressEvent::Started(ProgressOperation::Verify));
+    let result = session
+        .core(0)
+        .map_err(|e| format!("core access error: {}", e))
+        .and_then(|mut core| {
+            first_mismatch(&checked, |address, buf| {
+                let start = Instant::now();
+                core.read(address, buf)
+                    .map_err(|e| format!("read error at {:#x}: {}", address, e))?;
+                progress.emit(ProgressEvent::Progress {
+                    operation: ProgressOperation::Verify,
+                    size: buf.len() as u64,
+                    time: start.elapsed(),
+                });
+                Ok(())
+            })
+        });
+    let result = match result {
+        Ok(None) => Ok(()),
+        Ok(Some(address)) => Err(format!(
+            "verification failed: contents differ at {:#x} ({} verify)",
+            address,
+            mode.name()
+        )),
+        Err(e) => Err(e),
+    };
+    progress.emit(match result {
+        Ok(()) => ProgressEvent::Finished(ProgressOperation::Verify),
+        Err(_) => ProgressEvent::Failed(ProgressOperation::Verify),
+    });
+    result
+}
+
+/// Choose what the verify step of `pr_flash_*`, `pr_session_flash` and `pr_gang_flash`
+/// compares after programming: 0 = the programmed pages (default, probe-rs' verify; page bytes
+/// outside the image are skipped unless preserved ranges made them be rewritten), 1 = exactly
+/// the bytes of the image, 2 = every sector the image touches, expecting the erased value
+/// around the image. Mode 2 cannot be combined with preserved ranges, whose bytes are kept. The
+/// mode used is reported by `pr_flash_last_timing`.
+///
+/// Returns 0 on success, -1 on an unknown mode.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_option_verify_mode(mode: i32) -> i32 {
+    if VerifyMode::from_code(mode).is_none() {
+        set_error(format!("unknown verify mode {}", mode));
+        return -1;
+    }
+    VERIFY_MODE.store(mode, Ordering::Relaxed);
+    0
+}
+
+/// Compare the memory of the target with an image file through core `core_index`, without
+/// programming or halting anything. `format` is `"elf"`, `"hex"` or `"bin"`, or NULL/empty
+/// to detect it from the extension; `base` is the load address of BIN images. The flash
+/// patches (`pr_flash_set_patch`) are applied to the image first, as when flashing.
+///
+/// Returns 0 if the contents match, 1 on a mismatch with its first address written to
+/// `out_address` (may be NULL), -1 on invalid handle, arguments or image, -2 if reading the
+/// target failed.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_verify(
+    session: u64,
+    core_index: u32,
+    path: *const c_char,
+    format: *const c_char,
+    base: u64,
+    out_address: *mut u64,
+) -> i32 {
+    let image = cstr_to_string(path).and_then(|path| {
+        let format = optional_str(format)?;
+        load_image(&path, format.as_deref(), base)
+    });
+    let (sess, mut segments) = match (get_session(session), image) {
+        (Ok(s), Ok(image)) => (s, image.segments),
+        (Err(e), _) | (_, Err(e)) => {
+            set_error(e);
+            return -1;
+        }
+    };
+    apply_patches(&mut segments, &flash_patches_lock().lock().unwrap());
+    let mut lock = sess.lock().unwrap();
+    let mut core = match lock.core(core_index as usize) {
+        Ok(core) => core,
+        Err(e) => {
+            set_error(format!("core access error: {}", e));
+            return -1;
+        }
+    };
+    let result = first_mismatch(&segments, |address, buf| {
+        core.read(address, buf)
+            .map_err(|e| format!("read error at {:#x}: {}", address, e))
+    });
+    match result {
+        Ok(None) => 0,
+        Ok(Some(address)) => {
+            set_error(format!("contents differ at {:#x}", address));
+            if !out_address.is_null() {
+                unsafe { *out_address = address };
+            }
+            1
+        }
+        Err(e) => {
+            set_error(e);
+            -2
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_first_difference_after_patches() {
+        let mut segments = vec![Segment {
+            address: 0x1000,
+            data: vec![0xaa; 0x2000],
+        }];
+        apply_patches(&mut segments, &[(0x2ffe, vec![1, 2, 3, 4])]);
+        assert_eq!(&segments[0].data[0x1ffe..], [1, 2]);
+        assert_eq!(segments[1].range(), 0x2ffe..0x3002);
+
+        let mut memory = vec![0xaa; 0x2002];
+        memory[0x1ffe..].copy_from_slice(&[1, 2, 3, 4]);
+        let read = |memory: &Vec<u8>| {
+            let memory = memory.clone();
+            move |address: u64, buf: &mut [u8]| {
+                let at = (address - 0x1000) as usize;
+                buf.copy_from_slice(&memory[at..at + buf.len()]);
+                Ok(())
+            }
+        };
+        assert_eq!(first_mismatch(&segments, read(&memory)), Ok(None));
+        memory[0x1234] = 0;
+        assert_eq!(first_mismatch(&segments, read(&memory)), Ok(Some(0x2234)));
+    }
+    #[test]
+    fn sectors_are_padded_with_the_erased_value() {
+        let target = crate::registry()
+            .get_target_by_name("STM32F407VGTx")
+            .unwrap();
+        let segments = [
+            Segment {
+                address: 0x0800_0010,
+                data: vec![1; 0x10],
+            },
+            Segment {
+                address: 0x2000_0000,
+                data: vec![2; 4],
+            },
+        ];
+        let written = expected(&target, &segments, VerifyMode::Written);
+        assert_eq!(written[0].range(), 0x0800_0010..0x0800_0020);
+
+        // The first F407 sector is 16 KiB; RAM is compared as it is.
+        let sectors = expected(&target, &segments, VerifyMode::Sectors);
+        assert_eq!(sectors[0].range(), 0x0800_0000..0x0800_4000);
+        assert_eq!(sectors[0].data[..0x10], [0xff; 0x10]);
+        assert_eq!(sectors[0].data[0x10..0x20], [1; 0x10]);
+        assert_eq!(sectors[0].data[0x20], 0xff);
+        assert_eq!(sectors[1].range(), 0x2000_0000..0x2000_0004);
+
+        assert_eq!(pr_flash_option_verify_mode(3), -1);
+        assert_eq!(
+            VerifyMode::from_code(2).map(VerifyMode::name),
+            Some("sectors")
+        );
+    }
+}
@@ -0,0 +1,136 @@
+//! UTF-16 variants of the string taking calls, for Windows hosts (C#, Delphi, MFC) whose strings
+//! and paths are `wchar_t` and often not representable in the ANSI code page.
+//!
+//! The strings are converted to UTF-8 and passed on to the regular calls.
+
+use crate::{
+    pr_flash_auto, pr_session_flash, pr_session_open_auto, pr_session_open_with_probe, set_error,
+};
+use std::ffi::{CString, c_char};
+
+/// Convert a NUL terminated UTF-16 string; NULL stays `None` so the regular call reports it.
+fn wide_to_cstring(ptr: *const u16) -> Result<Option<CString>, String> {
+    if ptr.is_null() {
+        return Ok(None);
+    }
+    let len = (0..).take_while(|&i| unsafe { *ptr.add(i) } != 0).count();
+    let units = unsafe { std::slice::from_raw_parts(ptr, len) };
+    let s = String::from_utf16(units).map_err(|_| "invalid UTF-16 string".to_string())?;
+    // Cannot contain NUL, the scan stopped at the first one.
+    Ok(Some(CString::new(s).unwrap()))
+}
+
+fn as_ptr(s: &Option<CString>) -> *const c_char {
+    s.as_ref().map_or(std::ptr::null(), |s| s.as_ptr())
+}
+
+macro_rules! wide_args {
+    ($on_error:expr; $($arg:ident),+) => {
+        $(
+            let $arg = match wide_to_cstring($arg) {
+                Ok(s) => s,
+                Err(e) => {
+                    set_error(e);
+                    return $on_error;
+                }
+            };
+        )+
+    };
+}
+
+/// `pr_flash_auto` with UTF-16 `chip` and `path`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_flash_auto_w(
+    chip: *const u16,
+    path: *const u16,
+    base_address: u64,
+    skip: u32,
+    verify: i32,
+    preverify: i32,
+    chip_erase: i32,
+    speed_khz: u32,
+    protocol_code: i32,
+) -> i32 {
+    wide_args!(1; chip, path);
+    pr_flash_auto(
+        as_ptr(&chip),
+        as_ptr(&path),
+        base_address,
+        skip,
+        verify,
+        preverify,
+        chip_erase,
+        speed_khz,
+        protocol_code,
+    )
+}
+
+/// `pr_session_flash` with a UTF-16 `path`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_flash_w(
+    session: u64,
+    path: *const u16,
+    base_address: u64,
+    skip: u32,
+    verify: i32,
+    preverify: i32,
+    chip_erase: i32,
+) -> i32 {
+    wide_args!(1; path);
+    pr_session_flash(
+        session,
+        as_ptr(&path),
+        base_address,
+        skip,
+        verify,
+        preverify,
+        chip_erase,
+    )
+}
+
+/// `pr_session_open_auto` with a UTF-16 `chip`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_open_auto_w(
+    chip: *const u16,
+    speed_khz: u32,
+    protocol_code: i32,
+) -> u64 {
+    wide_args!(0; chip);
+    pr_session_open_auto(as_ptr(&chip), speed_khz, protocol_code)
+}
+
+/// `pr_session_open_with_probe` with UTF-16 `selector` and `chip`.
+#[unsafe(no_mangle)]
+pub extern "C" fn pr_session_open_with_probe_w(
+    selector: *const u16,
+    chip: *const u16,
+    speed_khz: u32,
+    protocol_code: i32,
+) -> u64 {
+    wide_args!(0; selector, chip);
+    pr_session_open_with_probe(as_ptr(&selector), as_ptr(&chip), speed_khz, protocol_code)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wide(s: &str) -> Vec<u16> {
+        s.encode_utf16().chain([0]).collect()
+    }
+
+    #[test]
+    fn converts_non_ascii_paths() {
+        let path = wide("C:\\固件\\Müller.hex");
+        let converted = wide_to_cstring(path.as_ptr()).unwrap().unwrap();
+        assert_eq!(converted.to_str().unwrap(), "C:\\固件\\Müller.hex");
+        assert_eq!(wide_to_cstring(std::ptr::null()).unwrap(), None);
+    }
+
+    #[test]
+    fn unpaired_surrogate_is_rejected() {
+        let path = [0x0041, 0xd800, 0x0042, 0];
+        assert!(wide_to_cstring(path.as_ptr()).is_err());
+        assert_eq!(pr_session_open_auto_w(path.as_ptr(), 0, 0), 0);
+    }
+}
@@ -1069,6 +1069,18 @@ pub trait ArmDebugSequence: Send + Sync + Debug {
         Ok(())
     }
 
+    /// Prepare the target for flashing with core `core_index`, e.g. disable a watchdog that
+    /// would otherwise reset the target while the flash algorithm runs.
+    ///
+    /// The core is halted when this is called.
+    fn prepare_flashing(
+        &self,
+        _core_index: usize,
+        _session: &mut Session,
+    ) -> Result<(), crate::Error> {
+        Ok(())
+    }
+
     /// This ARM sequence is called if an image was flashed to RAM directly.
     /// It will perform the necessary preparation to run that image.
     ///
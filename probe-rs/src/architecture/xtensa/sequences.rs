@@ -22,6 +22,17 @@ pub trait XtensaDebugSequence: Send + Sync + Debug {
         Ok(())
     }
 
+    /// Prepare the target for flashing, e.g. disable watchdogs that would otherwise reset the
+    /// target while the flash algorithm runs.
+    ///
+    /// The core is halted when this is called.
+    fn prepare_flashing(
+        &self,
+        _interface: &mut XtensaCommunicationInterface,
+    ) -> Result<(), crate::Error> {
+        Ok(())
+    }
+
     /// Detects the flash size of the target.
     fn detect_flash_size(&self, _session: &mut Session) -> Result<Option<usize>, crate::Error> {
         Ok(None)
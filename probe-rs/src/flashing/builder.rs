@@ -216,6 +216,44 @@ impl FlashBuilder {
         Ok(())
     }
 
+    /// Stages a chunk of data that replaces any data already staged at the same addresses.
+    ///
+    /// Parts of the chunk that do not overlap staged data are added like [`Self::add_data`].
+    pub(super) fn patch_data(&mut self, address: u64, data: &[u8]) -> Result<(), FlashError> {
+        let end = address + data.len() as u64;
+        let first = self
+            .data
+            .range(..=address)
+            .next_back()
+            .map_or(address, |(&addr, _)| addr);
+
+        let mut uncovered = Vec::new();
+        let mut cursor = address;
+        for (&chunk_addr, chunk) in self.data.range_mut(first..end) {
+            let chunk_end = chunk_addr + chunk.len() as u64;
+            if chunk_end <= cursor {
+                continue;
+            }
+            let from = chunk_addr.max(cursor);
+            let to = chunk_end.min(end);
+            if from > cursor {
+                uncovered.push(cursor..from);
+            }
+            chunk[(from - chunk_addr) as usize..(to - chunk_addr) as usize]
+                .copy_from_slice(&data[(from - address) as usize..(to - address) as usize]);
+            cursor = to;
+        }
+        if cursor < end {
+            uncovered.push(cursor..end);
+        }
+
+        for range in uncovered {
+            let bytes = &data[(range.start - address) as usize..(range.end - address) as usize];
+            self.add_data(range.start, bytes)?;
+        }
+        Ok(())
+    }
+
     /// Check whether there is staged data for a given address range.
     pub(crate) fn has_data_in_range(&self, range: &Range<u64>) -> bool {
         self.data_in_range(range).next().is_some()
@@ -1144,4 +1182,26 @@ mod tests {
             }
         )
     }
+
+    #[test]
+    fn patch_overwrites_and_fills_gaps() {
+        let mut flash_builder = FlashBuilder::new();
+        flash_builder.add_data(0x10, &[1; 4]).unwrap();
+        flash_builder.add_data(0x18, &[2; 4]).unwrap();
+
+        flash_builder.patch_data(0x12, &[9; 8]).unwrap();
+
+        let data: Vec<(u64, Vec<u8>)> = flash_builder
+            .data
+            .iter()
+            .map(|(addr, data)| (*addr, data.clone()))
+            .collect();
+        assert_eq!(
+            data,
+            vec![
+                (0x10, vec![1, 1, 9, 9, 9, 9, 9, 9]),
+                (0x18, vec![9, 9, 2, 2]),
+            ]
+        );
+    }
 }
@@ -93,6 +93,11 @@ pub enum FlashError {
     /// Failed to reset, and then halt the CPU.
     #[error("Failed to reset, and then halt the CPU.")]
     ResetAndHalt(#[source] error::Error),
+    /// The target specific preparation before flashing failed.
+    #[error(
+        "Failed to prepare the target for flashing (e.g. disabling the watchdog) with its debug sequence."
+    )]
+    PrepareFlashing(#[source] error::Error),
     /// Failed to start running code on the CPU.
     #[error("Failed to start running code on the CPU")]
     Run(#[source] error::Error),
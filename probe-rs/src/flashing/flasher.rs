@@ -164,6 +164,13 @@ impl Flasher {
 
     fn load(&mut self, session: &mut Session) -> Result<(), FlashError> {
         tracing::debug!("Initializing the flash algorithm.");
+        if let Some(timeout) = session.flash_sector_timeout() {
+            let timeout_ms = timeout.as_millis().try_into().unwrap_or(u32::MAX);
+            let properties = &mut self.flash_algorithm.flash_properties;
+            properties.erase_sector_timeout = timeout_ms;
+            properties.program_page_timeout = timeout_ms;
+        }
+        let halt_timeout = session.halt_timeout().unwrap_or(Duration::from_millis(500));
         let algo = &self.flash_algorithm;
 
         // Attach to memory and core.
@@ -171,12 +178,48 @@ impl Flasher {
 
         // TODO: we probably want a full system reset here to make sure peripherals don't interfere.
         tracing::debug!("Reset and halt core {}", self.core_index);
-        core.reset_and_halt(Duration::from_millis(500))
+        core.reset_and_halt(halt_timeout)
             .map_err(FlashError::ResetAndHalt)?;
+        drop(core);
+
+        // Target specific preparation, e.g. disabling watchdogs that would reset the target
+        // while the flash algorithm runs.
+        session
+            .prepare_flashing(self.core_index)
+            .map_err(FlashError::PrepareFlashing)?;
+
+        let fingerprint = Self::fingerprint(algo);
+        let data_start = Self::data_start(algo);
+        let resident = session.flash_algorithm_resident(self.core_index, fingerprint);
+        let mut core = session.core(self.core_index).map_err(FlashError::Core)?;
+
+        if resident && Self::code_intact(&mut core, algo, data_start)? {
+            // The firmware may have changed the algorithm's variables since the last flash.
+            tracing::debug!("Flash algorithm code is still resident, rewriting its data only.");
+            core.write_32(
+                algo.load_address + 4 * data_start as u64,
+                &algo.instructions[data_start..],
+            )
+            .map_err(FlashError::Core)?;
+        } else {
+            if resident {
+                tracing::debug!("Resident flash algorithm was overwritten, downloading it again.");
+            }
+            Self::download(&mut core, algo)?;
+        }
 
-        // TODO: Possible special preparation of the target such as enabling faster clocks for the flash e.g.
+        Self::fill_stack(&mut core, algo)?;
 
-        // Load flash algorithm code into target RAM.
+        tracing::debug!("RAM contents match flashing algo blob.");
+
+        drop(core);
+        session.set_flash_algorithm_resident(self.core_index, fingerprint);
+
+        Ok(())
+    }
+
+    /// Load the whole algorithm blob into target RAM and verify it.
+    fn download(core: &mut Core<'_>, algo: &FlashAlgorithm) -> Result<(), FlashError> {
         tracing::debug!("Downloading algorithm code to {:#010x}", algo.load_address);
 
         core.write(algo.load_address, algo.instructions.as_bytes())
@@ -204,31 +247,68 @@ impl Flasher {
 
             return Err(FlashError::FlashAlgorithmNotLoaded);
         }
+        Ok(())
+    }
 
-        if algo.stack_overflow_check {
-            // Fill the stack with known data.
-            let stack_bottom = algo.stack_top - algo.stack_size;
-            if algo.stack_size & 3 == 0 {
-                let fill = vec![
-                    u32::from_ne_bytes([
-                        STACK_FILL_BYTE,
-                        STACK_FILL_BYTE,
-                        STACK_FILL_BYTE,
-                        STACK_FILL_BYTE
-                    ]);
-                    algo.stack_size as usize / 4
-                ];
-                core.write_32(stack_bottom, &fill)
-                    .map_err(FlashError::Core)?;
-            } else {
-                let fill = vec![STACK_FILL_BYTE; algo.stack_size as usize];
-                core.write_8(stack_bottom, &fill)
-                    .map_err(FlashError::Core)?;
-            }
-        }
+    /// Identifies the loaded algorithm: its blob and where it is loaded, so a different
+    /// algorithm of the same name (or the same one at another address) is never taken for it.
+    fn fingerprint(algo: &FlashAlgorithm) -> u64 {
+        use std::hash::{Hash, Hasher};
+
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        algo.name.hash(&mut hasher);
+        algo.load_address.hash(&mut hasher);
+        algo.static_base.hash(&mut hasher);
+        algo.instructions.hash(&mut hasher);
+        hasher.finish()
+    }
 
-        tracing::debug!("RAM contents match flashing algo blob.");
+    /// Index of the first word of the algorithm's data (initialized and zeroed variables, which
+    /// follow the code in the blob); the whole blob if the static base is not inside it.
+    fn data_start(algo: &FlashAlgorithm) -> usize {
+        algo.static_base
+            .checked_sub(algo.load_address)
+            .map(|offset| (offset / 4) as usize)
+            .filter(|&start| start < algo.instructions.len())
+            .unwrap_or(algo.instructions.len())
+    }
 
+    /// Whether the code of the algorithm (the blob up to its data) is still in RAM, reading
+    /// all of it back.
+    fn code_intact(
+        core: &mut Core<'_>,
+        algo: &FlashAlgorithm,
+        data_start: usize,
+    ) -> Result<bool, FlashError> {
+        let mut data = vec![0u32; data_start];
+        core.read_32(algo.load_address, &mut data)
+            .map_err(FlashError::Core)?;
+        Ok(data == algo.instructions[..data_start])
+    }
+
+    /// Fill the stack with known data for the stack overflow check.
+    fn fill_stack(core: &mut Core<'_>, algo: &FlashAlgorithm) -> Result<(), FlashError> {
+        if !algo.stack_overflow_check {
+            return Ok(());
+        }
+        let stack_bottom = algo.stack_top - algo.stack_size;
+        if algo.stack_size & 3 == 0 {
+            let fill = vec![
+                u32::from_ne_bytes([
+                    STACK_FILL_BYTE,
+                    STACK_FILL_BYTE,
+                    STACK_FILL_BYTE,
+                    STACK_FILL_BYTE
+                ]);
+                algo.stack_size as usize / 4
+            ];
+            core.write_32(stack_bottom, &fill)
+                .map_err(FlashError::Core)?;
+        } else {
+            let fill = vec![STACK_FILL_BYTE; algo.stack_size as usize];
+            core.write_8(stack_bottom, &fill)
+                .map_err(FlashError::Core)?;
+        }
         Ok(())
     }
 
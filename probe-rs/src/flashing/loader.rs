@@ -382,6 +382,20 @@ impl FlashLoader {
         self.builder.add_data(address, data)
     }
 
+    /// Stages a chunk of data, overwriting data already staged at the same addresses.
+    ///
+    /// This is meant for patching per-device data such as serial numbers into a loaded image.
+    pub fn patch_data(&mut self, address: u64, data: &[u8]) -> Result<(), FlashError> {
+        tracing::trace!(
+            "Patching data at address {:#010x} with size {} bytes",
+            address,
+            data.len()
+        );
+
+        self.check_data_in_memory_map(address..address + data.len() as u64)?;
+        self.builder.patch_data(address, data)
+    }
+
     pub(super) fn get_region_for_address(
         memory_map: &[MemoryRegion],
         address: u64,
@@ -99,7 +99,7 @@ pub use crate::core::{
 };
 pub use crate::error::{BreakpointError, Error};
 pub use crate::memory::MemoryInterface;
-pub use crate::session::{Permissions, Session, SessionConfig};
+pub use crate::session::{DetachMode, Permissions, Session, SessionConfig};
 
 #[doc = include_str!("../../README.md")]
 #[cfg(doctest)]
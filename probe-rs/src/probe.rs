@@ -36,6 +36,7 @@ use std::any::Any;
 use std::collections::HashMap;
 use std::fmt;
 use std::sync::Arc;
+use std::time::Duration;
 
 /// Used to log warnings when the measured target voltage is
 /// lower than 1.4V, if at all measurable.
@@ -288,6 +289,7 @@ impl<T: ProbeError> From<T> for ProbeCreationError {
 pub struct Probe {
     inner: Box<dyn DebugProbe>,
     attached: bool,
+    attach_timeout: Option<Duration>,
 }
 
 impl Probe {
@@ -296,6 +298,7 @@ impl Probe {
         Self {
             inner: Box::new(probe),
             attached: false,
+            attach_timeout: None,
         }
     }
 
@@ -303,6 +306,7 @@ impl Probe {
         Self {
             inner: probe,
             attached: true,
+            attach_timeout: None,
         }
     }
 
@@ -311,6 +315,7 @@ impl Probe {
         Probe {
             inner: probe,
             attached: false,
+            attach_timeout: None,
         }
     }
 
@@ -319,6 +324,11 @@ impl Probe {
         self.inner.get_name().to_string()
     }
 
+    /// Get the firmware and hardware versions reported by the probe.
+    pub fn version_info(&mut self) -> ProbeVersionInfo {
+        self.inner.version_info()
+    }
+
     /// Attach to the chip.
     ///
     /// This runs all the necessary protocol init routines.
@@ -495,6 +505,28 @@ impl Probe {
         self.inner.speed_khz()
     }
 
+    /// Set how long attaching under reset waits for the cores to halt, `None` for the default
+    /// of 100 ms. Slowly clocked targets can need longer.
+    pub fn set_attach_timeout(&mut self, timeout: Option<Duration>) {
+        self.attach_timeout = timeout;
+    }
+
+    /// The timeout set with [`Probe::set_attach_timeout`].
+    pub fn attach_timeout(&self) -> Option<Duration> {
+        self.attach_timeout
+    }
+
+    /// Set how often a transfer answered with WAIT is retried before it fails.
+    ///
+    /// Must be called before attaching. Not all probes support this.
+    pub fn set_wait_retries(&mut self, retries: usize) -> Result<(), DebugProbeError> {
+        if !self.attached {
+            self.inner.set_wait_retries(retries)
+        } else {
+            Err(DebugProbeError::Attached)
+        }
+    }
+
     /// Check if the probe has an interface to
     /// debug Xtensa chips.
     pub fn has_xtensa_interface(&self) -> bool {
@@ -659,6 +691,13 @@ pub trait DebugProbe: Any + Send + fmt::Debug {
     ///
     fn set_speed(&mut self, speed_khz: u32) -> Result<u32, DebugProbeError>;
 
+    /// Set how often a transfer answered with WAIT is retried before it fails.
+    fn set_wait_retries(&mut self, _retries: usize) -> Result<(), DebugProbeError> {
+        Err(DebugProbeError::CommandNotSupportedByProbe {
+            command_name: "set_wait_retries",
+        })
+    }
+
     /// Attach to the chip.
     ///
     /// This should run all the necessary protocol init routines.
@@ -688,6 +727,11 @@ pub trait DebugProbe: Any + Send + fmt::Debug {
     /// Get the transport protocol currently in active use by the debug probe.
     fn active_protocol(&self) -> Option<WireProtocol>;
 
+    /// Firmware and hardware versions reported by the probe, as far as the driver knows them.
+    fn version_info(&mut self) -> ProbeVersionInfo {
+        ProbeVersionInfo::default()
+    }
+
     /// Check if the probe offers an interface to debug ARM chips.
     fn has_arm_interface(&self) -> bool {
         false
@@ -793,6 +837,17 @@ impl PartialEq for dyn ProbeFactory {
     }
 }
 
+/// Version information reported by an opened debug probe.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProbeVersionInfo {
+    /// Firmware version, in the notation the probe vendor uses (e.g. `V2J45` for an ST-Link).
+    pub firmware: Option<String>,
+    /// Hardware version or revision.
+    pub hardware: Option<String>,
+    /// Further driver specific details, e.g. capabilities, as `(name, value)` pairs.
+    pub details: Vec<(String, String)>,
+}
+
 /// Gathers some information about a debug probe which was found during a scan.
 #[derive(Debug, Clone, PartialEq)]
 pub struct DebugProbeInfo {
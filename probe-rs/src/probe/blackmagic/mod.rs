@@ -1041,6 +1041,11 @@ impl DebugProbe for BlackMagicProbe {
         Ok(self.speed_khz)
     }
 
+    fn set_wait_retries(&mut self, retries: usize) -> Result<(), DebugProbeError> {
+        self.swd_settings.num_retries_after_wait = retries;
+        Ok(())
+    }
+
     fn attach(&mut self) -> Result<(), DebugProbeError> {
         tracing::debug!("Attaching with protocol '{:?}'", self.protocol);
 
@@ -129,6 +129,11 @@ impl DebugProbe for Ch347UsbJtag {
         Ok(self.device.set_speed_khz(speed_khz))
     }
 
+    fn set_wait_retries(&mut self, retries: usize) -> Result<(), super::DebugProbeError> {
+        self.swd_settings.num_retries_after_wait = retries;
+        Ok(())
+    }
+
     fn attach(&mut self) -> Result<(), super::DebugProbeError> {
         self.device.attach()
     }
@@ -5,6 +5,7 @@ pub mod swj;
 pub mod swo;
 pub mod transfer;
 
+use crate::probe::cmsisdap::CmsisDapTransport;
 use crate::probe::cmsisdap::commands::general::info::PacketSizeCommand;
 use crate::probe::usb_util::InterfaceExt;
 use crate::probe::{ProbeError, WireProtocol};
@@ -55,6 +56,9 @@ pub enum CmsisDapError {
 
     /// The firmware on the probe is outdated, and not supported by probe-rs. The minimum supported firmware version is {0}.
     ProbeFirmwareOutdated(&'static str),
+
+    /// The probe cannot be opened using {0}.
+    TransportNotAvailable(CmsisDapTransport),
 }
 
 impl ProbeError for CmsisDapError {}
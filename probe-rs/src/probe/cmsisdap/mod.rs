@@ -23,10 +23,14 @@ use crate::{
     },
     probe::{
         AutoImplementJtagAccess, BatchCommand, DebugProbe, DebugProbeError, DebugProbeInfo,
-        DebugProbeSelector, JtagAccess, JtagDriverState, ProbeFactory, WireProtocol,
+        DebugProbeSelector, JtagAccess, JtagDriverState, ProbeFactory, ProbeVersionInfo,
+        WireProtocol,
         cmsisdap::commands::{
             CmsisDapError, RequestError,
-            general::info::{CapabilitiesCommand, PacketCountCommand, SWOTraceBufferSizeCommand},
+            general::info::{
+                CapabilitiesCommand, FirmwareVersionCommand, PacketCountCommand,
+                SWOTraceBufferSizeCommand,
+            },
         },
     },
 };
@@ -80,9 +84,12 @@ impl std::fmt::Display for CmsisDapFactory {
 
 impl ProbeFactory for CmsisDapFactory {
     fn open(&self, selector: &DebugProbeSelector) -> Result<Box<dyn DebugProbe>, DebugProbeError> {
-        CmsisDap::new_from_device(tools::open_device_from_selector(selector)?)
-            .map(Box::new)
-            .map(DebugProbe::into_probe)
+        CmsisDap::new_from_device(
+            tools::open_device_from_selector(selector)?,
+            selector.clone(),
+        )
+        .map(Box::new)
+        .map(DebugProbe::into_probe)
     }
 
     fn list_probes(&self) -> Vec<DebugProbeInfo> {
@@ -90,9 +97,19 @@ impl ProbeFactory for CmsisDapFactory {
     }
 }
 
+/// The USB transport used to talk to a CMSIS-DAP probe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, docsplay::Display)]
+pub enum CmsisDapTransport {
+    /// CMSIS-DAP v1 (HID)
+    V1,
+    /// CMSIS-DAP v2 (bulk)
+    V2,
+}
+
 /// A CMSIS-DAP probe.
 pub struct CmsisDap {
     device: CmsisDapDevice,
+    selector: DebugProbeSelector,
     _hw_version: u8,
     _jtag_version: u8,
     protocol: Option<WireProtocol>,
@@ -107,6 +124,8 @@ pub struct CmsisDap {
 
     /// Speed in kHz
     speed_khz: u32,
+    /// Retries of a transfer answered with WAIT, done by the probe itself.
+    wait_retries: u16,
 
     batch: Vec<BatchCommand>,
 
@@ -130,7 +149,10 @@ impl std::fmt::Debug for CmsisDap {
 }
 
 impl CmsisDap {
-    fn new_from_device(mut device: CmsisDapDevice) -> Result<Self, DebugProbeError> {
+    fn new_from_device(
+        mut device: CmsisDapDevice,
+        selector: DebugProbeSelector,
+    ) -> Result<Self, DebugProbeError> {
         // Discard anything left in buffer, as otherwise
         // we'll get out of sync between requests and responses.
         device.drain();
@@ -152,6 +174,7 @@ impl CmsisDap {
 
         Ok(Self {
             device,
+            selector,
             _hw_version: 0,
             _jtag_version: 0,
             protocol: None,
@@ -163,12 +186,39 @@ impl CmsisDap {
             swo_streaming: false,
             connected: false,
             speed_khz: 1_000,
+            wait_retries: 0xffff,
             batch: Vec::new(),
             jtag_state: JtagDriverState::default(),
             jtag_buffer: JtagBuffer::new(packet_size - 1),
         })
     }
 
+    /// The transport the probe was opened with. v1 is picked when v2 is not available, and
+    /// limits the throughput considerably.
+    pub fn transport(&self) -> CmsisDapTransport {
+        match self.device {
+            #[cfg(feature = "cmsisdap_v1")]
+            CmsisDapDevice::V1 { .. } => CmsisDapTransport::V1,
+            CmsisDapDevice::V2 { .. } => CmsisDapTransport::V2,
+        }
+    }
+
+    /// Reopen the probe using `transport`. Must be called before attaching.
+    pub fn set_transport(&mut self, transport: CmsisDapTransport) -> Result<(), DebugProbeError> {
+        if self.transport() == transport {
+            return Ok(());
+        }
+        let device = tools::open_device_with_transport(&self.selector, Some(transport))?;
+        let protocol = self.protocol;
+        let speed_khz = self.speed_khz;
+        let wait_retries = self.wait_retries;
+        *self = Self::new_from_device(device, self.selector.clone())?;
+        self.protocol = protocol;
+        self.speed_khz = speed_khz;
+        self.wait_retries = wait_retries;
+        Ok(())
+    }
+
     /// Set maximum JTAG/SWD clock frequency to use, in Hz.
     ///
     /// The actual clock frequency used by the device might be lower.
@@ -830,6 +880,11 @@ impl DebugProbe for CmsisDap {
         Ok(speed_khz)
     }
 
+    fn set_wait_retries(&mut self, retries: usize) -> Result<(), DebugProbeError> {
+        self.wait_retries = retries.try_into().unwrap_or(u16::MAX);
+        Ok(())
+    }
+
     /// Enters debug mode.
     #[tracing::instrument(skip(self))]
     fn attach(&mut self) -> Result<(), DebugProbeError> {
@@ -843,7 +898,7 @@ impl DebugProbe for CmsisDap {
 
         self.transfer_configure(ConfigureRequest {
             idle_cycles: 0,
-            wait_retry: 0xffff,
+            wait_retry: self.wait_retries,
             match_retry: 0,
         })?;
 
@@ -907,6 +962,41 @@ impl DebugProbe for CmsisDap {
         self.protocol
     }
 
+    fn version_info(&mut self) -> ProbeVersionInfo {
+        let firmware = commands::send_command(&mut self.device, &FirmwareVersionCommand {})
+            .ok()
+            .flatten();
+        let interface = match self.transport() {
+            CmsisDapTransport::V1 => "v1 (HID)",
+            CmsisDapTransport::V2 => "v2 (bulk)",
+        };
+        let caps = self.capabilities;
+        let details = [
+            ("interface", interface.to_string()),
+            ("packet_size", self.packet_size.to_string()),
+            ("packet_count", self.packet_count.to_string()),
+            ("swd", caps.swd_implemented.to_string()),
+            ("jtag", caps.jtag_implemented.to_string()),
+            ("swo_uart", caps.swo_uart_implemented.to_string()),
+            (
+                "swo_manchester",
+                caps.swo_manchester_implemented.to_string(),
+            ),
+            (
+                "swo_streaming",
+                caps.swo_streaming_trace_implemented.to_string(),
+            ),
+        ];
+        ProbeVersionInfo {
+            firmware,
+            hardware: None,
+            details: details
+                .into_iter()
+                .map(|(name, value)| (name.to_string(), value))
+                .collect(),
+        }
+    }
+
     /// Asserts the nRESET pin.
     fn target_reset(&mut self) -> Result<(), DebugProbeError> {
         commands::send_command(&mut self.device, &ResetRequest).map(|v: ResetResponse| {
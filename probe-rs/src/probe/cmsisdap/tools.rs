@@ -1,7 +1,7 @@
 use super::CmsisDapDevice;
 use crate::probe::{
     BoxedProbeError, DebugProbeInfo, DebugProbeSelector, ProbeCreationError,
-    cmsisdap::{CmsisDapFactory, commands::CmsisDapError},
+    cmsisdap::{CmsisDapFactory, CmsisDapTransport, commands::CmsisDapError},
 };
 #[cfg(feature = "cmsisdap_v1")]
 use hidapi::HidApi;
@@ -287,6 +287,14 @@ fn reject_probe_by_version(
 /// otherwise in v1 mode.
 pub fn open_device_from_selector(
     selector: &DebugProbeSelector,
+) -> Result<CmsisDapDevice, ProbeCreationError> {
+    open_device_with_transport(selector, None)
+}
+
+/// Like [`open_device_from_selector`], but only using `transport` if one is given.
+pub fn open_device_with_transport(
+    selector: &DebugProbeSelector,
+    transport: Option<CmsisDapTransport>,
 ) -> Result<CmsisDapDevice, ProbeCreationError> {
     tracing::trace!("Attempting to open device matching {}", selector);
 
@@ -311,7 +319,7 @@ pub fn open_device_from_selector(
                 if selector.matches(&device) {
                     hid_device_info = get_cmsisdap_info(&device);
 
-                    if hid_device_info.is_some() {
+                    if hid_device_info.is_some() && transport != Some(CmsisDapTransport::V1) {
                         // If the VID, PID, and potentially SN all match,
                         // and the device is a valid CMSIS-DAP probe,
                         // attempt to open the device in v2 mode.
@@ -327,6 +335,13 @@ pub fn open_device_from_selector(
         }
     }
 
+    match transport {
+        Some(CmsisDapTransport::V2) => return Err(transport_not_available(CmsisDapTransport::V2)),
+        #[cfg(not(feature = "cmsisdap_v1"))]
+        Some(CmsisDapTransport::V1) => return Err(transport_not_available(CmsisDapTransport::V1)),
+        _ => {}
+    }
+
     #[cfg(not(feature = "cmsisdap_v1"))]
     return Err(ProbeCreationError::NotFound);
 
@@ -401,6 +416,12 @@ pub fn open_device_from_selector(
     }
 }
 
+fn transport_not_available(transport: CmsisDapTransport) -> ProbeCreationError {
+    ProbeCreationError::ProbeSpecific(BoxedProbeError::from(CmsisDapError::TransportNotAvailable(
+        transport,
+    )))
+}
+
 /// We recognise cmsis dap interfaces if they have string like "CMSIS-DAP"
 /// in them. As devices spell CMSIS DAP differently we go through known
 /// spellings/patterns looking for a match
@@ -33,10 +33,52 @@ mod ftdaye;
 use command_compacter::Command;
 use ftdaye::{ChipType, error::FtdiError};
 
+/// The JTAG signals of the MPSSE engine: TCK, TDI and TMS are outputs, TDO an input.
+const JTAG_PIN_MASK: u16 = 0x000f;
+const JTAG_PIN_DIRECTION: u16 = 0x000b;
+
+/// The channel of a multi-channel FTDI chip (FT2232, FT4232) the JTAG signals are wired to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FtdiChannel {
+    /// Channel A (`ADBUS`/`ACBUS`), the default.
+    #[default]
+    A,
+    /// Channel B (`BDBUS`/`BCBUS`).
+    B,
+    /// Channel C; the FT4232H has no MPSSE on channels C and D.
+    C,
+    /// Channel D.
+    D,
+}
+
+impl From<FtdiChannel> for ftdaye::Interface {
+    fn from(channel: FtdiChannel) -> Self {
+        match channel {
+            FtdiChannel::A => ftdaye::Interface::A,
+            FtdiChannel::B => ftdaye::Interface::B,
+            FtdiChannel::C => ftdaye::Interface::C,
+            FtdiChannel::D => ftdaye::Interface::D,
+        }
+    }
+}
+
+/// A GPIO of the MPSSE channel wired to the target's reset line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FtdiResetPin {
+    /// Pin number: 0-7 for `xDBUS0-7`, 8-15 for `xCBUS0-7`. Pins 0-3 carry the JTAG signals.
+    pub pin: u8,
+    /// Whether the reset is asserted by driving the pin low (nRESET).
+    pub active_low: bool,
+}
+
 #[derive(Debug)]
 struct JtagAdapter {
     device: ftdaye::Device,
     speed_khz: u32,
+    /// Initial pin levels and directions set by the user, replacing the built-in layout.
+    custom_layout: Option<(u16, u16)>,
+    /// Current pin levels and directions.
+    pins: (u16, u16),
 
     command: Command,
     commands: Vec<u8>,
@@ -46,9 +88,13 @@ struct JtagAdapter {
 }
 
 impl JtagAdapter {
-    fn open(ftdi: FtdiDevice, usb_device: DeviceInfo) -> Result<Self, DebugProbeError> {
+    fn open(
+        ftdi: FtdiDevice,
+        usb_device: DeviceInfo,
+        channel: FtdiChannel,
+    ) -> Result<Self, DebugProbeError> {
         let device = ftdaye::Builder::new()
-            .with_interface(ftdaye::Interface::A)
+            .with_interface(channel.into())
             .with_read_timeout(Duration::from_secs(5))
             .with_write_timeout(Duration::from_secs(5))
             .usb_open(usb_device)?;
@@ -58,6 +104,8 @@ impl JtagAdapter {
         Ok(Self {
             device,
             speed_khz: 1000,
+            custom_layout: None,
+            pins: (0, 0),
             command: Command::default(),
             commands: vec![],
             in_bit_counts: vec![],
@@ -76,8 +124,8 @@ impl JtagAdapter {
         let mut junk = vec![];
         let _ = self.device.read_to_end(&mut junk);
 
-        let (output, direction) = self.pin_layout();
-        self.device.set_pins(output, direction)?;
+        self.pins = self.pin_layout();
+        self.device.set_pins(self.pins.0, self.pins.1)?;
 
         self.apply_clock_speed(self.speed_khz)?;
 
@@ -87,6 +135,9 @@ impl JtagAdapter {
     }
 
     fn pin_layout(&self) -> (u16, u16) {
+        if let Some(layout) = self.custom_layout {
+            return layout;
+        }
         let (output, direction) = match (
             self.device.vendor_id(),
             self.device.product_id(),
@@ -106,6 +157,21 @@ impl JtagAdapter {
         (output, direction)
     }
 
+    /// Drive `pin` to `level` as an output, keeping the other pins.
+    fn set_pin(&mut self, pin: u8, level: bool) -> Result<(), DebugProbeError> {
+        // Queued JTAG commands go out first, so the pin change happens in order.
+        self.flush()?;
+        let mask = 1u16 << pin;
+        let (output, direction) = self.pins;
+        self.pins = (
+            if level { output | mask } else { output & !mask },
+            direction | mask,
+        );
+        self.device
+            .set_pins(self.pins.0, self.pins.1)
+            .map_err(DebugProbeError::from)
+    }
+
     fn speed_khz(&self) -> u32 {
         self.speed_khz
     }
@@ -297,8 +363,13 @@ impl ProbeFactory for FtdiProbeFactory {
             tracing::warn!("More than one matching FTDI probe was found. Opening the first one.");
         }
 
+        let usb_device = probes.pop().unwrap();
         let probe = FtdiProbe {
-            adapter: JtagAdapter::open(ftdi, probes.pop().unwrap())?,
+            adapter: JtagAdapter::open(ftdi, usb_device.clone(), FtdiChannel::A)?,
+            ftdi,
+            usb_device,
+            channel: FtdiChannel::A,
+            reset_pin: None,
             jtag_state: JtagDriverState::default(),
             swd_settings: SwdSettings::default(),
             probe_statistics: ProbeStatistics::default(),
@@ -316,11 +387,69 @@ impl ProbeFactory for FtdiProbeFactory {
 #[derive(Debug)]
 pub struct FtdiProbe {
     adapter: JtagAdapter,
+    ftdi: FtdiDevice,
+    usb_device: DeviceInfo,
+    channel: FtdiChannel,
+    reset_pin: Option<FtdiResetPin>,
     jtag_state: JtagDriverState,
     probe_statistics: ProbeStatistics,
     swd_settings: SwdSettings,
 }
 
+impl FtdiProbe {
+    /// Use another channel of the FTDI chip, for boards that wire JTAG to channel B (or C, D).
+    /// Must be called before attaching; the pin layout and speed carry over.
+    pub fn set_channel(&mut self, channel: FtdiChannel) -> Result<(), DebugProbeError> {
+        if channel == self.channel {
+            return Ok(());
+        }
+        let mut adapter = JtagAdapter::open(self.ftdi, self.usb_device.clone(), channel)?;
+        adapter.speed_khz = self.adapter.speed_khz;
+        adapter.custom_layout = self.adapter.custom_layout;
+        self.adapter = adapter;
+        self.channel = channel;
+        Ok(())
+    }
+
+    /// Set the initial levels and directions (1 = output) of the channel's pins, bits 0-7 for
+    /// `xDBUS0-7` and 8-15 for `xCBUS0-7`, e.g. to enable the output buffers of a board. This
+    /// replaces the built-in layout of known adapters; must be called before attaching.
+    ///
+    /// The MPSSE engine fixes TCK, TDI, TDO and TMS to pins 0-3; their bits are ignored.
+    pub fn set_pin_layout(&mut self, output: u16, direction: u16) {
+        let output = (output & !JTAG_PIN_MASK) | 0x0008;
+        let direction = (direction & !JTAG_PIN_MASK) | JTAG_PIN_DIRECTION;
+        self.adapter.custom_layout = Some((output, direction));
+    }
+
+    /// Use a GPIO for target resets; without one, resets through the probe are not supported.
+    pub fn set_reset_pin(
+        &mut self,
+        reset_pin: Option<FtdiResetPin>,
+    ) -> Result<(), DebugProbeError> {
+        if let Some(reset) = reset_pin
+            && (reset.pin < 4 || reset.pin > 15)
+        {
+            return Err(DebugProbeError::Other(format!(
+                "FTDI pin {} cannot be used for reset",
+                reset.pin
+            )));
+        }
+        self.reset_pin = reset_pin;
+        Ok(())
+    }
+
+    fn drive_reset(&mut self, asserted: bool) -> Result<(), DebugProbeError> {
+        let Some(reset) = self.reset_pin else {
+            return Err(DebugProbeError::NotImplemented {
+                function_name: "target_reset",
+            });
+        };
+        self.adapter
+            .set_pin(reset.pin, asserted != reset.active_low)
+    }
+}
+
 impl DebugProbe for FtdiProbe {
     fn get_name(&self) -> &str {
         "FTDI"
@@ -334,6 +463,11 @@ impl DebugProbe for FtdiProbe {
         Ok(self.adapter.set_speed_khz(speed_khz))
     }
 
+    fn set_wait_retries(&mut self, retries: usize) -> Result<(), DebugProbeError> {
+        self.swd_settings.num_retries_after_wait = retries;
+        Ok(())
+    }
+
     fn attach(&mut self) -> Result<(), DebugProbeError> {
         tracing::debug!("Attaching...");
 
@@ -346,23 +480,19 @@ impl DebugProbe for FtdiProbe {
     }
 
     fn target_reset(&mut self) -> Result<(), DebugProbeError> {
-        // TODO we could add this by using a GPIO. However, different probes may connect
-        // different pins (if any) to the reset line, so we would need to make this configurable.
-        Err(DebugProbeError::NotImplemented {
-            function_name: "target_reset",
-        })
+        // Different probes connect different pins (if any) to the reset line, see
+        // `set_reset_pin`.
+        self.drive_reset(true)?;
+        std::thread::sleep(Duration::from_millis(20));
+        self.drive_reset(false)
     }
 
     fn target_reset_assert(&mut self) -> Result<(), DebugProbeError> {
-        Err(DebugProbeError::NotImplemented {
-            function_name: "target_reset_assert",
-        })
+        self.drive_reset(true)
     }
 
     fn target_reset_deassert(&mut self) -> Result<(), DebugProbeError> {
-        Err(DebugProbeError::NotImplemented {
-            function_name: "target_reset_deassert",
-        })
+        self.drive_reset(false)
     }
 
     fn select_protocol(&mut self, protocol: WireProtocol) -> Result<(), DebugProbeError> {
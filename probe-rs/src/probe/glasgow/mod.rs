@@ -171,6 +171,15 @@ impl GlasgowDevice {
     }
 }
 
+/// An I/O bank of the Glasgow Interface Explorer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GlasgowPort {
+    /// Port A.
+    A,
+    /// Port B.
+    B,
+}
+
 /// A Glasgow Interface Explorer device.
 pub struct Glasgow {
     device: GlasgowDevice,
@@ -196,6 +205,26 @@ impl Glasgow {
     }
 }
 
+impl Glasgow {
+    /// Set the I/O voltage of `ports` to the target's logic level; 0 mV turns the supply off.
+    ///
+    /// The applet's pins are chosen when the Glasgow toolkit builds its bitstream; this only
+    /// powers the bank they are on. Only available for probes connected over USB.
+    pub fn set_io_voltage(
+        &mut self,
+        ports: &[GlasgowPort],
+        millivolts: u16,
+    ) -> Result<(), DebugProbeError> {
+        let mask = ports.iter().fold(0, |mask, port| {
+            mask | match port {
+                GlasgowPort::A => 0b01,
+                GlasgowPort::B => 0b10,
+            }
+        });
+        self.device.set_io_voltage(mask, millivolts)
+    }
+}
+
 impl DebugProbe for Glasgow {
     fn get_name(&self) -> &str {
         "Glasgow Interface Explorer"
@@ -107,6 +107,19 @@ impl GlasgowDevice {
         }
     }
 
+    pub fn set_io_voltage(
+        &mut self,
+        port_mask: u16,
+        millivolts: u16,
+    ) -> Result<(), DebugProbeError> {
+        match &*self.inner.borrow() {
+            GlasgowDeviceInner::Usb(usb_device) => usb_device.set_io_voltage(port_mask, millivolts),
+            GlasgowDeviceInner::Net(_) => Err(DebugProbeError::Other(
+                "the I/O voltage of a Glasgow connected over the network is set by the host running the applet".to_string(),
+            )),
+        }
+    }
+
     fn collect_out_data(&self) -> Vec<u8> {
         let mut out_buffers = self.out_buffers.borrow_mut();
         // The (lack of) scheduling in this function has the potential for head-of-line blocking.
@@ -4,8 +4,9 @@ use async_io::block_on;
 use futures_lite::future;
 use nusb::{
     Interface, MaybeFuture,
-    transfer::{Buffer, Bulk, Direction, In, Out},
+    transfer::{Buffer, Bulk, ControlOut, ControlType, Direction, In, Out, Recipient},
 };
+use std::time::Duration;
 
 use crate::probe::{
     DebugProbeError, DebugProbeSelector, ProbeCreationError,
@@ -15,6 +16,9 @@ use crate::probe::{
 pub(super) const VID_QIHW: u16 = 0x20b7;
 pub(super) const PID_GLASGOW: u16 = 0x9db1;
 
+/// Vendor request of the Glasgow firmware setting the supply of the I/O banks.
+const REQ_IO_VOLT: u8 = 0x11;
+
 pub struct GlasgowUsbDevice {
     out_iface: Interface,
     in_iface: Interface,
@@ -109,6 +113,24 @@ impl GlasgowUsbDevice {
         })
     }
 
+    /// Set the supply of the I/O banks in `port_mask` (bit 0 = A, bit 1 = B); 0 mV turns it off.
+    pub fn set_io_voltage(&self, port_mask: u16, millivolts: u16) -> Result<(), DebugProbeError> {
+        self.out_iface
+            .control_out(
+                ControlOut {
+                    control_type: ControlType::Vendor,
+                    recipient: Recipient::Device,
+                    request: REQ_IO_VOLT,
+                    value: 0,
+                    index: port_mask,
+                    data: &millivolts.to_le_bytes(),
+                },
+                Duration::from_secs(1),
+            )
+            .wait()
+            .map_err(|e| DebugProbeError::Usb(e.into()))
+    }
+
     pub fn transfer(
         &mut self,
         output: Vec<u8>,
@@ -44,8 +44,8 @@ use crate::{
     },
     probe::{
         DebugProbe, DebugProbeError, DebugProbeInfo, DebugProbeSelector, IoSequenceItem,
-        JtagDriverState, ProbeFactory, ProbeStatistics, RawJtagIo, RawSwdIo, SwdSettings,
-        WireProtocol,
+        JtagDriverState, ProbeFactory, ProbeStatistics, ProbeVersionInfo, RawJtagIo, RawSwdIo,
+        SwdSettings, WireProtocol,
     },
 };
 
@@ -190,6 +190,7 @@ impl ProbeFactory for JLinkFactory {
             connection_handle: None,
 
             swo_config: None,
+            swo_speed: None,
             speed_khz: 0, // default is unknown
             swd_settings: SwdSettings::default(),
             probe_statistics: ProbeStatistics::default(),
@@ -375,6 +376,8 @@ pub struct JLink {
     connection_handle: Option<u16>,
 
     swo_config: Option<SwoConfig>,
+    /// SWO capture baud rate set by the user, replacing the one of the SWO configuration.
+    swo_speed: Option<u32>,
 
     /// Protocols supported by the probe.
     supported_protocols: Vec<WireProtocol>,
@@ -876,6 +879,12 @@ impl JLink {
         self.write_cmd(&[Command::SetKsPower as u8, if enable { 1 } else { 0 }])
     }
 
+    /// Capture SWO at `baud` instead of the baud rate of the SWO configuration, for firmware that
+    /// sets up the TPIU itself. `None` restores the default.
+    pub fn set_swo_speed(&mut self, baud: Option<u32>) {
+        self.swo_speed = baud;
+    }
+
     fn register_connection(&mut self) -> Result<u16, JlinkError> {
         if !self.caps.contains(Capability::Register) {
             return Ok(0);
@@ -972,6 +981,14 @@ impl DebugProbe for JLink {
         Some(self.protocol)
     }
 
+    fn version_info(&mut self) -> ProbeVersionInfo {
+        ProbeVersionInfo {
+            firmware: self.read_firmware_version().ok(),
+            hardware: self.read_hardware_version().ok().map(|v| v.to_string()),
+            details: vec![("capabilities".to_string(), format!("{:?}", self.caps))],
+        }
+    }
+
     fn get_name(&self) -> &'static str {
         "J-Link"
     }
@@ -1005,6 +1022,11 @@ impl DebugProbe for JLink {
         Ok(speed_khz)
     }
 
+    fn set_wait_retries(&mut self, retries: usize) -> Result<(), DebugProbeError> {
+        self.swd_settings.num_retries_after_wait = retries;
+        Ok(())
+    }
+
     fn attach(&mut self) -> Result<(), DebugProbeError> {
         tracing::debug!("Attaching to J-Link");
 
@@ -1237,7 +1259,8 @@ impl DapProbe for JLink {}
 impl SwoAccess for JLink {
     fn enable_swo(&mut self, config: &SwoConfig) -> Result<(), ArmError> {
         self.swo_config = Some(*config);
-        self.swo_start(SwoMode::Uart, config.baud(), SWO_BUFFER_SIZE.into())
+        let baud = self.swo_speed.unwrap_or(config.baud());
+        self.swo_start(SwoMode::Uart, baud, SWO_BUFFER_SIZE.into())
             .map_err(DebugProbeError::from)?;
         Ok(())
     }
@@ -21,7 +21,7 @@ use crate::{
     },
     probe::{
         DebugProbe, DebugProbeError, DebugProbeInfo, DebugProbeSelector, Probe, ProbeError,
-        ProbeFactory, WireProtocol,
+        ProbeFactory, ProbeVersionInfo, WireProtocol,
     },
 };
 
@@ -70,6 +70,7 @@ impl ProbeFactory for StLinkFactory {
             swd_speed_khz: 1_800,
             jtag_speed_khz: 1_120,
             swo_enabled: false,
+            wait_retries: DEFAULT_WAIT_RETRIES,
 
             opened_aps: vec![],
         };
@@ -95,6 +96,8 @@ pub struct StLink<D: StLinkUsb> {
     swd_speed_khz: u32,
     jtag_speed_khz: u32,
     swo_enabled: bool,
+    /// Attempts of a command answered with `SwdDpWait` or `SwdApWait`.
+    wait_retries: usize,
 
     /// List of opened APs
     opened_aps: Vec<u8>,
@@ -163,6 +166,11 @@ impl DebugProbe for StLink<StLinkUsbDevice> {
         }
     }
 
+    fn set_wait_retries(&mut self, retries: usize) -> Result<(), DebugProbeError> {
+        self.wait_retries = retries;
+        Ok(())
+    }
+
     #[tracing::instrument(skip(self))]
     fn attach(&mut self) -> Result<(), DebugProbeError> {
         self.enter_idle()?;
@@ -287,6 +295,14 @@ impl DebugProbe for StLink<StLinkUsbDevice> {
         Some(self.protocol)
     }
 
+    fn version_info(&mut self) -> ProbeVersionInfo {
+        ProbeVersionInfo {
+            firmware: Some(format!("V{}J{}", self.hw_version, self.jtag_version)),
+            hardware: Some(format!("V{}", self.hw_version)),
+            details: vec![("jtag_version".to_string(), self.jtag_version.to_string())],
+        }
+    }
+
     fn get_swo_interface(&self) -> Option<&dyn SwoAccess> {
         Some(self as _)
     }
@@ -685,7 +701,7 @@ impl<D: StLinkUsb> StLink<D> {
 
         let mut buf = [0; 2];
         tracing::trace!("JTAG_INIT_AP {}", apsel);
-        retry_on_wait(|| {
+        retry_on_wait(self.wait_retries, || {
             self.send_jtag_command(
                 &[commands::JTAG_COMMAND, commands::JTAG_INIT_AP, apsel],
                 &[],
@@ -711,7 +727,7 @@ impl<D: StLinkUsb> StLink<D> {
 
         let mut buf = [0; 2];
         tracing::trace!("JTAG_CLOSE_AP {}", apsel);
-        retry_on_wait(|| {
+        retry_on_wait(self.wait_retries, || {
             self.send_jtag_command(
                 &[commands::JTAG_COMMAND, commands::JTAG_CLOSE_AP_DBG, apsel],
                 &[],
@@ -824,7 +840,9 @@ impl<D: StLinkUsb> StLink<D> {
             0, // Maximum address for DAP registers is 0xFC
         ];
         let mut buf = [0; 8];
-        retry_on_wait(|| self.send_jtag_command(cmd, &[], &mut buf, TIMEOUT))?;
+        retry_on_wait(self.wait_retries, || {
+            self.send_jtag_command(cmd, &[], &mut buf, TIMEOUT)
+        })?;
         // Unwrap is ok!
         Ok(buf[4..8].pread_with(0, LE).unwrap())
     }
@@ -848,7 +866,9 @@ impl<D: StLinkUsb> StLink<D> {
         ];
         let mut buf = [0; 2];
 
-        retry_on_wait(|| self.send_jtag_command(cmd, &[], &mut buf, TIMEOUT))?;
+        retry_on_wait(self.wait_retries, || {
+            self.send_jtag_command(cmd, &[], &mut buf, TIMEOUT)
+        })?;
 
         Ok(())
     }
@@ -883,7 +903,7 @@ impl<D: StLinkUsb> StLink<D> {
             return Err(DebugProbeError::from(StlinkError::UnalignedAddress));
         }
 
-        retry_on_wait(|| {
+        retry_on_wait(self.wait_retries, || {
             self.device.write(
                 &memory_command(commands::JTAG_READMEM_32BIT, address, data.len(), apsel),
                 &[],
@@ -924,7 +944,7 @@ impl<D: StLinkUsb> StLink<D> {
             return Err(DebugProbeError::from(StlinkError::UnalignedAddress));
         }
 
-        retry_on_wait(|| {
+        retry_on_wait(self.wait_retries, || {
             self.device.write(
                 &memory_command(commands::JTAG_READMEM_16BIT, address, data.len(), apsel),
                 &[],
@@ -979,7 +999,7 @@ impl<D: StLinkUsb> StLink<D> {
 
         tracing::trace!("Read mem 8 bit, address={:08x}, length={}", address, length);
 
-        retry_on_wait(|| {
+        retry_on_wait(self.wait_retries, || {
             self.device.write(
                 &memory_command(commands::JTAG_READMEM_8BIT, address, length as usize, apsel),
                 &[],
@@ -1028,7 +1048,7 @@ impl<D: StLinkUsb> StLink<D> {
             return Err(DebugProbeError::from(StlinkError::UnalignedAddress));
         }
 
-        retry_on_wait(|| {
+        retry_on_wait(self.wait_retries, || {
             self.device.write(
                 &memory_command(commands::JTAG_WRITEMEM_32BIT, address, data.len(), apsel),
                 data,
@@ -1068,7 +1088,7 @@ impl<D: StLinkUsb> StLink<D> {
             return Err(DebugProbeError::from(StlinkError::UnalignedAddress));
         }
 
-        retry_on_wait(|| {
+        retry_on_wait(self.wait_retries, || {
             self.device.write(
                 &memory_command(commands::JTAG_WRITEMEM_16BIT, address, data.len(), apsel),
                 data,
@@ -1110,7 +1130,7 @@ impl<D: StLinkUsb> StLink<D> {
             );
         }
 
-        retry_on_wait(|| {
+        retry_on_wait(self.wait_retries, || {
             self.device.write(
                 &memory_command(commands::JTAG_WRITEMEM_8BIT, address, data.len(), apsel),
                 data,
@@ -1852,9 +1872,14 @@ fn is_wait_error(e: &StlinkError) -> bool {
     )
 }
 
-fn retry_on_wait<R>(mut f: impl FnMut() -> Result<R, StlinkError>) -> Result<R, StlinkError> {
+const DEFAULT_WAIT_RETRIES: usize = 13;
+
+fn retry_on_wait<R>(
+    retries: usize,
+    mut f: impl FnMut() -> Result<R, StlinkError>,
+) -> Result<R, StlinkError> {
     let mut last_err = None;
-    for attempt in 0..13 {
+    for attempt in 0..retries.max(1) {
         match f() {
             Ok(res) => return Ok(res),
             Err(e) => {
@@ -1868,7 +1893,7 @@ fn retry_on_wait<R>(mut f: impl FnMut() -> Result<R, StlinkError>) -> Result<R,
         }
 
         // Sleep with exponential backoff.
-        thread::sleep(Duration::from_micros(100 << attempt));
+        thread::sleep(Duration::from_micros(100 << attempt.min(12)));
     }
 
     tracing::warn!("too many retries, giving up");
@@ -1902,6 +1927,7 @@ mod test {
                 swd_speed_khz: 0,
                 jtag_speed_khz: 0,
                 swo_enabled: false,
+                wait_retries: DEFAULT_WAIT_RETRIES,
                 opened_aps: vec![],
             }
         }
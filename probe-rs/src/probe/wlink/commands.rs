@@ -125,6 +125,24 @@ impl WchLinkCommand for GetProbeInfo {
     }
 }
 
+/// Enable or disable SDI print, the virtual printf channel of the Qingke debug module. The
+/// probe forwards what the firmware prints to its serial port. Only available on WCH-LinkE.
+#[derive(Debug)]
+pub struct SetSdiPrint(pub bool);
+
+impl WchLinkCommand for SetSdiPrint {
+    const COMMAND_ID: CommandId = CommandId::Control;
+    type Response = u8;
+
+    fn payload(&self) -> Vec<u8> {
+        if self.0 {
+            vec![0xee, 0x00]
+        } else {
+            vec![0xee, 0x01]
+        }
+    }
+}
+
 /// Attach to the target chip
 #[derive(Debug)]
 pub struct AttachChip;
@@ -279,6 +279,19 @@ impl WchLink {
 
         Ok((resp.addr, resp.data, resp.op))
     }
+
+    /// Enable or disable SDI print: the probe polls the debug module's data registers the
+    /// firmware prints to, and forwards the output to the probe's serial port (VCP).
+    ///
+    /// Requires a WCH-LinkE with firmware 2.10 or newer.
+    pub fn set_sdi_print(&mut self, enabled: bool) -> Result<(), DebugProbeError> {
+        let version_code = self.v_major * 10 + self.v_minor;
+        if self.variant != WchLinkVariant::ECh32v305 || version_code < 30 {
+            return Err(WchLinkError::SdiPrintNotSupported.into());
+        }
+        self.device.send_command(commands::SetSdiPrint(enabled))?;
+        Ok(())
+    }
 }
 
 impl DebugProbe for WchLink {
@@ -575,6 +588,8 @@ pub(crate) enum WchLinkError {
     UnknownChip(u8),
     /// Unsupported operation.
     UnsupportedOperation,
+    /// SDI print requires a WCH-LinkE with firmware 2.10 or newer.
+    SdiPrintNotSupported,
 }
 
 impl ProbeError for WchLinkError {}
@@ -23,6 +23,7 @@ use crate::{
         fake_probe::FakeProbe, list::Lister,
     },
 };
+use probe_rs_target::{MemoryRange, MemoryRegion, NvmRegion};
 use std::ops::DerefMut;
 use std::{fmt, sync::Arc, time::Duration};
 
@@ -50,6 +51,28 @@ pub struct Session {
     interfaces: ArchitectureInterface,
     cores: Vec<CombinedCoreState>,
     configured_trace_sink: Option<TraceSink>,
+    keep_flash_algorithm: bool,
+    halt_timeout: Option<Duration>,
+    flash_sector_timeout: Option<Duration>,
+    /// `(core index, algorithm fingerprint)` of flash algorithms believed to be resident in RAM.
+    resident_flash_algorithms: Vec<(usize, u64)>,
+    detach_mode: DetachMode,
+    /// Set by [`Session::close_probe`]: the target is not accessed anymore.
+    probe_closed: bool,
+}
+
+/// What a [`Session`] does with the cores of the target when it is dropped.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DetachMode {
+    /// Only disable debugging. Whether a halted core resumes depends on the architecture.
+    #[default]
+    DisableDebug,
+    /// Resume halted cores, then disable debugging.
+    Run,
+    /// Halt the cores and leave debugging enabled, so they stay halted after the session ends.
+    Halt,
+    /// Reset the target without halting, then disable debugging.
+    Reset,
 }
 
 /// The `SessionConfig` struct is used to configure a new `Session` during auto-attach.
@@ -192,6 +215,7 @@ impl Session {
         cores: Vec<CombinedCoreState>,
     ) -> Result<Self, Error> {
         let default_core = target.default_core();
+        let attach_timeout = probe.attach_timeout().unwrap_or(Duration::from_millis(100));
 
         let default_memory_ap = default_core.memory_ap().ok_or_else(|| {
             Error::Other(format!(
@@ -296,6 +320,12 @@ impl Session {
                 interfaces: ArchitectureInterface::Arm(interface),
                 cores,
                 configured_trace_sink: None,
+                keep_flash_algorithm: false,
+                halt_timeout: None,
+                flash_sector_timeout: None,
+                resident_flash_algorithms: Vec::new(),
+                detach_mode: DetachMode::default(),
+                probe_closed: false,
             };
 
             {
@@ -306,7 +336,7 @@ impl Session {
                 for core_id in 0..session.cores.len() {
                     let mut core = session.core(core_id)?;
 
-                    core.wait_for_core_halted(Duration::from_millis(100))?;
+                    core.wait_for_core_halted(attach_timeout)?;
 
                     core.reset_catch_clear()?;
                 }
@@ -324,6 +354,12 @@ impl Session {
                 interfaces: ArchitectureInterface::Arm(interface),
                 cores,
                 configured_trace_sink: None,
+                keep_flash_algorithm: false,
+                halt_timeout: None,
+                flash_sector_timeout: None,
+                resident_flash_algorithms: Vec::new(),
+                detach_mode: DetachMode::default(),
+                probe_closed: false,
             })
         }
     }
@@ -418,6 +454,12 @@ impl Session {
             interfaces,
             cores,
             configured_trace_sink: None,
+            keep_flash_algorithm: false,
+            halt_timeout: None,
+            flash_sector_timeout: None,
+            resident_flash_algorithms: Vec::new(),
+            detach_mode: DetachMode::default(),
+            probe_closed: false,
         };
 
         // Connect to the cores
@@ -499,6 +541,7 @@ impl Session {
         &mut self,
         f: impl FnOnce(&mut Self) -> Result<R, Error>,
     ) -> Result<R, Error> {
+        let halt_timeout = self.halt_timeout.unwrap_or(Duration::from_millis(100));
         let mut resume_state = vec![];
         for (core, _) in self.list_cores() {
             let mut c = match self.core(core) {
@@ -510,7 +553,7 @@ impl Session {
             } else {
                 tracing::info!("Halting core {core}...");
                 resume_state.push(core);
-                c.halt(Duration::from_millis(100))?;
+                c.halt(halt_timeout)?;
             }
         }
 
@@ -549,6 +592,11 @@ impl Session {
     // By design, this is called frequently in a session, therefore we limit tracing level to "trace" to avoid spamming the logs.
     #[tracing::instrument(level = "trace", skip(self), name = "attach_to_core")]
     pub fn core(&mut self, core_index: usize) -> Result<Core<'_>, Error> {
+        if self.probe_closed {
+            return Err(Error::Other(
+                "the probe of the session was closed".to_string(),
+            ));
+        }
         let combined_state = self
             .cores
             .get_mut(core_index)
@@ -615,6 +663,17 @@ impl Session {
         Ok(SwoReader::new(interface))
     }
 
+    /// Get the probe of a JTAG attached (RISC-V, Xtensa) target, e.g. to call driver specific
+    /// functions through [`Probe::try_into`].
+    ///
+    /// Returns `None` for Arm targets, whose probe is owned by the debug interface.
+    pub fn probe(&mut self) -> Option<&mut Probe> {
+        match &mut self.interfaces {
+            ArchitectureInterface::Jtag(probe, _) => Some(probe),
+            ArchitectureInterface::Arm(_) => None,
+        }
+    }
+
     /// Get the Arm probe interface.
     pub fn get_arm_interface(&mut self) -> Result<&mut dyn ArmDebugInterface, ArmError> {
         let interface = match &mut self.interfaces {
@@ -710,6 +769,22 @@ impl Session {
         }
     }
 
+    /// Run the target specific preparation before flashing with the core `core_index`, such as
+    /// disabling watchdogs, as defined by the target's debug sequence.
+    ///
+    /// The flasher calls this after halting the core, before the flash algorithm is started.
+    pub fn prepare_flashing(&mut self, core_index: usize) -> Result<(), crate::Error> {
+        match self.target.debug_sequence.clone() {
+            DebugSequence::Arm(arm) => arm.prepare_flashing(core_index, self),
+            DebugSequence::Riscv(riscv) => {
+                riscv.prepare_flashing(&mut self.get_riscv_interface(core_index)?)
+            }
+            DebugSequence::Xtensa(xtensa) => {
+                xtensa.prepare_flashing(&mut self.get_xtensa_interface(core_index)?)
+            }
+        }
+    }
+
     /// Check if the connected device has a debug erase sequence defined
     pub fn has_sequence_erase_all(&self) -> bool {
         match &self.target.debug_sequence {
@@ -774,11 +849,188 @@ impl Session {
         get_arm_components(interface, dp)
     }
 
+    /// Keep flash algorithms loaded in target RAM between flash operations of this session.
+    ///
+    /// When enabled, a subsequent download or erase that uses the same algorithm on the same
+    /// core skips downloading the algorithm code again, as long as all of it still matches the
+    /// RAM contents. The core is reset before flashing as usual, and the algorithm's data
+    /// section is written again every time.
+    pub fn set_keep_flash_algorithm(&mut self, keep: bool) {
+        self.keep_flash_algorithm = keep;
+        if !keep {
+            self.resident_flash_algorithms.clear();
+        }
+    }
+
+    /// Whether flash algorithms are kept loaded between flash operations, see
+    /// [`Session::set_keep_flash_algorithm`].
+    pub fn keep_flash_algorithm(&self) -> bool {
+        self.keep_flash_algorithm
+    }
+
+    /// Set how long halting a core may take before it's reported as a timeout, for the halts the
+    /// session does on its own (before flashing, in [`Session::halted_access`], on detach).
+    ///
+    /// `None` restores the defaults, which are chosen for targets running at usual clock speeds.
+    pub fn set_halt_timeout(&mut self, timeout: Option<Duration>) {
+        self.halt_timeout = timeout;
+    }
+
+    /// The halt timeout set with [`Session::set_halt_timeout`], if any.
+    pub fn halt_timeout(&self) -> Option<Duration> {
+        self.halt_timeout
+    }
+
+    /// Set how long the flash algorithm may take to erase one sector or program one page,
+    /// overriding the timeouts of the algorithm's description.
+    ///
+    /// `None` restores the timeouts of the algorithm.
+    pub fn set_flash_sector_timeout(&mut self, timeout: Option<Duration>) {
+        self.flash_sector_timeout = timeout;
+    }
+
+    /// The flash sector timeout set with [`Session::set_flash_sector_timeout`], if any.
+    pub fn flash_sector_timeout(&self) -> Option<Duration> {
+        self.flash_sector_timeout
+    }
+
+    pub(crate) fn flash_algorithm_resident(&self, core_index: usize, fingerprint: u64) -> bool {
+        self.keep_flash_algorithm
+            && self
+                .resident_flash_algorithms
+                .contains(&(core_index, fingerprint))
+    }
+
+    /// Record that the algorithm with `fingerprint` was loaded on `core_index`, replacing
+    /// anything else that was loaded there before.
+    pub(crate) fn set_flash_algorithm_resident(&mut self, core_index: usize, fingerprint: u64) {
+        self.resident_flash_algorithms
+            .retain(|(core, _)| *core != core_index);
+        if self.keep_flash_algorithm {
+            self.resident_flash_algorithms
+                .push((core_index, fingerprint));
+        }
+    }
+
+    /// Choose what happens to the cores when the session is dropped. Hardware breakpoints are
+    /// cleared in every mode.
+    pub fn set_detach_mode(&mut self, mode: DetachMode) {
+        self.detach_mode = mode;
+    }
+
+    /// The [`DetachMode`] used when the session is dropped.
+    pub fn detach_mode(&self) -> DetachMode {
+        self.detach_mode
+    }
+
+    /// Close the probe without touching the target: hardware breakpoints are not cleared and
+    /// the cores are not detached. For replacing a session whose probe dropped off the bus, so
+    /// the probe can be opened again; afterwards every core access fails and dropping the
+    /// session does nothing.
+    pub fn close_probe(&mut self) {
+        self.probe_closed = true;
+        self.interfaces = ArchitectureInterface::Jtag(FakeProbe::new().into_probe(), Vec::new());
+    }
+
+    /// Run `f` on every core that is enabled.
+    fn for_each_core(
+        &mut self,
+        mut f: impl FnMut(&mut Core<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        (0..self.cores.len()).try_for_each(|core| match self.core(core) {
+            Ok(mut core) => f(&mut core),
+            Err(Error::CoreDisabled(_)) => Ok(()),
+            Err(err) => Err(err),
+        })
+    }
+
     /// Get the target description of the connected target.
     pub fn target(&self) -> &Target {
         &self.target
     }
 
+    /// Make the flash range of a flash algorithm programmable, e.g. an external (QSPI) flash
+    /// loader that is listed by the target but not part of its memory map.
+    ///
+    /// If the algorithm's range is not covered by a flash region yet, a region named after the
+    /// algorithm is added to the memory map. The algorithm also becomes the default for its
+    /// range, so it is chosen over other algorithms covering the same flash. Both changes last
+    /// for the rest of the session; [`Session::with_flash_algorithm_region`] undoes them.
+    pub fn map_flash_algorithm_region(&mut self, algorithm_name: &str) -> Result<NvmRegion, Error> {
+        let Some(algorithm) = self
+            .target
+            .flash_algorithms
+            .iter()
+            .find(|a| a.name == algorithm_name)
+        else {
+            return Err(Error::Other(format!(
+                "Flash algorithm {algorithm_name} not found for {}",
+                self.target.name
+            )));
+        };
+        let range = algorithm.flash_properties.address_range.clone();
+        let cores = if algorithm.cores.is_empty() {
+            self.target.cores.iter().map(|c| c.name.clone()).collect()
+        } else {
+            algorithm.cores.clone()
+        };
+
+        for algorithm in &mut self.target.flash_algorithms {
+            if algorithm
+                .flash_properties
+                .address_range
+                .intersects_range(&range)
+            {
+                algorithm.default = algorithm.name == algorithm_name;
+            }
+        }
+
+        let existing = self
+            .target
+            .memory_map
+            .iter()
+            .filter_map(MemoryRegion::as_nvm_region)
+            .find(|r| r.range.contains_range(&range) || range.contains_range(&r.range));
+        if let Some(region) = existing {
+            return Ok(region.clone());
+        }
+        let region = NvmRegion {
+            name: Some(algorithm_name.to_string()),
+            range,
+            cores,
+            is_alias: false,
+            access: None,
+        };
+        self.target
+            .memory_map
+            .push(MemoryRegion::Nvm(region.clone()));
+        Ok(region)
+    }
+
+    /// Run `f` with the flash range of a flash algorithm mapped as by
+    /// [`Session::map_flash_algorithm_region`], then restore the memory map and the default
+    /// flash algorithms.
+    pub fn with_flash_algorithm_region<T>(
+        &mut self,
+        algorithm_name: &str,
+        f: impl FnOnce(&mut Session, &NvmRegion) -> T,
+    ) -> Result<T, Error> {
+        let memory_map = self.target.memory_map.clone();
+        let defaults: Vec<bool> = self
+            .target
+            .flash_algorithms
+            .iter()
+            .map(|a| a.default)
+            .collect();
+        let region = self.map_flash_algorithm_region(algorithm_name)?;
+        let result = f(self, &region);
+        self.target.memory_map = memory_map;
+        for (algorithm, default) in self.target.flash_algorithms.iter_mut().zip(defaults) {
+            algorithm.default = default;
+        }
+        Ok(result)
+    }
+
     /// Configure the target and probe for serial wire view (SWV) tracing.
     pub fn setup_tracing(
         &mut self,
@@ -902,6 +1154,9 @@ const _: fn() = || {
 impl Drop for Session {
     #[tracing::instrument(name = "session_drop", skip(self))]
     fn drop(&mut self) {
+        if self.probe_closed {
+            return;
+        }
         if let Err(err) = self.clear_all_hw_breakpoints() {
             tracing::warn!(
                 "Could not clear all hardware breakpoints: {:?}",
@@ -909,12 +1164,33 @@ impl Drop for Session {
             );
         }
 
+        let detached = match self.detach_mode {
+            DetachMode::DisableDebug => Ok(()),
+            DetachMode::Run => self.for_each_core(|core| {
+                if core.core_halted()? {
+                    core.run()?;
+                }
+                Ok(())
+            }),
+            DetachMode::Halt => {
+                let halt_timeout = self.halt_timeout.unwrap_or(Duration::from_millis(100));
+                self.for_each_core(|core| {
+                    core.halt(halt_timeout)?;
+                    Ok(())
+                })
+            }
+            DetachMode::Reset => self.core(0).and_then(|mut core| core.reset()),
+        };
+        if let Err(err) = detached {
+            tracing::warn!("Failed to detach with {:?}: {:?}", self.detach_mode, err);
+        }
+        if self.detach_mode == DetachMode::Halt {
+            // Disabling debugging would let the cores run again.
+            return;
+        }
+
         // Call any necessary deconfiguration/shutdown hooks.
-        if let Err(err) = { 0..self.cores.len() }.try_for_each(|core| match self.core(core) {
-            Ok(mut core) => core.debug_core_stop(),
-            Err(Error::CoreDisabled(_)) => Ok(()),
-            Err(err) => Err(err),
-        }) {
+        if let Err(err) = self.for_each_core(|core| core.debug_core_stop()) {
             tracing::warn!("Failed to deconfigure device during shutdown: {:?}", err);
         }
     }